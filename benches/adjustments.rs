@@ -0,0 +1,70 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::prelude::*;
+
+use hyperion::{
+    color::ChannelAdjustmentsBuilder,
+    models::{ChannelAdjustment, Color16, ColorAdjustment},
+};
+
+fn random_colors(leds: usize) -> Vec<Color16> {
+    let mut rng = rand::rng();
+    (0..leds)
+        .map(|_| Color16::new(rng.random(), rng.random(), rng.random()))
+        .collect()
+}
+
+/// Adjustment for all the LEDs, like the default configuration
+fn single_adjustment() -> ColorAdjustment {
+    ColorAdjustment {
+        channel_adjustment: vec![ChannelAdjustment {
+            brightness: 80,
+            gamma_red: 2.2,
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+/// One adjustment per 100 LEDs, like a setup with differently calibrated strips
+fn ranged_adjustments(leds: usize) -> ColorAdjustment {
+    ColorAdjustment {
+        channel_adjustment: (0..leds / 100)
+            .map(|i| ChannelAdjustment {
+                id: format!("strip {}", i),
+                leds: format!("{}-{}", i * 100, i * 100 + 99),
+                brightness: 50 + (i % 50) as u32,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("channel adjustments");
+
+    for leds in [1000, 2000, 5000] {
+        for (name, config) in [
+            ("single", single_adjustment()),
+            ("ranged", ranged_adjustments(leds)),
+        ] {
+            let adjustments = ChannelAdjustmentsBuilder::new(&config)
+                .led_count(leds as _)
+                .build();
+            let colors = random_colors(leds);
+
+            group.bench_with_input(BenchmarkId::new(name, leds), &colors, |b, colors| {
+                let mut data = colors.clone();
+                b.iter(|| {
+                    data.copy_from_slice(colors);
+                    adjustments.apply(&mut data)
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
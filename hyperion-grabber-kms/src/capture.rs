@@ -192,9 +192,7 @@ impl KmsCapture {
         }
 
         unsafe {
-            let display_ptr = match self.display.raw_display() {
-                glutin::display::RawDisplay::Egl(ptr) => ptr,
-            };
+            let glutin::display::RawDisplay::Egl(display_ptr) = self.display.raw_display();
 
             let modifier: u64 = fb.modifier().unwrap().into();
 
@@ -171,12 +171,12 @@ impl Renderer {
 
             self.gl.Uniform1f(
                 self.gl
-                    .GetUniformLocation(self.program, b"toneMappingOffset\0".as_ptr() as _),
+                    .GetUniformLocation(self.program, c"toneMappingOffset".as_ptr() as _),
                 tone_mapping_offset,
             );
             self.gl.Uniform1f(
                 self.gl
-                    .GetUniformLocation(self.program, b"toneMappingScaling\0".as_ptr() as _),
+                    .GetUniformLocation(self.program, c"toneMappingScaling".as_ptr() as _),
                 tone_mapping_scaling,
             );
 
@@ -14,6 +14,7 @@ use bytes::Bytes;
 use bytes::BytesMut;
 use clap::Parser;
 use hyperion::api::proto::message::ClearRequest;
+use hyperion::api::proto::message::HyperionReply;
 use hyperion::api::proto::message::HyperionRequest;
 use hyperion::api::proto::message::ImageRequest;
 use hyperion::servers::proto::ProtoCodec;
@@ -75,7 +76,7 @@ fn network_loop(
     conn.set_quickack(true)?;
 
     // Hyperion protobuf codec
-    let mut proto_codec = ProtoCodec::new();
+    let mut proto_codec = ProtoCodec::<HyperionReply>::new();
 
     // Working buffer for RGBA -> RGB conversion
     let mut rgb_buf: Vec<u8> = Vec::new();
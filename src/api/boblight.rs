@@ -1,11 +1,11 @@
-use std::sync::Arc;
+use std::{collections::HashMap, ops::RangeInclusive, sync::Arc};
 
 use thiserror::Error;
 
 use crate::{
     global::{InputMessage, InputMessageData, InputSourceHandle, Message, PriorityGuard},
     instance::{InstanceHandle, InstanceHandleError},
-    models::Color,
+    models::{Color, Led},
 };
 
 pub mod message;
@@ -19,76 +19,92 @@ pub enum BoblightApiError {
     MissingCommand,
     #[error("invalid instance")]
     InvalidInstance(#[from] InstanceHandleError),
+    #[error("unknown light {0:?}")]
+    UnknownLight(String),
 }
 
+/// Priorities a client may request, like the configured one without the background priority
+const PRIORITIES: RangeInclusive<i32> = 100..=253;
+
 pub struct ClientConnection {
     handle: InputSourceHandle<InputMessage>,
     priority_guard: PriorityGuard,
+    /// Index of the lights, by name
+    lights: HashMap<String, usize>,
     led_colors: Vec<Color>,
+    /// Lights the client controls, the others are sent black
+    used: Vec<bool>,
     priority: i32,
+    /// Configured priority of the server, used until the client sets its own
+    default_priority: i32,
+    /// Set once the client sent a sync command, the LEDs are then only updated on sync
+    syncing: bool,
     instance: InstanceHandle,
 }
 
 impl ClientConnection {
     pub fn new(
         handle: InputSourceHandle<InputMessage>,
-        led_count: usize,
+        leds: &[Led],
+        priority: i32,
         instance: InstanceHandle,
     ) -> Self {
-        let priority_guard = PriorityGuard::new_mpsc(instance.input_channel().clone(), &handle);
+        let mut priority_guard = PriorityGuard::new_mpsc(instance.input_channel().clone(), &handle);
+        priority_guard.set_priority(Some(priority));
 
         Self {
             handle,
             priority_guard,
-            led_colors: vec![Color::default(); led_count],
-            priority: 128,
+            lights: leds
+                .iter()
+                .enumerate()
+                .map(|(i, led)| (message::light_name(i, led), i))
+                .collect(),
+            led_colors: vec![Color::default(); leds.len()],
+            used: vec![true; leds.len()],
+            priority,
+            default_priority: priority,
+            syncing: false,
             instance,
         }
     }
 
     async fn set_priority(&mut self, priority: i32) {
-        let new_priority = if !(128..254).contains(&priority) {
+        let new_priority = if PRIORITIES.contains(&priority) {
+            priority
+        } else {
+            // Pick the first free priority from the configured one
             self.instance
                 .current_priorities()
                 .await
                 .map(|priorities| {
-                    let mut used_priorities = priorities
-                        .iter()
-                        .map(|p| p.priority)
-                        .skip_while(|p| *p <= 128)
-                        .peekable();
-
-                    for i in 128..255 {
-                        loop {
-                            match used_priorities.peek().cloned() {
-                                Some(used) if used == i => {
-                                    // Current value is used, look at the next one
-                                    used_priorities.next();
-                                    break;
-                                }
-                                Some(used) if used < i => {
-                                    used_priorities.next();
-                                    continue;
-                                }
-                                _ => {
-                                    return i;
-                                }
-                            }
-                        }
-                    }
-
-                    128
+                    (self.default_priority..=*PRIORITIES.end())
+                        .find(|candidate| {
+                            *candidate == self.priority
+                                || !priorities.iter().any(|p| p.priority == *candidate)
+                        })
+                        .unwrap_or(self.default_priority)
                 })
-                .unwrap_or(128)
-        } else {
-            priority
+                .unwrap_or(self.default_priority)
         };
 
+        if new_priority != self.priority {
+            // Don't leave the colors of the client at its previous priority
+            self.priority_guard.clear().await;
+        }
+
         self.priority = new_priority;
         self.priority_guard.set_priority(Some(new_priority));
     }
 
     async fn sync(&self) -> Result<(), BoblightApiError> {
+        let led_colors = self
+            .led_colors
+            .iter()
+            .zip(&self.used)
+            .map(|(color, used)| if *used { *color } else { Color::default() })
+            .collect();
+
         Ok(self
             .instance
             .send(InputMessage::new(
@@ -97,12 +113,21 @@ impl ClientConnection {
                 InputMessageData::LedColors {
                     priority: self.priority,
                     duration: None,
-                    led_colors: Arc::new(self.led_colors.clone()),
+                    led_colors: Arc::new(led_colors),
                 },
             ))
             .await?)
     }
 
+    fn light_index(&self, name: &str) -> Result<usize, BoblightApiError> {
+        self.lights
+            .get(name)
+            .copied()
+            // Also accept indices for lights which have a name
+            .or_else(|| name.parse().ok().filter(|i| *i < self.led_colors.len()))
+            .ok_or_else(|| BoblightApiError::UnknownLight(name.to_owned()))
+    }
+
     #[instrument(skip(request))]
     pub async fn handle_request(
         &mut self,
@@ -110,7 +135,9 @@ impl ClientConnection {
     ) -> Result<Option<BoblightResponse>, BoblightApiError> {
         match request {
             BoblightRequest::Hello => Ok(Some(BoblightResponse::Hello)),
-            BoblightRequest::Ping => Ok(Some(BoblightResponse::Ping)),
+            BoblightRequest::Ping => Ok(Some(BoblightResponse::Ping(
+                self.used.iter().any(|used| *used),
+            ))),
             BoblightRequest::Get(get) => match get {
                 message::GetArg::Version => Ok(Some(BoblightResponse::Version)),
                 message::GetArg::Lights => Ok(Some(BoblightResponse::Lights {
@@ -119,15 +146,27 @@ impl ClientConnection {
             },
             BoblightRequest::Set(set) => {
                 match set {
-                    message::SetArg::Light(message::LightParam { index, data }) => {
-                        if let message::LightParamData::Color(color) = data {
-                            if let Some(color_mut) = self.led_colors.get_mut(index) {
-                                *color_mut = color;
+                    message::SetArg::Light(message::LightParam { name, data }) => {
+                        let index = self.light_index(&name)?;
 
-                                if index == self.led_colors.len() - 1 {
+                        match data {
+                            message::LightParamData::Color(color) => {
+                                self.led_colors[index] = color;
+
+                                // Clients which don't sync send the lights in order
+                                if !self.syncing && index == self.led_colors.len() - 1 {
                                     self.sync().await?;
                                 }
                             }
+                            message::LightParamData::Use(used) => {
+                                self.used[index] = used;
+                            }
+                            // Smoothing is done by the instance, speeds are only hints
+                            message::LightParamData::Speed(_)
+                            | message::LightParamData::SingleChange(_)
+                            | message::LightParamData::Interpolation(_) => {
+                                trace!(light = %name, hint = ?data, "ignored light hint");
+                            }
                         }
                     }
                     message::SetArg::Priority(priority) => {
@@ -138,12 +177,18 @@ impl ClientConnection {
                 Ok(None)
             }
             BoblightRequest::Sync => {
+                self.syncing = true;
                 self.sync().await?;
 
                 Ok(None)
             }
         }
     }
+
+    /// Clear the priority of this client, before disconnecting
+    pub async fn clear(&mut self) {
+        self.priority_guard.clear().await;
+    }
 }
 
 impl std::fmt::Debug for ClientConnection {
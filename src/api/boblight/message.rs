@@ -16,14 +16,28 @@ pub enum DecodeError {
     InvalidLightParam,
     #[error("invalid priority")]
     InvalidPriority,
-    #[error("invalid index")]
-    InvalidIndex,
+    #[error("invalid light")]
+    InvalidLight,
     #[error("invalid color")]
     InvalidColor,
     #[error("not enough data")]
     NotEnoughData,
 }
 
+/// Name of a light in the protocol, which can't contain whitespace
+///
+/// Lights are named after their LED when it has a name, and after their index otherwise.
+pub fn light_name(index: usize, led: &Led) -> String {
+    match &led.name {
+        Some(name) if !name.trim().is_empty() => name
+            .trim()
+            .chars()
+            .map(|c| if c.is_whitespace() { '_' } else { c })
+            .collect(),
+        _ => format!("{:03}", index),
+    }
+}
+
 #[derive(Debug)]
 pub enum GetArg {
     Version,
@@ -44,7 +58,8 @@ impl TryFrom<&[&str]> for GetArg {
 
 #[derive(Debug)]
 pub struct LightParam {
-    pub index: usize,
+    /// Name of the light, as sent in the `get lights` response
+    pub name: String,
     pub data: LightParamData,
 }
 
@@ -52,17 +67,14 @@ impl TryFrom<&[&str]> for LightParam {
     type Error = DecodeError;
 
     fn try_from(value: &[&str]) -> Result<Self, Self::Error> {
-        let index = value
-            .first()
-            .and_then(|s| s.parse().ok())
-            .ok_or(DecodeError::InvalidIndex)?;
+        let name = value.first().ok_or(DecodeError::InvalidLight)?;
 
         if value.len() <= 1 {
             return Err(DecodeError::NotEnoughData);
         }
 
         Ok(Self {
-            index,
+            name: (*name).to_owned(),
             data: LightParamData::try_from(&value[1..])?,
         })
     }
@@ -70,11 +82,31 @@ impl TryFrom<&[&str]> for LightParam {
 
 #[derive(Debug)]
 pub enum LightParamData {
+    /// Color of the light, sent as components between 0 and 1
     Color(Color),
-    Speed,
-    Interpolation,
-    Use,
-    SingleChange,
+    /// Smoothing speed hint, between 0 (no change) and 100 (immediate)
+    Speed(f32),
+    /// Interpolation hint, for clients sampling fewer points than lights
+    Interpolation(bool),
+    /// Whether the client controls this light
+    Use(bool),
+    /// Speed hint for the next change only
+    SingleChange(f32),
+}
+
+fn parse_float(value: Option<&&str>, error: DecodeError) -> Result<f32, DecodeError> {
+    value
+        .and_then(|s| s.parse::<f32>().ok())
+        .filter(|value| value.is_finite())
+        .ok_or(error)
+}
+
+fn parse_bool(value: Option<&&str>) -> Result<bool, DecodeError> {
+    match value.copied() {
+        Some("1") | Some("true") => Ok(true),
+        Some("0") | Some("false") => Ok(false),
+        _ => Err(DecodeError::InvalidLightParam),
+    }
 }
 
 impl TryFrom<&[&str]> for LightParamData {
@@ -82,29 +114,26 @@ impl TryFrom<&[&str]> for LightParamData {
 
     fn try_from(value: &[&str]) -> Result<Self, Self::Error> {
         match value.first().copied() {
-            Some("color") => match value.get(1).copied() {
-                Some("rgb") => {
-                    let r = value
-                        .get(2)
-                        .and_then(|s| s.parse().ok())
-                        .ok_or(DecodeError::InvalidColor)?;
-                    let g = value
-                        .get(3)
-                        .and_then(|s| s.parse().ok())
-                        .ok_or(DecodeError::InvalidColor)?;
-                    let b = value
-                        .get(4)
-                        .and_then(|s| s.parse().ok())
-                        .ok_or(DecodeError::InvalidColor)?;
-
-                    Ok(Self::Color(Color::new(r, g, b)))
-                }
-                _ => Err(DecodeError::InvalidColor),
-            },
-            Some("speed") => Ok(Self::Speed),
-            Some("interpolation") => Ok(Self::Interpolation),
-            Some("use") => Ok(Self::Use),
-            Some("singlechange") => Ok(Self::SingleChange),
+            Some("rgb") => {
+                let component = |i: usize| {
+                    parse_float(value.get(i), DecodeError::InvalidColor)
+                        .map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+                };
+
+                Ok(Self::Color(Color::new(
+                    component(1)?,
+                    component(2)?,
+                    component(3)?,
+                )))
+            }
+            Some("speed") => Ok(Self::Speed(
+                parse_float(value.get(1), DecodeError::InvalidLightParam)?.clamp(0., 100.),
+            )),
+            Some("interpolation") => Ok(Self::Interpolation(parse_bool(value.get(1))?)),
+            Some("use") => Ok(Self::Use(parse_bool(value.get(1))?)),
+            Some("singlechange") => Ok(Self::SingleChange(
+                parse_float(value.get(1), DecodeError::InvalidLightParam)?.clamp(0., 100.),
+            )),
             _ => Err(DecodeError::InvalidLightParam),
         }
     }
@@ -150,6 +179,27 @@ pub enum BoblightRequest {
     Sync,
 }
 
+impl BoblightRequest {
+    /// Name of the protocol feature used by this request
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Self::Hello => "hello",
+            Self::Ping => "ping",
+            Self::Get(GetArg::Version) => "get-version",
+            Self::Get(GetArg::Lights) => "get-lights",
+            Self::Set(SetArg::Priority(_)) => "set-priority",
+            Self::Set(SetArg::Light(LightParam { data, .. })) => match data {
+                LightParamData::Color(_) => "set-light-rgb",
+                LightParamData::Speed(_) => "set-light-speed",
+                LightParamData::Interpolation(_) => "set-light-interpolation",
+                LightParamData::Use(_) => "set-light-use",
+                LightParamData::SingleChange(_) => "set-light-singlechange",
+            },
+            Self::Sync => "sync",
+        }
+    }
+}
+
 impl FromStr for BoblightRequest {
     type Err = DecodeError;
 
@@ -186,43 +236,92 @@ impl FromStr for BoblightRequest {
 #[derive(Debug)]
 pub enum BoblightResponse {
     Hello,
-    Ping,
+    /// Whether the lights of the client are being displayed
+    Ping(bool),
     Version,
-    Lights { leds: Vec<Led> },
+    Lights {
+        leds: Vec<Led>,
+    },
 }
 
 impl std::fmt::Display for BoblightResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BoblightResponse::Hello => write!(f, "hello"),
-            BoblightResponse::Ping => write!(f, "ping 1"),
+            BoblightResponse::Ping(used) => write!(f, "ping {}", *used as u8),
             BoblightResponse::Version => write!(f, "version 5"),
             BoblightResponse::Lights { leds } => {
-                let n = leds.len();
-                if n > 0 {
-                    writeln!(f, "lights {}", n)?;
-
-                    for (i, led) in leds.iter().enumerate() {
-                        write!(
-                            f,
-                            "light {:03} scan {} {} {} {}",
-                            i,
-                            led.hmin * 100.,
-                            led.hmax * 100.,
-                            led.vmin * 100.,
-                            led.vmax * 100.
-                        )?;
-
-                        if i < n - 1 {
-                            writeln!(f)?;
-                        }
-                    }
-
-                    Ok(())
-                } else {
-                    write!(f, "lights 0")
+                write!(f, "lights {}", leds.len())?;
+
+                // Scan ranges are percentages of the picture, vertical range first
+                for (i, led) in leds.iter().enumerate() {
+                    write!(
+                        f,
+                        "\nlight {} scan {} {} {} {}",
+                        light_name(i, led),
+                        led.vmin * 100.,
+                        led.vmax * 100.,
+                        led.hmin * 100.,
+                        led.hmax * 100.
+                    )?;
                 }
+
+                Ok(())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_light() {
+        let request: BoblightRequest = "set light 002 rgb 1.000000 0.500000 0.000000"
+            .parse()
+            .unwrap();
+        assert!(matches!(
+            request,
+            BoblightRequest::Set(SetArg::Light(LightParam {
+                ref name,
+                data: LightParamData::Color(color),
+            })) if name == "002" && color == Color::new(255, 128, 0)
+        ));
+
+        assert!(matches!(
+            "set light left speed 150".parse(),
+            Ok(BoblightRequest::Set(SetArg::Light(LightParam {
+                data: LightParamData::Speed(speed),
+                ..
+            }))) if speed == 100.
+        ));
+        assert!("set light 000 use maybe"
+            .parse::<BoblightRequest>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_lights_response() {
+        let led = Led {
+            hmin: 0.,
+            hmax: 0.5,
+            vmin: 0.,
+            vmax: 0.25,
+            color_order: None,
+            name: None,
+        };
+        let leds = vec![
+            led.clone(),
+            Led {
+                name: Some("top left".to_owned()),
+                ..led
+            },
+        ];
+
+        assert_eq!(
+            BoblightResponse::Lights { leds }.to_string(),
+            "lights 2\nlight 000 scan 0 25 0 50\nlight top_left scan 0 25 0 50"
+        );
+    }
+}
@@ -1,23 +1,26 @@
 use std::convert::TryFrom;
 use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 use thiserror::Error;
 
-use super::types::i32_to_duration;
+use super::types::{i32_to_color, i32_to_duration, PriorityCompat};
 
 use crate::{
     component::ComponentName,
     global::{
         Global, InputMessage, InputMessageData, InputSourceHandle, InputSourceName, PriorityGuard,
     },
-    image::{RawImage, RawImageError},
-    models::Color,
+    image::{Image, ImageRegion, RawImage, RawImageError},
 };
 
 /// Schema definitions as Serde serializable structures and enums
 pub mod message;
 
+/// Priorities flatbuffers clients can register with
+const PRIORITY_RANGE: RangeInclusive<i32> = 100..=199;
+
 #[derive(Debug, Error)]
 pub enum FlatApiError {
     #[error("error broadcasting update: {0}")]
@@ -38,39 +41,53 @@ async fn handle_register(
     source: &mut Option<InputSourceHandle<InputMessage>>,
     global: &Global,
     priority_guard: &mut Option<PriorityGuard>,
+    priority_compat: &mut PriorityCompat,
 ) -> Result<(), FlatApiError> {
-    let priority = register.priority();
+    let priority = priority_compat.correct(register.priority(), PRIORITY_RANGE);
 
-    if !(100..200).contains(&priority) {
+    if !PRIORITY_RANGE.contains(&priority) {
+        // The previous registration, if any, is kept
         return Err(FlatApiError::InvalidPriority(priority));
-    } else {
-        // unwrap: we checked the priority value before
-        let new_source = global
-            .register_input_source(
-                InputSourceName::FlatBuffers {
-                    peer_addr,
-                    origin: register.origin().to_owned(),
-                },
-                Some(priority),
-            )
-            .await
-            .unwrap();
+    }
 
-        // Update priority guard
-        *priority_guard = Some(PriorityGuard::new_broadcast(&new_source));
-        *source = Some(new_source);
+    // Registering again with another priority releases the previous one
+    if let Some(mut guard) = priority_guard.take() {
+        if source.as_ref().and_then(|source| source.priority()) == Some(priority) {
+            guard.set_priority(None);
+        } else {
+            guard.clear().await;
+        }
     }
 
+    // unwrap: we checked the priority value before
+    let new_source = global
+        .register_input_source(
+            InputSourceName::FlatBuffers {
+                peer_addr,
+                origin: register.origin().to_owned(),
+            },
+            Some(priority),
+        )
+        .await
+        .unwrap();
+
+    debug!(priority = %priority, origin = %register.origin(), "registered");
+
+    // Update priority guard
+    *priority_guard = Some(PriorityGuard::new_broadcast(&new_source));
+    *source = Some(new_source);
+
     Ok(())
 }
 
-#[instrument(skip(request, source, global, priority_guard))]
+#[instrument(skip(request, source, global, priority_guard, priority_compat))]
 pub async fn handle_request(
     peer_addr: SocketAddr,
     request: message::Request<'_>,
     source: &mut Option<InputSourceHandle<InputMessage>>,
     global: &Global,
     priority_guard: &mut Option<PriorityGuard>,
+    priority_compat: &mut PriorityCompat,
 ) -> Result<(), FlatApiError> {
     if let Some(handle) = source.as_ref() {
         // unwrap: we set a priority when we got the register call
@@ -81,29 +98,30 @@ pub async fn handle_request(
             if clear.priority() < 0 {
                 handle.send(ComponentName::FlatbufServer, InputMessageData::ClearAll)?;
             } else {
+                let cleared = priority_compat.correct(clear.priority(), PRIORITY_RANGE);
                 handle.send(
                     ComponentName::FlatbufServer,
-                    InputMessageData::Clear {
-                        priority: clear.priority(),
-                    },
+                    InputMessageData::Clear { priority: cleared },
                 )?;
+
+                // Clearing its own priority unregisters the client, like in hyperion.ng
+                if cleared == priority {
+                    if let Some(mut guard) = priority_guard.take() {
+                        guard.set_priority(None);
+                    }
+
+                    *source = None;
+                    debug!(priority = %priority, "unregistered");
+                }
             }
         } else if let Some(color) = request.command_as_color() {
-            let rgb = color.data();
-            let rgb = (
-                (rgb & 0x000_000FF) as u8,
-                ((rgb & 0x0000_FF00) >> 8) as u8,
-                ((rgb & 0x00FF_0000) >> 16) as u8,
-            );
-
             // Update state
             handle.send(
                 ComponentName::FlatbufServer,
                 InputMessageData::SolidColor {
-                    // TODO
-                    priority: 0,
+                    priority,
                     duration: i32_to_duration(Some(color.duration())),
-                    color: Color::from_components(rgb),
+                    color: i32_to_color(color.data()),
                 },
             )?;
         } else if let Some(image) = request.command_as_image() {
@@ -123,6 +141,16 @@ pub async fn handle_request(
             let height = u32::try_from(height).map_err(|_| RawImageError::InvalidHeight)?;
             let raw_image = RawImage::try_from((data.bytes().to_vec(), width, height))?;
 
+            // An empty region means the whole image should be processed
+            let region = Some(ImageRegion::clipped(
+                image.region_x().max(0) as _,
+                image.region_y().max(0) as _,
+                image.region_width().max(0) as _,
+                image.region_height().max(0) as _,
+                (raw_image.width(), raw_image.height()),
+            ))
+            .filter(|region| !region.is_empty());
+
             // Update state
             handle.send(
                 ComponentName::FlatbufServer,
@@ -130,18 +158,127 @@ pub async fn handle_request(
                     priority,
                     duration: i32_to_duration(Some(duration)),
                     image: Arc::new(raw_image),
+                    region,
                 },
             )?;
         } else if let Some(register) = request.command_as_register() {
-            return handle_register(peer_addr, register, source, global, priority_guard).await;
+            return handle_register(
+                peer_addr,
+                register,
+                source,
+                global,
+                priority_guard,
+                priority_compat,
+            )
+            .await;
         } else {
             return Err(FlatApiError::UnknownCommand);
         }
     } else if let Some(register) = request.command_as_register() {
-        return handle_register(peer_addr, register, source, global, priority_guard).await;
+        return handle_register(
+            peer_addr,
+            register,
+            source,
+            global,
+            priority_guard,
+            priority_compat,
+        )
+        .await;
     } else {
         return Err(FlatApiError::Unregistered);
     };
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::flat::{clear_request, register_request},
+        global::{GlobalData, Message, Paths},
+        models::{Config, GlobalConfig},
+    };
+
+    /// Connection state of a client
+    #[derive(Default)]
+    struct Client {
+        source: Option<InputSourceHandle<InputMessage>>,
+        priority_guard: Option<PriorityGuard>,
+        priority_compat: PriorityCompat,
+    }
+
+    impl Client {
+        async fn send(
+            &mut self,
+            global: &Global,
+            request: bytes::Bytes,
+        ) -> Result<(), FlatApiError> {
+            handle_request(
+                "127.0.0.1:19400".parse().unwrap(),
+                message::root_as_request(&request).unwrap(),
+                &mut self.source,
+                global,
+                &mut self.priority_guard,
+                &mut self.priority_compat,
+            )
+            .await
+        }
+
+        fn priority(&self) -> Option<i32> {
+            self.source.as_ref().and_then(|source| source.priority())
+        }
+    }
+
+    fn cleared(rx: &mut tokio::sync::broadcast::Receiver<InputMessage>) -> Option<i32> {
+        match rx.try_recv().ok()?.data() {
+            InputMessageData::Clear { priority } => Some(*priority),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registration() {
+        let config = Config::new(GlobalConfig::default(), []);
+        let global = GlobalData::new(&config, Paths::new(None).unwrap()).wrap();
+        let mut rx = global.subscribe_input().await;
+        let mut builder = flatbuffers::FlatBufferBuilder::new();
+        let mut client = Client::default();
+
+        client
+            .send(&global, register_request(&mut builder, "test", 150))
+            .await
+            .unwrap();
+        assert_eq!(client.priority(), Some(150));
+
+        // Registering with another priority releases the previous one
+        client
+            .send(&global, register_request(&mut builder, "test", 160))
+            .await
+            .unwrap();
+        assert_eq!(cleared(&mut rx), Some(150));
+        assert_eq!(client.priority(), Some(160));
+
+        // Invalid registrations keep the current one
+        assert!(client
+            .send(&global, register_request(&mut builder, "test", 300))
+            .await
+            .is_err());
+        assert_eq!(client.priority(), Some(160));
+
+        // Clearing its own priority unregisters the client, there is nothing left to clear
+        client
+            .send(&global, clear_request(&mut builder, 160))
+            .await
+            .unwrap();
+        assert_eq!(cleared(&mut rx), Some(160));
+        assert_eq!(client.priority(), None);
+        assert!(matches!(
+            client.send(&global, clear_request(&mut builder, 160)).await,
+            Err(FlatApiError::Unregistered)
+        ));
+
+        drop(client);
+        assert_eq!(cleared(&mut rx), None);
+    }
+}
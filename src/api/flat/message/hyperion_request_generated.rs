@@ -469,6 +469,10 @@ impl<'a> Image<'a> {
   pub const VT_DATA_TYPE: flatbuffers::VOffsetT = 4;
   pub const VT_DATA: flatbuffers::VOffsetT = 6;
   pub const VT_DURATION: flatbuffers::VOffsetT = 8;
+  pub const VT_REGION_X: flatbuffers::VOffsetT = 10;
+  pub const VT_REGION_Y: flatbuffers::VOffsetT = 12;
+  pub const VT_REGION_WIDTH: flatbuffers::VOffsetT = 14;
+  pub const VT_REGION_HEIGHT: flatbuffers::VOffsetT = 16;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -480,6 +484,10 @@ impl<'a> Image<'a> {
     args: &'args ImageArgs
   ) -> flatbuffers::WIPOffset<Image<'bldr>> {
     let mut builder = ImageBuilder::new(_fbb);
+    builder.add_region_height(args.region_height);
+    builder.add_region_width(args.region_width);
+    builder.add_region_y(args.region_y);
+    builder.add_region_x(args.region_x);
     builder.add_duration(args.duration);
     if let Some(x) = args.data { builder.add_data(x); }
     builder.add_data_type(args.data_type);
@@ -509,6 +517,34 @@ impl<'a> Image<'a> {
     unsafe { self._tab.get::<i32>(Image::VT_DURATION, Some(-1)).unwrap()}
   }
   #[inline]
+  pub fn region_x(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(Image::VT_REGION_X, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn region_y(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(Image::VT_REGION_Y, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn region_width(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(Image::VT_REGION_WIDTH, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn region_height(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(Image::VT_REGION_HEIGHT, Some(0)).unwrap()}
+  }
+  #[inline]
   #[allow(non_snake_case)]
   pub fn data_as_raw_image(&self) -> Option<RawImage<'a>> {
     if self.data_type() == ImageType::RawImage {
@@ -538,6 +574,10 @@ impl flatbuffers::Verifiable for Image<'_> {
         }
      })?
      .visit_field::<i32>("duration", Self::VT_DURATION, false)?
+     .visit_field::<i32>("region_x", Self::VT_REGION_X, false)?
+     .visit_field::<i32>("region_y", Self::VT_REGION_Y, false)?
+     .visit_field::<i32>("region_width", Self::VT_REGION_WIDTH, false)?
+     .visit_field::<i32>("region_height", Self::VT_REGION_HEIGHT, false)?
      .finish();
     Ok(())
   }
@@ -546,6 +586,10 @@ pub struct ImageArgs {
     pub data_type: ImageType,
     pub data: Option<flatbuffers::WIPOffset<flatbuffers::UnionWIPOffset>>,
     pub duration: i32,
+    pub region_x: i32,
+    pub region_y: i32,
+    pub region_width: i32,
+    pub region_height: i32,
 }
 impl<'a> Default for ImageArgs {
   #[inline]
@@ -554,6 +598,10 @@ impl<'a> Default for ImageArgs {
       data_type: ImageType::NONE,
       data: None, // required field
       duration: -1,
+      region_x: 0,
+      region_y: 0,
+      region_width: 0,
+      region_height: 0,
     }
   }
 }
@@ -576,6 +624,22 @@ impl<'a: 'b, 'b> ImageBuilder<'a, 'b> {
     self.fbb_.push_slot::<i32>(Image::VT_DURATION, duration, -1);
   }
   #[inline]
+  pub fn add_region_x(&mut self, region_x: i32) {
+    self.fbb_.push_slot::<i32>(Image::VT_REGION_X, region_x, 0);
+  }
+  #[inline]
+  pub fn add_region_y(&mut self, region_y: i32) {
+    self.fbb_.push_slot::<i32>(Image::VT_REGION_Y, region_y, 0);
+  }
+  #[inline]
+  pub fn add_region_width(&mut self, region_width: i32) {
+    self.fbb_.push_slot::<i32>(Image::VT_REGION_WIDTH, region_width, 0);
+  }
+  #[inline]
+  pub fn add_region_height(&mut self, region_height: i32) {
+    self.fbb_.push_slot::<i32>(Image::VT_REGION_HEIGHT, region_height, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> ImageBuilder<'a, 'b> {
     let start = _fbb.start_table();
     ImageBuilder {
@@ -609,6 +673,10 @@ impl core::fmt::Debug for Image<'_> {
         },
       };
       ds.field("duration", &self.duration());
+      ds.field("region_x", &self.region_x());
+      ds.field("region_y", &self.region_y());
+      ds.field("region_width", &self.region_width());
+      ds.field("region_height", &self.region_height());
       ds.finish()
   }
 }
@@ -1,20 +1,37 @@
 use std::convert::TryFrom;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use base64::Engine;
 use thiserror::Error;
-use tokio::sync::{oneshot, Mutex};
+use tokio::sync::{broadcast, oneshot, Mutex};
 use validator::Validate;
 
 use crate::{
+    api::types::PriorityCompat,
+    color::LedMatch,
     component::ComponentName,
-    global::{Global, InputMessage, InputMessageData, InputSourceHandle, Message},
-    image::{RawImage, RawImageError},
-    instance::{InstanceHandle, InstanceHandleError, StartEffectError},
+    db::{models::DbSetting, Db, DbError, DbRestoreError},
+    effects::{create_effect, delete_effect, NewEffect},
+    global::{
+        AccessLevel, AuthError, ClientHandle, CommandTrace, Event, Global, InputMessage,
+        InputMessageData, InputSourceHandle, InstanceEvent, InstanceEventKind, LogRecord, Message,
+        TokenRequest,
+    },
+    image::{Image, ImageRegion, RawImage, RawImageError},
+    instance::{BlackBorderControl, InstanceHandle, InstanceHandleError, StartEffectError},
+    models::{
+        backend::{ConfigBackend, DbBackend},
+        ConfigError, InstanceConfig, LedGain, Setting, SmoothingOverride,
+    },
+    servers::ServerId,
 };
 
 /// Schema definitions as Serde serializable structures and enums
 pub mod message;
-use message::{HyperionCommand, HyperionMessage, HyperionResponse};
+use message::{
+    HyperionCommand, HyperionMessage, HyperionResponse, InstanceTarget, ServerInfoSubscription,
+};
 
 #[derive(Debug, Error)]
 pub enum JsonApiError {
@@ -32,24 +49,330 @@ pub enum JsonApiError {
     Instance(#[from] InstanceHandleError),
     #[error("no current instance found")]
     InstanceNotFound,
+    #[error("instance {0} is not running")]
+    UnknownInstance(i32),
+    #[error("unknown channel adjustment {0:?}")]
+    UnknownAdjustment(String),
+    #[error("the instance has no channel adjustment")]
+    NoAdjustment,
     #[error(transparent)]
     StartEffect(#[from] StartEffectError),
+    #[error("the configuration is in read-only mode")]
+    ReadOnly,
+    #[error("no configuration database in use")]
+    NoDatabase,
+    #[error("database error: {0}")]
+    Db(#[from] DbError),
+    #[error("error restoring database: {0}")]
+    Restore(#[from] DbRestoreError),
+    #[error("configuration error: {0}")]
+    Config(#[from] ConfigError),
+    #[error("missing database snapshot")]
+    MissingSnapshot,
+    #[error("error decoding database snapshot: {0}")]
+    Snapshot(#[from] base64::DecodeError),
+    #[error("background task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error("missing argument: {0}")]
+    MissingArgument(&'static str),
+    #[error("the {0} server is required and can't be stopped")]
+    RequiredServer(ServerId),
+    #[error("log records are not collected")]
+    NoLogBuffer,
+}
+
+/// Default interval between streamed updates
+const DEFAULT_STREAM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Quality of streamed JPEG images
+#[cfg(feature = "jpeg")]
+const STREAM_JPEG_QUALITY: u8 = 80;
+
+/// Periodic updates requested by a client
+struct Subscription {
+    interval: tokio::time::Interval,
+    oneshot: bool,
+    /// tan of the request which started the stream, echoed in updates
+    tan: Option<i32>,
+}
+
+impl Subscription {
+    fn new(interval: Option<u32>, oneshot: Option<bool>, tan: Option<i32>) -> Self {
+        let mut interval = tokio::time::interval(
+            interval.map_or(DEFAULT_STREAM_INTERVAL, |ms| Duration::from_millis(ms as _)),
+        );
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        Self {
+            interval,
+            oneshot: oneshot.unwrap_or(false),
+            tan,
+        }
+    }
+
+    /// Wait for the next update of the subscription, if any
+    ///
+    /// # Returns
+    ///
+    /// The tan of the subscription. Oneshot subscriptions are removed.
+    async fn tick(subscription: &mut Option<Self>) -> Option<i32> {
+        let current = match subscription.as_mut() {
+            Some(current) => current,
+            None => return futures::future::pending().await,
+        };
+
+        current.interval.tick().await;
+
+        let tan = current.tan;
+        if current.oneshot {
+            *subscription = None;
+        }
+
+        tan
+    }
+}
+
+/// Log records streamed to a client
+struct LogStream {
+    /// Records to send in the next update
+    pending: Vec<Arc<LogRecord>>,
+    receiver: broadcast::Receiver<Arc<LogRecord>>,
+    /// Time to wait for more records before sending an update, if any
+    interval: Option<Duration>,
+    /// End of the current wait for more records
+    deadline: Option<tokio::time::Instant>,
+    oneshot: bool,
+    /// tan of the request which started the stream, echoed in updates
+    tan: Option<i32>,
 }
 
 /// A client connected to the JSON endpoint
 pub struct ClientConnection {
     source: InputSourceHandle<InputMessage>,
     current_instance: Option<i32>,
+    led_stream: Option<Subscription>,
+    image_stream: Option<Subscription>,
+    image_stream_format: message::ImageStreamFormat,
+    log_stream: Option<LogStream>,
+    /// Last image sent on the image stream, to only send new ones
+    streamed_image: Option<Arc<RawImage>>,
+    /// State updates requested through `serverinfo`
+    state_updates: Vec<ServerInfoSubscription>,
+    events: Option<broadcast::Receiver<Event>>,
+    /// Access level of the client before it logged in
+    initial_access: AccessLevel,
+    access: AccessLevel,
+    /// Token request waiting for an administrator, with the tan of the request
+    token_request: Option<(Option<i32>, TokenRequest)>,
+    /// Registration of the network client, to record the features it uses
+    client: Option<ClientHandle>,
+    priority_compat: PriorityCompat,
 }
 
 impl ClientConnection {
-    pub fn new(source: InputSourceHandle<InputMessage>) -> Self {
+    pub fn new(source: InputSourceHandle<InputMessage>, access: AccessLevel) -> Self {
         Self {
             source,
             current_instance: None,
+            led_stream: None,
+            image_stream: None,
+            image_stream_format: Default::default(),
+            log_stream: None,
+            streamed_image: None,
+            state_updates: Vec::new(),
+            events: None,
+            initial_access: access,
+            access,
+            token_request: None,
+            client: None,
+            priority_compat: Default::default(),
         }
     }
 
+    pub fn with_client(mut self, client: ClientHandle) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Clamp out-of-range priorities from legacy clients instead of rejecting their requests
+    pub fn with_legacy_priorities(mut self, enabled: bool) -> Self {
+        self.priority_compat = PriorityCompat::new(enabled);
+        self
+    }
+
+    fn negotiated(&self, feature: &str) {
+        if let Some(client) = &self.client {
+            client.negotiated(feature);
+        }
+    }
+
+    /// Returns true if this client subscribed to updates
+    pub fn has_subscriptions(&self) -> bool {
+        self.led_stream.is_some()
+            || self.image_stream.is_some()
+            || self.log_stream.is_some()
+            || self.events.is_some()
+            || self.token_request.is_some()
+    }
+
+    /// Wait for the next update to push to this client
+    ///
+    /// This never completes if the client has no subscription. It is cancel-safe, so it can be
+    /// raced against incoming requests.
+    pub async fn next_update(&mut self, global: &Global) -> HyperionResponse {
+        loop {
+            let (tan, update) = tokio::select! {
+                tan = Subscription::tick(&mut self.led_stream) => {
+                    (tan, self.led_stream_update(global).await)
+                }
+                tan = Subscription::tick(&mut self.image_stream) => {
+                    (tan, self.image_stream_update(global).await)
+                }
+                (tan, records) = next_log_records(&mut self.log_stream) => {
+                    (tan, Ok(Some(HyperionResponse::log_message_update(&records))))
+                }
+                event = next_event(&mut self.events) => {
+                    (None, self.state_update(global, event).await)
+                }
+                (tan, answer) = token_request_answer(&mut self.token_request) => {
+                    (tan, Ok(Some(answer)))
+                }
+            };
+
+            match update {
+                Ok(Some(update)) => return update.with_tan(tan),
+                Ok(None) => {}
+                Err(error) => {
+                    debug!(error = %error, "cannot get streamed update");
+                }
+            }
+        }
+    }
+
+    async fn led_stream_update(
+        &mut self,
+        global: &Global,
+    ) -> Result<Option<HyperionResponse>, JsonApiError> {
+        // Follow the current instance, it may be switched while streaming
+        let colors = self.current_instance(global).await?.led_colors().await?;
+        Ok(Some(HyperionResponse::led_stream_update(&colors)))
+    }
+
+    async fn image_stream_update(
+        &mut self,
+        global: &Global,
+    ) -> Result<Option<HyperionResponse>, JsonApiError> {
+        let image = match self.current_instance(global).await?.current_image().await? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+
+        // Only send images which weren't sent yet
+        if self
+            .streamed_image
+            .as_ref()
+            .is_some_and(|streamed| Arc::ptr_eq(streamed, &image))
+        {
+            return Ok(None);
+        }
+
+        self.streamed_image = Some(image.clone());
+
+        let format = self.image_stream_format;
+        // Encoding large images takes a while, keep it off the async runtime workers
+        let response =
+            tokio::task::spawn_blocking(move || encode_stream_image(&image, format)).await??;
+
+        Ok(Some(response))
+    }
+
+    async fn subscribe_state_updates(
+        &mut self,
+        global: &Global,
+        subscriptions: Vec<ServerInfoSubscription>,
+    ) {
+        if subscriptions.is_empty() {
+            return;
+        }
+
+        for subscription in subscriptions {
+            self.negotiated(&format!("subscribe:{}", subscription.name()));
+
+            if !self.state_updates.contains(&subscription) {
+                self.state_updates.push(subscription);
+            }
+        }
+
+        if self.events.is_none() {
+            self.events = Some(global.subscribe_events().await);
+        }
+    }
+
+    /// Turn a global event into the state update for subscribed clients, if any
+    async fn state_update(
+        &mut self,
+        global: &Global,
+        event: Event,
+    ) -> Result<Option<HyperionResponse>, JsonApiError> {
+        let subscribed = |subscription| self.state_updates.contains(&subscription);
+
+        let update = match event {
+            Event::ComponentStateChanged { component, enabled }
+                if subscribed(ServerInfoSubscription::Components) =>
+            {
+                HyperionResponse::components_update(component, enabled)
+            }
+            Event::Instance(InstanceEvent {
+                id,
+                kind: InstanceEventKind::ComponentStateChanged { component, enabled },
+            }) if subscribed(ServerInfoSubscription::Components) => {
+                // Only the components of the current instance are reported
+                if self.current_instance(global).await?.id() != id {
+                    return Ok(None);
+                }
+
+                HyperionResponse::components_update(component, enabled)
+            }
+            Event::Instance(InstanceEvent {
+                kind: InstanceEventKind::Start | InstanceEventKind::Stop,
+                ..
+            }) if subscribed(ServerInfoSubscription::Instance) => {
+                HyperionResponse::instance_update(instance_infos(global).await)
+            }
+            Event::Instance(InstanceEvent {
+                id,
+                kind: InstanceEventKind::PrioritiesChanged,
+            }) if subscribed(ServerInfoSubscription::Priorities) => {
+                // Only the priorities of the current instance are reported
+                let instance = self.current_instance(global).await?;
+                if instance.id() != id {
+                    return Ok(None);
+                }
+
+                HyperionResponse::priorities_update(
+                    instance.current_priorities().await?,
+                    instance.priorities_autoselect().await?,
+                )
+            }
+            Event::Instance(InstanceEvent {
+                id,
+                kind: InstanceEventKind::AdjustmentChanged,
+            }) if subscribed(ServerInfoSubscription::Adjustment) => {
+                let instance = self.current_instance(global).await?;
+                if instance.id() != id {
+                    return Ok(None);
+                }
+
+                HyperionResponse::adjustment_update(adjustments(&*instance.config().await?))
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(update))
+    }
+
     async fn current_instance(&mut self, global: &Global) -> Result<InstanceHandle, JsonApiError> {
         if let Some(current_instance) = self.current_instance {
             if let Some(instance) = global.get_instance(current_instance).await {
@@ -68,19 +391,212 @@ impl ClientConnection {
         Err(JsonApiError::InstanceNotFound)
     }
 
+    /// Send an input message to the instances targeted by a command
+    ///
+    /// Commands without a target, or targeting all instances, go through the global input bus
+    /// which every instance listens on.
+    async fn send_input(
+        &self,
+        global: &Global,
+        target: Option<&InstanceTarget>,
+        component: ComponentName,
+        data: InputMessageData,
+        smoothing: Option<SmoothingOverride>,
+    ) -> Result<(), JsonApiError> {
+        let message = InputMessage::new(self.source.id(), component, data)
+            .with_smoothing(smoothing.map(Arc::new));
+
+        match target {
+            None | Some(InstanceTarget::All(_)) => {
+                self.source.channel().send(message)?;
+            }
+            Some(target) => {
+                for instance in target_instances(global, target).await? {
+                    instance.send(message.clone()).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Log in with an API token or an administrator session token
+    pub async fn login(&mut self, global: &Global, token: &str) -> Result<(), AuthError> {
+        let access = global.auth().await.login_token(token).await?;
+        self.access = self.access.max(access);
+        Ok(())
+    }
+
+    async fn authorize(
+        &mut self,
+        authorize: message::Authorize,
+        global: &Global,
+        tan: Option<i32>,
+    ) -> Result<HyperionResponse, JsonApiError> {
+        use message::AuthorizeCommand;
+
+        let message::Authorize {
+            subcommand,
+            password,
+            new_password,
+            token,
+            comment,
+            id,
+            accept,
+        } = authorize;
+        let auth = global.auth().await;
+
+        Ok(match subcommand {
+            AuthorizeCommand::TokenRequired => {
+                HyperionResponse::token_required(self.access < AccessLevel::Api)
+            }
+            AuthorizeCommand::AdminRequired => {
+                HyperionResponse::admin_required(self.access < AccessLevel::Admin)
+            }
+            AuthorizeCommand::NewPasswordRequired => {
+                HyperionResponse::new_password_required(auth.is_default_password().await)
+            }
+            AuthorizeCommand::Login => match (password, token) {
+                (Some(password), _) => {
+                    let token = auth.login_password(&password).await?;
+                    self.access = AccessLevel::Admin;
+                    HyperionResponse::login(Some(token))
+                }
+                (None, Some(token)) => {
+                    self.login(global, &token).await?;
+                    HyperionResponse::login(None)
+                }
+                (None, None) => return Err(JsonApiError::MissingArgument("password")),
+            },
+            AuthorizeCommand::Logout => {
+                self.access = self.initial_access;
+                HyperionResponse::success()
+            }
+            AuthorizeCommand::NewPassword => {
+                auth.set_password(
+                    &password.ok_or(JsonApiError::MissingArgument("password"))?,
+                    &new_password.ok_or(JsonApiError::MissingArgument("newPassword"))?,
+                )
+                .await?;
+                HyperionResponse::success()
+            }
+            AuthorizeCommand::CreateToken => {
+                let comment = comment.ok_or(JsonApiError::MissingArgument("comment"))?;
+                let (token, id) = auth.create_token(comment.clone()).await?;
+                HyperionResponse::create_token(comment, id, token)
+            }
+            AuthorizeCommand::RenameToken => {
+                let id = id.ok_or(JsonApiError::MissingArgument("id"))?;
+                let comment = comment.ok_or(JsonApiError::MissingArgument("comment"))?;
+                auth.rename_token(&id, comment.clone()).await?;
+                HyperionResponse::rename_token(comment, id)
+            }
+            AuthorizeCommand::DeleteToken => {
+                let id = id.ok_or(JsonApiError::MissingArgument("id"))?;
+                auth.delete_token(&id).await?;
+                HyperionResponse::delete_token(id)
+            }
+            AuthorizeCommand::GetTokenList => HyperionResponse::token_list(
+                auth.tokens().await.into_iter().map(Into::into).collect(),
+            ),
+            AuthorizeCommand::RequestToken => {
+                let id = id.ok_or(JsonApiError::MissingArgument("id"))?;
+
+                if accept == Some(false) {
+                    // Cancel our request, dropping it expires it
+                    if self
+                        .token_request
+                        .as_ref()
+                        .is_some_and(|(_, request)| request.id == id)
+                    {
+                        self.token_request = None;
+                    }
+                } else {
+                    let comment = comment.ok_or(JsonApiError::MissingArgument("comment"))?;
+                    // The token is pushed to the client once an administrator accepts
+                    self.token_request = Some((tan, auth.request_token(comment, id).await?));
+                }
+
+                HyperionResponse::success()
+            }
+            AuthorizeCommand::GetPendingTokenRequests => HyperionResponse::pending_token_requests(
+                auth.pending_requests()
+                    .await
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ),
+            AuthorizeCommand::AnswerRequest => {
+                auth.answer_request(
+                    &id.ok_or(JsonApiError::MissingArgument("id"))?,
+                    accept.ok_or(JsonApiError::MissingArgument("accept"))?,
+                )
+                .await?;
+                HyperionResponse::success()
+            }
+        })
+    }
+
     fn set_current_instance(&mut self, id: i32) {
         debug!("{}: switch to instance {}", &self.source.name(), id);
         self.current_instance = Some(id);
     }
 
-    #[instrument(skip(request, global))]
+    #[instrument(skip(request, global), fields(tan = ?request.tan, command = request.command.name()))]
     pub async fn handle_request(
         &mut self,
         request: HyperionMessage,
         global: &Global,
     ) -> Result<HyperionResponse, JsonApiError> {
+        let tan = request.tan;
+        let command = request.command.name();
+        let started = chrono::Utc::now();
+        let start = Instant::now();
+
+        let result = self.handle_command(request, global).await;
+
+        let duration = start.elapsed();
+        match &result {
+            Ok(_) => debug!(?duration, "command processed"),
+            Err(error) => debug!(?duration, %error, "command failed"),
+        }
+
+        global
+            .record_command(CommandTrace {
+                tan,
+                command,
+                source: self.source.name().to_string(),
+                started,
+                duration,
+                error: result.as_ref().err().map(ToString::to_string),
+            })
+            .await;
+
+        result
+    }
+
+    async fn handle_command(
+        &mut self,
+        mut request: HyperionMessage,
+        global: &Global,
+    ) -> Result<HyperionResponse, JsonApiError> {
+        if let Some((priority, range)) = request.command.priority_mut() {
+            *priority = self.priority_compat.correct(*priority, range);
+        }
+
         request.validate()?;
 
+        if request.command.required_access() > self.access {
+            // Keep the connection open, the client may still log in
+            warn!("unauthorized command");
+            return Ok(HyperionResponse::error(AuthError::NoAuthorization));
+        }
+
+        let tan = request.tan;
+        if request.command.writes_config() && global.is_read_only().await {
+            return Err(JsonApiError::ReadOnly);
+        }
+
         match request.command {
             HyperionCommand::ClearAll => {
                 // Update state
@@ -88,10 +604,16 @@ impl ClientConnection {
                     .send(ComponentName::All, InputMessageData::ClearAll)?;
             }
 
-            HyperionCommand::Clear(message::Clear { priority }) => {
+            HyperionCommand::Clear(message::Clear { priority, instance }) => {
                 // Update state
-                self.source
-                    .send(ComponentName::All, InputMessageData::Clear { priority })?;
+                self.send_input(
+                    global,
+                    instance.as_ref(),
+                    ComponentName::All,
+                    InputMessageData::Clear { priority },
+                    None,
+                )
+                .await?;
             }
 
             HyperionCommand::Color(message::Color {
@@ -99,18 +621,24 @@ impl ClientConnection {
                 duration,
                 color,
                 origin: _,
+                instance,
+                smoothing,
             }) => {
                 // TODO: Handle origin field
 
                 // Update state
-                self.source.send(
+                self.send_input(
+                    global,
+                    instance.as_ref(),
                     ComponentName::Color,
                     InputMessageData::SolidColor {
                         priority,
                         duration: duration.map(|ms| chrono::Duration::milliseconds(ms as _)),
                         color,
                     },
-                )?;
+                    smoothing,
+                )
+                .await?;
             }
 
             HyperionCommand::Image(message::Image {
@@ -120,21 +648,43 @@ impl ClientConnection {
                 imageheight,
                 imagedata,
                 origin: _,
-                format: _,
+                format: message::ImageFormat::Auto,
                 scale: _,
                 name: _,
+                region,
+                smoothing,
             }) => {
-                // TODO: Handle origin, format, scale, name fields
+                // TODO: Handle origin, scale, name fields
 
-                let raw_image = RawImage::try_from((imagedata, imagewidth, imageheight))?;
+                let raw_image = if imagedata.len()
+                    == imagewidth as usize * imageheight as usize * RawImage::CHANNELS as usize
+                {
+                    RawImage::try_from((imagedata, imagewidth, imageheight))?
+                } else {
+                    crate::image::decoder::decode(&imagedata)?
+                };
+                let region = region.map(|region| {
+                    ImageRegion::clipped(
+                        region.x,
+                        region.y,
+                        region.width,
+                        region.height,
+                        (raw_image.width(), raw_image.height()),
+                    )
+                });
 
-                self.source.send(
-                    ComponentName::Image,
-                    InputMessageData::Image {
-                        priority,
-                        duration: duration.map(|ms| chrono::Duration::milliseconds(ms as _)),
-                        image: Arc::new(raw_image),
-                    },
+                self.source.channel().send(
+                    InputMessage::new(
+                        self.source.id(),
+                        ComponentName::Image,
+                        InputMessageData::Image {
+                            priority,
+                            duration: duration.map(|ms| chrono::Duration::milliseconds(ms as _)),
+                            image: Arc::new(raw_image),
+                            region,
+                        },
+                    )
+                    .with_smoothing(smoothing.map(Arc::new)),
                 )?;
             }
 
@@ -145,88 +695,428 @@ impl ClientConnection {
                 effect,
                 python_script: _,
                 image_data: _,
+                instance,
             }) => {
                 // TODO: Handle origin, python_script, image_data
 
-                let instance = self.current_instance(global).await?;
-                let (tx, rx) = oneshot::channel();
+                let instances = match instance {
+                    Some(target) => target_instances(global, &target).await?,
+                    None => vec![self.current_instance(global).await?],
+                };
 
-                instance
-                    .send(InputMessage::new(
-                        self.source.id(),
-                        ComponentName::All,
-                        InputMessageData::Effect {
-                            priority,
-                            duration: duration.map(|ms| chrono::Duration::milliseconds(ms as _)),
-                            effect: effect.into(),
-                            response: Arc::new(Mutex::new(Some(tx))),
-                        },
-                    ))
-                    .await?;
+                let effect = Arc::new(effect);
+                let mut responses = Vec::with_capacity(instances.len());
+
+                // Start the effect everywhere before waiting, so all instances play it in sync
+                for instance in instances {
+                    let (tx, rx) = oneshot::channel();
+
+                    instance
+                        .send(InputMessage::new(
+                            self.source.id(),
+                            ComponentName::All,
+                            InputMessageData::Effect {
+                                priority,
+                                duration: duration
+                                    .map(|ms| chrono::Duration::milliseconds(ms as _)),
+                                effect: effect.clone(),
+                                response: Arc::new(Mutex::new(Some(tx))),
+                            },
+                        ))
+                        .await?;
+
+                    responses.push(rx);
+                }
+
+                for rx in responses {
+                    rx.await??;
+                }
+
+                return Ok(HyperionResponse::success());
+            }
+
+            HyperionCommand::EffectUpdate(message::EffectUpdate { priority, args }) => {
+                self.current_instance(global)
+                    .await?
+                    .update_effect_args(priority, args)
+                    .await??;
+            }
+
+            HyperionCommand::ComponentState(message::ComponentState {
+                componentstate: message::ComponentStatus { component, state },
+                instance,
+            }) => {
+                let instances = match instance {
+                    Some(target) => target_instances(global, &target).await?,
+                    None => vec![self.current_instance(global).await?],
+                };
 
-                return Ok(rx.await?.map(|_| HyperionResponse::success())?);
+                for instance in instances {
+                    global
+                        .set_component_state(instance.id(), component, state)
+                        .await;
+                }
             }
 
-            HyperionCommand::ServerInfo(message::ServerInfoRequest { subscribe: _ }) => {
-                // TODO: Handle subscribe field
+            HyperionCommand::ServerInfo(server_info) => {
+                self.subscribe_state_updates(global, server_info.subscriptions())
+                    .await;
 
-                let (adjustments, priorities) =
-                    if let Ok(handle) = self.current_instance(global).await {
+                let instance = self.current_instance(global).await.ok();
+                let (adjustments, priorities, priorities_autoselect, components) =
+                    if let Some(handle) = &instance {
                         (
-                            handle
-                                .config()
-                                .await?
-                                .color
-                                .channel_adjustment
-                                .iter()
-                                .map(|adj| message::ChannelAdjustment::from(adj.clone()))
-                                .collect(),
+                            adjustments(&*handle.config().await?),
                             handle.current_priorities().await?,
+                            handle.priorities_autoselect().await?,
+                            components(global, handle.id()).await,
                         )
                     } else {
-                        Default::default()
+                        (Vec::new(), Vec::new(), true, Vec::new())
                     };
 
-                // Read effect info
-                // TODO: Add per-instance effects
+                // Read effect info, as available to the current instance
                 let effects: Vec<message::EffectDefinition> = global
-                    .read_effects(|effects| effects.iter().map(Into::into).collect())
+                    .read_effects(|effects| match &instance {
+                        Some(handle) => handle.effects().iter(effects).map(Into::into).collect(),
+                        None => effects.iter().map(Into::into).collect(),
+                    })
                     .await;
 
                 // Just answer the serverinfo request, no need to update state
-                return Ok(global
-                    .read_config(|config| {
-                        let instances = config
-                            .instances
-                            .iter()
-                            .map(|instance_config| (&instance_config.1.instance).into())
-                            .collect();
+                return Ok(HyperionResponse::server_info(
+                    priorities,
+                    priorities_autoselect,
+                    adjustments,
+                    effects,
+                    components,
+                    instance_infos(global).await,
+                    active_devices(global).await,
+                ));
+            }
+
+            HyperionCommand::Authorize(authorize) => {
+                return match self.authorize(authorize, global, tan).await {
+                    // Rejected credentials are not fatal, the client may try again
+                    Err(JsonApiError::Auth(error))
+                        if !matches!(error, AuthError::Db(_) | AuthError::Config(_)) =>
+                    {
+                        Ok(HyperionResponse::error(error))
+                    }
+                    result => result,
+                };
+            }
+
+            HyperionCommand::SysInfo => {
+                return Ok(HyperionResponse::sys_info(
+                    global.read_config(|config| config.uuid()).await,
+                    global.is_read_only().await,
+                    global.environment().await.as_deref().cloned(),
+                    message::HealthInfo::new(
+                        global
+                            .server_failures()
+                            .await
+                            .into_iter()
+                            .map(|(id, failure)| message::ServerStatusInfo::failed(id, failure))
+                            .collect(),
+                    ),
+                ));
+            }
+
+            HyperionCommand::BuildInfo => {
+                return Ok(HyperionResponse::build_info());
+            }
+
+            HyperionCommand::Config(message::Config {
+                subcommand: message::ConfigCommand::GetWarnings,
+                ..
+            }) => {
+                return Ok(HyperionResponse::config_warnings(
+                    global
+                        .read_config(|config| config.warnings())
+                        .await
+                        .into_iter()
+                        .map(Into::into)
+                        .collect(),
+                ));
+            }
+
+            HyperionCommand::Config(message::Config {
+                subcommand: message::ConfigCommand::GetConfig,
+                ..
+            }) => {
+                let instance = self.current_instance(global).await.ok().map(|i| i.id());
+                let settings = global
+                    .read_config(|config| -> Result<_, serde_json::Error> {
+                        let mut settings = config.global.settings()?;
+                        if let Some(instance) = instance.and_then(|id| config.instances.get(&id)) {
+                            settings.extend(instance.settings()?);
+                        }
 
-                        HyperionResponse::server_info(priorities, adjustments, effects, instances)
+                        Ok(settings
+                            .into_iter()
+                            .map(|(ty, setting)| (ty.to_owned(), setting))
+                            .collect())
                     })
-                    .await);
+                    .await
+                    .map_err(ConfigError::from)?;
+
+                return Ok(HyperionResponse::get_config(settings));
             }
 
-            HyperionCommand::Authorize(message::Authorize { subcommand, .. }) => match subcommand {
-                message::AuthorizeCommand::AdminRequired => {
-                    // TODO: Perform actual authentication flow
-                    return Ok(HyperionResponse::admin_required(false));
+            HyperionCommand::Config(message::Config {
+                subcommand: message::ConfigCommand::SetConfig,
+                config,
+            }) => {
+                let instance = self.current_instance(global).await.ok().map(|i| i.id());
+                set_config(global, instance, config).await?;
+            }
+
+            HyperionCommand::Config(message::Config {
+                subcommand: message::ConfigCommand::Reload,
+                ..
+            }) => {
+                match global
+                    .read_database_path(|path| path.map(ToOwned::to_owned))
+                    .await
+                {
+                    Some(path) => {
+                        let _update = global.lock_config_update().await;
+                        let config = DbBackend::new(Db::open_read_only(&path).await?)
+                            .load()
+                            .await?;
+                        global.apply_config(config).await?;
+                    }
+                    // Configuration files are only read on startup
+                    None => global.request_reload().await,
                 }
-                message::AuthorizeCommand::TokenRequired => {
-                    // TODO: Perform actual authentication flow
-                    return Ok(HyperionResponse::token_required(false));
+            }
+
+            HyperionCommand::Blackout(message::Blackout { enable }) => {
+                if let Some(enable) = enable {
+                    global.set_blackout(enable).await;
                 }
-                _ => {
-                    return Err(JsonApiError::NotImplemented);
+
+                return Ok(HyperionResponse::blackout(global.is_blackout().await));
+            }
+
+            HyperionCommand::BlackBorder(message::BlackBorder {
+                subcommand,
+                duration,
+            }) => {
+                let control = match subcommand {
+                    message::BlackBorderCommand::GetState => None,
+                    message::BlackBorderCommand::Reset => Some(BlackBorderControl::Reset),
+                    message::BlackBorderCommand::Enable => Some(BlackBorderControl::Enable),
+                    message::BlackBorderCommand::Disable => Some(BlackBorderControl::Disable {
+                        duration: duration.map(std::time::Duration::from_millis),
+                    }),
+                };
+
+                let instance = self.current_instance(global).await?;
+                return Ok(HyperionResponse::black_border(
+                    instance.black_border(control).await?.into(),
+                ));
+            }
+
+            HyperionCommand::Server(message::Server {
+                subcommand,
+                server,
+                instance,
+            }) => {
+                if !matches!(subcommand, message::ServerCommand::List) {
+                    let id = match server.ok_or(JsonApiError::MissingArgument("server"))? {
+                        message::ServerName::Json => ServerId::Json,
+                        message::ServerName::Web => ServerId::Web,
+                        message::ServerName::Flatbuffers => ServerId::Flatbuffers,
+                        message::ServerName::Protobuf => ServerId::Protobuf,
+                        message::ServerName::Boblight => ServerId::Boblight {
+                            instance: match instance {
+                                Some(id) => id,
+                                None => self.current_instance(global).await?.id(),
+                            },
+                        },
+                        message::ServerName::UdpListener => ServerId::UdpListener {
+                            instance: match instance {
+                                Some(id) => id,
+                                None => self.current_instance(global).await?.id(),
+                            },
+                        },
+                    };
+
+                    if matches!(subcommand, message::ServerCommand::Start) {
+                        // The port may be used by another program, which is not fatal
+                        if let Err(error) = global.start_server(id).await {
+                            return Ok(HyperionResponse::error(error));
+                        }
+                    } else if id.is_enabled_always() {
+                        return Err(JsonApiError::RequiredServer(id));
+                    } else {
+                        global.stop_server(id).await;
+                    }
                 }
-            },
 
-            HyperionCommand::SysInfo => {
-                return Ok(HyperionResponse::sys_info(
-                    global.read_config(|config| config.uuid()).await,
+                let mut servers: Vec<_> = global
+                    .servers()
+                    .await
+                    .into_iter()
+                    .map(|(id, port)| message::ServerStatusInfo::running(id, port))
+                    .collect();
+                servers.extend(
+                    global
+                        .server_failures()
+                        .await
+                        .into_iter()
+                        .map(|(id, failure)| message::ServerStatusInfo::failed(id, failure)),
+                );
+
+                return Ok(HyperionResponse::servers(servers));
+            }
+
+            HyperionCommand::CommandTraces(message::CommandTracesRequest { count }) => {
+                return Ok(HyperionResponse::command_traces(
+                    global
+                        .read_command_traces(|traces| {
+                            traces
+                                .iter()
+                                .take(count.unwrap_or(usize::MAX))
+                                .map(Into::into)
+                                .collect()
+                        })
+                        .await,
+                ));
+            }
+
+            HyperionCommand::Clients => {
+                return Ok(HyperionResponse::clients(
+                    global.read_clients(|clients| clients.into()).await,
+                ));
+            }
+
+            HyperionCommand::Forwarder => {
+                return Ok(HyperionResponse::forwarder(
+                    global
+                        .forwarder_targets()
+                        .await
+                        .iter()
+                        .map(Into::into)
+                        .collect(),
                 ));
             }
 
+            HyperionCommand::PriorityTimeline(message::PriorityTimelineRequest { count }) => {
+                let instance = self.current_instance(global).await?;
+                return Ok(HyperionResponse::priority_timeline(
+                    instance
+                        .priority_timeline()
+                        .await?
+                        .iter()
+                        .take(count.unwrap_or(usize::MAX))
+                        .map(Into::into)
+                        .collect(),
+                ));
+            }
+
+            HyperionCommand::LedColors(message::LedColors {
+                subcommand,
+                oneshot,
+                interval,
+                format,
+            }) => match subcommand {
+                message::LedColorsSubcommand::LedStreamStart => {
+                    self.negotiated("ledstream");
+                    self.led_stream = Some(Subscription::new(interval, oneshot, tan));
+                }
+                message::LedColorsSubcommand::LedStreamStop => {
+                    self.led_stream = None;
+                }
+                message::LedColorsSubcommand::ImageStreamStart => {
+                    let format = format.unwrap_or_default();
+                    if format == message::ImageStreamFormat::Jpg && !cfg!(feature = "jpeg") {
+                        return Err(RawImageError::UnsupportedFormat.into());
+                    }
+
+                    self.negotiated(match format {
+                        message::ImageStreamFormat::Jpg => "imagestream:jpg",
+                        message::ImageStreamFormat::Raw => "imagestream:raw",
+                    });
+                    self.image_stream = Some(Subscription::new(interval, oneshot, tan));
+                    self.image_stream_format = format;
+                    self.streamed_image = None;
+                }
+                message::LedColorsSubcommand::ImageStreamStop => {
+                    self.image_stream = None;
+                    self.streamed_image = None;
+                }
+                message::LedColorsSubcommand::TestLed => return Err(JsonApiError::NotImplemented),
+            },
+
+            HyperionCommand::Database(message::Database {
+                subcommand,
+                snapshot,
+            }) => {
+                let path = global
+                    .read_database_path(|path| path.map(ToOwned::to_owned))
+                    .await
+                    .ok_or(JsonApiError::NoDatabase)?;
+
+                match subcommand {
+                    message::DatabaseCommand::Backup => {
+                        let mut db = Db::open_read_only(&path).await?;
+                        return Ok(HyperionResponse::database_backup(&db.backup().await?));
+                    }
+                    message::DatabaseCommand::Restore => {
+                        let snapshot = base64::engine::general_purpose::STANDARD
+                            .decode(snapshot.ok_or(JsonApiError::MissingSnapshot)?)?;
+
+                        let _update = global.lock_config_update().await;
+                        let config = Db::restore(&path, &snapshot).await?;
+                        global.apply_config(config).await?;
+
+                        // Restart everything, including what isn't part of the configuration
+                        // such as the auth tokens
+                        global.request_reload().await;
+                    }
+                }
+            }
+
+            HyperionCommand::Instance(message::Instance {
+                subcommand: message::InstanceCommand::CreateInstance,
+                name: Some(name),
+                template,
+                mut params,
+                ..
+            }) => {
+                let path = global
+                    .read_database_path(|path| path.map(ToOwned::to_owned))
+                    .await
+                    .ok_or(JsonApiError::NoDatabase)?;
+
+                let _update = global.lock_config_update().await;
+                let mut backend = DbBackend::new(Db::open(&path).await?);
+                let id = backend.next_instance_id().await?;
+
+                let config = match template {
+                    Some(template) => {
+                        params.name = Some(name);
+                        template.instantiate(id, &params)
+                    }
+                    None => {
+                        let mut config = InstanceConfig::new_dummy(id);
+                        config.instance.friendly_name = name;
+                        config
+                    }
+                };
+
+                backend.create_instance(&config).await?;
+                info!(id, template = ?template, "created instance");
+
+                // Adding an instance restarts everything to start it
+                let mut updated = global.read_config(Clone::clone).await;
+                updated.instances.insert(id, config);
+                global.apply_config(updated).await?;
+            }
+
             HyperionCommand::Instance(message::Instance {
                 subcommand: message::InstanceCommand::SwitchTo,
                 instance: Some(id),
@@ -242,6 +1132,168 @@ impl ClientConnection {
                 }
             }
 
+            HyperionCommand::Adjustment(message::Adjustment {
+                adjustment,
+                persist,
+            }) => {
+                let id = self.current_instance(global).await?.id();
+                let mut color = global
+                    .read_config(|config| {
+                        config
+                            .instances
+                            .get(&id)
+                            .map(|instance| instance.color.clone())
+                    })
+                    .await
+                    .ok_or(JsonApiError::InstanceNotFound)?;
+
+                let channel = match &adjustment.id {
+                    Some(name) => color
+                        .channel_adjustment
+                        .iter_mut()
+                        .find(|channel| &channel.id == name)
+                        .ok_or_else(|| JsonApiError::UnknownAdjustment(name.clone()))?,
+                    None => color
+                        .channel_adjustment
+                        .first_mut()
+                        .ok_or(JsonApiError::NoAdjustment)?,
+                };
+                adjustment.apply(channel);
+
+                if persist {
+                    save_settings(
+                        global,
+                        &[DbSetting {
+                            ty: "color".to_owned(),
+                            config: serde_json::to_string(&color).map_err(ConfigError::from)?,
+                            hyperion_inst: Some(id),
+                            updated_at: chrono::Utc::now().to_rfc3339(),
+                        }],
+                    )
+                    .await?;
+                }
+
+                global.set_color_adjustment(id, color).await?;
+            }
+
+            HyperionCommand::LedGainRamp(message::LedGainRamp {
+                first,
+                last,
+                start,
+                end,
+                apply,
+                persist,
+            }) => {
+                let led_gain = LedGain::ramp(first, last, start.into(), end.into());
+                if !apply && !persist {
+                    return Ok(HyperionResponse::led_gain_ramp(led_gain));
+                }
+
+                let id = self.current_instance(global).await?.id();
+                let mut color = global
+                    .read_config(|config| {
+                        config
+                            .instances
+                            .get(&id)
+                            .map(|instance| instance.color.clone())
+                    })
+                    .await
+                    .ok_or(JsonApiError::InstanceNotFound)?;
+
+                // Drop the gains the ramp replaces entirely, the others are overridden
+                let (first, last) = (first.min(last), first.max(last));
+                color.led_gain.retain(|led_gain| {
+                    let leds = LedMatch::from(led_gain.leds.as_str());
+                    !(leds.max_index().is_some_and(|max| max <= last)
+                        && leds.indices(last + 1).iter().all(|index| *index >= first))
+                });
+                color.led_gain.extend(led_gain.iter().cloned());
+
+                if persist {
+                    save_settings(
+                        global,
+                        &[DbSetting {
+                            ty: "color".to_owned(),
+                            config: serde_json::to_string(&color).map_err(ConfigError::from)?,
+                            hyperion_inst: Some(id),
+                            updated_at: chrono::Utc::now().to_rfc3339(),
+                        }],
+                    )
+                    .await?;
+                }
+
+                global.set_color_adjustment(id, color).await?;
+                return Ok(HyperionResponse::led_gain_ramp(led_gain));
+            }
+
+            HyperionCommand::SourceSelect(message::SourceSelect { priority, auto }) => {
+                let priority = if auto == Some(true) {
+                    None
+                } else {
+                    Some(priority.ok_or(JsonApiError::MissingArgument("priority"))?)
+                };
+
+                // Selecting a priority which is not set is not fatal
+                if let Err(error) = self
+                    .current_instance(global)
+                    .await?
+                    .select_priority(priority)
+                    .await?
+                {
+                    return Ok(HyperionResponse::error(error));
+                }
+            }
+
+            HyperionCommand::EffectCreate(message::EffectCreate {
+                name,
+                script,
+                args,
+                image_data,
+            }) => {
+                let effect = NewEffect {
+                    name,
+                    script,
+                    args,
+                    image: image_data.map(|data| data.0),
+                };
+
+                // Name collisions and unknown scripts are not fatal
+                if let Err(error) = create_effect(global, effect).await {
+                    return Ok(HyperionResponse::error(error));
+                }
+            }
+
+            HyperionCommand::EffectDelete(message::EffectDelete { name }) => {
+                if let Err(error) = delete_effect(global, &name).await {
+                    return Ok(HyperionResponse::error(error));
+                }
+            }
+
+            HyperionCommand::Logging(message::Logging {
+                subcommand,
+                oneshot,
+                interval,
+            }) => match subcommand {
+                message::LoggingCommand::Start | message::LoggingCommand::Update => {
+                    let log_buffer = global.log_buffer().await.ok_or(JsonApiError::NoLogBuffer)?;
+                    self.negotiated("logging");
+
+                    // The stream starts with the recent records, update sends them again
+                    let (pending, receiver) = log_buffer.subscribe();
+                    self.log_stream = Some(LogStream {
+                        pending,
+                        receiver,
+                        interval: interval.map(|ms| Duration::from_millis(ms as _)),
+                        deadline: None,
+                        oneshot: oneshot.unwrap_or(false),
+                        tan,
+                    });
+                }
+                message::LoggingCommand::Stop => {
+                    self.log_stream = None;
+                }
+            },
+
             _ => return Err(JsonApiError::NotImplemented),
         };
 
@@ -249,6 +1301,261 @@ impl ClientConnection {
     }
 }
 
+/// Encode an image for the image stream
+fn encode_stream_image(
+    image: &RawImage,
+    format: message::ImageStreamFormat,
+) -> Result<HyperionResponse, RawImageError> {
+    match format {
+        #[cfg(feature = "jpeg")]
+        message::ImageStreamFormat::Jpg => {
+            let mut jpeg = Vec::new();
+            image.write_jpeg(&mut jpeg, STREAM_JPEG_QUALITY)?;
+
+            Ok(HyperionResponse::image_stream_update(
+                format!(
+                    "data:image/jpg;base64,{}",
+                    base64::engine::general_purpose::STANDARD.encode(&jpeg)
+                ),
+                None,
+                None,
+            ))
+        }
+        #[cfg(not(feature = "jpeg"))]
+        message::ImageStreamFormat::Jpg => Err(RawImageError::UnsupportedFormat),
+        message::ImageStreamFormat::Raw => Ok(HyperionResponse::image_stream_update(
+            base64::engine::general_purpose::STANDARD.encode(image.data()),
+            Some(image.width()),
+            Some(image.height()),
+        )),
+    }
+}
+
+/// Wait for the next log records to stream to a client
+///
+/// This is cancel-safe: received records are kept until they are returned.
+async fn next_log_records(stream: &mut Option<LogStream>) -> (Option<i32>, Vec<Arc<LogRecord>>) {
+    loop {
+        let current = match stream.as_mut() {
+            Some(current) => current,
+            None => return futures::future::pending().await,
+        };
+
+        if current.pending.is_empty() {
+            match current.receiver.recv().await {
+                Ok(record) => current.pending.push(record),
+                // Not logged, this would only add to the records the client can't keep up with
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    *stream = None;
+                    continue;
+                }
+            }
+        }
+
+        // Batch the records received during the interval
+        if let Some(interval) = current.interval {
+            let deadline = *current
+                .deadline
+                .get_or_insert_with(|| tokio::time::Instant::now() + interval);
+            tokio::time::sleep_until(deadline).await;
+            current.deadline = None;
+        }
+
+        while let Ok(record) = current.receiver.try_recv() {
+            current.pending.push(record);
+        }
+
+        let result = (current.tan, std::mem::take(&mut current.pending));
+        if current.oneshot {
+            *stream = None;
+        }
+
+        return result;
+    }
+}
+
+/// Wait for the next global event, if the client subscribed to state updates
+///
+/// This never completes if the client has no state update subscription.
+async fn next_event(events: &mut Option<broadcast::Receiver<Event>>) -> Event {
+    loop {
+        let receiver = match events.as_mut() {
+            Some(receiver) => receiver,
+            None => return futures::future::pending().await,
+        };
+
+        match receiver.recv().await {
+            Ok(event) => return event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(skipped = %skipped, "client missed state updates");
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                *events = None;
+            }
+        }
+    }
+}
+
+/// Wait for the answer to the token request of a client
+///
+/// This never completes if the client has no pending token request.
+async fn token_request_answer(
+    request: &mut Option<(Option<i32>, TokenRequest)>,
+) -> (Option<i32>, HyperionResponse) {
+    let (tan, pending) = match request.as_mut() {
+        Some((tan, pending)) => (*tan, pending),
+        None => return futures::future::pending().await,
+    };
+
+    let response = match pending.answer().await {
+        Some(token) => {
+            HyperionResponse::request_token(pending.comment.clone(), pending.id.clone(), token)
+        }
+        None => HyperionResponse::error("Token request timeout or denied"),
+    };
+
+    *request = None;
+    (tan, response)
+}
+
+/// Save settings to the configuration database and apply them
+///
+/// Instance settings apply to `instance`, the current instance of the client.
+async fn set_config(
+    global: &Global,
+    instance: Option<i32>,
+    settings: serde_json::Map<String, serde_json::Value>,
+) -> Result<(), JsonApiError> {
+    let updated_at = chrono::Utc::now().to_rfc3339();
+    let _update = global.lock_config_update().await;
+    let mut config = global.read_config(Clone::clone).await;
+    let mut rows = Vec::with_capacity(settings.len());
+
+    for (ty, setting) in settings {
+        let mut row = DbSetting {
+            ty,
+            config: setting.to_string(),
+            hyperion_inst: None,
+            updated_at: updated_at.clone(),
+        };
+
+        // Parsing the row validates the setting like when loading the database
+        let mut setting = Setting::try_from(row.clone()).map_err(ConfigError::from)?;
+        if !setting.config.is_global() {
+            row.hyperion_inst = Some(instance.ok_or(JsonApiError::InstanceNotFound)?);
+            setting.hyperion_inst = row.hyperion_inst;
+        }
+
+        config.apply_setting(setting)?;
+        rows.push(row);
+    }
+
+    save_settings(global, &rows).await?;
+    Ok(global.apply_config(config).await?)
+}
+
+/// Write setting rows to the configuration database
+async fn save_settings(global: &Global, rows: &[DbSetting]) -> Result<(), JsonApiError> {
+    let path = global
+        .read_database_path(|path| path.map(ToOwned::to_owned))
+        .await
+        .ok_or(JsonApiError::NoDatabase)?;
+
+    DbBackend::new(Db::open(&path).await?)
+        .save_settings(rows)
+        .await?;
+    info!(settings = ?rows.iter().map(|row| &row.ty).collect::<Vec<_>>(), "saved settings");
+
+    Ok(())
+}
+
+/// State of the components of an instance
+async fn components(global: &Global, instance: i32) -> Vec<message::ComponentInfo> {
+    let states = global.component_states().await;
+
+    ComponentName::INSTANCE_COMPONENTS
+        .iter()
+        .map(|&name| message::ComponentInfo {
+            name,
+            enabled: states.is_enabled(instance, name),
+        })
+        .collect()
+}
+
+/// Device state and statistics of all running instances
+async fn active_devices(global: &Global) -> Vec<message::ActiveLedDevice> {
+    let mut devices = Vec::new();
+
+    for (_, handle) in global.instances().await {
+        // Skip instances stopping in the meantime
+        if let (Ok(config), Ok(info)) = (handle.config().await, handle.device_info().await) {
+            devices.push(message::ActiveLedDevice::new(
+                &config.instance,
+                &config.device,
+                info,
+            ));
+        }
+    }
+
+    devices
+}
+
+/// Resolve the instances targeted by a command
+///
+/// All ids are checked before returning, so a command naming a stopped instance is rejected
+/// without being applied to the others.
+async fn target_instances(
+    global: &Global,
+    target: &InstanceTarget,
+) -> Result<Vec<InstanceHandle>, JsonApiError> {
+    let running = global.instances().await;
+
+    let ids = match target {
+        InstanceTarget::All(_) => return Ok(running.into_iter().map(|(_, h)| h).collect()),
+        InstanceTarget::One(id) => std::slice::from_ref(id),
+        InstanceTarget::Many(ids) => &ids[..],
+    };
+
+    ids.iter()
+        .map(|id| {
+            running
+                .iter()
+                .find(|(running_id, _)| running_id == id)
+                .map(|(_, handle)| handle.clone())
+                .ok_or(JsonApiError::UnknownInstance(*id))
+        })
+        .collect()
+}
+
+/// Configured instances, with their current running state
+async fn instance_infos(global: &Global) -> Vec<message::InstanceInfo> {
+    let mut instances: Vec<message::InstanceInfo> = global
+        .read_config(|config| {
+            config
+                .instances
+                .values()
+                .map(|instance_config| (&instance_config.instance).into())
+                .collect()
+        })
+        .await;
+
+    for instance in &mut instances {
+        instance.running = global.get_instance(instance.instance).await.is_some();
+    }
+
+    instances
+}
+
+fn adjustments(config: &InstanceConfig) -> Vec<message::ChannelAdjustment> {
+    config
+        .color
+        .channel_adjustment
+        .iter()
+        .map(|adj| message::ChannelAdjustment::from(adj.clone()))
+        .collect()
+}
+
 impl std::fmt::Debug for ClientConnection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ClientConnection")
@@ -256,3 +1563,131 @@ impl std::fmt::Debug for ClientConnection {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sqlx::{sqlite::SqliteConnectOptions, Connection, Executor, SqliteConnection};
+
+    use super::*;
+    use crate::{
+        global::{GlobalData, InputSourceName, Paths},
+        models::{Config, GlobalConfig},
+    };
+
+    fn settings(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_set_config_invalid() {
+        let config = Config::new(GlobalConfig::default(), []);
+        let global = GlobalData::new(&config, Paths::new(None).unwrap()).wrap();
+
+        // Rejected before anything is saved or applied
+        assert!(
+            set_config(&global, None, settings(json!({"jsonServer": {"port": 80}})))
+                .await
+                .is_err()
+        );
+        assert!(set_config(&global, None, settings(json!({"unknown": {}})))
+            .await
+            .is_err());
+        assert_eq!(global.read_config(Clone::clone).await, config);
+    }
+
+    #[tokio::test]
+    async fn test_set_config_concurrent() {
+        let dir = std::env::temp_dir().join(format!("hyperion-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("hyperion.db");
+
+        let mut connection = SqliteConnection::connect_with(
+            &SqliteConnectOptions::new()
+                .filename(&path)
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        connection
+            .execute(include_str!("../../create-db.sql"))
+            .await
+            .unwrap();
+        connection.close().await.unwrap();
+
+        let config = Config::new(GlobalConfig::default(), []);
+        let global = GlobalData::new(&config, Paths::new(None).unwrap())
+            .database_path(&path)
+            .wrap();
+
+        // Both updates are kept, instead of the last one applied overwriting the other
+        let (json_server, general) = futures::join!(
+            set_config(
+                &global,
+                None,
+                settings(json!({"jsonServer": {"port": 20000}}))
+            ),
+            set_config(
+                &global,
+                None,
+                settings(json!({"general": {"name": "Living room"}}))
+            ),
+        );
+        json_server.unwrap();
+        general.unwrap();
+
+        let (port, name) = global
+            .read_config(|config| {
+                (
+                    config.global.json_server.port,
+                    config.global.general.name.clone(),
+                )
+            })
+            .await;
+        assert_eq!(port, 20000);
+        assert_eq!(name, "Living room");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let global = GlobalData::new(
+            &Config::new(GlobalConfig::default(), []),
+            Paths::new(None).unwrap(),
+        )
+        .read_only(true)
+        .wrap();
+        let mut connection = ClientConnection::new(
+            global
+                .register_input_source(
+                    InputSourceName::Json {
+                        peer_addr: "127.0.0.1:19444".parse().unwrap(),
+                    },
+                    None,
+                )
+                .await
+                .unwrap(),
+            AccessLevel::Admin,
+        );
+
+        for request in [
+            json!({"command": "config", "subcommand": "setconfig", "config": {}}),
+            json!({"command": "database", "subcommand": "restore"}),
+            json!({"command": "delete-effect", "name": "Rainbow swirl"}),
+            json!({"command": "instance", "subcommand": "createInstance", "name": "Living room"}),
+            json!({"command": "authorize", "subcommand": "deleteToken", "id": "abcde"}),
+        ] {
+            let request: HyperionMessage = serde_json::from_value(request).unwrap();
+            assert!(request.command.writes_config());
+            assert!(matches!(
+                connection.handle_request(request, &global).await,
+                Err(JsonApiError::ReadOnly)
+            ));
+        }
+
+        // Commands which don't change the configuration are still accepted
+        let request = serde_json::from_value(json!({"command": "sysinfo"})).unwrap();
+        assert!(connection.handle_request(request, &global).await.is_ok());
+    }
+}
@@ -1,18 +1,94 @@
 use std::{
+    collections::BTreeMap,
+    ops::RangeInclusive,
     path::PathBuf,
     process::{Command, Stdio},
+    sync::Arc,
 };
 
+use base64::Engine;
 use serde_derive::{Deserialize, Serialize};
 use validator::Validate;
 
-use crate::{api::types::PriorityInfo, component::ComponentName, models::Color as RgbColor};
+use crate::{
+    api::types::PriorityInfo,
+    component::ComponentName,
+    forwarder::{ForwarderTarget, TargetProtocol},
+    global::{
+        feature_matrix, version, AccessLevel, ApiToken, ClientProtocol, ClientRegistry,
+        EnvironmentReport, PendingTokenRequest,
+    },
+    models::{Color as RgbColor, SmoothingOverride},
+    servers::{ServerFailure, ServerId},
+};
 
 /// Change color adjustement values
 #[derive(Debug, Deserialize, Validate)]
 pub struct Adjustment {
     #[validate(nested)]
-    pub adjustment: ChannelAdjustment,
+    pub adjustment: ChannelAdjustmentUpdate,
+    /// Save the updated adjustment to the configuration database (hyperion.rs extension)
+    #[serde(default)]
+    pub persist: bool,
+}
+
+/// Values to change in a channel adjustment
+///
+/// The adjustment is selected by `id`, defaulting to the first one of the instance.
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelAdjustmentUpdate {
+    pub id: Option<String>,
+    pub white: Option<RgbColor>,
+    pub red: Option<RgbColor>,
+    pub green: Option<RgbColor>,
+    pub blue: Option<RgbColor>,
+    pub cyan: Option<RgbColor>,
+    pub magenta: Option<RgbColor>,
+    pub yellow: Option<RgbColor>,
+    #[validate(range(min = 0, max = 100))]
+    pub backlight_threshold: Option<u32>,
+    pub backlight_colored: Option<bool>,
+    #[validate(range(min = 0, max = 100))]
+    pub brightness: Option<u32>,
+    #[validate(range(min = 0, max = 100))]
+    pub brightness_compensation: Option<u32>,
+    #[validate(range(min = 0.1, max = 5.0))]
+    pub gamma_red: Option<f32>,
+    #[validate(range(min = 0.1, max = 5.0))]
+    pub gamma_green: Option<f32>,
+    #[validate(range(min = 0.1, max = 5.0))]
+    pub gamma_blue: Option<f32>,
+}
+
+impl ChannelAdjustmentUpdate {
+    /// Change the values of `adjustment` present in this update
+    pub fn apply(self, adjustment: &mut crate::models::ChannelAdjustment) {
+        macro_rules! update {
+            ($($field:ident),*) => {
+                $(if let Some(value) = self.$field {
+                    adjustment.$field = value;
+                })*
+            };
+        }
+
+        update!(
+            white,
+            red,
+            green,
+            blue,
+            cyan,
+            magenta,
+            yellow,
+            backlight_threshold,
+            backlight_colored,
+            brightness,
+            brightness_compensation,
+            gamma_red,
+            gamma_green,
+            gamma_blue
+        );
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
@@ -98,10 +174,28 @@ pub struct Authorize {
     pub accept: Option<bool>,
 }
 
+/// Marker for the `"all"` instance target
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum AllInstances {
+    #[serde(rename = "all")]
+    All,
+}
+
+/// Instances a command applies to, instead of the current instance of the client
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum InstanceTarget {
+    /// Every running instance
+    All(AllInstances),
+    One(i32),
+    Many(Vec<i32>),
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct Clear {
     #[validate(range(min = -1, max = 253))]
     pub priority: i32,
+    pub instance: Option<InstanceTarget>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -115,6 +209,10 @@ pub struct Color {
     #[validate(length(min = 4, max = 20))]
     pub origin: Option<String>,
     pub color: RgbColor,
+    pub instance: Option<InstanceTarget>,
+    /// Smoothing settings to use while this color is visible (hyperion.rs extension)
+    #[validate(nested)]
+    pub smoothing: Option<SmoothingOverride>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -126,6 +224,7 @@ pub struct ComponentStatus {
 #[derive(Debug, Deserialize, Validate)]
 pub struct ComponentState {
     pub componentstate: ComponentStatus,
+    pub instance: Option<InstanceTarget>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -135,6 +234,7 @@ pub enum ConfigCommand {
     GetConfig,
     GetSchema,
     Reload,
+    GetWarnings,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -144,13 +244,29 @@ pub struct Config {
     pub config: serde_json::Map<String, serde_json::Value>,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseCommand {
+    Backup,
+    Restore,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct Database {
+    pub subcommand: DatabaseCommand,
+    /// Base64-encoded database snapshot, for the restore subcommand
+    pub snapshot: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ImageData(#[serde(deserialize_with = "crate::serde::from_base64")] pub Vec<u8>);
 
 #[derive(Debug, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct EffectCreate {
+    #[validate(length(min = 1))]
     pub name: String,
+    #[validate(length(min = 1))]
     pub script: String,
     pub args: serde_json::Map<String, serde_json::Value>,
     pub image_data: Option<ImageData>,
@@ -159,6 +275,7 @@ pub struct EffectCreate {
 #[derive(Debug, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct EffectDelete {
+    #[validate(length(min = 1))]
     pub name: String,
 }
 
@@ -184,12 +301,22 @@ pub struct Effect {
     pub effect: EffectRequest,
     pub python_script: Option<String>,
     pub image_data: Option<ImageData>,
+    pub instance: Option<InstanceTarget>,
+}
+
+/// Update the arguments of a running effect, without restarting it if its provider supports it
+#[derive(Debug, Deserialize, Validate)]
+pub struct EffectUpdate {
+    #[validate(range(min = 1, max = 253))]
+    pub priority: i32,
+    pub args: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 #[derive(Default)]
 pub enum ImageFormat {
+    /// Raw RGB data if the size matches the dimensions, otherwise an encoded image
     #[default]
     Auto,
 }
@@ -211,6 +338,19 @@ pub struct Image {
     #[validate(range(min = 25, max = 2000))]
     pub scale: Option<i32>,
     pub name: Option<String>,
+    /// Part of the image that changed since the previous one sent on this priority
+    pub region: Option<ImageRegion>,
+    /// Smoothing settings to use while this image is visible (hyperion.rs extension)
+    #[validate(nested)]
+    pub smoothing: Option<SmoothingOverride>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ImageRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -231,6 +371,12 @@ pub struct Instance {
     pub instance: Option<i32>,
     #[validate(length(min = 5))]
     pub name: Option<String>,
+    /// Template for createInstance, the instance has default settings otherwise
+    pub template: Option<crate::models::InstanceTemplate>,
+    /// Parameters of the template
+    #[serde(default)]
+    #[validate(nested)]
+    pub params: crate::models::TemplateParams,
 }
 
 #[derive(Debug, Deserialize)]
@@ -253,6 +399,27 @@ pub struct LedColors {
     pub oneshot: Option<bool>,
     #[validate(range(min = 50))]
     pub interval: Option<u32>,
+    /// Encoding of streamed images, JPEG by default if supported
+    pub format: Option<ImageStreamFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStreamFormat {
+    /// JPEG data URL
+    Jpg,
+    /// Base64-encoded RGB data
+    Raw,
+}
+
+impl Default for ImageStreamFormat {
+    fn default() -> Self {
+        if cfg!(feature = "jpeg") {
+            Self::Jpg
+        } else {
+            Self::Raw
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -298,15 +465,174 @@ pub struct Processing {
     pub mapping_type: MappingType,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct Blackout {
+    /// New blackout state, or None to only query it
+    pub enable: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlackBorderCommand {
+    GetState,
+    Reset,
+    Enable,
+    Disable,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BlackBorder {
+    pub subcommand: BlackBorderCommand,
+    /// Duration of the disable command in milliseconds, or None to disable until enabled again
+    #[validate(range(min = 1))]
+    pub duration: Option<u64>,
+}
+
+/// Brightness of the red, green and blue channels of a LED, in any unit
+#[derive(Debug, Clone, Copy, Deserialize, Validate)]
+pub struct MeasuredBrightness {
+    #[validate(range(exclusive_min = 0.0))]
+    pub red: f32,
+    #[validate(range(exclusive_min = 0.0))]
+    pub green: f32,
+    #[validate(range(exclusive_min = 0.0))]
+    pub blue: f32,
+}
+
+impl From<MeasuredBrightness> for [f32; 3] {
+    fn from(brightness: MeasuredBrightness) -> Self {
+        [brightness.red, brightness.green, brightness.blue]
+    }
+}
+
+/// Generate a linear gain ramp between two measured LEDs (hyperion.rs extension)
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct LedGainRamp {
+    pub first: usize,
+    pub last: usize,
+    /// Brightness measured on the first LED
+    #[validate(nested)]
+    pub start: MeasuredBrightness,
+    /// Brightness measured on the last LED
+    #[validate(nested)]
+    pub end: MeasuredBrightness,
+    /// Replace the gains of the ramp LEDs in the current instance
+    #[serde(default)]
+    pub apply: bool,
+    /// Save the updated gains to the configuration database
+    #[serde(default)]
+    pub persist: bool,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CommandTracesRequest {
+    /// Maximum number of commands to return
+    #[validate(range(min = 1))]
+    pub count: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PriorityTimelineRequest {
+    /// Maximum number of events to return
+    #[validate(range(min = 1))]
+    pub count: Option<usize>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct ServerInfoRequest {
     pub subscribe: Option<Vec<serde_json::Value>>,
 }
 
+impl ServerInfoRequest {
+    /// Supported state updates requested in the `subscribe` field
+    ///
+    /// `all` subscribes to every supported update, unsupported ones are ignored.
+    pub fn subscriptions(&self) -> Vec<ServerInfoSubscription> {
+        let mut subscriptions = Vec::new();
+
+        for name in self.subscribe.iter().flatten() {
+            let name = name.as_str().unwrap_or_default();
+
+            if name == "all" {
+                return ServerInfoSubscription::ALL.to_vec();
+            }
+
+            match ServerInfoSubscription::ALL
+                .iter()
+                .find(|subscription| subscription.name() == name)
+            {
+                Some(subscription) => subscriptions.push(*subscription),
+                None => debug!(name = %name, "unsupported serverinfo subscription"),
+            }
+        }
+
+        subscriptions
+    }
+}
+
+/// State updates a client can subscribe to through `serverinfo`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerInfoSubscription {
+    Components,
+    Priorities,
+    Instance,
+    Adjustment,
+}
+
+impl ServerInfoSubscription {
+    pub const ALL: [Self; 4] = [
+        Self::Components,
+        Self::Priorities,
+        Self::Instance,
+        Self::Adjustment,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Components => "components-update",
+            Self::Priorities => "priorities-update",
+            Self::Instance => "instance-update",
+            Self::Adjustment => "adjustment-update",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerName {
+    Json,
+    Web,
+    Flatbuffers,
+    Protobuf,
+    Boblight,
+    #[serde(rename = "udplistener")]
+    UdpListener,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerCommand {
+    List,
+    Start,
+    Stop,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct Server {
+    pub subcommand: ServerCommand,
+    /// Server to start or stop
+    pub server: Option<ServerName>,
+    /// Instance of the Boblight server or UDP listener, defaults to the current instance
+    pub instance: Option<i32>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct SourceSelect {
+    /// Priority to show regardless of lower priorities
     #[validate(range(min = 0, max = 255))]
-    pub priority: i32,
+    pub priority: Option<i32>,
+    /// Go back to showing the lowest priority
     pub auto: Option<bool>,
 }
 
@@ -332,23 +658,39 @@ pub struct VideoModeRequest {
 pub enum HyperionCommand {
     Adjustment(Adjustment),
     Authorize(Authorize),
+    #[serde(rename = "blackborder")]
+    BlackBorder(BlackBorder),
+    Blackout(Blackout),
+    BuildInfo,
     Clear(Clear),
     /// Deprecated
     ClearAll,
+    Clients,
     Color(Color),
     ComponentState(ComponentState),
+    #[serde(rename = "command-traces")]
+    CommandTraces(CommandTracesRequest),
     Config(Config),
+    Database(Database),
     #[serde(rename = "create-effect")]
     EffectCreate(EffectCreate),
     #[serde(rename = "delete-effect")]
     EffectDelete(EffectDelete),
     Effect(Effect),
+    #[serde(rename = "effect-update")]
+    EffectUpdate(EffectUpdate),
+    Forwarder,
     Image(Image),
     Instance(Instance),
     LedColors(LedColors),
     LedDevice(LedDevice),
+    #[serde(rename = "led-gain-ramp")]
+    LedGainRamp(LedGainRamp),
     Logging(Logging),
+    #[serde(rename = "priorities-timeline")]
+    PriorityTimeline(PriorityTimelineRequest),
     Processing(Processing),
+    Server(Server),
     ServerInfo(ServerInfoRequest),
     SourceSelect(SourceSelect),
     SysInfo,
@@ -364,28 +706,140 @@ pub struct HyperionMessage {
     pub command: HyperionCommand,
 }
 
+impl HyperionCommand {
+    /// Priority set by this command, with the range it is validated against
+    pub fn priority_mut(&mut self) -> Option<(&mut i32, RangeInclusive<i32>)> {
+        match self {
+            HyperionCommand::Clear(Clear { priority, .. }) => Some((priority, -1..=253)),
+            HyperionCommand::Color(Color { priority, .. })
+            | HyperionCommand::Effect(Effect { priority, .. })
+            | HyperionCommand::EffectUpdate(EffectUpdate { priority, .. })
+            | HyperionCommand::Image(Image { priority, .. }) => Some((priority, 1..=253)),
+            _ => None,
+        }
+    }
+
+    /// Name of this command, as used in requests
+    pub fn name(&self) -> &'static str {
+        match self {
+            HyperionCommand::Adjustment(_) => "adjustment",
+            HyperionCommand::Authorize(_) => "authorize",
+            HyperionCommand::BlackBorder(_) => "blackborder",
+            HyperionCommand::Blackout(_) => "blackout",
+            HyperionCommand::BuildInfo => "buildinfo",
+            HyperionCommand::Clear(_) => "clear",
+            HyperionCommand::ClearAll => "clearall",
+            HyperionCommand::Clients => "clients",
+            HyperionCommand::Color(_) => "color",
+            HyperionCommand::ComponentState(_) => "componentstate",
+            HyperionCommand::CommandTraces(_) => "command-traces",
+            HyperionCommand::Config(_) => "config",
+            HyperionCommand::Database(_) => "database",
+            HyperionCommand::EffectCreate(_) => "create-effect",
+            HyperionCommand::EffectDelete(_) => "delete-effect",
+            HyperionCommand::Effect(_) => "effect",
+            HyperionCommand::EffectUpdate(_) => "effect-update",
+            HyperionCommand::Forwarder => "forwarder",
+            HyperionCommand::Image(_) => "image",
+            HyperionCommand::Instance(_) => "instance",
+            HyperionCommand::LedColors(_) => "ledcolors",
+            HyperionCommand::LedDevice(_) => "leddevice",
+            HyperionCommand::LedGainRamp(_) => "led-gain-ramp",
+            HyperionCommand::Logging(_) => "logging",
+            HyperionCommand::PriorityTimeline(_) => "priorities-timeline",
+            HyperionCommand::Processing(_) => "processing",
+            HyperionCommand::Server(_) => "server",
+            HyperionCommand::ServerInfo(_) => "serverinfo",
+            HyperionCommand::SourceSelect(_) => "sourceselect",
+            HyperionCommand::SysInfo => "sysinfo",
+            HyperionCommand::VideoMode(_) => "videomode",
+        }
+    }
+
+    /// Returns true if this command needs to persist changes to the configuration
+    pub fn writes_config(&self) -> bool {
+        match self {
+            HyperionCommand::Config(Config { subcommand, .. }) => {
+                matches!(subcommand, ConfigCommand::SetConfig)
+            }
+            HyperionCommand::Database(Database { subcommand, .. }) => {
+                matches!(subcommand, DatabaseCommand::Restore)
+            }
+            HyperionCommand::Adjustment(Adjustment { persist, .. })
+            | HyperionCommand::LedGainRamp(LedGainRamp { persist, .. }) => *persist,
+            HyperionCommand::EffectCreate(_) | HyperionCommand::EffectDelete(_) => true,
+            HyperionCommand::Instance(Instance { subcommand, .. }) => {
+                matches!(subcommand, InstanceCommand::CreateInstance)
+            }
+            HyperionCommand::Authorize(Authorize { subcommand, .. }) => matches!(
+                subcommand,
+                AuthorizeCommand::CreateToken
+                    | AuthorizeCommand::RenameToken
+                    | AuthorizeCommand::DeleteToken
+                    | AuthorizeCommand::NewPassword
+                    | AuthorizeCommand::AnswerRequest
+            ),
+            _ => false,
+        }
+    }
+
+    /// Access level a client needs to send this command
+    pub fn required_access(&self) -> AccessLevel {
+        match self {
+            HyperionCommand::Authorize(Authorize { subcommand, .. }) => match subcommand {
+                AuthorizeCommand::TokenRequired
+                | AuthorizeCommand::AdminRequired
+                | AuthorizeCommand::NewPasswordRequired
+                | AuthorizeCommand::Login
+                | AuthorizeCommand::Logout
+                | AuthorizeCommand::RequestToken => AccessLevel::None,
+                _ => AccessLevel::Admin,
+            },
+            // Backups include the credentials of the auth table
+            HyperionCommand::Config(_) | HyperionCommand::Database(_) => AccessLevel::Admin,
+            HyperionCommand::Server(Server { subcommand, .. }) => match subcommand {
+                ServerCommand::List => AccessLevel::Api,
+                ServerCommand::Start | ServerCommand::Stop => AccessLevel::Admin,
+            },
+            command if command.writes_config() => AccessLevel::Admin,
+            _ => AccessLevel::Api,
+        }
+    }
+}
+
 impl Validate for HyperionMessage {
     fn validate(&self) -> Result<(), validator::ValidationErrors> {
         match &self.command {
             HyperionCommand::Adjustment(adjustment) => adjustment.validate(),
             HyperionCommand::Authorize(authorize) => authorize.validate(),
+            HyperionCommand::BlackBorder(black_border) => black_border.validate(),
+            HyperionCommand::Blackout(blackout) => blackout.validate(),
             HyperionCommand::Clear(clear) => clear.validate(),
             HyperionCommand::ClearAll => Ok(()),
             HyperionCommand::Color(color) => color.validate(),
             HyperionCommand::ComponentState(component_state) => component_state.validate(),
+            HyperionCommand::CommandTraces(command_traces) => command_traces.validate(),
             HyperionCommand::Config(config) => config.validate(),
+            HyperionCommand::Database(database) => database.validate(),
             HyperionCommand::EffectCreate(effect_create) => effect_create.validate(),
             HyperionCommand::EffectDelete(effect_delete) => effect_delete.validate(),
             HyperionCommand::Effect(effect) => effect.validate(),
+            HyperionCommand::EffectUpdate(effect_update) => effect_update.validate(),
             HyperionCommand::Image(image) => image.validate(),
             HyperionCommand::Instance(instance) => instance.validate(),
             HyperionCommand::LedColors(led_colors) => led_colors.validate(),
             HyperionCommand::LedDevice(led_device) => led_device.validate(),
+            HyperionCommand::LedGainRamp(led_gain_ramp) => led_gain_ramp.validate(),
             HyperionCommand::Logging(logging) => logging.validate(),
+            HyperionCommand::PriorityTimeline(priority_timeline) => priority_timeline.validate(),
             HyperionCommand::Processing(processing) => processing.validate(),
+            HyperionCommand::Server(server) => server.validate(),
             HyperionCommand::ServerInfo(server_info) => server_info.validate(),
             HyperionCommand::SourceSelect(source_select) => source_select.validate(),
-            HyperionCommand::SysInfo => Ok(()),
+            HyperionCommand::BuildInfo
+            | HyperionCommand::Clients
+            | HyperionCommand::Forwarder
+            | HyperionCommand::SysInfo => Ok(()),
             HyperionCommand::VideoMode(video_mode) => video_mode.validate(),
         }
     }
@@ -402,6 +856,9 @@ pub struct EffectDefinition {
     pub script: String,
     /// Extra script arguments
     pub args: serde_json::Value,
+    /// JSON schema of the arguments, for rendering parameter forms (hyperion.rs extension)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
 }
 
 impl From<&crate::effects::EffectDefinition> for EffectDefinition {
@@ -411,6 +868,7 @@ impl From<&crate::effects::EffectDefinition> for EffectDefinition {
             file: value.file.to_string_lossy().to_string(),
             script: value.script.clone(),
             args: value.args.clone(),
+            schema: value.schema.as_ref().map(|schema| schema.value().clone()),
         }
     }
 }
@@ -421,28 +879,144 @@ pub enum LedDeviceClass {
     PhilipsHue,
     #[serde(rename = "Ws2812SPI")]
     Ws2812Spi,
+    #[serde(rename = "sk6812spi")]
+    Sk6812Spi,
+    #[serde(rename = "apa102")]
+    Apa102,
+    #[serde(rename = "ws281x")]
+    Ws281x,
     #[serde(rename = "file")]
     File,
+    #[serde(rename = "pipe")]
+    Pipe,
+    #[serde(rename = "wled")]
+    Wled,
+    #[serde(rename = "e131")]
+    E131,
+    #[serde(rename = "adalight")]
+    Adalight,
+    #[serde(rename = "boblight")]
+    Boblight,
+    #[serde(rename = "atmoorb")]
+    AtmoOrb,
+    #[serde(rename = "ddp")]
+    Ddp,
+}
+
+impl From<&crate::models::Device> for LedDeviceClass {
+    fn from(device: &crate::models::Device) -> Self {
+        use crate::models::Device;
+
+        match device {
+            Device::Dummy(_) => Self::Dummy,
+            Device::Ws2812Spi(_) => Self::Ws2812Spi,
+            Device::Sk6812Spi(_) => Self::Sk6812Spi,
+            Device::Apa102(_) => Self::Apa102,
+            Device::Ws281x(_) => Self::Ws281x,
+            Device::PhilipsHue(_) => Self::PhilipsHue,
+            Device::File(_) => Self::File,
+            Device::Pipe(_) => Self::Pipe,
+            Device::Wled(_) => Self::Wled,
+            Device::E131(_) => Self::E131,
+            Device::Adalight(_) => Self::Adalight,
+            Device::Boblight(_) => Self::Boblight,
+            Device::AtmoOrb(_) => Self::AtmoOrb,
+            Device::Ddp(_) => Self::Ddp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LedDeviceState {
+    Starting,
+    Connected,
+    Disconnected,
+    Failed,
+    DryRun,
+}
+
+impl From<crate::instance::DeviceStatus> for LedDeviceState {
+    fn from(status: crate::instance::DeviceStatus) -> Self {
+        use crate::instance::DeviceStatus;
+
+        match status {
+            DeviceStatus::Starting => Self::Starting,
+            DeviceStatus::Connected => Self::Connected,
+            DeviceStatus::Disconnected => Self::Disconnected,
+            DeviceStatus::Failed => Self::Failed,
+            DeviceStatus::DryRun => Self::DryRun,
+        }
+    }
+}
+
+/// Device of a running instance
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveLedDevice {
+    pub instance: i32,
+    pub friendly_name: String,
+    #[serde(rename = "type")]
+    pub class: LedDeviceClass,
+    pub state: LedDeviceState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Frames written per second, over the last second
+    pub fps: f64,
+    pub frames_written: u64,
+    /// Frames dropped in favor of newer ones by the rate limit or output delay of the device
+    pub frames_skipped: u64,
+    pub bytes_written: u64,
+}
+
+impl ActiveLedDevice {
+    pub fn new(
+        instance: &crate::models::Instance,
+        device: &crate::models::Device,
+        info: crate::instance::DeviceInfo,
+    ) -> Self {
+        Self {
+            instance: instance.id,
+            friendly_name: instance.friendly_name.clone(),
+            class: device.into(),
+            state: info.status.into(),
+            last_error: info.stats.last_error,
+            fps: info.stats.fps,
+            frames_written: info.stats.frames,
+            frames_skipped: info.stats.skipped,
+            bytes_written: info.stats.bytes,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct LedDevicesInfo {
     pub available: Vec<LedDeviceClass>,
+    /// Devices of the running instances
+    pub active: Vec<ActiveLedDevice>,
 }
 
 impl Default for LedDevicesInfo {
     fn default() -> Self {
-        Self::new()
+        Self::new(Vec::new())
     }
 }
 
 impl LedDevicesInfo {
-    pub fn new() -> Self {
+    pub fn new(active: Vec<ActiveLedDevice>) -> Self {
         use LedDeviceClass::*;
 
-        Self {
-            available: vec![Dummy, PhilipsHue, Ws2812Spi, File],
-        }
+        #[allow(unused_mut)]
+        let mut available = vec![
+            Dummy, PhilipsHue, Ws2812Spi, Sk6812Spi, Apa102, File, Pipe, Wled, E131, Adalight,
+            AtmoOrb, Ddp,
+        ];
+
+        // The rpi_ws281x library is only loaded in builds which support it
+        #[cfg(feature = "rpi-ws281x")]
+        available.push(Ws281x);
+
+        Self { available, active }
     }
 }
 
@@ -451,10 +1025,12 @@ pub enum GrabberClass {
     AmLogic,
     DirectX,
     Dispmanx,
+    Drm,
     Framebuffer,
     OSX,
     Qt,
     V4L2 { device: PathBuf },
+    Wayland,
     X11,
     Xcb,
 }
@@ -465,10 +1041,12 @@ impl std::fmt::Display for GrabberClass {
             GrabberClass::AmLogic => write!(f, "AmLogic"),
             GrabberClass::DirectX => write!(f, "DirectX"),
             GrabberClass::Dispmanx => write!(f, "Dispmanx"),
+            GrabberClass::Drm => write!(f, "DRM"),
             GrabberClass::Framebuffer => write!(f, "FrameBuffer"),
             GrabberClass::OSX => write!(f, "OSX FrameGrabber"),
             GrabberClass::Qt => write!(f, "Qt"),
             GrabberClass::V4L2 { device } => write!(f, "V4L2:{}", device.display()),
+            GrabberClass::Wayland => write!(f, "Wayland"),
             GrabberClass::X11 => write!(f, "X11"),
             GrabberClass::Xcb => write!(f, "Xcb"),
         }
@@ -499,11 +1077,23 @@ impl Default for GrabbersInfo {
 
 impl GrabbersInfo {
     pub fn new() -> Self {
+        // Only report the screen grabbers compiled in this build
+        let mut available = vec![];
+        if cfg!(feature = "amlogic") {
+            available.push(GrabberClass::AmLogic);
+        }
+        if cfg!(feature = "kms") {
+            available.push(GrabberClass::Drm);
+        }
+        if cfg!(feature = "wayland") {
+            available.push(GrabberClass::Wayland);
+        }
+
         Self {
             // TODO: Report active grabber
             active: None,
-            // TODO: Add grabbers when they are implemented
-            available: vec![], // TODO: Add v4l2_properties for available V4L2 devices
+            // TODO: Add v4l2_properties for available V4L2 devices
+            available,
         }
     }
 }
@@ -526,7 +1116,8 @@ pub struct ServerInfo {
     /// Current video mode
     #[serde(rename = "videomode")]
     pub video_mode: VideoMode,
-    // TODO: components field
+    /// State of the components of the current instance
+    pub components: Vec<ComponentInfo>,
     // TODO: imageToLedMappingType field
     // TODO: sessions field
     #[serde(rename = "instance")]
@@ -556,6 +1147,12 @@ impl BuildInfo {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct ComponentInfo {
+    pub name: ComponentName,
+    pub enabled: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct InstanceInfo {
     pub friendly_name: String,
@@ -568,7 +1165,7 @@ impl From<&crate::models::Instance> for InstanceInfo {
         Self {
             friendly_name: config.friendly_name.clone(),
             instance: config.id,
-            // TODO: Runtime state might differ from config enabled
+            // Replaced with the runtime state when the instance is registered
             running: config.enabled,
         }
     }
@@ -583,6 +1180,10 @@ pub struct HyperionResponse {
     error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", flatten)]
     info: Option<HyperionResponseInfo>,
+    #[serde(skip_serializing_if = "Option::is_none", flatten)]
+    result: Option<HyperionResponseResult>,
+    #[serde(skip_serializing_if = "Option::is_none", flatten)]
+    data: Option<HyperionResponseData>,
 }
 
 #[derive(Default, Debug, Serialize)]
@@ -653,14 +1254,14 @@ pub struct HyperionInfo {
 }
 
 impl HyperionInfo {
-    pub fn new(id: uuid::Uuid) -> Self {
+    pub fn new(id: uuid::Uuid, read_only_mode: bool) -> Self {
         // TODO: Fill in other fields
         Self {
             // We emulate hyperion.ng 2.0.0-alpha.8
             version: "2.0.0-alpha.8".to_owned(),
             build: version(),
             id,
-            read_only_mode: false,
+            read_only_mode,
             ..Default::default()
         }
     }
@@ -670,13 +1271,418 @@ impl HyperionInfo {
 pub struct SysInfo {
     pub system: SystemInfo,
     pub hyperion: HyperionInfo,
+    /// Build features, configuration and detected hardware, not part of hyperion.ng
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<EnvironmentReport>,
+    pub health: HealthInfo,
 }
 
 impl SysInfo {
-    pub fn new(id: uuid::Uuid) -> Self {
+    pub fn new(
+        id: uuid::Uuid,
+        read_only_mode: bool,
+        environment: Option<EnvironmentReport>,
+        health: HealthInfo,
+    ) -> Self {
         Self {
             system: SystemInfo::new(),
-            hyperion: HyperionInfo::new(id),
+            hyperion: HyperionInfo::new(id, read_only_mode),
+            environment,
+            health,
+        }
+    }
+}
+
+/// Capabilities compiled into the running binary, so missing features can be told apart from
+/// configuration issues
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildFeatures {
+    pub version: String,
+    /// Optional cargo features, with whether they are enabled
+    pub features: BTreeMap<&'static str, bool>,
+    pub led_devices: Vec<LedDeviceClass>,
+    pub grabbers: Vec<GrabberClass>,
+    /// Input sources besides the network servers
+    pub inputs: Vec<&'static str>,
+    pub effect_providers: Vec<&'static str>,
+    /// Formats accepted by the image command, besides raw RGB data
+    pub image_formats: Vec<&'static str>,
+}
+
+impl BuildFeatures {
+    pub fn new() -> Self {
+        let optional = |name: &'static str, enabled: bool| Some(name).filter(|_| enabled);
+
+        Self {
+            version: version(),
+            features: feature_matrix().into_iter().collect(),
+            led_devices: LedDevicesInfo::default().available,
+            grabbers: GrabbersInfo::new().available,
+            inputs: vec![
+                optional("ndiReceiver", cfg!(feature = "ndi")),
+                optional("audioGrabber", cfg!(feature = "audio")),
+                optional("mediaArtwork", cfg!(feature = "mpris")),
+                optional("dataPresets", cfg!(feature = "data-presets")),
+                optional("mqtt", cfg!(feature = "mqtt")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            effect_providers: crate::effects::Providers::new().names(),
+            image_formats: vec![
+                optional("png", cfg!(feature = "png")),
+                optional("jpeg", cfg!(feature = "jpeg")),
+                optional("webp", cfg!(feature = "webp")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        }
+    }
+}
+
+impl Default for BuildFeatures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConfigWarningInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<i32>,
+    pub setting: &'static str,
+    pub message: String,
+}
+
+impl From<crate::models::ConfigWarning> for ConfigWarningInfo {
+    fn from(warning: crate::models::ConfigWarning) -> Self {
+        Self {
+            instance: warning.instance,
+            setting: warning.setting,
+            message: warning.message,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerStatusInfo {
+    pub server: ServerName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub running: bool,
+    /// Error of the last attempt to start the server, if it is not running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+}
+
+impl ServerStatusInfo {
+    fn name(id: ServerId) -> (ServerName, Option<i32>) {
+        match id {
+            ServerId::Json => (ServerName::Json, None),
+            ServerId::Web => (ServerName::Web, None),
+            ServerId::Flatbuffers => (ServerName::Flatbuffers, None),
+            ServerId::Protobuf => (ServerName::Protobuf, None),
+            ServerId::Boblight { instance } => (ServerName::Boblight, Some(instance)),
+            ServerId::UdpListener { instance } => (ServerName::UdpListener, Some(instance)),
+        }
+    }
+
+    pub fn running(id: ServerId, port: u16) -> Self {
+        let (server, instance) = Self::name(id);
+
+        Self {
+            server,
+            instance,
+            port: Some(port),
+            running: true,
+            error: None,
+            attempts: None,
+        }
+    }
+
+    pub fn failed(id: ServerId, failure: ServerFailure) -> Self {
+        let (server, instance) = Self::name(id);
+
+        Self {
+            server,
+            instance,
+            port: failure.port,
+            running: false,
+            error: Some(failure.error),
+            attempts: Some(failure.attempts),
+        }
+    }
+}
+
+/// State of a forwarder target, not part of hyperion.ng
+#[derive(Debug, Serialize)]
+pub struct ForwarderTargetInfo {
+    pub protocol: TargetProtocol,
+    pub address: String,
+    pub connected: bool,
+    /// Error of the last connection attempt, or which closed the last connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Failed connection attempts since the last successful one
+    pub attempts: u32,
+    /// Number of requests forwarded to the target
+    pub sent: u64,
+}
+
+impl From<&ForwarderTarget> for ForwarderTargetInfo {
+    fn from(target: &ForwarderTarget) -> Self {
+        let health = target.link().health();
+
+        Self {
+            protocol: target.protocol(),
+            address: target.link().address().to_owned(),
+            connected: health.connected,
+            error: health.last_error,
+            attempts: health.failed_attempts,
+            sent: health.sent,
+        }
+    }
+}
+
+/// Health of the daemon, not part of hyperion.ng
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthInfo {
+    /// true if some components could not be started
+    pub degraded: bool,
+    /// Servers which could not be started, and are retried in the background
+    pub failed_servers: Vec<ServerStatusInfo>,
+}
+
+impl HealthInfo {
+    pub fn new(failed_servers: Vec<ServerStatusInfo>) -> Self {
+        Self {
+            degraded: !failed_servers.is_empty(),
+            failed_servers,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlackBorderInfo {
+    pub enabled: bool,
+    /// true if the detector was disabled at runtime
+    pub suspended: bool,
+    /// Time before the detector is enabled again, in milliseconds, if it was disabled for a
+    /// limited duration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suspended_for: Option<u64>,
+    pub mode: crate::models::BlackBorderDetectorMode,
+    pub unknown: bool,
+    pub horizontal_size: u16,
+    pub vertical_size: u16,
+    pub consistent_count: u32,
+    pub inconsistent_count: u32,
+}
+
+impl From<crate::instance::BlackBorderState> for BlackBorderInfo {
+    fn from(state: crate::instance::BlackBorderState) -> Self {
+        Self {
+            enabled: state.enabled,
+            suspended: state.suspended.is_some(),
+            suspended_for: state
+                .suspended
+                .filter(|duration| !duration.is_zero())
+                .map(|duration| duration.as_millis() as _),
+            mode: state.mode,
+            unknown: state.border.unknown,
+            horizontal_size: state.border.horizontal_size,
+            vertical_size: state.border.vertical_size,
+            consistent_count: state.consistent_cnt,
+            inconsistent_count: state.inconsistent_cnt,
+        }
+    }
+}
+
+/// Log record streamed by the `logging` command, in the hyperion.ng format
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogMessageInfo {
+    pub app_name: &'static str,
+    /// Module path the record was emitted from
+    pub logger_name: String,
+    pub function: String,
+    pub line: u32,
+    pub file_name: String,
+    pub message: String,
+    pub level_string: &'static str,
+    /// Record time, in milliseconds since the Unix epoch
+    pub utime: i64,
+}
+
+impl From<&crate::global::LogRecord> for LogMessageInfo {
+    fn from(record: &crate::global::LogRecord) -> Self {
+        use tracing::Level;
+
+        Self {
+            app_name: "hyperiond",
+            logger_name: record.target.clone(),
+            function: String::new(),
+            line: record.line.unwrap_or_default(),
+            file_name: record.file.clone().unwrap_or_default(),
+            message: record.message.clone(),
+            level_string: match record.level {
+                Level::ERROR => "ERROR",
+                Level::WARN => "WARNING",
+                Level::INFO => "INFO",
+                Level::DEBUG | Level::TRACE => "DEBUG",
+            },
+            utime: record.time.timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandTraceInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tan: Option<i32>,
+    pub command: &'static str,
+    pub source: String,
+    /// Start time, in milliseconds since the Unix epoch
+    pub started: i64,
+    /// Processing time, in microseconds
+    pub duration: u64,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<&crate::global::CommandTrace> for CommandTraceInfo {
+    fn from(trace: &crate::global::CommandTrace) -> Self {
+        Self {
+            tan: trace.tan,
+            command: trace.command,
+            source: trace.source.clone(),
+            started: trace.started.timestamp_millis(),
+            duration: trace.duration.as_micros() as _,
+            success: trace.error.is_none(),
+            error: trace.error.clone(),
+        }
+    }
+}
+
+/// Network clients, not part of hyperion.ng
+#[derive(Debug, Serialize)]
+pub struct ClientsInfo {
+    pub connected: Vec<ClientInfo>,
+    /// Features negotiated since startup
+    pub negotiated: Vec<NegotiatedFeatureInfo>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientInfo {
+    pub protocol: ClientProtocol,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Connection time, in milliseconds since the Unix epoch
+    pub connected: i64,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NegotiatedFeatureInfo {
+    pub protocol: ClientProtocol,
+    pub feature: String,
+    /// Number of clients which used the feature
+    pub clients: u64,
+}
+
+impl From<&ClientRegistry> for ClientsInfo {
+    fn from(registry: &ClientRegistry) -> Self {
+        Self {
+            connected: registry
+                .clients()
+                .map(|client| ClientInfo {
+                    protocol: client.protocol,
+                    address: client.peer_addr.to_string(),
+                    origin: client.origin.clone(),
+                    connected: client.connected.timestamp_millis(),
+                    features: client.features.iter().cloned().collect(),
+                })
+                .collect(),
+            negotiated: registry
+                .negotiated()
+                .map(|(protocol, feature, clients)| NegotiatedFeatureInfo {
+                    protocol,
+                    feature: feature.to_owned(),
+                    clients,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEventInfo {
+    /// Event time, in milliseconds since the Unix epoch
+    pub time: i64,
+    /// true if the input became visible, false if it stopped being visible
+    pub active: bool,
+    pub priority: i32,
+    pub component_id: ComponentName,
+    pub origin: String,
+}
+
+impl From<&crate::instance::TimelineEvent> for TimelineEventInfo {
+    fn from(event: &crate::instance::TimelineEvent) -> Self {
+        Self {
+            time: event.time.timestamp_millis(),
+            active: event.kind == crate::instance::TimelineEventKind::Activated,
+            priority: event.priority,
+            component_id: event.component,
+            origin: event.origin.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub comment: String,
+    pub id: String,
+    pub last_use: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<ApiToken> for TokenInfo {
+    fn from(token: ApiToken) -> Self {
+        Self {
+            comment: token.comment,
+            id: token.id,
+            last_use: token.last_use,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenRequestInfo {
+    pub comment: String,
+    pub id: String,
+    /// Time left before the request expires, in milliseconds
+    pub timeout: u64,
+}
+
+impl From<PendingTokenRequest> for TokenRequestInfo {
+    fn from(request: PendingTokenRequest) -> Self {
+        Self {
+            comment: request.comment,
+            id: request.id,
+            timeout: request.timeout.as_millis() as _,
         }
     }
 }
@@ -702,15 +1708,138 @@ pub enum HyperionResponseInfo {
         /// true if an auth token required
         required: bool,
     },
+    /// NewPasswordRequired response
+    #[serde(rename = "authorize-newPasswordRequired")]
+    NewPasswordRequired {
+        /// true if the administrator password still is the default one
+        #[serde(rename = "newPasswordRequired")]
+        new_password_required: bool,
+    },
+    /// Login response
+    #[serde(rename = "authorize-login")]
+    Login {
+        /// Session token, when logging in with a password
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// CreateToken response
+    #[serde(rename = "authorize-createToken")]
+    CreateToken {
+        comment: String,
+        id: String,
+        token: String,
+    },
+    /// RequestToken response, sent when an administrator accepted the request
+    #[serde(rename = "authorize-requestToken")]
+    RequestToken {
+        comment: String,
+        id: String,
+        token: String,
+    },
+    /// RenameToken response
+    #[serde(rename = "authorize-renameToken")]
+    RenameToken { comment: String, id: String },
+    /// DeleteToken response
+    #[serde(rename = "authorize-deleteToken")]
+    DeleteToken { id: String },
+    /// GetTokenList response
+    #[serde(rename = "authorize-getTokenList")]
+    TokenList(Vec<TokenInfo>),
+    /// GetPendingTokenRequests response
+    #[serde(rename = "authorize-getPendingTokenRequests")]
+    PendingTokenRequests(Vec<TokenRequestInfo>),
     /// SysInfo response
     #[serde(rename = "sysinfo")]
     SysInfo(SysInfo),
+    /// BuildInfo response
+    #[serde(rename = "buildinfo")]
+    BuildInfo(BuildFeatures),
     /// SwitchTo response
     #[serde(rename = "instance-switchTo")]
     SwitchTo {
         #[serde(skip_serializing_if = "Option::is_none")]
         instance: Option<i32>,
     },
+    /// Blackout state response
+    #[serde(rename = "blackout")]
+    Blackout { enabled: bool },
+    /// Black border detector state response
+    #[serde(rename = "blackborder")]
+    BlackBorder(BlackBorderInfo),
+    /// Running servers response
+    #[serde(rename = "server")]
+    Servers(Vec<ServerStatusInfo>),
+    /// Configuration response, with the global settings and those of the current instance
+    #[serde(rename = "config-getconfig")]
+    GetConfig(serde_json::Map<String, serde_json::Value>),
+    /// Configuration warnings response
+    #[serde(rename = "config-getwarnings")]
+    ConfigWarnings(Vec<ConfigWarningInfo>),
+    /// Command traces response
+    #[serde(rename = "command-traces")]
+    CommandTraces(Vec<CommandTraceInfo>),
+    /// Connected clients response
+    #[serde(rename = "clients")]
+    Clients(ClientsInfo),
+    /// Forwarder targets response
+    #[serde(rename = "forwarder")]
+    Forwarder(Vec<ForwarderTargetInfo>),
+    /// Priorities timeline response
+    #[serde(rename = "priorities-timeline")]
+    PriorityTimeline(Vec<TimelineEventInfo>),
+    /// Generated LED gains response
+    #[serde(rename = "led-gain-ramp")]
+    LedGainRamp(Vec<crate::models::LedGain>),
+    /// Database backup response
+    #[serde(rename = "database-backup")]
+    DatabaseBackup {
+        /// Base64-encoded database snapshot
+        snapshot: String,
+    },
+}
+
+/// Hyperion JSON streamed update, with its data in a `result` field like hyperion.ng
+#[derive(Debug, Serialize)]
+#[serde(tag = "command", content = "result")]
+pub enum HyperionResponseResult {
+    /// Current LED colors, as a flat array of RGB components
+    #[serde(rename = "ledcolors-ledstream-update")]
+    LedStreamUpdate { leds: Vec<u8> },
+    /// Image currently processed by the instance
+    #[serde(rename = "ledcolors-imagestream-update")]
+    ImageStreamUpdate {
+        /// JPEG data URL, or base64-encoded RGB data for raw images
+        image: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        imagewidth: Option<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        imageheight: Option<u16>,
+    },
+    /// New log records, or the recent ones when the stream starts
+    #[serde(rename = "logmsg-update")]
+    LogMessageUpdate { messages: Vec<LogMessageInfo> },
+}
+
+/// Hyperion JSON state update pushed to `serverinfo` subscribers, with its data in a `data` field
+/// like hyperion.ng
+#[derive(Debug, Serialize)]
+#[serde(tag = "command", content = "data")]
+pub enum HyperionResponseData {
+    /// A component was enabled or disabled
+    #[serde(rename = "components-update")]
+    ComponentsUpdate { name: ComponentName, enabled: bool },
+    /// The priorities of the current instance changed
+    #[serde(rename = "priorities-update")]
+    PrioritiesUpdate {
+        priorities: Vec<PriorityInfo>,
+        priorities_autoselect: bool,
+    },
+    /// An instance was started or stopped
+    #[serde(rename = "instance-update")]
+    InstanceUpdate(Vec<InstanceInfo>),
+    /// The color adjustments of the current instance changed
+    #[serde(rename = "adjustment-update")]
+    AdjustmentUpdate(Vec<ChannelAdjustment>),
 }
 
 impl HyperionResponse {
@@ -725,6 +1854,8 @@ impl HyperionResponse {
             tan: None,
             error: None,
             info: Some(info),
+            result: None,
+            data: None,
         }
     }
 
@@ -735,6 +1866,8 @@ impl HyperionResponse {
             tan: None,
             error: None,
             info: None,
+            result: None,
+            data: None,
         }
     }
 
@@ -745,6 +1878,8 @@ impl HyperionResponse {
             tan: None,
             error: Some(error.to_string()),
             info: None,
+            result: None,
+            data: None,
         }
     }
 
@@ -755,26 +1890,31 @@ impl HyperionResponse {
             tan: None,
             error: Some(error.to_string()),
             info: Some(info),
+            result: None,
+            data: None,
         }
     }
 
     /// Return a server information response
     pub fn server_info(
         priorities: Vec<PriorityInfo>,
+        priorities_autoselect: bool,
         adjustment: Vec<ChannelAdjustment>,
         effects: Vec<EffectDefinition>,
+        components: Vec<ComponentInfo>,
         instances: Vec<InstanceInfo>,
+        devices: Vec<ActiveLedDevice>,
     ) -> Self {
         Self::success_info(HyperionResponseInfo::ServerInfo(ServerInfo {
             priorities,
-            // TODO: Actual autoselect value
-            priorities_autoselect: true,
+            priorities_autoselect,
             adjustment,
             effects,
-            led_devices: LedDevicesInfo::new(),
+            led_devices: LedDevicesInfo::new(devices),
             grabbers: GrabbersInfo::new(),
             // TODO: Actual video mode
             video_mode: VideoMode::Mode2D,
+            components,
             instances,
             hostname: hostname(),
         }))
@@ -788,9 +1928,181 @@ impl HyperionResponse {
         Self::success_info(HyperionResponseInfo::TokenRequired { required })
     }
 
-    pub fn sys_info(id: uuid::Uuid) -> Self {
+    pub fn new_password_required(new_password_required: bool) -> Self {
+        Self::success_info(HyperionResponseInfo::NewPasswordRequired {
+            new_password_required,
+        })
+    }
+
+    pub fn login(token: Option<String>) -> Self {
+        Self::success_info(HyperionResponseInfo::Login { token })
+    }
+
+    pub fn create_token(comment: String, id: String, token: String) -> Self {
+        Self::success_info(HyperionResponseInfo::CreateToken { comment, id, token })
+    }
+
+    pub fn request_token(comment: String, id: String, token: String) -> Self {
+        Self::success_info(HyperionResponseInfo::RequestToken { comment, id, token })
+    }
+
+    pub fn rename_token(comment: String, id: String) -> Self {
+        Self::success_info(HyperionResponseInfo::RenameToken { comment, id })
+    }
+
+    pub fn delete_token(id: String) -> Self {
+        Self::success_info(HyperionResponseInfo::DeleteToken { id })
+    }
+
+    pub fn token_list(tokens: Vec<TokenInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::TokenList(tokens))
+    }
+
+    pub fn pending_token_requests(requests: Vec<TokenRequestInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::PendingTokenRequests(requests))
+    }
+
+    pub fn sys_info(
+        id: uuid::Uuid,
+        read_only_mode: bool,
+        environment: Option<EnvironmentReport>,
+        health: HealthInfo,
+    ) -> Self {
         // TODO: Properly fill out this response
-        Self::success_info(HyperionResponseInfo::SysInfo(SysInfo::new(id)))
+        Self::success_info(HyperionResponseInfo::SysInfo(SysInfo::new(
+            id,
+            read_only_mode,
+            environment,
+            health,
+        )))
+    }
+
+    pub fn build_info() -> Self {
+        Self::success_info(HyperionResponseInfo::BuildInfo(BuildFeatures::new()))
+    }
+
+    pub fn get_config(settings: serde_json::Map<String, serde_json::Value>) -> Self {
+        Self::success_info(HyperionResponseInfo::GetConfig(settings))
+    }
+
+    pub fn config_warnings(warnings: Vec<ConfigWarningInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::ConfigWarnings(warnings))
+    }
+
+    pub fn blackout(enabled: bool) -> Self {
+        Self::success_info(HyperionResponseInfo::Blackout { enabled })
+    }
+
+    pub fn black_border(info: BlackBorderInfo) -> Self {
+        Self::success_info(HyperionResponseInfo::BlackBorder(info))
+    }
+
+    pub fn servers(servers: Vec<ServerStatusInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::Servers(servers))
+    }
+
+    pub fn command_traces(traces: Vec<CommandTraceInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::CommandTraces(traces))
+    }
+
+    pub fn clients(clients: ClientsInfo) -> Self {
+        Self::success_info(HyperionResponseInfo::Clients(clients))
+    }
+
+    pub fn forwarder(targets: Vec<ForwarderTargetInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::Forwarder(targets))
+    }
+
+    pub fn priority_timeline(events: Vec<TimelineEventInfo>) -> Self {
+        Self::success_info(HyperionResponseInfo::PriorityTimeline(events))
+    }
+
+    pub fn led_gain_ramp(led_gain: Vec<crate::models::LedGain>) -> Self {
+        Self::success_info(HyperionResponseInfo::LedGainRamp(led_gain))
+    }
+
+    pub fn led_stream_update(leds: &[RgbColor]) -> Self {
+        Self {
+            success: true,
+            tan: None,
+            error: None,
+            info: None,
+            result: Some(HyperionResponseResult::LedStreamUpdate {
+                leds: leds
+                    .iter()
+                    .flat_map(|led| [led.red, led.green, led.blue])
+                    .collect(),
+            }),
+            data: None,
+        }
+    }
+
+    pub fn image_stream_update(
+        image: String,
+        imagewidth: Option<u16>,
+        imageheight: Option<u16>,
+    ) -> Self {
+        Self {
+            success: true,
+            tan: None,
+            error: None,
+            info: None,
+            result: Some(HyperionResponseResult::ImageStreamUpdate {
+                image,
+                imagewidth,
+                imageheight,
+            }),
+            data: None,
+        }
+    }
+
+    pub fn log_message_update(records: &[Arc<crate::global::LogRecord>]) -> Self {
+        Self {
+            success: true,
+            tan: None,
+            error: None,
+            info: None,
+            result: Some(HyperionResponseResult::LogMessageUpdate {
+                messages: records.iter().map(|record| (&**record).into()).collect(),
+            }),
+            data: None,
+        }
+    }
+
+    fn state_update(data: HyperionResponseData) -> Self {
+        Self {
+            success: true,
+            tan: None,
+            error: None,
+            info: None,
+            result: None,
+            data: Some(data),
+        }
+    }
+
+    pub fn components_update(name: ComponentName, enabled: bool) -> Self {
+        Self::state_update(HyperionResponseData::ComponentsUpdate { name, enabled })
+    }
+
+    pub fn priorities_update(priorities: Vec<PriorityInfo>, priorities_autoselect: bool) -> Self {
+        Self::state_update(HyperionResponseData::PrioritiesUpdate {
+            priorities,
+            priorities_autoselect,
+        })
+    }
+
+    pub fn instance_update(instances: Vec<InstanceInfo>) -> Self {
+        Self::state_update(HyperionResponseData::InstanceUpdate(instances))
+    }
+
+    pub fn adjustment_update(adjustments: Vec<ChannelAdjustment>) -> Self {
+        Self::state_update(HyperionResponseData::AdjustmentUpdate(adjustments))
+    }
+
+    pub fn database_backup(snapshot: &[u8]) -> Self {
+        Self::success_info(HyperionResponseInfo::DatabaseBackup {
+            snapshot: base64::engine::general_purpose::STANDARD.encode(snapshot),
+        })
     }
 
     pub fn switch_to(id: Option<i32>) -> Self {
@@ -811,12 +2123,3 @@ fn hostname() -> String {
         .map(|s| s.to_string_lossy().to_string())
         .unwrap_or_else(|_| "<unknown hostname>".to_owned())
 }
-
-fn version() -> String {
-    git_version::git_version!(
-        prefix = "hyperion.rs-",
-        args = ["--always", "--tags"],
-        fallback = env!("HYPERION_RS_GIT_VERSION")
-    )
-    .to_owned()
-}
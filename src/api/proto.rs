@@ -1,15 +1,14 @@
 use std::sync::Arc;
-use std::{convert::TryFrom, net::SocketAddr};
+use std::{convert::TryFrom, net::SocketAddr, ops::RangeInclusive};
 
 use thiserror::Error;
 
-use super::types::i32_to_duration;
+use super::types::{i32_to_color, i32_to_duration, PriorityCompat};
 
 use crate::{
     component::ComponentName,
     global::{InputMessage, InputMessageData, InputSourceHandle, PriorityGuard},
     image::{RawImage, RawImageError},
-    models::Color,
 };
 
 /// Schema definitions as Serde serializable structures and enums
@@ -28,12 +27,18 @@ pub enum ProtoApiError {
     InvalidPriority(i32),
 }
 
+/// Priorities protobuf clients can send inputs with
+const PRIORITY_RANGE: RangeInclusive<i32> = 100..=199;
+
 fn validate_priority(
     priority: i32,
     source: &InputSourceHandle<InputMessage>,
     priority_guard: &mut PriorityGuard,
+    priority_compat: &mut PriorityCompat,
 ) -> Result<i32, ProtoApiError> {
-    if !(100..200).contains(&priority) {
+    let priority = priority_compat.correct(priority, PRIORITY_RANGE);
+
+    if !PRIORITY_RANGE.contains(&priority) {
         return Err(ProtoApiError::InvalidPriority(priority));
     }
 
@@ -43,12 +48,13 @@ fn validate_priority(
     Ok(priority)
 }
 
-#[instrument(skip(request, source, priority_guard))]
+#[instrument(skip(request, source, priority_guard, priority_compat))]
 pub fn handle_request(
     peer_addr: SocketAddr,
     request: HyperionRequest,
     source: &InputSourceHandle<InputMessage>,
     priority_guard: &mut PriorityGuard,
+    priority_compat: &mut PriorityCompat,
 ) -> Result<(), ProtoApiError> {
     match request.command() {
         message::hyperion_request::Command::Clearall => {
@@ -65,7 +71,7 @@ pub fn handle_request(
             source.send(
                 ComponentName::ProtoServer,
                 InputMessageData::Clear {
-                    priority: clear_request.priority,
+                    priority: priority_compat.correct(clear_request.priority, PRIORITY_RANGE),
                 },
             )?;
         }
@@ -75,14 +81,12 @@ pub fn handle_request(
                 .color_request
                 .ok_or_else(|| ProtoApiError::MissingCommand)?;
 
-            let color = color_request.rgb_color;
-            let color = (
-                (color & 0x000_000FF) as u8,
-                ((color & 0x0000_FF00) >> 8) as u8,
-                ((color & 0x00FF_0000) >> 16) as u8,
-            );
-
-            let priority = validate_priority(color_request.priority, source, priority_guard)?;
+            let priority = validate_priority(
+                color_request.priority,
+                source,
+                priority_guard,
+                priority_compat,
+            )?;
 
             // Update state
             source.send(
@@ -90,7 +94,7 @@ pub fn handle_request(
                 InputMessageData::SolidColor {
                     priority,
                     duration: i32_to_duration(color_request.duration),
-                    color: Color::from_components(color),
+                    color: i32_to_color(color_request.rgb_color),
                 },
             )?;
         }
@@ -106,7 +110,12 @@ pub fn handle_request(
                 .map_err(|_| RawImageError::InvalidHeight)?;
             let raw_image = RawImage::try_from((image_request.imagedata.to_vec(), width, height))?;
 
-            let priority = validate_priority(image_request.priority, source, priority_guard)?;
+            let priority = validate_priority(
+                image_request.priority,
+                source,
+                priority_guard,
+                priority_compat,
+            )?;
 
             // Update state
             source.send(
@@ -115,6 +124,7 @@ pub fn handle_request(
                     priority,
                     duration: i32_to_duration(image_request.duration),
                     image: Arc::new(raw_image),
+                    region: None,
                 },
             )?;
         }
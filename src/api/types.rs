@@ -1,3 +1,5 @@
+use std::ops::RangeInclusive;
+
 use palette::{
     encoding::{Linear, Srgb},
     FromColor, Hsl,
@@ -36,6 +38,9 @@ pub struct PriorityInfo {
     pub visible: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub value: Option<LedColor>,
+    /// Friendly name of the instance reporting this priority
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_name: Option<String>,
 }
 
 impl PriorityInfo {
@@ -71,6 +76,7 @@ impl PriorityInfo {
                 active,
                 visible,
                 value: Some(color.into()),
+                instance_name: None,
             },
             InputMessageData::Image { priority, .. }
             | InputMessageData::LedColors { priority, .. }
@@ -83,6 +89,7 @@ impl PriorityInfo {
                 active,
                 visible,
                 value: None,
+                instance_name: None,
             },
             InputMessageData::Clear { .. } | InputMessageData::ClearAll => {
                 panic!("cannot create PriorityInfo for InputMessage")
@@ -113,6 +120,20 @@ impl From<&Color> for LedColor {
     }
 }
 
+/// Decode a color packed as 0x00RRGGBB, as used by the protobuf and flatbuffers protocols
+pub fn i32_to_color(rgb: i32) -> Color {
+    Color::new(
+        ((rgb & 0x00FF_0000) >> 16) as u8,
+        ((rgb & 0x0000_FF00) >> 8) as u8,
+        (rgb & 0x0000_00FF) as u8,
+    )
+}
+
+/// Pack a color as 0x00RRGGBB
+pub fn color_to_i32(color: Color) -> i32 {
+    ((color.red as i32) << 16) | ((color.green as i32) << 8) | color.blue as i32
+}
+
 pub fn i32_to_duration(d: Option<i32>) -> Option<chrono::Duration> {
     if let Some(d) = d {
         if d <= 0 {
@@ -124,3 +145,61 @@ pub fn i32_to_duration(d: Option<i32>) -> Option<chrono::Duration> {
         None
     }
 }
+
+/// Correction of the priorities sent by legacy clients, which may be outside of the range
+/// accepted by the current protocols
+#[derive(Debug, Default, Clone)]
+pub struct PriorityCompat {
+    /// Clamp out-of-range priorities instead of rejecting them
+    enabled: bool,
+    /// Last priority which was corrected, so the warning is only logged when it changes
+    last_corrected: Option<i32>,
+}
+
+impl PriorityCompat {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last_corrected: None,
+        }
+    }
+
+    /// Clamp `priority` into `range` if corrections are enabled
+    ///
+    /// # Returns
+    ///
+    /// The priority to use. It is only out of `range` if corrections are disabled, in which case
+    /// the request should be rejected as usual.
+    pub fn correct(&mut self, priority: i32, range: RangeInclusive<i32>) -> i32 {
+        if !self.enabled || range.contains(&priority) {
+            return priority;
+        }
+
+        let corrected = priority.clamp(*range.start(), *range.end());
+        if self.last_corrected != Some(priority) {
+            self.last_corrected = Some(priority);
+            warn!(
+                priority,
+                corrected, "clamping out-of-range priority from legacy client"
+            );
+        }
+
+        corrected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_compat() {
+        let mut compat = PriorityCompat::new(true);
+        assert_eq!(compat.correct(150, 100..=199), 150);
+        assert_eq!(compat.correct(0, 100..=199), 100);
+        assert_eq!(compat.correct(255, 1..=253), 253);
+
+        let mut compat = PriorityCompat::new(false);
+        assert_eq!(compat.correct(255, 1..=253), 255);
+    }
+}
@@ -0,0 +1,432 @@
+//! Soak test for long-running stability
+//!
+//! Runs instances with dummy devices under synthetic high-rate inputs: images, short-lived colors
+//! which expire through the muxer timeouts, and effects started and stopped periodically. Memory
+//! usage, image latency and the number of registered priorities are reported at a fixed interval,
+//! and the run fails if memory grows or latency drifts past the given limits.
+
+#[macro_use]
+extern crate tracing;
+
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, Result};
+use structopt::StructOpt;
+use tokio::{runtime::Builder, signal, time::MissedTickBehavior};
+
+use hyperion::{
+    api::json::message::EffectRequest,
+    image::RawImage,
+    instance::InstanceHandle,
+    models::{
+        ClassicLedConfig, Color, Config, Device, Dummy, GlobalConfig, InstanceConfig, ToLeds,
+    },
+    HostInput, HostInputData,
+};
+
+/// Priorities of the generated inputs: colors and effects hide the images while they last
+const COLOR_PRIORITY: i32 = 50;
+const EFFECT_PRIORITY: i32 = 75;
+const IMAGE_PRIORITY: i32 = 100;
+
+/// Size of the generated images
+const IMAGE_WIDTH: u16 = 64;
+const IMAGE_HEIGHT: u16 = 36;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "hyperion-soak")]
+struct Opts {
+    /// Log verbosity, overridden by HYPERION_LOG
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u32,
+    /// Duration of the run, in seconds
+    #[structopt(long, default_value = "3600")]
+    duration: u64,
+    /// Number of instances
+    #[structopt(long, default_value = "2")]
+    instances: i32,
+    /// Number of LEDs of each instance
+    #[structopt(long, default_value = "300")]
+    leds: u32,
+    /// Images sent per second
+    #[structopt(long, default_value = "60")]
+    image_rate: f64,
+    /// Interval between colors, in milliseconds. Each color lasts half of it
+    #[structopt(long, default_value = "500")]
+    color_interval: u64,
+    /// Effect started periodically, skipped if it can't be found
+    #[structopt(long, default_value = "Rainbow swirl fast")]
+    effect: String,
+    /// Interval between effects, in seconds. Each effect lasts half of it
+    #[structopt(long, default_value = "20")]
+    effect_interval: u64,
+    /// Interval between reports, in seconds
+    #[structopt(long, default_value = "60")]
+    report_interval: u64,
+    /// Time before the baseline is measured, in seconds
+    #[structopt(long, default_value = "60")]
+    warmup: u64,
+    /// Maximum growth of the resident memory over the baseline, in MiB
+    #[structopt(long, default_value = "32")]
+    max_memory_growth: f64,
+    /// Maximum increase of the 99th percentile image latency over the baseline, in milliseconds
+    #[structopt(long, default_value = "20")]
+    max_latency_drift: f64,
+}
+
+/// Latency measurement of the image inputs
+///
+/// Like the instance latency tracking, an image is measured when the first frame after it is
+/// written. Images sent while a color or an effect is visible don't produce frames, so they
+/// aren't measured.
+#[derive(Debug)]
+struct Probe {
+    /// Time the last image was sent, if no frame was written since
+    pending: Option<Instant>,
+    /// Images are hidden by another input until then
+    hidden_until: Instant,
+    samples: Vec<Duration>,
+}
+
+impl Probe {
+    fn new() -> Self {
+        Self {
+            pending: None,
+            hidden_until: Instant::now(),
+            samples: Vec::new(),
+        }
+    }
+
+    fn image_sent(&mut self) {
+        let now = Instant::now();
+        if now >= self.hidden_until {
+            self.pending = Some(now);
+        }
+    }
+
+    /// Notify that an input hiding the images was sent, for `duration` at most
+    fn hidden(&mut self, duration: Duration) {
+        // Margin for the output to switch back to the images
+        self.hidden_until = Instant::now() + duration + Duration::from_millis(100);
+        self.pending = None;
+    }
+
+    fn frame_written(&mut self) {
+        if let Some(sent) = self.pending.take() {
+            self.samples.push(sent.elapsed());
+        }
+    }
+}
+
+/// Statistics of a report interval
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// Resident memory, in MiB
+    memory: Option<f64>,
+    /// 99th percentile of the image latency, in milliseconds
+    p99: Option<f64>,
+}
+
+fn instance_config(id: i32, led_count: u32) -> InstanceConfig {
+    let mut config = InstanceConfig::new_dummy(id);
+    config.instance.friendly_name = format!("Soak {}", id);
+
+    // Spread the LEDs around the screen
+    let vertical = led_count / 6;
+    let horizontal = (led_count - 2 * vertical) / 2;
+    config.leds = ClassicLedConfig {
+        top: horizontal,
+        bottom: led_count - 2 * vertical - horizontal,
+        left: vertical,
+        right: vertical,
+        hdepth: 8,
+        vdepth: 5,
+        ..Default::default()
+    }
+    .to_leds();
+
+    config.device = Device::Dummy(Dummy {
+        hardware_led_count: led_count,
+        ..Default::default()
+    });
+
+    // Every input produces a frame, for measuring the latency
+    config.smoothing.enable = false;
+    config.frame_latency.report_interval_secs = 0;
+
+    config
+}
+
+/// Image with a gradient moving along with `phase`
+fn image(phase: u32) -> RawImage {
+    let mut data = Vec::with_capacity(IMAGE_WIDTH as usize * IMAGE_HEIGHT as usize * 3);
+    for y in 0..IMAGE_HEIGHT as u32 {
+        for x in 0..IMAGE_WIDTH as u32 {
+            data.extend_from_slice(&[
+                (x * 4 + phase) as u8,
+                (y * 7 + phase / 2) as u8,
+                (x + y + phase * 3) as u8,
+            ]);
+        }
+    }
+
+    // unwrap: the data matches the dimensions
+    RawImage::try_from((data, IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32)).unwrap()
+}
+
+/// Resident memory of the process, in MiB
+fn resident_memory() -> Option<f64> {
+    // Pages are assumed to be 4 KiB
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages as f64 * 4096. / (1024. * 1024.))
+}
+
+/// Nearest-rank percentile of a sorted, non-empty slice
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+async fn send_images(input: HostInput, rate: f64, probe: Arc<Mutex<Probe>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1. / rate));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    for phase in 0u32.. {
+        interval.tick().await;
+
+        let image = Arc::new(image(phase));
+        probe.lock().unwrap().image_sent();
+        if input.try_send(HostInputData::Image(image), None).is_err() {
+            break;
+        }
+    }
+}
+
+async fn send_colors(input: HostInput, interval: Duration, probe: Arc<Mutex<Probe>>) {
+    let duration = interval / 2;
+    let mut interval = tokio::time::interval(interval);
+
+    for i in 0u32.. {
+        interval.tick().await;
+
+        let color = Color::new((i * 37) as u8, (i * 91) as u8, (i * 13) as u8);
+        probe.lock().unwrap().hidden(duration);
+        if input
+            .try_send(HostInputData::Color(color), Some(duration))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+async fn send_effects(
+    input: HostInput,
+    name: String,
+    interval: Duration,
+    probe: Arc<Mutex<Probe>>,
+) {
+    let duration = interval / 2;
+    let mut interval = tokio::time::interval(interval);
+
+    let effect = Arc::new(EffectRequest {
+        name,
+        args: Default::default(),
+    });
+
+    loop {
+        interval.tick().await;
+
+        probe.lock().unwrap().hidden(duration);
+        if let Err(error) = input
+            .send(HostInputData::Effect(effect.clone()), Some(duration))
+            .await
+        {
+            warn!(error = %error, effect = %effect.name, "cannot start effect, skipping effects");
+            probe.lock().unwrap().hidden(Duration::ZERO);
+            break;
+        }
+    }
+}
+
+async fn observe_frames(instance: InstanceHandle, probe: Arc<Mutex<Probe>>) -> Result<u64> {
+    let mut frames = instance.observe_frames().await?;
+    while frames.next().await.is_some() {
+        probe.lock().unwrap().frame_written();
+    }
+
+    Ok(frames.skipped())
+}
+
+async fn report(
+    elapsed: Duration,
+    instances: &[InstanceHandle],
+    probe: &Mutex<Probe>,
+) -> Result<Sample> {
+    let mut samples = std::mem::take(&mut probe.lock().unwrap().samples);
+    samples.sort_unstable();
+
+    let mut priorities = 0;
+    let mut dropped_frames = 0;
+    for instance in instances {
+        priorities += instance.current_priorities().await?.len();
+        dropped_frames += instance.input_queue_stats().await?.dropped_frames;
+    }
+
+    let memory = resident_memory();
+    let p99 = (!samples.is_empty()).then(|| percentile(&samples, 0.99));
+
+    info!(
+        elapsed = ?elapsed,
+        memory_mib = ?memory,
+        images = %samples.len(),
+        p50 = ?(!samples.is_empty()).then(|| percentile(&samples, 0.5)),
+        p99 = ?p99,
+        priorities = %priorities,
+        dropped_frames = %dropped_frames,
+        "soak report"
+    );
+
+    Ok(Sample {
+        memory,
+        p99: p99.map(|p99| p99.as_secs_f64() * 1000.),
+    })
+}
+
+async fn run(opts: Opts) -> Result<()> {
+    let config = Config::new(
+        GlobalConfig::default(),
+        (0..opts.instances).map(|id| instance_config(id, opts.leds)),
+    );
+
+    let hyperion = hyperion::Builder::new(config).build().await?;
+
+    let mut instances = Vec::new();
+    for id in 0..opts.instances {
+        if let Some(instance) = hyperion.instance(id).await {
+            instances.push(instance);
+        }
+    }
+
+    // Only the first instance is probed, the others add load
+    let probe = Arc::new(Mutex::new(Probe::new()));
+    let observer = tokio::spawn(observe_frames(instances[0].clone(), probe.clone()));
+
+    let generators = vec![
+        tokio::spawn(send_images(
+            hyperion.input_source("soak-images", IMAGE_PRIORITY).await?,
+            opts.image_rate,
+            probe.clone(),
+        )),
+        tokio::spawn(send_colors(
+            hyperion.input_source("soak-colors", COLOR_PRIORITY).await?,
+            Duration::from_millis(opts.color_interval),
+            probe.clone(),
+        )),
+        tokio::spawn(send_effects(
+            hyperion
+                .input_source("soak-effects", EFFECT_PRIORITY)
+                .await?,
+            opts.effect.clone(),
+            Duration::from_secs(opts.effect_interval),
+            probe.clone(),
+        )),
+    ];
+
+    let start = Instant::now();
+    let end = start + Duration::from_secs(opts.duration);
+    let warmup = Duration::from_secs(opts.warmup);
+
+    let mut interval = tokio::time::interval(Duration::from_secs(opts.report_interval));
+    interval.tick().await;
+
+    let mut baseline: Option<Sample> = None;
+    let mut last = None;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::time::sleep_until(end.into()) => break,
+            _ = signal::ctrl_c() => {
+                warn!("interrupted");
+                break;
+            }
+        }
+
+        let sample = report(start.elapsed(), &instances, &probe).await?;
+        if start.elapsed() >= warmup && baseline.is_none() {
+            baseline = Some(sample);
+        }
+
+        last = Some(sample);
+    }
+
+    for generator in generators {
+        generator.abort();
+    }
+
+    hyperion.stop().await;
+    let skipped = observer.await??;
+
+    let (baseline, last) = match (baseline, last) {
+        (Some(baseline), Some(last)) => (baseline, last),
+        _ => bail!("the run ended before the warmup, nothing to compare"),
+    };
+
+    let memory_growth = last.memory.zip(baseline.memory).map(|(a, b)| a - b);
+    let latency_drift = last.p99.zip(baseline.p99).map(|(a, b)| a - b);
+
+    info!(
+        memory_growth_mib = ?memory_growth,
+        latency_drift_ms = ?latency_drift,
+        skipped_frames = %skipped,
+        "soak test finished"
+    );
+
+    if let Some(growth) = memory_growth.filter(|growth| *growth > opts.max_memory_growth) {
+        bail!("resident memory grew by {:.1} MiB", growth);
+    }
+
+    if let Some(drift) = latency_drift.filter(|drift| *drift > opts.max_latency_drift) {
+        bail!("image latency drifted by {:.1} ms", drift);
+    }
+
+    Ok(())
+}
+
+fn install_tracing(opts: &Opts) -> Result<(), tracing_subscriber::util::TryInitError> {
+    use tracing_error::ErrorLayer;
+    use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+    let filter_layer = EnvFilter::try_from_env("HYPERION_LOG").unwrap_or_else(|_| {
+        EnvFilter::new(match opts.verbose {
+            0 => "hyperion=warn,hyperion_soak=info",
+            1 => "hyperion=info,hyperion_soak=info",
+            2 => "hyperion=debug,hyperion_soak=debug",
+            _ => "hyperion=trace,hyperion_soak=trace",
+        })
+    });
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(ErrorLayer::default())
+        .try_init()
+}
+
+#[paw::main]
+fn main(opts: Opts) -> Result<()> {
+    color_eyre::install()?;
+    install_tracing(&opts)?;
+
+    let rt = Builder::new_multi_thread()
+        .worker_threads(num_cpus::get().clamp(2, 4))
+        .enable_all()
+        .build()?;
+    rt.block_on(run(opts))
+}
@@ -0,0 +1,419 @@
+//! Embedding hyperion in other applications
+//!
+//! [Builder] sets up the state shared by all instances, discovers effects and starts the
+//! instances of a [Config], which can be loaded from a backend or built in code. The network
+//! servers are optional: without them, inputs are injected through [Hyperion::input_source] and
+//! instances are controlled through their [InstanceHandle].
+//!
+//! ```no_run
+//! use hyperion::{
+//!     models::{Color, Config, GlobalConfig, InstanceConfig},
+//!     Builder, HostInputData,
+//! };
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = Config::new(GlobalConfig::default(), [InstanceConfig::new_dummy(0)]);
+//! let hyperion = Builder::new(config).build().await?;
+//!
+//! let input = hyperion.input_source("my-app", 100).await?;
+//! input.send(HostInputData::Color(Color::new(255, 0, 0)), None).await?;
+//!
+//! // From a real-time thread, without awaiting
+//! input.try_send(HostInputData::Color(Color::new(0, 0, 255)), None)?;
+//!
+//! hyperion.stop().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+mod input;
+pub use input::*;
+
+use crate::{
+    effects::{EffectDefinitionError, EffectRegistry, Providers},
+    global::{
+        EnvironmentReport, Event, Global, GlobalData, HookRunner, InputSourceError,
+        InputSourceName, InstanceEventKind, LogBuffer, Paths,
+    },
+    instance::{Instance, InstanceHandle},
+    models::{Config, InstanceConfig},
+    servers::{
+        mdns::{self, MdnsHandle},
+        ssdp::{self, SsdpHandle},
+        ServerId,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum BuilderError {
+    #[error("error resolving paths: {0}")]
+    Paths(#[from] std::io::Error),
+    #[error("error discovering effects: {0}")]
+    Effects(#[from] EffectDefinitionError),
+    #[error("instance {0} is already running")]
+    InstanceExists(i32),
+}
+
+/// Builder for a running [Hyperion] core
+pub struct Builder {
+    config: Config,
+    paths: Option<Paths>,
+    database_path: Option<PathBuf>,
+    read_only: bool,
+    environment: Option<EnvironmentReport>,
+    log_buffer: Option<LogBuffer>,
+    effect_paths: Vec<String>,
+    servers: bool,
+    mdns: bool,
+    ssdp: bool,
+}
+
+impl Builder {
+    /// Start building a core running the instances of `config`
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            paths: None,
+            database_path: None,
+            read_only: false,
+            environment: None,
+            log_buffer: None,
+            effect_paths: vec![
+                "$SYSTEM/effects".to_owned(),
+                crate::effects::CUSTOM_EFFECTS_PATH.to_owned(),
+            ],
+            servers: false,
+            mdns: false,
+            ssdp: false,
+        }
+    }
+
+    /// Set the paths to resolve `$ROOT` and `$SYSTEM` with, instead of the default user root
+    pub fn paths(mut self, paths: Paths) -> Self {
+        self.paths = Some(paths);
+        self
+    }
+
+    /// Set the path to the configuration database `config` was loaded from
+    ///
+    /// Configuration changes made through the API are saved there.
+    pub fn database_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.database_path = Some(path.into());
+        self
+    }
+
+    /// Mark the configuration as read-only, i.e. changes to it can't be persisted
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Set the environment report returned by the `sysinfo` command
+    pub fn environment(mut self, environment: EnvironmentReport) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Set the buffer log records are collected into, for the JSON `logging` command
+    ///
+    /// Its [layer](LogBuffer::layer) has to be installed in the tracing subscriber.
+    pub fn log_buffer(mut self, log_buffer: LogBuffer) -> Self {
+        self.log_buffer = Some(log_buffer);
+        self
+    }
+
+    /// Set the directories to discover effects in, which may use path variables
+    ///
+    /// Missing directories are skipped.
+    pub fn effect_paths(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.effect_paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Start the servers enabled in the configuration (JSON, web, Flatbuffers, Protobuf and
+    /// Boblight)
+    ///
+    /// Disabled by default. Servers can also be started later with [Global::start_server].
+    pub fn servers(mut self, servers: bool) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// Advertise the running servers over mDNS, for clients discovering hyperion.ng servers
+    ///
+    /// Disabled by default.
+    pub fn mdns(mut self, mdns: bool) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Answer SSDP searches with the description served by the web server
+    ///
+    /// Disabled by default.
+    pub fn ssdp(mut self, ssdp: bool) -> Self {
+        self.ssdp = ssdp;
+        self
+    }
+
+    /// Start the core
+    pub async fn build(self) -> Result<Hyperion, BuilderError> {
+        let paths = match self.paths {
+            Some(paths) => paths,
+            None => Paths::new(None)?,
+        };
+
+        let mut global = GlobalData::new(&self.config, paths.clone()).read_only(self.read_only);
+        if let Some(environment) = self.environment {
+            global = global.environment(environment);
+        }
+        if let Some(path) = self.database_path {
+            global = global.database_path(path);
+        }
+        if let Some(log_buffer) = self.log_buffer {
+            global = global.log_buffer(log_buffer);
+        }
+        let global = global.wrap();
+
+        let mut effects = EffectRegistry::new();
+        let providers = Providers::new();
+
+        for path in &self.effect_paths {
+            effects
+                .add_dir(&providers, &paths.resolve_path(path))
+                .await?;
+        }
+
+        info!("discovered {} effects", effects.len());
+        global.write_effects(|e| *e = effects).await;
+
+        tokio::spawn(
+            HookRunner::new(
+                self.config.global.hooks.clone(),
+                global.subscribe_events().await,
+            )
+            .run(),
+        );
+
+        // Started before the instances, so it announces them as they start
+        #[cfg(feature = "mqtt")]
+        if self.config.global.mqtt.enable {
+            crate::mqtt::start(self.config.global.mqtt.clone(), global.clone())
+                .await
+                .unwrap_or_else(|error| {
+                    error!(error = %error, "cannot start MQTT client");
+                });
+        }
+
+        #[cfg(not(feature = "mqtt"))]
+        if self.config.global.mqtt.enable {
+            warn!("MQTT is enabled, but hyperion.rs was built without MQTT support");
+        }
+
+        if self.config.global.forwarder.enable {
+            crate::forwarder::start(self.config.global.forwarder.clone(), global.clone()).await;
+        }
+
+        let mdns = if self.mdns {
+            mdns::start(global.clone())
+                .map_err(|error| {
+                    error!(error = %error, "cannot start mDNS responder");
+                })
+                .ok()
+        } else {
+            None
+        };
+
+        let ssdp = if self.ssdp {
+            ssdp::start(global.clone())
+                .map_err(|error| {
+                    error!(error = %error, "cannot start SSDP responder");
+                })
+                .ok()
+        } else {
+            None
+        };
+
+        let hyperion = Hyperion { global, mdns, ssdp };
+        for config in self.config.instances.values() {
+            hyperion.add_instance(config.clone()).await?;
+        }
+
+        // Servers which can't be bound are retried without failing the build
+        if self.servers {
+            for id in ServerId::GLOBAL {
+                if id.is_enabled(&self.config) {
+                    hyperion.global.start_server(id).await.ok();
+                }
+            }
+        }
+
+        // ok: no one may be listening to events
+        hyperion.global.get_event_tx().await.send(Event::Start).ok();
+
+        Ok(hyperion)
+    }
+}
+
+/// Running hyperion core, see [Builder]
+pub struct Hyperion {
+    global: Global,
+    mdns: Option<MdnsHandle>,
+    ssdp: Option<SsdpHandle>,
+}
+
+impl Hyperion {
+    /// State shared by all instances
+    pub fn global(&self) -> &Global {
+        &self.global
+    }
+
+    pub async fn instance(&self, id: i32) -> Option<InstanceHandle> {
+        self.global.get_instance(id).await
+    }
+
+    /// Start a new instance
+    ///
+    /// The instance runs until it is stopped through its handle, and is started again when
+    /// restarted by a configuration change.
+    pub async fn add_instance(
+        &self,
+        config: InstanceConfig,
+    ) -> Result<InstanceHandle, BuilderError> {
+        let id = config.instance.id;
+        if self.global.get_instance(id).await.is_some() {
+            return Err(BuilderError::InstanceExists(id));
+        }
+
+        let (instance, handle) = Instance::new(self.global.clone(), config).await;
+        self.global.register_instance(handle.clone()).await;
+        tokio::spawn(run_instance(self.global.clone(), id, instance));
+
+        Ok(handle)
+    }
+
+    /// Register a source for injecting inputs into all instances
+    ///
+    /// The inputs of the source are cleared when the returned handle is dropped.
+    pub async fn input_source(
+        &self,
+        name: impl Into<String>,
+        priority: i32,
+    ) -> Result<HostInput, InputSourceError> {
+        let source = self
+            .global
+            .register_input_source(InputSourceName::Api { name: name.into() }, Some(priority))
+            .await?;
+
+        Ok(HostInput::new(source, priority))
+    }
+
+    /// Stop the servers, then all instances
+    pub async fn stop(self) {
+        if let Some(mdns) = self.mdns {
+            mdns.stop().await;
+        }
+
+        if let Some(ssdp) = self.ssdp {
+            ssdp.stop().await;
+        }
+
+        // Disconnect clients while instances are still running, so their inputs are cleared
+        self.global.stop_servers().await;
+
+        for (_, instance) in self.global.instances().await {
+            instance.stop().await.ok();
+        }
+
+        self.global.get_event_tx().await.send(Event::Stop).ok();
+    }
+}
+
+/// Run an instance until it stops, creating it again every time it is restarted
+async fn run_instance(global: Global, id: i32, mut instance: Instance) {
+    let event_tx = global.get_event_tx().await;
+
+    loop {
+        event_tx
+            .send(Event::instance(id, InstanceEventKind::Start))
+            .map(|_| ())
+            .unwrap_or_else(|err| {
+                error!(error = %err, "event error");
+            });
+
+        if let Err(error) = instance.run().await {
+            error!(error = %error, "instance error");
+        }
+
+        // Restarted instances are created again with their updated configuration
+        let restart = global.take_instance_restart(id).await;
+        if restart.is_none() {
+            global.unregister_instance(id).await;
+        }
+
+        event_tx
+            .send(Event::instance(id, InstanceEventKind::Stop))
+            .map(|_| ())
+            .unwrap_or_else(|err| {
+                error!(error = %err, "event error");
+            });
+
+        match restart {
+            Some(config) => {
+                let (new_instance, handle) = Instance::new(global.clone(), config).await;
+                global.register_instance(handle).await;
+                instance = new_instance;
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Color, GlobalConfig};
+
+    #[tokio::test]
+    async fn test_host_input() {
+        let config = Config::new(GlobalConfig::default(), [InstanceConfig::new_dummy(0)]);
+        let hyperion = Builder::new(config)
+            .effect_paths(Vec::<String>::new())
+            .build()
+            .await
+            .unwrap();
+
+        let mut frames = hyperion
+            .instance(0)
+            .await
+            .unwrap()
+            .observe_frames()
+            .await
+            .unwrap();
+
+        let input = hyperion.input_source("test", 100).await.unwrap();
+        input
+            .try_send(HostInputData::Color(Color::new(255, 0, 0)), None)
+            .unwrap();
+
+        // Wait for smoothing to settle on the input color
+        let red = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while let Some(frame) = frames.next().await {
+                if frame.led_colors.iter().all(|c| *c == Color::new(255, 0, 0)) {
+                    return true;
+                }
+            }
+
+            false
+        })
+        .await;
+
+        assert_eq!(red.ok(), Some(true));
+
+        drop(input);
+        hyperion.stop().await;
+    }
+}
@@ -0,0 +1,157 @@
+//! Inputs pushed by the application embedding hyperion
+
+use std::{sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::sync::{oneshot, Mutex};
+
+use crate::{
+    api::json::message::EffectRequest,
+    component::ComponentName,
+    global::{InputMessage, InputMessageData, InputSourceHandle},
+    image::RawImage,
+    instance::StartEffectError,
+    models::Color,
+};
+
+#[derive(Debug, Error)]
+pub enum HostInputError {
+    #[error("no instance is running")]
+    NoInstance,
+    #[error("no instance answered the effect request")]
+    NoAnswer,
+    #[error(transparent)]
+    StartEffect(#[from] StartEffectError),
+}
+
+/// Input of a host application
+#[derive(Debug, Clone)]
+pub enum HostInputData {
+    /// Same color on all LEDs
+    Color(Color),
+    /// Image, mapped to the LEDs through the layout of each instance
+    Image(Arc<RawImage>),
+    /// Color of each LED, in layout order
+    LedColors(Arc<Vec<Color>>),
+    /// Effect to start, by name
+    Effect(Arc<EffectRequest>),
+}
+
+/// Source of inputs for all instances, at a fixed priority
+///
+/// Inputs of this source are cleared when it is dropped. Dropping it briefly waits for the global
+/// state, so it shouldn't be done from a real-time thread.
+pub struct HostInput {
+    source: InputSourceHandle<InputMessage>,
+    priority: i32,
+}
+
+impl HostInput {
+    pub(crate) fn new(source: InputSourceHandle<InputMessage>, priority: i32) -> Self {
+        Self { source, priority }
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Push an input, and wait for effects to be started
+    ///
+    /// `duration` is the time after which the input is cleared, `None` to keep it until it is
+    /// replaced or cleared.
+    pub async fn send(
+        &self,
+        input: HostInputData,
+        duration: Option<Duration>,
+    ) -> Result<(), HostInputError> {
+        let effect = matches!(input, HostInputData::Effect(_));
+        let (tx, rx) = oneshot::channel();
+
+        self.send_message(input, duration, Some(tx))?;
+
+        if effect {
+            rx.await.map_err(|_| HostInputError::NoAnswer)??;
+        }
+
+        Ok(())
+    }
+
+    /// Push an input without waiting
+    ///
+    /// This neither blocks nor needs an async runtime, so it can be called from real-time
+    /// threads. Instances which lag behind skip the oldest inputs, and failures to start effects
+    /// are only logged.
+    pub fn try_send(
+        &self,
+        input: HostInputData,
+        duration: Option<Duration>,
+    ) -> Result<(), HostInputError> {
+        self.send_message(input, duration, None)
+    }
+
+    /// Clear the input of this source, without waiting
+    pub fn clear(&self) -> Result<(), HostInputError> {
+        self.source
+            .send(
+                ComponentName::All,
+                InputMessageData::Clear {
+                    priority: self.priority,
+                },
+            )
+            .map(|_| ())
+            .map_err(|_| HostInputError::NoInstance)
+    }
+
+    fn send_message(
+        &self,
+        input: HostInputData,
+        duration: Option<Duration>,
+        response: Option<oneshot::Sender<Result<(), StartEffectError>>>,
+    ) -> Result<(), HostInputError> {
+        let priority = self.priority;
+        let duration = duration.and_then(|duration| chrono::Duration::from_std(duration).ok());
+
+        let (component, data) = match input {
+            HostInputData::Color(color) => (
+                ComponentName::Color,
+                InputMessageData::SolidColor {
+                    priority,
+                    duration,
+                    color,
+                },
+            ),
+            HostInputData::Image(image) => (
+                ComponentName::Image,
+                InputMessageData::Image {
+                    priority,
+                    duration,
+                    image,
+                    region: None,
+                },
+            ),
+            HostInputData::LedColors(led_colors) => (
+                ComponentName::Color,
+                InputMessageData::LedColors {
+                    priority,
+                    duration,
+                    led_colors,
+                },
+            ),
+            HostInputData::Effect(effect) => (
+                ComponentName::All,
+                InputMessageData::Effect {
+                    priority,
+                    duration,
+                    effect,
+                    response: Arc::new(Mutex::new(response)),
+                },
+            ),
+        };
+
+        // Sending only fails if no instance is subscribed to inputs
+        self.source
+            .send(component, data)
+            .map(|_| ())
+            .map_err(|_| HostInputError::NoInstance)
+    }
+}
@@ -0,0 +1,27 @@
+//! Clients for the hyperion network protocols
+//!
+//! Each submodule connects to a hyperion server (either hyperion.rs or the original
+//! implementation) over one of the protocols served by [`crate::servers`], and sends colors and
+//! images to it. The wire formats are shared with the server side, so applications can embed a
+//! client without depending on the rest of the daemon.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), hyperion::client::json::JsonClientError> {
+//! use hyperion::{client::json::JsonClient, models::Color};
+//!
+//! let mut client = JsonClient::connect("127.0.0.1:19444", "my-app").await?;
+//! client.color(150, Color::new(255, 0, 0), None).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+pub mod flat;
+pub mod json;
+pub mod proto;
+
+/// Convert a duration to milliseconds, as used on the wire
+fn duration_to_ms(duration: Duration) -> i32 {
+    duration.as_millis().min(i32::MAX as u128) as i32
+}
@@ -0,0 +1,222 @@
+//! Flatbuffers protocol client
+
+use std::time::Duration;
+
+use futures::prelude::*;
+use thiserror::Error;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use super::duration_to_ms;
+use crate::{
+    api::{flat::message, types::color_to_i32},
+    image::{Image, RawImage},
+    models::Color,
+};
+
+#[derive(Debug, Error)]
+pub enum FlatClientError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("error decoding reply")]
+    FlatBuffer(#[from] flatbuffers::InvalidFlatbuffer),
+    #[error("connection closed by the server")]
+    Closed,
+    #[error("request failed: {0}")]
+    Failed(String),
+}
+
+/// Client for the flatbuffers protocol
+///
+/// Flatbuffers clients register with a single priority, which is used for all the colors and
+/// images they send. Sending to a different priority registers again.
+pub struct FlatClient {
+    framed: Framed<TcpStream, LengthDelimitedCodec>,
+    builder: flatbuffers::FlatBufferBuilder<'static>,
+    origin: String,
+    registered: Option<i32>,
+}
+
+impl FlatClient {
+    /// Connect to a flatbuffers server
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`: address of the server
+    /// * `origin`: name reported to the server for the colors and images sent by this client
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        origin: impl Into<String>,
+    ) -> Result<Self, FlatClientError> {
+        let socket = TcpStream::connect(addr).await?;
+
+        Ok(Self {
+            framed: LengthDelimitedCodec::builder()
+                .length_field_length(4)
+                .new_framed(socket),
+            builder: flatbuffers::FlatBufferBuilder::new(),
+            origin: origin.into(),
+            registered: None,
+        })
+    }
+
+    /// Send a request and wait for its reply
+    async fn send(&mut self, request: bytes::Bytes) -> Result<(), FlatClientError> {
+        self.framed.send(request).await?;
+
+        let reply = self.framed.next().await.ok_or(FlatClientError::Closed)??;
+        let reply = message::root_as_reply(&reply)?;
+
+        match reply.error() {
+            Some(error) => Err(FlatClientError::Failed(error.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    /// Register with the given priority, if not already done
+    async fn register(&mut self, priority: i32) -> Result<(), FlatClientError> {
+        if self.registered == Some(priority) {
+            return Ok(());
+        }
+
+        let request = register_request(&mut self.builder, &self.origin, priority);
+        self.send(request).await?;
+        self.registered = Some(priority);
+        Ok(())
+    }
+
+    /// Set a solid color
+    pub async fn color(
+        &mut self,
+        priority: i32,
+        color: Color,
+        duration: Option<Duration>,
+    ) -> Result<(), FlatClientError> {
+        self.register(priority).await?;
+
+        let request = color_request(&mut self.builder, color, duration);
+        self.send(request).await
+    }
+
+    /// Set an image
+    pub async fn image(
+        &mut self,
+        priority: i32,
+        image: &RawImage,
+        duration: Option<Duration>,
+    ) -> Result<(), FlatClientError> {
+        self.register(priority).await?;
+
+        let request = image_request(&mut self.builder, image, duration);
+        self.send(request).await
+    }
+
+    /// Clear the given priority
+    pub async fn clear(&mut self, priority: i32) -> Result<(), FlatClientError> {
+        self.register(priority).await?;
+
+        let request = clear_request(&mut self.builder, priority);
+        self.send(request).await
+    }
+
+    /// Clear all priorities
+    pub async fn clear_all(&mut self) -> Result<(), FlatClientError> {
+        let request = clear_request(&mut self.builder, -1);
+        self.send(request).await
+    }
+}
+
+/// Finish a request in `builder`, returning it without its length prefix
+fn finish_request(
+    builder: &mut flatbuffers::FlatBufferBuilder<'static>,
+    command_type: message::Command,
+    command: flatbuffers::WIPOffset<flatbuffers::UnionWIPOffset>,
+) -> bytes::Bytes {
+    let request = message::Request::create(
+        builder,
+        &message::RequestArgs {
+            command_type,
+            command: Some(command),
+        },
+    );
+
+    builder.finish(request, None);
+    let data = bytes::Bytes::copy_from_slice(builder.finished_data());
+    builder.reset();
+    data
+}
+
+/// Request registering with a priority, used by the following colors and images
+pub(crate) fn register_request(
+    builder: &mut flatbuffers::FlatBufferBuilder<'static>,
+    origin: &str,
+    priority: i32,
+) -> bytes::Bytes {
+    let origin = builder.create_string(origin);
+    let register = message::Register::create(
+        builder,
+        &message::RegisterArgs {
+            origin: Some(origin),
+            priority,
+        },
+    );
+
+    finish_request(
+        builder,
+        message::Command::Register,
+        register.as_union_value(),
+    )
+}
+
+pub(crate) fn color_request(
+    builder: &mut flatbuffers::FlatBufferBuilder<'static>,
+    color: Color,
+    duration: Option<Duration>,
+) -> bytes::Bytes {
+    let color = message::Color::create(
+        builder,
+        &message::ColorArgs {
+            data: color_to_i32(color),
+            duration: duration.map(duration_to_ms).unwrap_or(-1),
+        },
+    );
+
+    finish_request(builder, message::Command::Color, color.as_union_value())
+}
+
+pub(crate) fn image_request(
+    builder: &mut flatbuffers::FlatBufferBuilder<'static>,
+    image: &RawImage,
+    duration: Option<Duration>,
+) -> bytes::Bytes {
+    let data = builder.create_vector(image.data());
+    let raw_image = message::RawImage::create(
+        builder,
+        &message::RawImageArgs {
+            data: Some(data),
+            width: image.width() as _,
+            height: image.height() as _,
+        },
+    );
+
+    let image = message::Image::create(
+        builder,
+        &message::ImageArgs {
+            data_type: message::ImageType::RawImage,
+            data: Some(raw_image.as_union_value()),
+            duration: duration.map(duration_to_ms).unwrap_or(-1),
+            ..Default::default()
+        },
+    );
+
+    finish_request(builder, message::Command::Image, image.as_union_value())
+}
+
+/// Request clearing a priority, or all of them if it is negative
+pub(crate) fn clear_request(
+    builder: &mut flatbuffers::FlatBufferBuilder<'static>,
+    priority: i32,
+) -> bytes::Bytes {
+    let clear = message::Clear::create(builder, &message::ClearArgs { priority });
+    finish_request(builder, message::Command::Clear, clear.as_union_value())
+}
@@ -0,0 +1,206 @@
+//! JSON protocol client
+
+use std::{collections::VecDeque, time::Duration};
+
+use base64::Engine;
+use futures::prelude::*;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+use super::duration_to_ms;
+use crate::{
+    image::{Image, RawImage},
+    models::Color,
+    servers::json::{JsonCodec, JsonCodecError},
+};
+
+#[derive(Debug, Error)]
+pub enum JsonClientError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("codec error: {0}")]
+    Codec(#[from] JsonCodecError),
+    #[error("invalid reply: {0}")]
+    InvalidReply(#[from] serde_json::Error),
+    #[error("connection closed by the server")]
+    Closed,
+    #[error("request failed: {0}")]
+    Failed(String),
+}
+
+/// Reply to a JSON request
+#[derive(Debug, Deserialize)]
+pub struct JsonReply {
+    pub success: bool,
+    pub tan: Option<i32>,
+    pub error: Option<String>,
+    /// Remaining fields of the reply, e.g. `command` and `info`
+    #[serde(flatten)]
+    pub info: serde_json::Map<String, Value>,
+}
+
+/// Request setting a solid color
+pub(crate) fn color_request(
+    priority: i32,
+    color: Color,
+    duration: Option<Duration>,
+    origin: &str,
+) -> Value {
+    json!({
+        "command": "color",
+        "priority": priority,
+        "color": [color.red, color.green, color.blue],
+        "duration": duration.map(duration_to_ms),
+        "origin": origin,
+    })
+}
+
+/// Request setting an image, sent as raw RGB data
+pub(crate) fn image_request(
+    priority: i32,
+    image: &RawImage,
+    duration: Option<Duration>,
+    origin: &str,
+) -> Value {
+    json!({
+        "command": "image",
+        "priority": priority,
+        "imagewidth": image.width(),
+        "imageheight": image.height(),
+        "imagedata": base64::engine::general_purpose::STANDARD.encode(image.data()),
+        "duration": duration.map(duration_to_ms),
+        "origin": origin,
+    })
+}
+
+pub(crate) fn clear_request(priority: i32) -> Value {
+    json!({ "command": "clear", "priority": priority })
+}
+
+pub(crate) fn clear_all_request() -> Value {
+    json!({ "command": "clearall" })
+}
+
+/// Client for the JSON protocol
+pub struct JsonClient {
+    framed: Framed<TcpStream, JsonCodec<Value>>,
+    origin: String,
+    next_tan: i32,
+    /// Messages received while waiting for a reply, e.g. subscription updates
+    updates: VecDeque<Value>,
+}
+
+impl JsonClient {
+    /// Connect to a JSON server
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`: address of the server
+    /// * `origin`: name reported to the server for the colors and images sent by this client
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        origin: impl Into<String>,
+    ) -> Result<Self, JsonClientError> {
+        let socket = TcpStream::connect(addr).await?;
+
+        Ok(Self {
+            framed: Framed::new(socket, JsonCodec::new()),
+            origin: origin.into(),
+            next_tan: 1,
+            updates: VecDeque::new(),
+        })
+    }
+
+    /// Send a request and wait for its reply
+    ///
+    /// The `tan` field of `command` is overwritten to match the reply with the request.
+    pub async fn request(&mut self, mut command: Value) -> Result<JsonReply, JsonClientError> {
+        let tan = self.next_tan;
+        self.next_tan = self.next_tan.wrapping_add(1);
+        command["tan"] = tan.into();
+
+        self.framed.send(command).await?;
+
+        loop {
+            let message = self.framed.next().await.ok_or(JsonClientError::Closed)??;
+
+            // Errors for requests which couldn't be parsed don't carry the tan
+            let is_reply = match message.get("tan").and_then(Value::as_i64) {
+                Some(reply_tan) => reply_tan == tan as i64,
+                None => message.get("success") == Some(&Value::Bool(false)),
+            };
+
+            if !is_reply {
+                self.updates.push_back(message);
+                continue;
+            }
+
+            let reply: JsonReply = serde_json::from_value(message)?;
+            if !reply.success {
+                return Err(JsonClientError::Failed(
+                    reply.error.unwrap_or_else(|| "unknown error".to_owned()),
+                ));
+            }
+
+            return Ok(reply);
+        }
+    }
+
+    /// Wait for the next message which isn't a reply to a request, e.g. a subscription update
+    pub async fn next_update(&mut self) -> Result<Value, JsonClientError> {
+        if let Some(update) = self.updates.pop_front() {
+            return Ok(update);
+        }
+
+        Ok(self.framed.next().await.ok_or(JsonClientError::Closed)??)
+    }
+
+    /// Set a solid color
+    pub async fn color(
+        &mut self,
+        priority: i32,
+        color: Color,
+        duration: Option<Duration>,
+    ) -> Result<(), JsonClientError> {
+        self.request(color_request(priority, color, duration, &self.origin))
+            .await
+            .map(|_| ())
+    }
+
+    /// Set an image, sent as raw RGB data
+    pub async fn image(
+        &mut self,
+        priority: i32,
+        image: &RawImage,
+        duration: Option<Duration>,
+    ) -> Result<(), JsonClientError> {
+        self.request(image_request(priority, image, duration, &self.origin))
+            .await
+            .map(|_| ())
+    }
+
+    /// Clear the given priority
+    pub async fn clear(&mut self, priority: i32) -> Result<(), JsonClientError> {
+        self.request(clear_request(priority)).await.map(|_| ())
+    }
+
+    /// Clear all priorities
+    pub async fn clear_all(&mut self) -> Result<(), JsonClientError> {
+        self.request(clear_all_request()).await.map(|_| ())
+    }
+
+    /// Get the server information
+    ///
+    /// The server then sends updates for the `subscribe` topics, see [`JsonClient::next_update`].
+    pub async fn server_info(&mut self, subscribe: &[&str]) -> Result<JsonReply, JsonClientError> {
+        let mut command = json!({ "command": "serverinfo" });
+        if !subscribe.is_empty() {
+            command["subscribe"] = subscribe.into();
+        }
+
+        self.request(command).await
+    }
+}
@@ -0,0 +1,165 @@
+//! Protobuf protocol client
+
+use std::time::Duration;
+
+use futures::prelude::*;
+use thiserror::Error;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+use super::duration_to_ms;
+use crate::{
+    api::{proto::message, types::color_to_i32},
+    image::{Image, RawImage},
+    models::Color,
+    servers::proto::{ProtoCodec, ProtoCodecError},
+};
+
+#[derive(Debug, Error)]
+pub enum ProtoClientError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("codec error: {0}")]
+    Codec(#[from] ProtoCodecError),
+    #[error("connection closed by the server")]
+    Closed,
+    #[error("request failed: {0}")]
+    Failed(String),
+}
+
+/// Client for the protobuf protocol
+pub struct ProtoClient {
+    framed: Framed<TcpStream, ProtoCodec<message::HyperionReply>>,
+}
+
+impl ProtoClient {
+    /// Connect to a protobuf server
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, ProtoClientError> {
+        let socket = TcpStream::connect(addr).await?;
+
+        Ok(Self {
+            framed: Framed::new(socket, ProtoCodec::new()),
+        })
+    }
+
+    /// Send a request and wait for its reply
+    pub async fn request(
+        &mut self,
+        request: message::HyperionRequest,
+    ) -> Result<(), ProtoClientError> {
+        self.framed.send(request).await?;
+
+        loop {
+            let reply = self.framed.next().await.ok_or(ProtoClientError::Closed)??;
+
+            // Skip the video mode notifications sent by the original implementation
+            if reply.r#type() != message::hyperion_reply::Type::Reply {
+                continue;
+            }
+
+            return if reply.success() {
+                Ok(())
+            } else {
+                Err(ProtoClientError::Failed(
+                    reply.error.unwrap_or_else(|| "unknown error".to_owned()),
+                ))
+            };
+        }
+    }
+
+    /// Set a solid color
+    pub async fn color(
+        &mut self,
+        priority: i32,
+        color: Color,
+        duration: Option<Duration>,
+    ) -> Result<(), ProtoClientError> {
+        self.request(message::HyperionRequest {
+            command: message::hyperion_request::Command::Color.into(),
+            color_request: Some(message::ColorRequest {
+                priority,
+                rgb_color: color_to_i32(color),
+                duration: duration.map(duration_to_ms),
+            }),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Set an image
+    pub async fn image(
+        &mut self,
+        priority: i32,
+        image: &RawImage,
+        duration: Option<Duration>,
+    ) -> Result<(), ProtoClientError> {
+        self.request(message::HyperionRequest {
+            command: message::hyperion_request::Command::Image.into(),
+            image_request: Some(message::ImageRequest {
+                priority,
+                imagewidth: image.width() as _,
+                imageheight: image.height() as _,
+                imagedata: image.data().to_vec(),
+                duration: duration.map(duration_to_ms),
+            }),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Clear the given priority
+    pub async fn clear(&mut self, priority: i32) -> Result<(), ProtoClientError> {
+        self.request(message::HyperionRequest {
+            command: message::hyperion_request::Command::Clear.into(),
+            clear_request: Some(message::ClearRequest { priority }),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Clear all priorities
+    pub async fn clear_all(&mut self) -> Result<(), ProtoClientError> {
+        self.request(message::HyperionRequest {
+            command: message::hyperion_request::Command::Clearall.into(),
+            ..Default::default()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+    use crate::api::types::i32_to_color;
+
+    #[test]
+    fn test_color_round_trip() {
+        let color = Color::new(255, 128, 1);
+
+        let mut buf = BytesMut::new();
+        ProtoCodec::<message::HyperionReply>::new()
+            .encode(
+                message::HyperionRequest {
+                    command: message::hyperion_request::Command::Color.into(),
+                    color_request: Some(message::ColorRequest {
+                        priority: 150,
+                        rgb_color: color_to_i32(color),
+                        duration: None,
+                    }),
+                    ..Default::default()
+                },
+                &mut buf,
+            )
+            .unwrap();
+
+        let request: message::HyperionRequest =
+            ProtoCodec::new().decode(&mut buf).unwrap().unwrap();
+        let color_request = request.color_request.unwrap();
+
+        assert_eq!(color_request.rgb_color, 0x00FF8001);
+        assert_eq!(i32_to_color(color_request.rgb_color), color);
+    }
+}
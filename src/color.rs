@@ -1,11 +1,20 @@
-use std::{convert::TryFrom, num::ParseIntError};
+use std::{convert::TryFrom, num::ParseIntError, ops::Range};
 
 use slotmap::{DefaultKey, SlotMap};
 
 use crate::models::{Color, Color16};
 
 mod utils;
-pub use utils::{color_to16, color_to8};
+pub use utils::{color_to16, color_to8, kelvin_to_rgb16};
+
+/// Number of LEDs adjusted together
+///
+/// Batches are processed lane by lane over fixed-size arrays, which the compiler turns into
+/// SIMD instructions.
+const LANES: usize = 8;
+
+#[cfg(test)]
+mod golden;
 
 #[derive(Default, Debug, Clone, Copy)]
 struct RgbChannelAdjustment {
@@ -69,20 +78,21 @@ impl RgbTransform {
     }
 
     pub fn brightness_components(&self) -> BrightnessComponents {
-        let fw = self.brightness_compensation as f32 * 2.0 / 100.0 + 1.0;
-        let fcmy = self.brightness_compensation as f32 / 100.0 + 1.0;
+        // Computed in double precision and rounded up, like hyperion.ng
+        let fw = self.brightness_compensation as f64 * 2.0 / 100.0 + 1.0;
+        let fcmy = self.brightness_compensation as f64 / 100.0 + 1.0;
 
         if self.brightness > 0 {
             let b_in = if self.brightness < 50 {
-                -0.09 * self.brightness as f32 + 7.5
+                -0.09 * self.brightness as f64 + 7.5
             } else {
-                -0.04 * self.brightness as f32 + 5.0
+                -0.04 * self.brightness as f64 + 5.0
             };
 
             BrightnessComponents {
-                rgb: (255.0 / b_in).min(255.0) as u8,
-                cmy: (255.0 / (b_in * fcmy)).min(255.0) as u8,
-                w: (255.0 / (b_in * fw)).min(255.0) as u8,
+                rgb: (255.0 / b_in).min(255.0).ceil() as u8,
+                cmy: (255.0 / (b_in * fcmy)).min(255.0).ceil() as u8,
+                w: (255.0 / (b_in * fw)).min(255.0).ceil() as u8,
             }
         } else {
             BrightnessComponents::default()
@@ -116,7 +126,8 @@ impl RgbTransform {
                     rgb_sum = r as f32 + g as f32 + b as f32;
                 }
 
-                let cl = (self.sum_brightness_low / rgb_sum).min(255.0);
+                // hyperion.ng truncates the scaling factor to an integer
+                let cl = (self.sum_brightness_low / rgb_sum).min(255.0).trunc();
 
                 r = (r as f32 * cl) as u8;
                 g = (g as f32 * cl) as u8;
@@ -185,6 +196,115 @@ impl ColorAdjustmentData {
     }
 }
 
+/// Channel adjustment prepared for adjusting runs of LEDs
+///
+/// This computes the same colors as [ColorAdjustmentData::apply], with the gamma curves
+/// tabulated and the brightness of each channel computed once.
+#[derive(Debug, Clone)]
+struct BatchAdjustment {
+    data: ColorAdjustmentData,
+    /// Gamma curves of the red, green and blue components
+    gamma: [[u8; 256]; 3],
+    /// Red, green, blue, cyan, magenta, yellow and white channels, with their brightness
+    ///
+    /// The black channel is left out, it always maps to black.
+    channels: [(Color, u32); 7],
+}
+
+impl From<ColorAdjustmentData> for BatchAdjustment {
+    fn from(data: ColorAdjustmentData) -> Self {
+        let transform = &data.transform;
+        let gamma = [transform.gamma_r, transform.gamma_g, transform.gamma_b]
+            .map(|gamma| std::array::from_fn(|x| RgbTransform::gamma(x as _, gamma)));
+
+        let brightness = data.transform.brightness_components();
+        let (rgb, cmy, w) = (
+            brightness.rgb as u32,
+            brightness.cmy as u32,
+            brightness.w as u32,
+        );
+
+        Self {
+            data,
+            gamma,
+            channels: [
+                (data.red.adjust, rgb),
+                (data.green.adjust, rgb),
+                (data.blue.adjust, rgb),
+                (data.cyan.adjust, cmy),
+                (data.magenta.adjust, cmy),
+                (data.yellow.adjust, cmy),
+                (data.white.adjust, w),
+            ],
+        }
+    }
+}
+
+impl BatchAdjustment {
+    fn apply(&self, leds: &mut [Color16]) {
+        // The backlight depends on the sum of the components, so it doesn't fit the tables
+        if self.data.transform.backlight_enabled {
+            for led in leds {
+                *led = color_to16(self.data.apply(color_to8(*led)));
+            }
+
+            return;
+        }
+
+        for batch in leds.chunks_mut(LANES) {
+            self.apply_batch(batch);
+        }
+    }
+
+    fn apply_batch(&self, batch: &mut [Color16]) {
+        let mut input = [[0u32; LANES]; 3];
+        for (i, led) in batch.iter().enumerate() {
+            let (r, g, b) = color_to8(*led).into_components();
+            input[0][i] = self.gamma[0][r as usize] as u32;
+            input[1][i] = self.gamma[1][g as usize] as u32;
+            input[2][i] = self.gamma[2][b as usize] as u32;
+        }
+
+        // Share of each channel in the input colors
+        let mut weights = [[0u32; LANES]; 7];
+        for i in 0..LANES {
+            let (r, g, b) = (input[0][i], input[1][i], input[2][i]);
+
+            let nrng = (255 - r) * (255 - g);
+            let rng = r * (255 - g);
+            let nrg = (255 - r) * g;
+            let rg = r * g;
+
+            weights[0][i] = rng * (255 - b) / 65025;
+            weights[1][i] = nrg * (255 - b) / 65025;
+            weights[2][i] = nrng * b / 65025;
+            weights[3][i] = nrg * b / 65025;
+            weights[4][i] = rng * b / 65025;
+            weights[5][i] = rg * (255 - b) / 65025;
+            weights[6][i] = rg * b / 65025;
+        }
+
+        let mut output = [[0u32; LANES]; 3];
+        for (weights, &(color, brightness)) in weights.iter().zip(&self.channels) {
+            let adjust = [color.red as u32, color.green as u32, color.blue as u32];
+
+            for (output, adjust) in output.iter_mut().zip(adjust) {
+                for i in 0..LANES {
+                    output[i] += brightness * weights[i] * adjust / 65025;
+                }
+            }
+        }
+
+        for (i, led) in batch.iter_mut().enumerate() {
+            *led = color_to16(Color::new(
+                output[0][i] as u8,
+                output[1][i] as u8,
+                output[2][i] as u8,
+            ));
+        }
+    }
+}
+
 impl From<&crate::models::ChannelAdjustment> for ColorAdjustmentData {
     fn from(settings: &crate::models::ChannelAdjustment) -> Self {
         Self {
@@ -215,6 +335,30 @@ lazy_static::lazy_static! {
     static ref PATTERN_REGEX: regex::Regex = regex::Regex::new("([0-9]+(\\-[0-9]+)?)(,[ ]*([0-9]+(\\-[0-9]+)?))*").unwrap();
 }
 
+impl LedMatch {
+    /// Highest LED index explicitly targeted by this filter
+    pub fn max_index(&self) -> Option<usize> {
+        match self {
+            LedMatch::Ranges(ranges) => ranges.ranges.iter().map(|range| *range.end()).max(),
+            _ => None,
+        }
+    }
+
+    /// Indices matched by this filter, out of `led_count` LEDs
+    pub fn indices(&self, led_count: usize) -> Vec<usize> {
+        match self {
+            LedMatch::All => (0..led_count).collect(),
+            LedMatch::Ranges(ranges) => ranges
+                .ranges
+                .iter()
+                .flat_map(|range| range.clone())
+                .filter(|index| *index < led_count)
+                .collect(),
+            LedMatch::None => Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LedRanges {
     ranges: Vec<std::ops::RangeInclusive<usize>>,
@@ -279,9 +423,35 @@ impl From<&crate::models::ChannelAdjustment> for ColorAdjustment {
     }
 }
 
+/// Fixed-point representation of a gain of 1
+const GAIN_ONE: u32 = 1 << 15;
+
+/// Gain correction of the LEDs matched by a filter
+#[derive(Debug, Clone)]
+struct LedGain {
+    leds: LedMatch,
+    gain: [u32; 3],
+}
+
+impl From<&crate::models::LedGain> for LedGain {
+    fn from(settings: &crate::models::LedGain) -> Self {
+        let fixed = |gain: f32| (gain.max(0.0) * GAIN_ONE as f32).round() as u32;
+
+        Self {
+            leds: settings.leds.as_str().into(),
+            gain: [
+                fixed(settings.red),
+                fixed(settings.green),
+                fixed(settings.blue),
+            ],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ChannelAdjustmentsBuilder {
     adjustments: Vec<ColorAdjustment>,
+    led_gains: Vec<LedGain>,
     rgb_temperature: u32,
     led_count: u32,
 }
@@ -290,6 +460,7 @@ impl ChannelAdjustmentsBuilder {
     pub fn new(config: &crate::models::ColorAdjustment) -> Self {
         Self {
             adjustments: config.channel_adjustment.iter().map(Into::into).collect(),
+            led_gains: config.led_gain.iter().map(Into::into).collect(),
             rgb_temperature: config.rgb_temperature,
             led_count: 0,
         }
@@ -307,14 +478,21 @@ impl ChannelAdjustmentsBuilder {
         for adjustment in &self.adjustments {
             match &adjustment.leds {
                 LedMatch::All => {
-                    let key = adjustments.insert(adjustment.data);
+                    let key = adjustments.insert(BatchAdjustment::from(adjustment.data));
                     led_mappings.fill(Some(key));
                 }
                 LedMatch::Ranges(ranges) => {
-                    let key = adjustments.insert(adjustment.data);
+                    let key = adjustments.insert(BatchAdjustment::from(adjustment.data));
                     for range in &ranges.ranges {
-                        if let Some(range) = led_mappings.get_mut(range.clone()) {
-                            range.fill(Some(key));
+                        // Clamp the range to the LEDs that actually exist
+                        let end = (*range.end()).min(led_mappings.len().saturating_sub(1));
+
+                        if let Some(mappings) = led_mappings.get_mut(*range.start()..=end) {
+                            if end != *range.end() {
+                                warn!(range = ?range, led_count = %self.led_count, "clamped out of bounds range");
+                            }
+
+                            mappings.fill(Some(key));
                         } else {
                             error!(range = ?range, led_count = %self.led_count, "invalid range");
                         }
@@ -324,44 +502,84 @@ impl ChannelAdjustmentsBuilder {
             }
         }
 
+        // Group the LEDs into contiguous runs, so the adjustments are looked up once per run
+        let mut runs: Vec<AdjustmentRun> = Vec::new();
+        for (i, key) in led_mappings.into_iter().enumerate() {
+            match runs.last_mut() {
+                Some(run) if run.adjustment == key => run.leds.end = i + 1,
+                _ => runs.push(AdjustmentRun {
+                    leds: i..i + 1,
+                    adjustment: key,
+                }),
+            }
+        }
+
         let rgb_whitepoint = utils::kelvin_to_rgb16(self.rgb_temperature);
         debug!(
             ?rgb_whitepoint,
             temperature = self.rgb_temperature,
+            runs = runs.len(),
             "computed RGB whitepoint"
         );
 
+        // Only LEDs with a gain different from 1 need to be corrected
+        let mut led_gains = vec![[GAIN_ONE; 3]; self.led_count as _];
+        for led_gain in &self.led_gains {
+            for index in led_gain.leds.indices(led_gains.len()) {
+                led_gains[index] = led_gain.gain;
+            }
+        }
+
+        if led_gains.iter().all(|gain| *gain == [GAIN_ONE; 3]) {
+            led_gains.clear();
+        }
+
         ChannelAdjustments {
             adjustments,
-            led_mappings,
+            runs,
             rgb_whitepoint,
-            srgb_whitepoint: utils::srgb_white(),
+            led_gains,
         }
     }
 }
 
+/// LEDs sharing the same channel adjustment
+#[derive(Debug, Clone)]
+struct AdjustmentRun {
+    leds: Range<usize>,
+    adjustment: Option<DefaultKey>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChannelAdjustments {
-    adjustments: SlotMap<DefaultKey, ColorAdjustmentData>,
-    led_mappings: Vec<Option<DefaultKey>>,
+    adjustments: SlotMap<DefaultKey, BatchAdjustment>,
+    runs: Vec<AdjustmentRun>,
     rgb_whitepoint: Color16,
-    srgb_whitepoint: Color16,
+    /// Gain of each LED, empty if none is corrected
+    led_gains: Vec<[u32; 3]>,
 }
 
 impl ChannelAdjustments {
     pub fn apply(&self, led_data: &mut [Color16]) {
-        for (i, led) in led_data.iter_mut().enumerate() {
-            if let Some(adjustment) = self
-                .led_mappings
-                .get(i)
-                .and_then(|key| *key)
-                .and_then(|key| self.adjustments.get(key))
-            {
-                // TODO: Actual 16-bit color transforms?
-                *led = color_to16(adjustment.apply(color_to8(*led)));
+        for run in &self.runs {
+            let leds = run.leds.start.min(led_data.len())..run.leds.end.min(led_data.len());
+
+            if let Some(adjustment) = run.adjustment.and_then(|key| self.adjustments.get(key)) {
+                adjustment.apply(&mut led_data[leds]);
             }
+        }
 
-            *led = utils::whitebalance(*led, self.srgb_whitepoint, self.rgb_whitepoint);
+        let srgb_whitepoint = utils::srgb_white();
+        for led in led_data.iter_mut() {
+            *led = utils::whitebalance(*led, srgb_whitepoint, self.rgb_whitepoint);
+        }
+
+        for (led, gain) in led_data.iter_mut().zip(&self.led_gains) {
+            let (r, g, b) = led.into_components();
+            let scale =
+                |c: u16, gain: u32| ((c as u32 * gain) / GAIN_ONE).min(u16::MAX as u32) as u16;
+
+            *led = Color16::new(scale(r, gain[0]), scale(g, gain[1]), scale(b, gain[2]));
         }
     }
 }
@@ -396,6 +614,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
     lazy_static::lazy_static! {
@@ -420,6 +640,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_out_of_bounds_range_is_clamped() {
+        let config = crate::models::ColorAdjustment {
+            channel_adjustment: vec![crate::models::ChannelAdjustment {
+                leds: "2-10".to_owned(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let adjustments = ChannelAdjustmentsBuilder::new(&config).led_count(4).build();
+        assert_eq!(
+            adjustments
+                .runs
+                .iter()
+                .flat_map(|run| std::iter::repeat_n(run.adjustment.is_some(), run.leds.len()))
+                .collect::<Vec<_>>(),
+            vec![false, false, true, true]
+        );
+    }
+
+    #[test]
+    fn test_led_gain_ramp() {
+        let led_gain = crate::models::LedGain::ramp(4, 0, [50.0, 100.0, 100.0], [100.0; 3]);
+        assert_eq!(led_gain.len(), 5);
+        assert_eq!(led_gain[0].leds, "0");
+        assert_eq!((led_gain[0].red, led_gain[0].green), (0.5, 1.0));
+        assert_eq!((led_gain[4].red, led_gain[4].green), (1.0, 1.0));
+
+        let config = crate::models::ColorAdjustment {
+            rgb_temperature: 6600,
+            channel_adjustment: vec![crate::models::ChannelAdjustment {
+                gamma_red: 1.0,
+                gamma_green: 1.0,
+                gamma_blue: 1.0,
+                ..Default::default()
+            }],
+            led_gain,
+            ..Default::default()
+        };
+
+        let adjustments = ChannelAdjustmentsBuilder::new(&config).led_count(6).build();
+        let mut leds = vec![Color16::new(60000, 60000, 60000); 6];
+        let mut expected = leds.clone();
+        ChannelAdjustmentsBuilder::new(&crate::models::ColorAdjustment {
+            led_gain: Vec::new(),
+            ..config
+        })
+        .led_count(6)
+        .build()
+        .apply(&mut expected);
+        adjustments.apply(&mut leds);
+
+        assert_eq!(leds[0].red, expected[0].red / 2);
+        assert_eq!(leds[0].green, expected[0].green);
+        assert_eq!(leds[4], expected[4]);
+        // LEDs outside of the ramp are not corrected
+        assert_eq!(leds[5], expected[5]);
+    }
+
     #[test]
     fn test_color_adjustment_data() {
         let channel_adjustment: ColorAdjustmentData =
@@ -429,4 +709,36 @@ mod tests {
             assert_eq!(color, channel_adjustment.apply(color));
         }
     }
+
+    proptest! {
+        #[test]
+        fn test_batch_adjustment(
+            leds in prop::collection::vec(any::<(u16, u16, u16)>(), 0..40),
+            red in any::<(u8, u8, u8)>(),
+            white in any::<(u8, u8, u8)>(),
+            brightness in 0u32..=100,
+            brightness_compensation in 0u32..=100,
+            gamma in (0.1f32..5., 0.1f32..5., 0.1f32..5.),
+        ) {
+            let data = ColorAdjustmentData::from(&crate::models::ChannelAdjustment {
+                red: Color::from_components(red),
+                white: Color::from_components(white),
+                brightness,
+                brightness_compensation,
+                gamma_red: gamma.0,
+                gamma_green: gamma.1,
+                gamma_blue: gamma.2,
+                ..Default::default()
+            });
+
+            let mut leds: Vec<_> = leds.into_iter().map(Color16::from_components).collect();
+            let expected: Vec<_> = leds
+                .iter()
+                .map(|led| color_to16(data.apply(color_to8(*led))))
+                .collect();
+
+            BatchAdjustment::from(data).apply(&mut leds);
+            prop_assert_eq!(leds, expected);
+        }
+    }
 }
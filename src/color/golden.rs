@@ -0,0 +1,137 @@
+//! Golden tests for the color pipeline
+//!
+//! The expected values are computed with the algorithm of hyperion.ng's `RgbTransform`,
+//! `RgbChannelAdjustment` and `MultiColorAdjustment::applyAdjustment`, using double precision
+//! math like the C++ code, so calibration results carry over when switching daemons.
+//!
+//! Intentional divergences from hyperion.ng:
+//!
+//! - Backlight colors are computed with saturating arithmetic. hyperion.ng converts an
+//!   out-of-range `double` to `uint8_t` when the scaled color exceeds 255, which is undefined
+//!   behavior in C++.
+//! - The RGB color temperature whitebalance (applied by [`ChannelAdjustments`] after the
+//!   adjustments) is specific to hyperion.rs and is not covered by these vectors.
+
+use super::*;
+use crate::models::ChannelAdjustment;
+
+type Vector = ([u8; 3], [u8; 3]);
+
+/// Default channel adjustment
+const DEFAULT: &[Vector] = &[
+    ([0, 0, 0], [0, 0, 0]),
+    ([255, 255, 255], [255, 255, 255]),
+    ([255, 0, 0], [255, 0, 0]),
+    ([0, 255, 0], [0, 255, 0]),
+    ([0, 0, 255], [0, 0, 255]),
+    ([0, 255, 255], [0, 255, 255]),
+    ([255, 0, 255], [255, 0, 255]),
+    ([255, 255, 0], [255, 255, 0]),
+    ([1, 1, 1], [0, 0, 0]),
+    ([64, 64, 64], [30, 30, 30]),
+    ([128, 128, 128], [88, 88, 88]),
+    ([200, 200, 200], [175, 175, 175]),
+    ([254, 254, 254], [251, 251, 251]),
+    ([12, 34, 56], [1, 11, 25]),
+    ([250, 128, 3], [246, 89, 0]),
+    ([77, 200, 180], [40, 175, 149]),
+    ([140, 20, 210], [101, 3, 189]),
+];
+
+/// Per-channel gamma, brightness 75, brightness compensation 50
+const CALIBRATED: &[Vector] = &[
+    ([0, 0, 0], [0, 0, 0]),
+    ([255, 255, 255], [64, 64, 64]),
+    ([255, 0, 0], [128, 0, 0]),
+    ([0, 255, 0], [0, 128, 0]),
+    ([0, 0, 255], [0, 0, 128]),
+    ([0, 255, 255], [0, 85, 85]),
+    ([255, 0, 255], [85, 0, 85]),
+    ([255, 255, 0], [85, 85, 0]),
+    ([1, 1, 1], [0, 0, 0]),
+    ([64, 64, 64], [5, 6, 9]),
+    ([128, 128, 128], [20, 24, 28]),
+    ([200, 200, 200], [46, 48, 52]),
+    ([254, 254, 254], [62, 62, 62]),
+    ([12, 34, 56], [0, 1, 7]),
+    ([250, 128, 3], [111, 21, 0]),
+    ([77, 200, 180], [4, 60, 51]),
+    ([140, 20, 210], [25, 0, 80]),
+];
+
+/// Linear gamma, brightness 30, brightness compensation 100, calibrated primaries
+const DIMMED: &[Vector] = &[
+    ([0, 0, 0], [0, 0, 0]),
+    ([255, 255, 255], [18, 15, 12]),
+    ([255, 0, 0], [54, 6, 0]),
+    ([0, 255, 0], [2, 54, 4]),
+    ([0, 0, 255], [0, 0, 42]),
+    ([0, 255, 255], [0, 25, 27]),
+    ([255, 0, 255], [26, 0, 24]),
+    ([255, 255, 0], [27, 21, 0]),
+    ([1, 1, 1], [0, 0, 0]),
+    ([64, 64, 64], [9, 8, 7]),
+    ([128, 128, 128], [14, 12, 11]),
+    ([200, 200, 200], [15, 13, 13]),
+    ([254, 254, 254], [17, 15, 12]),
+    ([12, 34, 56], [1, 5, 7]),
+    ([250, 128, 3], [39, 13, 0]),
+    ([77, 200, 180], [4, 20, 17]),
+    ([140, 20, 210], [15, 0, 24]),
+];
+
+fn check(settings: &ChannelAdjustment, vectors: &[Vector]) {
+    let adjustment = ColorAdjustmentData::from(settings);
+
+    for (input, expected) in vectors {
+        let output = adjustment.apply(Color::from(*input));
+        assert_eq!(
+            <[u8; 3]>::from(output.into_components()),
+            *expected,
+            "input: {:?}",
+            input
+        );
+    }
+}
+
+#[test]
+fn golden_default() {
+    check(&ChannelAdjustment::default(), DEFAULT);
+}
+
+#[test]
+fn golden_calibrated() {
+    check(
+        &ChannelAdjustment {
+            gamma_red: 2.2,
+            gamma_green: 2.0,
+            gamma_blue: 1.8,
+            brightness: 75,
+            brightness_compensation: 50,
+            ..Default::default()
+        },
+        CALIBRATED,
+    );
+}
+
+#[test]
+fn golden_dimmed() {
+    check(
+        &ChannelAdjustment {
+            gamma_red: 1.0,
+            gamma_green: 1.0,
+            gamma_blue: 1.0,
+            brightness: 30,
+            brightness_compensation: 100,
+            red: Color::new(255, 30, 0),
+            green: Color::new(10, 255, 20),
+            blue: Color::new(0, 0, 200),
+            cyan: Color::new(0, 240, 255),
+            magenta: Color::new(250, 0, 230),
+            yellow: Color::new(255, 200, 0),
+            white: Color::new(255, 220, 180),
+            ..Default::default()
+        },
+        DIMMED,
+    );
+}
@@ -2,9 +2,14 @@
 
 use parse_display::Display;
 use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
 
-#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Name of a component, as used by the JSON API (e.g. `V4L`) through [Into<&'static str>]
+#[derive(
+    Display, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, IntoStaticStr,
+)]
 #[serde(rename_all = "UPPERCASE")]
+#[strum(serialize_all = "UPPERCASE")]
 pub enum ComponentName {
     #[display("Hyperion")]
     All,
@@ -17,9 +22,17 @@ pub enum ComponentName {
     #[display("Boblight server")]
     BoblightServer,
     #[display("Framegrabber")]
-    Grabber,
+    #[serde(rename = "GRABBER")]
+    #[strum(serialize = "GRABBER")]
+    SystemGrabber,
     #[display("V4L capture device")]
-    V4L,
+    #[serde(rename = "V4L")]
+    #[strum(serialize = "V4L")]
+    V4LGrabber,
+    #[display("Audio capture device")]
+    #[serde(rename = "AUDIO")]
+    #[strum(serialize = "AUDIO")]
+    AudioGrabber,
     #[display("Solid color")]
     Color,
     #[display("Effect")]
@@ -32,4 +45,45 @@ pub enum ComponentName {
     FlatbufServer,
     #[display("Proto Server")]
     ProtoServer,
+    #[display("NDI receiver")]
+    NdiReceiver,
+    #[display("Media artwork")]
+    MediaArtwork,
+    #[display("Data presets")]
+    DataPresets,
+    #[display("Instance mirror")]
+    InstanceMirror,
+    #[display("UDP listener")]
+    UdpListener,
+}
+
+impl ComponentName {
+    /// Components of an instance which can be toggled at runtime, as listed in `serverinfo`
+    pub const INSTANCE_COMPONENTS: [Self; 14] = [
+        Self::All,
+        Self::Smoothing,
+        Self::BlackBorder,
+        Self::Forwarder,
+        Self::BoblightServer,
+        Self::SystemGrabber,
+        Self::V4LGrabber,
+        Self::AudioGrabber,
+        Self::Color,
+        Self::Effect,
+        Self::Image,
+        Self::LedDevice,
+        Self::FlatbufServer,
+        Self::ProtoServer,
+    ];
+
+    /// Returns true if this component captures images or sound from the system
+    pub fn is_grabber(&self) -> bool {
+        matches!(
+            self,
+            ComponentName::SystemGrabber
+                | ComponentName::V4LGrabber
+                | ComponentName::AudioGrabber
+                | ComponentName::NdiReceiver
+        )
+    }
 }
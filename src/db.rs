@@ -1,26 +1,196 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use sqlx::prelude::*;
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqliteConnection;
+use thiserror::Error;
+
+use crate::models::{
+    backend::{ConfigBackend, DbBackend},
+    Config, ConfigError,
+};
 
 pub mod models;
 
 pub type DbError = sqlx::Error;
 
+#[derive(Debug, Error)]
+pub enum DbRestoreError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("error opening snapshot: {0}")]
+    Db(#[from] DbError),
+    #[error("snapshot failed integrity check: {0}")]
+    Integrity(String),
+    #[error("invalid configuration in snapshot: {0}")]
+    Config(#[from] ConfigError),
+}
+
+// Primary SQLite result codes that indicate the database can't be written to
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_READONLY: i32 = 8;
+const SQLITE_CANTOPEN: i32 = 14;
+
 pub struct Db {
     connection: SqliteConnection,
+    path: PathBuf,
+    read_only: bool,
 }
 
 impl Db {
+    /// Open the database at the given path
+    ///
+    /// If the database is locked or can't be written to, this falls back to opening it in
+    /// read-only mode. Use [`Db::is_read_only`] to find out which mode was selected.
     pub async fn open(path: &Path) -> Result<Self, DbError> {
         debug!(path = %path.display(), "loading database");
 
+        match Self::open_read_write(path).await {
+            Ok(db) => Ok(db),
+            Err(error) if is_write_error(&error) => {
+                warn!(path = %path.display(), error = %error, "database is not writable, falling back to read-only mode");
+                Self::open_read_only(path).await
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Open the database at the given path in read-only mode
+    pub async fn open_read_only(path: &Path) -> Result<Self, DbError> {
+        debug!(path = %path.display(), "loading database (read-only)");
+
+        Ok(Self {
+            connection: SqliteConnection::connect_with(
+                &SqliteConnectOptions::new().filename(path).read_only(true),
+            )
+            .await?,
+            path: path.to_owned(),
+            read_only: true,
+        })
+    }
+
+    async fn open_read_write(path: &Path) -> Result<Self, DbError> {
+        let mut connection =
+            SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(path)).await?;
+
+        // Opening succeeds on read-only files, so check we can actually take a write lock
+        connection.execute("BEGIN IMMEDIATE").await?;
+        connection.execute("ROLLBACK").await?;
+
         Ok(Self {
-            connection: SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(path))
-                .await?,
+            connection,
+            path: path.to_owned(),
+            read_only: false,
         })
     }
+
+    /// Returns true if this database was opened in read-only mode
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Create a consistent snapshot of the database
+    ///
+    /// # Returns
+    ///
+    /// The contents of the snapshot, as a standalone SQLite database file
+    pub async fn backup(&mut self) -> Result<Vec<u8>, DbError> {
+        // The snapshot holds the auth tokens, so it is written next to the database in a file
+        // only we can read, rather than in the shared temporary directory
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".backup-{}", uuid::Uuid::new_v4()));
+        let path = PathBuf::from(path);
+
+        create_private_file(&path).await?;
+
+        // VACUUM INTO accepts an existing target as long as it is empty
+        let result = sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy().as_ref())
+            .execute(&mut self.connection)
+            .await;
+
+        let data = match result {
+            Ok(_) => tokio::fs::read(&path).await.map_err(DbError::from),
+            Err(error) => Err(error),
+        };
+        tokio::fs::remove_file(&path).await.ok();
+
+        data
+    }
+
+    /// Replace the database at `path` with the given snapshot
+    ///
+    /// The snapshot is written next to the target, checked for integrity and fully loaded before
+    /// atomically replacing the existing database.
+    ///
+    /// # Returns
+    ///
+    /// The configuration contained in the snapshot
+    pub async fn restore(path: &Path, snapshot: &[u8]) -> Result<Config, DbRestoreError> {
+        let mut staging = path.as_os_str().to_owned();
+        staging.push(".restore");
+        let staging = std::path::PathBuf::from(staging);
+
+        // The snapshot holds the auth tokens, so the restored database must only be readable by
+        // us. Remove any staging file left over by an interrupted restore first.
+        tokio::fs::remove_file(&staging).await.ok();
+        create_private_file(&staging).await?;
+        tokio::fs::write(&staging, snapshot).await?;
+
+        match Self::check_snapshot(&staging).await {
+            Ok(config) => {
+                tokio::fs::rename(&staging, path).await?;
+                info!(path = %path.display(), "restored database snapshot");
+                Ok(config)
+            }
+            Err(error) => {
+                tokio::fs::remove_file(&staging).await.ok();
+                Err(error)
+            }
+        }
+    }
+
+    async fn check_snapshot(path: &Path) -> Result<Config, DbRestoreError> {
+        let mut db = Self::open_read_only(path).await?;
+
+        let result: String = sqlx::query_scalar("PRAGMA quick_check")
+            .fetch_one(&mut db.connection)
+            .await?;
+        if result != "ok" {
+            return Err(DbRestoreError::Integrity(result));
+        }
+
+        let mut backend = DbBackend::new(db);
+        Ok(backend.load().await?)
+    }
+}
+
+/// Create a new empty file, only readable by the current user
+async fn create_private_file(path: &Path) -> std::io::Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    options.open(path).await.map(|_| ())
+}
+
+fn is_write_error(error: &DbError) -> bool {
+    match error {
+        DbError::Database(error) => error
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .map(|code| {
+                // Extended result codes carry the primary code in their lower byte
+                matches!(
+                    code & 0xff,
+                    SQLITE_BUSY | SQLITE_LOCKED | SQLITE_READONLY | SQLITE_CANTOPEN
+                )
+            })
+            .unwrap_or(false),
+        _ => false,
+    }
 }
 
 impl std::ops::Deref for Db {
@@ -36,3 +206,77 @@ impl std::ops::DerefMut for Db {
         &mut self.connection
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InstanceConfig;
+
+    async fn create_db(path: &Path) {
+        let mut connection = SqliteConnection::connect_with(
+            &SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+
+        connection
+            .execute(include_str!("../create-db.sql"))
+            .await
+            .unwrap();
+        connection.close().await.unwrap();
+
+        DbBackend::new(Db::open(path).await.unwrap())
+            .create_instance(&InstanceConfig::new_dummy(1))
+            .await
+            .unwrap();
+    }
+
+    /// Temporary files left over by backup or restore
+    fn leftover_files(dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.contains(".backup-") || name.ends_with(".restore")
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_backup_restore() {
+        let dir = std::env::temp_dir().join(format!("hyperion-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("hyperion.db");
+        let target = dir.join("restored.db");
+        create_db(&path).await;
+
+        let snapshot = Db::open_read_only(&path)
+            .await
+            .unwrap()
+            .backup()
+            .await
+            .unwrap();
+        assert_eq!(leftover_files(&dir), Vec::<PathBuf>::new());
+
+        let config = Db::restore(&target, &snapshot).await.unwrap();
+        assert_eq!(config.instances.keys().copied().collect::<Vec<_>>(), [1]);
+
+        // Invalid snapshots don't replace the existing database
+        assert!(Db::restore(&target, b"not a database").await.is_err());
+        assert_eq!(leftover_files(&dir), Vec::<PathBuf>::new());
+        assert_eq!(std::fs::read(&target).unwrap(), snapshot);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
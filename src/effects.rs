@@ -1,4 +1,9 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use thiserror::Error;
 use tokio::{
@@ -6,11 +11,24 @@ use tokio::{
     task::JoinHandle,
 };
 
-use crate::{global::InputSourceError, image::RawImage, models::Color};
+use crate::{
+    global::{InputSourceError, Paths},
+    image::{ImageQueue, QueuedFrame, RawImage},
+    models::{self, Color},
+};
 
 mod definition;
 pub use definition::*;
 
+mod checkpoint;
+pub use checkpoint::*;
+
+mod custom;
+pub use custom::*;
+
+mod schema;
+pub use schema::*;
+
 mod providers;
 pub use providers::Providers;
 
@@ -22,11 +40,23 @@ use self::providers::{Provider, ProviderError};
 pub struct EffectRunHandle {
     ctx: Sender<ControlMessage>,
     join_handle: Option<JoinHandle<()>>,
+    /// true if the provider supports updating the arguments of running effects
+    args_update: bool,
 
     pub priority: i32,
 }
 
 impl EffectRunHandle {
+    /// Send new arguments to the running effect
+    ///
+    /// # Returns
+    ///
+    /// `false` if the effect can't take them while running, either because its provider doesn't
+    /// support it or because it isn't processing control messages. It should then be restarted.
+    pub fn update_args(&mut self, args: serde_json::Value) -> bool {
+        self.args_update && self.ctx.try_send(ControlMessage::UpdateArgs(args)).is_ok()
+    }
+
     pub async fn abort(&mut self) {
         self.ctx
             .send(ControlMessage::Abort)
@@ -69,15 +99,38 @@ pub struct EffectMessage<X> {
 
 #[derive(Debug)]
 pub enum EffectMessageKind {
-    SetColor { color: Color },
-    SetImage { image: Arc<RawImage> },
-    SetLedColors { colors: Arc<Vec<Color>> },
-    Completed { result: Result<(), ProviderError> },
+    SetColor {
+        color: Color,
+    },
+    /// Image mapped onto the LED layout like captured frames, including black border detection
+    SetImage {
+        image: Arc<RawImage>,
+    },
+    SetLedColors {
+        colors: Arc<Vec<Color>>,
+    },
+    Completed {
+        result: Result<(), ProviderError>,
+    },
+}
+
+impl QueuedFrame for EffectMessageKind {
+    fn frame_bytes(&self) -> Option<usize> {
+        match self {
+            EffectMessageKind::SetImage { image } => Some(image.data().len()),
+            EffectMessageKind::SetLedColors { colors } => Some(colors.len() * 3),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct EffectRegistry {
     effects: Vec<EffectHandle>,
+    /// Directories searched for effects, including missing ones
+    dirs: Vec<PathBuf>,
+    /// Argument schemas found in these directories, see [script_key]
+    schemas: HashMap<String, Arc<EffectSchema>>,
 }
 
 impl EffectRegistry {
@@ -93,6 +146,17 @@ impl EffectRegistry {
         self.effects.iter().find(|e| e.definition.name == name)
     }
 
+    /// Remove an effect from this registry
+    ///
+    /// # Returns
+    ///
+    /// `true` if the effect was registered.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.effects.len();
+        self.effects.retain(|e| e.definition.name != name);
+        self.effects.len() != len
+    }
+
     pub fn is_empty(&self) -> bool {
         self.effects.is_empty()
     }
@@ -101,6 +165,36 @@ impl EffectRegistry {
         self.effects.len()
     }
 
+    /// Directories searched for the effects of this registry
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Discover the effects in `path` and add them to this registry
+    ///
+    /// Missing directories are skipped, but still recorded as searched.
+    pub async fn add_dir(
+        &mut self,
+        providers: &Providers,
+        path: &Path,
+    ) -> Result<(), EffectDefinitionError> {
+        self.dirs.push(path.to_owned());
+
+        // Custom effects are only saved there once the first one is created
+        if !path.exists() {
+            return Ok(());
+        }
+
+        self.schemas
+            .extend(EffectSchema::read_dir(&path.join(SCHEMA_DIR)).await);
+
+        let mut discovered = EffectDefinition::read_dir(path).await?;
+        discovered.sort_by(|a, b| a.file.cmp(&b.file));
+        self.add_definitions(providers, discovered);
+
+        Ok(())
+    }
+
     /// Add definitions to this registry
     ///
     /// # Parameters
@@ -118,7 +212,14 @@ impl EffectRegistry {
     ) -> Vec<EffectDefinition> {
         let mut remaining = vec![];
 
-        for definition in definitions {
+        for mut definition in definitions {
+            // Custom effects share the schema of the effect whose script they use
+            if definition.schema.is_none() {
+                definition.schema = script_key(&definition.script)
+                    .and_then(|key| self.schemas.get(&key))
+                    .cloned();
+            }
+
             if let Some(provider) = providers.get(&definition.script) {
                 debug!(provider=?provider, effect=%definition.name, "assigned provider to effect");
 
@@ -137,6 +238,88 @@ impl EffectRegistry {
     }
 }
 
+/// Effects available to an instance
+///
+/// These are the global effects, along with the ones found in the effect paths of the instance,
+/// which take precedence over global effects of the same name. Disabled effects are hidden.
+#[derive(Default, Debug)]
+pub struct InstanceEffects {
+    registry: EffectRegistry,
+    disabled: HashSet<String>,
+}
+
+impl InstanceEffects {
+    /// Discover the effects of an instance
+    ///
+    /// # Parameters
+    ///
+    /// * `config`: effect settings of the instance
+    /// * `paths`: paths used to resolve the effect paths
+    /// * `global_dirs`: directories of the global effects, which are not searched again
+    pub async fn new(config: &models::Effects, paths: &Paths, global_dirs: &[PathBuf]) -> Self {
+        let providers = Providers::new();
+        let mut registry = EffectRegistry::new();
+
+        for path in &config.paths {
+            let path = paths.resolve_path(path);
+            if global_dirs.contains(&path) || registry.dirs().contains(&path) {
+                continue;
+            }
+
+            if let Err(error) = registry.add_dir(&providers, &path).await {
+                warn!(path = %path.display(), error = %error, "cannot read instance effects");
+            }
+        }
+
+        Self {
+            registry,
+            disabled: config.disable.iter().cloned().collect(),
+        }
+    }
+
+    pub fn find_effect<'a>(
+        &'a self,
+        global: &'a EffectRegistry,
+        name: &str,
+    ) -> Option<&'a EffectHandle> {
+        if self.disabled.contains(name) {
+            return None;
+        }
+
+        self.registry
+            .find_effect(name)
+            .or_else(|| global.find_effect(name))
+    }
+
+    pub fn iter<'a>(
+        &'a self,
+        global: &'a EffectRegistry,
+    ) -> impl Iterator<Item = &'a EffectDefinition> {
+        self.registry
+            .iter()
+            .chain(
+                global
+                    .iter()
+                    .filter(move |effect| self.registry.find_effect(&effect.name).is_none()),
+            )
+            .filter(move |effect| !self.disabled.contains(&effect.name))
+    }
+}
+
+/// Parameters of the instance an effect runs for
+#[derive(Debug)]
+pub struct EffectRunConfig {
+    pub led_count: usize,
+    /// Total size of the frames waiting to be sent to the instance, in bytes
+    pub queue_bytes: usize,
+    /// Where the effect state is persisted, if it should be
+    pub checkpoint: Option<Checkpoint>,
+    /// Latch time of the device, effects shouldn't update the LEDs more often
+    pub latch_time: Duration,
+    /// Size of the images painted by effects, see [crate::models::Leds::grid_size]
+    pub image_size: (u32, u32),
+}
+
 #[derive(Debug, Clone)]
 pub struct EffectHandle {
     pub definition: EffectDefinition,
@@ -147,7 +330,7 @@ impl EffectHandle {
     pub fn run<X: std::fmt::Debug + Clone + Send + 'static>(
         &self,
         args: serde_json::Value,
-        led_count: usize,
+        config: EffectRunConfig,
         duration: Option<chrono::Duration>,
         priority: i32,
         tx: Sender<EffectMessage<X>>,
@@ -155,9 +338,11 @@ impl EffectHandle {
     ) -> Result<EffectRunHandle, RunEffectError> {
         // Resolve path
         let full_path = self.definition.script_path()?;
+        let name = self.definition.name.clone();
 
         // Clone provider arc
         let provider = self.provider.clone();
+        let args_update = provider.supports_args_update();
 
         // Create control channel
         let (ctx, crx) = channel(1);
@@ -166,10 +351,11 @@ impl EffectHandle {
         let (etx, mut erx) = channel(1);
 
         // Create instance methods
+        let queue_bytes = config.queue_bytes;
         let methods = Arc::new(InstanceMethods::new(
             etx,
             crx,
-            led_count,
+            config,
             duration.and_then(|d| d.to_std().ok()),
         ));
 
@@ -178,18 +364,36 @@ impl EffectHandle {
             let methods = methods.clone();
 
             // Create the blocking task
-            let mut run_effect =
-                tokio::task::spawn_blocking(move || provider.run(&full_path, args, methods));
+            let mut run_effect = tokio::task::spawn_blocking(move || {
+                let result = provider.run(&full_path, args, methods.clone());
+                methods.finish_checkpoint(result.is_ok());
+                result
+            });
+
+            // Messages waiting for the instance, so the effect isn't slowed down by it
+            let mut queue = ImageQueue::new(queue_bytes);
 
             // Join the blocking task while forwarding the effect messages
             let result = loop {
                 tokio::select! {
                     kind = erx.recv() => {
                         if let Some(kind) = kind {
-                            // Add the extra marker to the message and forward it to the instance
-                            let msg = EffectMessage { kind, extra: extra.clone() };
-
-                            if let Err(err) = tx.send(msg).await {
+                            if queue.push(kind) > 0 && queue.stats().dropped_frames == 1 {
+                                warn!(effect = %name, "effect frames are dropped, the instance can't keep up");
+                            }
+                        }
+                    }
+                    permit = tx.reserve(), if !queue.is_empty() => {
+                        match permit {
+                            Ok(permit) => {
+                                // unwrap: the queue isn't empty
+                                let kind = queue.pop().unwrap();
+
+                                // Add the extra marker to the message and forward it to the
+                                // instance
+                                permit.send(EffectMessage { kind, extra: extra.clone() });
+                            }
+                            Err(err) => {
                                 // This would happen if the effect is running and the instance has
                                 // already shutdown.
                                 error!(err=%err, "failed to forward effect message");
@@ -204,7 +408,18 @@ impl EffectHandle {
                 }
             };
 
-            // Send the completion, ignoring failures in case we're shutting down
+            let stats = queue.stats();
+            if stats.dropped_frames > 0 {
+                info!(
+                    effect = %name,
+                    dropped_frames = %stats.dropped_frames,
+                    dropped_bytes = %stats.dropped_bytes,
+                    "effect frames dropped"
+                );
+            }
+
+            // Messages still queued are outdated now that the effect is done, only send the
+            // completion, ignoring failures in case we're shutting down
             tx.send(EffectMessage {
                 kind: EffectMessageKind::Completed { result },
                 extra,
@@ -216,7 +431,57 @@ impl EffectHandle {
         Ok(EffectRunHandle {
             ctx,
             join_handle: join_handle.into(),
+            args_update,
             priority,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_instance_effects() {
+        let providers = Providers::new();
+        let definition = |name: &str, script: &str| {
+            EffectDefinition::new(
+                name.to_owned(),
+                PathBuf::from(format!("{}.json", script)),
+                format!("{}.png", script),
+                Default::default(),
+                PathBuf::new(),
+            )
+        };
+
+        let mut global = EffectRegistry::new();
+        global.add_definitions(
+            &providers,
+            vec![
+                definition("Rainbow", "global"),
+                definition("Fire", "global"),
+                definition("Police", "global"),
+            ],
+        );
+
+        let mut registry = EffectRegistry::new();
+        registry.add_definitions(&providers, vec![definition("Fire", "instance")]);
+        let effects = InstanceEffects {
+            registry,
+            disabled: vec!["Police".to_owned()].into_iter().collect(),
+        };
+
+        // Instance effects take precedence, disabled ones are hidden
+        let names: Vec<_> = effects.iter(&global).map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Fire", "Rainbow"]);
+        assert_eq!(
+            effects
+                .find_effect(&global, "Fire")
+                .map(|e| e.definition.script.as_str()),
+            Some("instance.png")
+        );
+        assert!(effects.find_effect(&global, "Rainbow").is_some());
+        assert!(effects.find_effect(&global, "Police").is_none());
+    }
+}
@@ -0,0 +1,187 @@
+//! Persisted state of running effects
+//!
+//! Effects which checkpoint their state through [super::RuntimeMethods::save_state] get it back
+//! when they are started again with the same arguments on the same instance, so long-running
+//! effects resume where they were instead of restarting from scratch after the daemon restarts.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Directory holding the checkpoints, relative to the user root
+pub const CHECKPOINT_DIR: &str = "$ROOT/effect-state";
+
+/// Minimum time between two writes of the state of an effect
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointFile {
+    effect: String,
+    args: serde_json::Value,
+    state: serde_json::Value,
+}
+
+/// Checkpoint of an effect running on an instance
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    effect: String,
+    args: serde_json::Value,
+    /// State saved since the last write
+    pending: Option<serde_json::Value>,
+    last_write: Option<Instant>,
+    /// true if the last write failed, so failures are only logged once
+    failed: bool,
+}
+
+impl Checkpoint {
+    pub fn new(dir: &Path, instance: i32, effect: &str, args: serde_json::Value) -> Self {
+        let name: String = effect
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+
+        Self {
+            path: dir.join(format!("{}-{}.json", instance, name)),
+            effect: effect.to_owned(),
+            args,
+            pending: None,
+            last_write: None,
+            failed: false,
+        }
+    }
+
+    /// Load the state saved by a previous run of the effect
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no state, or if it was saved by a run with different arguments.
+    pub fn load(&self) -> Option<serde_json::Value> {
+        let file: CheckpointFile = match std::fs::read(&self.path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(file) => file,
+                Err(error) => {
+                    warn!(path = %self.path.display(), error = %error, "invalid effect checkpoint");
+                    return None;
+                }
+            },
+            Err(_) => return None,
+        };
+
+        if file.effect == self.effect && file.args == self.args {
+            debug!(effect = %self.effect, "restored effect state");
+            Some(file.state)
+        } else {
+            None
+        }
+    }
+
+    /// Update the arguments of the running effect
+    ///
+    /// Later runs only restore the state if they use the same arguments.
+    pub fn set_args(&mut self, args: serde_json::Value) {
+        self.args = args;
+    }
+
+    /// Save the current state of the effect, writing it if the last write is old enough
+    pub fn save(&mut self, state: serde_json::Value) {
+        self.pending = Some(state);
+
+        if self
+            .last_write
+            .map(|last| last.elapsed() >= CHECKPOINT_INTERVAL)
+            .unwrap_or(true)
+        {
+            self.flush();
+        }
+    }
+
+    /// Write the state saved since the last write
+    pub fn flush(&mut self) {
+        let state = match self.pending.take() {
+            Some(state) => state,
+            None => return,
+        };
+
+        self.last_write = Some(Instant::now());
+
+        match self.write(state) {
+            Ok(()) => self.failed = false,
+            Err(error) => {
+                if !self.failed {
+                    warn!(path = %self.path.display(), error = %error, "failed to write effect checkpoint");
+                }
+
+                self.failed = true;
+            }
+        }
+    }
+
+    /// Remove the saved state, so the next run starts from scratch
+    pub fn clear(&mut self) {
+        self.pending = None;
+
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            if error.kind() != io::ErrorKind::NotFound {
+                warn!(path = %self.path.display(), error = %error, "failed to remove effect checkpoint");
+            }
+        }
+    }
+
+    fn write(&self, state: serde_json::Value) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let data = serde_json::to_vec(&CheckpointFile {
+            effect: self.effect.clone(),
+            args: self.args.clone(),
+            state,
+        })?;
+
+        // Replace the previous checkpoint atomically, so it isn't lost if we crash while writing
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("hyperion-{}", uuid::Uuid::new_v4()));
+
+        let mut checkpoint = Checkpoint::new(&dir, 0, "Candle", json!({"speed": 1}));
+        assert_eq!(checkpoint.load(), None);
+
+        // The first state is written right away, the next ones wait for the interval
+        checkpoint.save(json!(1));
+        checkpoint.save(json!(2));
+        assert_eq!(checkpoint.load(), Some(json!(1)));
+        checkpoint.flush();
+        assert_eq!(checkpoint.load(), Some(json!(2)));
+
+        // Runs with other arguments or on other instances start from scratch
+        assert_eq!(
+            Checkpoint::new(&dir, 0, "Candle", json!({"speed": 2})).load(),
+            None
+        );
+        assert_eq!(
+            Checkpoint::new(&dir, 1, "Candle", json!({"speed": 1})).load(),
+            None
+        );
+
+        checkpoint.clear();
+        assert_eq!(checkpoint.load(), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
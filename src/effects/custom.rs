@@ -0,0 +1,228 @@
+//! User-defined effects, created and deleted through the API
+//!
+//! Custom effects are saved to [CUSTOM_EFFECTS_PATH] as effect definitions, next to a copy of the
+//! script they are based on and their image, if any. They are registered right away, and
+//! discovered again on startup.
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use tokio::fs;
+
+use super::{EffectDefinition, EffectDefinitionError, EffectRegistry, Providers};
+use crate::global::Global;
+
+/// Directory custom effects are saved to
+pub const CUSTOM_EFFECTS_PATH: &str = "$ROOT/custom-effects";
+
+#[derive(Debug, Error)]
+pub enum CustomEffectError {
+    #[error("invalid effect name {0:?}")]
+    InvalidName(String),
+    #[error("effect {0:?} is a system effect, it can't be replaced or deleted")]
+    SystemEffect(String),
+    #[error("effect {0:?} not found")]
+    NotFound(String),
+    #[error("effect file {file} is already used by effect {existing:?}")]
+    FileCollision { file: String, existing: String },
+    #[error("no effect uses the script {0:?}")]
+    UnknownScript(String),
+    #[error("unsupported effect image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Definition(#[from] EffectDefinitionError),
+}
+
+/// Effect to save as a custom effect
+#[derive(Debug)]
+pub struct NewEffect {
+    pub name: String,
+    /// Script of an existing effect this one is based on
+    pub script: String,
+    pub args: serde_json::Map<String, serde_json::Value>,
+    /// Image file used by the effect, passed as its `image` argument
+    pub image: Option<Vec<u8>>,
+}
+
+/// Save a custom effect and register it, replacing the custom effect with the same name
+pub async fn create_effect(global: &Global, effect: NewEffect) -> Result<(), CustomEffectError> {
+    let dir = global.paths().await.resolve_path(CUSTOM_EFFECTS_PATH);
+    let stem = file_stem(&effect.name)?;
+
+    let (file, script) = global
+        .read_effects(|effects| -> Result<_, CustomEffectError> {
+            let file = match effects.find_effect(&effect.name) {
+                Some(existing) if existing.definition.base_path() != dir => {
+                    return Err(CustomEffectError::SystemEffect(effect.name.clone()));
+                }
+                // Replace the definition of the existing effect
+                Some(existing) => existing.definition.file.clone(),
+                None => {
+                    let file = PathBuf::from(format!("{}.json", stem));
+
+                    if let Some(existing) =
+                        custom_effects(effects, &dir).find(|definition| definition.file == file)
+                    {
+                        return Err(CustomEffectError::FileCollision {
+                            file: file.display().to_string(),
+                            existing: existing.name.clone(),
+                        });
+                    }
+
+                    file
+                }
+            };
+
+            let script = effects
+                .iter()
+                .find(|definition| definition.script == effect.script)
+                .ok_or_else(|| CustomEffectError::UnknownScript(effect.script.clone()))?
+                .script_path()?;
+
+            Ok((file, script))
+        })
+        .await?;
+
+    let mut args = effect.args;
+    fs::create_dir_all(&dir).await?;
+
+    if let Some(image) = effect.image {
+        let extension = image::guess_format(&image)?.extensions_str()[0];
+        let image_file = format!("{}.{}", stem, extension);
+
+        fs::write(dir.join(&image_file), image).await?;
+        args.insert("image".to_owned(), image_file.into());
+    }
+
+    let definition = EffectDefinition::new(effect.name, file, effect.script, args.into(), dir);
+
+    // Copy the script, unless another custom effect already uses it
+    let target = definition.script_path()?;
+    if target != script {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        if !fs::try_exists(&target).await? {
+            fs::copy(&script, &target).await?;
+        }
+    }
+
+    definition.write().await?;
+    info!(effect = %definition.name, path = %definition.full_path().display(), "saved custom effect");
+
+    let providers = Providers::new();
+    global
+        .write_effects(|effects| {
+            effects.remove(&definition.name);
+            effects.add_definitions(&providers, vec![definition]);
+        })
+        .await;
+
+    Ok(())
+}
+
+/// Delete a custom effect, and the files only it uses
+pub async fn delete_effect(global: &Global, name: &str) -> Result<(), CustomEffectError> {
+    let dir = global.paths().await.resolve_path(CUSTOM_EFFECTS_PATH);
+
+    let (definition, unused) = global
+        .read_effects(|effects| -> Result<_, CustomEffectError> {
+            let definition = match effects.find_effect(name) {
+                Some(handle) if handle.definition.base_path() != dir => {
+                    return Err(CustomEffectError::SystemEffect(name.to_owned()));
+                }
+                Some(handle) => handle.definition.clone(),
+                None => return Err(CustomEffectError::NotFound(name.to_owned())),
+            };
+
+            let image = definition
+                .args
+                .get("image")
+                .and_then(|image| image.as_str());
+            let others: Vec<_> = custom_effects(effects, &dir)
+                .filter(|other| other.name != name)
+                .collect();
+
+            let mut unused = Vec::new();
+            if others.iter().all(|other| other.script != definition.script) {
+                unused.push(definition.script_path()?);
+            }
+
+            if let Some(image) = image {
+                if others
+                    .iter()
+                    .all(|other| other.args.get("image").and_then(|i| i.as_str()) != Some(image))
+                {
+                    unused.push(dir.join(Path::new(image).file_name().unwrap_or_default()));
+                }
+            }
+
+            Ok((definition, unused))
+        })
+        .await?;
+
+    match fs::remove_file(definition.full_path()).await {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+
+    for path in unused {
+        // ok: the script or image may have been removed by the user
+        fs::remove_file(&path).await.ok();
+    }
+
+    info!(effect = %name, "deleted custom effect");
+    global.write_effects(|effects| effects.remove(name)).await;
+
+    Ok(())
+}
+
+/// Custom effects in a registry
+fn custom_effects<'a>(
+    effects: &'a EffectRegistry,
+    dir: &'a Path,
+) -> impl Iterator<Item = &'a EffectDefinition> {
+    effects
+        .iter()
+        .filter(move |definition| definition.base_path() == dir)
+}
+
+/// Name of the files of an effect, from its user-friendly name
+fn file_stem(name: &str) -> Result<String, CustomEffectError> {
+    let mut stem = String::with_capacity(name.len());
+
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '-' {
+            stem.push(c.to_ascii_lowercase());
+        } else if !stem.ends_with('_') {
+            stem.push('_');
+        }
+    }
+
+    let stem = stem.trim_matches('_');
+    if stem.is_empty() {
+        Err(CustomEffectError::InvalidName(name.to_owned()))
+    } else {
+        Ok(stem.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("Rainbow swirl").unwrap(), "rainbow_swirl");
+        assert_eq!(
+            file_stem(" Knight  Rider (fast)! ").unwrap(),
+            "knight_rider_fast"
+        );
+        assert_eq!(file_stem("x-mas").unwrap(), "x-mas");
+        assert!(file_stem("../").is_err());
+        assert!(file_stem("").is_err());
+    }
+}
@@ -6,6 +6,8 @@ use thiserror::Error;
 use tokio::fs;
 use tracing::error;
 
+use super::EffectSchema;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct EffectDefinition {
@@ -22,6 +24,9 @@ pub struct EffectDefinition {
     /// Path this definition is located in
     #[serde(skip)]
     base_path: Arc<PathBuf>,
+    /// Schema of the arguments, if the script comes with one
+    #[serde(skip)]
+    pub schema: Option<Arc<EffectSchema>>,
 }
 
 #[derive(Debug, Error)]
@@ -38,6 +43,40 @@ pub enum EffectDefinitionError {
 }
 
 impl EffectDefinition {
+    /// Create the definition of an effect, to be saved to `base_path` with [Self::write]
+    pub fn new(
+        name: String,
+        file: PathBuf,
+        script: String,
+        args: serde_json::Value,
+        base_path: PathBuf,
+    ) -> Self {
+        Self {
+            name,
+            file,
+            script,
+            args,
+            base_path: Arc::new(base_path),
+            schema: None,
+        }
+    }
+
+    /// Directory containing this definition, its script is resolved from there
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Path to the definition file
+    pub fn full_path(&self) -> PathBuf {
+        self.base_path.join(&self.file)
+    }
+
+    /// Save this definition to its file
+    pub async fn write(&self) -> Result<(), EffectDefinitionError> {
+        fs::write(self.full_path(), serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+
     pub async fn read_dir(path: impl AsRef<Path>) -> Result<Vec<Self>, EffectDefinitionError> {
         let base_path = Arc::new(path.as_ref().to_owned());
         let mut definitions = Vec::new();
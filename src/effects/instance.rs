@@ -1,4 +1,7 @@
-use std::time::{Duration, Instant};
+use std::{
+    sync::Mutex as SyncMutex,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use thiserror::Error;
@@ -12,40 +15,69 @@ use crate::{
     models::Color,
 };
 
-use super::EffectMessageKind;
+use super::{Checkpoint, EffectMessageKind, EffectRunConfig};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ControlMessage {
     Abort,
+    /// New arguments for effects which can update them while running
+    UpdateArgs(serde_json::Value),
 }
 
 struct InstanceMethodsData {
     crx: Receiver<ControlMessage>,
     aborted: bool,
+    /// Arguments received since the effect last checked for them
+    args: Option<serde_json::Value>,
 }
 
 pub struct InstanceMethods {
     tx: Sender<EffectMessageKind>,
     led_count: usize,
+    latch_time: Duration,
+    image_size: (u32, u32),
     deadline: Option<Instant>,
     data: Mutex<InstanceMethodsData>,
+    /// Checkpoint of the effect state, accessed from the blocking effect thread
+    checkpoint: Option<SyncMutex<Checkpoint>>,
 }
 
 impl InstanceMethods {
     pub fn new(
         tx: Sender<EffectMessageKind>,
         crx: Receiver<ControlMessage>,
-        led_count: usize,
+        config: EffectRunConfig,
         duration: Option<Duration>,
     ) -> Self {
         Self {
             tx,
-            led_count,
+            led_count: config.led_count,
+            latch_time: config.latch_time,
+            image_size: config.image_size,
             deadline: duration.map(|d| Instant::now() + d),
             data: Mutex::new(InstanceMethodsData {
                 crx,
                 aborted: false,
+                args: None,
             }),
+            checkpoint: config.checkpoint.map(SyncMutex::new),
+        }
+    }
+
+    /// Persist the state of the effect once it's done running
+    ///
+    /// Effects which were stopped or failed keep their last state, while effects which completed
+    /// by themselves start from scratch the next time.
+    pub fn finish_checkpoint(&self, success: bool) {
+        if let Some(checkpoint) = &self.checkpoint {
+            // unwrap: the lock is never held across panics
+            let mut checkpoint = checkpoint.lock().unwrap();
+
+            if success && !self.completed(&self.data.blocking_lock()) {
+                checkpoint.clear();
+            } else {
+                checkpoint.flush();
+            }
         }
     }
 
@@ -56,23 +88,24 @@ impl InstanceMethods {
     /// Returns true if the should abort
     async fn poll_control(&self) -> Result<(), RuntimeMethodError> {
         let mut data = self.data.lock().await;
-        match data.crx.try_recv() {
-            Ok(m) => match m {
-                ControlMessage::Abort => {
+
+        loop {
+            match data.crx.try_recv() {
+                Ok(ControlMessage::Abort) => {
                     data.aborted = true;
                     return Err(RuntimeMethodError::EffectAborted);
                 }
-            },
-            Err(err) => {
-                match err {
-                    tokio::sync::mpsc::error::TryRecvError::Empty => {
-                        // No control messages pending
-                    }
-                    tokio::sync::mpsc::error::TryRecvError::Disconnected => {
-                        // We were disconnected
-                        data.aborted = true;
-                        return Err(RuntimeMethodError::EffectAborted);
-                    }
+                Ok(ControlMessage::UpdateArgs(args)) => {
+                    data.args = Some(args);
+                }
+                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
+                    // No control messages pending
+                    break;
+                }
+                Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                    // We were disconnected
+                    data.aborted = true;
+                    return Err(RuntimeMethodError::EffectAborted);
                 }
             }
         }
@@ -105,10 +138,42 @@ impl RuntimeMethods for InstanceMethods {
         self.led_count
     }
 
+    fn get_latch_time(&self) -> Duration {
+        self.latch_time
+    }
+
+    fn get_image_size(&self) -> (u32, u32) {
+        self.image_size
+    }
+
     async fn abort(&self) -> bool {
         self.poll_control().await.is_err()
     }
 
+    async fn updated_args(&self) -> Option<serde_json::Value> {
+        self.poll_control().await.ok()?;
+        let args = self.data.lock().await.args.take()?;
+
+        if let Some(checkpoint) = &self.checkpoint {
+            // unwrap: the lock is never held across panics
+            checkpoint.lock().unwrap().set_args(args.clone());
+        }
+
+        Some(args)
+    }
+
+    fn restored_state(&self) -> Option<serde_json::Value> {
+        // unwrap: the lock is never held across panics
+        self.checkpoint.as_ref()?.lock().unwrap().load()
+    }
+
+    fn save_state(&self, state: serde_json::Value) {
+        if let Some(checkpoint) = &self.checkpoint {
+            // unwrap: the lock is never held across panics
+            checkpoint.lock().unwrap().save(state);
+        }
+    }
+
     async fn set_color(&self, color: crate::models::Color) -> Result<(), RuntimeMethodError> {
         self.poll_control().await?;
 
@@ -149,8 +214,26 @@ impl RuntimeMethods for InstanceMethods {
 #[async_trait]
 pub trait RuntimeMethods: Send {
     fn get_led_count(&self) -> usize;
+
+    /// Minimum time between two LED updates the device can handle
+    fn get_latch_time(&self) -> Duration;
+
+    /// Initial size of the images painted by effects
+    fn get_image_size(&self) -> (u32, u32);
     async fn abort(&self) -> bool;
 
+    /// Arguments sent to the effect since the last call, for providers which support updating
+    /// them while the effect is running
+    async fn updated_args(&self) -> Option<serde_json::Value>;
+
+    /// State saved by a previous run of the effect with the same arguments on this instance
+    fn restored_state(&self) -> Option<serde_json::Value>;
+
+    /// Checkpoint the state of the effect, so it can resume from it when it is started again
+    ///
+    /// This can be called as often as needed, writes to disk are throttled.
+    fn save_state(&self, state: serde_json::Value);
+
     async fn set_color(&self, color: Color) -> Result<(), RuntimeMethodError>;
     async fn set_led_colors(&self, colors: Vec<Color>) -> Result<(), RuntimeMethodError>;
     async fn set_image(&self, image: RawImage) -> Result<(), RuntimeMethodError>;
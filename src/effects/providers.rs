@@ -4,11 +4,16 @@ use thiserror::Error;
 
 use super::instance::RuntimeMethods;
 
+#[cfg(any(feature = "gif", feature = "png"))]
+mod animation;
 #[cfg(feature = "python")]
 mod python;
 
 #[derive(Debug, Error)]
 pub enum ProviderError {
+    #[cfg(any(feature = "gif", feature = "png"))]
+    #[error(transparent)]
+    Animation(#[from] animation::Error),
     #[cfg(feature = "python")]
     #[error(transparent)]
     Python(#[from] python::Error),
@@ -18,6 +23,9 @@ pub enum ProviderError {
 ///
 /// An effect provider is able to load an effect script and run it when given input parameters.
 pub trait Provider: std::fmt::Debug + Send + Sync {
+    /// Name of this provider, as reported by the `buildinfo` command
+    fn name(&self) -> &'static str;
+
     /// Returns if this provider supports the given effect
     ///
     /// # Parameters
@@ -30,6 +38,14 @@ pub trait Provider: std::fmt::Debug + Send + Sync {
     /// `true` if this provider can handle this script file, `false` otherwise.
     fn supports(&self, script_path: &str) -> bool;
 
+    /// Returns true if effects run by this provider pick up new arguments while running, see
+    /// [RuntimeMethods::updated_args]
+    ///
+    /// Effects from other providers are restarted with their new arguments.
+    fn supports_args_update(&self) -> bool {
+        false
+    }
+
     /// Run the given effect to completion in a blocking fashion
     ///
     /// # Parameters
@@ -62,10 +78,20 @@ impl Providers {
             providers: vec![
                 #[cfg(feature = "python")]
                 Arc::new(python::PythonProvider::new()),
+                #[cfg(any(feature = "gif", feature = "png"))]
+                Arc::new(animation::AnimationProvider::new()),
             ],
         }
     }
 
+    /// Names of the available providers
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect()
+    }
+
     pub fn get(&self, script_path: &str) -> Option<Arc<dyn Provider>> {
         self.providers
             .iter()
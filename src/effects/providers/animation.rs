@@ -0,0 +1,296 @@
+//! Effect provider playing animated images (GIF, APNG) as effects
+//!
+//! Frames are sent with `setImage`, so they are mapped onto the LED layout like any captured
+//! image. Supported arguments:
+//!
+//! * `speed`: playback speed multiplier, defaults to 1
+//! * `loop`: repeat the animation until the effect is stopped (default), or play it once
+//!
+//! The current frame is checkpointed, so restarted animations resume where they were.
+
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::executor::block_on;
+use image::{AnimationDecoder, Frame};
+use serde_derive::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    effects::{RuntimeMethodError, RuntimeMethods},
+    image::{RawImage, RawImageError},
+};
+
+/// Delay used for frames without one, or with one too short to be honored
+///
+/// Browsers display these frames for 100ms, and animations are authored accordingly.
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+
+/// Maximum time between two checks for the effect being stopped
+const ABORT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid animation: {0}")]
+    Decode(#[from] image::ImageError),
+    #[error("invalid animation frame: {0}")]
+    Frame(#[from] RawImageError),
+    #[error("animation has no frames")]
+    NoFrames,
+    #[error("invalid effect arguments: {0}")]
+    Arguments(#[from] serde_json::Error),
+    #[error("speed must be a positive number")]
+    InvalidSpeed,
+    #[error(transparent)]
+    Runtime(#[from] RuntimeMethodError),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct AnimationArgs {
+    speed: f64,
+    #[serde(rename = "loop")]
+    repeat: bool,
+}
+
+impl Default for AnimationArgs {
+    fn default() -> Self {
+        Self {
+            speed: 1.,
+            repeat: true,
+        }
+    }
+}
+
+impl AnimationArgs {
+    fn parse(args: serde_json::Value) -> Result<Self, Error> {
+        let args: Self = serde_json::from_value(args)?;
+
+        if !(args.speed.is_finite() && args.speed > 0.) {
+            return Err(Error::InvalidSpeed);
+        }
+
+        Ok(args)
+    }
+}
+
+/// Checkpointed state of a playing animation
+#[derive(Debug, Deserialize)]
+struct AnimationState {
+    frame: usize,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct AnimationProvider;
+
+impl AnimationProvider {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl super::Provider for AnimationProvider {
+    fn name(&self) -> &'static str {
+        "animation"
+    }
+
+    fn supports(&self, script_path: &str) -> bool {
+        let script_path = script_path.to_ascii_lowercase();
+
+        (cfg!(feature = "gif") && script_path.ends_with(".gif"))
+            || (cfg!(feature = "png")
+                && (script_path.ends_with(".png") || script_path.ends_with(".apng")))
+    }
+
+    fn run(
+        &self,
+        full_script_path: &Path,
+        args: serde_json::Value,
+        methods: Arc<dyn RuntimeMethods>,
+    ) -> Result<(), super::ProviderError> {
+        let mut args = AnimationArgs::parse(args)?;
+        let frames = decode(full_script_path)?;
+        Ok(play(&frames, &mut args, &*methods)?)
+    }
+
+    fn supports_args_update(&self) -> bool {
+        true
+    }
+}
+
+/// Decode all the frames of an animation, with their display duration
+fn decode(path: &Path) -> Result<Vec<(RawImage, Duration)>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let is_gif = path
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+
+    let frames = if is_gif {
+        decode_gif(reader)?
+    } else {
+        decode_png(reader)?
+    };
+
+    if frames.is_empty() {
+        return Err(Error::NoFrames);
+    }
+
+    frames
+        .into_iter()
+        .map(|frame| {
+            let delay = Duration::from(frame.delay());
+            let delay = if delay < MIN_FRAME_DELAY {
+                DEFAULT_FRAME_DELAY
+            } else {
+                delay
+            };
+
+            Ok((to_raw_image(frame)?, delay))
+        })
+        .collect()
+}
+
+#[cfg(feature = "gif")]
+fn decode_gif(reader: BufReader<File>) -> Result<Vec<Frame>, Error> {
+    Ok(image::codecs::gif::GifDecoder::new(reader)?
+        .into_frames()
+        .collect_frames()?)
+}
+
+#[cfg(not(feature = "gif"))]
+fn decode_gif(_reader: BufReader<File>) -> Result<Vec<Frame>, Error> {
+    Err(RawImageError::UnsupportedFormat.into())
+}
+
+#[cfg(feature = "png")]
+fn decode_png(reader: BufReader<File>) -> Result<Vec<Frame>, Error> {
+    let decoder = image::codecs::png::PngDecoder::new(reader)?;
+
+    if decoder.is_apng()? {
+        Ok(decoder.apng()?.into_frames().collect_frames()?)
+    } else {
+        // Still images are shown as a single frame animation
+        Ok(vec![Frame::new(
+            image::DynamicImage::from_decoder(decoder)?.into_rgba8(),
+        )])
+    }
+}
+
+#[cfg(not(feature = "png"))]
+fn decode_png(_reader: BufReader<File>) -> Result<Vec<Frame>, Error> {
+    Err(RawImageError::UnsupportedFormat.into())
+}
+
+/// Convert an animation frame to RGB, blending transparent pixels with black (LEDs off)
+fn to_raw_image(frame: Frame) -> Result<RawImage, RawImageError> {
+    let buffer = frame.into_buffer();
+    let (width, height) = buffer.dimensions();
+
+    let data = buffer
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            let blend = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+            [blend(r), blend(g), blend(b)]
+        })
+        .collect();
+
+    RawImage::try_from((data, width, height))
+}
+
+/// Play the animation until it ends or the effect is stopped
+fn play(
+    frames: &[(RawImage, Duration)],
+    args: &mut AnimationArgs,
+    methods: &dyn RuntimeMethods,
+) -> Result<(), Error> {
+    let mut start = methods
+        .restored_state()
+        .and_then(|state| serde_json::from_value::<AnimationState>(state).ok())
+        .map(|state| state.frame)
+        .filter(|&frame| frame < frames.len())
+        .unwrap_or(0);
+
+    loop {
+        for (frame, (image, delay)) in frames.iter().enumerate().skip(start) {
+            if let Some(updated) = block_on(methods.updated_args()) {
+                match AnimationArgs::parse(updated) {
+                    Ok(updated) => *args = updated,
+                    Err(error) => warn!(error = %error, "ignoring invalid effect arguments"),
+                }
+            }
+
+            match block_on(methods.set_image(image.clone())) {
+                Ok(()) => {}
+                Err(RuntimeMethodError::EffectAborted) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            }
+
+            methods.save_state(serde_json::json!({ "frame": frame }));
+
+            if !wait(methods, delay.div_f64(args.speed)) {
+                return Ok(());
+            }
+        }
+
+        start = 0;
+        if !args.repeat {
+            return Ok(());
+        }
+    }
+}
+
+/// Wait for `duration`
+///
+/// # Returns
+///
+/// `false` if the effect was stopped while waiting.
+fn wait(methods: &dyn RuntimeMethods, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+
+    loop {
+        if block_on(methods.abort()) {
+            return false;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+
+        std::thread::sleep((deadline - now).min(ABORT_POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+
+    #[test]
+    fn test_to_raw_image() {
+        let buffer =
+            image::RgbaImage::from_raw(2, 1, vec![255, 128, 0, 255, 200, 100, 50, 128]).unwrap();
+        let image = to_raw_image(Frame::new(buffer)).unwrap();
+
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(
+            image.color_at(0, 0).unwrap(),
+            crate::models::Color::new(255, 128, 0)
+        );
+        assert_eq!(
+            image.color_at(1, 0).unwrap(),
+            crate::models::Color::new(100, 50, 25)
+        );
+    }
+}
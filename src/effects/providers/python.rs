@@ -1,11 +1,17 @@
-use std::{convert::TryFrom, ffi::CString, path::Path, sync::Arc};
+use std::{
+    convert::TryFrom,
+    ffi::CString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use pyo3::{
     exceptions::{PyRuntimeError, PyTypeError},
     prelude::*,
     types::{PyByteArray, PyTuple},
 };
-use pythonize::pythonize;
+use pythonize::{depythonize, pythonize};
 
 use crate::{
     effects::{RuntimeMethodError, RuntimeMethods},
@@ -13,8 +19,13 @@ use crate::{
     models::Color,
 };
 
+mod canvas;
 mod context;
 use context::Context;
+mod painter;
+
+/// Shortest interval between updates reported to effects, for devices without a latch time
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(5);
 
 pub type Error = pyo3::PyErr;
 
@@ -28,6 +39,10 @@ impl PythonProvider {
 }
 
 impl super::Provider for PythonProvider {
+    fn name(&self) -> &'static str {
+        "python"
+    }
+
     fn supports(&self, script_path: &str) -> bool {
         script_path.ends_with(".py")
     }
@@ -38,7 +53,9 @@ impl super::Provider for PythonProvider {
         args: serde_json::Value,
         methods: Arc<dyn RuntimeMethods>,
     ) -> Result<(), super::ProviderError> {
-        Ok(do_run(methods, args, |py| {
+        let script_dir = full_script_path.parent().map(Path::to_owned);
+
+        Ok(do_run(methods, args, script_dir, |py| {
             // Run script
             py.run(
                 CString::new(std::fs::read_to_string(full_script_path)?)?.as_c_str(),
@@ -118,16 +135,42 @@ fn set_image(width: u16, height: u16, data: Bound<'_, PyByteArray>) -> Result<()
     })
 }
 
+/// Checkpoint the effect state, restored as `hyperion.state` when the effect is started again
+#[pyfunction]
+#[pyo3(name = "saveState")]
+fn save_state(state: Bound<'_, PyAny>) -> Result<(), PyErr> {
+    let state: serde_json::Value = depythonize(&state)?;
+    Context::with_current(|m| async move { m.save_state(state) });
+    Ok(())
+}
+
+/// Shortest interval between two updates of the LEDs, in seconds
+#[pyfunction]
+#[pyo3(name = "lowestUpdateInterval")]
+fn lowest_update_interval() -> f64 {
+    Context::with_current(|m| async move { m.get_latch_time() })
+        .max(MIN_UPDATE_INTERVAL)
+        .as_secs_f64()
+}
+
 #[pymodule]
 fn hyperion(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(abort, m)?)?;
     m.add_function(wrap_pyfunction!(set_color, m)?)?;
     m.add_function(wrap_pyfunction!(set_image, m)?)?;
+    m.add_function(wrap_pyfunction!(save_state, m)?)?;
+    m.add_function(wrap_pyfunction!(lowest_update_interval, m)?)?;
+    painter::register(m)?;
 
     m.add(
         "ledCount",
         Context::with_current(|m| async move { m.get_led_count() }),
     )?;
+    // Latch time in milliseconds, used by older effects instead of lowestUpdateInterval
+    m.add(
+        "latchTime",
+        Context::with_current(|m| async move { m.get_latch_time().as_millis() as u64 }),
+    )?;
 
     Ok(())
 }
@@ -135,15 +178,19 @@ fn hyperion(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
 fn do_run<T>(
     methods: Arc<dyn RuntimeMethods>,
     args: serde_json::Value,
+    script_dir: Option<PathBuf>,
     f: impl FnOnce(Python) -> Result<T, PyErr>,
 ) -> Result<T, PyErr> {
-    Context::with(methods, |ctx| {
+    let state = methods.restored_state();
+
+    Context::with(methods, script_dir, |ctx| {
         // Run the given code
         Python::attach(|py| {
             ctx.run(py, || {
                 // Register arguments
                 let hyperion_mod = py.import("hyperion")?;
                 hyperion_mod.add("args", pythonize(py, &args)?)?;
+                hyperion_mod.add("state", pythonize(py, &state)?)?;
 
                 f(py)
             })
@@ -0,0 +1,660 @@
+//! Image painted by Python effects through the `hyperion.image*` functions
+//!
+//! This follows the QPainter semantics hyperion.ng effects are written for, without antialiasing:
+//! a pixel is painted when its center is inside the shape, after mapping it through the current
+//! transformation. Pixel `(x, y)` covers the area from `(x, y)` to `(x + 1, y + 1)`.
+
+use std::convert::TryFrom;
+
+use crate::image::{RawImage, RawImageError};
+
+/// Largest size effects can request for their image
+const MAX_SIZE: u32 = 4096;
+
+/// Color with an alpha channel, blended over the image
+pub type Rgba = [u8; 4];
+
+/// Affine transformation of the coordinates used by effects to pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transform {
+    m11: f64,
+    m12: f64,
+    m21: f64,
+    m22: f64,
+    dx: f64,
+    dy: f64,
+}
+
+impl Transform {
+    const IDENTITY: Self = Self::new(1., 0., 0., 1., 0., 0.);
+
+    const fn new(m11: f64, m12: f64, m21: f64, m22: f64, dx: f64, dy: f64) -> Self {
+        Self {
+            m11,
+            m12,
+            m21,
+            m22,
+            dx,
+            dy,
+        }
+    }
+
+    fn map(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.m11 * x + self.m21 * y + self.dx,
+            self.m12 * x + self.m22 * y + self.dy,
+        )
+    }
+
+    /// Transformation applying `first`, then this one
+    fn after(&self, first: &Self) -> Self {
+        Self::new(
+            self.m11 * first.m11 + self.m21 * first.m12,
+            self.m12 * first.m11 + self.m22 * first.m12,
+            self.m11 * first.m21 + self.m21 * first.m22,
+            self.m12 * first.m21 + self.m22 * first.m22,
+            self.m11 * first.dx + self.m21 * first.dy + self.dx,
+            self.m12 * first.dx + self.m22 * first.dy + self.dy,
+        )
+    }
+
+    fn inverse(&self) -> Option<Self> {
+        let det = self.m11 * self.m22 - self.m12 * self.m21;
+        if det.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let (m11, m12, m21, m22) = (
+            self.m22 / det,
+            -self.m12 / det,
+            -self.m21 / det,
+            self.m11 / det,
+        );
+
+        Some(Self::new(
+            m11,
+            m12,
+            m21,
+            m22,
+            -(m11 * self.dx + m21 * self.dy),
+            -(m12 * self.dx + m22 * self.dy),
+        ))
+    }
+}
+
+/// Behavior of a gradient outside of its stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spread {
+    Pad,
+    Reflect,
+    Repeat,
+}
+
+impl From<i32> for Spread {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Reflect,
+            2 => Self::Repeat,
+            _ => Self::Pad,
+        }
+    }
+}
+
+impl Spread {
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Self::Pad => t.clamp(0., 1.),
+            Self::Reflect => {
+                let t = t.rem_euclid(2.);
+                if t > 1. {
+                    2. - t
+                } else {
+                    t
+                }
+            }
+            Self::Repeat => t.rem_euclid(1.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f64, Rgba)>,
+    spread: Spread,
+}
+
+impl Gradient {
+    /// Parse gradient stops of 5 bytes each: position (0 to 255), red, green, blue and alpha
+    pub fn new(stops: &[u8], spread: Spread) -> Option<Self> {
+        if stops.is_empty() || !stops.len().is_multiple_of(5) {
+            return None;
+        }
+
+        let mut stops: Vec<_> = stops
+            .chunks_exact(5)
+            .map(|stop| (stop[0] as f64 / 255., [stop[1], stop[2], stop[3], stop[4]]))
+            .collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Some(Self { stops, spread })
+    }
+
+    fn color_at(&self, t: f64) -> Rgba {
+        let t = self.spread.apply(t);
+
+        let mut previous = self.stops[0];
+        if t <= previous.0 {
+            return previous.1;
+        }
+
+        for &(position, color) in &self.stops[1..] {
+            if t <= position {
+                let f = (t - previous.0) / (position - previous.0);
+                let mut mixed = [0; 4];
+                for (c, (a, b)) in mixed.iter_mut().zip(previous.1.iter().zip(&color)) {
+                    *c = (*a as f64 + (*b as f64 - *a as f64) * f).round() as u8;
+                }
+                return mixed;
+            }
+
+            previous = (position, color);
+        }
+
+        previous.1
+    }
+}
+
+/// Color source of painted shapes, in the coordinates used by effects
+#[derive(Debug, Clone)]
+pub enum Brush {
+    Solid(Rgba),
+    Linear {
+        start: (f64, f64),
+        end: (f64, f64),
+        gradient: Gradient,
+    },
+    /// Gradient from a focal circle to an outer circle
+    Radial {
+        center: (f64, f64),
+        radius: f64,
+        focal: (f64, f64),
+        focal_radius: f64,
+        gradient: Gradient,
+    },
+    /// Gradient around a center, counter-clockwise from an angle in degrees
+    Conical {
+        center: (f64, f64),
+        angle: f64,
+        gradient: Gradient,
+    },
+}
+
+impl Brush {
+    fn color_at(&self, x: f64, y: f64) -> Option<Rgba> {
+        match self {
+            Self::Solid(color) => Some(*color),
+            Self::Linear {
+                start,
+                end,
+                gradient,
+            } => {
+                let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+                let length = dx * dx + dy * dy;
+                let t = if length > 0. {
+                    ((x - start.0) * dx + (y - start.1) * dy) / length
+                } else {
+                    0.
+                };
+
+                Some(gradient.color_at(t))
+            }
+            Self::Radial {
+                center,
+                radius,
+                focal,
+                focal_radius,
+                gradient,
+            } => {
+                // Largest t for which (x, y) is on the circle interpolated between the focal
+                // circle (t = 0) and the outer circle (t = 1), with a positive radius
+                let (cdx, cdy) = (center.0 - focal.0, center.1 - focal.1);
+                let dr = radius - focal_radius;
+                let (pdx, pdy) = (x - focal.0, y - focal.1);
+
+                let a = cdx * cdx + cdy * cdy - dr * dr;
+                let b = pdx * cdx + pdy * cdy + focal_radius * dr;
+                let c = pdx * pdx + pdy * pdy - focal_radius * focal_radius;
+
+                let valid = |t: f64| t.is_finite() && focal_radius + t * dr >= 0.;
+                let t = if a.abs() < f64::EPSILON {
+                    Some(c / (2. * b)).filter(|t| valid(*t))
+                } else {
+                    let discriminant = b * b - a * c;
+                    if discriminant < 0. {
+                        return None;
+                    }
+
+                    let root = discriminant.sqrt();
+                    let (t0, t1) = ((b + root) / a, (b - root) / a);
+                    vec![t0.max(t1), t0.min(t1)].into_iter().find(|t| valid(*t))
+                };
+
+                t.map(|t| gradient.color_at(t))
+            }
+            Self::Conical {
+                center,
+                angle,
+                gradient,
+            } => {
+                Some(gradient.color_at(
+                    ((degrees(x - center.0, y - center.1) - angle) / 360.).rem_euclid(1.),
+                ))
+            }
+        }
+    }
+}
+
+/// Counter-clockwise angle of a vector in degrees, with y pointing down
+fn degrees(dx: f64, dy: f64) -> f64 {
+    (-dy).atan2(dx).to_degrees()
+}
+
+/// Check if `(x, y)` is within `half_width` of the segment from `start` to `end`, extended by
+/// `half_width` at both ends like a square pen cap
+fn on_segment(start: (f64, f64), end: (f64, f64), half_width: f64, x: f64, y: f64) -> bool {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let (px, py) = (x - start.0, y - start.1);
+    let length = (dx * dx + dy * dy).sqrt();
+
+    if length < f64::EPSILON {
+        return px.abs() <= half_width && py.abs() <= half_width;
+    }
+
+    let (ux, uy) = (dx / length, dy / length);
+    let along = px * ux + py * uy;
+    let across = (px * uy - py * ux).abs();
+
+    along >= -half_width && along <= length + half_width && across <= half_width
+}
+
+/// Check if `(x, y)` is inside `polygon`, with the even-odd rule
+fn in_polygon(polygon: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+
+    for (i, &(x1, y1)) in polygon.iter().enumerate() {
+        let (x0, y0) = polygon[(i + polygon.len() - 1) % polygon.len()];
+        if (y0 > y) != (y1 > y) && x < x0 + (y - y0) * (x1 - x0) / (y1 - y0) {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// Center of the pixel at integer coordinates, which 1 pixel wide lines go through
+fn pixel_center((x, y): (f64, f64)) -> (f64, f64) {
+    (x + 0.5, y + 0.5)
+}
+
+#[derive(Debug)]
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    /// RGB pixels, row by row
+    data: Vec<u8>,
+    transform: Transform,
+    /// Images saved by effects to show later
+    saved: Vec<RawImage>,
+}
+
+impl Canvas {
+    pub fn new((width, height): (u32, u32)) -> Self {
+        let (width, height) = (width.clamp(1, MAX_SIZE), height.clamp(1, MAX_SIZE));
+
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 3],
+            transform: Transform::IDENTITY,
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Scale the image up so it's at least `width` by `height`, keeping its aspect ratio
+    ///
+    /// The image is scaled to fit in the largest of the current and requested sizes, so one of
+    /// its dimensions may stay below the requested one.
+    pub fn min_size(&mut self, width: u32, height: u32) -> (u32, u32) {
+        if width > self.width || height > self.height {
+            let (bound_width, bound_height) = (
+                width.max(self.width).min(MAX_SIZE) as u64,
+                height.max(self.height).min(MAX_SIZE) as u64,
+            );
+            let (current_width, current_height) = (self.width as u64, self.height as u64);
+
+            let scaled_width = bound_height * current_width / current_height;
+            let (new_width, new_height) = if scaled_width <= bound_width {
+                (scaled_width, bound_height)
+            } else {
+                (bound_width, bound_width * current_height / current_width)
+            };
+
+            self.resize(new_width.max(1) as u32, new_height.max(1) as u32);
+        }
+
+        (self.width, self.height)
+    }
+
+    /// Resize the image, sampling the nearest pixels
+    fn resize(&mut self, width: u32, height: u32) {
+        let mut data = Vec::with_capacity(width as usize * height as usize * 3);
+
+        for y in 0..height {
+            let source_y = (y as u64 * self.height as u64 / height as u64) as usize;
+            for x in 0..width {
+                let source_x = (x as u64 * self.width as u64 / width as u64) as usize;
+                let offset = (source_y * self.width as usize + source_x) * 3;
+                data.extend_from_slice(&self.data[offset..offset + 3]);
+            }
+        }
+
+        self.width = width;
+        self.height = height;
+        self.data = data;
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Option<[u8; 3]> {
+        let offset = self.offset(x, y)?;
+        Some([
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+        ])
+    }
+
+    /// Set a pixel, regardless of the current transformation
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: [u8; 3]) {
+        if let Some(offset) = self.offset(x, y) {
+            self.data[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return None;
+        }
+
+        Some((y as usize * self.width as usize + x as usize) * 3)
+    }
+
+    fn blend(&mut self, offset: usize, [r, g, b, a]: Rgba) {
+        let alpha = a as u32;
+        for (dst, src) in self.data[offset..offset + 3].iter_mut().zip(&[r, g, b]) {
+            *dst = ((*src as u32 * alpha + *dst as u32 * (255 - alpha) + 127) / 255) as u8;
+        }
+    }
+
+    /// Paint the pixels which are `inside` the shape bounded by `bounds` with `brush`
+    ///
+    /// `bounds` and the coordinates given to `inside` are in the coordinate system of effects.
+    fn paint(
+        &mut self,
+        bounds: ((f64, f64), (f64, f64)),
+        brush: &Brush,
+        inside: impl Fn(f64, f64) -> bool,
+    ) {
+        let inverse = match self.transform.inverse() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        // Pixels covered by the transformed bounds
+        let ((x0, y0), (x1, y1)) = bounds;
+        let corners: Vec<_> = vec![(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+            .into_iter()
+            .map(|(x, y)| self.transform.map(x, y))
+            .collect();
+        let range = |values: Vec<f64>, max: u32| {
+            let low = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let high = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            (low.floor().max(0.) as u32)..(high.ceil().min(max as f64) as u32)
+        };
+        let columns = range(corners.iter().map(|c| c.0).collect(), self.width);
+        let rows = range(corners.iter().map(|c| c.1).collect(), self.height);
+
+        for py in rows {
+            for px in columns.clone() {
+                let (x, y) = inverse.map(px as f64 + 0.5, py as f64 + 0.5);
+                if !inside(x, y) {
+                    continue;
+                }
+
+                if let Some(color) = brush.color_at(x, y) {
+                    let offset = (py as usize * self.width as usize + px as usize) * 3;
+                    self.blend(offset, color);
+                }
+            }
+        }
+    }
+
+    /// Fill a rectangle, or the whole image if `rect` is `None`
+    pub fn fill_rect(&mut self, rect: Option<(f64, f64, f64, f64)>, brush: &Brush) {
+        let (x, y, width, height) = rect.unwrap_or((0., 0., self.width as f64, self.height as f64));
+
+        self.paint(((x, y), (x + width, y + height)), brush, |px, py| {
+            px >= x && px < x + width && py >= y && py < y + height
+        });
+    }
+
+    pub fn fill_polygon(&mut self, points: &[(f64, f64)], color: Rgba) {
+        if points.is_empty() {
+            return;
+        }
+
+        let polygon: Vec<_> = points.iter().copied().map(pixel_center).collect();
+        self.paint(bounds(&polygon, 0.), &Brush::Solid(color), |x, y| {
+            in_polygon(&polygon, x, y)
+        });
+
+        // Outline, so the edges are included like with a QPainter pen
+        for (i, &start) in points.iter().enumerate() {
+            self.draw_line(start, points[(i + 1) % points.len()], 1., color);
+        }
+    }
+
+    /// Fill a pie of the circle at `center`, from `start` degrees and over `span` degrees
+    /// counter-clockwise (clockwise if negative)
+    pub fn fill_pie(
+        &mut self,
+        center: (f64, f64),
+        radius: f64,
+        start: f64,
+        span: f64,
+        brush: &Brush,
+    ) {
+        let (cx, cy) = center;
+        let bounds = ((cx - radius, cy - radius), (cx + radius, cy + radius));
+
+        self.paint(bounds, brush, |x, y| {
+            let (dx, dy) = (x - cx, y - cy);
+            if dx * dx + dy * dy > radius * radius {
+                return false;
+            }
+
+            if span.abs() >= 360. {
+                return true;
+            }
+
+            let relative = (degrees(dx, dy) - start).rem_euclid(360.);
+            if span >= 0. {
+                relative <= span
+            } else {
+                (360. - relative).rem_euclid(360.) <= -span
+            }
+        });
+    }
+
+    /// Draw a line between the pixels at `start` and `end`
+    pub fn draw_line(&mut self, start: (f64, f64), end: (f64, f64), thickness: f64, color: Rgba) {
+        let (start, end) = (pixel_center(start), pixel_center(end));
+        let half_width = thickness.max(1.) / 2.;
+
+        self.paint(
+            bounds(&[start, end], half_width),
+            &Brush::Solid(color),
+            |x, y| on_segment(start, end, half_width, x, y),
+        );
+    }
+
+    /// Draw a square point of `thickness` pixels
+    pub fn draw_point(&mut self, point: (f64, f64), thickness: f64, color: Rgba) {
+        self.draw_line(point, point, thickness, color);
+    }
+
+    /// Draw the outline of a rectangle
+    pub fn draw_rect(
+        &mut self,
+        (x, y, width, height): (f64, f64, f64, f64),
+        thickness: f64,
+        color: Rgba,
+    ) {
+        let corners = [
+            (x, y),
+            (x + width, y),
+            (x + width, y + height),
+            (x, y + height),
+        ];
+
+        for (i, &start) in corners.iter().enumerate() {
+            self.draw_line(start, corners[(i + 1) % corners.len()], thickness, color);
+        }
+    }
+
+    /// Rotate the coordinate system clockwise by `angle` degrees
+    pub fn rotate(&mut self, angle: f64) {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        self.transform = self
+            .transform
+            .after(&Transform::new(cos, sin, -sin, cos, 0., 0.));
+    }
+
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        self.transform = self
+            .transform
+            .after(&Transform::new(1., 0., 0., 1., dx, dy));
+    }
+
+    pub fn shear(&mut self, sh: f64, sv: f64) {
+        self.transform = self
+            .transform
+            .after(&Transform::new(1., sv, sh, 1., 0., 0.));
+    }
+
+    pub fn reset_transform(&mut self) {
+        self.transform = Transform::IDENTITY;
+    }
+
+    /// Save a copy of the image, returning its id for [Canvas::to_image]
+    pub fn save(&mut self) -> Result<usize, RawImageError> {
+        let image = self.to_image(None)?;
+        self.saved.push(image);
+        Ok(self.saved.len() - 1)
+    }
+
+    /// Get the saved image with the given id, or the current image
+    pub fn to_image(&self, id: Option<usize>) -> Result<RawImage, RawImageError> {
+        match id.and_then(|id| self.saved.get(id)) {
+            Some(image) => Ok(image.clone()),
+            None => RawImage::try_from((self.data.clone(), self.width, self.height)),
+        }
+    }
+}
+
+/// Bounds of `points`, extended by `margin`
+fn bounds(points: &[(f64, f64)], margin: f64) -> ((f64, f64), (f64, f64)) {
+    let mut bounds = (
+        (f64::INFINITY, f64::INFINITY),
+        (f64::NEG_INFINITY, f64::NEG_INFINITY),
+    );
+
+    for &(x, y) in points {
+        bounds.0 = (bounds.0 .0.min(x - margin), bounds.0 .1.min(y - margin));
+        bounds.1 = (bounds.1 .0.max(x + margin), bounds.1 .1.max(y + margin));
+    }
+
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(canvas: &Canvas) -> Vec<String> {
+        (0..canvas.height() as i32)
+            .map(|y| {
+                (0..canvas.width() as i32)
+                    .map(|x| match canvas.pixel(x, y) {
+                        Some([0, 0, 0]) => '.',
+                        _ => '#',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shapes() {
+        let white = [255, 255, 255, 255];
+        let mut canvas = Canvas::new((6, 4));
+
+        canvas.draw_line((0., 0.), (3., 0.), 1., white);
+        canvas.fill_rect(Some((4., 2., 2., 2.)), &Brush::Solid(white));
+        canvas.draw_point((1., 2.), 1., white);
+        assert_eq!(rows(&canvas), ["####..", "......", ".#..##", "....##"]);
+
+        // Shapes are drawn in the transformed coordinate system
+        let mut canvas = Canvas::new((4, 4));
+        canvas.translate(4., 0.);
+        canvas.rotate(90.);
+        canvas.fill_rect(Some((0., 0., 1., 4.)), &Brush::Solid(white));
+        assert_eq!(rows(&canvas), ["####", "....", "....", "...."]);
+    }
+
+    #[test]
+    fn test_gradients() {
+        let gradient = Gradient::new(&[0, 0, 0, 0, 255, 255, 255, 0, 0, 255], Spread::Pad).unwrap();
+        assert_eq!(gradient.color_at(-1.), [0, 0, 0, 255]);
+        assert_eq!(gradient.color_at(0.5), [128, 0, 0, 255]);
+
+        let mut canvas = Canvas::new((4, 1));
+        canvas.fill_rect(
+            None,
+            &Brush::Linear {
+                start: (0., 0.),
+                end: (4., 0.),
+                gradient,
+            },
+        );
+        let reds: Vec<_> = (0..4).map(|x| canvas.pixel(x, 0).unwrap()[0]).collect();
+        assert_eq!(reds, [32, 96, 159, 223]);
+    }
+
+    #[test]
+    fn test_min_size() {
+        let mut canvas = Canvas::new((4, 2));
+        canvas.set_pixel(3, 1, [255, 0, 0]);
+
+        // The aspect ratio is kept
+        assert_eq!(canvas.min_size(10, 10), (10, 5));
+        assert_eq!(canvas.pixel(9, 4), Some([255, 0, 0]));
+        assert_eq!(canvas.min_size(2, 2), (10, 5));
+    }
+}
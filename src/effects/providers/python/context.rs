@@ -1,6 +1,7 @@
 use std::{
     cell::RefCell,
     panic,
+    path::{Path, PathBuf},
     sync::{Arc, Once, Weak},
 };
 
@@ -8,7 +9,7 @@ use drop_bomb::DropBomb;
 use futures::Future;
 use pyo3::prelude::*;
 
-use super::{hyperion, RuntimeMethods};
+use super::{canvas::Canvas, hyperion, RuntimeMethods};
 
 static INITIALIZED_PYTHON: Once = Once::new();
 
@@ -21,11 +22,19 @@ thread_local! {
 pub struct Context {
     tstate: *mut pyo3::ffi::PyThreadState,
     methods: Weak<dyn RuntimeMethods>,
+    /// Image painted by the effect
+    canvas: RefCell<Canvas>,
+    /// Directory of the effect script, relative image paths are resolved from it
+    script_dir: Option<PathBuf>,
     bomb: DropBomb,
 }
 
 impl Context {
-    unsafe fn new(_py: Python, methods: Weak<dyn RuntimeMethods>) -> Result<Self, ()> {
+    unsafe fn new(
+        _py: Python,
+        methods: &Arc<dyn RuntimeMethods>,
+        script_dir: Option<PathBuf>,
+    ) -> Result<Self, ()> {
         // Get the main_state ptr
         let main_state = pyo3::ffi::PyEval_SaveThread();
 
@@ -44,7 +53,9 @@ impl Context {
         } else {
             Ok(Self {
                 tstate,
-                methods,
+                methods: Arc::downgrade(methods),
+                canvas: RefCell::new(Canvas::new(methods.get_image_size())),
+                script_dir,
                 bomb: DropBomb::new("Context::release must be called before dropping it"),
             })
         }
@@ -85,7 +96,11 @@ impl Context {
         }
     }
 
-    pub fn with<U>(methods: Arc<dyn RuntimeMethods>, f: impl FnOnce(&Self) -> U) -> U {
+    pub fn with<U>(
+        methods: Arc<dyn RuntimeMethods>,
+        script_dir: Option<PathBuf>,
+        f: impl FnOnce(&Self) -> U,
+    ) -> U {
         unsafe {
             // Initialize the Python interpreter global state
             INITIALIZED_PYTHON.call_once(|| {
@@ -97,7 +112,7 @@ impl Context {
             let result = CONTEXT.with(|ctx| {
                 // Initialize the thread-local state, i.e. interpreter
                 *ctx.borrow_mut() = Some(Python::attach(|py| {
-                    Self::new(py, Arc::downgrade(&methods))
+                    Self::new(py, &methods, script_dir)
                         .expect("failed initializing python subinterp")
                 }));
 
@@ -140,4 +155,34 @@ impl Context {
                 .expect("no current methods")))
         })
     }
+
+    pub fn with_canvas<U>(f: impl FnOnce(&mut Canvas) -> U) -> U {
+        CONTEXT.with(|ctx| {
+            f(&mut ctx
+                .borrow()
+                .as_ref()
+                .expect("no current context")
+                .canvas
+                .borrow_mut())
+        })
+    }
+
+    /// Resolve the path of a file used by the effect
+    ///
+    /// Relative paths, and paths starting with `:` like hyperion.ng resources, are resolved from
+    /// the directory of the effect script.
+    pub fn resolve_path(path: &str) -> PathBuf {
+        let path = Path::new(path.strip_prefix(':').unwrap_or(path));
+
+        CONTEXT.with(|ctx| {
+            match ctx
+                .borrow()
+                .as_ref()
+                .and_then(|ctx| ctx.script_dir.as_ref())
+            {
+                Some(script_dir) => script_dir.join(path),
+                None => path.to_owned(),
+            }
+        })
+    }
 }
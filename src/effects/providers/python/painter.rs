@@ -0,0 +1,474 @@
+//! `hyperion.image*` functions painting the effect image, and `hyperion.getImage`
+//!
+//! Overloads follow the ones of hyperion.ng, selected by the number of arguments. Colors are
+//! given as red, green and blue components, optionally followed by alpha. Gradients are given as a
+//! bytearray of stops, see [Gradient::new].
+
+use std::{convert::TryFrom, fs::File, io::BufReader, path::Path};
+
+use pyo3::{
+    exceptions::PyOSError,
+    prelude::*,
+    types::{PyByteArray, PyDict, PyList, PyTuple},
+};
+
+use crate::effects::RuntimeMethodError;
+
+use super::{
+    canvas::{Brush, Canvas, Gradient, Rgba, Spread},
+    Context,
+};
+
+/// Positional arguments of an overloaded function
+struct Args<'py> {
+    name: &'static str,
+    args: Bound<'py, PyTuple>,
+}
+
+impl<'py> Args<'py> {
+    fn new(name: &'static str, args: Bound<'py, PyTuple>) -> Self {
+        Self { name, args }
+    }
+
+    fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    fn invalid(&self) -> PyErr {
+        RuntimeMethodError::InvalidArguments { name: self.name }.into()
+    }
+
+    fn f64(&self, index: usize) -> PyResult<f64> {
+        self.args.get_item(index)?.extract()
+    }
+
+    fn point(&self, index: usize) -> PyResult<(f64, f64)> {
+        Ok((self.f64(index)?, self.f64(index + 1)?))
+    }
+
+    fn rect(&self, index: usize) -> PyResult<(f64, f64, f64, f64)> {
+        Ok((
+            self.f64(index)?,
+            self.f64(index + 1)?,
+            self.f64(index + 2)?,
+            self.f64(index + 3)?,
+        ))
+    }
+
+    fn component(&self, index: usize) -> PyResult<u8> {
+        Ok(self.args.get_item(index)?.extract::<i32>()?.clamp(0, 255) as u8)
+    }
+
+    /// Color starting at `index`, with an alpha component if it's followed by 4 arguments
+    fn color(&self, index: usize) -> PyResult<Rgba> {
+        Ok([
+            self.component(index)?,
+            self.component(index + 1)?,
+            self.component(index + 2)?,
+            if self.len() > index + 3 {
+                self.component(index + 3)?
+            } else {
+                255
+            },
+        ])
+    }
+
+    fn bytes(&self, index: usize) -> PyResult<Vec<u8>> {
+        Ok(self
+            .args
+            .get_item(index)?
+            .cast_into::<PyByteArray>()
+            .map_err(|_| self.invalid())?
+            .to_vec())
+    }
+
+    /// Gradient stops at `index`, with the spread at `index + 1` if given
+    fn gradient(&self, index: usize) -> PyResult<Gradient> {
+        let spread = if self.len() > index + 1 {
+            self.args.get_item(index + 1)?.extract::<i32>()?
+        } else {
+            0
+        };
+
+        Gradient::new(&self.bytes(index)?, Spread::from(spread)).ok_or_else(|| self.invalid())
+    }
+}
+
+/// Show the current image, or the one saved with the given id, on the LEDs
+#[pyfunction(signature = (image_id = -1))]
+#[pyo3(name = "imageShow")]
+fn image_show(image_id: i64) -> PyResult<()> {
+    let image = Context::with_canvas(|canvas| canvas.to_image(usize::try_from(image_id).ok()))
+        .map_err(RuntimeMethodError::InvalidImageData)?;
+
+    Context::with_current(|m| async move { m.set_image(image).await })?;
+    Ok(())
+}
+
+/// Save a copy of the current image, returning its id for `imageShow`
+#[pyfunction]
+#[pyo3(name = "imageSave")]
+fn image_save() -> PyResult<usize> {
+    Ok(Context::with_canvas(Canvas::save).map_err(RuntimeMethodError::InvalidImageData)?)
+}
+
+/// Scale the image up to at least the given size, returning the new size
+#[pyfunction]
+#[pyo3(name = "imageMinSize")]
+fn image_min_size(width: u32, height: u32) -> (u32, u32) {
+    Context::with_canvas(|canvas| canvas.min_size(width, height))
+}
+
+#[pyfunction]
+#[pyo3(name = "imageWidth")]
+fn image_width() -> u32 {
+    Context::with_canvas(|canvas| canvas.width())
+}
+
+#[pyfunction]
+#[pyo3(name = "imageHeight")]
+fn image_height() -> u32 {
+    Context::with_canvas(|canvas| canvas.height())
+}
+
+/// `([x, y, width, height,] r, g, b[, a])`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageSolidFill")]
+fn image_solid_fill(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageSolidFill", args);
+
+    let (rect, color) = match args.len() {
+        3 | 4 => (None, args.color(0)?),
+        7 | 8 => (Some(args.rect(0)?), args.color(4)?),
+        _ => return Err(args.invalid()),
+    };
+
+    Context::with_canvas(|canvas| canvas.fill_rect(rect, &Brush::Solid(color)));
+    Ok(())
+}
+
+/// `([x, y, width, height,] startX, startY, endX, endY, gradient, spread)`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageLinearGradient")]
+fn image_linear_gradient(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageLinearGradient", args);
+
+    let (rect, index) = match args.len() {
+        6 => (None, 0),
+        10 => (Some(args.rect(0)?), 4),
+        _ => return Err(args.invalid()),
+    };
+
+    let brush = Brush::Linear {
+        start: args.point(index)?,
+        end: args.point(index + 2)?,
+        gradient: args.gradient(index + 4)?,
+    };
+
+    Context::with_canvas(|canvas| canvas.fill_rect(rect, &brush));
+    Ok(())
+}
+
+/// `([x, y, width, height,] centerX, centerY, angle, gradient)`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageConicalGradient")]
+fn image_conical_gradient(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageConicalGradient", args);
+
+    let (rect, index) = match args.len() {
+        4 => (None, 0),
+        8 => (Some(args.rect(0)?), 4),
+        _ => return Err(args.invalid()),
+    };
+
+    let brush = Brush::Conical {
+        center: args.point(index)?,
+        angle: args.f64(index + 2)?,
+        gradient: args.gradient(index + 3)?,
+    };
+
+    Context::with_canvas(|canvas| canvas.fill_rect(rect, &brush));
+    Ok(())
+}
+
+/// `([x, y, width, height,] centerX, centerY, radius[, focalX, focalY, focalRadius], gradient,
+/// spread)`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageRadialGradient")]
+fn image_radial_gradient(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageRadialGradient", args);
+
+    let (rect, index, focal) = match args.len() {
+        5 => (None, 0, false),
+        8 => (None, 0, true),
+        9 => (Some(args.rect(0)?), 4, false),
+        12 => (Some(args.rect(0)?), 4, true),
+        _ => return Err(args.invalid()),
+    };
+
+    let center = args.point(index)?;
+    let radius = args.f64(index + 2)?;
+    let (focal, focal_radius, gradient) = if focal {
+        (
+            args.point(index + 3)?,
+            args.f64(index + 5)?,
+            args.gradient(index + 6)?,
+        )
+    } else {
+        (center, 0., args.gradient(index + 3)?)
+    };
+
+    let brush = Brush::Radial {
+        center,
+        radius,
+        focal,
+        focal_radius,
+        gradient,
+    };
+
+    Context::with_canvas(|canvas| canvas.fill_rect(rect, &brush));
+    Ok(())
+}
+
+/// `(points, r, g, b[, a])`, with points as a bytearray of x and y coordinates
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageDrawPolygon")]
+fn image_draw_polygon(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageDrawPolygon", args);
+    if !matches!(args.len(), 4 | 5) {
+        return Err(args.invalid());
+    }
+
+    let points = args.bytes(0)?;
+    if !points.len().is_multiple_of(2) {
+        return Err(args.invalid());
+    }
+
+    let points: Vec<_> = points
+        .chunks_exact(2)
+        .map(|point| (point[0] as f64, point[1] as f64))
+        .collect();
+    let color = args.color(1)?;
+
+    Context::with_canvas(|canvas| canvas.fill_polygon(&points, color));
+    Ok(())
+}
+
+/// `(centerX, centerY, radius, startAngle, spanAngle, r, g, b[, a])`, or
+/// `(centerX, centerY, radius, startAngle, spanAngle, gradient)` for a conical gradient
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageDrawPie")]
+fn image_draw_pie(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageDrawPie", args);
+
+    let center = args.point(0)?;
+    let (radius, start, span) = (args.f64(2)?, args.f64(3)?, args.f64(4)?);
+    let brush = match args.len() {
+        6 => Brush::Conical {
+            center,
+            angle: start,
+            gradient: args.gradient(5)?,
+        },
+        8 | 9 => Brush::Solid(args.color(5)?),
+        _ => return Err(args.invalid()),
+    };
+
+    Context::with_canvas(|canvas| canvas.fill_pie(center, radius, start, span, &brush));
+    Ok(())
+}
+
+/// `(startX, startY, endX, endY, thickness, r, g, b[, a])`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageDrawLine")]
+fn image_draw_line(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageDrawLine", args);
+    if !matches!(args.len(), 8 | 9) {
+        return Err(args.invalid());
+    }
+
+    let (start, end, thickness) = (args.point(0)?, args.point(2)?, args.f64(4)?);
+    let color = args.color(5)?;
+
+    Context::with_canvas(|canvas| canvas.draw_line(start, end, thickness, color));
+    Ok(())
+}
+
+/// `(x, y, thickness, r, g, b[, a])`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageDrawPoint")]
+fn image_draw_point(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageDrawPoint", args);
+    if !matches!(args.len(), 6 | 7) {
+        return Err(args.invalid());
+    }
+
+    let (point, thickness) = (args.point(0)?, args.f64(2)?);
+    let color = args.color(3)?;
+
+    Context::with_canvas(|canvas| canvas.draw_point(point, thickness, color));
+    Ok(())
+}
+
+/// `(x, y, width, height, thickness, r, g, b[, a])`
+#[pyfunction(signature = (*args))]
+#[pyo3(name = "imageDrawRect")]
+fn image_draw_rect(args: Bound<'_, PyTuple>) -> PyResult<()> {
+    let args = Args::new("imageDrawRect", args);
+    if !matches!(args.len(), 8 | 9) {
+        return Err(args.invalid());
+    }
+
+    let (rect, thickness) = (args.rect(0)?, args.f64(4)?);
+    let color = args.color(5)?;
+
+    Context::with_canvas(|canvas| canvas.draw_rect(rect, thickness, color));
+    Ok(())
+}
+
+/// Set a pixel, ignoring the current transformation
+#[pyfunction]
+#[pyo3(name = "imageSetPixel")]
+fn image_set_pixel(x: i32, y: i32, r: i32, g: i32, b: i32) {
+    let color = [r, g, b].map(|c| c.clamp(0, 255) as u8);
+    Context::with_canvas(|canvas| canvas.set_pixel(x, y, color));
+}
+
+/// Get a pixel as a `(r, g, b)` tuple, black if it's out of the image
+#[pyfunction]
+#[pyo3(name = "imageGetPixel")]
+fn image_get_pixel(x: i32, y: i32) -> (u8, u8, u8) {
+    let [r, g, b] = Context::with_canvas(|canvas| canvas.pixel(x, y)).unwrap_or_default();
+    (r, g, b)
+}
+
+/// Rotate the coordinate system clockwise, in degrees
+#[pyfunction]
+#[pyo3(name = "imageCRotate")]
+fn image_c_rotate(angle: f64) {
+    Context::with_canvas(|canvas| canvas.rotate(angle));
+}
+
+/// Move the origin of the coordinate system
+#[pyfunction]
+#[pyo3(name = "imageCOffset")]
+fn image_c_offset(x: f64, y: f64) {
+    Context::with_canvas(|canvas| canvas.translate(x, y));
+}
+
+#[pyfunction]
+#[pyo3(name = "imageCShear")]
+fn image_c_shear(sh: f64, sv: f64) {
+    Context::with_canvas(|canvas| canvas.shear(sh, sv));
+}
+
+/// Reset the coordinate system changes of `imageCRotate`, `imageCOffset` and `imageCShear`
+#[pyfunction]
+#[pyo3(name = "imageResetT")]
+fn image_reset_t() {
+    Context::with_canvas(Canvas::reset_transform);
+}
+
+/// Decode the frames of an image file, as RGB
+fn decode_frames(path: &Path) -> Result<Vec<image::RgbImage>, image::ImageError> {
+    #[cfg(feature = "gif")]
+    {
+        use image::AnimationDecoder;
+
+        if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+        {
+            let decoder = image::codecs::gif::GifDecoder::new(BufReader::new(File::open(path)?))?;
+            return Ok(decoder
+                .into_frames()
+                .collect_frames()?
+                .into_iter()
+                .map(|frame| image::DynamicImage::from(frame.into_buffer()).into_rgb8())
+                .collect());
+        }
+    }
+
+    Ok(vec![image::ImageReader::new(BufReader::new(File::open(
+        path,
+    )?))
+    .with_guessed_format()?
+    .decode()?
+    .into_rgb8()])
+}
+
+/// Read the frames of an image file, as a list of dicts with `imageWidth`, `imageHeight` and
+/// `imageData` keys
+///
+/// Frames are cropped by the given number of pixels on each side, and converted to grayscale if
+/// requested.
+#[pyfunction(signature = (source, crop_left = 0, crop_top = 0, crop_right = 0, crop_bottom = 0, grayscale = false))]
+#[pyo3(name = "getImage")]
+fn get_image<'py>(
+    py: Python<'py>,
+    source: &str,
+    crop_left: u32,
+    crop_top: u32,
+    crop_right: u32,
+    crop_bottom: u32,
+    grayscale: bool,
+) -> PyResult<Bound<'py, PyList>> {
+    let path = Context::resolve_path(source);
+    let frames = decode_frames(&path)
+        .map_err(|err| PyOSError::new_err(format!("cannot read {}: {}", path.display(), err)))?;
+
+    let result = PyList::empty(py);
+    for frame in frames {
+        let (width, height) = frame.dimensions();
+        let left = crop_left.min(width);
+        let top = crop_top.min(height);
+        let width = width - left - crop_right.min(width - left);
+        let height = height - top - crop_bottom.min(height - top);
+
+        let mut data = Vec::with_capacity(width as usize * height as usize * 3);
+        for y in top..top + height {
+            for x in left..left + width {
+                let [r, g, b] = frame.get_pixel(x, y).0;
+                if grayscale {
+                    let gray = ((r as u32 * 11 + g as u32 * 16 + b as u32 * 5) / 32) as u8;
+                    data.extend_from_slice(&[gray, gray, gray]);
+                } else {
+                    data.extend_from_slice(&[r, g, b]);
+                }
+            }
+        }
+
+        let item = PyDict::new(py);
+        item.set_item("imageWidth", width)?;
+        item.set_item("imageHeight", height)?;
+        item.set_item("imageData", PyByteArray::new(py, &data))?;
+        result.append(item)?;
+    }
+
+    Ok(result)
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(image_show, m)?)?;
+    m.add_function(wrap_pyfunction!(image_save, m)?)?;
+    m.add_function(wrap_pyfunction!(image_min_size, m)?)?;
+    m.add_function(wrap_pyfunction!(image_width, m)?)?;
+    m.add_function(wrap_pyfunction!(image_height, m)?)?;
+    m.add_function(wrap_pyfunction!(image_solid_fill, m)?)?;
+    m.add_function(wrap_pyfunction!(image_linear_gradient, m)?)?;
+    m.add_function(wrap_pyfunction!(image_conical_gradient, m)?)?;
+    m.add_function(wrap_pyfunction!(image_radial_gradient, m)?)?;
+    m.add_function(wrap_pyfunction!(image_draw_polygon, m)?)?;
+    m.add_function(wrap_pyfunction!(image_draw_pie, m)?)?;
+    m.add_function(wrap_pyfunction!(image_draw_line, m)?)?;
+    m.add_function(wrap_pyfunction!(image_draw_point, m)?)?;
+    m.add_function(wrap_pyfunction!(image_draw_rect, m)?)?;
+    m.add_function(wrap_pyfunction!(image_set_pixel, m)?)?;
+    m.add_function(wrap_pyfunction!(image_get_pixel, m)?)?;
+    m.add_function(wrap_pyfunction!(image_c_rotate, m)?)?;
+    m.add_function(wrap_pyfunction!(image_c_offset, m)?)?;
+    m.add_function(wrap_pyfunction!(image_c_shear, m)?)?;
+    m.add_function(wrap_pyfunction!(image_reset_t, m)?)?;
+    m.add_function(wrap_pyfunction!(get_image, m)?)?;
+
+    Ok(())
+}
@@ -11,7 +11,7 @@ use pyo3::{prelude::*, types::PyDict};
 use crate::{
     color::AnsiDisplayExt,
     effects::{providers::Provider, EffectDefinition},
-    image::RawImage,
+    image::{Image, RawImage},
     models::Color,
 };
 
@@ -22,7 +22,7 @@ fn run_string(
     args: serde_json::Value,
     methods: Arc<dyn RuntimeMethods>,
 ) -> Result<Py<PyDict>, PyErr> {
-    do_run(methods, args, |py| {
+    do_run(methods, args, None, |py| {
         let locals = pyo3::types::PyDict::new(py);
         let result = py.run(
             CString::new(source).unwrap().as_c_str(),
@@ -37,6 +37,8 @@ fn run_string(
 struct TestMethodData {
     abort: bool,
     leds: Vec<Color>,
+    state: Option<serde_json::Value>,
+    image: Option<RawImage>,
 }
 
 #[derive(Default, Clone)]
@@ -51,6 +53,8 @@ impl TestMethods {
         Self(Arc::new(Mutex::new(TestMethodData {
             abort: false,
             leds: vec![Color::default(); led_count],
+            state: None,
+            image: None,
         })))
     }
 
@@ -65,10 +69,30 @@ impl RuntimeMethods for TestMethods {
         self.0.lock().unwrap().leds.len()
     }
 
+    fn get_latch_time(&self) -> Duration {
+        Duration::from_millis(10)
+    }
+
+    fn get_image_size(&self) -> (u32, u32) {
+        (8, 4)
+    }
+
     async fn abort(&self) -> bool {
         self.0.lock().unwrap().abort
     }
 
+    async fn updated_args(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn restored_state(&self) -> Option<serde_json::Value> {
+        self.0.lock().unwrap().state.clone()
+    }
+
+    fn save_state(&self, state: serde_json::Value) {
+        self.0.lock().unwrap().state = Some(state);
+    }
+
     async fn set_color(&self, color: Color) -> Result<(), RuntimeMethodError> {
         eprintln!("set_color({:?})", color);
         Ok(())
@@ -88,7 +112,9 @@ impl RuntimeMethods for TestMethods {
 
     async fn set_image(&self, image: RawImage) -> Result<(), RuntimeMethodError> {
         eprintln!("set_image({:?})", image);
+        #[cfg(feature = "png")]
         image.write_to_kitty(&mut std::io::stderr()).unwrap();
+        self.0.lock().unwrap().image = Some(image);
         Ok(())
     }
 }
@@ -168,6 +194,104 @@ leds = hyperion.ledCount
     });
 }
 
+#[test]
+fn test_state() {
+    let tm = Arc::new(TestMethods::new());
+    let source = "import hyperion
+step = (hyperion.state or {'step': 0})['step'] + 1
+hyperion.saveState({'step': step})
+";
+
+    // The second run picks up the state saved by the first one
+    for expected in 1..=2 {
+        run_string(source, Default::default(), tm.clone()).expect("failed to run effect code");
+        assert_eq!(
+            tm.restored_state(),
+            Some(serde_json::json!({ "step": expected }))
+        );
+    }
+}
+
+#[test]
+fn test_image() {
+    let tm = Arc::new(TestMethods::new());
+
+    let result = run_string(
+        "import hyperion
+width, height = hyperion.imageMinSize(16, 8)
+hyperion.imageSolidFill(255, 0, 0)
+hyperion.imageDrawLine(0, 0, width - 1, 0, 1, 0, 0, 255)
+hyperion.imageCOffset(width, height)
+hyperion.imageCRotate(180)
+hyperion.imageSolidFill(0, 0, 2, 2, 0, 255, 0)
+hyperion.imageShow()
+pixel = hyperion.imageGetPixel(0, 0)
+interval = hyperion.lowestUpdateInterval()
+try:
+    hyperion.imageSolidFill(1, 2)
+    invalid = False
+except TypeError:
+    invalid = True
+",
+        Default::default(),
+        tm.clone(),
+    )
+    .expect("failed to run effect code");
+
+    let image = tm.0.lock().unwrap().image.take().expect("no image shown");
+    assert_eq!((image.width(), image.height()), (16, 8));
+    assert_eq!(image.color_at(3, 0), Some(Color::new(0, 0, 255)));
+    assert_eq!(image.color_at(3, 1), Some(Color::new(255, 0, 0)));
+    assert_eq!(image.color_at(14, 6), Some(Color::new(0, 255, 0)));
+
+    Python::attach(|py| {
+        let result = result.bind_borrowed(py);
+        let get = |name| result.get_item(name).unwrap().unwrap();
+        assert_eq!(get("pixel").extract::<(u8, u8, u8)>().unwrap(), (0, 0, 255));
+        assert_eq!(get("interval").extract::<f64>().unwrap(), 0.01);
+        assert!(get("invalid").extract::<bool>().unwrap());
+    });
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn test_get_image() {
+    let path = std::env::temp_dir().join(format!("hyperion-get-image-{}.png", std::process::id()));
+    image::RgbImage::from_raw(3, 1, vec![255, 0, 0, 0, 255, 0, 0, 0, 255])
+        .unwrap()
+        .save(&path)
+        .unwrap();
+
+    let result = run_string(
+        &format!(
+            "import hyperion
+frames = hyperion.getImage({:?}, 1)
+frame = frames[0]
+size = (len(frames), frame['imageWidth'], frame['imageHeight'])
+data = bytes(frame['imageData'])
+",
+            path.display().to_string()
+        ),
+        Default::default(),
+        Arc::new(TestMethods::new()),
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    Python::attach(|py| {
+        let result = result.expect("failed to run effect code");
+        let result = result.bind_borrowed(py);
+        let get = |name| result.get_item(name).unwrap().unwrap();
+        assert_eq!(
+            get("size").extract::<(usize, u32, u32)>().unwrap(),
+            (1, 2, 1)
+        );
+        assert_eq!(
+            get("data").extract::<Vec<u8>>().unwrap(),
+            [0, 255, 0, 0, 0, 255]
+        );
+    });
+}
+
 async fn run_effect(path: impl AsRef<Path>, duration: Duration) -> Result<(), String> {
     // Resolve effect definition path
     let path = crate::global::Paths::new(None)
@@ -231,27 +355,27 @@ macro_rules! test_effect {
 mod builtin {
     use super::run_effect;
 
-    test_effect!(#[ignore = "getImage missing"] test_effect_fire, "$SYSTEM/effects/fire.json");
-    test_effect!(#[ignore = "getImage missing"] test_effect_lights, "$SYSTEM/effects/lights.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_atomic, "$SYSTEM/effects/atomic.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_double_swirl, "$SYSTEM/effects/double-swirl.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_flag, "$SYSTEM/effects/flag.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_light_clock, "$SYSTEM/effects/light-clock.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_plasma, "$SYSTEM/effects/plasma.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_rainbow_swirl, "$SYSTEM/effects/rainbow-swirl.json");
-    test_effect!(#[ignore = "imageMinSize missing"] test_effect_rainbow_swirl_fast, "$SYSTEM/effects/rainbow-swirl-fast.json");
-    test_effect!(#[ignore = "imageWidth missing"] test_effect_seawaves, "$SYSTEM/effects/Seawaves.json");
-    test_effect!(#[ignore = "imageWidth missing"] test_effect_trails, "$SYSTEM/effects/trails.json");
-    test_effect!(#[ignore = "imageWidth missing"] test_effect_trails_color, "$SYSTEM/effects/trails_color.json");
-    test_effect!(#[ignore = "imageWidth missing"] test_effect_waves, "$SYSTEM/effects/waves.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_breath, "$SYSTEM/effects/breath.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_cinema_fade_in, "$SYSTEM/effects/cinema-fade-in.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_cinema_fade_off, "$SYSTEM/effects/cinema-fade-off.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_notify_blue, "$SYSTEM/effects/notify-blue.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_random, "$SYSTEM/effects/random.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_strobe_red, "$SYSTEM/effects/strobe-red.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_strobe_white, "$SYSTEM/effects/strobe-white.json");
-    test_effect!(#[ignore = "latchTime missing"] test_effect_traces, "$SYSTEM/effects/traces.json");
+    test_effect!(test_effect_fire, "$SYSTEM/effects/fire.json");
+    test_effect!(test_effect_lights, "$SYSTEM/effects/lights.json");
+    test_effect!(test_effect_atomic, "$SYSTEM/effects/atomic.json");
+    test_effect!(test_effect_double_swirl, "$SYSTEM/effects/double-swirl.json");
+    test_effect!(test_effect_flag, "$SYSTEM/effects/flag.json");
+    test_effect!(test_effect_light_clock, "$SYSTEM/effects/light-clock.json");
+    test_effect!(test_effect_plasma, "$SYSTEM/effects/plasma.json");
+    test_effect!(test_effect_rainbow_swirl, "$SYSTEM/effects/rainbow-swirl.json");
+    test_effect!(test_effect_rainbow_swirl_fast, "$SYSTEM/effects/rainbow-swirl-fast.json");
+    test_effect!(test_effect_seawaves, "$SYSTEM/effects/Seawaves.json");
+    test_effect!(test_effect_trails, "$SYSTEM/effects/trails.json");
+    test_effect!(test_effect_trails_color, "$SYSTEM/effects/trails_color.json");
+    test_effect!(test_effect_waves, "$SYSTEM/effects/waves.json");
+    test_effect!(test_effect_breath, "$SYSTEM/effects/breath.json");
+    test_effect!(test_effect_cinema_fade_in, "$SYSTEM/effects/cinema-fade-in.json");
+    test_effect!(test_effect_cinema_fade_off, "$SYSTEM/effects/cinema-fade-off.json");
+    test_effect!(test_effect_notify_blue, "$SYSTEM/effects/notify-blue.json");
+    test_effect!(test_effect_random, "$SYSTEM/effects/random.json");
+    test_effect!(test_effect_strobe_red, "$SYSTEM/effects/strobe-red.json");
+    test_effect!(test_effect_strobe_white, "$SYSTEM/effects/strobe-white.json");
+    test_effect!(test_effect_traces, "$SYSTEM/effects/traces.json");
     test_effect!(test_effect_candle, "$SYSTEM/effects/candle.json");
     test_effect!(test_effect_collision, "$SYSTEM/effects/collision.json");
     test_effect!(test_effect_knight_rider, "$SYSTEM/effects/knight-rider.json");
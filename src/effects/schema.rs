@@ -0,0 +1,297 @@
+//! JSON schemas of effect arguments
+//!
+//! hyperion.ng ships a `schema/<script>.schema.json` file next to its effect definitions, which
+//! the web UI uses to render the parameter forms of effects. Arguments sent to an effect are
+//! checked against the subset of JSON schema these files use: `type`, `enum`, numeric and length
+//! bounds, `items`, `properties` and `additionalProperties`. Omitted arguments are not an error,
+//! effects fall back to their defaults.
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use serde_json::Value;
+use thiserror::Error;
+use tokio::fs;
+
+/// Name of the directory holding the schemas, relative to the effect definitions
+pub const SCHEMA_DIR: &str = "schema";
+
+const SCHEMA_EXTENSION: &str = ".schema.json";
+
+/// Invalid effect argument
+#[derive(Debug, Error)]
+#[error("{path}: {message}")]
+pub struct SchemaError {
+    /// JSON pointer to the invalid value
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectSchema {
+    schema: Value,
+}
+
+impl EffectSchema {
+    pub fn new(schema: Value) -> Self {
+        Self { schema }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Read the schemas in `path`, keyed by the file stem of the script they describe
+    ///
+    /// Schemas name their script in their `script` property, or through their file name.
+    pub async fn read_dir(path: &Path) -> HashMap<String, Arc<Self>> {
+        let mut schemas = HashMap::new();
+
+        let mut read_dir = match fs::read_dir(path).await {
+            Ok(read_dir) => read_dir,
+            // Only hyperion.ng effects come with schemas
+            Err(_) => return schemas,
+        };
+
+        loop {
+            let path = match read_dir.next_entry().await {
+                Ok(Some(entry)) => entry.path(),
+                Ok(None) => break,
+                Err(err) => {
+                    error!(error = %err, "error reading effect schema directory");
+                    break;
+                }
+            };
+
+            let file_name = match path.file_name().and_then(std::ffi::OsStr::to_str) {
+                Some(name) if name.ends_with(SCHEMA_EXTENSION) => name,
+                _ => continue,
+            };
+
+            let schema: Value = match fs::read(&path)
+                .await
+                .map_err(|err| err.to_string())
+                .and_then(|json| serde_json::from_slice(&json).map_err(|err| err.to_string()))
+            {
+                Ok(schema) => schema,
+                Err(err) => {
+                    error!(path = %path.display(), error = %err, "error reading effect schema");
+                    continue;
+                }
+            };
+
+            let key = schema
+                .get("script")
+                .and_then(Value::as_str)
+                .and_then(script_key)
+                .unwrap_or_else(|| file_name.trim_end_matches(SCHEMA_EXTENSION).to_owned());
+
+            schemas.insert(key, Arc::new(Self::new(schema)));
+        }
+
+        schemas
+    }
+
+    /// Check effect arguments against this schema
+    pub fn validate(&self, args: &Value) -> Result<(), SchemaError> {
+        check(&self.schema, args, "")
+    }
+}
+
+/// Key of the schema of `script` in the result of [EffectSchema::read_dir]
+pub fn script_key(script: &str) -> Option<String> {
+    // Built-in hyperion.ng effects refer to their script as a Qt resource
+    Path::new(script.trim_start_matches(':'))
+        .file_stem()
+        .and_then(std::ffi::OsStr::to_str)
+        .map(ToOwned::to_owned)
+}
+
+fn check(schema: &Value, value: &Value, path: &str) -> Result<(), SchemaError> {
+    let error = |message: String| {
+        Err(SchemaError {
+            path: if path.is_empty() {
+                "/".to_owned()
+            } else {
+                path.to_owned()
+            },
+            message,
+        })
+    };
+
+    if let Some(ty) = schema.get("type") {
+        let matches = match ty {
+            Value::String(ty) => has_type(value, ty),
+            Value::Array(types) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|ty| has_type(value, ty)),
+            _ => true,
+        };
+
+        if !matches {
+            return error(format!("expected {}, got {}", ty, value));
+        }
+    }
+
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            return error(format!(
+                "{} is not one of {}",
+                value,
+                Value::from(values.clone())
+            ));
+        }
+    }
+
+    match value {
+        Value::Number(number) => {
+            // ok: all JSON numbers convert to f64, possibly rounded
+            let number = number.as_f64().unwrap_or_default();
+
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+                if number < minimum {
+                    return error(format!("{} is less than the minimum {}", value, minimum));
+                }
+            }
+
+            if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+                if number > maximum {
+                    return error(format!("{} is greater than the maximum {}", value, maximum));
+                }
+            }
+        }
+
+        Value::String(string) => {
+            let len = string.chars().count() as u64;
+
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if len < min {
+                    return error(format!("expected at least {} characters", min));
+                }
+            }
+
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if len > max {
+                    return error(format!("expected at most {} characters", max));
+                }
+            }
+        }
+
+        Value::Array(items) => {
+            let len = items.len() as u64;
+
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if len < min {
+                    return error(format!("expected at least {} items", min));
+                }
+            }
+
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if len > max {
+                    return error(format!("expected at most {} items", max));
+                }
+            }
+
+            match schema.get("items") {
+                Some(Value::Array(schemas)) => {
+                    for (i, (schema, item)) in schemas.iter().zip(items).enumerate() {
+                        check(schema, item, &format!("{}/{}", path, i))?;
+                    }
+                }
+                Some(schema) => {
+                    for (i, item) in items.iter().enumerate() {
+                        check(schema, item, &format!("{}/{}", path, i))?;
+                    }
+                }
+                None => {}
+            }
+        }
+
+        Value::Object(members) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+
+            for (name, member) in members {
+                let member_path = format!("{}/{}", path, name);
+
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(schema) => check(schema, member, &member_path)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return error(format!("unknown property {:?}", name));
+                        }
+                        Some(schema @ Value::Object(_)) => check(schema, member, &member_path)?,
+                        _ => {}
+                    },
+                }
+            }
+        }
+
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64()
+                || value.is_u64()
+                || value.as_f64().map(|n| n.fract() == 0.).unwrap_or(false)
+        }
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        // Unknown types are left to the effect
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let schema = EffectSchema::new(json!({
+            "type": "object",
+            "script": "candle.py",
+            "properties": {
+                "speed": { "type": "number", "minimum": 0.1, "maximum": 10 },
+                "color": {
+                    "type": "array",
+                    "items": { "type": "integer", "minimum": 0, "maximum": 255 },
+                    "minItems": 3,
+                    "maxItems": 3
+                },
+                "mode": { "type": "string", "enum": ["fast", "slow"] }
+            },
+            "additionalProperties": false
+        }));
+
+        assert!(schema.validate(&json!({})).is_ok());
+        assert!(schema
+            .validate(&json!({"speed": 2, "color": [255, 0, 0], "mode": "fast"}))
+            .is_ok());
+
+        let path = |args| schema.validate(&args).unwrap_err().path;
+        assert_eq!(path(json!({"speed": 0})), "/speed");
+        assert_eq!(path(json!({"speed": "fast"})), "/speed");
+        assert_eq!(path(json!({"color": [255, 0]})), "/color");
+        assert_eq!(path(json!({"color": [255, 0, 256]})), "/color/2");
+        assert_eq!(path(json!({"color": [255, 0.5, 0]})), "/color/1");
+        assert_eq!(path(json!({"mode": "medium"})), "/mode");
+        assert_eq!(path(json!({"brightness": 1})), "/");
+        assert_eq!(path(json!([])), "/");
+    }
+
+    #[test]
+    fn test_script_key() {
+        assert_eq!(script_key(":/effects/candle.py").unwrap(), "candle");
+        assert_eq!(script_key("knight-rider.py").unwrap(), "knight-rider");
+    }
+}
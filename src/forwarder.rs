@@ -0,0 +1,304 @@
+//! Forwarding of the inputs to other hyperion servers
+//!
+//! Colors, images and clears sent to all instances (e.g. by the JSON, flatbuffers and protobuf
+//! servers) are sent again to each JSON and flatbuffers target. Inputs sent to a single instance,
+//! LED colors and effects aren't forwarded. Flatbuffers clients register a single priority, so
+//! flatbuffers targets are best used with a single forwarded priority in `[100, 200)`.
+//!
+//! Forwarding follows the `FORWARDER` component of the first instance: disabling it disconnects
+//! the targets, which drop the forwarded inputs until it is enabled again.
+
+use std::{ops::Range, sync::Arc};
+
+use bytes::{Bytes, BytesMut};
+use parse_display::Display;
+use serde_derive::Serialize;
+use tokio::sync::broadcast;
+use tokio_util::codec::{Encoder as _, LengthDelimitedCodec};
+
+mod target;
+pub use target::*;
+
+use crate::{
+    client,
+    component::ComponentName,
+    global::{
+        Event, Global, InputMessage, InputMessageData, InstanceEvent, InstanceEventKind, Message,
+    },
+    models,
+    servers::json::JsonCodec,
+};
+
+/// Origin of the forwarded inputs
+const ORIGIN: &str = "hyperion.rs";
+
+/// Priorities flatbuffers clients can register
+const FLAT_PRIORITIES: Range<i32> = 100..200;
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetProtocol {
+    #[display("JSON")]
+    Json,
+    #[display("flatbuffers")]
+    Flat,
+}
+
+/// Downstream server the inputs are forwarded to
+#[derive(Debug)]
+pub struct ForwarderTarget {
+    protocol: TargetProtocol,
+    link: TargetLink,
+}
+
+impl ForwarderTarget {
+    pub fn protocol(&self) -> TargetProtocol {
+        self.protocol
+    }
+
+    pub fn link(&self) -> &TargetLink {
+        &self.link
+    }
+}
+
+/// Start forwarding the inputs to the configured targets
+///
+/// Targets are connected in the background, until hyperion stops.
+pub async fn start(config: models::Forwarder, global: Global) {
+    let targets: Vec<_> = config
+        .json
+        .into_iter()
+        .map(|address| (TargetProtocol::Json, address))
+        .chain(
+            config
+                .flat
+                .into_iter()
+                .map(|address| (TargetProtocol::Flat, address)),
+        )
+        .map(|(protocol, address)| {
+            info!(target = %address, protocol = %protocol, "forwarding inputs");
+
+            ForwarderTarget {
+                protocol,
+                link: TargetLink::new(address, None),
+            }
+        })
+        .collect();
+
+    let targets = Arc::new(targets);
+    global.set_forwarder_targets(targets.clone()).await;
+
+    let input = global.subscribe_input().await;
+    let events = global.subscribe_events().await;
+
+    tokio::spawn(
+        Forwarder {
+            global,
+            targets,
+            encoder: Default::default(),
+            enabled: true,
+        }
+        .run(input, events),
+    );
+}
+
+/// Encoding of the forwarded inputs, as written on the wire
+struct RequestEncoder {
+    builder: flatbuffers::FlatBufferBuilder<'static>,
+    json: JsonCodec<serde_json::Value>,
+    flat: LengthDelimitedCodec,
+}
+
+impl Default for RequestEncoder {
+    fn default() -> Self {
+        Self {
+            builder: flatbuffers::FlatBufferBuilder::new(),
+            json: JsonCodec::new(),
+            flat: LengthDelimitedCodec::builder()
+                .length_field_length(4)
+                .new_codec(),
+        }
+    }
+}
+
+impl RequestEncoder {
+    /// Encode an input for a target, if it can be forwarded to it
+    fn encode(&mut self, protocol: TargetProtocol, data: &InputMessageData) -> Option<Bytes> {
+        let duration = data.duration().and_then(|duration| duration.to_std().ok());
+
+        match protocol {
+            TargetProtocol::Json => {
+                let request = match data {
+                    InputMessageData::ClearAll => client::json::clear_all_request(),
+                    InputMessageData::Clear { priority } => client::json::clear_request(*priority),
+                    InputMessageData::SolidColor {
+                        priority, color, ..
+                    } => client::json::color_request(*priority, *color, duration, ORIGIN),
+                    InputMessageData::Image {
+                        priority, image, ..
+                    } => client::json::image_request(*priority, image, duration, ORIGIN),
+                    InputMessageData::LedColors { .. } | InputMessageData::Effect { .. } => {
+                        return None
+                    }
+                };
+
+                let mut buf = BytesMut::new();
+                // unwrap: JSON values can always be encoded
+                self.json.encode(request, &mut buf).unwrap();
+                Some(buf.freeze())
+            }
+
+            TargetProtocol::Flat => {
+                let priority = data.priority().unwrap_or(FLAT_PRIORITIES.start);
+                if !FLAT_PRIORITIES.contains(&priority) {
+                    return None;
+                }
+
+                let builder = &mut self.builder;
+                let request = match data {
+                    InputMessageData::ClearAll => client::flat::clear_request(builder, -1),
+                    InputMessageData::Clear { priority } => {
+                        client::flat::clear_request(builder, *priority)
+                    }
+                    InputMessageData::SolidColor { color, .. } => {
+                        client::flat::color_request(builder, *color, duration)
+                    }
+                    InputMessageData::Image { image, .. } => {
+                        client::flat::image_request(builder, image, duration)
+                    }
+                    InputMessageData::LedColors { .. } | InputMessageData::Effect { .. } => {
+                        return None
+                    }
+                };
+
+                // Inputs of different priorities are written in any order, so each one registers
+                let register = client::flat::register_request(builder, ORIGIN, priority);
+
+                let mut buf = BytesMut::new();
+                for frame in [register, request] {
+                    // Images over the frame length limit would be rejected by the target
+                    self.flat.encode(frame, &mut buf).ok()?;
+                }
+                Some(buf.freeze())
+            }
+        }
+    }
+}
+
+struct Forwarder {
+    global: Global,
+    targets: Arc<Vec<ForwarderTarget>>,
+    encoder: RequestEncoder,
+    enabled: bool,
+}
+
+impl Forwarder {
+    async fn run(
+        mut self,
+        mut input: broadcast::Receiver<InputMessage>,
+        mut events: broadcast::Receiver<Event>,
+    ) {
+        loop {
+            tokio::select! {
+                message = input.recv() => match message {
+                    Ok(message) => self.forward(message.data()),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!(skipped = %skipped, "forwarder lagging behind inputs");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                event = events.recv() => match event {
+                    Ok(Event::Instance(InstanceEvent {
+                        kind:
+                            InstanceEventKind::Start
+                            | InstanceEventKind::Stop
+                            | InstanceEventKind::ComponentStateChanged {
+                                component: ComponentName::Forwarder,
+                                ..
+                            },
+                        ..
+                    })) => self.update_enabled().await,
+                    Ok(Event::Stop) | Err(broadcast::error::RecvError::Closed) => break,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                },
+            }
+        }
+
+        // Dropping the links disconnects the targets
+        self.global.set_forwarder_targets(Default::default()).await;
+    }
+
+    fn forward(&mut self, data: &InputMessageData) {
+        for target in self.targets.iter() {
+            let payload = match self.encoder.encode(target.protocol, data) {
+                Some(payload) => payload,
+                None => continue,
+            };
+
+            match data {
+                InputMessageData::ClearAll => target.link.clear(None, payload),
+                InputMessageData::Clear { priority } => target.link.clear(Some(*priority), payload),
+                _ => {
+                    if let Some(priority) = data.priority() {
+                        target.link.set(priority, payload);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn update_enabled(&mut self) {
+        let enabled = match self.global.default_instance().await {
+            Some((id, _)) => self
+                .global
+                .component_states()
+                .await
+                .is_enabled(id, ComponentName::Forwarder),
+            None => true,
+        };
+
+        if enabled != self.enabled {
+            info!(enabled = %enabled, "forwarder state changed");
+
+            self.enabled = enabled;
+            for target in self.targets.iter() {
+                target.link.set_enabled(enabled);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    #[test]
+    fn test_encode_requests() {
+        let mut encoder = RequestEncoder::default();
+        let color = InputMessageData::SolidColor {
+            priority: 150,
+            duration: None,
+            color: models::Color::new(255, 0, 0),
+        };
+
+        let json = encoder.encode(TargetProtocol::Json, &color).unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(request["command"], "color");
+        assert_eq!(request["color"], serde_json::json!([255, 0, 0]));
+        assert!(json.ends_with(b"\n"));
+
+        // Register, then color frames
+        let flat = encoder.encode(TargetProtocol::Flat, &color).unwrap();
+        let register_len = u32::from_be_bytes(flat[..4].try_into().unwrap()) as usize;
+        let color_len =
+            u32::from_be_bytes(flat[4 + register_len..8 + register_len].try_into().unwrap());
+        assert_eq!(flat.len(), 8 + register_len + color_len as usize);
+
+        // Flatbuffers clients can't register this priority
+        let clear = InputMessageData::Clear { priority: 250 };
+        assert!(encoder.encode(TargetProtocol::Json, &clear).is_some());
+        assert!(encoder.encode(TargetProtocol::Flat, &clear).is_none());
+    }
+}
@@ -0,0 +1,349 @@
+//! Connection to a downstream server, surviving its outages
+//!
+//! Inputs aren't queued: the link keeps the latest payload of each priority, and sends the ones
+//! which changed since its last write. A slow target only misses intermediate frames, and a
+//! target which comes back gets the current state of each priority instead of resuming black.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Notify,
+};
+use tokio_util::sync::CancellationToken;
+
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Time to wait for a target to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health of a forwarding target
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetHealth {
+    pub connected: bool,
+    /// Failed connection attempts since the last successful one
+    pub failed_attempts: u32,
+    /// Error of the last connection attempt, or which closed the last connection
+    pub last_error: Option<String>,
+    /// Number of payloads written to the target
+    pub sent: u64,
+}
+
+#[derive(Default)]
+struct State {
+    /// Latest payload of each priority
+    latest: BTreeMap<i32, Bytes>,
+    /// Priorities which changed since they were last written
+    dirty: BTreeSet<i32>,
+    /// Clears not written yet, which come before the dirty priorities
+    clears: Vec<Bytes>,
+    /// Disabled links stay disconnected, but still track the latest state
+    enabled: bool,
+    health: TargetHealth,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+/// Link to a forwarding target, reconnecting with exponential backoff until dropped
+pub struct TargetLink {
+    address: String,
+    shared: Arc<Shared>,
+    stop: CancellationToken,
+}
+
+impl TargetLink {
+    /// Start connecting to `address`
+    ///
+    /// `handshake` is written first on each new connection, e.g. to register with the target.
+    pub fn new(address: String, handshake: Option<Bytes>) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                enabled: true,
+                ..Default::default()
+            }),
+            notify: Notify::new(),
+        });
+        let stop = CancellationToken::new();
+
+        tokio::spawn(
+            Connection {
+                address: address.clone(),
+                handshake,
+                shared: shared.clone(),
+            }
+            .run(stop.clone()),
+        );
+
+        Self {
+            address,
+            shared,
+            stop,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn health(&self) -> TargetHealth {
+        self.shared.state.lock().unwrap().health.clone()
+    }
+
+    /// Connect to the target, or disconnect from it so it drops the forwarded inputs
+    ///
+    /// The latest state is sent again once enabled.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shared.state.lock().unwrap().enabled = enabled;
+        self.shared.notify.notify_one();
+    }
+
+    /// Set the payload of a priority, replacing the previous one
+    pub fn set(&self, priority: i32, payload: Bytes) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.latest.insert(priority, payload);
+        state.dirty.insert(priority);
+        drop(state);
+
+        self.shared.notify.notify_one();
+    }
+
+    /// Clear a priority, or all of them if `priority` is `None`, with the given payload
+    pub fn clear(&self, priority: Option<i32>, payload: Bytes) {
+        let mut state = self.shared.state.lock().unwrap();
+        match priority {
+            Some(priority) => {
+                state.latest.remove(&priority);
+            }
+            None => state.latest.clear(),
+        }
+        state.clears.push(payload);
+        drop(state);
+
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Drop for TargetLink {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+impl std::fmt::Debug for TargetLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TargetLink")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+struct Connection {
+    address: String,
+    handshake: Option<Bytes>,
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    async fn run(self, stop: CancellationToken) {
+        let mut delay = RECONNECT_MIN_DELAY;
+
+        loop {
+            while !self.shared.state.lock().unwrap().enabled {
+                delay = RECONNECT_MIN_DELAY;
+
+                tokio::select! {
+                    _ = self.shared.notify.notified() => {}
+                    _ = stop.cancelled() => return,
+                }
+            }
+
+            let result = tokio::select! {
+                result = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.address)) => {
+                    result.unwrap_or_else(|_| {
+                        Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "connection timed out"))
+                    })
+                }
+                _ = stop.cancelled() => break,
+            };
+
+            match result {
+                Ok(stream) => {
+                    delay = RECONNECT_MIN_DELAY;
+                    info!(target = %self.address, "connected to forwarding target");
+
+                    let result = tokio::select! {
+                        result = self.forward(stream) => result,
+                        _ = stop.cancelled() => break,
+                    };
+
+                    self.update_health(|health| health.connected = false);
+
+                    match result {
+                        Ok(()) => {
+                            info!(target = %self.address, "disconnected from forwarding target");
+                            continue;
+                        }
+                        Err(error) => {
+                            warn!(target = %self.address, error = %error, "forwarding target disconnected");
+                            self.update_health(|health| {
+                                health.last_error = Some(error.to_string())
+                            });
+                        }
+                    }
+                }
+                Err(error) => {
+                    let attempts = self.update_health(|health| {
+                        health.failed_attempts += 1;
+                        health.last_error = Some(error.to_string());
+                        health.failed_attempts
+                    });
+
+                    // Only log the first failure, the target may be down for a while
+                    if attempts == 1 {
+                        warn!(target = %self.address, error = %error, "cannot connect to forwarding target");
+                    } else {
+                        debug!(target = %self.address, error = %error, attempts = %attempts, "cannot connect to forwarding target");
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = stop.cancelled() => break,
+            }
+
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        }
+    }
+
+    fn update_health<T>(&self, f: impl FnOnce(&mut TargetHealth) -> T) -> T {
+        f(&mut self.shared.state.lock().unwrap().health)
+    }
+
+    /// Write the state to a new connection, then its changes until it fails or the link is
+    /// disabled
+    async fn forward(&self, mut stream: TcpStream) -> Result<(), std::io::Error> {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            // The target dropped our inputs with the previous connection, resend all of them
+            state.clears.clear();
+            state.dirty = state.latest.keys().copied().collect();
+            state.health.connected = true;
+            state.health.failed_attempts = 0;
+            state.health.last_error = None;
+        }
+
+        if let Some(handshake) = &self.handshake {
+            stream.write_all(handshake).await?;
+        }
+
+        // Replies of the target are not used, but have to be read so it doesn't block
+        let mut buf = vec![0u8; 4096];
+
+        loop {
+            let payloads: Vec<Bytes> = {
+                let mut state = self.shared.state.lock().unwrap();
+                let State {
+                    latest,
+                    dirty,
+                    clears,
+                    enabled,
+                    ..
+                } = &mut *state;
+
+                if !*enabled {
+                    return Ok(());
+                }
+
+                clears
+                    .drain(..)
+                    .chain(
+                        std::mem::take(dirty)
+                            .into_iter()
+                            .filter_map(|priority| latest.get(&priority).cloned()),
+                    )
+                    .collect()
+            };
+
+            for payload in &payloads {
+                stream.write_all(payload).await?;
+            }
+
+            if !payloads.is_empty() {
+                self.update_health(|health| health.sent += payloads.len() as u64);
+            }
+
+            tokio::select! {
+                _ = self.shared.notify.notified() => {}
+                result = stream.read(&mut buf) => {
+                    if result? == 0 {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "connection closed by the target",
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    async fn read_exact(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_target_link_resends_latest_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let link = TargetLink::new(
+            listener.local_addr().unwrap().to_string(),
+            Some(Bytes::from_static(b"H")),
+        );
+
+        link.set(100, Bytes::from_static(b"a"));
+        let (mut stream, _) = listener.accept().await.unwrap();
+        assert_eq!(read_exact(&mut stream, 2).await, b"Ha");
+
+        link.clear(Some(100), Bytes::from_static(b"c"));
+        link.set(150, Bytes::from_static(b"b"));
+        assert_eq!(read_exact(&mut stream, 2).await, b"cb");
+        assert!(link.health().connected);
+
+        // Changes while the target is down are replayed, without the clears
+        drop(stream);
+        link.set(150, Bytes::from_static(b"d"));
+        link.clear(Some(100), Bytes::from_static(b"c"));
+        link.set(200, Bytes::from_static(b"e"));
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        assert_eq!(read_exact(&mut stream, 3).await, b"Hde");
+        assert!(link.health().connected);
+        assert_eq!(link.health().last_error, None);
+
+        // Disabling the link closes the connection, enabling it replays the state
+        link.set_enabled(false);
+        assert_eq!(stream.read(&mut [0u8; 1]).await.unwrap(), 0);
+        link.set_enabled(true);
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        assert_eq!(read_exact(&mut stream, 3).await, b"Hde");
+    }
+}
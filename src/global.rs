@@ -1,10 +1,29 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use futures::{future::BoxFuture, FutureExt};
 use parse_display::Display;
 use tokio::sync::broadcast;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, Notify, RwLock};
+use tokio::task::JoinHandle;
+
+mod auth;
+pub use auth::*;
+
+mod client_registry;
+pub use client_registry::*;
+
+mod command_trace;
+pub use command_trace::*;
+
+mod component_states;
+pub use component_states::*;
+
+mod environment;
+pub use environment::*;
 
 mod event;
 pub use event::*;
@@ -18,16 +37,31 @@ pub use input_message::*;
 mod input_source;
 pub use input_source::*;
 
+mod log_buffer;
+pub use log_buffer::*;
+
 mod paths;
 pub use paths::*;
 
 mod priority_guard;
 pub use priority_guard::*;
 
+mod watchdog;
+pub use watchdog::*;
+
 use crate::{
-    component::ComponentName, effects::EffectRegistry, instance::InstanceHandle, models::Config,
+    component::ComponentName,
+    effects::EffectRegistry,
+    forwarder::ForwarderTarget,
+    grabber::screen::{ScreenCapture, ScreenCaptureError},
+    instance::{InstanceHandle, InstanceHandleError},
+    models::{ColorAdjustment, Config, InstanceConfig},
+    servers::{ServerError, ServerFailure, ServerHandle, ServerId},
 };
 
+#[cfg(feature = "audio")]
+use crate::grabber::audio::AudioCapture;
+
 pub trait Message: Sized {
     type Data;
 
@@ -64,6 +98,26 @@ pub enum InputSourceName {
     PriorityMuxer,
     #[display("Effect({name})")]
     Effect { name: String },
+    #[display("NDI({source})")]
+    Ndi { source: String },
+    #[display("MediaArtwork")]
+    MediaArtwork,
+    #[display("DataPresets({url})")]
+    DataPresets { url: String },
+    #[display("SystemGrabber({name})")]
+    SystemGrabber { name: String },
+    #[display("V4L({device})")]
+    V4L { device: String },
+    #[display("Audio({device})")]
+    Audio { device: String },
+    #[display("Api({name})")]
+    Api { name: String },
+    #[display("MQTT")]
+    Mqtt,
+    #[display("InstanceMirror({source})")]
+    InstanceMirror { source: i32 },
+    #[display("UdpListener({port})")]
+    UdpListener { port: u16 },
 }
 
 impl InputSourceName {
@@ -73,6 +127,14 @@ impl InputSourceName {
             InputSourceName::FlatBuffers { .. } => ComponentName::FlatbufServer,
             InputSourceName::Protobuf { .. } => ComponentName::ProtoServer,
             InputSourceName::Effect { .. } => ComponentName::Effect,
+            InputSourceName::Ndi { .. } => ComponentName::NdiReceiver,
+            InputSourceName::MediaArtwork => ComponentName::MediaArtwork,
+            InputSourceName::DataPresets { .. } => ComponentName::DataPresets,
+            InputSourceName::SystemGrabber { .. } => ComponentName::SystemGrabber,
+            InputSourceName::V4L { .. } => ComponentName::V4LGrabber,
+            InputSourceName::Audio { .. } => ComponentName::AudioGrabber,
+            InputSourceName::InstanceMirror { .. } => ComponentName::InstanceMirror,
+            InputSourceName::UdpListener { .. } => ComponentName::UdpListener,
             _ => ComponentName::All,
         }
     }
@@ -106,10 +168,20 @@ impl Global {
     }
 
     pub async fn register_instance(&self, handle: InstanceHandle) {
+        let id = handle.id();
         self.0.write().await.register_instance(handle);
+
+        // The instance servers forward their inputs to the previous handle of restarted instances
+        self.restart_server(ServerId::Boblight { instance: id })
+            .await;
+        self.restart_server(ServerId::UdpListener { instance: id })
+            .await;
     }
 
     pub async fn unregister_instance(&self, id: i32) {
+        self.stop_server(ServerId::Boblight { instance: id }).await;
+        self.stop_server(ServerId::UdpListener { instance: id })
+            .await;
         self.0.write().await.unregister_instance(id);
     }
 
@@ -117,6 +189,17 @@ impl Global {
         self.0.read().await.instances.get(&id).cloned()
     }
 
+    /// Handles to all running instances, ordered by id
+    pub async fn instances(&self) -> Vec<(i32, InstanceHandle)> {
+        self.0
+            .read()
+            .await
+            .instances
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect()
+    }
+
     pub async fn default_instance(&self) -> Option<(i32, InstanceHandle)> {
         self.0
             .read()
@@ -132,6 +215,310 @@ impl Global {
         f(&data.config)
     }
 
+    /// Lock held while the configuration is modified and applied
+    ///
+    /// The configuration must be read after taking the lock, so concurrent updates don't
+    /// overwrite each other's changes.
+    pub async fn lock_config_update(&self) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = self.0.read().await.config_update.clone();
+        lock.lock_owned().await
+    }
+
+    /// Replace the configuration, restarting what is needed to apply it
+    ///
+    /// Instances and servers whose settings changed are restarted. Network settings are read when
+    /// clients connect, so they apply right away. Other global settings and added or removed
+    /// instances need all components to be restarted.
+    pub async fn apply_config(&self, config: Config) -> Result<(), InstanceHandleError> {
+        let previous = std::mem::replace(&mut self.0.write().await.config, config.clone());
+
+        let mut previous_global = previous.global.clone();
+        previous_global.network = config.global.network.clone();
+
+        previous_global.flatbuffers_server = config.global.flatbuffers_server.clone();
+        previous_global.proto_server = config.global.proto_server.clone();
+
+        if previous_global != config.global
+            || !previous.instances.keys().eq(config.instances.keys())
+        {
+            info!("configuration changed, restarting all components");
+            self.request_reload().await;
+            return Ok(());
+        }
+
+        if previous.global.flatbuffers_server != config.global.flatbuffers_server {
+            self.restart_server(ServerId::Flatbuffers).await;
+        }
+
+        if previous.global.proto_server != config.global.proto_server {
+            self.restart_server(ServerId::Protobuf).await;
+        }
+
+        for (&id, instance) in &config.instances {
+            if previous.instances.get(&id) != Some(instance) {
+                self.restart_instance(id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replace the color adjustments of an instance
+    ///
+    /// Unlike [Global::apply_config], the instance is updated while it runs.
+    pub async fn set_color_adjustment(
+        &self,
+        id: i32,
+        color: ColorAdjustment,
+    ) -> Result<(), InstanceHandleError> {
+        let handle = {
+            let mut data = self.0.write().await;
+            if let Some(instance) = data.config.instances.get_mut(&id) {
+                instance.color = color.clone();
+            }
+
+            data.instances.get(&id).cloned()
+        };
+
+        if let Some(handle) = handle {
+            handle.set_color_adjustment(color).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Restart an instance, using its current configuration
+    pub async fn restart_instance(&self, id: i32) -> Result<(), InstanceHandleError> {
+        let handle = {
+            let mut data = self.0.write().await;
+            let handle = data.instances.get(&id).cloned();
+
+            // Instances which aren't running have nothing to restart
+            if handle.is_some() {
+                data.restarting.insert(id);
+            }
+
+            handle
+        };
+
+        if let Some(handle) = handle {
+            info!(id = %id, name = %handle.name(), "restarting instance");
+            handle.stop().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check if a stopped instance should be started again
+    ///
+    /// # Returns
+    ///
+    /// The configuration to restart the instance with, if [Global::restart_instance] was called
+    /// for it.
+    pub async fn take_instance_restart(&self, id: i32) -> Option<InstanceConfig> {
+        let mut data = self.0.write().await;
+
+        if data.restarting.remove(&id) {
+            data.config.instances.get(&id).cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Start a server, unless it is already running
+    ///
+    /// Servers which can't be bound, e.g. because their port is used by another program, are
+    /// retried in the background with an increasing delay until they start or are stopped. The
+    /// future is boxed, since API clients of the servers started here can start servers.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the server was started.
+    pub fn start_server(&self, id: ServerId) -> BoxFuture<'_, Result<bool, ServerError>> {
+        async move {
+            let mut data = self.0.write().await;
+            if data.servers.contains_key(&id) {
+                return Ok(false);
+            }
+
+            let instance = match id {
+                ServerId::Boblight { instance } | ServerId::UdpListener { instance } => {
+                    data.instances.get(&instance).cloned()
+                }
+                _ => None,
+            };
+
+            match id
+                .bind(&data.config, &data.paths, self.clone(), instance)
+                .await
+            {
+                Ok(handle) => {
+                    if let Some(retry) = data.bind_retries.remove(&id) {
+                        info!(server = %id, attempts = %retry.failure.attempts, "server started after failing to bind");
+                        retry.task.abort();
+                    }
+
+                    data.servers.insert(id, handle);
+                    Ok(true)
+                }
+                Err(error @ ServerError::NoInstance(_)) => Err(error),
+                Err(error) => {
+                    if let Some(retry) = data.bind_retries.get_mut(&id) {
+                        retry.failure.attempts += 1;
+                        retry.failure.error = error.to_string();
+                        warn!(server = %id, error = %error, attempts = %retry.failure.attempts, "cannot start server");
+                    } else {
+                        error!(server = %id, error = %error, "cannot start server, retrying in the background");
+
+                        let failure = ServerFailure {
+                            port: id.port(&data.config),
+                            error: error.to_string(),
+                            attempts: 1,
+                        };
+                        let task = tokio::spawn(self.clone().retry_server(id));
+                        data.bind_retries.insert(id, BindRetry { failure, task });
+                    }
+
+                    Err(error)
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Try to start a server until it succeeds
+    fn retry_server(self, id: ServerId) -> BoxFuture<'static, ()> {
+        async move {
+            let mut delay = BIND_RETRY_MIN_DELAY;
+
+            loop {
+                tokio::time::sleep(delay).await;
+
+                if self.start_server(id).await.is_ok() {
+                    break;
+                }
+
+                delay = (delay * 2).min(BIND_RETRY_MAX_DELAY);
+            }
+        }
+        .boxed()
+    }
+
+    /// Stop a server, and wait for its port to be released and its clients to disconnect
+    ///
+    /// # Returns
+    ///
+    /// `true` if the server was running, or waiting to be started again.
+    pub async fn stop_server(&self, id: ServerId) -> bool {
+        let (handle, retry) = {
+            let mut data = self.0.write().await;
+            (data.servers.remove(&id), data.bind_retries.remove(&id))
+        };
+
+        if let Some(retry) = &retry {
+            retry.task.abort();
+        }
+
+        if let Some(handle) = handle {
+            handle.shutdown().await;
+            info!(server = %id, "server stopped");
+            true
+        } else {
+            retry.is_some()
+        }
+    }
+
+    /// Stop all the servers started through [Global::start_server]
+    pub async fn stop_servers(&self) {
+        let (servers, retries) = {
+            let mut data = self.0.write().await;
+            (
+                std::mem::take(&mut data.servers),
+                std::mem::take(&mut data.bind_retries),
+            )
+        };
+
+        for retry in retries.into_values() {
+            retry.task.abort();
+        }
+
+        for handle in servers.into_values() {
+            handle.shutdown().await;
+        }
+    }
+
+    /// Stop a server, and start it again if it is enabled in the current configuration
+    async fn restart_server(&self, id: ServerId) {
+        self.stop_server(id).await;
+
+        if self.read_config(|config| id.is_enabled(config)).await {
+            // ok: failures are logged and retried
+            self.start_server(id).await.ok();
+        }
+    }
+
+    /// Running servers and the port they are listening on, ordered by id
+    pub async fn servers(&self) -> Vec<(ServerId, u16)> {
+        self.0
+            .read()
+            .await
+            .servers
+            .iter()
+            .map(|(id, handle)| (*id, handle.port()))
+            .collect()
+    }
+
+    /// Servers which could not be started, and are retried in the background
+    pub async fn server_failures(&self) -> Vec<(ServerId, ServerFailure)> {
+        self.0
+            .read()
+            .await
+            .bind_retries
+            .iter()
+            .map(|(id, retry)| (*id, retry.failure.clone()))
+            .collect()
+    }
+
+    /// Targets of the forwarder, empty unless it is enabled
+    pub async fn forwarder_targets(&self) -> Arc<Vec<ForwarderTarget>> {
+        self.0.read().await.forwarder_targets.clone()
+    }
+
+    pub(crate) async fn set_forwarder_targets(&self, targets: Arc<Vec<ForwarderTarget>>) {
+        self.0.write().await.forwarder_targets = targets;
+    }
+
+    /// Get the running audio capture, starting it if no instance is using it
+    #[cfg(feature = "audio")]
+    pub async fn audio_capture(&self) -> Arc<AudioCapture> {
+        let mut data = self.0.write().await;
+        if let Some(capture) = data.audio_capture.upgrade() {
+            return capture;
+        }
+
+        let capture = Arc::new(AudioCapture::start(&data.config.global.grabber_audio));
+        data.audio_capture = Arc::downgrade(&capture);
+        capture
+    }
+
+    /// Get the running screen capture, starting it if no instance is using it
+    ///
+    /// Returns `None` if no screen grabber is configured or detected.
+    pub async fn screen_capture(&self) -> Result<Option<Arc<ScreenCapture>>, ScreenCaptureError> {
+        let mut data = self.0.write().await;
+        if let Some(capture) = data.screen_capture.upgrade() {
+            return Ok(Some(capture));
+        }
+
+        let capture =
+            ScreenCapture::start(&data.config.global.framegrabber, &data.paths)?.map(Arc::new);
+        if let Some(capture) = &capture {
+            data.screen_capture = Arc::downgrade(capture);
+        }
+
+        Ok(capture)
+    }
+
     pub async fn read_effects<T>(&self, f: impl FnOnce(&EffectRegistry) -> T) -> T {
         let data = self.0.read().await;
         f(&data.effects)
@@ -150,6 +537,118 @@ impl Global {
         f(&data.input_sources)
     }
 
+    pub async fn is_read_only(&self) -> bool {
+        self.0.read().await.read_only
+    }
+
+    pub async fn read_database_path<T>(&self, f: impl FnOnce(Option<&Path>) -> T) -> T {
+        let data = self.0.read().await;
+        f(data.database_path.as_deref())
+    }
+
+    pub async fn paths(&self) -> Paths {
+        self.0.read().await.paths.clone()
+    }
+
+    pub async fn environment(&self) -> Option<Arc<EnvironmentReport>> {
+        self.0.read().await.environment.clone()
+    }
+
+    /// Recent log records, if they are collected
+    pub async fn log_buffer(&self) -> Option<LogBuffer> {
+        self.0.read().await.log_buffer.clone()
+    }
+
+    /// Ask the daemon to reload its configuration and restart all components
+    pub async fn request_reload(&self) {
+        self.0.read().await.reload.notify_one();
+    }
+
+    /// Wait until a reload is requested using [`Global::request_reload`]
+    pub async fn reload_requested(&self) {
+        let reload = self.0.read().await.reload.clone();
+        reload.notified().await;
+    }
+
+    pub async fn record_command(&self, trace: CommandTrace) {
+        let traces = self.0.read().await.command_traces.clone();
+        traces.lock().unwrap().push(trace);
+    }
+
+    pub async fn read_command_traces<T>(&self, f: impl FnOnce(&CommandTraces) -> T) -> T {
+        let traces = self.0.read().await.command_traces.clone();
+        let traces = traces.lock().unwrap();
+        f(&traces)
+    }
+
+    pub async fn register_client(
+        &self,
+        protocol: ClientProtocol,
+        peer_addr: SocketAddr,
+    ) -> ClientHandle {
+        ClientHandle::new(self.0.read().await.clients.clone(), protocol, peer_addr)
+    }
+
+    pub async fn read_clients<T>(&self, f: impl FnOnce(&ClientRegistry) -> T) -> T {
+        let clients = self.0.read().await.clients.clone();
+        let clients = clients.lock().unwrap();
+        f(&clients)
+    }
+
+    /// Blank all instances regardless of their inputs, or resume normal output
+    pub async fn set_blackout(&self, enabled: bool) {
+        let previous = self.0.read().await.blackout.send_replace(enabled);
+
+        if previous != enabled {
+            info!(enabled = %enabled, "blackout changed");
+        }
+    }
+
+    pub async fn is_blackout(&self) -> bool {
+        *self.0.read().await.blackout.borrow()
+    }
+
+    pub async fn subscribe_blackout(&self) -> watch::Receiver<bool> {
+        self.0.read().await.blackout.subscribe()
+    }
+
+    /// Enable or disable a component of an instance at runtime
+    pub async fn set_component_state(
+        &self,
+        instance: i32,
+        component: ComponentName,
+        enabled: bool,
+    ) {
+        let data = self.0.read().await;
+        let changed = data
+            .components
+            .send_if_modified(|states| states.set(instance, component, enabled));
+
+        if changed {
+            info!(instance = %instance, component = %component, enabled = %enabled, "component state changed");
+
+            // ok: there may be no event receiver
+            data.event_tx
+                .send(Event::instance(
+                    instance,
+                    InstanceEventKind::ComponentStateChanged { component, enabled },
+                ))
+                .ok();
+        }
+    }
+
+    pub async fn component_states(&self) -> ComponentStates {
+        self.0.read().await.components.borrow().clone()
+    }
+
+    pub async fn subscribe_component_states(&self) -> watch::Receiver<ComponentStates> {
+        self.0.read().await.components.subscribe()
+    }
+
+    pub async fn auth(&self) -> AuthManager {
+        self.0.read().await.auth.clone()
+    }
+
     pub async fn get_event_tx(&self) -> broadcast::Sender<Event> {
         self.0.read().await.event_tx.clone()
     }
@@ -164,27 +663,108 @@ pub struct GlobalData {
     input_sources: HashMap<usize, Arc<InputSource<InputMessage>>>,
     next_input_source_id: usize,
     config: Config,
+    /// Serializes changes to the configuration, see [Global::lock_config_update]
+    config_update: Arc<tokio::sync::Mutex<()>>,
     instances: BTreeMap<i32, InstanceHandle>,
     event_tx: broadcast::Sender<Event>,
     effects: EffectRegistry,
+    read_only: bool,
+    database_path: Option<PathBuf>,
+    environment: Option<Arc<EnvironmentReport>>,
+    log_buffer: Option<LogBuffer>,
+    reload: Arc<Notify>,
+    command_traces: Arc<Mutex<CommandTraces>>,
+    clients: Arc<Mutex<ClientRegistry>>,
+    blackout: watch::Sender<bool>,
+    components: watch::Sender<ComponentStates>,
+    auth: AuthManager,
+    /// Instances to start again once they stop
+    restarting: HashSet<i32>,
+    paths: Paths,
+    /// Servers which can be started and stopped at runtime
+    servers: BTreeMap<ServerId, ServerHandle>,
+    /// Servers which failed to start
+    bind_retries: BTreeMap<ServerId, BindRetry>,
+    /// Targets of the forwarder, if it is running
+    forwarder_targets: Arc<Vec<ForwarderTarget>>,
+    /// Audio capture, running while instances use it
+    #[cfg(feature = "audio")]
+    audio_capture: std::sync::Weak<AudioCapture>,
+    /// Screen capture, running while instances use it
+    screen_capture: std::sync::Weak<ScreenCapture>,
+}
+
+/// Delay before binding a server again after a failure, doubled after each attempt
+const BIND_RETRY_MIN_DELAY: Duration = Duration::from_secs(1);
+const BIND_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+struct BindRetry {
+    failure: ServerFailure,
+    task: JoinHandle<()>,
 }
 
 impl GlobalData {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(config: &Config, paths: Paths) -> Self {
         let (input_tx, _) = broadcast::channel(4);
-        let (event_tx, _) = broadcast::channel(4);
+        // API clients subscribed to state updates may lag behind bursts of priority changes
+        let (event_tx, _) = broadcast::channel(64);
 
         Self {
             input_tx,
             input_sources: Default::default(),
             next_input_source_id: 1,
             config: config.clone(),
+            config_update: Default::default(),
             instances: Default::default(),
             event_tx,
             effects: Default::default(),
+            read_only: false,
+            database_path: None,
+            environment: None,
+            log_buffer: None,
+            reload: Default::default(),
+            command_traces: Default::default(),
+            clients: Default::default(),
+            blackout: watch::channel(false).0,
+            components: watch::channel(Default::default()).0,
+            auth: AuthManager::new(config.users()),
+            restarting: Default::default(),
+            paths,
+            servers: Default::default(),
+            bind_retries: Default::default(),
+            forwarder_targets: Default::default(),
+            #[cfg(feature = "audio")]
+            audio_capture: Default::default(),
+            screen_capture: Default::default(),
         }
     }
 
+    /// Set the path to the configuration database this instance was loaded from
+    pub fn database_path(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.auth = self.auth.with_database(path.clone());
+        self.database_path = Some(path);
+        self
+    }
+
+    /// Set the environment report of this daemon
+    pub fn environment(mut self, environment: EnvironmentReport) -> Self {
+        self.environment = Some(Arc::new(environment));
+        self
+    }
+
+    /// Set the buffer log records are collected into, for streaming them to API clients
+    pub fn log_buffer(mut self, log_buffer: LogBuffer) -> Self {
+        self.log_buffer = Some(log_buffer);
+        self
+    }
+
+    /// Mark the configuration as read-only, i.e. changes to it can't be persisted
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
     pub fn wrap(self) -> Global {
         Global(Arc::new(RwLock::new(self)))
     }
@@ -201,26 +781,126 @@ impl GlobalData {
 
         info!(source = %input_source, "registered new input source");
 
+        let component = input_source.name().component();
+        let started = !self.has_input_source(component);
+
         self.input_sources.insert(id, input_source.clone());
 
+        if started {
+            self.notify_component_state(component, true);
+        }
+
         input_source
     }
 
     fn unregister_input_source(&mut self, source: &InputSource<InputMessage>) {
         if let Some(is) = self.input_sources.remove(&source.id()) {
             info!(source = %*is, "unregistered input source");
+
+            let component = is.name().component();
+            if !self.has_input_source(component) {
+                self.notify_component_state(component, false);
+            }
+        }
+    }
+
+    fn has_input_source(&self, component: ComponentName) -> bool {
+        self.input_sources
+            .values()
+            .any(|source| source.name().component() == component)
+    }
+
+    /// Notify of a grabber starting or stopping capture, i.e. its first input source was
+    /// registered or its last one was unregistered
+    fn notify_component_state(&self, component: ComponentName, enabled: bool) {
+        if !component.is_grabber() {
+            return;
         }
+
+        info!(component = %component, enabled = %enabled, "component state changed");
+
+        // ok: there may be no event receiver
+        self.event_tx
+            .send(Event::ComponentStateChanged { component, enabled })
+            .ok();
     }
 
     fn register_instance(&mut self, handle: InstanceHandle) {
         let id = handle.id();
+        info!(id = %id, name = %handle.name(), "registered instance");
         self.instances.insert(id, handle);
-        info!(id = %id, "registered instance");
     }
 
     fn unregister_instance(&mut self, id: i32) {
-        if self.instances.remove(&id).is_some() {
-            info!(id = %id, "unregistered instance");
+        if let Some(handle) = self.instances.remove(&id) {
+            info!(id = %id, name = %handle.name(), "unregistered instance");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instance::Instance, models::GlobalConfig};
+
+    /// Global state running dummy instances 0 and 1
+    async fn running_instances() -> (Global, Config) {
+        let config = Config::new(
+            GlobalConfig::default(),
+            [InstanceConfig::new_dummy(0), InstanceConfig::new_dummy(1)],
+        );
+        let global = GlobalData::new(&config, Paths::new(None).unwrap()).wrap();
+
+        for instance in config.instances.values() {
+            let (instance, handle) = Instance::new(global.clone(), instance.clone()).await;
+            global.register_instance(handle).await;
+            tokio::spawn(instance.run());
+        }
+
+        (global, config)
+    }
+
+    async fn reload_requested(global: &Global) -> bool {
+        tokio::time::timeout(Duration::from_millis(50), global.reload_requested())
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_apply_instance_config() {
+        let (global, mut config) = running_instances().await;
+
+        config.instances.get_mut(&1).unwrap().instance.friendly_name = "Renamed".to_owned();
+        global.apply_config(config).await.unwrap();
+
+        // Only the changed instance is restarted, with its new configuration
+        assert_eq!(
+            global
+                .take_instance_restart(1)
+                .await
+                .map(|config| config.instance.friendly_name),
+            Some("Renamed".to_owned())
+        );
+        assert!(global.take_instance_restart(0).await.is_none());
+        assert!(!reload_requested(&global).await);
+    }
+
+    #[tokio::test]
+    async fn test_apply_global_config() {
+        let (global, mut config) = running_instances().await;
+
+        config.global.general.name = "Renamed".to_owned();
+        global.apply_config(config).await.unwrap();
+
+        // Global changes restart everything through a reload instead
+        assert!(reload_requested(&global).await);
+        assert!(global.take_instance_restart(0).await.is_none());
+        assert!(global.take_instance_restart(1).await.is_none());
+        assert_eq!(
+            global
+                .read_config(|config| config.global.general.name.clone())
+                .await,
+            "Renamed"
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! Authentication of API clients
+//!
+//! Clients get an [AccessLevel] when they connect, depending on the `network` settings and on
+//! whether they connect from the local network. They can raise it by logging in with the
+//! administrator password or with an API token.
+
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{
+    sync::{oneshot, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    db::{Db, DbError},
+    models::{backend::DbBackend, ConfigError, Network, User},
+};
+
+/// Time a token request waits for an administrator to answer it
+pub const TOKEN_REQUEST_TIMEOUT: Duration = Duration::from_secs(180);
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("No Authorization")]
+    NoAuthorization,
+    #[error("invalid password")]
+    InvalidPassword,
+    #[error("unknown token id: {0}")]
+    UnknownToken(String),
+    #[error("token id already in use: {0}")]
+    DuplicateId(String),
+    #[error("no pending token request with id {0}")]
+    UnknownRequest(String),
+    #[error("error opening database: {0}")]
+    Db(#[from] DbError),
+    #[error("error saving credentials: {0}")]
+    Config(#[from] ConfigError),
+}
+
+/// Commands a client is allowed to send
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessLevel {
+    /// Authentication commands only
+    None,
+    /// Regular API commands
+    Api,
+    /// Token management and configuration changes
+    Admin,
+}
+
+impl AccessLevel {
+    /// Access level of a client before it logs in
+    ///
+    /// `peer` is `None` if the address of the client is unknown, it is then considered remote.
+    pub fn for_peer(network: &Network, peer: Option<IpAddr>) -> Self {
+        let local = peer.is_some_and(is_local);
+        let api = !network.api_auth || (local && !network.local_api_auth);
+
+        if api && local && !network.local_admin_auth {
+            Self::Admin
+        } else if api {
+            Self::Api
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Returns true if `addr` is a loopback, private or link-local address
+fn is_local(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_loopback() || addr.is_private() || addr.is_link_local(),
+        IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+            Some(addr) => is_local(addr.into()),
+            None => {
+                let prefix = addr.segments()[0];
+                // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+                addr.is_loopback() || prefix & 0xfe00 == 0xfc00 || prefix & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// An API token, without its secret value
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub id: String,
+    pub comment: String,
+    pub last_use: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<&User> for ApiToken {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id.clone().unwrap_or_default(),
+            comment: user.comment.clone().unwrap_or_default(),
+            last_use: user.last_use,
+        }
+    }
+}
+
+/// A token request waiting for an administrator
+#[derive(Debug, Clone)]
+pub struct PendingTokenRequest {
+    pub id: String,
+    pub comment: String,
+    /// Time left before the request expires
+    pub timeout: Duration,
+}
+
+struct PendingRequest {
+    id: String,
+    comment: String,
+    deadline: Instant,
+    answer: oneshot::Sender<Option<String>>,
+}
+
+impl PendingRequest {
+    /// Returns true if the client stopped waiting for an answer
+    fn is_expired(&self) -> bool {
+        self.answer.is_closed() || self.deadline <= Instant::now()
+    }
+}
+
+/// Token request of a client, see [AuthManager::request_token]
+#[derive(Debug)]
+pub struct TokenRequest {
+    pub id: String,
+    pub comment: String,
+    deadline: Instant,
+    answer: oneshot::Receiver<Option<String>>,
+}
+
+impl TokenRequest {
+    /// Wait for an administrator to answer this request
+    ///
+    /// This is cancel-safe.
+    ///
+    /// # Returns
+    ///
+    /// The new token if the request was accepted, `None` if it was denied or timed out.
+    pub async fn answer(&mut self) -> Option<String> {
+        tokio::time::timeout_at(self.deadline, &mut self.answer)
+            .await
+            .ok()?
+            .ok()?
+    }
+}
+
+#[derive(Default)]
+struct AuthData {
+    users: Vec<User>,
+    requests: Vec<PendingRequest>,
+}
+
+impl AuthData {
+    fn admin(&self) -> Option<&User> {
+        self.users
+            .iter()
+            .find(|user| user.name == User::ADMIN && !user.is_api_token())
+    }
+
+    fn admin_mut(&mut self) -> Option<&mut User> {
+        self.users
+            .iter_mut()
+            .find(|user| user.name == User::ADMIN && !user.is_api_token())
+    }
+
+    fn token_mut(&mut self, id: &str) -> Result<&mut User, AuthError> {
+        self.users
+            .iter_mut()
+            .find(|user| user.id.as_deref() == Some(id))
+            .ok_or_else(|| AuthError::UnknownToken(id.to_owned()))
+    }
+
+    fn is_id_used(&mut self, id: &str) -> bool {
+        self.requests.retain(|request| !request.is_expired());
+
+        self.users.iter().any(|user| user.name == id)
+            || self.requests.iter().any(|request| request.id == id)
+    }
+}
+
+/// Users, API tokens and pending token requests, shared by all API servers
+///
+/// Changes are saved to the `auth` table of the configuration database, if any. Otherwise they
+/// only last until the daemon is restarted.
+#[derive(Clone)]
+pub struct AuthManager {
+    data: Arc<Mutex<AuthData>>,
+    database_path: Option<PathBuf>,
+}
+
+impl AuthManager {
+    pub fn new(users: &[User]) -> Self {
+        let mut users = users.to_vec();
+
+        if !users
+            .iter()
+            .any(|user| user.name == User::ADMIN && !user.is_api_token())
+        {
+            users.push(User::hyperion());
+        }
+
+        Self {
+            data: Arc::new(Mutex::new(AuthData {
+                users,
+                requests: Vec::new(),
+            })),
+            database_path: None,
+        }
+    }
+
+    /// Save changes to the given configuration database
+    pub fn with_database(mut self, path: PathBuf) -> Self {
+        self.database_path = Some(path);
+        self
+    }
+
+    async fn save(&self, user: &User) -> Result<(), AuthError> {
+        if let Some(path) = &self.database_path {
+            DbBackend::new(Db::open(path).await?)
+                .save_user(user)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), AuthError> {
+        if let Some(path) = &self.database_path {
+            DbBackend::new(Db::open(path).await?)
+                .delete_user(name)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the administrator password still is the default one
+    pub async fn is_default_password(&self) -> bool {
+        self.data
+            .lock()
+            .await
+            .admin()
+            .is_some_and(|admin| admin.check_password(User::DEFAULT_PASSWORD))
+    }
+
+    /// Log in with the administrator password
+    ///
+    /// # Returns
+    ///
+    /// The session token of the administrator, which can be used to log in again.
+    pub async fn login_password(&self, password: &str) -> Result<String, AuthError> {
+        let data = self.data.lock().await;
+
+        match data.admin() {
+            Some(admin) if admin.check_password(password) => Ok(hex::encode(&admin.token)),
+            _ => Err(AuthError::NoAuthorization),
+        }
+    }
+
+    /// Log in with an API token or the session token of the administrator
+    pub async fn login_token(&self, token: &str) -> Result<AccessLevel, AuthError> {
+        let mut data = self.data.lock().await;
+
+        if data
+            .admin()
+            .is_some_and(|admin| hex::encode(&admin.token) == token)
+        {
+            return Ok(AccessLevel::Admin);
+        }
+
+        let hash = User::hash_token(token);
+        let user = data
+            .users
+            .iter_mut()
+            .find(|user| user.is_api_token() && user.token == hash)
+            .ok_or(AuthError::NoAuthorization)?;
+
+        user.last_use = chrono::Utc::now();
+        let user = user.clone();
+
+        // The token is valid even if its last use time can't be saved (e.g. read-only database)
+        if let Err(error) = self.save(&user).await {
+            warn!(error = %error, id = ?user.id, "cannot save token last use time");
+        }
+
+        Ok(AccessLevel::Api)
+    }
+
+    /// Change the administrator password
+    pub async fn set_password(&self, password: &str, new_password: &str) -> Result<(), AuthError> {
+        let mut data = self.data.lock().await;
+        let admin = data.admin_mut().ok_or(AuthError::NoAuthorization)?;
+
+        if !admin.check_password(password) {
+            return Err(AuthError::InvalidPassword);
+        }
+
+        admin.set_password(new_password);
+        let admin = admin.clone();
+        self.save(&admin).await?;
+
+        info!("administrator password changed");
+        Ok(())
+    }
+
+    async fn add_token(
+        &self,
+        data: &mut AuthData,
+        comment: String,
+        id: String,
+    ) -> Result<String, AuthError> {
+        let token = uuid::Uuid::new_v4().to_string();
+        let user = User::api_token(&token, comment, id);
+
+        self.save(&user).await?;
+        info!(id = ?user.id, comment = ?user.comment, "created API token");
+
+        data.users.push(user);
+        Ok(token)
+    }
+
+    /// Create a new API token
+    ///
+    /// # Returns
+    ///
+    /// The token and its id.
+    pub async fn create_token(&self, comment: String) -> Result<(String, String), AuthError> {
+        let mut data = self.data.lock().await;
+
+        let id = loop {
+            let id = uuid::Uuid::new_v4().simple().to_string()[..5].to_owned();
+            if !data.is_id_used(&id) {
+                break id;
+            }
+        };
+
+        let token = self.add_token(&mut data, comment, id.clone()).await?;
+        Ok((token, id))
+    }
+
+    pub async fn rename_token(&self, id: &str, comment: String) -> Result<(), AuthError> {
+        let mut data = self.data.lock().await;
+        let user = data.token_mut(id)?;
+
+        user.comment = Some(comment);
+        let user = user.clone();
+        self.save(&user).await
+    }
+
+    pub async fn delete_token(&self, id: &str) -> Result<(), AuthError> {
+        let mut data = self.data.lock().await;
+        let name = data.token_mut(id)?.name.clone();
+
+        self.delete(&name).await?;
+        data.users.retain(|user| user.name != name);
+
+        info!(id = %id, "deleted API token");
+        Ok(())
+    }
+
+    pub async fn tokens(&self) -> Vec<ApiToken> {
+        self.data
+            .lock()
+            .await
+            .users
+            .iter()
+            .filter(|user| user.is_api_token())
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Ask an administrator for a new API token
+    ///
+    /// `id` is chosen by the client to identify its request.
+    pub async fn request_token(
+        &self,
+        comment: String,
+        id: String,
+    ) -> Result<TokenRequest, AuthError> {
+        let mut data = self.data.lock().await;
+
+        if data.is_id_used(&id) {
+            return Err(AuthError::DuplicateId(id));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let deadline = Instant::now() + TOKEN_REQUEST_TIMEOUT;
+
+        info!(id = %id, comment = %comment, "API token requested");
+
+        data.requests.push(PendingRequest {
+            id: id.clone(),
+            comment: comment.clone(),
+            deadline,
+            answer: tx,
+        });
+
+        Ok(TokenRequest {
+            id,
+            comment,
+            deadline,
+            answer: rx,
+        })
+    }
+
+    pub async fn pending_requests(&self) -> Vec<PendingTokenRequest> {
+        let mut data = self.data.lock().await;
+        data.requests.retain(|request| !request.is_expired());
+
+        let now = Instant::now();
+        data.requests
+            .iter()
+            .map(|request| PendingTokenRequest {
+                id: request.id.clone(),
+                comment: request.comment.clone(),
+                timeout: request.deadline - now,
+            })
+            .collect()
+    }
+
+    /// Accept or deny a pending token request
+    pub async fn answer_request(&self, id: &str, accept: bool) -> Result<(), AuthError> {
+        let mut data = self.data.lock().await;
+        data.requests.retain(|request| !request.is_expired());
+
+        let index = data
+            .requests
+            .iter()
+            .position(|request| request.id == id)
+            .ok_or_else(|| AuthError::UnknownRequest(id.to_owned()))?;
+        let request = data.requests.remove(index);
+
+        let token = if accept {
+            Some(
+                self.add_token(&mut data, request.comment, request.id)
+                    .await?,
+            )
+        } else {
+            info!(id = %id, "API token request denied");
+            None
+        };
+
+        // The client may have disconnected since the last expiration check
+        request.answer.send(token).ok();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_level() {
+        let network = Network::default();
+        let local = Some([192, 168, 1, 10].into());
+        let remote = Some([8, 8, 8, 8].into());
+
+        assert_eq!(AccessLevel::for_peer(&network, local), AccessLevel::Api);
+        assert_eq!(AccessLevel::for_peer(&network, remote), AccessLevel::None);
+        assert_eq!(AccessLevel::for_peer(&network, None), AccessLevel::None);
+
+        let network = Network {
+            local_admin_auth: false,
+            ..Default::default()
+        };
+        assert_eq!(AccessLevel::for_peer(&network, local), AccessLevel::Admin);
+
+        let network = Network {
+            api_auth: false,
+            ..Default::default()
+        };
+        assert_eq!(AccessLevel::for_peer(&network, remote), AccessLevel::Api);
+    }
+
+    #[tokio::test]
+    async fn test_token_request() {
+        let auth = AuthManager::new(&[]);
+        let mut request = auth
+            .request_token("Test client".to_owned(), "abcde".to_owned())
+            .await
+            .unwrap();
+
+        assert_eq!(auth.pending_requests().await.len(), 1);
+        auth.answer_request("abcde", true).await.unwrap();
+
+        let token = request.answer().await.unwrap();
+        assert_eq!(auth.login_token(&token).await.unwrap(), AccessLevel::Api);
+        assert!(auth.pending_requests().await.is_empty());
+        assert_eq!(auth.tokens().await[0].id, "abcde");
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use parse_display::Display;
+use serde_derive::Serialize;
+
+/// Protocol spoken by a network client
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[display(style = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ClientProtocol {
+    Boblight,
+    Flatbuffers,
+    Json,
+    Protobuf,
+}
+
+/// State of a connected client
+#[derive(Debug, Clone)]
+pub struct ClientRecord {
+    pub protocol: ClientProtocol,
+    pub peer_addr: SocketAddr,
+    /// Time at which the client connected
+    pub connected: chrono::DateTime<chrono::Utc>,
+    /// Name the client gave itself, if the protocol supports it
+    pub origin: Option<String>,
+    /// Protocol features used by the client so far
+    pub features: BTreeSet<String>,
+}
+
+/// Connected network clients, and the protocol features they negotiated
+#[derive(Debug, Default)]
+pub struct ClientRegistry {
+    clients: BTreeMap<usize, ClientRecord>,
+    next_id: usize,
+    /// Number of clients which used each feature since startup
+    negotiated: BTreeMap<(ClientProtocol, String), u64>,
+}
+
+impl ClientRegistry {
+    fn connect(&mut self, protocol: ClientProtocol, peer_addr: SocketAddr) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.clients.insert(
+            id,
+            ClientRecord {
+                protocol,
+                peer_addr,
+                connected: chrono::Utc::now(),
+                origin: None,
+                features: Default::default(),
+            },
+        );
+
+        id
+    }
+
+    fn negotiate(&mut self, id: usize, feature: &str) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            if client.features.contains(feature) {
+                return;
+            }
+
+            debug!(
+                protocol = %client.protocol,
+                peer_addr = %client.peer_addr,
+                origin = ?client.origin,
+                feature,
+                "client negotiated"
+            );
+
+            client.features.insert(feature.to_owned());
+            *self
+                .negotiated
+                .entry((client.protocol, feature.to_owned()))
+                .or_default() += 1;
+        }
+    }
+
+    fn set_origin(&mut self, id: usize, origin: &str) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.origin = Some(origin.to_owned());
+        }
+    }
+
+    fn disconnect(&mut self, id: usize) {
+        self.clients.remove(&id);
+    }
+
+    /// Iterate over the connected clients, in connection order
+    pub fn clients(&self) -> impl Iterator<Item = &ClientRecord> {
+        self.clients.values()
+    }
+
+    /// Iterate over the features negotiated since startup, with the number of clients which did
+    pub fn negotiated(&self) -> impl Iterator<Item = (ClientProtocol, &str, u64)> {
+        self.negotiated
+            .iter()
+            .map(|((protocol, feature), count)| (*protocol, feature.as_str(), *count))
+    }
+}
+
+/// Registration of a client in the [ClientRegistry], removed when dropped
+#[derive(Debug)]
+pub struct ClientHandle {
+    id: usize,
+    registry: Arc<Mutex<ClientRegistry>>,
+}
+
+impl ClientHandle {
+    pub(super) fn new(
+        registry: Arc<Mutex<ClientRegistry>>,
+        protocol: ClientProtocol,
+        peer_addr: SocketAddr,
+    ) -> Self {
+        let id = registry.lock().unwrap().connect(protocol, peer_addr);
+        Self { id, registry }
+    }
+
+    /// Record that the client used a protocol feature
+    pub fn negotiated(&self, feature: &str) {
+        self.registry.lock().unwrap().negotiate(self.id, feature);
+    }
+
+    pub fn set_origin(&self, origin: &str) {
+        self.registry.lock().unwrap().set_origin(self.id, origin);
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.registry.lock().unwrap().disconnect(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_registry() {
+        let registry = Arc::new(Mutex::new(ClientRegistry::default()));
+        let peer_addr = "127.0.0.1:19400".parse().unwrap();
+
+        let first = ClientHandle::new(registry.clone(), ClientProtocol::Flatbuffers, peer_addr);
+        let second = ClientHandle::new(registry.clone(), ClientProtocol::Flatbuffers, peer_addr);
+
+        // Features are counted once per client
+        first.negotiated("register");
+        first.negotiated("register");
+        first.set_origin("kodi");
+        second.negotiated("register");
+        second.negotiated("image");
+
+        {
+            let registry = registry.lock().unwrap();
+            assert_eq!(
+                registry.negotiated().collect::<Vec<_>>(),
+                vec![
+                    (ClientProtocol::Flatbuffers, "image", 1),
+                    (ClientProtocol::Flatbuffers, "register", 2)
+                ]
+            );
+            assert_eq!(
+                registry.clients().next().unwrap().origin.as_deref(),
+                Some("kodi")
+            );
+        }
+
+        // Disconnected clients are removed, their features are still counted
+        drop(first);
+        let registry = registry.lock().unwrap();
+        assert_eq!(registry.clients().count(), 1);
+        assert_eq!(registry.negotiated().count(), 2);
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of commands kept by [CommandTraces]
+pub const COMMAND_TRACE_CAPACITY: usize = 100;
+
+/// Record of a processed API command
+#[derive(Debug, Clone)]
+pub struct CommandTrace {
+    /// Request identifier, if given by the client
+    pub tan: Option<i32>,
+    /// Name of the command
+    pub command: &'static str,
+    /// Name of the input source that sent the command
+    pub source: String,
+    /// Time at which processing started
+    pub started: chrono::DateTime<chrono::Utc>,
+    /// Processing time
+    pub duration: Duration,
+    /// Error message, if the command failed
+    pub error: Option<String>,
+}
+
+/// Ring buffer of the last processed commands
+#[derive(Debug, Default)]
+pub struct CommandTraces {
+    traces: VecDeque<CommandTrace>,
+}
+
+impl CommandTraces {
+    pub fn push(&mut self, trace: CommandTrace) {
+        if self.traces.len() >= COMMAND_TRACE_CAPACITY {
+            self.traces.pop_front();
+        }
+
+        self.traces.push_back(trace);
+    }
+
+    /// Iterate over the recorded commands, most recent first
+    pub fn iter(&self) -> impl Iterator<Item = &CommandTrace> {
+        self.traces.iter().rev()
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::component::ComponentName;
+
+/// Components of each instance disabled at runtime by API clients
+///
+/// Components are enabled unless disabled here. The state is not saved to the configuration, all
+/// components are enabled again when hyperion.rs restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentStates {
+    disabled: BTreeMap<i32, HashSet<ComponentName>>,
+}
+
+impl ComponentStates {
+    pub fn is_enabled(&self, instance: i32, component: ComponentName) -> bool {
+        self.disabled
+            .get(&instance)
+            .is_none_or(|disabled| !disabled.contains(&component))
+    }
+
+    /// Components disabled on the given instance
+    pub fn disabled(&self, instance: i32) -> HashSet<ComponentName> {
+        self.disabled.get(&instance).cloned().unwrap_or_default()
+    }
+
+    /// Enable or disable a component of an instance
+    ///
+    /// # Returns
+    ///
+    /// true if the state of the component changed
+    pub fn set(&mut self, instance: i32, component: ComponentName, enabled: bool) -> bool {
+        if enabled {
+            let disabled = match self.disabled.get_mut(&instance) {
+                Some(disabled) => disabled,
+                None => return false,
+            };
+
+            let changed = disabled.remove(&component);
+            if disabled.is_empty() {
+                self.disabled.remove(&instance);
+            }
+
+            changed
+        } else {
+            self.disabled.entry(instance).or_default().insert(component)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_component_states() {
+        let mut states = ComponentStates::default();
+        assert!(states.is_enabled(0, ComponentName::Smoothing));
+
+        assert!(states.set(0, ComponentName::Smoothing, false));
+        assert!(!states.set(0, ComponentName::Smoothing, false));
+        assert!(!states.is_enabled(0, ComponentName::Smoothing));
+        assert!(states.is_enabled(1, ComponentName::Smoothing));
+
+        assert!(states.set(0, ComponentName::Smoothing, true));
+        assert!(!states.set(0, ComponentName::Smoothing, true));
+        assert_eq!(states, ComponentStates::default());
+    }
+}
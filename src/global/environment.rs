@@ -0,0 +1,165 @@
+use serde_derive::Serialize;
+
+use crate::models::Config;
+
+/// Prefixes of device nodes for LED controllers: SPI buses and USB or on-board serial ports
+const HARDWARE_PREFIXES: &[&str] = &["spidev", "ttyUSB", "ttyACM", "ttyAMA"];
+
+/// Version string of this build
+pub fn version() -> String {
+    git_version::git_version!(
+        prefix = "hyperion.rs-",
+        args = ["--always", "--tags"],
+        fallback = env!("HYPERION_RS_GIT_VERSION")
+    )
+    .to_owned()
+}
+
+/// Optional cargo features, with whether this build was compiled with them
+pub fn feature_matrix() -> Vec<(&'static str, bool)> {
+    vec![
+        ("python", cfg!(feature = "python")),
+        ("png", cfg!(feature = "png")),
+        ("jpeg", cfg!(feature = "jpeg")),
+        ("webp", cfg!(feature = "webp")),
+        ("gif", cfg!(feature = "gif")),
+        ("tls", cfg!(feature = "tls")),
+        ("ndi", cfg!(feature = "ndi")),
+        ("audio", cfg!(feature = "audio")),
+        ("wayland", cfg!(feature = "wayland")),
+        ("kms", cfg!(feature = "kms")),
+        ("amlogic", cfg!(feature = "amlogic")),
+        ("rpi-ws281x", cfg!(feature = "rpi-ws281x")),
+        ("ftdi", cfg!(feature = "ftdi")),
+        ("mpris", cfg!(feature = "mpris")),
+        ("data-presets", cfg!(feature = "data-presets")),
+        ("mqtt", cfg!(feature = "mqtt")),
+    ]
+}
+
+/// Cargo features this build was compiled with
+pub fn enabled_features() -> Vec<&'static str> {
+    feature_matrix()
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortInfo {
+    pub name: String,
+    pub port: u16,
+}
+
+/// Report of the build and runtime environment, logged on startup for support requests
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvironmentReport {
+    pub version: String,
+    pub features: Vec<&'static str>,
+    /// Description of where the configuration was loaded from
+    pub config_backend: String,
+    /// TCP ports of the enabled servers
+    pub ports: Vec<PortInfo>,
+    /// Paths to the SPI and serial device nodes present on this system
+    pub hardware: Vec<String>,
+}
+
+impl EnvironmentReport {
+    pub fn new(config: &Config, config_backend: impl Into<String>) -> Self {
+        let global = &config.global;
+        let mut ports = vec![PortInfo {
+            name: "JSON".to_owned(),
+            port: global.json_server.port,
+        }];
+
+        if global.flatbuffers_server.enable {
+            ports.push(PortInfo {
+                name: "Flatbuffers".to_owned(),
+                port: global.flatbuffers_server.port,
+            });
+        }
+
+        if global.proto_server.enable {
+            ports.push(PortInfo {
+                name: "Protobuf".to_owned(),
+                port: global.proto_server.port,
+            });
+        }
+
+        ports.push(PortInfo {
+            name: "Web".to_owned(),
+            port: global.web_config.port,
+        });
+
+        if cfg!(feature = "tls") && global.web_config.is_ssl_enabled() {
+            ports.push(PortInfo {
+                name: "Web (HTTPS)".to_owned(),
+                port: global.web_config.ssl_port,
+            });
+        }
+
+        for (id, instance) in &config.instances {
+            if instance.boblight_server.enable {
+                ports.push(PortInfo {
+                    name: format!("Boblight (instance {})", id),
+                    port: instance.boblight_server.port,
+                });
+            }
+
+            if instance.udp_listener.enable {
+                ports.push(PortInfo {
+                    name: format!("UDP listener (instance {})", id),
+                    port: instance.udp_listener.port,
+                });
+            }
+        }
+
+        Self {
+            version: version(),
+            features: enabled_features(),
+            config_backend: config_backend.into(),
+            ports,
+            hardware: detect_hardware(),
+        }
+    }
+
+    pub fn log(&self) {
+        let ports = self
+            .ports
+            .iter()
+            .map(|port| format!("{}={}", port.name, port.port))
+            .collect::<Vec<_>>();
+
+        info!(
+            version = %self.version,
+            features = %self.features.join(","),
+            config = %self.config_backend,
+            ports = %ports.join(","),
+            hardware = %self.hardware.join(","),
+            "starting hyperion.rs"
+        );
+    }
+}
+
+/// List the device nodes of potential LED controllers
+fn detect_hardware() -> Vec<String> {
+    let mut found: Vec<_> = std::fs::read_dir("/dev")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .is_some_and(|name| HARDWARE_PREFIXES.iter().any(|p| name.starts_with(p)))
+                })
+                .map(|entry| entry.path().display().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    found.sort();
+    found
+}
@@ -1,8 +1,15 @@
+use crate::component::ComponentName;
+
 #[derive(Debug, Clone)]
 pub enum Event {
     Start,
     Stop,
     Instance(InstanceEvent),
+    /// A grabber started or stopped capturing
+    ComponentStateChanged {
+        component: ComponentName,
+        enabled: bool,
+    },
 }
 
 impl Event {
@@ -23,4 +30,18 @@ pub enum InstanceEventKind {
     Stop,
     Activate,
     Deactivate,
+    /// The LED count reported by the instance device changed
+    DeviceLedCountChanged {
+        previous: usize,
+        current: usize,
+    },
+    /// An input was registered or removed at some priority
+    PrioritiesChanged,
+    /// The channel adjustments of the instance changed
+    AdjustmentChanged,
+    /// A component of the instance was enabled or disabled by an API client
+    ComponentStateChanged {
+        component: ComponentName,
+        enabled: bool,
+    },
 }
@@ -6,6 +6,10 @@ use super::{Event, InstanceEvent, InstanceEventKind};
 use crate::models::Hooks;
 
 const INSTANCE_ID: &str = "HYPERION_INSTANCE_ID";
+const LED_COUNT: &str = "HYPERION_LED_COUNT";
+const PREVIOUS_LED_COUNT: &str = "HYPERION_PREVIOUS_LED_COUNT";
+const COMPONENT: &str = "HYPERION_COMPONENT";
+const COMPONENT_ENABLED: &str = "HYPERION_COMPONENT_ENABLED";
 
 struct HookBuilder<'s> {
     variables: BTreeMap<&'static str, String>,
@@ -60,11 +64,31 @@ impl HookRunner {
         match message {
             Event::Start => HookBuilder::new(&self.config.start).run(),
             Event::Stop => HookBuilder::new(&self.config.stop).run(),
+            Event::ComponentStateChanged { component, enabled } => {
+                HookBuilder::new(&self.config.component_state_changed)
+                    .arg(COMPONENT, <&'static str>::from(component))
+                    .arg(COMPONENT_ENABLED, enabled)
+                    .run()
+            }
             Event::Instance(InstanceEvent { id, kind }) => match kind {
                 InstanceEventKind::Start => HookBuilder::new(&self.config.instance_start),
                 InstanceEventKind::Stop => HookBuilder::new(&self.config.instance_stop),
                 InstanceEventKind::Activate => HookBuilder::new(&self.config.instance_activate),
                 InstanceEventKind::Deactivate => HookBuilder::new(&self.config.instance_deactivate),
+                InstanceEventKind::DeviceLedCountChanged { previous, current } => {
+                    HookBuilder::new(&self.config.instance_led_count_changed)
+                        .arg(PREVIOUS_LED_COUNT, previous)
+                        .arg(LED_COUNT, current)
+                }
+                InstanceEventKind::ComponentStateChanged { component, enabled } => {
+                    HookBuilder::new(&self.config.component_state_changed)
+                        .arg(COMPONENT, <&'static str>::from(component))
+                        .arg(COMPONENT_ENABLED, enabled)
+                }
+                InstanceEventKind::PrioritiesChanged | InstanceEventKind::AdjustmentChanged => {
+                    // State updates for API clients, no hook
+                    return None;
+                }
             }
             .arg(INSTANCE_ID, id)
             .run(),
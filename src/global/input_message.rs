@@ -1,10 +1,13 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use tokio::sync::{oneshot, Mutex};
 
 use crate::{
-    api::json::message::EffectRequest, component::ComponentName, image::RawImage,
-    instance::StartEffectError, models::Color,
+    api::json::message::EffectRequest,
+    component::ComponentName,
+    image::{ImageRegion, QueuedFrame, RawImage},
+    instance::StartEffectError,
+    models::{Color, SmoothingOverride},
 };
 
 use super::Message;
@@ -14,6 +17,38 @@ pub struct InputMessage {
     source_id: usize,
     component: ComponentName,
     data: InputMessageData,
+    /// Time at which this message was captured or received
+    timestamp: Instant,
+    /// Smoothing settings to use while this input is visible, instead of the instance ones
+    smoothing: Option<Arc<SmoothingOverride>>,
+    /// Span this message was created in, used to trace its processing
+    span: tracing::Span,
+}
+
+impl InputMessage {
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    /// Set the capture time of this message, if it is known to be earlier than its creation
+    pub fn with_timestamp(mut self, timestamp: Instant) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn smoothing(&self) -> Option<&Arc<SmoothingOverride>> {
+        self.smoothing.as_ref()
+    }
+
+    /// Set the smoothing settings to use while this input is visible
+    pub fn with_smoothing(mut self, smoothing: Option<Arc<SmoothingOverride>>) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
 }
 
 impl Message for InputMessage {
@@ -24,6 +59,9 @@ impl Message for InputMessage {
             source_id,
             component,
             data,
+            timestamp: Instant::now(),
+            smoothing: None,
+            span: tracing::Span::current(),
         }
     }
 
@@ -44,6 +82,26 @@ impl Message for InputMessage {
     }
 }
 
+impl QueuedFrame for InputMessage {
+    fn frame_bytes(&self) -> Option<usize> {
+        match &self.data {
+            InputMessageData::Image { image, .. } => Some(image.data().len()),
+            InputMessageData::LedColors { led_colors, .. } => Some(led_colors.len() * 3),
+            _ => None,
+        }
+    }
+
+    fn follows(&self, previous: &Self) -> bool {
+        self.source_id == previous.source_id && self.data.priority() == previous.data.priority()
+    }
+
+    fn clear_region(&mut self) {
+        if let InputMessageData::Image { region, .. } = &mut self.data {
+            *region = None;
+        }
+    }
+}
+
 pub type StartEffectResponseCallback = Mutex<Option<oneshot::Sender<Result<(), StartEffectError>>>>;
 
 #[derive(Debug, Clone)]
@@ -61,6 +119,9 @@ pub enum InputMessageData {
         priority: i32,
         duration: Option<chrono::Duration>,
         image: Arc<RawImage>,
+        /// Region of the image that changed since the previous one sent on this priority, if
+        /// known
+        region: Option<ImageRegion>,
     },
     LedColors {
         priority: i32,
@@ -97,4 +158,24 @@ impl InputMessageData {
             InputMessageData::Effect { duration, .. } => *duration,
         }
     }
+
+    /// Returns true if both messages would result in the same output, regardless of their
+    /// durations
+    pub fn same_output(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                InputMessageData::SolidColor { color: a, .. },
+                InputMessageData::SolidColor { color: b, .. },
+            ) => a == b,
+            (
+                InputMessageData::Image { image: a, .. },
+                InputMessageData::Image { image: b, .. },
+            ) => Arc::ptr_eq(a, b) || a == b,
+            (
+                InputMessageData::LedColors { led_colors: a, .. },
+                InputMessageData::LedColors { led_colors: b, .. },
+            ) => Arc::ptr_eq(a, b) || a == b,
+            _ => false,
+        }
+    }
 }
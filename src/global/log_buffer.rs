@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Number of log records kept by [LogBuffer]
+pub const LOG_BUFFER_CAPACITY: usize = 200;
+
+/// A log record, as streamed to API clients
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub level: Level,
+    /// Module path the record was emitted from
+    pub target: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Message, followed by the other fields of the record
+    pub message: String,
+}
+
+struct LogBufferData {
+    records: Mutex<VecDeque<Arc<LogRecord>>>,
+    tx: broadcast::Sender<Arc<LogRecord>>,
+}
+
+/// Recent log records of the daemon, with a stream of the new ones
+///
+/// [LogBuffer::layer] has to be installed in the tracing subscriber for records to be collected.
+#[derive(Clone)]
+pub struct LogBuffer(Arc<LogBufferData>);
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self(Arc::new(LogBufferData {
+            records: Mutex::new(VecDeque::with_capacity(LOG_BUFFER_CAPACITY)),
+            tx: broadcast::channel(64).0,
+        }))
+    }
+
+    /// Tracing layer collecting records into this buffer
+    pub fn layer(&self) -> LogBufferLayer {
+        LogBufferLayer(self.clone())
+    }
+
+    /// Recorded log records, oldest first, and the receiver of the records after them
+    pub fn subscribe(&self) -> (Vec<Arc<LogRecord>>, broadcast::Receiver<Arc<LogRecord>>) {
+        let records = self.0.records.lock().unwrap();
+        // Subscribe while holding the lock, so no record is missed or received twice
+        (records.iter().cloned().collect(), self.0.tx.subscribe())
+    }
+
+    fn push(&self, record: LogRecord) {
+        let record = Arc::new(record);
+        let mut records = self.0.records.lock().unwrap();
+
+        if records.len() >= LOG_BUFFER_CAPACITY {
+            records.pop_front();
+        }
+
+        records.push_back(record.clone());
+        // ok: no client may be streaming logs
+        self.0.tx.send(record).ok();
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for LogBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogBuffer").finish_non_exhaustive()
+    }
+}
+
+/// Tracing layer of a [LogBuffer]
+pub struct LogBufferLayer(LogBuffer);
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        self.0.push(LogRecord {
+            time: chrono::Utc::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            file: metadata.file().map(ToOwned::to_owned),
+            line: metadata.line(),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).ok();
+        } else {
+            write!(self.fields, " {}={:?}", field.name(), value).ok();
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            write!(self.fields, " {}={}", field.name(), value).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_log_buffer() {
+        let buffer = LogBuffer::new();
+        let subscriber = tracing_subscriber::registry().with(buffer.layer());
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!(priority = 100, source = "test", "input registered");
+        });
+
+        let (records, _rx) = buffer.subscribe();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, Level::INFO);
+        assert_eq!(
+            records[0].message,
+            "input registered priority=100 source=test"
+        );
+    }
+}
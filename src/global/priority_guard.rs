@@ -62,20 +62,27 @@ impl PriorityGuard {
     pub fn set_priority(&mut self, priority: Option<i32>) {
         self.priority = priority;
     }
+
+    /// Clear the guarded priority now rather than when the guard is dropped
+    pub async fn clear(&mut self) {
+        if let Some(priority) = self.priority.take() {
+            self.channel.send(self.clear_message(priority)).await;
+        }
+    }
+
+    fn clear_message(&self, priority: i32) -> InputMessage {
+        InputMessage::new(
+            self.source_id,
+            self.component,
+            InputMessageData::Clear { priority },
+        )
+    }
 }
 
 impl Drop for PriorityGuard {
     fn drop(&mut self) {
         if let Some(priority) = self.priority {
-            futures::executor::block_on(async {
-                self.channel
-                    .send(InputMessage::new(
-                        self.source_id,
-                        self.component,
-                        InputMessageData::Clear { priority },
-                    ))
-                    .await;
-            })
+            futures::executor::block_on(self.channel.send(self.clear_message(priority)))
         }
     }
 }
@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+/// Interval between two checks of the event loop latency
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Extra latency above which a check counts as a stall
+const STALL_THRESHOLD: Duration = Duration::from_millis(50);
+
+/// Minimum interval between two stall warnings
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Stalls observed since the last report
+#[derive(Debug, Default)]
+struct StallStats {
+    count: u32,
+    worst: Duration,
+    last_report: Option<Instant>,
+}
+
+impl StallStats {
+    /// Record the latency of a check
+    ///
+    /// # Returns
+    ///
+    /// The number of stalls and the worst latency since the last report, if a new report is due
+    fn record(&mut self, lag: Duration, now: Instant) -> Option<(u32, Duration)> {
+        if lag >= STALL_THRESHOLD {
+            self.count += 1;
+            self.worst = self.worst.max(lag);
+        }
+
+        if self.count == 0
+            || self
+                .last_report
+                .is_some_and(|last_report| now - last_report < REPORT_INTERVAL)
+        {
+            return None;
+        }
+
+        self.last_report = Some(now);
+        Some((
+            std::mem::take(&mut self.count),
+            std::mem::take(&mut self.worst),
+        ))
+    }
+}
+
+/// Detects async runtime workers being blocked by synchronous code
+///
+/// The watchdog sleeps for a fixed interval and measures how late it wakes up. A task that blocks
+/// the worker thread it runs on (synchronous I/O, DNS lookups, long computations) delays the
+/// timers and tasks scheduled on that worker, which shows up as extra latency.
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    stats: StallStats,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            let deadline = tokio::time::Instant::now() + CHECK_INTERVAL;
+            tokio::time::sleep_until(deadline).await;
+
+            let now = tokio::time::Instant::now();
+            if let Some((count, worst)) = self
+                .stats
+                .record(now.saturating_duration_since(deadline), now.into_std())
+            {
+                warn!(
+                    stalls = %count,
+                    worst_ms = %worst.as_millis(),
+                    "async runtime workers were blocked, a task is running blocking code"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_reports() {
+        let mut stats = StallStats::default();
+        let start = Instant::now();
+
+        // No report without stalls
+        assert_eq!(stats.record(Duration::from_millis(1), start), None);
+
+        // The first stall is reported right away
+        assert_eq!(
+            stats.record(Duration::from_millis(80), start),
+            Some((1, Duration::from_millis(80)))
+        );
+
+        // Later ones are aggregated until the report interval elapsed
+        let later = start + Duration::from_secs(1);
+        assert_eq!(stats.record(Duration::from_millis(200), later), None);
+        assert_eq!(stats.record(Duration::from_millis(60), later), None);
+        assert_eq!(
+            stats.record(Duration::ZERO, start + REPORT_INTERVAL),
+            Some((2, Duration::from_millis(200)))
+        );
+    }
+}
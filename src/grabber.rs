@@ -0,0 +1,21 @@
+//! Screen and audio capture, and processing stages shared with video capture devices
+
+pub mod screen;
+
+mod signal;
+pub use signal::*;
+
+#[cfg(feature = "audio")]
+pub mod audio;
+
+#[cfg(feature = "wayland")]
+pub mod wayland;
+
+#[cfg(feature = "kms")]
+pub mod kms;
+
+#[cfg(feature = "amlogic")]
+pub mod amlogic;
+
+#[cfg(any(feature = "kms", feature = "amlogic"))]
+mod polling;
@@ -0,0 +1,162 @@
+//! Screen capture on AMLogic TV boxes
+//!
+//! Hardware-decoded video is shown on a dedicated video layer, which doesn't appear in the
+//! framebuffer: while a video is playing, it is captured through the amvideocap device, scaled to
+//! the framegrabber size by the GE2D hardware. Otherwise the framebuffer holding the user
+//! interface is captured instead.
+
+use std::{
+    ffi::c_int,
+    fs::{File, OpenOptions},
+    io::Read,
+    os::fd::AsRawFd,
+};
+
+use thiserror::Error;
+
+use super::{
+    polling::PollingGrabber,
+    screen::{FrameProcessor, PixelFormat},
+};
+use crate::{image::RawImage, models};
+
+mod framebuffer;
+use framebuffer::Framebuffer;
+
+#[derive(Debug, Error)]
+pub enum AmlogicError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unsupported framebuffer format: {0} bits per pixel")]
+    UnsupportedFormat(u32),
+}
+
+/// Video layer device, queried for the playback state
+const VIDEO_PATH: &str = "/dev/amvideo";
+
+/// Capture device of the video layer
+const AMVIDEOCAP_PATH: &str = "/dev/amvideocap0";
+
+// Definitions from amvideocap.h and amstream.h
+
+const AMVIDEOCAP_IOW_SET_WANTFRAME_FORMAT: u64 = 0x4004_5601;
+const AMVIDEOCAP_IOW_SET_WANTFRAME_WIDTH: u64 = 0x4004_5602;
+const AMVIDEOCAP_IOW_SET_WANTFRAME_HEIGHT: u64 = 0x4004_5603;
+const AMVIDEOCAP_IOW_SET_WANTFRAME_AT_FLAGS: u64 = 0x4004_5606;
+const AMSTREAM_IOC_GET_VIDEO_DISABLE: u64 = 0x8004_5348;
+
+/// Little-endian 24 bits BGR, the format used by hyperion.ng
+const GE2D_FORMAT_S24_BGR: c_int = (1 << 24) | (5 << 20) | 0x200;
+
+/// Capture the last decoded frame
+const CAP_FLAG_AT_END: c_int = 2;
+
+/// Whether this system has the AMLogic video capture device
+pub fn is_available() -> bool {
+    std::path::Path::new(AMVIDEOCAP_PATH).exists()
+}
+
+/// Reader of the video layer, falling back to the framebuffer
+pub struct AmlogicGrabber {
+    video: File,
+    framebuffer: Framebuffer,
+    size: (u32, u32),
+    processor: FrameProcessor,
+    buffer: Vec<u8>,
+    /// Whether the last frame came from the video layer
+    playing: bool,
+}
+
+impl PollingGrabber for AmlogicGrabber {
+    type Error = AmlogicError;
+
+    fn open(config: &models::Framegrabber) -> Result<Self, AmlogicError> {
+        let video = File::open(VIDEO_PATH)?;
+        let framebuffer = Framebuffer::open(config)?;
+        info!(display = %config.display, "screen capture started");
+
+        Ok(Self {
+            video,
+            framebuffer,
+            size: (config.width, config.height),
+            processor: FrameProcessor::new(config),
+            buffer: vec![0; (config.width * config.height) as usize * 3],
+            playing: false,
+        })
+    }
+
+    fn grab(&mut self) -> Result<Option<RawImage>, AmlogicError> {
+        let image = if self.is_video_playing()? {
+            self.grab_video()?
+        } else {
+            None
+        };
+
+        if image.is_some() != self.playing {
+            self.playing = image.is_some();
+            debug!(playing = %self.playing, "switching capture source");
+        }
+
+        match image {
+            Some(image) => Ok(Some(image)),
+            None => self.framebuffer.grab(),
+        }
+    }
+}
+
+impl AmlogicGrabber {
+    fn is_video_playing(&self) -> Result<bool, AmlogicError> {
+        let mut disabled: c_int = 1;
+
+        // Safety: the request writes an int
+        if unsafe {
+            libc::ioctl(
+                self.video.as_raw_fd(),
+                AMSTREAM_IOC_GET_VIDEO_DISABLE as _,
+                &mut disabled,
+            )
+        } < 0
+        {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(disabled == 0)
+    }
+
+    /// Capture the video layer, or `None` if no frame is ready
+    fn grab_video(&mut self) -> Result<Option<RawImage>, AmlogicError> {
+        // Keeping the capture device open would block the video decoder
+        let mut device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(AMVIDEOCAP_PATH)?;
+
+        for (request, value) in [
+            (AMVIDEOCAP_IOW_SET_WANTFRAME_FORMAT, GE2D_FORMAT_S24_BGR),
+            (AMVIDEOCAP_IOW_SET_WANTFRAME_WIDTH, self.size.0 as c_int),
+            (AMVIDEOCAP_IOW_SET_WANTFRAME_HEIGHT, self.size.1 as c_int),
+            (AMVIDEOCAP_IOW_SET_WANTFRAME_AT_FLAGS, CAP_FLAG_AT_END),
+        ] {
+            // Safety: the settings are passed by value
+            if unsafe { libc::ioctl(device.as_raw_fd(), request as _, value) } < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+
+        match device.read(&mut self.buffer) {
+            Ok(read) if read == self.buffer.len() => {}
+            Ok(_) => return Ok(None),
+            Err(error) if matches!(error.raw_os_error(), Some(libc::EAGAIN | libc::ENODATA)) => {
+                return Ok(None)
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        Ok(self.processor.process(
+            &self.buffer,
+            self.size,
+            self.size.0 as usize * 3,
+            PixelFormat::Bgr,
+        ))
+    }
+}
@@ -0,0 +1,165 @@
+//! Linux framebuffer device, holding the user interface
+
+use std::{
+    ffi::{c_char, c_ulong},
+    fs::File,
+    os::{fd::AsRawFd, unix::fs::FileExt},
+};
+
+use super::AmlogicError;
+use crate::{
+    grabber::screen::{FrameProcessor, PixelFormat},
+    image::RawImage,
+    models,
+};
+
+// Definitions from linux/fb.h, the fields not read here are set by the kernel
+
+const FBIOGET_VSCREENINFO: u64 = 0x4600;
+const FBIOGET_FSCREENINFO: u64 = 0x4602;
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct FbVarScreeninfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    _rest: [u32; 20],
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct FbFixScreeninfo {
+    id: [c_char; 16],
+    smem_start: c_ulong,
+    smem_len: u32,
+    ty: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    _reserved: [u16; 2],
+}
+
+pub struct Framebuffer {
+    file: File,
+    config: models::Framegrabber,
+    buffer: Vec<u8>,
+}
+
+impl Framebuffer {
+    /// Open the framebuffer numbered after the `display` setting
+    pub fn open(config: &models::Framegrabber) -> Result<Self, AmlogicError> {
+        Ok(Self {
+            file: File::open(format!("/dev/fb{}", config.display))?,
+            config: config.clone(),
+            buffer: Vec::new(),
+        })
+    }
+
+    fn ioctl<T: Default>(&self, request: u64) -> Result<T, AmlogicError> {
+        let mut info = T::default();
+
+        // Safety: T is the structure written by the request
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, &mut info as *mut T) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(info)
+    }
+
+    /// Read the visible part of the framebuffer
+    ///
+    /// The frame is decimated down to about the framegrabber width.
+    pub fn grab(&mut self) -> Result<Option<RawImage>, AmlogicError> {
+        // The resolution may change, query it for every frame
+        let var: FbVarScreeninfo = self.ioctl(FBIOGET_VSCREENINFO)?;
+        let fix: FbFixScreeninfo = self.ioctl(FBIOGET_FSCREENINFO)?;
+
+        let format =
+            pixel_format(&var).ok_or(AmlogicError::UnsupportedFormat(var.bits_per_pixel))?;
+        let stride = fix.line_length as usize;
+        let offset =
+            var.yoffset as usize * stride + var.xoffset as usize * format.bytes_per_pixel();
+
+        self.buffer.resize(var.yres as usize * stride, 0);
+        self.file.read_exact_at(&mut self.buffer, offset as u64)?;
+
+        let processor = FrameProcessor::new(&models::Framegrabber {
+            pixel_decimation: self
+                .config
+                .pixel_decimation
+                .max(var.xres / self.config.width.max(1)),
+            ..self.config.clone()
+        });
+
+        Ok(processor.process(&self.buffer, (var.xres, var.yres), stride, format))
+    }
+}
+
+/// Byte layout of the 24 and 32 bits RGB formats
+fn pixel_format(var: &FbVarScreeninfo) -> Option<PixelFormat> {
+    match (var.bits_per_pixel, var.red.offset, var.blue.offset) {
+        (24, 16, 0) => Some(PixelFormat::Bgr),
+        (24, 0, 16) => Some(PixelFormat::Rgb),
+        (32, 16, 0) => Some(PixelFormat::Bgrx),
+        (32, 0, 16) => Some(PixelFormat::Rgbx),
+        (32, 24, 8) => Some(PixelFormat::Xbgr),
+        (32, 8, 24) => Some(PixelFormat::Xrgb),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_format() {
+        let format = |bits_per_pixel, red, blue| {
+            pixel_format(&FbVarScreeninfo {
+                bits_per_pixel,
+                red: FbBitfield {
+                    offset: red,
+                    ..Default::default()
+                },
+                blue: FbBitfield {
+                    offset: blue,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        };
+
+        // ARGB8888 as used by the AMLogic user interface
+        assert_eq!(format(32, 16, 0), Some(PixelFormat::Bgrx));
+        assert_eq!(format(24, 0, 16), Some(PixelFormat::Rgb));
+        assert_eq!(format(16, 11, 0), None);
+    }
+}
@@ -0,0 +1,130 @@
+//! Audio capture for music visualizations
+//!
+//! A single capture runs while at least one instance shows the audio visualization, see
+//! [crate::global::Global::audio_capture]. ALSA is not linked at build time, it is loaded when the
+//! capture starts.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::models;
+
+mod alsa;
+
+mod analyzer;
+pub use analyzer::*;
+
+mod visualizer;
+pub use visualizer::*;
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    #[error("cannot load ALSA: {0}")]
+    Load(#[from] libloading::Error),
+    #[error("invalid device name: {0}")]
+    InvalidDevice(#[from] std::ffi::NulError),
+    #[error("{call} failed: {message}")]
+    Alsa { call: &'static str, message: String },
+}
+
+/// Time to wait after an error, before opening the device again
+const ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// Running audio capture, which stops when dropped
+pub struct AudioCapture {
+    device: String,
+    levels: watch::Receiver<Arc<Spectrum>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl AudioCapture {
+    /// Start capturing from the configured device on a dedicated thread
+    pub fn start(config: &models::GrabberAudio) -> Self {
+        let (tx, levels) = watch::channel(Arc::new(Spectrum::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        std::thread::Builder::new()
+            .name("audio-capture".to_owned())
+            .spawn({
+                let device = config.device.clone();
+                let bands = config.bands as usize;
+                let stop = stop.clone();
+
+                move || capture(&device, bands, &stop, &tx)
+            })
+            .expect("failed to spawn audio capture thread");
+
+        Self {
+            device: config.device.clone(),
+            levels,
+            stop,
+        }
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Receive the levels of the captured audio, updated for every analyzed frame
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Spectrum>> {
+        self.levels.clone()
+    }
+}
+
+impl Drop for AudioCapture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn capture(device: &str, bands: usize, stop: &AtomicBool, tx: &watch::Sender<Arc<Spectrum>>) {
+    let analyzer = Analyzer::new(alsa::RATE, bands);
+    let mut buffer = vec![0i16; FFT_SIZE * alsa::CHANNELS];
+    let mut samples = vec![0f32; FFT_SIZE];
+    let mut failed = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut pcm = match alsa::Pcm::open(device) {
+            Ok(pcm) => {
+                info!(device = %device, "audio capture started");
+                failed = false;
+                pcm
+            }
+            Err(error) => {
+                // Only report the first of repeated failures
+                if !failed {
+                    error!(device = %device, error = %error, "cannot open audio device");
+                    failed = true;
+                }
+
+                std::thread::sleep(ERROR_DELAY);
+                continue;
+            }
+        };
+
+        while !stop.load(Ordering::Relaxed) {
+            if let Err(error) = pcm.read(&mut buffer) {
+                error!(device = %device, error = %error, "audio capture failed");
+                std::thread::sleep(ERROR_DELAY);
+                break;
+            }
+
+            for (sample, frame) in samples.iter_mut().zip(buffer.chunks_exact(alsa::CHANNELS)) {
+                *sample = frame.iter().map(|&s| s as f32).sum::<f32>()
+                    / (alsa::CHANNELS as f32 * -(i16::MIN as f32));
+            }
+
+            tx.send_replace(Arc::new(analyzer.analyze(&samples)));
+        }
+    }
+
+    debug!(device = %device, "audio capture stopped");
+}
@@ -0,0 +1,161 @@
+//! Capture through ALSA, loaded when the capture starts
+
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void, CStr, CString};
+
+use libloading::Library;
+
+use super::AudioError;
+
+/// Sample rate of the capture, in Hz
+pub const RATE: u32 = 44100;
+
+/// Number of interleaved channels of the capture
+pub const CHANNELS: usize = 2;
+
+/// Buffering requested from ALSA, in microseconds
+const LATENCY_US: c_uint = 100_000;
+
+// Definitions from alsa/pcm.h
+
+const STREAM_CAPTURE: c_int = 1;
+const FORMAT_S16_LE: c_int = 2;
+const ACCESS_RW_INTERLEAVED: c_int = 3;
+
+type OpenFn = unsafe extern "C" fn(*mut *mut c_void, *const c_char, c_int, c_int) -> c_int;
+type SetParamsFn =
+    unsafe extern "C" fn(*mut c_void, c_int, c_int, c_uint, c_uint, c_int, c_uint) -> c_int;
+type ReadiFn = unsafe extern "C" fn(*mut c_void, *mut c_void, c_ulong) -> c_long;
+type RecoverFn = unsafe extern "C" fn(*mut c_void, c_int, c_int) -> c_int;
+type CloseFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type StrerrorFn = unsafe extern "C" fn(c_int) -> *const c_char;
+
+/// Loaded ALSA library
+struct Alsa {
+    open: OpenFn,
+    set_params: SetParamsFn,
+    readi: ReadiFn,
+    recover: RecoverFn,
+    close: CloseFn,
+    strerror: StrerrorFn,
+    // Must outlive the function pointers above
+    _library: Library,
+}
+
+impl Alsa {
+    const LIBRARY_NAMES: &'static [&'static str] = &["libasound.so.2", "libasound.so"];
+
+    fn load() -> Result<Self, AudioError> {
+        let mut last_error = None;
+
+        for name in Self::LIBRARY_NAMES {
+            // Safety: loading ALSA has no initialization side-effects
+            match unsafe { Library::new(name) } {
+                Ok(library) => {
+                    debug!(name = %name, "loaded ALSA library");
+
+                    // Safety: the symbol types match the declarations in alsa/pcm.h
+                    return unsafe {
+                        Ok(Self {
+                            open: *library.get(b"snd_pcm_open\0")?,
+                            set_params: *library.get(b"snd_pcm_set_params\0")?,
+                            readi: *library.get(b"snd_pcm_readi\0")?,
+                            recover: *library.get(b"snd_pcm_recover\0")?,
+                            close: *library.get(b"snd_pcm_close\0")?,
+                            strerror: *library.get(b"snd_strerror\0")?,
+                            _library: library,
+                        })
+                    };
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.expect("no ALSA library candidates").into())
+    }
+
+    fn check(&self, call: &'static str, result: c_int) -> Result<c_int, AudioError> {
+        if result >= 0 {
+            return Ok(result);
+        }
+
+        // Safety: snd_strerror returns a static string for any error code
+        let message = unsafe { CStr::from_ptr((self.strerror)(result)) };
+        Err(AudioError::Alsa {
+            call,
+            message: message.to_string_lossy().into_owned(),
+        })
+    }
+}
+
+/// Open capture device
+pub struct Pcm {
+    alsa: Alsa,
+    pcm: *mut c_void,
+}
+
+impl Pcm {
+    /// Open a capture device by name, `auto` being the default device
+    pub fn open(device: &str) -> Result<Self, AudioError> {
+        let alsa = Alsa::load()?;
+        let name = CString::new(if device == "auto" { "default" } else { device })?;
+
+        let mut pcm = std::ptr::null_mut();
+        // Safety: pcm is only used if the call succeeded, name is valid for the call
+        alsa.check("snd_pcm_open", unsafe {
+            (alsa.open)(&mut pcm, name.as_ptr(), STREAM_CAPTURE, 0)
+        })?;
+
+        let this = Self { alsa, pcm };
+
+        // Safety: pcm is an open device
+        this.alsa.check("snd_pcm_set_params", unsafe {
+            (this.alsa.set_params)(
+                this.pcm,
+                FORMAT_S16_LE,
+                ACCESS_RW_INTERLEAVED,
+                CHANNELS as c_uint,
+                RATE,
+                1,
+                LATENCY_US,
+            )
+        })?;
+
+        Ok(this)
+    }
+
+    /// Fill `buffer` with interleaved samples, recovering from overruns
+    pub fn read(&mut self, buffer: &mut [i16]) -> Result<(), AudioError> {
+        let mut offset = 0;
+
+        while offset < buffer.len() {
+            let remaining = &mut buffer[offset..];
+
+            // Safety: remaining holds the requested number of frames
+            let read = unsafe {
+                (self.alsa.readi)(
+                    self.pcm,
+                    remaining.as_mut_ptr() as *mut c_void,
+                    (remaining.len() / CHANNELS) as c_ulong,
+                )
+            };
+
+            if read < 0 {
+                // Safety: pcm is an open device
+                self.alsa.check("snd_pcm_readi", unsafe {
+                    (self.alsa.recover)(self.pcm, read as c_int, 1)
+                })?;
+            } else {
+                offset += read as usize * CHANNELS;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Pcm {
+    fn drop(&mut self) {
+        // Safety: pcm was opened by snd_pcm_open and is closed only once
+        unsafe { (self.alsa.close)(self.pcm) };
+    }
+}
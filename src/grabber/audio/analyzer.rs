@@ -0,0 +1,158 @@
+use std::f32::consts::PI;
+
+/// Number of samples analyzed at once, about 23ms of audio at 44.1kHz
+pub const FFT_SIZE: usize = 1024;
+
+/// Frequency range covered by the bands, in Hz
+const MIN_FREQUENCY: f32 = 50.;
+const MAX_FREQUENCY: f32 = 16000.;
+
+/// Dynamic range of the band levels: quieter sounds are at level 0, in dB below full scale
+const DB_RANGE: f32 = 60.;
+
+/// Levels of the latest captured audio
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spectrum {
+    /// RMS volume, between 0 and 1
+    pub volume: f32,
+    /// Level of each frequency band from the lowest to the highest, between 0 and 1
+    pub bands: Vec<f32>,
+}
+
+/// Computes the spectrum of mono audio frames of [FFT_SIZE] samples
+#[derive(Debug)]
+pub struct Analyzer {
+    window: Vec<f32>,
+    window_sum: f32,
+    /// Range of FFT bins of each band
+    bands: Vec<(usize, usize)>,
+}
+
+impl Analyzer {
+    pub fn new(rate: u32, bands: usize) -> Self {
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        let max_frequency = MAX_FREQUENCY.min(rate as f32 / 2.);
+        let bin = |band: usize| {
+            let frequency =
+                MIN_FREQUENCY * (max_frequency / MIN_FREQUENCY).powf(band as f32 / bands as f32);
+            ((frequency * FFT_SIZE as f32 / rate as f32).round() as usize).min(FFT_SIZE / 2)
+        };
+
+        Self {
+            window_sum: window.iter().sum(),
+            window,
+            bands: (0..bands)
+                .map(|band| {
+                    let start = bin(band).min(FFT_SIZE / 2 - 1);
+                    (start, bin(band + 1).max(start + 1))
+                })
+                .collect(),
+        }
+    }
+
+    /// Analyze samples between -1 and 1
+    pub fn analyze(&self, samples: &[f32]) -> Spectrum {
+        assert_eq!(samples.len(), FFT_SIZE, "invalid audio frame size");
+
+        let volume = (samples.iter().map(|s| s * s).sum::<f32>() / FFT_SIZE as f32).sqrt();
+
+        let mut re: Vec<f32> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, weight)| sample * weight)
+            .collect();
+        let mut im = vec![0.; FFT_SIZE];
+        fft(&mut re, &mut im);
+
+        let bands = self
+            .bands
+            .iter()
+            .map(|&(start, end)| {
+                let amplitude = (start..end)
+                    .map(|k| 2. * (re[k] * re[k] + im[k] * im[k]).sqrt() / self.window_sum)
+                    .fold(0., f32::max);
+
+                ((20. * amplitude.log10() + DB_RANGE) / DB_RANGE).clamp(0., 1.)
+            })
+            .collect();
+
+        Spectrum { volume, bands }
+    }
+}
+
+/// In-place radix-2 FFT, the length must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2. * PI / len as f32;
+
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let analyzer = Analyzer::new(44100, 16);
+
+        let silence = analyzer.analyze(&[0.; FFT_SIZE]);
+        assert_eq!(silence.volume, 0.);
+        assert!(silence.bands.iter().all(|&level| level == 0.));
+
+        let sine: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| (2. * PI * 1000. * i as f32 / 44100.).sin())
+            .collect();
+        let spectrum = analyzer.analyze(&sine);
+        assert!((spectrum.volume - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+
+        // A full-scale tone peaks in its band, at full level
+        let (loudest, &level) = spectrum
+            .bands
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        let (start, end) = analyzer.bands[loudest];
+        let bin = 1000. * FFT_SIZE as f32 / 44100.;
+        assert!(start as f32 <= bin.ceil() && bin.floor() < end as f32);
+        assert!(level > 0.95);
+    }
+}
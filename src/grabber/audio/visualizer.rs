@@ -0,0 +1,151 @@
+use std::convert::TryFrom;
+
+use super::Spectrum;
+use crate::{
+    image::RawImage,
+    models::{self, AudioEffect, AudioFlip, Color},
+};
+
+/// Decay of the peak level tracked by the automatic gain, per rendered frame
+const PEAK_DECAY: f32 = 0.998;
+
+/// Lowest peak level the automatic gain adjusts to, so silence isn't amplified to full scale
+const MIN_PEAK: f32 = 0.01;
+
+/// Renders the captured audio levels to images
+#[derive(Debug)]
+pub struct Visualizer {
+    config: models::GrabberAudio,
+    width: u32,
+    height: u32,
+    /// Recent peak level, for the automatic gain
+    peak: f32,
+}
+
+impl Visualizer {
+    pub fn new(config: models::GrabberAudio, (width, height): (u32, u32)) -> Self {
+        Self {
+            config,
+            width: width.max(1),
+            height: height.max(1),
+            peak: MIN_PEAK,
+        }
+    }
+
+    pub fn render(&mut self, spectrum: &Spectrum) -> RawImage {
+        let levels: Vec<f32> = match self.config.audio_effect {
+            AudioEffect::VuMeter => {
+                let gain = self.gain(spectrum.volume);
+                vec![spectrum.volume * gain]
+            }
+            AudioEffect::Spectrum => {
+                let gain = self.gain(spectrum.bands.iter().copied().fold(0., f32::max));
+                spectrum.bands.iter().map(|level| level * gain).collect()
+            }
+        };
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut data = vec![0; width * height * RawImage::CHANNELS as usize];
+
+        for x in 0..width {
+            let level = match levels.get(x * levels.len() / width) {
+                Some(level) => level.clamp(0., 1.),
+                None => 0.,
+            };
+
+            // Rows from the bottom of the image
+            for row in 0..(level * height as f32).round() as usize {
+                let color = self.color((row + 1) as f32 / height as f32);
+
+                let (x, y) = match self.config.vu_meter.flip {
+                    AudioFlip::NoChange => (x, height - 1 - row),
+                    AudioFlip::Horizontal => (width - 1 - x, height - 1 - row),
+                    AudioFlip::Vertical => (x, row),
+                    AudioFlip::Both => (width - 1 - x, row),
+                };
+
+                let offset = (y * width + x) * RawImage::CHANNELS as usize;
+                let (r, g, b) = color.into_components();
+                data[offset..offset + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+
+        // unwrap: the buffer matches the image size
+        RawImage::try_from((data, self.width, self.height)).unwrap()
+    }
+
+    /// Gain applied to levels whose loudest one is `level`
+    fn gain(&mut self, level: f32) -> f32 {
+        let config = &self.config.vu_meter;
+        if config.multiplier > 0. {
+            return config.multiplier;
+        }
+
+        self.peak = level.max(self.peak * PEAK_DECAY).max(MIN_PEAK);
+        (100 - config.tolerance.min(100)) as f32 / 100. / self.peak
+    }
+
+    /// Color of the meter at `level`, between 0 and 1
+    fn color(&self, level: f32) -> Color {
+        let config = &self.config.vu_meter;
+        let percent = level * 100.;
+
+        if percent > config.warn_value as f32 {
+            config.hot_color
+        } else if percent > config.safe_value as f32 {
+            config.warn_color
+        } else {
+            config.safe_color
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+
+    fn column(image: &RawImage, x: u16) -> Vec<Color> {
+        (0..image.height())
+            .map(|y| image.color_at(x, y).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_vu_meter() {
+        let mut visualizer = Visualizer::new(Default::default(), (2, 10));
+        let image = visualizer.render(&Spectrum {
+            volume: 0.6,
+            bands: vec![],
+        });
+
+        let (black, green, yellow) = (
+            Color::new(0, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(255, 255, 0),
+        );
+        let expected = [vec![black; 4], vec![yellow; 2], vec![green; 4]].concat();
+        assert_eq!(column(&image, 0), expected);
+        assert_eq!(column(&image, 1), expected);
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let mut config = models::GrabberAudio {
+            audio_effect: AudioEffect::Spectrum,
+            ..Default::default()
+        };
+        config.vu_meter.flip = AudioFlip::Both;
+
+        let mut visualizer = Visualizer::new(config, (4, 4));
+        let image = visualizer.render(&Spectrum {
+            volume: 0.,
+            bands: vec![1., 0.],
+        });
+
+        // The first band is on the right, growing from the top
+        assert_eq!(column(&image, 0)[0], Color::new(0, 0, 0));
+        assert_eq!(column(&image, 3)[0], Color::new(0, 255, 0));
+        assert_eq!(column(&image, 3)[3], Color::new(255, 0, 0));
+    }
+}
@@ -0,0 +1,225 @@
+//! Screen capture from the KMS/DRM scanout buffer
+//!
+//! Without a desktop session, such as Kodi running in GBM mode, the framebuffer shown by a CRTC is
+//! read directly from the DRM device. Only linear RGB framebuffers are supported, like the primary
+//! plane of the Raspberry Pi. Reading another client's framebuffer needs the `CAP_SYS_ADMIN`
+//! capability.
+
+use std::{
+    fs::{File, OpenOptions},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+    path::{Path, PathBuf},
+    ptr::null_mut,
+};
+
+use drm::{
+    buffer::{DrmFourcc, DrmModifier},
+    control::{crtc, Device as ControlDevice},
+    Device,
+};
+use thiserror::Error;
+
+use super::{
+    polling::PollingGrabber,
+    screen::{FrameProcessor, PixelFormat},
+};
+use crate::{image::RawImage, models};
+
+#[derive(Debug, Error)]
+pub enum KmsError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("no active display {0} on the DRM devices")]
+    NoDisplay(u32),
+    #[error("cannot access the framebuffer, CAP_SYS_ADMIN is required")]
+    NoHandle,
+    #[error("unsupported pixel format {0}")]
+    UnsupportedFormat(DrmFourcc),
+    #[error("unsupported format modifier {0:?}, only linear framebuffers can be read")]
+    UnsupportedModifier(DrmModifier),
+}
+
+/// Directory of the DRM device nodes
+const DRI_PATH: &str = "/dev/dri";
+
+/// `DMA_BUF_IOCTL_SYNC` and its flags, from linux/dma-buf.h
+const DMA_BUF_IOCTL_SYNC: u64 = 0x4008_6200;
+const DMA_BUF_SYNC_READ: u64 = 1 << 0;
+const DMA_BUF_SYNC_START: u64 = 0 << 2;
+const DMA_BUF_SYNC_END: u64 = 1 << 2;
+
+/// Whether this system has DRM devices to capture from
+pub fn is_available() -> bool {
+    Path::new(DRI_PATH).is_dir()
+}
+
+/// Opened DRM device node
+struct Card(File);
+
+impl AsFd for Card {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl Device for Card {}
+impl ControlDevice for Card {}
+
+/// Reader of the framebuffer shown by a CRTC
+pub struct KmsGrabber {
+    card: Card,
+    crtc: crtc::Handle,
+    processor: FrameProcessor,
+}
+
+impl PollingGrabber for KmsGrabber {
+    type Error = KmsError;
+
+    /// Find the `display`-th CRTC showing a framebuffer, over all the DRM devices
+    fn open(config: &models::Framegrabber) -> Result<Self, KmsError> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(DRI_PATH)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("card"))
+            })
+            .collect();
+        paths.sort();
+
+        let mut index = 0;
+        for path in paths {
+            let card = Card(OpenOptions::new().read(true).write(true).open(&path)?);
+
+            // Devices without modesetting, such as render-only GPUs, have no resources
+            let crtcs = match card.resource_handles() {
+                Ok(resources) => resources.crtcs().to_vec(),
+                Err(_) => continue,
+            };
+
+            for crtc in crtcs {
+                if card.get_crtc(crtc)?.framebuffer().is_none() {
+                    continue;
+                }
+
+                if index == config.display {
+                    info!(card = %path.display(), crtc = ?crtc, "screen capture started");
+                    return Ok(Self {
+                        card,
+                        crtc,
+                        processor: FrameProcessor::new(config),
+                    });
+                }
+
+                index += 1;
+            }
+        }
+
+        Err(KmsError::NoDisplay(config.display))
+    }
+
+    /// Read the current framebuffer, or `None` if the CRTC is not showing any
+    fn grab(&mut self) -> Result<Option<RawImage>, KmsError> {
+        let handle = match self.card.get_crtc(self.crtc)?.framebuffer() {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+
+        let info = self.card.get_planar_framebuffer(handle)?;
+        let format = pixel_format(info.pixel_format())
+            .ok_or(KmsError::UnsupportedFormat(info.pixel_format()))?;
+        if let Some(modifier) = info.modifier() {
+            if modifier != DrmModifier::Linear {
+                return Err(KmsError::UnsupportedModifier(modifier));
+            }
+        }
+
+        // Without CAP_SYS_ADMIN, the kernel doesn't give out buffer handles
+        let buffer = info.buffers()[0].ok_or(KmsError::NoHandle)?;
+        let fd = self.card.buffer_to_prime_fd(buffer, libc::O_CLOEXEC as u32);
+        self.card.close_buffer(buffer)?;
+
+        let size = info.size();
+        let (pitch, offset) = (info.pitches()[0] as usize, info.offsets()[0] as usize);
+        let mapping = Mapping::new(fd?, offset + pitch * size.1 as usize)?;
+
+        Ok(mapping.read(|data| self.processor.process(&data[offset..], size, pitch, format)))
+    }
+}
+
+/// Pixel layout of the single plane RGB formats, stored little-endian
+fn pixel_format(fourcc: DrmFourcc) -> Option<PixelFormat> {
+    match fourcc {
+        DrmFourcc::Xrgb8888 | DrmFourcc::Argb8888 => Some(PixelFormat::Bgrx),
+        DrmFourcc::Xbgr8888 | DrmFourcc::Abgr8888 => Some(PixelFormat::Rgbx),
+        DrmFourcc::Rgbx8888 | DrmFourcc::Rgba8888 => Some(PixelFormat::Xbgr),
+        DrmFourcc::Bgrx8888 | DrmFourcc::Bgra8888 => Some(PixelFormat::Xrgb),
+        _ => None,
+    }
+}
+
+/// Read-only mapping of an exported DMA buffer, unmapped when dropped
+struct Mapping {
+    fd: OwnedFd,
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: OwnedFd, len: usize) -> Result<Self, KmsError> {
+        // Safety: the kernel checks the length against the buffer size
+        let ptr = unsafe {
+            libc::mmap(
+                null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Self { fd, ptr, len })
+    }
+
+    /// Access the buffer contents, synchronized with the GPU caches
+    fn read<T>(&self, f: impl FnOnce(&[u8]) -> T) -> T {
+        self.sync(DMA_BUF_SYNC_START);
+        // Safety: the mapping is valid for len bytes until dropped
+        let result = f(unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) });
+        self.sync(DMA_BUF_SYNC_END);
+
+        result
+    }
+
+    fn sync(&self, flags: u64) {
+        let flags = flags | DMA_BUF_SYNC_READ;
+        // Safety: the argument is a struct dma_buf_sync, made of its flags. Drivers which
+        // don't need synchronization may fail, which is harmless.
+        unsafe { libc::ioctl(self.fd.as_raw_fd(), DMA_BUF_IOCTL_SYNC as _, &flags) };
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Safety: ptr and len describe the mapping created in new
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_format() {
+        // XRGB8888 is stored as B, G, R, X in memory
+        assert_eq!(pixel_format(DrmFourcc::Xrgb8888), Some(PixelFormat::Bgrx));
+        assert_eq!(pixel_format(DrmFourcc::Bgra8888), Some(PixelFormat::Xrgb));
+        assert_eq!(pixel_format(DrmFourcc::Nv12), None);
+    }
+}
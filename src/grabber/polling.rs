@@ -0,0 +1,81 @@
+//! Capture loop of the grabbers reading the screen device with blocking calls
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::{image::RawImage, models};
+
+/// Time to wait after an error, before opening the device again
+const ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// Grabber polled for frames on the blocking thread pool
+pub trait PollingGrabber: Sized + Send + 'static {
+    type Error: std::fmt::Display + Send + 'static;
+
+    fn open(config: &models::Framegrabber) -> Result<Self, Self::Error>;
+
+    /// Read the current frame, or `None` if the display is off
+    fn grab(&mut self) -> Result<Option<RawImage>, Self::Error>;
+}
+
+/// Capture the screen until the task is aborted, sending the processed frames to `tx`
+///
+/// The grabber is polled `frequency_Hz` times per second, and opened again after errors.
+pub async fn capture<G: PollingGrabber>(
+    config: models::Framegrabber,
+    tx: watch::Sender<Option<Arc<RawImage>>>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / config.frequency_hz.max(1));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let config = Arc::new(config);
+    let mut grabber = None;
+    let mut failed = false;
+
+    loop {
+        interval.tick().await;
+
+        let current = grabber.take();
+        let config = config.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut grabber = match current {
+                Some(grabber) => grabber,
+                None => G::open(&config)?,
+            };
+
+            let image = grabber.grab()?;
+            Ok::<_, G::Error>((grabber, image))
+        })
+        .await
+        .expect("screen capture panicked");
+
+        match result {
+            Ok((current, image)) => {
+                grabber = Some(current);
+                failed = false;
+
+                match image {
+                    Some(image) => {
+                        tx.send_replace(Some(Arc::new(image)));
+                    }
+                    None => {
+                        // The display is off, only notify once
+                        tx.send_if_modified(|frame| frame.take().is_some());
+                    }
+                }
+            }
+            Err(error) => {
+                tx.send_replace(None);
+
+                // Only report the first of repeated failures
+                if !failed {
+                    error!(error = %error, "screen capture failed");
+                    failed = true;
+                }
+
+                tokio::time::sleep(ERROR_DELAY).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Screen capture
+//!
+//! A single capture runs while at least one instance shows the screen, see
+//! [crate::global::Global::screen_capture]. The grabber is selected by the `type` of the
+//! `framegrabber` settings.
+
+use std::{convert::TryFrom, sync::Arc};
+
+use thiserror::Error;
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    global::Paths,
+    image::RawImage,
+    models::{self, FramegrabberType},
+};
+
+#[derive(Debug, Error)]
+pub enum ScreenCaptureError {
+    #[error("{0:?} screen capture is not supported by this build")]
+    Unsupported(FramegrabberType),
+}
+
+/// Byte layouts of captured pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Bgr,
+    Rgb,
+    Bgrx,
+    Rgbx,
+    Xbgr,
+    Xrgb,
+}
+
+impl PixelFormat {
+    /// Offsets of the red, green and blue bytes of a pixel
+    fn offsets(self) -> [usize; 3] {
+        match self {
+            PixelFormat::Bgr | PixelFormat::Bgrx => [2, 1, 0],
+            PixelFormat::Rgb | PixelFormat::Rgbx => [0, 1, 2],
+            PixelFormat::Xbgr => [3, 2, 1],
+            PixelFormat::Xrgb => [1, 2, 3],
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgr | PixelFormat::Rgb => 3,
+            _ => 4,
+        }
+    }
+}
+
+/// Crops and decimates captured frames according to the framegrabber settings
+#[derive(Debug, Clone)]
+pub struct FrameProcessor {
+    crop_left: u32,
+    crop_right: u32,
+    crop_top: u32,
+    crop_bottom: u32,
+    pixel_decimation: u32,
+}
+
+impl FrameProcessor {
+    pub fn new(config: &models::Framegrabber) -> Self {
+        Self {
+            crop_left: config.crop_left,
+            crop_right: config.crop_right,
+            crop_top: config.crop_top,
+            crop_bottom: config.crop_bottom,
+            pixel_decimation: config.pixel_decimation.max(1),
+        }
+    }
+
+    /// Convert a frame of `width` by `height` pixels stored in rows of `stride` bytes
+    ///
+    /// One pixel is kept for every block of `pixel_decimation` pixels. Returns `None` if the crop
+    /// leaves nothing of the frame, or if `data` is too short for its size.
+    pub fn process(
+        &self,
+        data: &[u8],
+        (width, height): (u32, u32),
+        stride: usize,
+        format: PixelFormat,
+    ) -> Option<RawImage> {
+        let left = self.crop_left.min(width);
+        let width = width - left - self.crop_right.min(width - left);
+        let top = self.crop_top.min(height);
+        let height = height - top - self.crop_bottom.min(height - top);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let step = self.pixel_decimation;
+        let (out_width, out_height) = (width.div_ceil(step), height.div_ceil(step));
+        let offsets = format.offsets();
+        let bpp = format.bytes_per_pixel();
+        let mut out = Vec::with_capacity((out_width * out_height) as usize * 3);
+
+        for oy in 0..out_height {
+            // Sample the center of each block
+            let y = (top + oy * step + step / 2).min(top + height - 1) as usize;
+
+            for ox in 0..out_width {
+                let x = (left + ox * step + step / 2).min(left + width - 1) as usize;
+                let pixel = data.get(y * stride + x * bpp..y * stride + (x + 1) * bpp)?;
+                out.extend(offsets.iter().map(|&offset| pixel[offset]));
+            }
+        }
+
+        // unwrap: the buffer matches the image size
+        Some(RawImage::try_from((out, out_width, out_height)).unwrap())
+    }
+}
+
+/// Running screen capture, which stops when dropped
+pub struct ScreenCapture {
+    name: &'static str,
+    frames: watch::Receiver<Option<Arc<RawImage>>>,
+    join_handle: JoinHandle<()>,
+}
+
+impl ScreenCapture {
+    /// Start the grabber selected by `config`
+    ///
+    /// Returns `None` if the grabber type is `auto` and no grabber is usable in this environment.
+    pub fn start(
+        config: &models::Framegrabber,
+        paths: &Paths,
+    ) -> Result<Option<Self>, ScreenCaptureError> {
+        let ty = match config.ty {
+            FramegrabberType::Auto => match Self::detect() {
+                Some(ty) => ty,
+                None => return Ok(None),
+            },
+            ty => ty,
+        };
+
+        let (tx, frames) = watch::channel(None);
+        let (name, join_handle) = Self::spawn(ty, config, paths, tx)?;
+
+        Ok(Some(Self {
+            name,
+            frames,
+            join_handle,
+        }))
+    }
+
+    /// Spawn the capture task of a grabber type, with its name
+    fn spawn(
+        ty: FramegrabberType,
+        config: &models::Framegrabber,
+        paths: &Paths,
+        tx: watch::Sender<Option<Arc<RawImage>>>,
+    ) -> Result<(&'static str, JoinHandle<()>), ScreenCaptureError> {
+        match ty {
+            #[cfg(feature = "wayland")]
+            FramegrabberType::Wayland => Ok((
+                "Wayland",
+                tokio::spawn(super::wayland::capture(config.clone(), paths.clone(), tx)),
+            )),
+            #[cfg(feature = "amlogic")]
+            FramegrabberType::AMLogic => Ok((
+                "AmLogic",
+                tokio::spawn(super::polling::capture::<super::amlogic::AmlogicGrabber>(
+                    config.clone(),
+                    tx,
+                )),
+            )),
+            #[cfg(feature = "kms")]
+            FramegrabberType::Drm => Ok((
+                "DRM",
+                tokio::spawn(super::polling::capture::<super::kms::KmsGrabber>(
+                    config.clone(),
+                    tx,
+                )),
+            )),
+            ty => {
+                // Only used by the grabbers enabled in this build
+                let _ = (config, paths, tx);
+                Err(ScreenCaptureError::Unsupported(ty))
+            }
+        }
+    }
+
+    /// Grabber type to use for `auto`, from the running desktop session
+    fn detect() -> Option<FramegrabberType> {
+        #[cfg(feature = "wayland")]
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            return Some(FramegrabberType::Wayland);
+        }
+
+        #[cfg(feature = "amlogic")]
+        if super::amlogic::is_available() {
+            return Some(FramegrabberType::AMLogic);
+        }
+
+        // Without any desktop session, read the display directly
+        #[cfg(feature = "kms")]
+        if std::env::var_os("WAYLAND_DISPLAY").is_none()
+            && std::env::var_os("DISPLAY").is_none()
+            && super::kms::is_available()
+        {
+            return Some(FramegrabberType::Drm);
+        }
+
+        None
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Receive the captured frames, `None` while the capture is not running
+    pub fn subscribe(&self) -> watch::Receiver<Option<Arc<RawImage>>> {
+        self.frames.clone()
+    }
+}
+
+impl Drop for ScreenCapture {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{image::Image, models::Color};
+
+    #[test]
+    fn test_process() {
+        // 4x2 BGRx frame with padded rows, the pixel at (x, y) has red x and green y
+        let stride = 20;
+        let mut data = vec![0xff; stride * 2];
+        for y in 0..2 {
+            for x in 0..4 {
+                data[y * stride + x * 4..y * stride + x * 4 + 3]
+                    .copy_from_slice(&[7, y as u8, x as u8]);
+            }
+        }
+
+        let processor = FrameProcessor::new(&models::Framegrabber {
+            crop_left: 1,
+            pixel_decimation: 2,
+            ..Default::default()
+        });
+        let image = processor
+            .process(&data, (4, 2), stride, PixelFormat::Bgrx)
+            .unwrap();
+
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.color_at(0, 0), Some(Color::new(2, 1, 7)));
+        assert_eq!(image.color_at(1, 0), Some(Color::new(3, 1, 7)));
+
+        // Cropping everything leaves no image, truncated frames are rejected
+        let processor = FrameProcessor::new(&models::Framegrabber {
+            crop_left: 2,
+            crop_right: 2,
+            ..Default::default()
+        });
+        assert!(processor
+            .process(&data, (4, 2), stride, PixelFormat::Bgrx)
+            .is_none());
+        assert!(FrameProcessor::new(&Default::default())
+            .process(&data[..30], (4, 2), stride, PixelFormat::Bgrx)
+            .is_none());
+
+        // Packed 3 bytes formats
+        let image = FrameProcessor::new(&Default::default())
+            .process(&[1, 2, 3, 4, 5, 6], (2, 1), 6, PixelFormat::Bgr)
+            .unwrap();
+        assert_eq!(image.color_at(1, 0), Some(Color::new(6, 5, 4)));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    global::InputMessageData,
+    image::{Image, RawImage},
+    models::{Color, GrabberV4L2},
+};
+
+/// Time without signal after which the grabber priority is cleared
+const NO_SIGNAL_DELAY: Duration = Duration::from_secs(3);
+
+/// Result of the signal detection for a captured frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalState {
+    /// The frame has a signal and should be forwarded
+    Signal,
+    /// The signal was just lost, the grabber priority should be cleared
+    Lost,
+    /// There still is no signal, the frame should be dropped
+    NoSignal,
+}
+
+/// Detects capture devices outputting "no signal" frames
+///
+/// Video capture devices usually keep producing frames when their source is turned off, either
+/// black or with a uniform background. Frames where no pixel of the detection area exceeds the
+/// configured thresholds count as "no signal".
+#[derive(Debug)]
+pub struct SignalDetector {
+    threshold: Color,
+    /// Detection area, as fractions of the frame width and height
+    x: (f32, f32),
+    y: (f32, f32),
+    /// Number of consecutive "no signal" frames before the signal is considered lost
+    max_no_signal_frames: u32,
+    no_signal_frames: u32,
+}
+
+impl SignalDetector {
+    /// Create a signal detector for the given grabber settings, if signal detection is enabled
+    pub fn new(config: &GrabberV4L2) -> Option<Self> {
+        if !config.signal_detection {
+            return None;
+        }
+
+        let threshold = |percent: u32| (percent.min(100) * 255 / 100) as u8;
+
+        Some(Self {
+            threshold: Color::new(
+                threshold(config.red_signal_threshold),
+                threshold(config.green_signal_threshold),
+                threshold(config.blue_signal_threshold),
+            ),
+            x: (config.sdh_offset_min, config.sdh_offset_max),
+            y: (config.sdv_offset_min, config.sdv_offset_max),
+            max_no_signal_frames: (config.fps as f32 * NO_SIGNAL_DELAY.as_secs_f32()).ceil() as u32,
+            no_signal_frames: 0,
+        })
+    }
+
+    /// Returns true if the signal is currently considered lost
+    pub fn is_lost(&self) -> bool {
+        self.no_signal_frames >= self.max_no_signal_frames
+    }
+
+    /// Returns true if any pixel of the detection area is above the thresholds
+    fn has_signal(&self, image: &impl Image) -> bool {
+        let range = |(min, max): (f32, f32), size: u16| {
+            let start = ((min.min(max) * size as f32).floor() as u16).min(size);
+            let end = ((min.max(max) * size as f32).ceil() as u16).clamp(start, size);
+            start..end
+        };
+
+        let xs = range(self.x, image.width());
+        let ys = range(self.y, image.height());
+
+        ys.into_iter().any(|y| {
+            xs.clone().any(|x| {
+                // Safety: x (resp. y) are necessarily in 0..width (resp. 0..height)
+                let color = unsafe { image.color_at_unchecked(x, y) };
+                color.red > self.threshold.red
+                    || color.green > self.threshold.green
+                    || color.blue > self.threshold.blue
+            })
+        })
+    }
+
+    /// Process a captured frame
+    pub fn process(&mut self, image: &impl Image) -> SignalState {
+        if self.has_signal(image) {
+            if self.is_lost() {
+                info!("capture signal detected");
+            }
+
+            self.no_signal_frames = 0;
+            return SignalState::Signal;
+        }
+
+        if self.is_lost() {
+            return SignalState::NoSignal;
+        }
+
+        self.no_signal_frames += 1;
+        if self.is_lost() {
+            info!("capture signal lost");
+            SignalState::Lost
+        } else {
+            // Keep forwarding frames until the signal is considered lost, short black scenes
+            // are not a lost signal
+            SignalState::Signal
+        }
+    }
+
+    /// Turn a captured frame into the message to send on the grabber input source
+    ///
+    /// # Returns
+    ///
+    /// The image message while there is a signal, a message clearing the grabber priority when
+    /// the signal is lost so lower priorities (e.g. background effects) are visible again, and
+    /// `None` while there is no signal.
+    pub fn filter(&mut self, priority: i32, image: Arc<RawImage>) -> Option<InputMessageData> {
+        match self.process(&*image) {
+            SignalState::Signal => Some(InputMessageData::Image {
+                priority,
+                duration: None,
+                image,
+                region: None,
+            }),
+            SignalState::Lost => Some(InputMessageData::Clear { priority }),
+            SignalState::NoSignal => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn frame(center: u8) -> RawImage {
+        // 4x4 frame with a bright border, only the center is in the default detection area
+        let mut data = vec![255; 4 * 4 * 3];
+        for y in 1..3 {
+            for x in 1..3 {
+                let idx = (y * 4 + x) * 3;
+                data[idx..idx + 3].copy_from_slice(&[center, center, center]);
+            }
+        }
+
+        RawImage::try_from((data, 4, 4)).unwrap()
+    }
+
+    #[test]
+    fn test_signal_detection() {
+        let config = GrabberV4L2 {
+            signal_detection: true,
+            fps: 1,
+            ..Default::default()
+        };
+
+        assert!(SignalDetector::new(&GrabberV4L2::default()).is_none());
+
+        let mut detector = SignalDetector::new(&config).unwrap();
+        assert_eq!(detector.process(&frame(200)), SignalState::Signal);
+
+        // Below the 5% threshold, tolerated for 3 frames at 1 FPS
+        assert_eq!(detector.process(&frame(12)), SignalState::Signal);
+        assert_eq!(detector.process(&frame(0)), SignalState::Signal);
+        assert_eq!(detector.process(&frame(0)), SignalState::Lost);
+        assert_eq!(detector.process(&frame(0)), SignalState::NoSignal);
+
+        assert_eq!(detector.process(&frame(13)), SignalState::Signal);
+        assert!(!detector.is_lost());
+    }
+}
@@ -0,0 +1,119 @@
+//! Screen capture on Wayland desktops
+//!
+//! Wayland compositors don't let clients read the screen directly: the ScreenCast interface of
+//! xdg-desktop-portal asks the user which monitor to share, and the frames are then received from
+//! a PipeWire stream. PipeWire is not linked at build time, it is loaded when the capture starts.
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::sync::{mpsc, watch};
+
+use super::screen::FrameProcessor;
+use crate::{global::Paths, image::RawImage, models};
+
+mod pipewire;
+mod pod;
+mod portal;
+
+#[derive(Debug, Error)]
+pub enum WaylandError {
+    #[error("D-Bus error: {0}")]
+    DBus(#[from] zbus::Error),
+    #[error("invalid D-Bus value: {0}")]
+    Variant(#[from] zbus::zvariant::Error),
+    #[error("{method} failed: {message}")]
+    Portal {
+        method: &'static str,
+        message: String,
+    },
+    #[error("screen sharing was cancelled")]
+    Cancelled,
+    #[error("cannot load PipeWire: {0}")]
+    Load(#[from] libloading::Error),
+    #[error("unsupported PipeWire version {0}")]
+    UnsupportedVersion(String),
+    #[error("{call} failed: {message}")]
+    PipeWire { call: &'static str, message: String },
+    #[error("stream error: {0}")]
+    Stream(String),
+}
+
+/// Where the token to restore the shared monitor is kept between runs
+const RESTORE_TOKEN_PATH: &str = "$ROOT/wayland-restore-token";
+
+/// Time to wait after an error, before starting a new session
+const ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// Capture the screen until the task is aborted, sending the processed frames to `tx`
+///
+/// Failed sessions are started again, unless the user cancelled or stopped the screen sharing.
+pub async fn capture(
+    config: models::Framegrabber,
+    paths: Paths,
+    tx: watch::Sender<Option<Arc<RawImage>>>,
+) {
+    let token_path = paths.resolve_path(RESTORE_TOKEN_PATH);
+    let mut failed = false;
+
+    loop {
+        let result = run(&config, &token_path, &tx).await;
+        tx.send_replace(None);
+
+        match result {
+            Ok(()) => {
+                info!("screen sharing was stopped");
+                break;
+            }
+            Err(WaylandError::Cancelled) => {
+                warn!("screen sharing was cancelled, the screen will not be captured");
+                break;
+            }
+            Err(error) => {
+                // Only report the first of repeated failures
+                if !failed {
+                    error!(error = %error, "screen capture failed");
+                    failed = true;
+                }
+            }
+        }
+
+        tokio::time::sleep(ERROR_DELAY).await;
+    }
+}
+
+/// Run a screen cast session until its stream ends
+async fn run(
+    config: &models::Framegrabber,
+    token_path: &Path,
+    tx: &watch::Sender<Option<Arc<RawImage>>>,
+) -> Result<(), WaylandError> {
+    let restore_token = tokio::fs::read_to_string(token_path)
+        .await
+        .ok()
+        .map(|token| token.trim().to_owned())
+        .filter(|token| !token.is_empty());
+
+    let session = portal::Session::start(restore_token).await?;
+
+    if let Some(token) = &session.restore_token {
+        if let Err(error) = tokio::fs::write(token_path, token).await {
+            warn!(path = %token_path.display(), error = %error, "cannot save screen sharing token");
+        }
+    }
+
+    let (ended_tx, mut ended) = mpsc::unbounded_channel();
+    let _stream = pipewire::Stream::connect(
+        session.fd,
+        session.node_id,
+        FrameProcessor::new(config),
+        config.frequency_hz,
+        tx.clone(),
+        ended_tx,
+    )?;
+
+    match ended.recv().await.flatten() {
+        Some(error) => Err(WaylandError::Stream(error)),
+        None => Ok(()),
+    }
+}
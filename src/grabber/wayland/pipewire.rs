@@ -0,0 +1,562 @@
+//! PipeWire video stream, loaded when the capture starts
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr},
+    os::fd::{IntoRawFd, OwnedFd},
+    ptr::null_mut,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use libloading::Library;
+use tokio::sync::{mpsc, watch};
+
+use super::{pod, WaylandError};
+use crate::{
+    grabber::screen::{FrameProcessor, PixelFormat},
+    image::RawImage,
+};
+
+// Definitions from pipewire/stream.h and spa/buffer/buffer.h
+
+const DIRECTION_INPUT: c_int = 0;
+const STREAM_FLAG_AUTOCONNECT: c_int = 1 << 0;
+const STREAM_FLAG_MAP_BUFFERS: c_int = 1 << 2;
+
+const STREAM_STATE_ERROR: c_int = -1;
+const STREAM_STATE_UNCONNECTED: c_int = 0;
+const STREAM_STATE_STREAMING: c_int = 3;
+
+/// Formats offered to the compositor, in order of preference, with their pixel layout
+const FORMATS: &[(u32, PixelFormat)] = &[
+    (8, PixelFormat::Bgrx),
+    (7, PixelFormat::Rgbx),
+    (12, PixelFormat::Bgrx),
+    (11, PixelFormat::Rgbx),
+    (10, PixelFormat::Xbgr),
+    (9, PixelFormat::Xrgb),
+    (14, PixelFormat::Xbgr),
+    (13, PixelFormat::Xrgb),
+];
+
+/// Versions of libpipewire-0.3 the layouts below were checked against, the upper bound excluded
+///
+/// They are the version 0 layouts of the PipeWire 0.3 headers, which later releases only extend:
+/// `StreamEvents` is passed with `version: 0`, so PipeWire doesn't read the fields added since.
+const SUPPORTED_VERSIONS: std::ops::Range<(u32, u32)> = (0, 3)..(2, 0);
+
+// Layouts from the C headers, the fields not read here are used by PipeWire
+
+#[repr(C)]
+struct SpaHook {
+    _fields: [*mut c_void; 6],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct StreamEvents {
+    version: u32,
+    destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+    state_changed: Option<unsafe extern "C" fn(*mut c_void, c_int, c_int, *const c_char)>,
+    control_info: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+    io_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *mut c_void, u32)>,
+    param_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *const u32)>,
+    add_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+    remove_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+    process: Option<unsafe extern "C" fn(*mut c_void)>,
+    drained: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+#[repr(C)]
+struct PwBuffer {
+    buffer: *mut SpaBuffer,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct SpaBuffer {
+    n_metas: u32,
+    n_datas: u32,
+    metas: *mut c_void,
+    datas: *mut SpaData,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct SpaData {
+    ty: u32,
+    flags: u32,
+    fd: i64,
+    mapoffset: u32,
+    maxsize: u32,
+    data: *mut c_void,
+    chunk: *mut SpaChunk,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct SpaChunk {
+    offset: u32,
+    size: u32,
+    stride: i32,
+    flags: i32,
+}
+
+static EVENTS: StreamEvents = StreamEvents {
+    version: 0,
+    destroy: None,
+    state_changed: Some(on_state_changed),
+    control_info: None,
+    io_changed: None,
+    param_changed: Some(on_param_changed),
+    add_buffer: None,
+    remove_buffer: None,
+    process: Some(on_process),
+    drained: None,
+};
+
+type InitFn = unsafe extern "C" fn(*mut c_int, *mut *mut *mut c_char);
+type VersionFn = unsafe extern "C" fn() -> *const c_char;
+type ThreadLoopNewFn = unsafe extern "C" fn(*const c_char, *const c_void) -> *mut c_void;
+type ObjectFn = unsafe extern "C" fn(*mut c_void);
+type GetLoopFn = unsafe extern "C" fn(*mut c_void) -> *mut c_void;
+type StartFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type ContextNewFn = unsafe extern "C" fn(*mut c_void, *mut c_void, usize) -> *mut c_void;
+type ConnectFdFn = unsafe extern "C" fn(*mut c_void, c_int, *mut c_void, usize) -> *mut c_void;
+type PropertiesNewFn = unsafe extern "C" fn(*const c_char, ...) -> *mut c_void;
+type StreamNewFn = unsafe extern "C" fn(*mut c_void, *const c_char, *mut c_void) -> *mut c_void;
+type AddListenerFn =
+    unsafe extern "C" fn(*mut c_void, *mut SpaHook, *const StreamEvents, *mut c_void);
+type StreamConnectFn =
+    unsafe extern "C" fn(*mut c_void, c_int, u32, c_int, *mut *const c_void, u32) -> c_int;
+type DequeueFn = unsafe extern "C" fn(*mut c_void) -> *mut PwBuffer;
+type QueueFn = unsafe extern "C" fn(*mut c_void, *mut PwBuffer) -> c_int;
+
+/// Loaded PipeWire library
+struct PipeWire {
+    init: InitFn,
+    thread_loop_new: ThreadLoopNewFn,
+    thread_loop_get_loop: GetLoopFn,
+    thread_loop_start: StartFn,
+    thread_loop_stop: ObjectFn,
+    thread_loop_lock: ObjectFn,
+    thread_loop_unlock: ObjectFn,
+    thread_loop_destroy: ObjectFn,
+    context_new: ContextNewFn,
+    context_connect_fd: ConnectFdFn,
+    context_destroy: ObjectFn,
+    core_disconnect: StartFn,
+    properties_new: PropertiesNewFn,
+    stream_new: StreamNewFn,
+    stream_add_listener: AddListenerFn,
+    stream_connect: StreamConnectFn,
+    stream_destroy: ObjectFn,
+    stream_dequeue_buffer: DequeueFn,
+    stream_queue_buffer: QueueFn,
+    // Must outlive the function pointers above
+    _library: Library,
+}
+
+impl PipeWire {
+    const LIBRARY_NAMES: &'static [&'static str] = &["libpipewire-0.3.so.0", "libpipewire-0.3.so"];
+
+    fn load() -> Result<Self, WaylandError> {
+        let mut last_error = None;
+
+        for name in Self::LIBRARY_NAMES {
+            // Safety: loading PipeWire has no initialization side-effects
+            match unsafe { Library::new(name) } {
+                Ok(library) => {
+                    debug!(name = %name, "loaded PipeWire library");
+
+                    // Safety: the symbol type matches its declaration in pipewire/version.h, and
+                    // the returned string is static
+                    let version = unsafe {
+                        let get_version: libloading::Symbol<VersionFn> =
+                            library.get(b"pw_get_library_version\0")?;
+                        CStr::from_ptr(get_version()).to_string_lossy().into_owned()
+                    };
+
+                    if !parse_version(&version).is_some_and(|v| SUPPORTED_VERSIONS.contains(&v)) {
+                        return Err(WaylandError::UnsupportedVersion(version));
+                    }
+
+                    debug!(version = %version, "PipeWire library version");
+
+                    // Safety: the symbol types match the declarations in the pipewire headers
+                    return unsafe {
+                        Ok(Self {
+                            init: *library.get(b"pw_init\0")?,
+                            thread_loop_new: *library.get(b"pw_thread_loop_new\0")?,
+                            thread_loop_get_loop: *library.get(b"pw_thread_loop_get_loop\0")?,
+                            thread_loop_start: *library.get(b"pw_thread_loop_start\0")?,
+                            thread_loop_stop: *library.get(b"pw_thread_loop_stop\0")?,
+                            thread_loop_lock: *library.get(b"pw_thread_loop_lock\0")?,
+                            thread_loop_unlock: *library.get(b"pw_thread_loop_unlock\0")?,
+                            thread_loop_destroy: *library.get(b"pw_thread_loop_destroy\0")?,
+                            context_new: *library.get(b"pw_context_new\0")?,
+                            context_connect_fd: *library.get(b"pw_context_connect_fd\0")?,
+                            context_destroy: *library.get(b"pw_context_destroy\0")?,
+                            core_disconnect: *library.get(b"pw_core_disconnect\0")?,
+                            properties_new: *library.get(b"pw_properties_new\0")?,
+                            stream_new: *library.get(b"pw_stream_new\0")?,
+                            stream_add_listener: *library.get(b"pw_stream_add_listener\0")?,
+                            stream_connect: *library.get(b"pw_stream_connect\0")?,
+                            stream_destroy: *library.get(b"pw_stream_destroy\0")?,
+                            stream_dequeue_buffer: *library.get(b"pw_stream_dequeue_buffer\0")?,
+                            stream_queue_buffer: *library.get(b"pw_stream_queue_buffer\0")?,
+                            _library: library,
+                        })
+                    };
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.expect("no PipeWire library candidates").into())
+    }
+}
+
+/// Major and minor numbers of a PipeWire version string, e.g. `1.2.7`
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut numbers = version.split('.').map(str::parse);
+    Some((numbers.next()?.ok()?, numbers.next()?.ok()?))
+}
+
+/// Fail with the current errno if `ptr` is null
+fn check_ptr(call: &'static str, ptr: *mut c_void) -> Result<*mut c_void, WaylandError> {
+    if ptr.is_null() {
+        Err(WaylandError::PipeWire {
+            call,
+            message: std::io::Error::last_os_error().to_string(),
+        })
+    } else {
+        Ok(ptr)
+    }
+}
+
+/// Fail if `result` is a negative errno
+fn check(call: &'static str, result: c_int) -> Result<(), WaylandError> {
+    if result < 0 {
+        Err(WaylandError::PipeWire {
+            call,
+            message: std::io::Error::from_raw_os_error(-result).to_string(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// State shared with the stream callbacks, which run on the PipeWire thread
+struct StreamData {
+    stream: *mut c_void,
+    dequeue_buffer: DequeueFn,
+    queue_buffer: QueueFn,
+    processor: FrameProcessor,
+    interval: Duration,
+    /// Negotiated pixel layout and frame size
+    format: Mutex<Option<(PixelFormat, (u32, u32))>>,
+    last_frame: Mutex<Option<Instant>>,
+    frames: watch::Sender<Option<Arc<RawImage>>>,
+    ended: mpsc::UnboundedSender<Option<String>>,
+}
+
+unsafe extern "C" fn on_state_changed(
+    data: *mut c_void,
+    old: c_int,
+    state: c_int,
+    error: *const c_char,
+) {
+    // Safety: data is the StreamData registered with the listener
+    let data = unsafe { &*(data as *const StreamData) };
+
+    match state {
+        STREAM_STATE_STREAMING => {
+            info!(format = ?data.format.lock().unwrap(), "screen capture started");
+        }
+        STREAM_STATE_ERROR => {
+            let message = if error.is_null() {
+                "unknown error".to_owned()
+            } else {
+                // Safety: error is a valid string for the duration of the callback
+                unsafe { CStr::from_ptr(error) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+
+            data.ended.send(Some(message)).ok();
+        }
+        STREAM_STATE_UNCONNECTED if old != STREAM_STATE_UNCONNECTED => {
+            data.ended.send(None).ok();
+        }
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn on_param_changed(data: *mut c_void, id: u32, param: *const u32) {
+    if id != pod::PARAM_FORMAT || param.is_null() {
+        return;
+    }
+
+    // Safety: data is the StreamData registered with the listener, param is a pod whose size
+    // excludes its 8 bytes header
+    let (data, param) = unsafe {
+        (
+            &*(data as *const StreamData),
+            std::slice::from_raw_parts(param, 2 + *param as usize / 4),
+        )
+    };
+
+    *data.format.lock().unwrap() = pod::parse_format(param).and_then(|(format, size)| {
+        FORMATS
+            .iter()
+            .find(|(id, _)| *id == format)
+            .map(|(_, layout)| (*layout, size))
+    });
+}
+
+unsafe extern "C" fn on_process(data: *mut c_void) {
+    // Safety: data is the StreamData registered with the listener
+    let data = unsafe { &*(data as *const StreamData) };
+
+    // Safety: stream is valid while its listener is registered
+    let buffer = unsafe { (data.dequeue_buffer)(data.stream) };
+    if buffer.is_null() {
+        return;
+    }
+
+    let now = Instant::now();
+    let mut last_frame = data.last_frame.lock().unwrap();
+    let due = last_frame.is_none_or(|last| now.duration_since(last) >= data.interval);
+
+    if let (true, Some((format, size))) = (due, *data.format.lock().unwrap()) {
+        // Safety: buffers are mapped by PipeWire and valid until they are queued back
+        if let Some(image) = unsafe { process_buffer(&data.processor, &*buffer, format, size) } {
+            *last_frame = Some(now);
+            data.frames.send_replace(Some(Arc::new(image)));
+        }
+    }
+
+    // Safety: buffer was dequeued from this stream
+    unsafe { (data.queue_buffer)(data.stream, buffer) };
+}
+
+/// Convert the first plane of a mapped buffer
+unsafe fn process_buffer(
+    processor: &FrameProcessor,
+    buffer: &PwBuffer,
+    format: PixelFormat,
+    size: (u32, u32),
+) -> Option<RawImage> {
+    let buffer = unsafe { buffer.buffer.as_ref()? };
+    if buffer.n_datas == 0 {
+        return None;
+    }
+
+    let plane = unsafe { buffer.datas.as_ref()? };
+    let chunk = unsafe { plane.chunk.as_ref()? };
+    if plane.data.is_null() || chunk.offset >= plane.maxsize {
+        return None;
+    }
+
+    let stride = if chunk.stride > 0 {
+        chunk.stride as usize
+    } else {
+        size.0 as usize * 4
+    };
+
+    let frame = unsafe {
+        std::slice::from_raw_parts(
+            (plane.data as *const u8).add(chunk.offset as usize),
+            (plane.maxsize - chunk.offset) as usize,
+        )
+    };
+
+    processor.process(frame, size, stride, format)
+}
+
+/// Connected video stream, which disconnects when dropped
+pub struct Stream {
+    thread_loop: *mut c_void,
+    context: *mut c_void,
+    core: *mut c_void,
+    stream: *mut c_void,
+    hook: Box<SpaHook>,
+    data: Box<StreamData>,
+    pipewire: PipeWire,
+}
+
+// Safety: the PipeWire objects are only used under the thread loop lock
+unsafe impl Send for Stream {}
+
+impl Stream {
+    /// Connect to the screen cast `node_id` through the PipeWire remote `fd`
+    ///
+    /// Frames are converted at most `frequency_hz` times per second and sent to `frames`. When the
+    /// stream ends, its error if any is sent to `ended`.
+    pub fn connect(
+        fd: OwnedFd,
+        node_id: u32,
+        processor: FrameProcessor,
+        frequency_hz: u32,
+        frames: watch::Sender<Option<Arc<RawImage>>>,
+        ended: mpsc::UnboundedSender<Option<String>>,
+    ) -> Result<Self, WaylandError> {
+        let pipewire = PipeWire::load()?;
+        // Safety: PipeWire accepts no arguments
+        unsafe { (pipewire.init)(null_mut(), null_mut()) };
+
+        let data = Box::new(StreamData {
+            stream: null_mut(),
+            dequeue_buffer: pipewire.stream_dequeue_buffer,
+            queue_buffer: pipewire.stream_queue_buffer,
+            processor,
+            interval: Duration::from_secs(1) / frequency_hz.max(1),
+            format: Mutex::new(None),
+            last_frame: Mutex::new(None),
+            frames,
+            ended,
+        });
+
+        let mut this = Self {
+            thread_loop: null_mut(),
+            context: null_mut(),
+            core: null_mut(),
+            stream: null_mut(),
+            hook: Box::new(SpaHook {
+                _fields: [null_mut(); 6],
+            }),
+            data,
+            pipewire,
+        };
+
+        // Safety: the objects are created in order, and destroyed by Drop if anything fails
+        unsafe {
+            let pw = &this.pipewire;
+            this.thread_loop = check_ptr(
+                "pw_thread_loop_new",
+                (pw.thread_loop_new)(b"hyperion-screen\0".as_ptr() as _, std::ptr::null()),
+            )?;
+            this.context = check_ptr(
+                "pw_context_new",
+                (pw.context_new)((pw.thread_loop_get_loop)(this.thread_loop), null_mut(), 0),
+            )?;
+            check(
+                "pw_thread_loop_start",
+                (pw.thread_loop_start)(this.thread_loop),
+            )?;
+
+            (pw.thread_loop_lock)(this.thread_loop);
+            let result = this.connect_stream(fd, node_id, frequency_hz);
+            (this.pipewire.thread_loop_unlock)(this.thread_loop);
+            result?;
+        }
+
+        Ok(this)
+    }
+
+    /// Create and connect the stream, with the thread loop locked
+    unsafe fn connect_stream(
+        &mut self,
+        fd: OwnedFd,
+        node_id: u32,
+        frequency_hz: u32,
+    ) -> Result<(), WaylandError> {
+        let pw = &self.pipewire;
+
+        // The fd is closed by PipeWire, even if the connection fails
+        self.core = check_ptr("pw_context_connect_fd", unsafe {
+            (pw.context_connect_fd)(self.context, fd.into_raw_fd(), null_mut(), 0)
+        })?;
+
+        self.stream = check_ptr("pw_stream_new", unsafe {
+            let properties = (pw.properties_new)(
+                b"media.type\0".as_ptr() as *const c_char,
+                b"Video\0".as_ptr() as *const c_char,
+                b"media.category\0".as_ptr() as *const c_char,
+                b"Capture\0".as_ptr() as *const c_char,
+                b"media.role\0".as_ptr() as *const c_char,
+                b"Screen\0".as_ptr() as *const c_char,
+                std::ptr::null::<c_char>(),
+            );
+
+            (pw.stream_new)(self.core, b"hyperion.rs\0".as_ptr() as _, properties)
+        })?;
+
+        self.data.stream = self.stream;
+        let formats: Vec<u32> = FORMATS.iter().map(|(id, _)| *id).collect();
+        let params = pod::enum_format(&formats, frequency_hz);
+        let mut param_ptrs = [params.as_ptr() as *const c_void];
+
+        // Safety: the hook and data are boxed, so they stay in place while the stream exists
+        unsafe {
+            (pw.stream_add_listener)(
+                self.stream,
+                &mut *self.hook,
+                &EVENTS,
+                &*self.data as *const StreamData as *mut c_void,
+            );
+
+            check(
+                "pw_stream_connect",
+                (pw.stream_connect)(
+                    self.stream,
+                    DIRECTION_INPUT,
+                    node_id,
+                    STREAM_FLAG_AUTOCONNECT | STREAM_FLAG_MAP_BUFFERS,
+                    param_ptrs.as_mut_ptr(),
+                    param_ptrs.len() as u32,
+                ),
+            )
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let pw = &self.pipewire;
+
+        // Safety: each object was created by PipeWire and is destroyed only once, the stream under
+        // the thread loop lock and the rest once the loop is stopped
+        unsafe {
+            if self.thread_loop.is_null() {
+                return;
+            }
+
+            if !self.stream.is_null() {
+                (pw.thread_loop_lock)(self.thread_loop);
+                (pw.stream_destroy)(self.stream);
+                (pw.thread_loop_unlock)(self.thread_loop);
+            }
+
+            (pw.thread_loop_stop)(self.thread_loop);
+
+            if !self.core.is_null() {
+                (pw.core_disconnect)(self.core);
+            }
+
+            if !self.context.is_null() {
+                (pw.context_destroy)(self.context);
+            }
+
+            (pw.thread_loop_destroy)(self.thread_loop);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_versions() {
+        let supported =
+            |version| parse_version(version).is_some_and(|v| SUPPORTED_VERSIONS.contains(&v));
+
+        assert!(supported("0.3.48"));
+        assert!(supported("1.2.7"));
+        assert!(!supported("0.2.7"));
+        assert!(!supported("2.0.0"));
+        assert!(!supported("unknown"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Serialization of the SPA pods negotiating the video format, see spa/pod/pod.h
+
+// Definitions from spa/utils/type.h, spa/param/param.h, spa/param/format.h and
+// spa/param/video/raw.h
+
+const TYPE_ID: u32 = 3;
+const TYPE_RECTANGLE: u32 = 10;
+const TYPE_FRACTION: u32 = 11;
+const TYPE_OBJECT: u32 = 15;
+const TYPE_CHOICE: u32 = 19;
+const TYPE_OBJECT_FORMAT: u32 = 0x40003;
+
+pub const PARAM_ENUM_FORMAT: u32 = 3;
+pub const PARAM_FORMAT: u32 = 4;
+
+const FORMAT_MEDIA_TYPE: u32 = 1;
+const FORMAT_MEDIA_SUBTYPE: u32 = 2;
+const FORMAT_VIDEO_FORMAT: u32 = 0x20001;
+const FORMAT_VIDEO_SIZE: u32 = 0x20003;
+const FORMAT_VIDEO_FRAMERATE: u32 = 0x20004;
+
+const MEDIA_TYPE_VIDEO: u32 = 2;
+const MEDIA_SUBTYPE_RAW: u32 = 1;
+
+const CHOICE_RANGE: u32 = 1;
+const CHOICE_ENUM: u32 = 3;
+
+/// Largest frame size accepted from the compositor
+const MAX_SIZE: u32 = 8192;
+
+/// Pod being written, as native-endian words
+#[derive(Default)]
+struct Builder {
+    words: Vec<u32>,
+}
+
+impl Builder {
+    fn pod(&mut self, ty: u32, body: &[u32]) {
+        self.words.push(body.len() as u32 * 4);
+        self.words.push(ty);
+        self.words.extend_from_slice(body);
+        self.pad();
+    }
+
+    /// Pods are aligned on 8 bytes
+    fn pad(&mut self) {
+        if !self.words.len().is_multiple_of(2) {
+            self.words.push(0);
+        }
+    }
+
+    fn property(&mut self, key: u32) {
+        self.words.extend_from_slice(&[key, 0]);
+    }
+
+    /// Choice between `values`, each made of `child_size` bytes
+    fn choice(&mut self, choice: u32, child_type: u32, child_size: u32, values: &[u32]) {
+        let mut body = vec![choice, 0, child_size, child_type];
+        body.extend_from_slice(values);
+        self.pod(TYPE_CHOICE, &body);
+    }
+}
+
+/// Video formats offered to the compositor, as an `EnumFormat` object pod
+///
+/// `formats` are `spa_video_format` values, the first one being preferred.
+pub fn enum_format(formats: &[u32], framerate: u32) -> Vec<u64> {
+    let mut builder = Builder::default();
+    builder
+        .words
+        .extend_from_slice(&[0, TYPE_OBJECT, TYPE_OBJECT_FORMAT, PARAM_ENUM_FORMAT]);
+
+    builder.property(FORMAT_MEDIA_TYPE);
+    builder.pod(TYPE_ID, &[MEDIA_TYPE_VIDEO]);
+    builder.property(FORMAT_MEDIA_SUBTYPE);
+    builder.pod(TYPE_ID, &[MEDIA_SUBTYPE_RAW]);
+
+    // The default value comes first, followed by the alternatives
+    builder.property(FORMAT_VIDEO_FORMAT);
+    let mut values = formats[..1].to_vec();
+    values.extend_from_slice(formats);
+    builder.choice(CHOICE_ENUM, TYPE_ID, 4, &values);
+
+    builder.property(FORMAT_VIDEO_SIZE);
+    builder.choice(
+        CHOICE_RANGE,
+        TYPE_RECTANGLE,
+        8,
+        &[1920, 1080, 1, 1, MAX_SIZE, MAX_SIZE],
+    );
+
+    builder.property(FORMAT_VIDEO_FRAMERATE);
+    builder.choice(
+        CHOICE_RANGE,
+        TYPE_FRACTION,
+        8,
+        &[framerate, 1, 0, 1, 360, 1],
+    );
+
+    // The object size excludes its header
+    builder.words[0] = (builder.words.len() as u32 - 2) * 4;
+
+    builder
+        .words
+        .chunks(2)
+        .map(|pair| {
+            let mut bytes = [0; 8];
+            bytes[..4].copy_from_slice(&pair[0].to_ne_bytes());
+            bytes[4..].copy_from_slice(&pair[1].to_ne_bytes());
+            u64::from_ne_bytes(bytes)
+        })
+        .collect()
+}
+
+/// Negotiated `spa_video_format` and size of a `Format` object pod, given with its header
+pub fn parse_format(pod: &[u32]) -> Option<(u32, (u32, u32))> {
+    if pod.get(1) != Some(&TYPE_OBJECT) || pod.get(2) != Some(&TYPE_OBJECT_FORMAT) {
+        return None;
+    }
+
+    let end = (2 + *pod.first()? as usize / 4).min(pod.len());
+    let mut offset = 4;
+    let (mut format, mut size) = (None, None);
+
+    // Properties: key, flags, then the value pod
+    while offset + 4 <= end {
+        let key = pod[offset];
+        let value_size = pod[offset + 2] as usize;
+        let value = pod.get(offset + 2..(offset + 4 + value_size / 4).min(end))?;
+
+        match key {
+            FORMAT_MEDIA_TYPE if single(value, TYPE_ID, 1)? != [MEDIA_TYPE_VIDEO] => return None,
+            FORMAT_MEDIA_SUBTYPE if single(value, TYPE_ID, 1)? != [MEDIA_SUBTYPE_RAW] => {
+                return None
+            }
+            FORMAT_VIDEO_FORMAT => format = Some(single(value, TYPE_ID, 1)?[0]),
+            FORMAT_VIDEO_SIZE => {
+                let rectangle = single(value, TYPE_RECTANGLE, 2)?;
+                size = Some((rectangle[0], rectangle[1]));
+            }
+            _ => {}
+        }
+
+        offset += 4 + value_size.div_ceil(8) * 2;
+    }
+
+    Some((format?, size?))
+}
+
+/// Words of a value pod of type `ty`, or of the default value of a choice
+fn single(value: &[u32], ty: u32, words: usize) -> Option<&[u32]> {
+    match *value.get(1)? {
+        t if t == ty => value.get(2..2 + words),
+        TYPE_CHOICE if value.get(5) == Some(&ty) => value.get(6..6 + words),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enum_format() {
+        let pod = enum_format(&[8, 7], 10);
+        let words: Vec<u32> = pod
+            .iter()
+            .flat_map(|word| {
+                let bytes = word.to_ne_bytes();
+                vec![
+                    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+                ]
+            })
+            .collect();
+
+        assert_eq!(words[0] as usize, (words.len() - 2) * 4);
+        assert_eq!(
+            &words[1..4],
+            &[TYPE_OBJECT, TYPE_OBJECT_FORMAT, PARAM_ENUM_FORMAT]
+        );
+
+        // The defaults of the choices are parsed like a negotiated format
+        assert_eq!(parse_format(&words), Some((8, (1920, 1080))));
+    }
+}
@@ -0,0 +1,179 @@
+//! Screen cast sessions of the xdg-desktop-portal
+
+use std::{collections::HashMap, convert::TryFrom, os::fd::OwnedFd};
+
+use futures::StreamExt;
+use serde::Serialize;
+use zbus::{
+    zvariant::{
+        self, Array, DynamicType, ObjectPath, OwnedObjectPath, OwnedValue, Structure, Value,
+    },
+    Connection, Proxy,
+};
+
+use super::WaylandError;
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREEN_CAST: &str = "org.freedesktop.portal.ScreenCast";
+const REQUEST: &str = "org.freedesktop.portal.Request";
+
+/// Source types to share, 1 being monitors
+const SOURCE_TYPE_MONITOR: u32 = 1;
+
+/// Keep the permission until it is revoked, so later sessions don't ask again
+const PERSIST_MODE_PERSISTENT: u32 = 2;
+
+/// Response codes of portal requests
+const RESPONSE_SUCCESS: u32 = 0;
+const RESPONSE_CANCELLED: u32 = 1;
+
+/// Started screen cast, which ends when dropped
+pub struct Session {
+    /// The portal closes the session when this connection does
+    _connection: Connection,
+    /// PipeWire node of the shared monitor
+    pub node_id: u32,
+    /// Connection to the PipeWire remote granted access to the node
+    pub fd: OwnedFd,
+    /// Token to share the same monitor without asking in the next session
+    pub restore_token: Option<String>,
+}
+
+impl Session {
+    /// Ask the user to share a monitor, unless `restore_token` is still valid
+    pub async fn start(restore_token: Option<String>) -> Result<Self, WaylandError> {
+        let connection = Connection::session().await?;
+        let proxy = Proxy::new(&connection, DESTINATION, PATH, SCREEN_CAST).await?;
+        let portal = Portal {
+            sender: connection
+                .unique_name()
+                .map(|name| name.trim_start_matches(':').replace('.', "_"))
+                .unwrap_or_default(),
+            connection: &connection,
+            proxy,
+        };
+
+        let results = portal
+            .request("CreateSession", |token| {
+                (options(vec![
+                    ("handle_token", token.to_owned().into()),
+                    ("session_handle_token", "hyperion_rs".into()),
+                ]),)
+            })
+            .await?;
+
+        let session_handle: &str = results
+            .get("session_handle")
+            .and_then(|handle| handle.downcast_ref().ok())
+            .ok_or(WaylandError::Portal {
+                method: "CreateSession",
+                message: "missing session handle".to_owned(),
+            })?;
+        let session = ObjectPath::try_from(session_handle)?;
+
+        portal
+            .request("SelectSources", |token| {
+                let mut options = options(vec![
+                    ("handle_token", token.to_owned().into()),
+                    ("types", SOURCE_TYPE_MONITOR.into()),
+                    ("multiple", false.into()),
+                    ("persist_mode", PERSIST_MODE_PERSISTENT.into()),
+                ]);
+
+                if let Some(restore_token) = &restore_token {
+                    options.insert("restore_token", restore_token.as_str().into());
+                }
+
+                (&session, options)
+            })
+            .await?;
+
+        let results = portal
+            .request("Start", |token| {
+                (
+                    &session,
+                    "",
+                    options(vec![("handle_token", token.to_owned().into())]),
+                )
+            })
+            .await?;
+
+        let node_id = first_stream(&results).ok_or(WaylandError::Portal {
+            method: "Start",
+            message: "no stream was shared".to_owned(),
+        })?;
+        let restore_token = results
+            .get("restore_token")
+            .and_then(|token| token.downcast_ref::<&str>().ok())
+            .map(str::to_owned);
+
+        let fd: zvariant::OwnedFd = portal
+            .proxy
+            .call("OpenPipeWireRemote", &(&session, options(vec![])))
+            .await?;
+
+        Ok(Self {
+            node_id,
+            fd: fd.into(),
+            restore_token,
+            _connection: connection,
+        })
+    }
+}
+
+struct Portal<'c> {
+    connection: &'c Connection,
+    proxy: Proxy<'static>,
+    /// Unique name of the connection, as used in request paths
+    sender: String,
+}
+
+impl Portal<'_> {
+    /// Call a method which answers through a request object, and wait for its results
+    ///
+    /// `body` builds the arguments of the method from the handle token of the request.
+    async fn request<B>(
+        &self,
+        method: &'static str,
+        body: impl FnOnce(&str) -> B,
+    ) -> Result<HashMap<String, OwnedValue>, WaylandError>
+    where
+        B: Serialize + DynamicType,
+    {
+        let token = format!("hyperion_rs_{}", method.to_lowercase());
+        let path = format!("{}/request/{}/{}", PATH, self.sender, token);
+
+        // Subscribe before calling, so the response can't be missed
+        let request = Proxy::new(self.connection, DESTINATION, path, REQUEST).await?;
+        let mut responses = request.receive_signal("Response").await?;
+
+        let _: OwnedObjectPath = self.proxy.call(method, &body(&token)).await?;
+
+        let response = responses.next().await.ok_or(WaylandError::Portal {
+            method,
+            message: "no response".to_owned(),
+        })?;
+        let (code, results): (u32, HashMap<String, OwnedValue>) = response.body().deserialize()?;
+
+        match code {
+            RESPONSE_SUCCESS => Ok(results),
+            RESPONSE_CANCELLED => Err(WaylandError::Cancelled),
+            code => Err(WaylandError::Portal {
+                method,
+                message: format!("request failed with code {}", code),
+            }),
+        }
+    }
+}
+
+fn options<'a>(entries: Vec<(&'static str, Value<'a>)>) -> HashMap<&'static str, Value<'a>> {
+    entries.into_iter().collect()
+}
+
+/// PipeWire node of the first stream in the results of `Start`
+fn first_stream(results: &HashMap<String, OwnedValue>) -> Option<u32> {
+    let streams: &Array = results.get("streams")?.downcast_ref().ok()?;
+    let stream: &Structure = streams.inner().first()?.downcast_ref().ok()?;
+    stream.fields().first()?.downcast_ref().ok()
+}
@@ -1,11 +1,15 @@
 use std::convert::TryFrom;
 
-use base64::Engine;
-use image::ImageEncoder;
 use thiserror::Error;
 
 use crate::models::Color;
 
+pub mod decoder;
+pub use decoder::ImageDecoder;
+
+mod queue;
+pub use queue::*;
+
 mod reducer;
 pub use reducer::*;
 
@@ -54,9 +58,68 @@ pub enum RawImageError {
     Io(#[from] std::io::Error),
     #[error("encoding error")]
     Encoding(#[from] image::ImageError),
+    #[error("unsupported image format")]
+    UnsupportedFormat,
+}
+
+/// Rectangular region of an image, in pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRegion {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl ImageRegion {
+    /// Create a new region, clipped to the bounds of an image of the given dimensions
+    pub fn clipped(x: u32, y: u32, width: u32, height: u32, bounds: (u16, u16)) -> Self {
+        let (max_width, max_height) = (bounds.0 as u32, bounds.1 as u32);
+        let x = x.min(max_width);
+        let y = y.min(max_height);
+
+        Self {
+            x: x as _,
+            y: y as _,
+            width: width.min(max_width - x) as _,
+            height: height.min(max_height - y) as _,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Translate this region into the coordinates of a view over the given ranges
+    pub fn view(&self, x: &std::ops::Range<u16>, y: &std::ops::Range<u16>) -> Self {
+        let xmin = self.x.clamp(x.start, x.end);
+        let xmax = (self.x.saturating_add(self.width)).clamp(x.start, x.end);
+        let ymin = self.y.clamp(y.start, y.end);
+        let ymax = (self.y.saturating_add(self.height)).clamp(y.start, y.end);
+
+        Self {
+            x: xmin - x.start,
+            y: ymin - y.start,
+            width: xmax - xmin,
+            height: ymax - ymin,
+        }
+    }
+
+    /// Returns true if this region overlaps the given inclusive pixel ranges
+    pub fn overlaps(
+        &self,
+        x: std::ops::RangeInclusive<u16>,
+        y: std::ops::RangeInclusive<u16>,
+    ) -> bool {
+        !self.is_empty()
+            && (self.x as u32) <= *x.end() as u32
+            && (*x.start() as u32) < self.x as u32 + self.width as u32
+            && (self.y as u32) <= *y.end() as u32
+            && (*y.start() as u32) < self.y as u32 + self.height as u32
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct RawImage {
     data: Vec<u8>,
     width: u16,
@@ -66,18 +129,46 @@ pub struct RawImage {
 impl RawImage {
     pub const CHANNELS: u16 = 3;
 
-    pub fn write_to_kitty(&self, out: &mut dyn std::io::Write) -> Result<(), RawImageError> {
-        // Buffer for raw PNG data
-        let mut buf = Vec::new();
-        // PNG encoder
-        let encoder = image::codecs::png::PngEncoder::new(&mut buf);
-        // Write PNG to buffer
-        encoder.write_image(
+    /// RGB data of this image, row by row
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, out: impl std::io::Write) -> Result<(), RawImageError> {
+        use image::ImageEncoder;
+
+        image::codecs::png::PngEncoder::new(out).write_image(
             &self.data[..],
             self.width as _,
             self.height as _,
             image::ColorType::Rgb8.into(),
         )?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "jpeg")]
+    pub fn write_jpeg(&self, out: impl std::io::Write, quality: u8) -> Result<(), RawImageError> {
+        use image::ImageEncoder;
+
+        image::codecs::jpeg::JpegEncoder::new_with_quality(out, quality).write_image(
+            &self.data[..],
+            self.width as _,
+            self.height as _,
+            image::ColorType::Rgb8.into(),
+        )?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "png")]
+    pub fn write_to_kitty(&self, out: &mut dyn std::io::Write) -> Result<(), RawImageError> {
+        use base64::Engine;
+
+        // Buffer for raw PNG data
+        let mut buf = Vec::new();
+        self.write_png(&mut buf)?;
         // Encode to base64
         let encoded = base64::engine::general_purpose::STANDARD_NO_PAD.encode(&buf);
         // Split into chunks
@@ -0,0 +1,108 @@
+//! Decoders for compressed image payloads
+//!
+//! Each codec is behind a cargo feature of the same name (`png`, `jpeg`, `webp`), so builds for
+//! constrained targets can leave out the ones they don't need.
+
+use std::convert::TryFrom;
+
+use super::{RawImage, RawImageError};
+
+/// Decoder for a compressed image format
+pub trait ImageDecoder: Send + Sync {
+    /// Name of the decoded format
+    fn name(&self) -> &'static str;
+
+    /// Returns true if `data` starts with the signature of this format
+    fn matches(&self, data: &[u8]) -> bool;
+
+    /// Decode `data` into an RGB image
+    fn decode(&self, data: &[u8]) -> Result<RawImage, RawImageError>;
+}
+
+/// Decoder backed by the `image` crate
+#[allow(dead_code)]
+struct ImageCrateDecoder {
+    name: &'static str,
+    format: image::ImageFormat,
+    matches: fn(&[u8]) -> bool,
+}
+
+impl ImageDecoder for ImageCrateDecoder {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn matches(&self, data: &[u8]) -> bool {
+        (self.matches)(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<RawImage, RawImageError> {
+        let image = image::load_from_memory_with_format(data, self.format)?.into_rgb8();
+        let (width, height) = image.dimensions();
+
+        RawImage::try_from((image.into_raw(), width, height))
+    }
+}
+
+#[cfg(feature = "png")]
+static PNG: ImageCrateDecoder = ImageCrateDecoder {
+    name: "png",
+    format: image::ImageFormat::Png,
+    matches: |data| data.starts_with(b"\x89PNG\r\n\x1a\n"),
+};
+
+#[cfg(feature = "jpeg")]
+static JPEG: ImageCrateDecoder = ImageCrateDecoder {
+    name: "jpeg",
+    format: image::ImageFormat::Jpeg,
+    matches: |data| data.starts_with(b"\xff\xd8\xff"),
+};
+
+#[cfg(feature = "webp")]
+static WEBP: ImageCrateDecoder = ImageCrateDecoder {
+    name: "webp",
+    format: image::ImageFormat::WebP,
+    matches: |data| data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP",
+};
+
+static DECODERS: &[&dyn ImageDecoder] = &[
+    #[cfg(feature = "png")]
+    &PNG,
+    #[cfg(feature = "jpeg")]
+    &JPEG,
+    #[cfg(feature = "webp")]
+    &WEBP,
+];
+
+/// Decoders enabled in this build
+pub fn decoders() -> &'static [&'static dyn ImageDecoder] {
+    DECODERS
+}
+
+/// Decode an image, detecting its format from its contents
+pub fn decode(data: &[u8]) -> Result<RawImage, RawImageError> {
+    decoders()
+        .iter()
+        .find(|decoder| decoder.matches(data))
+        .ok_or(RawImageError::UnsupportedFormat)?
+        .decode(data)
+}
+
+#[cfg(all(test, feature = "png"))]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+
+    #[test]
+    fn decode_png() {
+        let data = vec![10, 20, 30, 40, 50, 60];
+        let image = RawImage::try_from((data.clone(), 2, 1)).unwrap();
+
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        let decoded = decode(&png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (2, 1));
+        assert_eq!(decoded, image);
+    }
+}
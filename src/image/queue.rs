@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+
+/// A message which may hold a frame: an image or LED colors
+pub trait QueuedFrame {
+    /// Size of the frame held by this message in bytes, or `None` if it doesn't hold one
+    ///
+    /// Messages without frames are never dropped, so clears and color changes aren't lost.
+    fn frame_bytes(&self) -> Option<usize>;
+
+    /// Returns true if this message holds the next frame of the input of `previous`
+    fn follows(&self, _previous: &Self) -> bool {
+        false
+    }
+
+    /// Process the whole frame instead of the region updated since the previous one
+    fn clear_region(&mut self) {}
+}
+
+/// Counters of the frames dropped by an [ImageQueue]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImageQueueStats {
+    /// Number of frames dropped
+    pub dropped_frames: u64,
+    /// Total size of the frames dropped, in bytes
+    pub dropped_bytes: u64,
+    /// Largest size of the queued frames so far, in bytes
+    pub peak_bytes: usize,
+}
+
+/// FIFO queue of messages holding at most a given size of frames
+///
+/// When a new frame doesn't fit, the oldest frames are dropped until it does. A frame larger than
+/// the limit is still queued once all the others are dropped, so the latest input is never lost.
+#[derive(Debug)]
+pub struct ImageQueue<T> {
+    messages: VecDeque<T>,
+    /// Size of the frames of `messages`
+    bytes: usize,
+    limit: usize,
+    stats: ImageQueueStats,
+}
+
+impl<T: QueuedFrame> ImageQueue<T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            bytes: 0,
+            limit,
+            stats: Default::default(),
+        }
+    }
+
+    /// Queue a message
+    ///
+    /// # Returns
+    ///
+    /// The number of frames dropped to make room for it.
+    pub fn push(&mut self, mut message: T) -> usize {
+        let mut dropped = 0;
+
+        if let Some(size) = message.frame_bytes() {
+            while self.bytes + size > self.limit {
+                match self.messages.iter().position(|m| m.frame_bytes().is_some()) {
+                    Some(index) => {
+                        // unwrap: the index was just found
+                        let removed = self.messages.remove(index).unwrap();
+                        let size = removed.frame_bytes().unwrap_or_default();
+
+                        // The region of the next frame of this input is relative to the dropped
+                        // one, which never reaches the instance
+                        if let Some(next) = self
+                            .messages
+                            .iter_mut()
+                            .skip(index)
+                            .chain(std::iter::once(&mut message))
+                            .find(|m| m.frame_bytes().is_some() && m.follows(&removed))
+                        {
+                            next.clear_region();
+                        }
+
+                        self.bytes -= size;
+                        self.stats.dropped_frames += 1;
+                        self.stats.dropped_bytes += size as u64;
+                        dropped += 1;
+                    }
+                    None => break,
+                }
+            }
+
+            self.bytes += size;
+            self.stats.peak_bytes = self.stats.peak_bytes.max(self.bytes);
+        }
+
+        self.messages.push_back(message);
+        dropped
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let message = self.messages.pop_front()?;
+        self.bytes -= message.frame_bytes().unwrap_or_default();
+        Some(message)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn stats(&self) -> ImageQueueStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl QueuedFrame for Option<usize> {
+        fn frame_bytes(&self) -> Option<usize> {
+            *self
+        }
+    }
+
+    #[test]
+    fn test_image_queue() {
+        let mut queue = ImageQueue::new(10);
+
+        assert_eq!(queue.push(Some(4)), 0);
+        assert_eq!(queue.push(None), 0);
+        assert_eq!(queue.push(Some(4)), 0);
+
+        // The oldest frame is dropped, messages without frames are kept
+        assert_eq!(queue.push(Some(4)), 1);
+        assert_eq!(
+            queue.stats(),
+            ImageQueueStats {
+                dropped_frames: 1,
+                dropped_bytes: 4,
+                peak_bytes: 8,
+            }
+        );
+
+        // Oversized frames replace all the others
+        assert_eq!(queue.push(Some(20)), 2);
+        assert_eq!(queue.stats().peak_bytes, 20);
+
+        assert_eq!(queue.pop(), Some(None));
+        assert_eq!(queue.pop(), Some(Some(20)));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+
+        assert_eq!(queue.push(Some(10)), 0);
+    }
+
+    #[test]
+    fn test_image_queue_region() {
+        use std::{convert::TryFrom, sync::Arc};
+
+        use crate::{
+            component::ComponentName,
+            global::{InputMessage, InputMessageData, Message},
+            image::{ImageRegion, RawImage},
+        };
+
+        let image = |source_id, region| {
+            InputMessage::new(
+                source_id,
+                ComponentName::FlatbufServer,
+                InputMessageData::Image {
+                    priority: 150,
+                    duration: None,
+                    image: Arc::new(RawImage::try_from((vec![0; 12], 2, 2)).unwrap()),
+                    region,
+                },
+            )
+        };
+        let region = |message: InputMessage| match message.data() {
+            InputMessageData::Image { region, .. } => *region,
+            _ => panic!("expected an image"),
+        };
+        let partial = ImageRegion::clipped(0, 0, 1, 1, (2, 2));
+
+        let mut queue = ImageQueue::new(36);
+        queue.push(image(1, None));
+        queue.push(image(2, Some(partial)));
+        queue.push(image(1, Some(partial)));
+
+        // The first frame of source 1 is dropped, its next partial update becomes a full one
+        assert_eq!(queue.push(image(1, Some(partial))), 1);
+        assert_eq!(region(queue.pop().unwrap()), Some(partial));
+        assert_eq!(region(queue.pop().unwrap()), None);
+        assert_eq!(region(queue.pop().unwrap()), Some(partial));
+        assert!(queue.is_empty());
+
+        // Without any queued frame of this input, the pushed frame is processed whole
+        let mut queue = ImageQueue::new(12);
+        queue.push(image(1, None));
+        assert_eq!(queue.push(image(1, Some(partial))), 1);
+        assert_eq!(region(queue.pop().unwrap()), None);
+    }
+}
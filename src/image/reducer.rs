@@ -1,12 +1,65 @@
+use std::{
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+};
+
 use crate::models::{Color16, Led};
 
-use super::Image;
+use super::{Image, ImageRegion};
+
+/// Number of LED maps kept, for sources alternating between image sizes
+const MAP_CACHE_SIZE: usize = 4;
 
 #[derive(Debug, Default)]
 pub struct Reducer {
+    /// LED maps of the last image sizes and layouts, the current one first
+    maps: VecDeque<LedMap>,
+    /// Colors computed for the last reduced image, used for partial updates
+    values: Vec<Color16>,
+}
+
+/// Pixel ranges of each LED, for a given image size and layout
+#[derive(Debug)]
+struct LedMap {
+    width: u16,
+    height: u16,
+    layout: u64,
     spec: Vec<LedSpec>,
-    spec_width: u16,
-    spec_height: u16,
+}
+
+impl LedMap {
+    fn new(width: u16, height: u16, layout: u64, leds: &[Led]) -> Self {
+        let fwidth = width as f32;
+        let fheight = height as f32;
+
+        Self {
+            width,
+            height,
+            layout,
+            spec: leds
+                .iter()
+                .map(|spec| LedSpec::new(spec, width, height, fwidth, fheight))
+                .collect(),
+        }
+    }
+
+    fn matches(&self, width: u16, height: u16, layout: u64) -> bool {
+        self.width == width && self.height == height && self.layout == layout
+    }
+}
+
+/// Hash of the scan ranges of a layout, to tell LED maps of different layouts apart
+fn layout_hash(leds: &[Led]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+
+    leds.len().hash(&mut hasher);
+    for led in leds {
+        for value in [led.hmin, led.hmax, led.vmin, led.vmax] {
+            value.to_bits().hash(&mut hasher);
+        }
+    }
+
+    hasher.finish()
 }
 
 #[derive(Debug)]
@@ -31,58 +84,195 @@ impl LedSpec {
             lymax: (lymax.ceil() as u16).min(height - 1),
         }
     }
+
+    fn reduce(&self, image: &impl Image) -> Color16 {
+        let mut r_acc = 0u64;
+        let mut g_acc = 0u64;
+        let mut b_acc = 0u64;
+        let mut cnt = 0u64;
+
+        for y in self.lymin..=self.lymax {
+            for x in self.lxmin..=self.lxmax {
+                // Safety: x (resp. y) are necessarily in 0..width (resp. 0..height)
+                let rgb = unsafe { image.color_at_unchecked(x as _, y as _) };
+                let area = 255;
+
+                let (r, g, b) = rgb.into_components();
+                r_acc += (r as u64 * 255) * area;
+                g_acc += (g as u64 * 255) * area;
+                b_acc += (b as u64 * 255) * area;
+                cnt += area;
+            }
+        }
+
+        Color16::new(
+            ((r_acc / cnt.max(1)) * 65535 / (255 * 255)).min(u16::MAX as _) as u16,
+            ((g_acc / cnt.max(1)) * 65535 / (255 * 255)).min(u16::MAX as _) as u16,
+            ((b_acc / cnt.max(1)) * 65535 / (255 * 255)).min(u16::MAX as _) as u16,
+        )
+    }
 }
 
 impl Reducer {
-    pub fn reset(&mut self, width: u16, height: u16, leds: &[Led]) {
-        self.spec_width = width;
-        self.spec_height = height;
+    /// Make the LED map of an image size and layout the current one, computing it if it isn't
+    /// cached
+    fn select(&mut self, width: u16, height: u16, leds: &[Led]) {
+        let layout = layout_hash(leds);
 
-        let fwidth = width as f32;
-        let fheight = height as f32;
+        match self
+            .maps
+            .iter()
+            .position(|map| map.matches(width, height, layout))
+        {
+            Some(0) => {}
+            Some(index) => {
+                // unwrap: the index was just found
+                let map = self.maps.remove(index).unwrap();
+                self.maps.push_front(map);
+            }
+            None => {
+                self.maps.truncate(MAP_CACHE_SIZE - 1);
+                self.maps
+                    .push_front(LedMap::new(width, height, layout, leds));
+            }
+        }
 
-        self.spec.clear();
-        self.spec.reserve(leds.len());
+        self.values.resize(leds.len(), Color16::default());
+    }
 
-        for spec in leds.iter() {
-            self.spec
-                .push(LedSpec::new(spec, width, height, fwidth, fheight));
-        }
+    fn is_valid_for(&self, image: &impl Image, leds: &[Led]) -> bool {
+        self.maps
+            .front()
+            .is_some_and(|map| map.matches(image.width(), image.height(), layout_hash(leds)))
     }
 
     pub fn reduce(&mut self, image: &impl Image, leds: &[Led], color_data: &mut [Color16]) {
-        let width = image.width();
-        let height = image.height();
+        self.select(image.width(), image.height(), leds);
 
-        if self.spec_width != width || self.spec_height != height || self.spec.len() != leds.len() {
-            self.reset(width, height, leds);
+        // unwrap: a map was just selected
+        let map = self.maps.front().unwrap();
+        for (spec, value) in map.spec.iter().zip(self.values.iter_mut()) {
+            *value = spec.reduce(image);
         }
 
-        for (spec, value) in self.spec.iter().zip(color_data.iter_mut()) {
-            let mut r_acc = 0u64;
-            let mut g_acc = 0u64;
-            let mut b_acc = 0u64;
-            let mut cnt = 0u64;
-
-            for y in spec.lymin..=spec.lymax {
-                for x in spec.lxmin..=spec.lxmax {
-                    // Safety: x (resp. y) are necessarily in 0..width (resp. 0..height)
-                    let rgb = unsafe { image.color_at_unchecked(x as _, y as _) };
-                    let area = 255;
-
-                    let (r, g, b) = rgb.into_components();
-                    r_acc += (r as u64 * 255) * area;
-                    g_acc += (g as u64 * 255) * area;
-                    b_acc += (b as u64 * 255) * area;
-                    cnt += area;
-                }
+        self.copy_values(color_data);
+    }
+
+    /// Update the color data for an image which only changed in the given region since the last
+    /// call to [`Reducer::reduce`] or [`Reducer::reduce_region`]
+    ///
+    /// Only LEDs which overlap the region are sampled again. If the image dimensions or the LEDs
+    /// changed, this falls back to reducing the whole image.
+    pub fn reduce_region(
+        &mut self,
+        image: &impl Image,
+        leds: &[Led],
+        color_data: &mut [Color16],
+        region: ImageRegion,
+    ) {
+        if !self.is_valid_for(image, leds) {
+            self.reduce(image, leds, color_data);
+            return;
+        }
+
+        // unwrap: the current map is valid for this image
+        let map = self.maps.front().unwrap();
+        for (spec, value) in map.spec.iter().zip(self.values.iter_mut()) {
+            if region.overlaps(spec.lxmin..=spec.lxmax, spec.lymin..=spec.lymax) {
+                *value = spec.reduce(image);
             }
+        }
+
+        self.copy_values(color_data);
+    }
 
-            *value = Color16::new(
-                ((r_acc / cnt.max(1)) * 65535 / (255 * 255)).min(u16::MAX as _) as u16,
-                ((g_acc / cnt.max(1)) * 65535 / (255 * 255)).min(u16::MAX as _) as u16,
-                ((b_acc / cnt.max(1)) * 65535 / (255 * 255)).min(u16::MAX as _) as u16,
-            );
+    fn copy_values(&self, color_data: &mut [Color16]) {
+        for (value, dst) in self.values.iter().zip(color_data.iter_mut()) {
+            *dst = *value;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::{
+        image::RawImage,
+        models::{ClassicLedConfig, ToLeds},
+    };
+
+    fn image(width: u16, height: u16, f: impl Fn(u16, u16) -> u8) -> RawImage {
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let v = f(x, y);
+                data.extend_from_slice(&[v, v / 2, 255 - v]);
+            }
+        }
+
+        RawImage::try_from((data, width as u32, height as u32)).unwrap()
+    }
+
+    #[test]
+    fn reduce_region_matches_full_reduce() {
+        let leds = ClassicLedConfig {
+            top: 8,
+            bottom: 8,
+            left: 4,
+            right: 4,
+            ..Default::default()
+        }
+        .to_leds();
+
+        let before = image(64, 48, |_, _| 10);
+        // Only the top-left corner changes
+        let after = image(64, 48, |x, y| if x < 16 && y < 8 { 200 } else { 10 });
+        let region = ImageRegion::clipped(0, 0, 16, 8, (64, 48));
+
+        let mut expected = vec![Color16::default(); leds.leds.len()];
+        Reducer::default().reduce(&after, &leds.leds, &mut expected);
+
+        let mut reducer = Reducer::default();
+        let mut actual = vec![Color16::default(); leds.leds.len()];
+        reducer.reduce(&before, &leds.leds, &mut actual);
+        reducer.reduce_region(&after, &leds.leds, &mut actual, region);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn led_maps_are_cached() {
+        let leds = ClassicLedConfig {
+            top: 8,
+            bottom: 8,
+            left: 4,
+            right: 4,
+            ..Default::default()
+        }
+        .to_leds();
+
+        let large = image(64, 48, |x, _| (x * 4) as u8);
+        let small = image(32, 24, |x, _| (x * 8) as u8);
+
+        let mut reducer = Reducer::default();
+        let mut actual = vec![Color16::default(); leds.leds.len()];
+        for image in [&large, &small, &large, &small] {
+            reducer.reduce(image, &leds.leds, &mut actual);
+
+            let mut expected = vec![Color16::default(); leds.leds.len()];
+            Reducer::default().reduce(image, &leds.leds, &mut expected);
+            assert_eq!(expected, actual);
+        }
+
+        assert_eq!(reducer.maps.len(), 2);
+
+        // A different layout with the same LED count gets its own map
+        let mut moved = leds.leds.clone();
+        moved[0].hmax = 0.5;
+        reducer.reduce(&small, &moved, &mut actual);
+        assert_eq!(reducer.maps.len(), 3);
+        assert_eq!(reducer.maps[0].layout, layout_hash(&moved));
+    }
+}
@@ -1,34 +1,73 @@
+use std::collections::HashSet;
+use std::future::Future;
 use std::sync::Arc;
 
+use futures::FutureExt;
 use thiserror::Error;
 use tokio::{
     select,
-    sync::{broadcast, mpsc, oneshot},
+    sync::{broadcast, mpsc, oneshot, watch},
 };
+use tracing::Instrument;
 
 use crate::{
     api::types::PriorityInfo,
-    global::{Event, Global, InputMessage, InstanceEventKind},
-    models::{Color, InstanceConfig},
-    servers::{self, ServerHandle},
+    component::ComponentName,
+    effects::InstanceEffects,
+    global::{ComponentStates, Event, Global, InputMessage, InstanceEventKind},
+    image::{ImageQueue, ImageQueueStats, RawImage},
+    models::{Color, Color16, ColorAdjustment, DeviceConfig, InstanceConfig},
 };
 
+#[cfg(feature = "audio")]
+mod audio;
+
 mod black_border_detector;
 use black_border_detector::*;
+pub use black_border_detector::{BlackBorderControl, BlackBorderState};
+
+mod boot_splash;
+use boot_splash::*;
 
 mod core;
 use self::core::*;
 
+#[cfg(feature = "data-presets")]
+mod data_presets;
+
 mod device;
 use device::*;
+pub use device::{DeviceInfo, DeviceStats, DeviceStatus};
+
+mod frames;
+use frames::FrameSender;
+pub use frames::{Frame, FrameObserver};
+
+mod latency;
+use latency::*;
+
+#[cfg(feature = "mpris")]
+mod media_artwork;
+
+mod mirror;
 
 mod muxer;
-pub use muxer::StartEffectError;
 use muxer::*;
+pub use muxer::{SelectPriorityError, StartEffectError, TimelineEvent, TimelineEventKind};
+
+#[cfg(feature = "ndi")]
+mod ndi;
+
+mod quiet_hours;
+use quiet_hours::*;
+
+mod screen;
 
 mod smoothing;
 use smoothing::*;
 
+mod startup;
+
 #[derive(Debug, Error)]
 pub enum InstanceError {
     #[error("i/o error: {0}")]
@@ -47,79 +86,236 @@ enum ActiveState {
     Deactivating,
 }
 
+/// Muxer updates already due which are coalesced with the output of a tick, bounded so running
+/// effects can't delay it
+const MAX_DUE_MUXER_UPDATES: usize = 8;
+
 pub struct Instance {
     config: Arc<InstanceConfig>,
     device: InstanceDevice,
     handle_rx: mpsc::Receiver<InstanceMessage>,
     receiver: broadcast::Receiver<InputMessage>,
     local_receiver: mpsc::Receiver<InputMessage>,
+    /// Input messages received but not processed yet
+    input_queue: ImageQueue<InputMessage>,
+    /// Muxer output waiting for the end of the tick
+    output: OutputCoalescer,
     event_tx: broadcast::Sender<Event>,
     muxer: PriorityMuxer,
     core: Core,
-    _boblight_server: Option<Result<ServerHandle, std::io::Error>>,
+    #[cfg(feature = "ndi")]
+    _ndi_receiver: Option<ndi::NdiReceiverHandle>,
+    #[cfg(feature = "audio")]
+    _audio: Option<audio::AudioHandle>,
+    _screen: Option<screen::ScreenHandle>,
+    #[cfg(feature = "mpris")]
+    _media_artwork: Option<media_artwork::MediaArtworkHandle>,
+    #[cfg(feature = "data-presets")]
+    _data_presets: Option<data_presets::DataPresetsHandle>,
+    _mirror: Option<mirror::InstanceMirrorHandle>,
     active_state: ActiveState,
+    boot_splash: Option<BootSplash>,
+    blackout: watch::Receiver<bool>,
+    components: watch::Receiver<ComponentStates>,
+    /// Components of this instance disabled at runtime
+    disabled: HashSet<ComponentName>,
+    quiet_hours: QuietHours,
+    latency: FrameLatency,
 }
 
 impl Instance {
     pub async fn new(global: Global, config: InstanceConfig) -> (Self, InstanceHandle) {
-        let device: InstanceDevice =
-            Device::new(&config.instance.friendly_name, config.device.clone())
-                .await
-                .into();
-
         let led_count = config.leds.leds.len();
 
-        if let Err(error) = &device.inner {
+        // The device is initialized in the background, once it is ready
+        let device = if config.dry_run.enable {
+            info!(
+                instance = %config.instance.id,
+                name = %config.instance.friendly_name,
+                "dry run, the device will not be written to"
+            );
+            InstanceDevice::dry_run()
+        } else {
+            InstanceDevice::starting({
+                let name = config.instance.friendly_name.clone();
+                let device = config.device.clone();
+                let startup = config.startup.clone();
+
+                async move {
+                    startup::wait_ready(&startup).await;
+                    Device::new(&name, device, led_count).await
+                }
+            })
+        };
+
+        // Mismatches are handled at runtime by clamping, but they are most likely a mistake
+        for mismatch in config.led_count_mismatches() {
             error!(
                 instance = %config.instance.id,
                 name = %config.instance.friendly_name,
-                error = %error,
-                "initializing instance failed"
+                error = %mismatch,
+                "inconsistent LED count"
             );
         }
 
         let receiver = global.subscribe_input().await;
         let (local_tx, local_receiver) = mpsc::channel(4);
 
-        let muxer = PriorityMuxer::new(global.clone(), MuxerConfig { led_count }).await;
-        let core = Core::new(&config).await;
+        let effects = {
+            let global_dirs = global.read_effects(|e| e.dirs().to_vec()).await;
+            let effects =
+                InstanceEffects::new(&config.effects, &global.paths().await, &global_dirs).await;
+            Arc::new(effects)
+        };
+
+        let input_queue = ImageQueue::new(config.memory_limits.input_queue_bytes());
+        let muxer = PriorityMuxer::new(
+            global.clone(),
+            effects.clone(),
+            MuxerConfig {
+                instance_id: config.instance.id,
+                led_count,
+                effect_queue_bytes: config.memory_limits.effect_queue_bytes(),
+                latch_time: config.device.latch_time(),
+                image_size: config.leds.grid_size(),
+            },
+        )
+        .await;
+        let mut core = Core::new(&config).await;
+
+        let quiet_hours = QuietHours::new(config.quiet_hours.clone());
+        core.set_brightness_cap(quiet_hours.current());
+        let latency = FrameLatency::new(&config.frame_latency);
 
         let (tx, handle_rx) = mpsc::channel(1);
         let id = config.instance.id;
-        let handle = InstanceHandle { id, tx, local_tx };
+        let handle = InstanceHandle {
+            id,
+            name: config.instance.friendly_name.clone(),
+            effects,
+            tx,
+            local_tx,
+        };
 
         let config = Arc::new(config);
-        let _boblight_server = if config.boblight_server.enable {
-            let server_handle = servers::bind(
-                "Boblight",
-                config.boblight_server.clone(),
+        #[cfg(feature = "ndi")]
+        let _ndi_receiver = start_input(
+            &config,
+            config.ndi_receiver.enable,
+            "NDI receiver",
+            ndi::start(
+                config.ndi_receiver.clone(),
+                format!("hyperion.rs {}", config.instance.friendly_name),
                 global.clone(),
-                {
-                    let handle = handle.clone();
+                handle.input_channel().clone(),
+            ),
+        )
+        .await;
 
-                    move |tcp, global| {
-                        servers::boblight::handle_client(tcp, led_count, handle.clone(), global)
-                    }
-                },
-            )
-            .await;
-
-            if let Err(error) = &server_handle {
-                error!(
-                    instance = %config.instance.id,
-                    name = %config.instance.friendly_name,
-                    error = %error,
-                    "cannot start Boblight server"
-                );
-            }
+        #[cfg(not(feature = "ndi"))]
+        warn_unsupported(&config, config.ndi_receiver.enable, "NDI receiver", "ndi");
 
-            Some(server_handle)
+        #[cfg(feature = "audio")]
+        let _audio = start_input(
+            &config,
+            config.instance_capture.audio_enable,
+            "audio visualization",
+            audio::start(
+                &config.instance_capture,
+                config.leds.grid_size(),
+                global.clone(),
+                handle.input_channel().clone(),
+            ),
+        )
+        .await;
+
+        #[cfg(not(feature = "audio"))]
+        warn_unsupported(
+            &config,
+            config.instance_capture.audio_enable,
+            "audio visualization",
+            "audio",
+        );
+
+        let _screen = start_input(
+            &config,
+            config.instance_capture.system_enable,
+            "screen capture",
+            screen::start(
+                &config.instance_capture,
+                global.clone(),
+                handle.input_channel().clone(),
+            ),
+        )
+        .await
+        .flatten();
+
+        #[cfg(feature = "mpris")]
+        let _media_artwork = start_input(
+            &config,
+            config.media_artwork.enable,
+            "media artwork",
+            media_artwork::start(
+                config.media_artwork.clone(),
+                global.clone(),
+                handle.input_channel().clone(),
+            ),
+        )
+        .await;
+
+        #[cfg(not(feature = "mpris"))]
+        warn_unsupported(
+            &config,
+            config.media_artwork.enable,
+            "media artwork",
+            "mpris",
+        );
+
+        #[cfg(feature = "data-presets")]
+        let _data_presets = start_input(
+            &config,
+            config.data_presets.enable,
+            "data presets",
+            data_presets::start(
+                config.data_presets.clone(),
+                global.clone(),
+                handle.input_channel().clone(),
+            ),
+        )
+        .await;
+
+        #[cfg(not(feature = "data-presets"))]
+        warn_unsupported(
+            &config,
+            config.data_presets.enable,
+            "data presets",
+            "data-presets",
+        );
+
+        let _mirror = start_input(
+            &config,
+            config.instance_mirror.enable,
+            "instance mirror",
+            mirror::start(
+                config.instance_mirror.clone(),
+                config.instance.id,
+                led_count,
+                global.clone(),
+                handle.input_channel().clone(),
+            ),
+        )
+        .await;
+
+        let event_tx = global.get_event_tx().await;
+        let blackout = global.subscribe_blackout().await;
+        let components = global.subscribe_component_states().await;
+
+        let boot_splash = if config.boot_splash.enable {
+            Some(BootSplash::new(config.boot_splash.clone(), led_count))
         } else {
             None
         };
 
-        let event_tx = global.get_event_tx().await;
-
         (
             Self {
                 config,
@@ -127,24 +323,171 @@ impl Instance {
                 handle_rx,
                 receiver,
                 local_receiver,
+                input_queue,
+                output: Default::default(),
                 event_tx,
                 muxer,
                 core,
-                _boblight_server,
+                #[cfg(feature = "ndi")]
+                _ndi_receiver,
+                #[cfg(feature = "audio")]
+                _audio,
+                _screen,
+                #[cfg(feature = "mpris")]
+                _media_artwork,
+                #[cfg(feature = "data-presets")]
+                _data_presets,
+                _mirror,
                 active_state: ActiveState::default(),
+                boot_splash,
+                blackout,
+                components,
+                disabled: Default::default(),
+                quiet_hours,
+                latency,
             },
             handle,
         )
     }
 
+    /// Queue an input message, along with the ones already waiting in the input channels
+    ///
+    /// Draining the channels lets the queue drop the oldest frames of a burst instead of
+    /// processing all of them.
+    fn queue_input_messages(&mut self, message: InputMessage) {
+        self.queue_input_message(message);
+        self.queue_ready_input_messages();
+    }
+
+    /// Queue the input messages waiting in the input channels
+    fn queue_ready_input_messages(&mut self) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => self.queue_input_message(message),
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    warn!(skipped = %skipped, "skipped input messages");
+                }
+                // Closed channels are handled by the main loop
+                Err(_) => break,
+            }
+        }
+
+        while let Ok(message) = self.local_receiver.try_recv() {
+            self.queue_input_message(message);
+        }
+    }
+
+    fn queue_input_message(&mut self, message: InputMessage) {
+        let dropped = self.input_queue.push(message);
+
+        if dropped > 0 {
+            let stats = self.input_queue.stats();
+
+            if stats.dropped_frames == dropped as u64 {
+                warn!(
+                    limit_mb = %self.config.memory_limits.input_queue_mb,
+                    "input frames are dropped, the instance can't keep up"
+                );
+            } else {
+                debug!(dropped = %dropped, total = %stats.dropped_frames, "dropped input frames");
+            }
+        }
+    }
+
+    /// Process the queued input messages
+    async fn on_input_messages(&mut self) {
+        while let Some(message) = self.input_queue.pop() {
+            self.on_input_message(message).await;
+        }
+    }
+
     async fn on_input_message(&mut self, message: InputMessage) {
-        if let Some(message) = self.muxer.handle_message(message).await {
+        // Process the message in the context of the request that sent it
+        let span = message.span().clone();
+
+        if let Some(message) = self
+            .muxer
+            .handle_message(message)
+            .instrument(span.clone())
+            .await
+        {
             // The message triggered a muxing update
+            let _entered = span.enter();
             self.on_muxed_message(message);
         }
+
+        self.notify_priorities_changed();
+    }
+
+    fn notify_priorities_changed(&mut self) {
+        if self.muxer.take_priorities_changed() {
+            // ok: there may be no event receiver
+            self.event_tx
+                .send(Event::instance(
+                    self.id(),
+                    InstanceEventKind::PrioritiesChanged,
+                ))
+                .ok();
+        }
     }
 
     fn on_muxed_message(&mut self, message: MuxedMessage) {
+        if let MuxedMessageData::Image { .. } = message.data() {
+            if self.latency.is_stale(message.timestamp()) {
+                trace!(age = ?message.timestamp().elapsed(), "dropping stale image");
+                // The region of the next image of this priority is relative to this one
+                self.core.invalidate_region();
+                return;
+            }
+
+            self.latency.frame_received(message.timestamp());
+        }
+
+        // The core only gets the last output of the tick
+        self.output.push(message);
+    }
+
+    /// Hand the last muxer output of the current tick to the core
+    ///
+    /// Inputs and muxer updates which are already due are handled first, so priorities switching
+    /// several times in a row, e.g. an input expiring right before a new one arrives, only produce
+    /// their final state.
+    async fn flush_muxed_output(&mut self) {
+        if !self.output.is_pending() {
+            return;
+        }
+
+        self.queue_ready_input_messages();
+        self.on_input_messages().await;
+
+        for _ in 0..MAX_DUE_MUXER_UPDATES {
+            match self.muxer.update().now_or_never() {
+                Some(message) => {
+                    if let Some(message) = message {
+                        self.on_muxed_message(message);
+                    }
+
+                    self.notify_priorities_changed();
+                }
+                None => break,
+            }
+        }
+
+        if let Some(message) = self.output.take() {
+            self.apply_muxed_output(message);
+        }
+    }
+
+    fn apply_muxed_output(&mut self, message: MuxedMessage) {
+        // The first real input cancels the startup animation
+        if self.boot_splash.is_some()
+            && (message.priority() != muxer::MAX_PRIORITY
+                || message.color() != Some(Color::new(0, 0, 0)))
+        {
+            debug!("input received, cancelling boot splash");
+            self.boot_splash = None;
+        }
+
         if self.active_state == ActiveState::Active {
             if message.priority() == muxer::MAX_PRIORITY
                 && message.color() == Some(Color::new(0, 0, 0))
@@ -168,16 +511,170 @@ impl Instance {
         self.config.instance.id
     }
 
+    fn is_output_disabled(&self) -> bool {
+        output_disabled(&self.blackout, &self.disabled)
+    }
+
+    async fn on_device_led_count_changed(
+        &mut self,
+        change: LedCountChange,
+    ) -> Result<(), InstanceError> {
+        // ok: there may be no event receiver
+        self.event_tx
+            .send(Event::instance(
+                self.id(),
+                InstanceEventKind::DeviceLedCountChanged {
+                    previous: change.previous,
+                    current: change.current,
+                },
+            ))
+            .ok();
+
+        // Rewrite the current output so the device gets data in its new layout right away
+        if self.is_output_disabled() {
+            let black = vec![Color::default(); self.config.leds.leds.len()];
+            self.device.set_led_data(&black).await?;
+        } else if self.boot_splash.is_none() {
+            self.device.set_led_data(self.core.current()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn on_output_state_changed(&mut self) -> Result<(), InstanceError> {
+        if self.is_output_disabled() {
+            debug!("output disabled");
+            let black = vec![Color::default(); self.config.leds.leds.len()];
+            self.device.set_led_data(&black).await?;
+        } else {
+            debug!("output enabled");
+            // The boot splash is not resumed, go straight to the current output
+            self.boot_splash = None;
+            self.device.set_led_data(self.core.current()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn on_component_states_changed(&mut self) -> Result<(), InstanceError> {
+        let id = self.id();
+        let disabled = self.components.borrow_and_update().disabled(id);
+        let was_output_disabled = self.is_output_disabled();
+
+        let changed: Vec<_> = disabled
+            .symmetric_difference(&self.disabled)
+            .copied()
+            .collect();
+        self.disabled = disabled;
+
+        for component in changed {
+            let enabled = !self.disabled.contains(&component);
+            debug!(component = %component, enabled = %enabled, "component state changed");
+
+            match component {
+                // Handled through the output state
+                ComponentName::All | ComponentName::LedDevice => {}
+                ComponentName::Smoothing => self.core.set_smoothing_enabled(enabled),
+                ComponentName::BlackBorder => {
+                    self.core.black_border_detector().control(if enabled {
+                        BlackBorderControl::Enable
+                    } else {
+                        BlackBorderControl::Disable { duration: None }
+                    });
+                }
+                component => {
+                    if let Some(message) =
+                        self.muxer.set_component_enabled(component, enabled).await
+                    {
+                        self.on_muxed_message(message);
+                    }
+
+                    self.notify_priorities_changed();
+                }
+            }
+        }
+
+        if was_output_disabled != self.is_output_disabled() {
+            self.on_output_state_changed().await?;
+        }
+
+        Ok(())
+    }
+
     async fn handle_instance_message(&mut self, message: InstanceMessage) -> InstanceControl {
         // ok: the instance shouldn't care if the receiver dropped
 
         match message {
             InstanceMessage::PriorityInfo(tx) => {
-                tx.send(self.muxer.current_priorities().await).ok();
+                let mut priorities = self.muxer.current_priorities().await;
+                for priority in &mut priorities {
+                    priority.instance_name = Some(self.config.instance.friendly_name.clone());
+                }
+
+                tx.send(priorities).ok();
+            }
+            InstanceMessage::PrioritiesAutoselect(tx) => {
+                tx.send(self.muxer.is_auto_select()).ok();
+            }
+            InstanceMessage::SelectPriority(priority, tx) => {
+                let result = self.muxer.select_priority(priority).map(|message| {
+                    if let Some(message) = message {
+                        self.on_muxed_message(message);
+                    }
+                });
+
+                tx.send(result).ok();
+                self.notify_priorities_changed();
+            }
+            InstanceMessage::PriorityTimeline(tx) => {
+                tx.send(self.muxer.timeline().cloned().collect()).ok();
+            }
+            InstanceMessage::LedColors(tx) => {
+                tx.send(self.core.current().to_vec()).ok();
+            }
+            InstanceMessage::ObserveFrames(tx) => {
+                tx.send(self.device.frames.subscribe()).ok();
+            }
+            InstanceMessage::CurrentImage(tx) => {
+                tx.send(self.core.current_image().cloned()).ok();
             }
             InstanceMessage::Config(tx) => {
                 tx.send(self.config.clone()).ok();
             }
+            InstanceMessage::DeviceInfo(tx) => {
+                tx.send(self.device.info()).ok();
+            }
+            InstanceMessage::InputQueueStats(tx) => {
+                tx.send(self.input_queue.stats()).ok();
+            }
+            InstanceMessage::BlackBorder(control, tx) => {
+                let detector = self.core.black_border_detector();
+                if let Some(control) = control {
+                    if detector.control(control) {
+                        debug!(control = ?control, "black border cleared");
+                    }
+                }
+
+                tx.send(detector.state()).ok();
+            }
+            InstanceMessage::UpdateEffectArgs(priority, args, tx) => {
+                tx.send(self.muxer.update_effect_args(priority, args).await)
+                    .ok();
+                self.notify_priorities_changed();
+            }
+            InstanceMessage::SetColorAdjustment(color, tx) => {
+                self.core.set_color_adjustment(&color);
+                Arc::make_mut(&mut self.config).color = color;
+                tx.send(()).ok();
+
+                // ok: there may be no event receiver
+                self.event_tx
+                    .send(Event::instance(
+                        self.id(),
+                        InstanceEventKind::AdjustmentChanged,
+                    ))
+                    .ok();
+            }
             InstanceMessage::Stop(tx) => {
                 tx.send(()).ok();
                 return InstanceControl::Break;
@@ -189,15 +686,24 @@ impl Instance {
 
     #[instrument]
     pub async fn run(mut self) -> Result<(), InstanceError> {
+        // Components may have been disabled before this instance (re)started
+        self.on_component_states_changed().await?;
+
         loop {
             select! {
                 update = self.device.update() => {
                     trace!("device update");
 
-                    if let Err(error) = update {
-                        // A device update shouldn't error, disable it
-                        error!(error = %error, "device update failed, disabling device");
-                        self.device.inner = Err(error);
+                    match update {
+                        Ok(Some(change)) => {
+                            self.on_device_led_count_changed(change).await?;
+                        }
+                        Ok(None) => {}
+                        Err(error) => {
+                            // A device update shouldn't error, disable it
+                            error!(error = %error, "device update failed, disabling device");
+                            self.device.fail(&error);
+                        }
                     }
                 },
                 message = self.receiver.recv() => {
@@ -205,7 +711,8 @@ impl Instance {
 
                     match message {
                         Ok(message) => {
-                            self.on_input_message(message).await;
+                            self.queue_input_messages(message);
+                            self.on_input_messages().await;
                         },
                         Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                             // No more input messages
@@ -220,7 +727,8 @@ impl Instance {
                     trace!(message = ?message, "local msg");
 
                     if let Some(message) = message {
-                        self.on_input_message(message).await;
+                        self.queue_input_messages(message);
+                        self.on_input_messages().await;
                     } else {
                         break Ok(());
                     }
@@ -232,12 +740,46 @@ impl Instance {
                     if let Some(message) = message {
                         self.on_muxed_message(message);
                     }
+
+                    self.notify_priorities_changed();
+                },
+                cap = self.quiet_hours.update() => {
+                    debug!(cap = %cap, "quiet hours brightness cap changed");
+                    self.core.set_brightness_cap(cap);
+                },
+                Ok(()) = self.blackout.changed() => {
+                    self.on_output_state_changed().await?;
+                },
+                Ok(()) = self.components.changed() => {
+                    self.on_component_states_changed().await?;
+                },
+                frame = boot_splash_update(&mut self.boot_splash) => {
+                    trace!("boot splash update");
+
+                    if let Some(led_data) = frame {
+                        if !output_disabled(&self.blackout, &self.disabled) {
+                            self.device.set_led_data(led_data).await?;
+                        }
+                    } else {
+                        self.boot_splash = None;
+                    }
                 },
                 (led_data, update) = self.core.update() => {
                     trace!("core update");
 
-                    // LED data changed
-                    self.device.set_led_data(led_data).await?;
+                    // LED data changed, unless the boot splash is still running or the output is
+                    // blacked out
+                    if self.boot_splash.is_none() && !output_disabled(&self.blackout, &self.disabled) {
+                        if self.config.smoothing.dithering {
+                            let settled = update == SmoothingUpdate::Settled;
+                            self.device
+                                .set_smoothed_led_data(self.core.current16(), settled)
+                                .await?;
+                        } else {
+                            self.device.set_led_data(led_data).await?;
+                        }
+                        self.latency.frame_written();
+                    }
 
                     if update == SmoothingUpdate::Settled &&
                         self.active_state == ActiveState::Deactivating {
@@ -260,43 +802,224 @@ impl Instance {
                     }
                 }
             }
+
+            self.flush_muxed_output().await;
+        }
+    }
+}
+
+/// true if the device should be kept black: during a blackout, or while the instance or its
+/// device are disabled
+fn output_disabled(blackout: &watch::Receiver<bool>, disabled: &HashSet<ComponentName>) -> bool {
+    *blackout.borrow()
+        || disabled.contains(&ComponentName::All)
+        || disabled.contains(&ComponentName::LedDevice)
+}
+
+/// Start an input of the instance if it is enabled
+///
+/// Inputs which fail to start are logged and left out, the instance runs without them.
+async fn start_input<H, E: std::fmt::Display>(
+    config: &InstanceConfig,
+    enabled: bool,
+    what: &str,
+    start: impl Future<Output = Result<H, E>>,
+) -> Option<H> {
+    if !enabled {
+        return None;
+    }
+
+    match start.await {
+        Ok(handle) => Some(handle),
+        Err(error) => {
+            error!(
+                instance = %config.instance.id,
+                name = %config.instance.friendly_name,
+                error = %error,
+                "cannot start {}", what
+            );
+
+            None
         }
     }
 }
 
+/// Warn about an input enabled in the configuration, but left out of this build
+#[cfg(any(
+    not(feature = "ndi"),
+    not(feature = "audio"),
+    not(feature = "mpris"),
+    not(feature = "data-presets")
+))]
+fn warn_unsupported(config: &InstanceConfig, enabled: bool, what: &str, feature: &str) {
+    if enabled {
+        warn!(
+            instance = %config.instance.id,
+            name = %config.instance.friendly_name,
+            "cannot start {}: hyperion.rs was built without the {} feature", what, feature
+        );
+    }
+}
+
+async fn boot_splash_update(boot_splash: &mut Option<BootSplash>) -> Option<&[Color]> {
+    if let Some(boot_splash) = boot_splash {
+        boot_splash.update().await
+    } else {
+        futures::future::pending().await
+    }
+}
+
 impl std::fmt::Debug for Instance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Instance").field("id", &self.id()).finish()
+        f.debug_struct("Instance")
+            .field("id", &self.id())
+            .field("name", &self.config.instance.friendly_name)
+            .finish()
     }
 }
 
-/// A wrapper for a device that may have failed initializing
+/// A wrapper for a device that may still be starting, or may have failed initializing
 struct InstanceDevice {
-    inner: Result<Device, DeviceError>,
+    state: DeviceState,
+    frames: FrameSender,
+    dither: Ditherer,
+    dithered: Vec<Color>,
+}
+
+enum DeviceState {
+    Starting {
+        device: futures::future::BoxFuture<'static, Result<Device, DeviceError>>,
+        /// Latest LED data received while starting
+        led_data: Option<Vec<Color>>,
+    },
+    Ready(Box<Device>),
+    /// The device was disabled, with its last statistics
+    Failed(DeviceStats),
+    /// The output is only sent to the frame observers
+    DryRun,
 }
 
 impl InstanceDevice {
-    async fn update(&mut self) -> Result<(), DeviceError> {
-        if let Ok(device) = &mut self.inner {
-            device.update().await
-        } else {
-            futures::future::pending::<()>().await;
-            Ok(())
+    fn starting(
+        device: impl futures::Future<Output = Result<Device, DeviceError>> + Send + 'static,
+    ) -> Self {
+        Self {
+            state: DeviceState::Starting {
+                device: Box::pin(device),
+                led_data: None,
+            },
+            frames: FrameSender::new(),
+            dither: Default::default(),
+            dithered: Vec::new(),
+        }
+    }
+
+    fn dry_run() -> Self {
+        Self {
+            state: DeviceState::DryRun,
+            frames: FrameSender::new(),
+            dither: Default::default(),
+            dithered: Vec::new(),
+        }
+    }
+
+    async fn update(&mut self) -> Result<Option<LedCountChange>, DeviceError> {
+        match &mut self.state {
+            DeviceState::Starting { device, led_data } => match device.await {
+                Ok(device) => {
+                    let led_data = led_data.take();
+                    self.state = DeviceState::Ready(Box::new(device));
+
+                    // Catch up with the LED data we missed while starting
+                    match (&mut self.state, led_data) {
+                        (DeviceState::Ready(device), Some(led_data)) => {
+                            device.set_led_data(&led_data).await.map(|_| None)
+                        }
+                        _ => Ok(None),
+                    }
+                }
+                Err(error) => {
+                    error!(error = %error, "initializing device failed");
+                    self.fail(&error);
+                    Ok(None)
+                }
+            },
+            DeviceState::Ready(device) => device.update().await,
+            DeviceState::Failed(_) | DeviceState::DryRun => futures::future::pending().await,
         }
     }
 
     async fn set_led_data(&mut self, led_data: &[Color]) -> Result<(), DeviceError> {
-        if let Ok(device) = &mut self.inner {
-            device.set_led_data(led_data).await
-        } else {
-            Ok(())
+        self.frames.send(led_data);
+
+        match &mut self.state {
+            DeviceState::Starting {
+                led_data: pending, ..
+            } => {
+                let pending = pending.get_or_insert_with(Vec::new);
+                pending.clear();
+                pending.extend_from_slice(led_data);
+                Ok(())
+            }
+            DeviceState::Ready(device) => device.set_led_data(led_data).await,
+            DeviceState::Failed(_) | DeviceState::DryRun => Ok(()),
         }
     }
-}
 
-impl From<Result<Device, DeviceError>> for InstanceDevice {
-    fn from(inner: Result<Device, DeviceError>) -> Self {
-        Self { inner }
+    /// Write the output of the smoothing, dithered while it is still changing
+    async fn set_smoothed_led_data(
+        &mut self,
+        led_data: &[Color16],
+        settled: bool,
+    ) -> Result<(), DeviceError> {
+        let mut dithered = std::mem::take(&mut self.dithered);
+        self.dither.quantize(led_data, settled, &mut dithered);
+
+        let result = self.set_led_data(&dithered).await;
+        self.dithered = dithered;
+        result
+    }
+
+    /// Disable the device, keeping the statistics of what was written before
+    fn fail(&mut self, error: &DeviceError) {
+        let stats = match &self.state {
+            DeviceState::Ready(device) => device.stats(),
+            _ => Default::default(),
+        };
+
+        self.state = DeviceState::Failed(DeviceStats {
+            last_error: Some(error.to_string()),
+            ..stats
+        });
+    }
+
+    fn info(&self) -> DeviceInfo {
+        match &self.state {
+            DeviceState::Starting { .. } => DeviceInfo {
+                status: DeviceStatus::Starting,
+                stats: Default::default(),
+            },
+            DeviceState::Ready(device) => {
+                let stats = device.stats();
+
+                DeviceInfo {
+                    status: if stats.last_error.is_some() {
+                        DeviceStatus::Disconnected
+                    } else {
+                        DeviceStatus::Connected
+                    },
+                    stats,
+                }
+            }
+            DeviceState::Failed(stats) => DeviceInfo {
+                status: DeviceStatus::Failed,
+                stats: stats.clone(),
+            },
+            DeviceState::DryRun => DeviceInfo {
+                status: DeviceStatus::DryRun,
+                stats: Default::default(),
+            },
+        }
     }
 }
 
@@ -309,13 +1032,36 @@ enum InstanceControl {
 #[derive(Debug)]
 enum InstanceMessage {
     PriorityInfo(oneshot::Sender<Vec<PriorityInfo>>),
+    PrioritiesAutoselect(oneshot::Sender<bool>),
+    SelectPriority(
+        Option<i32>,
+        oneshot::Sender<Result<(), SelectPriorityError>>,
+    ),
+    PriorityTimeline(oneshot::Sender<Vec<TimelineEvent>>),
+    LedColors(oneshot::Sender<Vec<Color>>),
+    ObserveFrames(oneshot::Sender<FrameObserver>),
+    CurrentImage(oneshot::Sender<Option<Arc<RawImage>>>),
     Config(oneshot::Sender<Arc<InstanceConfig>>),
+    DeviceInfo(oneshot::Sender<DeviceInfo>),
+    InputQueueStats(oneshot::Sender<ImageQueueStats>),
+    BlackBorder(
+        Option<BlackBorderControl>,
+        oneshot::Sender<BlackBorderState>,
+    ),
+    UpdateEffectArgs(
+        i32,
+        serde_json::Map<String, serde_json::Value>,
+        oneshot::Sender<Result<(), StartEffectError>>,
+    ),
+    SetColorAdjustment(ColorAdjustment, oneshot::Sender<()>),
     Stop(oneshot::Sender<()>),
 }
 
 #[derive(Clone)]
 pub struct InstanceHandle {
     id: i32,
+    name: String,
+    effects: Arc<InstanceEffects>,
     tx: mpsc::Sender<InstanceMessage>,
     local_tx: mpsc::Sender<InputMessage>,
 }
@@ -343,6 +1089,16 @@ impl InstanceHandle {
         self.id
     }
 
+    /// Friendly name of the instance when it was started
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Effects available to the instance
+    pub fn effects(&self) -> &InstanceEffects {
+        &self.effects
+    }
+
     pub fn input_channel(&self) -> &mpsc::Sender<InputMessage> {
         &self.local_tx
     }
@@ -357,15 +1113,215 @@ impl InstanceHandle {
         Ok(rx.await?)
     }
 
+    /// Check if the visible priority is the lowest one, i.e. no priority was selected manually
+    pub async fn priorities_autoselect(&self) -> Result<bool, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(InstanceMessage::PrioritiesAutoselect(tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Show the input at `priority` regardless of lower priorities, or go back to showing the
+    /// lowest priority if `None`
+    pub async fn select_priority(
+        &self,
+        priority: Option<i32>,
+    ) -> Result<Result<(), SelectPriorityError>, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(InstanceMessage::SelectPriority(priority, tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Get the last changes of the visible priority, most recent first
+    pub async fn priority_timeline(&self) -> Result<Vec<TimelineEvent>, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(InstanceMessage::PriorityTimeline(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Get the current LED colors of the instance, after smoothing
+    pub async fn led_colors(&self) -> Result<Vec<Color>, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(InstanceMessage::LedColors(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Observe the frames written to the device of the instance
+    ///
+    /// Frames are in layout order, black while the output is disabled, and include the boot
+    /// splash.
+    pub async fn observe_frames(&self) -> Result<FrameObserver, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(InstanceMessage::ObserveFrames(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Call `callback` with every frame written to the device of the instance, until it stops
+    pub async fn on_frame(
+        &self,
+        mut callback: impl FnMut(Frame) + Send + 'static,
+    ) -> Result<tokio::task::JoinHandle<()>, InstanceHandleError> {
+        let mut observer = self.observe_frames().await?;
+
+        Ok(tokio::spawn(async move {
+            while let Some(frame) = observer.next().await {
+                callback(frame);
+            }
+        }))
+    }
+
+    /// Get the image currently processed by the instance, if the visible input is an image
+    pub async fn current_image(&self) -> Result<Option<Arc<RawImage>>, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(InstanceMessage::CurrentImage(tx)).await?;
+        Ok(rx.await?)
+    }
+
     pub async fn config(&self) -> Result<Arc<InstanceConfig>, InstanceHandleError> {
         let (tx, rx) = oneshot::channel();
         self.tx.send(InstanceMessage::Config(tx)).await?;
         Ok(rx.await?)
     }
 
+    /// Get the connection state and output statistics of the device
+    pub async fn device_info(&self) -> Result<DeviceInfo, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(InstanceMessage::DeviceInfo(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Get the counters of the frames dropped from the input queue
+    pub async fn input_queue_stats(&self) -> Result<ImageQueueStats, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(InstanceMessage::InputQueueStats(tx)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Get the state of the black border detector, after applying the given control command
+    pub async fn black_border(
+        &self,
+        control: Option<BlackBorderControl>,
+    ) -> Result<BlackBorderState, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(InstanceMessage::BlackBorder(control, tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Update the arguments of the effect running at `priority`, restarting it if its provider
+    /// can't update them while it runs
+    pub async fn update_effect_args(
+        &self,
+        priority: i32,
+        args: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Result<(), StartEffectError>, InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(InstanceMessage::UpdateEffectArgs(priority, args, tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Replace the color adjustments of this instance, without restarting it
+    pub async fn set_color_adjustment(
+        &self,
+        color: ColorAdjustment,
+    ) -> Result<(), InstanceHandleError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(InstanceMessage::SetColorAdjustment(color, tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
     pub async fn stop(&self) -> Result<(), InstanceHandleError> {
         let (tx, rx) = oneshot::channel();
         self.tx.send(InstanceMessage::Stop(tx)).await?;
         Ok(rx.await?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        global::{GlobalData, Paths},
+        models::{Config, GlobalConfig},
+    };
+
+    #[tokio::test]
+    async fn test_blackout_disables_output() {
+        let global = GlobalData::new(
+            &Config::new(GlobalConfig::default(), []),
+            Paths::new(None).unwrap(),
+        )
+        .wrap();
+        let blackout = global.subscribe_blackout().await;
+        let mut disabled = HashSet::new();
+
+        assert!(!output_disabled(&blackout, &disabled));
+        global.set_blackout(true).await;
+        assert!(output_disabled(&blackout, &disabled));
+        global.set_blackout(false).await;
+        assert!(!output_disabled(&blackout, &disabled));
+
+        // Disabling the device also keeps it black
+        disabled.insert(ComponentName::LedDevice);
+        assert!(output_disabled(&blackout, &disabled));
+    }
+
+    fn dummy_device() -> crate::models::Device {
+        crate::models::Device::Dummy(crate::models::Dummy {
+            hardware_led_count: 2,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_device_starting_ready() {
+        let (started_tx, started_rx) = oneshot::channel();
+        let mut device = InstanceDevice::starting(async move {
+            started_rx.await.ok();
+            Device::new("test", dummy_device(), 2).await
+        });
+        assert_eq!(device.info().status, DeviceStatus::Starting);
+
+        // LED data received while starting is written once the device is ready
+        device
+            .set_led_data(&[Color::new(255, 0, 0); 2])
+            .await
+            .unwrap();
+        assert_eq!(device.info().status, DeviceStatus::Starting);
+
+        started_tx.send(()).unwrap();
+        assert_eq!(device.update().await.unwrap(), None);
+
+        let info = device.info();
+        assert_eq!(info.status, DeviceStatus::Connected);
+        assert_eq!(info.stats.frames, 1);
+    }
+
+    #[tokio::test]
+    async fn test_device_starting_failed() {
+        let mut device = InstanceDevice::starting(async { Err(DeviceError::NotSupported("test")) });
+        device
+            .set_led_data(&[Color::new(255, 0, 0); 2])
+            .await
+            .unwrap();
+
+        // Initialization errors disable the device instead of stopping the instance
+        assert_eq!(device.update().await.unwrap(), None);
+
+        let info = device.info();
+        assert_eq!(info.status, DeviceStatus::Failed);
+        assert_eq!(
+            info.stats.last_error.as_deref(),
+            Some("device not supported: test")
+        );
+        assert!(device.set_led_data(&[Color::default(); 2]).await.is_ok());
+    }
+}
@@ -0,0 +1,82 @@
+//! Visualization of the captured audio
+
+use std::sync::Arc;
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    global::{
+        Global, InputMessage, InputMessageData, InputSourceError, InputSourceName, Message,
+        PriorityGuard,
+    },
+    grabber::audio::Visualizer,
+    models,
+};
+
+/// Handle to the visualization task, which stops it when dropped
+pub struct AudioHandle {
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for AudioHandle {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+/// Start showing the captured audio
+///
+/// Images of `image_size` are sent to `tx` at the audio priority of `config`, until the returned
+/// handle is dropped. The audio capture runs as long as one instance shows it.
+pub async fn start(
+    config: &models::InstanceCapture,
+    image_size: (u32, u32),
+    global: Global,
+    tx: mpsc::Sender<InputMessage>,
+) -> Result<AudioHandle, InputSourceError> {
+    let capture = global.audio_capture().await;
+    let source = global
+        .register_input_source(
+            InputSourceName::Audio {
+                device: capture.device().to_owned(),
+            },
+            Some(config.audio_priority),
+        )
+        .await?;
+
+    let mut visualizer = Visualizer::new(
+        global
+            .read_config(|config| config.global.grabber_audio.clone())
+            .await,
+        image_size,
+    );
+    let priority = config.audio_priority;
+
+    let join_handle = tokio::spawn(async move {
+        // Clear our priority when stopping
+        let _guard = PriorityGuard::new_mpsc(tx.clone(), &source);
+        let mut levels = capture.subscribe();
+
+        while levels.changed().await.is_ok() {
+            let image = visualizer.render(&levels.borrow_and_update());
+
+            let message = InputMessage::new(
+                source.id(),
+                source.name().component(),
+                InputMessageData::Image {
+                    priority,
+                    duration: None,
+                    image: Arc::new(image),
+                    region: None,
+                },
+            );
+
+            if tx.send(message).await.is_err() {
+                // The instance stopped
+                break;
+            }
+        }
+    });
+
+    Ok(AudioHandle { join_handle })
+}
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use crate::{image::Image, models};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -241,12 +243,44 @@ impl Default for BlackBorder {
     }
 }
 
+/// Runtime control of a black border detector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlackBorderControl {
+    /// Forget the detected border and start detecting again
+    Reset,
+    /// Cancel a previous [BlackBorderControl::Disable]
+    Enable,
+    /// Stop detecting borders, for the given duration or until enabled again
+    Disable { duration: Option<Duration> },
+}
+
+/// Snapshot of the state of a black border detector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlackBorderState {
+    /// true if the detector is enabled in the configuration
+    pub enabled: bool,
+    /// Time left before the detector is enabled again, if it was disabled at runtime. Zero if it
+    /// was disabled indefinitely.
+    pub suspended: Option<Duration>,
+    pub mode: models::BlackBorderDetectorMode,
+    pub border: BlackBorder,
+    pub consistent_cnt: u32,
+    pub inconsistent_cnt: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Suspension {
+    Indefinite,
+    Until(Instant),
+}
+
 pub struct BlackBorderDetector {
     config: models::BlackBorderDetector,
     current_border: BlackBorder,
     previous_border: BlackBorder,
     consistent_cnt: u32,
     inconsistent_cnt: u32,
+    suspension: Option<Suspension>,
 }
 
 impl BlackBorderDetector {
@@ -257,6 +291,62 @@ impl BlackBorderDetector {
             previous_border: Default::default(),
             consistent_cnt: 0,
             inconsistent_cnt: 0,
+            suspension: None,
+        }
+    }
+
+    /// Apply a runtime control command
+    ///
+    /// # Returns
+    ///
+    /// true if a border was detected before, and is now cleared
+    pub fn control(&mut self, control: BlackBorderControl) -> bool {
+        match control {
+            BlackBorderControl::Reset => {}
+            BlackBorderControl::Enable => {
+                self.suspension = None;
+                return false;
+            }
+            BlackBorderControl::Disable { duration } => {
+                self.suspension = Some(match duration {
+                    Some(duration) => Suspension::Until(Instant::now() + duration),
+                    None => Suspension::Indefinite,
+                });
+            }
+        }
+
+        let changed = !self.current_border.unknown;
+        self.current_border = Default::default();
+        self.previous_border = Default::default();
+        self.consistent_cnt = 0;
+        self.inconsistent_cnt = 0;
+        changed
+    }
+
+    /// Time left in the current suspension, if any
+    fn suspended(&mut self) -> Option<Duration> {
+        match self.suspension? {
+            Suspension::Indefinite => Some(Duration::ZERO),
+            Suspension::Until(instant) => {
+                let now = Instant::now();
+                if instant > now {
+                    Some(instant - now)
+                } else {
+                    self.suspension = None;
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn state(&mut self) -> BlackBorderState {
+        BlackBorderState {
+            enabled: self.config.enable,
+            suspended: self.suspended(),
+            mode: self.config.mode,
+            border: self.current_border,
+            consistent_cnt: self.consistent_cnt,
+            inconsistent_cnt: self.inconsistent_cnt,
         }
     }
 
@@ -310,6 +400,11 @@ impl BlackBorderDetector {
     pub fn process(&mut self, image: &impl Image) -> bool {
         let mut image_border = BlackBorder::new(self.threshold());
 
+        if self.suspended().is_some() {
+            // Disabled at runtime: no border, without waiting for unknown_frame_cnt frames
+            return false;
+        }
+
         if !self.config.enable {
             return self.update_border(image_border);
         }
@@ -320,3 +415,78 @@ impl BlackBorderDetector {
         self.update_border(image_border)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::image::RawImage;
+
+    /// 12x12 white image with a letterbox of two black lines
+    fn letterbox() -> RawImage {
+        let data = (0..12)
+            .flat_map(|y| {
+                let level = if (2..10).contains(&y) { 255 } else { 0 };
+                std::iter::repeat_n(level, 12 * 3)
+            })
+            .collect::<Vec<u8>>();
+
+        RawImage::try_from((data, 12, 12)).unwrap()
+    }
+
+    /// Process frames until a border is detected, or give up
+    fn detect(detector: &mut BlackBorderDetector, image: &RawImage) -> bool {
+        (0..20).any(|_| detector.process(image))
+    }
+
+    #[test]
+    fn test_control() {
+        let image = letterbox();
+        let mut detector = BlackBorderDetector::new(Default::default());
+
+        assert!(detect(&mut detector, &image));
+        assert_eq!(detector.current_border().horizontal_size, 3);
+
+        // Reset forgets the border, and detection starts over
+        assert!(detector.control(BlackBorderControl::Reset));
+        let state = detector.state();
+        assert!(state.border.unknown);
+        assert_eq!(state.suspended, None);
+        assert_eq!((state.consistent_cnt, state.inconsistent_cnt), (0, 0));
+        assert!(detect(&mut detector, &image));
+
+        // Disabling clears the border until enabled again
+        assert!(detector.control(BlackBorderControl::Disable { duration: None }));
+        assert_eq!(detector.state().suspended, Some(Duration::ZERO));
+        assert!(!detect(&mut detector, &image));
+        assert!(detector.current_border().unknown);
+
+        assert!(!detector.control(BlackBorderControl::Enable));
+        assert_eq!(detector.state().suspended, None);
+        assert!(detect(&mut detector, &image));
+    }
+
+    #[test]
+    fn test_disable_duration() {
+        let image = letterbox();
+        let mut detector = BlackBorderDetector::new(Default::default());
+        assert!(detect(&mut detector, &image));
+
+        let duration = Duration::from_millis(50);
+        assert!(detector.control(BlackBorderControl::Disable {
+            duration: Some(duration)
+        }));
+        assert!(detector
+            .state()
+            .suspended
+            .is_some_and(|left| left > Duration::ZERO && left <= duration));
+        assert!(!detect(&mut detector, &image));
+
+        // Detection resumes once the duration elapsed
+        std::thread::sleep(duration);
+        assert_eq!(detector.state().suspended, None);
+        assert!(detect(&mut detector, &image));
+        assert!(!detector.current_border().unknown);
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::{Duration, Instant};
+
+use crate::models::{self, BootSplashAnimation, Color};
+
+/// Interval between two frames of the animation
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Startup animation of an instance
+pub struct BootSplash {
+    config: models::BootSplash,
+    led_data: Vec<Color>,
+    started: Instant,
+    next_frame: Instant,
+}
+
+impl BootSplash {
+    pub fn new(config: models::BootSplash, led_count: usize) -> Self {
+        let now = Instant::now();
+
+        Self {
+            config,
+            led_data: vec![Default::default(); led_count],
+            started: now,
+            next_frame: now,
+        }
+    }
+
+    fn render(&mut self, t: f32) {
+        let (r, g, b) = self.config.color.into_components();
+        let scaled = |factor: f32| {
+            Color::new(
+                (r as f32 * factor) as u8,
+                (g as f32 * factor) as u8,
+                (b as f32 * factor) as u8,
+            )
+        };
+
+        match self.config.animation {
+            BootSplashAnimation::Wipe => {
+                if t < 0.5 {
+                    let led_count = self.led_data.len();
+                    let lit = (led_count as f32 * t * 2.).ceil() as usize;
+                    let (on, off) = self.led_data.split_at_mut(lit.min(led_count));
+                    on.fill(self.config.color);
+                    off.fill(Color::default());
+                } else {
+                    self.led_data.fill(scaled(2. - t * 2.));
+                }
+            }
+            BootSplashAnimation::Pulse => {
+                self.led_data
+                    .fill(scaled((t * std::f32::consts::PI).sin().max(0.)));
+            }
+        }
+    }
+
+    /// Wait for the next frame of the animation
+    ///
+    /// # Returns
+    ///
+    /// The LED data for this frame, or None if the animation is over. The last frame is always
+    /// black.
+    pub async fn update(&mut self) -> Option<&[Color]> {
+        tokio::time::sleep_until(self.next_frame.into()).await;
+
+        let now = Instant::now();
+        let duration = Duration::from_millis(self.config.duration_ms as _);
+        let elapsed = now.duration_since(self.started);
+
+        if elapsed > duration + FRAME_INTERVAL {
+            return None;
+        }
+
+        if elapsed >= duration {
+            self.led_data.fill(Color::default());
+        } else {
+            self.render(elapsed.as_secs_f32() / duration.as_secs_f32());
+        }
+
+        self.next_frame = now + FRAME_INTERVAL;
+        Some(&self.led_data)
+    }
+}
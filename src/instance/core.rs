@@ -1,7 +1,9 @@
+use std::sync::Arc;
+
 use crate::{
     color::{color_to16, ChannelAdjustments, ChannelAdjustmentsBuilder},
-    image::{prelude::*, Reducer},
-    models::{Color, Color16, InstanceConfig, Leds},
+    image::{prelude::*, ImageRegion, RawImage, Reducer},
+    models::{Color, Color16, ColorAdjustment, InstanceConfig, Leds},
 };
 
 use super::{BlackBorderDetector, MuxedMessage, MuxedMessageData, Smoothing, SmoothingUpdate};
@@ -11,12 +13,23 @@ use super::{BlackBorderDetector, MuxedMessage, MuxedMessageData, Smoothing, Smoo
 /// This handles incoming message and computes LED colors.
 pub struct Core {
     leds: Leds,
+    /// Color data from the current input, before channel adjustments
     color_data: Vec<Color16>,
     black_border_detector: BlackBorderDetector,
     channel_adjustments: ChannelAdjustments,
+    /// Color data after applying the channel adjustments
+    adjusted_data: Vec<Color16>,
     smoothing: Smoothing,
     notified_inconsistent_led_data: bool,
     reducer: Reducer,
+    /// Priority of the last image processed, if the last message was an image
+    last_image_priority: Option<i32>,
+    /// Last image processed, if the last message was an image
+    last_image: Option<Arc<RawImage>>,
+    /// Maximum brightness of the output, between 0 and 1
+    brightness_cap: f32,
+    /// Color data after applying the brightness cap
+    capped_data: Vec<Color16>,
 }
 
 impl Core {
@@ -33,9 +46,14 @@ impl Core {
             color_data: vec![Color16::default(); led_count],
             black_border_detector,
             channel_adjustments,
+            adjusted_data: vec![Color16::default(); led_count],
             smoothing,
             notified_inconsistent_led_data: false,
             reducer: Default::default(),
+            last_image_priority: None,
+            last_image: None,
+            brightness_cap: 1.,
+            capped_data: vec![Color16::default(); led_count],
         }
     }
 
@@ -43,20 +61,28 @@ impl Core {
         self.color_data.fill(color_to16(color));
     }
 
-    fn handle_image(&mut self, image: &impl Image) {
+    fn handle_image(&mut self, image: &impl Image, region: Option<ImageRegion>) {
         // Update the black border
-        self.black_border_detector.process(image);
+        let border_changed = self.black_border_detector.process(image);
         let black_border = self.black_border_detector.current_border();
 
         // Crop the image using a view
-        let image = {
-            let (x, y) = black_border.get_ranges(image.width(), image.height());
-            image.wrap(x, y)
-        };
+        let (x, y) = black_border.get_ranges(image.width(), image.height());
+        let region = region.map(|region| region.view(&x, &y));
+        let image = image.wrap(x, y);
 
         // Update the 16-bit color data from the LED ranges and the image
-        self.reducer
-            .reduce(&image, &self.leds.leds[..], &mut self.color_data);
+        match region {
+            Some(region) if !border_changed => self.reducer.reduce_region(
+                &image,
+                &self.leds.leds[..],
+                &mut self.color_data,
+                region,
+            ),
+            _ => self
+                .reducer
+                .reduce(&image, &self.leds.leds[..], &mut self.color_data),
+        }
     }
 
     fn handle_led_colors(&mut self, led_colors: &[Color]) {
@@ -94,27 +120,206 @@ impl Core {
     }
 
     pub fn handle_message(&mut self, message: MuxedMessage) {
+        self.smoothing.set_override(message.smoothing());
+
         // Update color data
         match message.data() {
             MuxedMessageData::SolidColor { color, .. } => {
+                self.last_image_priority = None;
+                self.last_image = None;
                 self.handle_color(*color);
             }
-            MuxedMessageData::Image { image, .. } => {
-                self.handle_image(image.as_ref());
+            MuxedMessageData::Image {
+                priority,
+                image,
+                region,
+                ..
+            } => {
+                // The region is relative to the previous image on the same priority, so it can
+                // only be used if that's the last image we processed
+                let region = region.filter(|_| self.last_image_priority == Some(*priority));
+                self.last_image_priority = Some(*priority);
+                self.last_image = Some(image.clone());
+                self.handle_image(image.as_ref(), region);
             }
             MuxedMessageData::LedColors { led_colors, .. } => {
+                self.last_image_priority = None;
+                self.last_image = None;
                 self.handle_led_colors(led_colors);
             }
         }
 
-        // In-place transform colors
-        self.channel_adjustments.apply(&mut self.color_data);
+        self.update_adjusted();
+    }
+
+    /// Process the next image whole, since the image its region is relative to was dropped
+    /// before reaching the core
+    pub fn invalidate_region(&mut self) {
+        self.last_image_priority = None;
+    }
+
+    fn update_adjusted(&mut self) {
+        self.adjusted_data.copy_from_slice(&self.color_data);
+        self.channel_adjustments.apply(&mut self.adjusted_data);
+
+        self.update_target();
+    }
+
+    /// Replace the channel adjustments, and apply them to the current output
+    pub fn set_color_adjustment(&mut self, config: &ColorAdjustment) {
+        self.channel_adjustments = ChannelAdjustmentsBuilder::new(config)
+            .led_count(self.leds.leds.len() as _)
+            .build();
+
+        self.update_adjusted();
+    }
+
+    pub fn black_border_detector(&mut self) -> &mut BlackBorderDetector {
+        &mut self.black_border_detector
+    }
+
+    pub fn set_smoothing_enabled(&mut self, enabled: bool) {
+        self.smoothing.set_enabled(enabled);
+    }
+
+    /// Set the maximum brightness of the output, between 0 and 1
+    pub fn set_brightness_cap(&mut self, cap: f32) {
+        if cap != self.brightness_cap {
+            self.brightness_cap = cap;
+            self.update_target();
+        }
+    }
+
+    fn update_target(&mut self) {
+        if self.brightness_cap < 1. {
+            for (capped, color) in self.capped_data.iter_mut().zip(&self.adjusted_data) {
+                let (r, g, b) = color.into_components();
+                *capped = Color16::new(
+                    (r as f32 * self.brightness_cap) as u16,
+                    (g as f32 * self.brightness_cap) as u16,
+                    (b as f32 * self.brightness_cap) as u16,
+                );
+            }
+
+            self.smoothing.set_target(&self.capped_data);
+        } else {
+            self.smoothing.set_target(&self.adjusted_data);
+        }
+    }
+
+    /// Last image processed, if the visible input is an image
+    pub fn current_image(&self) -> Option<&Arc<RawImage>> {
+        self.last_image.as_ref()
+    }
+
+    /// LED data from the last update
+    pub fn current(&self) -> &[Color] {
+        self.smoothing.current()
+    }
 
-        // Update the smoothing state with the new color data
-        self.smoothing.set_target(&self.color_data);
+    /// Smoothed LED data from the last update, in 16 bits
+    pub fn current16(&self) -> &[Color16] {
+        self.smoothing.current16()
     }
 
     pub async fn update(&mut self) -> (&[Color], SmoothingUpdate) {
         self.smoothing.update().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::models::Led;
+
+    /// Images sent by effects are mapped like captured frames, black border removal included
+    #[tokio::test]
+    async fn test_effect_image_black_border() {
+        let mut config = InstanceConfig::new_dummy(0);
+        config.leds = Leds {
+            leds: vec![Led {
+                hmin: 0.,
+                hmax: 1.,
+                vmin: 0.,
+                vmax: 0.1,
+                color_order: None,
+                name: None,
+            }],
+        };
+        config.smoothing.enable = false;
+        let mut core = Core::new(&config).await;
+
+        // Red image with black bars at the top and bottom
+        let mut data = vec![0; 16 * 16 * 3];
+        for pixel in data[4 * 16 * 3..12 * 16 * 3].chunks_exact_mut(3) {
+            pixel[0] = 255;
+        }
+        let image = Arc::new(RawImage::try_from((data, 16, 16)).unwrap());
+
+        let mut top = Vec::new();
+        for _ in 0..=config.black_border_detector.border_frame_cnt {
+            core.handle_message(MuxedMessage::new(MuxedMessageData::Image {
+                priority: 64,
+                duration: None,
+                image: image.clone(),
+                region: None,
+            }));
+            top.push(core.update().await.0[0]);
+        }
+
+        assert_eq!(top.first(), Some(&Color::new(0, 0, 0)));
+        assert_eq!(top.last(), Some(&Color::new(255, 0, 0)));
+    }
+
+    /// A partial update following an image the core never saw must update all the LEDs
+    #[tokio::test]
+    async fn test_invalidate_region() {
+        let led = |hmin, hmax| Led {
+            hmin,
+            hmax,
+            vmin: 0.,
+            vmax: 1.,
+            color_order: None,
+            name: None,
+        };
+
+        let mut config = InstanceConfig::new_dummy(0);
+        config.leds = Leds {
+            leds: vec![led(0., 0.5), led(0.5, 1.)],
+        };
+        config.smoothing.enable = false;
+        let mut core = Core::new(&config).await;
+
+        let image = |left: [u8; 3], right: [u8; 3], region| {
+            let mut data = Vec::new();
+            for x in 0..16 {
+                data.extend_from_slice(if x % 4 < 2 { &left } else { &right });
+            }
+
+            MuxedMessage::new(MuxedMessageData::Image {
+                priority: 64,
+                duration: None,
+                image: Arc::new(RawImage::try_from((data, 4, 4)).unwrap()),
+                region,
+            })
+        };
+        let (red, green, blue) = ([255, 0, 0], [0, 255, 0], [0, 0, 255]);
+
+        core.handle_message(image(red, red, None));
+        assert_eq!(core.update().await.0[1], Color::new(255, 0, 0));
+
+        // A green image is dropped, then only the left half of the next one changes
+        core.invalidate_region();
+        core.handle_message(image(
+            blue,
+            green,
+            Some(ImageRegion::clipped(0, 0, 2, 4, (4, 4))),
+        ));
+
+        let current = core.update().await.0;
+        assert_eq!(current[0], Color::new(0, 0, 255));
+        assert_eq!(current[1], Color::new(0, 255, 0));
+    }
+}
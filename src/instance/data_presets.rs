@@ -0,0 +1,235 @@
+//! Colors and effects driven by a JSON document
+
+use std::{sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{
+    sync::{mpsc, oneshot, Mutex},
+    task::JoinHandle,
+};
+
+use crate::{
+    api::json::message::EffectRequest,
+    global::{
+        Global, InputMessage, InputMessageData, InputSourceError, InputSourceHandle,
+        InputSourceName, Message, PriorityGuard,
+    },
+    models::{self, DataPresetRule, EffectType},
+};
+
+#[derive(Debug, Error)]
+pub enum DataPresetsError {
+    #[error("http error: {0}")]
+    Http(#[from] ureq::Error),
+    #[error("invalid document: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    InputSource(#[from] InputSourceError),
+}
+
+/// Maximum size of the fetched document
+const MAX_DOCUMENT_SIZE: u64 = 1024 * 1024;
+
+/// Time allowed for fetching the document, so an unresponsive server doesn't stall the polling
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handle to the data presets task, which stops it when dropped
+pub struct DataPresetsHandle {
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for DataPresetsHandle {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+/// Start polling the configured URL
+///
+/// The output of the matching rule is sent to `tx` at the configured priority, until the returned
+/// handle is dropped.
+pub async fn start(
+    config: models::DataPresets,
+    global: Global,
+    tx: mpsc::Sender<InputMessage>,
+) -> Result<DataPresetsHandle, DataPresetsError> {
+    let source = global
+        .register_input_source(
+            InputSourceName::DataPresets {
+                url: config.url.clone(),
+            },
+            Some(config.priority),
+        )
+        .await?;
+
+    let join_handle = tokio::spawn(
+        DataPresets {
+            _guard: PriorityGuard::new_mpsc(tx.clone(), &source),
+            config,
+            source,
+            tx,
+            current: None,
+        }
+        .run(),
+    );
+
+    Ok(DataPresetsHandle { join_handle })
+}
+
+struct DataPresets {
+    // Clears our priority when the task stops, so it must be dropped before source
+    _guard: PriorityGuard,
+    config: models::DataPresets,
+    source: InputSourceHandle<InputMessage>,
+    tx: mpsc::Sender<InputMessage>,
+    /// Index of the rule currently applied
+    current: Option<usize>,
+}
+
+impl DataPresets {
+    async fn run(mut self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.poll_interval_secs as _));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            // Keep the current output if the document can't be fetched
+            let document = match fetch(&self.config.url).await {
+                Ok(document) => document,
+                Err(error) => {
+                    warn!(url = %self.config.url, error = %error, "cannot fetch data presets document");
+                    continue;
+                }
+            };
+
+            let rule = matching_rule(&self.config.rules, &document);
+            if rule == self.current {
+                continue;
+            }
+
+            debug!(url = %self.config.url, rule = ?rule, "data preset changed");
+            self.current = rule;
+
+            let data = match rule.map(|index| &self.config.rules[index]) {
+                Some(rule) => self.rule_data(rule),
+                None => InputMessageData::Clear {
+                    priority: self.config.priority,
+                },
+            };
+
+            if self.send(data).await.is_err() {
+                // The instance stopped
+                break;
+            }
+        }
+    }
+
+    fn rule_data(&self, rule: &DataPresetRule) -> InputMessageData {
+        match rule.ty {
+            EffectType::Color => InputMessageData::SolidColor {
+                priority: self.config.priority,
+                duration: None,
+                color: rule.color,
+            },
+            EffectType::Effect => {
+                let (tx, rx) = oneshot::channel();
+
+                tokio::spawn({
+                    let name = rule.effect.clone();
+
+                    async move {
+                        if let Ok(Err(error)) = rx.await {
+                            warn!(effect = %name, error = %error, "cannot start data preset effect");
+                        }
+                    }
+                });
+
+                InputMessageData::Effect {
+                    priority: self.config.priority,
+                    duration: None,
+                    effect: Arc::new(EffectRequest {
+                        name: rule.effect.clone(),
+                        args: Default::default(),
+                    }),
+                    response: Arc::new(Mutex::new(Some(tx))),
+                }
+            }
+        }
+    }
+
+    async fn send(&self, data: InputMessageData) -> Result<(), ()> {
+        self.tx
+            .send(InputMessage::new(
+                self.source.id(),
+                self.source.name().component(),
+                data,
+            ))
+            .await
+            .map_err(|_| ())
+    }
+}
+
+async fn fetch(url: &str) -> Result<serde_json::Value, DataPresetsError> {
+    let url = url.to_owned();
+    let data = tokio::task::spawn_blocking(move || {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(FETCH_TIMEOUT))
+            .build()
+            .into();
+
+        agent
+            .get(&url)
+            .call()?
+            .body_mut()
+            .with_config()
+            .limit(MAX_DOCUMENT_SIZE)
+            .read_to_vec()
+    })
+    .await
+    .expect("data presets request panicked")?;
+
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Find the first rule matching the given document
+fn matching_rule(rules: &[DataPresetRule], document: &serde_json::Value) -> Option<usize> {
+    rules.iter().position(|rule| {
+        let value = match document.pointer(&rule.pointer) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        rule.equals.as_ref().is_none_or(|equals| value == equals)
+            && rule
+                .above
+                .is_none_or(|above| value.as_f64().is_some_and(|v| v > above))
+            && rule
+                .below
+                .is_none_or(|below| value.as_f64().is_some_and(|v| v < below))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_rule() {
+        let rules: Vec<DataPresetRule> = serde_json::from_value(serde_json::json!([
+            { "pointer": "/build/status", "equals": "failed" },
+            { "pointer": "/main/temp", "below": 0.0 },
+            { "pointer": "/main/temp", "above": 25.0, "below": 40.0 },
+        ]))
+        .unwrap();
+
+        let document = |status: &str, temp: f64| serde_json::json!({ "build": { "status": status }, "main": { "temp": temp } });
+
+        assert_eq!(matching_rule(&rules, &document("failed", 30.)), Some(0));
+        assert_eq!(matching_rule(&rules, &document("passed", -5.)), Some(1));
+        assert_eq!(matching_rule(&rules, &document("passed", 30.)), Some(2));
+        assert_eq!(matching_rule(&rules, &document("passed", 45.)), None);
+        assert_eq!(matching_rule(&rules, &serde_json::json!({})), None);
+    }
+}
@@ -1,15 +1,50 @@
 use async_trait::async_trait;
 use thiserror::Error;
+use tokio::select;
 
 use crate::models::{self, DeviceConfig};
 
 mod common;
+mod resolver;
+
+mod delay;
+use delay::FrameDelay;
+
+mod limiter;
+use limiter::RateLimiter;
+
+mod lut;
+use lut::Lut;
+
+mod dither;
+pub use dither::Ditherer;
+
+mod spi;
+
+mod segments;
+use segments::SegmentMapper;
+
+mod voltage_drop;
+use voltage_drop::VoltageDropCompensator;
+
+mod white;
+use white::{WhiteConverter, WhiteLevels};
 
 // Device implementation modules
 
+mod apa102;
+mod atmoorb;
+mod boblight;
+mod ddp;
 mod dummy;
+mod e131;
 mod file;
+mod pipe;
+mod serial;
+mod wled;
 mod ws2812spi;
+#[cfg(feature = "rpi-ws281x")]
+mod ws281x;
 
 #[derive(Debug, Error)]
 pub enum DeviceError {
@@ -19,6 +54,15 @@ pub enum DeviceError {
     FuturesIo(#[from] futures_io::Error),
     #[error("Format error: {0}")]
     FormatError(#[from] std::fmt::Error),
+    #[error("invalid device reply: {0}")]
+    InvalidReply(String),
+    #[error("invalid LUT: {0}")]
+    InvalidLut(String),
+    #[error(transparent)]
+    Spi(#[from] spi::SpiError),
+    #[cfg(feature = "rpi-ws281x")]
+    #[error(transparent)]
+    Ws281x(#[from] ws281x::Ws281xError),
 }
 
 #[async_trait]
@@ -31,17 +75,99 @@ trait DeviceImpl: Send {
     /// wrapper is responsible for ensuring the given slice is the right size.
     async fn set_led_data(&mut self, led_data: &[models::Color]) -> Result<(), DeviceError>;
 
+    /// Set the levels of the white channels of the LEDs, before the next call to
+    /// [DeviceImpl::set_led_data]
+    ///
+    /// Only called for devices configured with white channels.
+    fn set_white_data(&mut self, white_data: &[WhiteLevels]);
+
     /// Update the device implementation's temporal data. For devices that require regular rewrites
     /// (regardless of actual changes in the LED data), this should return a future that performs
     /// the required work.
     async fn update(&mut self) -> Result<(), DeviceError>;
+
+    /// Query the device for its current LED count
+    ///
+    /// Returns `None` if the device can't report its LED count. If a count is returned, the
+    /// implementation must accept LED data of that size from then on.
+    async fn query_led_count(&mut self) -> Result<Option<usize>, DeviceError> {
+        Ok(None)
+    }
+
+    /// Statistics of the frames written to the device so far
+    fn stats(&self) -> DeviceStats;
+}
+
+/// Statistics of the frames written to a device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceStats {
+    /// Number of frames written
+    pub frames: u64,
+    /// Number of frames dropped in favor of newer ones, to limit the rate or latency of writes
+    pub skipped: u64,
+    /// Number of bytes written
+    pub bytes: u64,
+    /// Frames written per second, over the last second
+    pub fps: f64,
+    /// Error of the last write, if it failed
+    pub last_error: Option<String>,
+}
+
+/// Connection state of the device of an instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceStatus {
+    /// The device is still being initialized
+    Starting,
+    /// The last write to the device succeeded
+    Connected,
+    /// The last write to the device failed, it will be retried on the next one
+    Disconnected,
+    /// The device failed and was disabled
+    Failed,
+    /// The instance runs without writing to the device
+    DryRun,
+}
+
+/// Snapshot of the state of the device of an instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub status: DeviceStatus,
+    pub stats: DeviceStats,
+}
+
+/// A change in the number of LEDs reported by a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedCountChange {
+    pub previous: usize,
+    pub current: usize,
 }
 
 pub struct Device {
     name: String,
+    /// Type of the device, as in its configuration
+    kind: &'static str,
     inner: Box<dyn DeviceImpl>,
     led_data: Vec<models::Color>,
     notified_inconsistent_led_data: bool,
+    segments: Option<SegmentMapper>,
+    segment_data: Vec<models::Color>,
+    keep_alive: Option<models::KeepAlive>,
+    voltage_drop: Option<Box<VoltageDropCompensator>>,
+    lut: Option<Box<Lut>>,
+    white: Option<WhiteConverter>,
+    white_data: Vec<WhiteLevels>,
+    led_count_refresh: Option<tokio::time::Interval>,
+    /// Frames held back by the output delay of the device, if any
+    delay: Option<Box<FrameDelay>>,
+    limiter: Option<Box<RateLimiter>>,
+}
+
+/// Event handled by [Device::update]
+enum UpdateEvent {
+    Updated,
+    RefreshLedCount,
+    DelayedFrame,
+    LimitedFrame,
 }
 
 impl Device {
@@ -51,29 +177,149 @@ impl Device {
             models::Device::Ws2812Spi(ws2812spi) => {
                 Box::new(ws2812spi::Ws2812SpiDevice::new(ws2812spi)?)
             }
+            models::Device::Sk6812Spi(sk6812spi) => {
+                Box::new(ws2812spi::Ws2812SpiDevice::new(sk6812spi.into())?)
+            }
+            models::Device::Apa102(apa102) => Box::new(apa102::Apa102Device::new(apa102)?),
+            #[cfg(feature = "rpi-ws281x")]
+            models::Device::Ws281x(ws281x) => Box::new(ws281x::Ws281xDevice::new(ws281x)?),
             models::Device::File(file) => Box::new(file::FileDevice::new(file)?),
+            models::Device::Pipe(pipe) => Box::new(pipe::PipeDevice::new(pipe)?),
+            models::Device::Wled(wled) => Box::new(wled::WledDevice::new(wled)?),
+            models::Device::E131(e131) => Box::new(e131::E131Device::new(e131)?),
+            models::Device::Adalight(adalight) => Box::new(serial::AdalightDevice::new(adalight)?),
+            models::Device::Boblight(boblight) => {
+                Box::new(boblight::BoblightDevice::new(boblight)?)
+            }
+            models::Device::AtmoOrb(atmoorb) => Box::new(atmoorb::AtmoOrbDevice::new(atmoorb)?),
+            models::Device::Ddp(ddp) => Box::new(ddp::DdpDevice::new(ddp)?),
             other => {
                 return Err(DeviceError::NotSupported(other.into()));
             }
         })
     }
 
-    #[instrument(skip(config))]
-    pub async fn new(name: &str, config: models::Device) -> Result<Self, DeviceError> {
+    /// Create a new device
+    ///
+    /// `layout_led_count` is the number of LEDs in the instance layout, which is used to resolve
+    /// the device segments if any.
+    #[instrument(skip(config), fields(kind = <&'static str>::from(&config)))]
+    pub async fn new(
+        name: &str,
+        config: models::Device,
+        layout_led_count: usize,
+    ) -> Result<Self, DeviceError> {
         let led_count = config.hardware_led_count();
+        let segments = if config.segments().is_empty() {
+            None
+        } else {
+            Some(SegmentMapper::new(config.segments(), layout_led_count))
+        };
+        let led_count_refresh = config.led_count_refresh_time().map(|period| {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        });
+        let keep_alive = config.keep_alive().cloned();
+        let voltage_drop = config
+            .voltage_drop()
+            .cloned()
+            .map(|config| Box::new(VoltageDropCompensator::new(config)));
+        let lut = match config.lut() {
+            Some(lut) => Some(Box::new(Lut::new(lut).await?)),
+            None => None,
+        };
+        let white = config.white_output().map(WhiteConverter::new);
+        let output_delay = config.output_delay();
+        let delay = if output_delay.is_zero() {
+            None
+        } else {
+            Some(Box::new(FrameDelay::new(output_delay)))
+        };
+        let limiter = config
+            .max_fps()
+            .map(|max_fps| Box::new(RateLimiter::new(max_fps)));
+        let kind = (&config).into();
         let inner = Self::build_inner(config)?;
 
         Ok(Self {
             name: name.to_owned(),
+            kind,
             inner,
             led_data: vec![Default::default(); led_count],
             notified_inconsistent_led_data: false,
+            segment_data: Vec::with_capacity(segments.as_ref().map_or(0, |s| s.segment_count())),
+            segments,
+            keep_alive,
+            voltage_drop,
+            lut,
+            white,
+            white_data: Vec::new(),
+            led_count_refresh,
+            delay,
+            limiter,
         })
     }
 
     #[instrument(skip(led_data))]
     pub async fn set_led_data(&mut self, led_data: &[models::Color]) -> Result<(), DeviceError> {
-        // Store the LED data for updates
+        if let Some(delay) = &mut self.delay {
+            // Written by update once the delay elapses
+            delay.push(led_data, tokio::time::Instant::now());
+            return Ok(());
+        }
+
+        self.write_limited(led_data).await
+    }
+
+    /// Write the LED data unless the device was written to too recently, in which case it is
+    /// held until the next write
+    async fn write_limited(&mut self, led_data: &[models::Color]) -> Result<(), DeviceError> {
+        if let Some(limiter) = &mut self.limiter {
+            if !limiter.try_write(tokio::time::Instant::now()) {
+                limiter.hold(led_data);
+                return Ok(());
+            }
+        }
+
+        self.write_led_data(led_data).await
+    }
+
+    async fn write_led_data(&mut self, led_data: &[models::Color]) -> Result<(), DeviceError> {
+        if let Some(segments) = &self.segments {
+            // Aggregate the layout LEDs into device segments
+            let mut segment_data = std::mem::take(&mut self.segment_data);
+            segments.map(led_data, &mut segment_data);
+            self.store_led_data(&segment_data);
+            self.segment_data = segment_data;
+        } else {
+            self.store_led_data(led_data);
+        }
+
+        if let Some(lut) = &self.lut {
+            lut.apply(&mut self.led_data);
+        }
+
+        if let Some(voltage_drop) = &mut self.voltage_drop {
+            voltage_drop.apply(&mut self.led_data);
+        }
+
+        if let Some(keep_alive) = &self.keep_alive {
+            keep_alive_led(&mut self.led_data, keep_alive);
+        }
+
+        if let Some(white) = &self.white {
+            white.apply(&mut self.led_data, &mut self.white_data);
+            self.inner.set_white_data(&self.white_data);
+        }
+
+        // Notify device of new write: some devices write immediately
+        self.inner.set_led_data(&self.led_data).await
+    }
+
+    /// Store the LED data for updates, fitting it to the hardware LED count
+    fn store_led_data(&mut self, led_data: &[models::Color]) {
         let led_count = led_data.len();
         let hw_led_count = self.led_data.len();
 
@@ -107,19 +353,258 @@ impl Device {
                 );
             }
         }
-
-        // Notify device of new write: some devices write immediately
-        self.inner.set_led_data(&self.led_data).await
     }
 
+    /// Update the device
+    ///
+    /// # Returns
+    ///
+    /// The change in the device LED count, if it was re-queried and found to be different. The
+    /// stored LED data is already resized when this returns, so the caller only needs to send the
+    /// current LED data again.
     #[instrument]
-    pub async fn update(&mut self) -> Result<(), DeviceError> {
-        self.inner.update().await
+    pub async fn update(&mut self) -> Result<Option<LedCountChange>, DeviceError> {
+        let event = select! {
+            result = self.inner.update() => {
+                result?;
+                UpdateEvent::Updated
+            }
+            _ = next_tick(&mut self.led_count_refresh) => UpdateEvent::RefreshLedCount,
+            _ = next_due(&self.delay) => UpdateEvent::DelayedFrame,
+            _ = next_allowed(&self.limiter) => UpdateEvent::LimitedFrame,
+        };
+
+        match event {
+            UpdateEvent::Updated => Ok(None),
+            UpdateEvent::RefreshLedCount => self.refresh_led_count().await,
+            UpdateEvent::DelayedFrame => {
+                self.write_delayed_frame().await?;
+                Ok(None)
+            }
+            UpdateEvent::LimitedFrame => {
+                self.write_limited_frame().await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Write the latest frame whose output delay elapsed
+    async fn write_delayed_frame(&mut self) -> Result<(), DeviceError> {
+        let start = tokio::time::Instant::now();
+        let frame = match self.delay.as_mut().and_then(|delay| delay.pop(start)) {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let result = self.write_limited(&frame).await;
+
+        // unwrap: frames are only queued when there is a delay
+        let delay = self.delay.as_mut().unwrap();
+        delay.record_latency(start.elapsed());
+        delay.recycle(frame);
+
+        result
+    }
+
+    /// Write the frame held by the rate limiter
+    async fn write_limited_frame(&mut self) -> Result<(), DeviceError> {
+        let now = tokio::time::Instant::now();
+        let frame = match self.limiter.as_mut().and_then(|limiter| limiter.take(now)) {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let result = self.write_led_data(&frame).await;
+
+        // unwrap: frames are only held when there is a limiter
+        self.limiter.as_mut().unwrap().recycle(frame);
+
+        result
+    }
+
+    pub fn stats(&self) -> DeviceStats {
+        let mut stats = self.inner.stats();
+        stats.skipped += self.delay.as_ref().map_or(0, |delay| delay.skipped())
+            + self.limiter.as_ref().map_or(0, |limiter| limiter.skipped());
+        stats
+    }
+
+    async fn refresh_led_count(&mut self) -> Result<Option<LedCountChange>, DeviceError> {
+        let current = match self.inner.query_led_count().await? {
+            Some(current) => current,
+            None => return Ok(None),
+        };
+
+        let previous = self.led_data.len();
+        if current == previous {
+            return Ok(None);
+        }
+
+        warn!(
+            previous = %previous,
+            current = %current,
+            "device LED count changed"
+        );
+
+        // Padding or truncating is handled by store_led_data on the next write
+        self.led_data.resize(current, Default::default());
+        self.notified_inconsistent_led_data = false;
+
+        Ok(Some(LedCountChange { previous, current }))
+    }
+}
+
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+async fn next_due(delay: &Option<Box<FrameDelay>>) {
+    match delay {
+        Some(delay) => delay.due().await,
+        None => futures::future::pending().await,
+    }
+}
+
+async fn next_allowed(limiter: &Option<Box<RateLimiter>>) {
+    match limiter {
+        Some(limiter) => limiter.due().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Light the keep-alive LED if all the LEDs are black
+///
+/// LEDs which are only dimmed are left as is, so fading out to black doesn't make the keep-alive
+/// LED blink.
+fn keep_alive_led(led_data: &mut [models::Color], keep_alive: &models::KeepAlive) {
+    if led_data
+        .iter()
+        .all(|color| *color == models::Color::new(0, 0, 0))
+    {
+        if let Some(color) = led_data.get_mut(keep_alive.led as usize) {
+            let level = keep_alive.level;
+            *color = models::Color::new(level, level, level);
+        }
     }
 }
 
 impl std::fmt::Debug for Device {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Device").field("name", &self.name).finish()
+        f.debug_struct("Device")
+            .field("name", &self.name)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Device whose reported LED count can be changed, recording the data written to it
+    #[derive(Default, Clone)]
+    struct ResizableDevice {
+        led_count: Arc<Mutex<Option<usize>>>,
+        written: Arc<Mutex<Vec<models::Color>>>,
+    }
+
+    #[async_trait]
+    impl DeviceImpl for ResizableDevice {
+        async fn set_led_data(&mut self, led_data: &[models::Color]) -> Result<(), DeviceError> {
+            *self.written.lock().unwrap() = led_data.to_vec();
+            Ok(())
+        }
+
+        fn set_white_data(&mut self, _white_data: &[WhiteLevels]) {}
+
+        async fn update(&mut self) -> Result<(), DeviceError> {
+            futures::future::pending().await
+        }
+
+        async fn query_led_count(&mut self) -> Result<Option<usize>, DeviceError> {
+            Ok(*self.led_count.lock().unwrap())
+        }
+
+        fn stats(&self) -> DeviceStats {
+            DeviceStats::default()
+        }
+    }
+
+    async fn test_device(inner: ResizableDevice, led_count: usize) -> Device {
+        let config = models::Device::Dummy(models::Dummy {
+            hardware_led_count: led_count as _,
+            ..Default::default()
+        });
+
+        Device {
+            inner: Box::new(inner),
+            led_count_refresh: Some(tokio::time::interval(std::time::Duration::from_millis(1))),
+            ..Device::new("test", config, led_count).await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_led_count() {
+        let inner = ResizableDevice::default();
+        let mut device = test_device(inner.clone(), 2).await;
+        let (red, green) = (models::Color::new(255, 0, 0), models::Color::new(0, 255, 0));
+
+        // Devices which don't report a count, or report the same one, are left as is
+        assert_eq!(device.update().await.unwrap(), None);
+        *inner.led_count.lock().unwrap() = Some(2);
+        assert_eq!(device.update().await.unwrap(), None);
+
+        // Growing devices get the data padded with black after the resize
+        *inner.led_count.lock().unwrap() = Some(3);
+        assert_eq!(
+            device.update().await.unwrap(),
+            Some(LedCountChange {
+                previous: 2,
+                current: 3
+            })
+        );
+        device.set_led_data(&[red, green]).await.unwrap();
+        assert_eq!(
+            *inner.written.lock().unwrap(),
+            [red, green, models::Color::default()]
+        );
+
+        // Shrinking devices get the data truncated
+        *inner.led_count.lock().unwrap() = Some(1);
+        assert_eq!(
+            device.refresh_led_count().await.unwrap(),
+            Some(LedCountChange {
+                previous: 3,
+                current: 1
+            })
+        );
+        device.set_led_data(&[red, green]).await.unwrap();
+        assert_eq!(*inner.written.lock().unwrap(), [red]);
+    }
+
+    #[test]
+    fn test_keep_alive_led() {
+        let keep_alive = models::KeepAlive { led: 1, level: 2 };
+        let black = models::Color::new(0, 0, 0);
+
+        let mut led_data = vec![black; 3];
+        keep_alive_led(&mut led_data, &keep_alive);
+        assert_eq!(led_data, [black, models::Color::new(2, 2, 2), black]);
+
+        let mut led_data = vec![black, black, models::Color::new(0, 0, 1)];
+        keep_alive_led(&mut led_data, &keep_alive);
+        assert_eq!(led_data, [black, black, models::Color::new(0, 0, 1)]);
+
+        // Out of range LEDs are ignored
+        let mut led_data = vec![black];
+        keep_alive_led(&mut led_data, &keep_alive);
+        assert_eq!(led_data, [black]);
     }
 }
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+
+use super::{common::*, spi::SpiOutput, DeviceError};
+use crate::models;
+
+pub type Apa102Device = Rewriter<Apa102Impl>;
+
+pub struct Apa102Impl {
+    dev: SpiOutput,
+    buf: Vec<u8>,
+}
+
+const START_FRAME_BYTES: usize = 4;
+const BYTES_PER_LED: usize = 4;
+/// Marker of the first byte of a LED frame, followed by the 5-bit global brightness
+const LED_FRAME_MARKER: u8 = 0b1110_0000;
+
+/// Length of the end frame
+///
+/// The data is delayed by half a clock cycle at each LED, the end frame clocks it out to the last
+/// ones.
+fn end_frame_bytes(led_count: usize) -> usize {
+    led_count.div_ceil(16).max(4)
+}
+
+#[async_trait]
+impl WritingDevice for Apa102Impl {
+    type Config = models::Apa102;
+
+    fn new(config: &models::Apa102) -> Result<Self, DeviceError> {
+        let led_count = config.hardware_led_count as usize;
+        let buf =
+            vec![0; START_FRAME_BYTES + led_count * BYTES_PER_LED + end_frame_bytes(led_count)];
+
+        Ok(Self {
+            dev: SpiOutput::new(&config.output, config.rate),
+            buf,
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        encode_frame(&mut self.buf, config, led_data);
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        self.dev.write(&mut self.buf).await
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.dev.last_error()
+    }
+}
+
+fn encode_frame(buf: &mut [u8], config: &models::Apa102, led_data: &[models::Color]) {
+    let (start, rest) = buf.split_at_mut(START_FRAME_BYTES);
+    let (leds, end) =
+        rest.split_at_mut(rest.len() - end_frame_bytes(config.hardware_led_count as _));
+
+    start.fill(0);
+
+    let marker = LED_FRAME_MARKER | config.brightness.min(31);
+    for (dst, led) in leds.chunks_exact_mut(BYTES_PER_LED).zip(led_data) {
+        let (a, b, c) = config.color_order.reorder_from_rgb(*led).into_components();
+        dst.copy_from_slice(&[marker, a, b, c]);
+    }
+
+    end.fill(0xFF);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apa102_frame() {
+        let config: models::Apa102 = serde_json::from_value(serde_json::json!({
+            "hardwareLedCount": 2,
+            "output": "/dev/spidev0.0",
+            "brightness": 16,
+        }))
+        .unwrap();
+
+        let mut buf = vec![0xAA; 4 + 2 * 4 + 4];
+        encode_frame(
+            &mut buf,
+            &config,
+            &[models::Color::new(1, 2, 3), models::Color::new(4, 5, 6)],
+        );
+
+        // Blue, green and red follow the brightness byte by default
+        assert_eq!(
+            buf,
+            [
+                0, 0, 0, 0, // start frame
+                0xF0, 3, 2, 1, // first LED
+                0xF0, 6, 5, 4, // second LED
+                0xFF, 0xFF, 0xFF, 0xFF, // end frame
+            ]
+        );
+    }
+}
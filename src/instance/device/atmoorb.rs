@@ -0,0 +1,176 @@
+use async_trait::async_trait;
+
+use super::{common::*, DeviceError};
+use crate::models;
+
+pub type AtmoOrbDevice = Rewriter<AtmoOrbDeviceImpl>;
+
+/// Identifier at the start of every datagram
+const COMMAND_PREFIX: [u8; 3] = [0xC0, 0xFF, 0xEE];
+/// Set the color, fading to it if smoothing is enabled on the orb
+const COMMAND_SMOOTH_COLOR: u8 = 2;
+/// Set the color immediately
+const COMMAND_COLOR: u8 = 4;
+/// Size of the header: prefix, command and orb ID
+const HEADER_SIZE: usize = 5;
+
+pub struct AtmoOrbDeviceImpl {
+    output: UdpOutput,
+    /// Last color encoded for each orb, indexed like `orb_ids`
+    last_colors: Vec<Option<models::Color>>,
+    /// Datagrams for the current LED data, only the first `packet_count` are valid
+    packets: Vec<Vec<u8>>,
+    packet_count: usize,
+}
+
+#[async_trait]
+impl WritingDevice for AtmoOrbDeviceImpl {
+    type Config = models::AtmoOrb;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        Ok(Self {
+            output: UdpOutput::new("AtmoOrb", &config.host, config.port),
+            last_colors: vec![None; config.orb_ids.len()],
+            packets: Vec::new(),
+            packet_count: 0,
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        self.packet_count = encode(config, led_data, &mut self.last_colors, &mut self.packets);
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        let packets = &self.packets[..self.packet_count];
+        let result = self.output.send(packets).await;
+        self.output
+            .report(result, packets.iter().map(Vec::len).sum())
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.output.last_error()
+    }
+}
+
+/// Command for changing the color of an orb from `last` to `color`
+fn command(config: &models::AtmoOrb, last: Option<models::Color>, color: models::Color) -> u8 {
+    if !config.use_orb_smoothing {
+        return COMMAND_COLOR;
+    }
+
+    // Large changes (e.g. scene cuts) skip the smoothing, so the orbs don't lag behind
+    let threshold = config.skip_smoothing_diff;
+    let skip = threshold != 0
+        && last.is_none_or(|last| {
+            let (r, g, b) = color.into_components();
+            let (lr, lg, lb) = last.into_components();
+            r.abs_diff(lr) >= threshold
+                || g.abs_diff(lg) >= threshold
+                || b.abs_diff(lb) >= threshold
+        });
+
+    if skip {
+        COMMAND_COLOR
+    } else {
+        COMMAND_SMOOTH_COLOR
+    }
+}
+
+/// Encode LED data into one datagram per orb, reusing the buffers in `packets`
+///
+/// Orb `n` takes the color of LED `n - 1`, orbs without a matching LED are skipped.
+///
+/// # Returns
+///
+/// The number of datagrams to send
+fn encode(
+    config: &models::AtmoOrb,
+    led_data: &[models::Color],
+    last_colors: &mut [Option<models::Color>],
+    packets: &mut Vec<Vec<u8>>,
+) -> usize {
+    let mut count = 0;
+
+    for (&id, last) in config.orb_ids.iter().zip(last_colors) {
+        let color = match led_data.get(id as usize - 1) {
+            Some(color) => *color,
+            None => continue,
+        };
+
+        if count == packets.len() {
+            packets.push(Vec::new());
+        }
+
+        // The orbs read a single color, the rest of the datagram is padding they expect
+        let packet = &mut packets[count];
+        packet.clear();
+        packet.extend_from_slice(&COMMAND_PREFIX);
+        packet.push(command(config, *last, color));
+        packet.push(id);
+        let (r, g, b) = color.into_components();
+        packet.extend_from_slice(&[r, g, b]);
+        packet.resize(HEADER_SIZE + config.num_leds as usize * 3, 0);
+
+        *last = Some(color);
+        count += 1;
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let config: models::AtmoOrb = serde_json::from_value(serde_json::json!({
+            "hardwareLedCount": 3,
+            "orbIds": "3, 1",
+            "useOrbSmoothing": true,
+            "skipSmoothingDiff": 50,
+            "numLeds": 2,
+        }))
+        .unwrap();
+        let mut last_colors = vec![None; 2];
+        let mut packets = Vec::new();
+
+        let leds = [
+            models::Color::new(10, 20, 30),
+            models::Color::new(0, 0, 0),
+            models::Color::new(100, 0, 0),
+        ];
+        assert_eq!(encode(&config, &leds, &mut last_colors, &mut packets), 2);
+        // The first colors are sent immediately
+        assert_eq!(
+            packets[0],
+            [0xC0, 0xFF, 0xEE, COMMAND_COLOR, 3, 100, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            packets[1],
+            [0xC0, 0xFF, 0xEE, COMMAND_COLOR, 1, 10, 20, 30, 0, 0, 0]
+        );
+
+        // Small changes are smoothed, large ones aren't
+        let leds = [
+            models::Color::new(20, 20, 30),
+            models::Color::new(0, 0, 0),
+            models::Color::new(200, 0, 0),
+        ];
+        assert_eq!(encode(&config, &leds, &mut last_colors, &mut packets), 2);
+        assert_eq!(&packets[0][3..6], [COMMAND_COLOR, 3, 200]);
+        assert_eq!(&packets[1][3..6], [COMMAND_SMOOTH_COLOR, 1, 20]);
+
+        // Orbs without a LED are skipped
+        assert_eq!(
+            encode(&config, &leds[..1], &mut last_colors, &mut packets),
+            1
+        );
+        assert_eq!(packets[0][4], 1);
+    }
+}
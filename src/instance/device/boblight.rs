@@ -0,0 +1,269 @@
+//! Client of a boblightd server, which drives the LEDs with its own hardware setup
+//!
+//! LEDs are mapped to the lights of the server in the order it lists them. The speed of the
+//! lights is set to 100 on connection, so boblightd doesn't smooth the output again.
+
+use std::{fmt::Write as _, net::SocketAddr, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::{common::*, resolver::HostResolver, DeviceError};
+use crate::models;
+
+pub type BoblightDevice = Rewriter<BoblightDeviceImpl>;
+
+/// Protocol version of boblightd
+const PROTOCOL_VERSION: &str = "5";
+
+/// Time allowed for the server to answer the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maximum number of lights accepted from the server
+const MAX_LIGHTS: usize = 65536;
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+    /// Names of the lights of the server
+    lights: Vec<String>,
+}
+
+pub struct BoblightDeviceImpl {
+    resolver: HostResolver,
+    priority: u8,
+    led_count: usize,
+    connection: Option<Connection>,
+    led_data: Vec<models::Color>,
+    /// Request for the current LED data
+    buf: String,
+    /// Error of the last write, logged when it first occurs
+    last_error: Option<String>,
+    notified_mismatch: bool,
+}
+
+impl BoblightDeviceImpl {
+    async fn connection(&mut self) -> Result<&mut Connection, DeviceError> {
+        if self.connection.is_none() {
+            // Resolved on first write, the server may not be up when the instance starts
+            let address = self.resolver.address().await?;
+
+            let connection =
+                tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(address, self.priority))
+                    .await
+                    .map_err(|_| DeviceError::InvalidReply("handshake timed out".to_owned()))??;
+
+            info!(
+                host = %self.resolver.host(),
+                address = %address,
+                lights = %connection.lights.len(),
+                "connected to boblightd"
+            );
+
+            if connection.lights.len() != self.led_count && !self.notified_mismatch {
+                self.notified_mismatch = true;
+                warn!(
+                    lights = %connection.lights.len(),
+                    leds = %self.led_count,
+                    "boblightd doesn't have as many lights as the device has LEDs"
+                );
+            }
+
+            self.connection = Some(connection);
+        }
+
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    async fn send(&mut self) -> Result<usize, DeviceError> {
+        let led_data = std::mem::take(&mut self.led_data);
+        let mut buf = std::mem::take(&mut self.buf);
+
+        let result = async {
+            let connection = self.connection().await?;
+            encode(&connection.lights, &led_data, &mut buf);
+            connection
+                .stream
+                .get_mut()
+                .write_all(buf.as_bytes())
+                .await?;
+
+            Ok::<_, DeviceError>(buf.len())
+        }
+        .await;
+
+        self.led_data = led_data;
+        self.buf = buf;
+        result
+    }
+}
+
+#[async_trait]
+impl WritingDevice for BoblightDeviceImpl {
+    type Config = models::Boblight;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        Ok(Self {
+            resolver: HostResolver::new(&config.host, config.port),
+            priority: config.priority,
+            led_count: config.hardware_led_count as usize,
+            connection: None,
+            led_data: vec![Default::default(); config.hardware_led_count as usize],
+            buf: String::new(),
+            last_error: None,
+            notified_mismatch: false,
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        _config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        self.led_data.clear();
+        self.led_data.extend_from_slice(led_data);
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        match self.send().await {
+            Ok(written) => {
+                self.last_error = None;
+                self.resolver.report_success();
+                Ok(written)
+            }
+            Err(error) => {
+                // Connect again on the next write, resolving the address again if this keeps
+                // failing
+                self.connection = None;
+                self.resolver.report_failure();
+
+                if self.last_error.is_none() {
+                    error!(host = %self.resolver.host(), error = %error, "failed to write to boblightd");
+                }
+
+                self.last_error = Some(error.to_string());
+                Ok(0)
+            }
+        }
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+}
+
+/// Connect to boblightd, get its lights and set the client priority
+async fn handshake(address: SocketAddr, priority: u8) -> Result<Connection, DeviceError> {
+    let mut stream = BufReader::new(TcpStream::connect(address).await?);
+    stream
+        .get_mut()
+        .write_all(b"hello\nget version\nget lights\n")
+        .await?;
+
+    let hello = read_line(&mut stream).await?;
+    if hello != "hello" {
+        return Err(DeviceError::InvalidReply(hello));
+    }
+
+    let version = read_line(&mut stream).await?;
+    if version.strip_prefix("version ").map(str::trim) != Some(PROTOCOL_VERSION) {
+        return Err(DeviceError::InvalidReply(version));
+    }
+
+    let count = read_line(&mut stream).await?;
+    let count = count
+        .strip_prefix("lights ")
+        .and_then(|count| count.trim().parse::<usize>().ok())
+        .filter(|count| *count <= MAX_LIGHTS)
+        .ok_or(DeviceError::InvalidReply(count))?;
+
+    let mut lights = Vec::with_capacity(count);
+    for _ in 0..count {
+        let light = read_line(&mut stream).await?;
+        lights.push(parse_light(&light).ok_or(DeviceError::InvalidReply(light))?);
+    }
+
+    let mut request = format!("set priority {}\n", priority);
+    for light in &lights {
+        // unwrap: writing to a String cannot fail
+        writeln!(request, "set light {} speed 100", light).unwrap();
+    }
+    stream.get_mut().write_all(request.as_bytes()).await?;
+
+    Ok(Connection { stream, lights })
+}
+
+async fn read_line(stream: &mut BufReader<TcpStream>) -> Result<String, DeviceError> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(DeviceError::InvalidReply(
+            "connection closed by boblightd".to_owned(),
+        ));
+    }
+
+    Ok(line.trim().to_owned())
+}
+
+/// Name of the light described by a line of the `get lights` reply
+fn parse_light(line: &str) -> Option<String> {
+    let mut parts = line.split_whitespace();
+    if parts.next()? != "light" {
+        return None;
+    }
+
+    parts.next().map(ToOwned::to_owned)
+}
+
+/// Encode the colors of the lights into `buf`, followed by a sync
+fn encode(lights: &[String], led_data: &[models::Color], buf: &mut String) {
+    buf.clear();
+
+    for (light, led) in lights.iter().zip(led_data) {
+        // unwrap: writing to a String cannot fail
+        writeln!(
+            buf,
+            "set light {} rgb {:.6} {:.6} {:.6}",
+            light,
+            led.red as f32 / 255.,
+            led.green as f32 / 255.,
+            led.blue as f32 / 255.
+        )
+        .unwrap();
+    }
+
+    buf.push_str("sync\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            parse_light("light left scan 0 100 0 10"),
+            Some("left".to_owned())
+        );
+        assert_eq!(parse_light("lights 2"), None);
+
+        let lights = vec!["left".to_owned(), "right".to_owned()];
+        let mut buf = String::new();
+        encode(
+            &lights,
+            &[
+                models::Color::new(255, 0, 51),
+                models::Color::new(0, 255, 0),
+            ],
+            &mut buf,
+        );
+        assert_eq!(
+            buf,
+            "set light left rgb 1.000000 0.000000 0.200000\n\
+             set light right rgb 0.000000 1.000000 0.000000\n\
+             sync\n"
+        );
+    }
+}
@@ -1,10 +1,18 @@
-use std::time::Instant;
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
+use tokio::net::UdpSocket;
 
-use super::{DeviceError, DeviceImpl};
+use super::{resolver::HostResolver, DeviceError, DeviceImpl, DeviceStats, WhiteLevels};
 use crate::models::{self, DeviceConfig};
 
+/// Period over which the frame rate of a device is measured
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
 #[async_trait]
 pub trait WritingDevice: Send + Sized {
     type Config: DeviceConfig;
@@ -17,7 +25,29 @@ pub trait WritingDevice: Send + Sized {
         led_data: &[models::Color],
     ) -> Result<(), DeviceError>;
 
-    async fn write(&mut self) -> Result<(), DeviceError>;
+    /// Set the levels of the white channels, for devices whose config has a white output
+    fn set_white_data(&mut self, _config: &Self::Config, _white_data: &[WhiteLevels]) {}
+
+    /// Write the current LED data to the device
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written. Devices which recover from write errors by themselves (e.g.
+    /// by reconnecting on the next write) return `Ok(0)` and report the error through
+    /// [WritingDevice::last_error].
+    async fn write(&mut self) -> Result<usize, DeviceError>;
+
+    /// Error of the last write, if the device recovered from it
+    fn last_error(&self) -> Option<&str> {
+        None
+    }
+
+    async fn query_led_count(
+        &mut self,
+        _config: &Self::Config,
+    ) -> Result<Option<usize>, DeviceError> {
+        Ok(None)
+    }
 }
 
 pub struct Rewriter<D: WritingDevice> {
@@ -25,6 +55,55 @@ pub struct Rewriter<D: WritingDevice> {
     config: D::Config,
     last_write_time: Option<Instant>,
     next_write_time: Option<Instant>,
+    stats: WriteStats,
+}
+
+#[derive(Default)]
+struct WriteStats {
+    frames: u64,
+    bytes: u64,
+    /// Number of frames replaced by a newer one while waiting for the latch time
+    skipped: u64,
+    /// Time of the frames written during the last [FPS_WINDOW]
+    recent: VecDeque<Instant>,
+    last_error: Option<String>,
+}
+
+impl WriteStats {
+    fn record(&mut self, result: &Result<usize, DeviceError>, last_error: Option<&str>) {
+        match (result, last_error) {
+            (Ok(bytes), None) => {
+                let now = Instant::now();
+                while self
+                    .recent
+                    .front()
+                    .is_some_and(|time| now.duration_since(*time) > FPS_WINDOW)
+                {
+                    self.recent.pop_front();
+                }
+
+                self.recent.push_back(now);
+                self.frames += 1;
+                self.bytes += *bytes as u64;
+                self.last_error = None;
+            }
+            (Ok(_), Some(error)) => {
+                self.last_error = Some(error.to_owned());
+            }
+            (Err(error), _) => {
+                self.last_error = Some(error.to_string());
+            }
+        }
+    }
+
+    fn fps(&self) -> f64 {
+        let now = Instant::now();
+        self.recent
+            .iter()
+            .filter(|time| now.duration_since(**time) <= FPS_WINDOW)
+            .count() as f64
+            / FPS_WINDOW.as_secs_f64()
+    }
 }
 
 impl<D: WritingDevice> Rewriter<D> {
@@ -36,11 +115,15 @@ impl<D: WritingDevice> Rewriter<D> {
             config,
             last_write_time: None,
             next_write_time: None,
+            stats: Default::default(),
         })
     }
 
     async fn write(&mut self) -> Result<(), DeviceError> {
-        self.inner.write().await?;
+        let result = self.inner.write().await;
+        self.stats.record(&result, self.inner.last_error());
+        result?;
+
         self.last_write_time = Some(Instant::now());
         self.next_write_time = None;
         Ok(())
@@ -60,8 +143,11 @@ impl<D: WritingDevice> Rewriter<D> {
                 // Latch time elapsed already
                 self.write().await?;
             } else {
-                // Not elapsed yet, so schedule it
-                self.next_write_time = Some(next_write_time);
+                // Not elapsed yet, so schedule it. A write that was already scheduled now
+                // writes the newer data instead.
+                if self.next_write_time.replace(next_write_time).is_some() {
+                    self.stats.skipped += 1;
+                }
             }
         } else {
             // Never wrote anything, so immediately write
@@ -80,6 +166,10 @@ impl<D: WritingDevice> DeviceImpl for Rewriter<D> {
         Ok(())
     }
 
+    fn set_white_data(&mut self, white_data: &[WhiteLevels]) {
+        self.inner.set_white_data(&self.config, white_data);
+    }
+
     async fn update(&mut self) -> Result<(), DeviceError> {
         // Handle latching
         if let Some(next_write_time) = self.next_write_time {
@@ -115,4 +205,229 @@ impl<D: WritingDevice> DeviceImpl for Rewriter<D> {
             futures::future::pending().await
         }
     }
+
+    async fn query_led_count(&mut self) -> Result<Option<usize>, DeviceError> {
+        self.inner.query_led_count(&self.config).await
+    }
+
+    fn stats(&self) -> DeviceStats {
+        DeviceStats {
+            frames: self.stats.frames,
+            skipped: self.stats.skipped,
+            bytes: self.stats.bytes,
+            fps: self.stats.fps(),
+            last_error: self.stats.last_error.clone(),
+        }
+    }
+}
+
+/// UDP socket to a network device, for implementing [WritingDevice::write]
+///
+/// The device address is resolved on the first write, since the device may not be up when the
+/// instance starts, and the socket is opened again after a failed write. Write errors are logged
+/// once and reported through [UdpOutput::last_error] until a write succeeds.
+pub struct UdpOutput {
+    /// Kind of device, for logs
+    name: &'static str,
+    /// Address of the device, if it has a host
+    resolver: Option<HostResolver>,
+    /// Socket, with the address it is connected to if any
+    socket: Option<(UdpSocket, Option<SocketAddr>)>,
+    last_error: Option<String>,
+}
+
+impl UdpOutput {
+    /// Create an output to the given host
+    ///
+    /// Without a host, datagrams can only be sent to explicit destinations with
+    /// [UdpOutput::send_to].
+    pub fn new(name: &'static str, host: &str, port: u16) -> Self {
+        Self {
+            name,
+            resolver: (!host.is_empty()).then(|| HostResolver::new(host, port)),
+            socket: None,
+            last_error: None,
+        }
+    }
+
+    fn host(&self) -> &str {
+        self.resolver
+            .as_ref()
+            .map_or("multicast", |resolver| resolver.host())
+    }
+
+    /// Current address of the device
+    pub async fn address(&mut self) -> Result<SocketAddr, DeviceError> {
+        match &mut self.resolver {
+            Some(resolver) => resolver.address().await,
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotConnected).into()),
+        }
+    }
+
+    /// Get a socket for sending to `destination`, connected to it if `connect` is true
+    async fn socket(
+        &mut self,
+        destination: SocketAddr,
+        connect: bool,
+    ) -> Result<&UdpSocket, DeviceError> {
+        let connected = connect.then_some(destination);
+        let matches = match &self.socket {
+            Some((_, Some(address))) => connected == Some(*address),
+            Some((socket, None)) => {
+                connected.is_none() && socket.local_addr()?.is_ipv4() == destination.is_ipv4()
+            }
+            None => false,
+        };
+
+        if !matches {
+            let socket = UdpSocket::bind(if destination.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            })
+            .await?;
+
+            if let Some(address) = connected {
+                socket.connect(address).await?;
+                info!(host = %self.host(), address = %address, "connected to {}", self.name);
+            }
+
+            self.socket = Some((socket, connected));
+        }
+
+        // unwrap: the socket was just created if it was missing
+        Ok(&self.socket.as_ref().unwrap().0)
+    }
+
+    /// Send the datagrams of a frame to the device
+    pub async fn send(&mut self, packets: &[Vec<u8>]) -> Result<(), DeviceError> {
+        let address = self.address().await?;
+        let socket = self.socket(address, true).await?;
+        for packet in packets {
+            socket.send(packet).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a datagram to the given destination, for devices without a single address
+    pub async fn send_to(
+        &mut self,
+        packet: &[u8],
+        destination: SocketAddr,
+    ) -> Result<(), DeviceError> {
+        self.socket(destination, false)
+            .await?
+            .send_to(packet, destination)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record the result of sending a frame of `bytes` bytes
+    ///
+    /// # Returns
+    ///
+    /// The result of the write for [WritingDevice::write]: failures are recovered from by
+    /// opening a new socket, resolving the address again if they keep occurring.
+    pub fn report(
+        &mut self,
+        result: Result<(), DeviceError>,
+        bytes: usize,
+    ) -> Result<usize, DeviceError> {
+        match result {
+            Ok(()) => {
+                self.last_error = None;
+                if let Some(resolver) = &mut self.resolver {
+                    resolver.report_success();
+                }
+
+                Ok(bytes)
+            }
+            Err(error) => {
+                self.socket = None;
+                if let Some(resolver) = &mut self.resolver {
+                    resolver.report_failure();
+                }
+
+                if self.last_error.is_none() {
+                    error!(host = %self.host(), error = %error, "failed to write to {}", self.name);
+                }
+
+                self.last_error = Some(error.to_string());
+                Ok(0)
+            }
+        }
+    }
+
+    /// Error of the last write, if it failed
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_stats() {
+        let mut stats = WriteStats::default();
+
+        stats.record(&Ok(12), None);
+        stats.record(&Ok(12), None);
+        assert_eq!((stats.frames, stats.bytes), (2, 24));
+        assert_eq!(stats.fps(), 2.);
+        assert_eq!(stats.last_error, None);
+
+        // Errors the device recovered from aren't counted as written frames
+        stats.record(&Ok(0), Some("port closed"));
+        assert_eq!((stats.frames, stats.bytes), (2, 24));
+        assert_eq!(stats.last_error.as_deref(), Some("port closed"));
+
+        stats.record(&Ok(12), None);
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn test_udp_output() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let mut output = UdpOutput::new("test device", "127.0.0.1", port);
+
+        let result = output.send(&[vec![1, 2], vec![3]]).await;
+        assert_eq!(output.report(result, 3).unwrap(), 3);
+        assert_eq!(output.last_error(), None);
+
+        let mut buffer = [0; 4];
+        assert_eq!(receiver.recv(&mut buffer).await.unwrap(), 2);
+        assert_eq!(receiver.recv(&mut buffer).await.unwrap(), 1);
+
+        // Failures are reported until a write succeeds, and reconnect the socket
+        let result = Err(DeviceError::InvalidReply("unreachable".to_owned()));
+        assert_eq!(output.report(result, 3).unwrap(), 0);
+        assert_eq!(
+            output.last_error(),
+            Some("invalid device reply: unreachable")
+        );
+        assert!(output.socket.is_none());
+
+        let result = output.send(&[vec![4]]).await;
+        assert_eq!(output.report(result, 1).unwrap(), 1);
+        assert_eq!(output.last_error(), None);
+        assert_eq!(receiver.recv(&mut buffer).await.unwrap(), 1);
+        assert_eq!(buffer[0], 4);
+
+        // Outputs without a host only send to explicit destinations
+        let mut output = UdpOutput::new("test device", "", port);
+        let result = output.send(&[vec![5]]).await;
+        assert!(result.is_err());
+        assert_eq!(output.report(result, 1).unwrap(), 0);
+
+        let result = output.send_to(&[6], receiver.local_addr().unwrap()).await;
+        assert_eq!(output.report(result, 1).unwrap(), 1);
+        assert_eq!(receiver.recv(&mut buffer).await.unwrap(), 1);
+        assert_eq!(buffer[0], 6);
+    }
 }
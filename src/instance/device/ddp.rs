@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+
+use super::{common::*, DeviceError};
+use crate::models;
+
+pub type DdpDevice = Rewriter<DdpDeviceImpl>;
+
+/// Version 1 of the protocol, in the flags byte
+const FLAG_VERSION_1: u8 = 0x40;
+/// Display the data received so far, set on the last datagram of a frame
+const FLAG_PUSH: u8 = 0x01;
+/// RGB pixels with 8 bits per channel
+const DATA_TYPE_RGB24: u8 = 0x0B;
+const HEADER_SIZE: usize = 10;
+/// Offset of the sequence number, updated on every write
+const SEQUENCE_OFFSET: usize = 1;
+/// Maximum number of LEDs in a datagram, for 1440 bytes of data
+const MAX_LEDS: usize = 480;
+/// Sequence numbers go from 1 to 15, 0 means they aren't used
+const MAX_SEQUENCE: u8 = 15;
+
+pub struct DdpDeviceImpl {
+    output: UdpOutput,
+    sequence: u8,
+    /// Datagrams for the current LED data, only the first `packet_count` are valid
+    packets: Vec<Vec<u8>>,
+    packet_count: usize,
+}
+
+#[async_trait]
+impl WritingDevice for DdpDeviceImpl {
+    type Config = models::Ddp;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        Ok(Self {
+            output: UdpOutput::new("DDP controller", &config.host, config.port),
+            sequence: 0,
+            packets: Vec::new(),
+            packet_count: 0,
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        self.packet_count = encode(config, led_data, &mut self.packets);
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        // All the datagrams of a frame share its sequence number, so the controller can tell
+        // frames apart when datagrams are lost or reordered
+        self.sequence = self.sequence % MAX_SEQUENCE + 1;
+        for packet in &mut self.packets[..self.packet_count] {
+            packet[SEQUENCE_OFFSET] = self.sequence;
+        }
+
+        let packets = &self.packets[..self.packet_count];
+        let result = self.output.send(packets).await;
+        self.output
+            .report(result, packets.iter().map(Vec::len).sum())
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.output.last_error()
+    }
+}
+
+/// Encode LED data into datagrams of at most [MAX_LEDS] LEDs, reusing the buffers in `packets`
+///
+/// # Returns
+///
+/// The number of datagrams to send
+fn encode(config: &models::Ddp, led_data: &[models::Color], packets: &mut Vec<Vec<u8>>) -> usize {
+    let count = led_data.len().div_ceil(MAX_LEDS);
+    if packets.len() < count {
+        packets.resize_with(count, Vec::new);
+    }
+
+    for (i, (chunk, packet)) in led_data
+        .chunks(MAX_LEDS)
+        .zip(packets.iter_mut())
+        .enumerate()
+    {
+        let flags = if i + 1 == count {
+            FLAG_VERSION_1 | FLAG_PUSH
+        } else {
+            FLAG_VERSION_1
+        };
+        let offset = (i * MAX_LEDS * 3) as u32;
+
+        packet.clear();
+        packet.reserve(HEADER_SIZE + chunk.len() * 3);
+        // The sequence number is set when writing
+        packet.extend_from_slice(&[flags, 0, DATA_TYPE_RGB24, config.destination_id]);
+        packet.extend_from_slice(&offset.to_be_bytes());
+        packet.extend_from_slice(&((chunk.len() * 3) as u16).to_be_bytes());
+
+        for led in chunk {
+            let (a, b, c) = config.color_order.reorder_from_rgb(*led).into_components();
+            packet.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let config: models::Ddp = serde_json::from_value(serde_json::json!({
+            "hardwareLedCount": 500,
+            "host": "127.0.0.1",
+        }))
+        .unwrap();
+        let leds: Vec<_> = (0..500)
+            .map(|i| models::Color::new(i as u8, 1, 2))
+            .collect();
+        let mut packets = Vec::new();
+
+        assert_eq!(encode(&config, &leds, &mut packets), 2);
+
+        assert_eq!(packets[0].len(), HEADER_SIZE + 1440);
+        assert_eq!(
+            &packets[0][..HEADER_SIZE],
+            [0x40, 0, 0x0B, 1, 0, 0, 0, 0, 0x05, 0xA0]
+        );
+        assert_eq!(
+            &packets[0][HEADER_SIZE..HEADER_SIZE + 6],
+            [0, 1, 2, 1, 1, 2]
+        );
+
+        // The last datagram pushes the frame
+        assert_eq!(packets[1].len(), HEADER_SIZE + 60);
+        assert_eq!(
+            &packets[1][..HEADER_SIZE],
+            [0x41, 0, 0x0B, 1, 0, 0, 0x05, 0xA0, 0, 60]
+        );
+        assert_eq!(
+            &packets[1][HEADER_SIZE..HEADER_SIZE + 3],
+            [480u16 as u8, 1, 2]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::models;
+
+/// Maximum number of frames held back, older frames are dropped beyond it
+const MAX_QUEUED_FRAMES: usize = 1024;
+
+/// Weight of the previous estimate when measuring the write latency
+const LATENCY_SMOOTHING: u32 = 8;
+
+/// Queue of LED frames, each released once the output delay of the device has elapsed
+///
+/// Frames are released early by the time the device takes to write them, so the LEDs change
+/// color when the delay elapses rather than after it.
+#[derive(Debug)]
+pub struct FrameDelay {
+    delay: Duration,
+    /// Estimated time taken by the device to write a frame
+    latency: Duration,
+    frames: VecDeque<(Instant, Vec<models::Color>)>,
+    /// Buffers of released frames, reused for the next ones
+    spare: Vec<Vec<models::Color>>,
+    /// Number of frames dropped because a newer one was due, or the queue was full
+    skipped: u64,
+}
+
+impl FrameDelay {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            latency: Duration::ZERO,
+            frames: VecDeque::new(),
+            spare: Vec::new(),
+            skipped: 0,
+        }
+    }
+
+    /// Queue a frame received at `now`
+    pub fn push(&mut self, led_data: &[models::Color], now: Instant) {
+        if self.frames.len() >= MAX_QUEUED_FRAMES {
+            if let Some((_, frame)) = self.frames.pop_front() {
+                self.spare.push(frame);
+                self.skipped += 1;
+            }
+        }
+
+        let mut frame = self.spare.pop().unwrap_or_default();
+        frame.clear();
+        frame.extend_from_slice(led_data);
+
+        let due = now + self.delay.saturating_sub(self.latency);
+        self.frames.push_back((due, frame));
+    }
+
+    /// Wait until the next frame is due
+    pub async fn due(&self) {
+        match self.frames.front() {
+            Some((due, _)) => tokio::time::sleep_until(*due).await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Take the latest frame due at `now`, dropping the older ones which are superseded by it
+    pub fn pop(&mut self, now: Instant) -> Option<Vec<models::Color>> {
+        let mut latest = None;
+
+        while self.frames.front().is_some_and(|(due, _)| *due <= now) {
+            // unwrap: the queue was just checked not to be empty
+            let (_, frame) = self.frames.pop_front().unwrap();
+            if let Some(previous) = latest.replace(frame) {
+                self.spare.push(previous);
+                self.skipped += 1;
+            }
+        }
+
+        latest
+    }
+
+    /// Give back the buffer of a frame returned by [FrameDelay::pop]
+    pub fn recycle(&mut self, frame: Vec<models::Color>) {
+        self.spare.push(frame);
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Account for the time the device took to write a frame in the next release times
+    pub fn record_latency(&mut self, elapsed: Duration) {
+        self.latency = ((self.latency * (LATENCY_SMOOTHING - 1) + elapsed) / LATENCY_SMOOTHING)
+            .min(self.delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_delay() {
+        let mut delay = FrameDelay::new(Duration::from_millis(100));
+        let start = Instant::now();
+        let frame = |v: u8| vec![models::Color::new(v, v, v)];
+
+        delay.push(&frame(1), start);
+        delay.push(&frame(2), start + Duration::from_millis(10));
+        delay.push(&frame(3), start + Duration::from_millis(50));
+
+        assert_eq!(delay.pop(start + Duration::from_millis(99)), None);
+        assert_eq!(
+            delay.pop(start + Duration::from_millis(100)),
+            Some(frame(1))
+        );
+
+        // Frames due at the same time only output the latest one
+        assert_eq!(
+            delay.pop(start + Duration::from_millis(200)),
+            Some(frame(3))
+        );
+        assert_eq!(delay.pop(start + Duration::from_millis(300)), None);
+        assert_eq!(delay.skipped(), 1);
+
+        // Slow writes release the next frames earlier
+        for _ in 0..100 {
+            delay.record_latency(Duration::from_millis(20));
+        }
+        delay.push(&frame(4), start);
+        assert_eq!(delay.pop(start + Duration::from_millis(81)), Some(frame(4)));
+    }
+}
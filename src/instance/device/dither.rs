@@ -0,0 +1,77 @@
+//! Temporal dithering of smoothed colors to the 8-bit device output
+//!
+//! Each LED carries the part of its 16-bit value which was truncated in the previous frames, and
+//! adds it to the next one. Over a few frames, the LED averages to its exact value instead of
+//! staying on the level below, so slow fades near black don't step visibly.
+
+use crate::{
+    color::color_to8,
+    models::{Color, Color16},
+};
+
+/// Ratio between the 16-bit and 8-bit ranges
+const FACTOR: u32 = 257;
+
+#[derive(Debug, Default)]
+pub struct Ditherer {
+    /// Truncated part of each channel, in 16-bit units
+    error: Vec<[u16; 3]>,
+}
+
+impl Ditherer {
+    /// Quantize `led_data` to `output`
+    ///
+    /// Once `settled`, the colors are truncated as without dithering, so static output doesn't
+    /// flicker.
+    pub fn quantize(&mut self, led_data: &[Color16], settled: bool, output: &mut Vec<Color>) {
+        output.clear();
+
+        if settled {
+            self.error.clear();
+            output.extend(led_data.iter().map(|color| color_to8(*color)));
+            return;
+        }
+
+        self.error.resize(led_data.len(), Default::default());
+        output.extend(led_data.iter().zip(&mut self.error).map(|(color, error)| {
+            let (r, g, b) = color.into_components();
+            Color::new(
+                dither(r, &mut error[0]),
+                dither(g, &mut error[1]),
+                dither(b, &mut error[2]),
+            )
+        }));
+    }
+}
+
+fn dither(value: u16, error: &mut u16) -> u8 {
+    let value = value as u32 + *error as u32;
+    let level = (value / FACTOR).min(255);
+    *error = (value - level * FACTOR).min(FACTOR - 1) as u16;
+    level as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dither() {
+        let mut ditherer = Ditherer::default();
+        let mut output = Vec::new();
+
+        // A quarter of the first level lights the LED one frame out of four
+        let led_data = [Color16::new(64, 0, 65535)];
+        let mut lit = 0;
+        for _ in 0..257 {
+            ditherer.quantize(&led_data, false, &mut output);
+            lit += output[0].red as u32;
+            assert_eq!((output[0].green, output[0].blue), (0, 255));
+        }
+        assert_eq!(lit, 64);
+
+        // Settled colors are truncated
+        ditherer.quantize(&led_data, true, &mut output);
+        assert_eq!(output, [Color::new(0, 0, 255)]);
+    }
+}
@@ -33,7 +33,7 @@ impl WritingDevice for DummyDeviceImpl {
         Ok(())
     }
 
-    async fn write(&mut self) -> Result<(), DeviceError> {
+    async fn write(&mut self) -> Result<usize, DeviceError> {
         // Write to log when we get new data
         match self.mode {
             models::DummyDeviceMode::Text => {
@@ -61,6 +61,7 @@ impl WritingDevice for DummyDeviceImpl {
             }
         }
 
-        Ok(())
+        // Nothing reaches actual hardware
+        Ok(0)
     }
 }
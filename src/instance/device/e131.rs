@@ -0,0 +1,243 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::{common::*, DeviceError};
+use crate::models;
+
+pub type E131Device = Rewriter<E131DeviceImpl>;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+/// Flags of the PDU length fields
+const PDU_FLAGS: u16 = 0x7000;
+
+/// Offsets of the root, framing and DMP layers in a data packet
+const ROOT_LAYER_OFFSET: usize = 16;
+const FRAMING_LAYER_OFFSET: usize = 38;
+const DMP_LAYER_OFFSET: usize = 115;
+/// Offset of the sequence number, updated on every write
+const SEQUENCE_OFFSET: usize = 111;
+const SOURCE_NAME_SIZE: usize = 64;
+
+const MAX_UNIVERSE: u16 = 63999;
+const CHANNELS_PER_LED: usize = 3;
+
+pub struct E131DeviceImpl {
+    output: UdpOutput,
+    /// Multicast each universe to its group, when no controller host is configured
+    multicast: bool,
+    port: u16,
+    /// Universe of the first packet
+    universe: u16,
+    cid: Uuid,
+    sequence: u8,
+    notified_truncated: bool,
+    /// Packets for the current LED data, only the first `packet_count` are valid
+    packets: Vec<Vec<u8>>,
+    packet_count: usize,
+}
+
+impl E131DeviceImpl {
+    async fn send(&mut self) -> Result<(), DeviceError> {
+        let packets = &self.packets[..self.packet_count];
+        if !self.multicast {
+            return self.output.send(packets).await;
+        }
+
+        for (i, packet) in packets.iter().enumerate() {
+            let [high, low] = (self.universe + i as u16).to_be_bytes();
+            let destination = SocketAddr::new(Ipv4Addr::new(239, 255, high, low).into(), self.port);
+            self.output.send_to(packet, destination).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WritingDevice for E131DeviceImpl {
+    type Config = models::E131;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        let cid = config.cid.unwrap_or_else(|| {
+            let hostname = hostname::get().unwrap_or_default();
+            Uuid::new_v5(
+                &Uuid::NAMESPACE_OID,
+                format!("{}/{}", hostname.to_string_lossy(), config.source_name).as_bytes(),
+            )
+        });
+
+        Ok(Self {
+            output: UdpOutput::new("E1.31 controller", &config.host, config.port),
+            multicast: config.host.is_empty(),
+            port: config.port,
+            universe: config.universe,
+            cid,
+            sequence: 0,
+            notified_truncated: false,
+            packets: Vec::new(),
+            packet_count: 0,
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        self.packet_count = encode(config, &self.cid, led_data, &mut self.packets);
+
+        let sent = self.packet_count * leds_per_universe(config);
+        if sent < led_data.len() && !self.notified_truncated {
+            self.notified_truncated = true;
+            warn!(
+                "universes after {} are not addressable, the last {} LEDs are not sent",
+                MAX_UNIVERSE,
+                led_data.len() - sent
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        // Receivers discard packets which don't have a newer sequence number, including rewrites
+        self.sequence = self.sequence.wrapping_add(1);
+        for packet in &mut self.packets[..self.packet_count] {
+            packet[SEQUENCE_OFFSET] = self.sequence;
+        }
+
+        let result = self.send().await;
+        let bytes = self.packets[..self.packet_count].iter().map(Vec::len).sum();
+        self.output.report(result, bytes)
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.output.last_error()
+    }
+}
+
+fn leds_per_universe(config: &models::E131) -> usize {
+    config.universe_size as usize / CHANNELS_PER_LED
+}
+
+/// Encode LED data into one data packet per universe, reusing the buffers in `packets`
+///
+/// # Returns
+///
+/// The number of packets to send
+fn encode(
+    config: &models::E131,
+    cid: &Uuid,
+    led_data: &[models::Color],
+    packets: &mut Vec<Vec<u8>>,
+) -> usize {
+    let mut count = 0;
+
+    for (i, chunk) in led_data.chunks(leds_per_universe(config)).enumerate() {
+        let universe = config.universe as usize + i;
+        if universe > MAX_UNIVERSE as usize {
+            break;
+        }
+
+        if count == packets.len() {
+            packets.push(Vec::new());
+        }
+
+        let packet = &mut packets[count];
+        packet.clear();
+        encode_header(
+            packet,
+            config,
+            cid,
+            universe as u16,
+            chunk.len() * CHANNELS_PER_LED,
+        );
+
+        for led in chunk {
+            let (a, b, c) = config.color_order.reorder_from_rgb(*led).into_components();
+            packet.extend_from_slice(&[a, b, c]);
+        }
+
+        count += 1;
+    }
+
+    count
+}
+
+/// Write the header of a data packet carrying `channels` DMX values to `universe`
+fn encode_header(
+    packet: &mut Vec<u8>,
+    config: &models::E131,
+    cid: &Uuid,
+    universe: u16,
+    channels: usize,
+) {
+    let length = DMP_LAYER_OFFSET + 11 + channels;
+    let pdu_length = |offset: usize| (PDU_FLAGS | (length - offset) as u16).to_be_bytes();
+
+    // Root layer
+    packet.extend_from_slice(&0x0010u16.to_be_bytes());
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.extend_from_slice(ACN_PACKET_IDENTIFIER);
+    packet.extend_from_slice(&pdu_length(ROOT_LAYER_OFFSET));
+    packet.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+    packet.extend_from_slice(cid.as_bytes());
+
+    // Framing layer
+    packet.extend_from_slice(&pdu_length(FRAMING_LAYER_OFFSET));
+    packet.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+    let name = config.source_name.as_bytes();
+    let name = &name[..name.len().min(SOURCE_NAME_SIZE - 1)];
+    packet.extend_from_slice(name);
+    packet.resize(packet.len() + SOURCE_NAME_SIZE - name.len(), 0);
+    packet.push(config.priority);
+    // Synchronization address, sequence number and options
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+    packet.extend_from_slice(&universe.to_be_bytes());
+
+    // DMP layer
+    packet.extend_from_slice(&pdu_length(DMP_LAYER_OFFSET));
+    packet.push(VECTOR_DMP_SET_PROPERTY);
+    // Address and data type, first property address, address increment
+    packet.extend_from_slice(&[0xa1, 0, 0, 0, 1]);
+    packet.extend_from_slice(&(channels as u16 + 1).to_be_bytes());
+    // DMX start code
+    packet.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let config: models::E131 = serde_json::from_value(serde_json::json!({
+            "hardwareLedCount": 200,
+            "universe": 3,
+            "sourceName": "test",
+        }))
+        .unwrap();
+        let leds: Vec<_> = (0..200)
+            .map(|i| models::Color::new(i as u8, 1, 2))
+            .collect();
+        let mut packets = Vec::new();
+
+        // 170 LEDs fit in a 510 channel universe, the rest go in the next one
+        assert_eq!(encode(&config, &Uuid::nil(), &leds, &mut packets), 2);
+
+        let packet = &packets[1];
+        assert_eq!(packet.len(), 126 + 30 * 3);
+        assert_eq!(&packet[4..16], ACN_PACKET_IDENTIFIER);
+        assert_eq!(&packet[16..18], (0x7000u16 | (126 + 90 - 16)).to_be_bytes());
+        assert_eq!(&packet[44..49], b"test\0");
+        assert_eq!(packet[108], 100);
+        assert_eq!(&packet[113..115], 4u16.to_be_bytes());
+        assert_eq!(&packet[123..125], 91u16.to_be_bytes());
+        assert_eq!(&packet[125..129], [0, 170, 1, 2]);
+    }
+}
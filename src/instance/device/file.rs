@@ -45,7 +45,7 @@ impl WritingDevice for FileDeviceImpl {
         Ok(())
     }
 
-    async fn write(&mut self) -> Result<(), DeviceError> {
+    async fn write(&mut self) -> Result<usize, DeviceError> {
         self.str_buf.clear();
 
         if self.print_timestamp {
@@ -66,6 +66,6 @@ impl WritingDevice for FileDeviceImpl {
         self.file_handle.write_all(self.str_buf.as_bytes()).await?;
         self.file_handle.flush().await?;
 
-        Ok(())
+        Ok(self.str_buf.len())
     }
 }
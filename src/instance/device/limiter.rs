@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::models;
+
+/// Limit on the rate of frames written to a device
+///
+/// Frames received while the device is still within its minimum interval are held, and replaced
+/// by the newer ones, so slow devices always get the latest frame instead of falling behind.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    last_write: Option<Instant>,
+    /// Latest frame received too early, if any
+    pending: Option<Vec<models::Color>>,
+    /// Buffer of the last written pending frame, reused for the next one
+    spare: Vec<models::Color>,
+    /// Number of frames replaced by a newer one before being written
+    skipped: u64,
+}
+
+impl RateLimiter {
+    pub fn new(max_fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_fps,
+            last_write: None,
+            pending: None,
+            spare: Vec::new(),
+            skipped: 0,
+        }
+    }
+
+    fn next_write(&self) -> Option<Instant> {
+        self.last_write.map(|last_write| last_write + self.interval)
+    }
+
+    /// Check if a frame received at `now` can be written right away
+    ///
+    /// If it can, it is accounted as written. Otherwise, it should be given to
+    /// [RateLimiter::hold].
+    pub fn try_write(&mut self, now: Instant) -> bool {
+        if self.pending.is_none() && self.next_write().is_none_or(|next| next <= now) {
+            self.last_write = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Hold a frame until the interval elapses, replacing the frame held so far
+    pub fn hold(&mut self, led_data: &[models::Color]) {
+        let frame = match &mut self.pending {
+            Some(frame) => {
+                self.skipped += 1;
+                frame
+            }
+            None => self.pending.insert(std::mem::take(&mut self.spare)),
+        };
+
+        frame.clear();
+        frame.extend_from_slice(led_data);
+    }
+
+    /// Wait until the held frame can be written
+    pub async fn due(&self) {
+        match (&self.pending, self.next_write()) {
+            (Some(_), Some(next)) => tokio::time::sleep_until(next).await,
+            (Some(_), None) => {}
+            (None, _) => futures::future::pending().await,
+        }
+    }
+
+    /// Take the held frame, accounting it as written at `now`
+    pub fn take(&mut self, now: Instant) -> Option<Vec<models::Color>> {
+        let frame = self.pending.take()?;
+        self.last_write = Some(now);
+        Some(frame)
+    }
+
+    /// Give back the buffer of a frame returned by [RateLimiter::take]
+    pub fn recycle(&mut self, frame: Vec<models::Color>) {
+        self.spare = frame;
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(10);
+        let start = Instant::now();
+        let frame = |v: u8| vec![models::Color::new(v, v, v)];
+
+        assert!(limiter.try_write(start));
+
+        // Frames within the interval are coalesced into the latest one
+        assert!(!limiter.try_write(start + Duration::from_millis(10)));
+        limiter.hold(&frame(1));
+        assert!(!limiter.try_write(start + Duration::from_millis(20)));
+        limiter.hold(&frame(2));
+        assert_eq!(limiter.skipped(), 1);
+
+        // The held frame goes before any newer one
+        assert!(!limiter.try_write(start + Duration::from_millis(150)));
+        limiter.hold(&frame(3));
+        assert_eq!(
+            limiter.take(start + Duration::from_millis(150)),
+            Some(frame(3))
+        );
+        assert_eq!(limiter.skipped(), 2);
+
+        assert!(!limiter.try_write(start + Duration::from_millis(200)));
+        assert!(limiter.try_write(start + Duration::from_millis(250)));
+    }
+}
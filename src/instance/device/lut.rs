@@ -0,0 +1,132 @@
+//! Lookup tables applied to the output levels of a device
+//!
+//! These linearize strips whose dimming curve doesn't follow the one assumed by the color
+//! adjustments, independently of the perceptual gamma of the channel adjustments.
+
+use super::DeviceError;
+use crate::models::{self, DeviceLut};
+
+/// Tables of a device, resolved to the 8-bit levels of the output
+#[derive(Debug)]
+pub struct Lut {
+    tables: [[u8; 256]; 3],
+}
+
+impl Lut {
+    pub async fn new(config: &DeviceLut) -> Result<Self, DeviceError> {
+        match &config.file {
+            Some(path) => {
+                let text = tokio::fs::read_to_string(path).await?;
+                let [red, green, blue] = parse(&text)?;
+                Ok(Self::from_tables(&red, &green, &blue))
+            }
+            None => Ok(Self::from_tables(&config.red, &config.green, &config.blue)),
+        }
+    }
+
+    fn from_tables(red: &[u16], green: &[u16], blue: &[u16]) -> Self {
+        let resolve = |table: &[u16]| {
+            let mut levels = [0u8; 256];
+            for (level, output) in levels.iter_mut().enumerate() {
+                *output = sample(table, level as u8);
+            }
+            levels
+        };
+
+        Self {
+            tables: [resolve(red), resolve(green), resolve(blue)],
+        }
+    }
+
+    pub fn apply(&self, led_data: &mut [models::Color]) {
+        let [red, green, blue] = &self.tables;
+
+        for led in led_data {
+            let (r, g, b) = led.into_components();
+            *led = models::Color::new(red[r as usize], green[g as usize], blue[b as usize]);
+        }
+    }
+}
+
+/// Output level of `table` for the 8-bit `level`
+fn sample(table: &[u16], level: u8) -> u8 {
+    match table.len() {
+        0 => level,
+        256 => table[level as usize] as u8,
+        // 16-bit tables, rounded to the nearest 8-bit level
+        _ => ((table[level as usize * 257] as u32 + 128) / 257) as u8,
+    }
+}
+
+/// Parse the tables of a LUT file
+///
+/// Each line holds the entry of either all the channels, or of the red, green and blue channels,
+/// separated by spaces or commas. Empty lines and lines starting with `#` are ignored.
+fn parse(text: &str) -> Result<[Vec<u16>; 3], DeviceError> {
+    let mut tables = [Vec::new(), Vec::new(), Vec::new()];
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let levels = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|level| !level.is_empty())
+            .map(|level| level.parse::<u16>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| DeviceError::InvalidLut(format!("line {}: {}", i + 1, err)))?;
+
+        match levels[..] {
+            [level] => tables.iter_mut().for_each(|table| table.push(level)),
+            [r, g, b] => {
+                tables[0].push(r);
+                tables[1].push(g);
+                tables[2].push(b);
+            }
+            _ => {
+                return Err(DeviceError::InvalidLut(format!(
+                    "line {}: expected 1 or 3 levels, got {}",
+                    i + 1,
+                    levels.len()
+                )))
+            }
+        }
+    }
+
+    for table in &tables {
+        DeviceLut::validate_table(table).map_err(|err| {
+            DeviceError::InvalidLut(format!("{} entries: {}", table.len(), err.code))
+        })?;
+    }
+
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lut() {
+        let red: Vec<u16> = (0..256).map(|level| level / 2).collect();
+        let green: Vec<u16> = (0..65536).map(|level| (65535 - level) as u16).collect();
+        let lut = Lut::from_tables(&red, &green, &[]);
+
+        let mut led_data = vec![models::Color::new(200, 1, 3)];
+        lut.apply(&mut led_data);
+        assert_eq!(led_data, [models::Color::new(100, 254, 3)]);
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# level\n0\n\n".to_owned() + &"1, 2 3\n".repeat(255);
+        let [red, green, blue] = parse(&text).unwrap();
+        assert_eq!((red[0], green[0], blue[0]), (0, 0, 0));
+        assert_eq!((red[255], green[255], blue[255]), (1, 2, 3));
+
+        assert!(parse("1 2\n").is_err());
+        assert!(parse("0\n1\n").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt::Write;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::models;
+
+use super::{common::*, DeviceError, WhiteLevels};
+
+pub type PipeDevice = Rewriter<PipeDeviceImpl>;
+
+pub struct PipeDeviceImpl {
+    leds: Vec<models::Color>,
+    white: Vec<WhiteLevels>,
+    /// Number of white channels written after the RGB ones
+    white_channels: usize,
+    format: models::PipeFormat,
+    output: String,
+    handle: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    frame: u64,
+    buf: Vec<u8>,
+    str_buf: String,
+}
+
+impl PipeDeviceImpl {
+    async fn handle(&mut self) -> Result<&mut (dyn AsyncWrite + Send + Unpin), DeviceError> {
+        if self.handle.is_none() {
+            // Opened on first write, since opening a named pipe blocks until a reader shows up
+            self.handle = Some(if self.output == "-" {
+                Box::new(tokio::io::stdout())
+            } else {
+                Box::new(
+                    tokio::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&self.output)
+                        .await?,
+                )
+            });
+        }
+
+        Ok(self.handle.as_deref_mut().unwrap())
+    }
+
+    fn encode(&mut self) -> Result<(), DeviceError> {
+        self.buf.clear();
+
+        match self.format {
+            models::PipeFormat::Binary => {
+                let channel_count = 3 + self.white_channels;
+                self.buf
+                    .extend_from_slice(&((self.leds.len() * channel_count) as u32).to_be_bytes());
+
+                for (i, led) in self.leds.iter().enumerate() {
+                    let white = self.white.get(i).copied().unwrap_or_default();
+                    self.buf.extend_from_slice(
+                        &[led.red, led.green, led.blue, white.white, white.warm][..channel_count],
+                    );
+                }
+            }
+
+            models::PipeFormat::Json => {
+                self.str_buf.clear();
+
+                write!(self.str_buf, "{{\"frame\":{},\"leds\":[", self.frame)?;
+                for (i, led) in self.leds.iter().enumerate() {
+                    if i > 0 {
+                        self.str_buf.push(',');
+                    }
+
+                    write!(self.str_buf, "[{},{},{}", led.red, led.green, led.blue)?;
+
+                    let white = self.white.get(i).copied().unwrap_or_default();
+                    for level in [white.white, white.warm].iter().take(self.white_channels) {
+                        write!(self.str_buf, ",{}", level)?;
+                    }
+
+                    self.str_buf.push(']');
+                }
+                writeln!(self.str_buf, "]}}")?;
+
+                self.buf.extend_from_slice(self.str_buf.as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WritingDevice for PipeDeviceImpl {
+    type Config = models::Pipe;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        Ok(Self {
+            leds: vec![Default::default(); config.hardware_led_count as _],
+            white: Vec::new(),
+            white_channels: config
+                .white_output
+                .as_ref()
+                .map_or(0, |white| white.channels.count()),
+            format: config.format,
+            output: config.output.clone(),
+            handle: None,
+            frame: 0,
+            buf: Vec::new(),
+            str_buf: String::new(),
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        _config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        self.leds.copy_from_slice(led_data);
+        Ok(())
+    }
+
+    fn set_white_data(&mut self, _config: &Self::Config, white_data: &[WhiteLevels]) {
+        self.white.clear();
+        self.white.extend_from_slice(white_data);
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        self.encode()?;
+        self.frame += 1;
+
+        let buf = std::mem::take(&mut self.buf);
+        let result = async {
+            let handle = self.handle().await?;
+            handle.write_all(&buf).await?;
+            handle.flush().await?;
+            Ok::<_, DeviceError>(buf.len())
+        }
+        .await;
+        self.buf = buf;
+
+        if result.is_err() {
+            // Reopen the output on the next write, e.g. when the reader of a named pipe went away
+            self.handle = None;
+        }
+
+        result
+    }
+}
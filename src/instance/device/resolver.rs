@@ -0,0 +1,249 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use tokio::net::UdpSocket;
+
+use super::DeviceError;
+
+/// Interval after which a hostname is resolved again, in case its address changed (DHCP lease...)
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of consecutive send failures after which a hostname is resolved again
+const MAX_FAILURES: u32 = 3;
+
+const MDNS_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+const MDNS_TIMEOUT: Duration = Duration::from_secs(1);
+const DNS_TYPE_A: u16 = 1;
+const DNS_CLASS_IN: u16 = 1;
+
+/// Address of a network device configured by hostname, resolved again periodically
+#[derive(Debug)]
+pub struct HostResolver {
+    host: String,
+    port: u16,
+    address: Option<SocketAddr>,
+    resolved_at: Option<Instant>,
+    failures: u32,
+}
+
+impl HostResolver {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            address: None,
+            resolved_at: None,
+            failures: 0,
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn is_due(&self) -> bool {
+        self.address.is_none()
+            || self.failures >= MAX_FAILURES
+            || self
+                .resolved_at
+                .is_none_or(|resolved_at| resolved_at.elapsed() >= REFRESH_INTERVAL)
+    }
+
+    /// Get the address of the device, resolving the hostname if it never was, if the last
+    /// resolution is too old or if sends keep failing
+    ///
+    /// If the hostname can't be resolved again, the previous address is kept.
+    pub async fn address(&mut self) -> Result<SocketAddr, DeviceError> {
+        if !self.is_due() {
+            return Ok(self.address.unwrap());
+        }
+
+        self.resolved_at = Some(Instant::now());
+        self.failures = 0;
+
+        match resolve(&self.host, self.port).await {
+            Ok(address) => {
+                if let Some(previous) = self.address.filter(|previous| *previous != address) {
+                    info!(host = %self.host, previous = %previous, address = %address, "device address changed");
+                }
+
+                self.address = Some(address);
+                Ok(address)
+            }
+            Err(error) => match self.address {
+                Some(previous) => {
+                    warn!(host = %self.host, error = %error, "cannot resolve device address, keeping the previous one");
+                    Ok(previous)
+                }
+                None => Err(error),
+            },
+        }
+    }
+
+    /// Report that sending data to the current address failed
+    pub fn report_failure(&mut self) {
+        self.failures += 1;
+    }
+
+    /// Report that sending data to the current address succeeded
+    pub fn report_success(&mut self) {
+        self.failures = 0;
+    }
+}
+
+async fn resolve(host: &str, port: u16) -> Result<SocketAddr, DeviceError> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+
+    // .local names are answered by the devices themselves over multicast DNS, the system
+    // resolver only knows about them if an mDNS plugin is installed
+    if host.to_ascii_lowercase().ends_with(".local") {
+        match tokio::time::timeout(MDNS_TIMEOUT, resolve_mdns(host)).await {
+            Ok(Ok(ip)) => return Ok(SocketAddr::new(ip.into(), port)),
+            Ok(Err(error)) => {
+                debug!(host = %host, error = %error, "mDNS query failed, using the system resolver")
+            }
+            Err(_) => debug!(host = %host, "no mDNS answer, using the system resolver"),
+        }
+    }
+
+    Ok(tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "host not found"))?)
+}
+
+/// Send a one-shot mDNS query for the IPv4 address of `host` and wait for the answer
+async fn resolve_mdns(host: &str) -> Result<Ipv4Addr, DeviceError> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.send_to(&mdns_query(host), MDNS_ADDRESS).await?;
+
+    let mut buf = vec![0; 9000];
+    loop {
+        let (len, _) = socket.recv_from(&mut buf).await?;
+
+        if let Some(ip) = parse_mdns_answer(&buf[..len], host) {
+            return Ok(ip);
+        }
+    }
+}
+
+/// Build an A query for `host`, asking for a unicast answer
+fn mdns_query(host: &str) -> Vec<u8> {
+    // Header: id, flags, 1 question, no answer, authority or additional records
+    let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    for label in host.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+    // Top bit of the class requests a unicast response
+    query.extend_from_slice(&(DNS_CLASS_IN | 0x8000).to_be_bytes());
+    query
+}
+
+/// Find the A record for `host` in an mDNS response
+fn parse_mdns_answer(packet: &[u8], host: &str) -> Option<Ipv4Addr> {
+    let read_u16 = |offset: usize| {
+        packet
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    // Only responses are relevant
+    if packet.get(2)? & 0x80 == 0 {
+        return None;
+    }
+
+    let questions = read_u16(4)?;
+    let records = read_u16(6)? as usize + read_u16(8)? as usize + read_u16(10)? as usize;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        let (_, end) = read_name(packet, offset)?;
+        offset = end + 4;
+    }
+
+    let host = host.trim_end_matches('.');
+    for _ in 0..records {
+        let (name, end) = read_name(packet, offset)?;
+        let ty = read_u16(end)?;
+        let class = read_u16(end + 2)? & 0x7FFF;
+        let len = read_u16(end + 8)? as usize;
+        let data = packet.get(end + 10..end + 10 + len)?;
+        offset = end + 10 + len;
+
+        if ty == DNS_TYPE_A && class == DNS_CLASS_IN && len == 4 && name.eq_ignore_ascii_case(host)
+        {
+            return Some(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+        }
+    }
+
+    None
+}
+
+/// Read a possibly compressed name
+///
+/// # Returns
+///
+/// The name and the offset following it
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // Bound the number of jumps to avoid looping on malicious packets
+    for _ in 0..128 {
+        let len = *packet.get(offset)? as usize;
+
+        if len & 0xC0 == 0xC0 {
+            let pointer = ((len & 0x3F) << 8) | *packet.get(offset + 1)? as usize;
+            end.get_or_insert(offset + 2);
+            offset = pointer;
+        } else if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(offset + 1)));
+        } else {
+            let label = packet.get(offset + 1..offset + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + len;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mdns_answer() {
+        let query = mdns_query("wled-livingroom.local");
+
+        // Response echoing the question, with the answer name compressed as a pointer to it
+        let mut response = query.clone();
+        response[2] = 0x84;
+        response[7] = 1;
+        response.extend_from_slice(&[0xC0, 12]);
+        response.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+        response.extend_from_slice(&(DNS_CLASS_IN | 0x8000).to_be_bytes());
+        response.extend_from_slice(&120u32.to_be_bytes());
+        response.extend_from_slice(&[0, 4, 192, 168, 1, 42]);
+
+        assert_eq!(
+            parse_mdns_answer(&response, "WLED-livingroom.local"),
+            Some(Ipv4Addr::new(192, 168, 1, 42))
+        );
+        assert_eq!(parse_mdns_answer(&response, "other.local"), None);
+        assert_eq!(parse_mdns_answer(&query, "wled-livingroom.local"), None);
+        assert_eq!(
+            parse_mdns_answer(&response[..response.len() - 2], "wled-livingroom.local"),
+            None
+        );
+    }
+}
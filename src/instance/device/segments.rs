@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use crate::{
+    color::LedMatch,
+    models::{Color, DeviceSegment, SegmentAggregation},
+};
+
+/// Number of low bits discarded when bucketing colors for dominant aggregation
+const DOMINANT_BUCKET_SHIFT: u32 = 5;
+
+struct Segment {
+    indices: Vec<usize>,
+    aggregation: SegmentAggregation,
+}
+
+/// Maps LED data from the instance layout to the native segments of a device
+pub struct SegmentMapper {
+    segments: Vec<Segment>,
+}
+
+impl SegmentMapper {
+    pub fn new(segments: &[DeviceSegment], led_count: usize) -> Self {
+        Self {
+            segments: segments
+                .iter()
+                .map(|segment| Segment {
+                    indices: LedMatch::from(segment.leds.as_str()).indices(led_count),
+                    aggregation: segment.aggregation,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Compute the color of each segment from the layout LED data
+    ///
+    /// Segments without any LED in `led_data` are black.
+    pub fn map(&self, led_data: &[Color], output: &mut Vec<Color>) {
+        output.clear();
+        output.extend(self.segments.iter().map(|segment| {
+            let colors = segment
+                .indices
+                .iter()
+                .filter_map(|index| led_data.get(*index).copied());
+
+            match segment.aggregation {
+                SegmentAggregation::Mean => mean(colors),
+                SegmentAggregation::Dominant => dominant(colors),
+            }
+        }));
+    }
+}
+
+fn mean(colors: impl Iterator<Item = Color>) -> Color {
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+
+    for color in colors {
+        sum[0] += color.red as u32;
+        sum[1] += color.green as u32;
+        sum[2] += color.blue as u32;
+        count += 1;
+    }
+
+    if count == 0 {
+        return Color::default();
+    }
+
+    Color::new(
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+    )
+}
+
+fn dominant(colors: impl Iterator<Item = Color>) -> Color {
+    let mut buckets: HashMap<(u8, u8, u8), Vec<Color>> = HashMap::new();
+
+    for color in colors {
+        buckets
+            .entry((
+                color.red >> DOMINANT_BUCKET_SHIFT,
+                color.green >> DOMINANT_BUCKET_SHIFT,
+                color.blue >> DOMINANT_BUCKET_SHIFT,
+            ))
+            .or_default()
+            .push(color);
+    }
+
+    buckets
+        .into_values()
+        .max_by_key(Vec::len)
+        .map(|bucket| mean(bucket.into_iter()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(leds: &str, aggregation: SegmentAggregation) -> DeviceSegment {
+        DeviceSegment {
+            leds: leds.to_owned(),
+            aggregation,
+        }
+    }
+
+    #[test]
+    fn map_segments() {
+        let mapper = SegmentMapper::new(
+            &[
+                segment("0-1", SegmentAggregation::Mean),
+                segment("1-3", SegmentAggregation::Dominant),
+                segment("8-9", SegmentAggregation::Mean),
+            ],
+            4,
+        );
+
+        let led_data = [
+            Color::new(200, 0, 0),
+            Color::new(0, 0, 100),
+            Color::new(0, 0, 110),
+            Color::new(255, 255, 255),
+        ];
+
+        let mut output = Vec::new();
+        mapper.map(&led_data, &mut output);
+
+        assert_eq!(
+            output,
+            vec![
+                Color::new(100, 0, 50),
+                Color::new(0, 0, 105),
+                Color::new(0, 0, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+use super::{common::*, DeviceError};
+use crate::models;
+
+pub type AdalightDevice = Rewriter<AdalightDeviceImpl>;
+
+/// Magic bytes starting every Adalight frame
+const ADALIGHT_MAGIC: &[u8; 3] = b"Ada";
+const ADALIGHT_HEADER_SIZE: usize = 6;
+
+pub struct AdalightDeviceImpl {
+    output: String,
+    rate: u32,
+    port: Option<SerialStream>,
+    /// Error of the last write, logged when it first occurs
+    last_error: Option<String>,
+    buf: Vec<u8>,
+}
+
+impl AdalightDeviceImpl {
+    /// Open the serial port if it isn't already
+    ///
+    /// The port is closed on write errors, so unplugging the controller and plugging it back in
+    /// resumes the output on the next write.
+    fn port(&mut self) -> Result<&mut SerialStream, DeviceError> {
+        if self.port.is_none() {
+            let port = tokio_serial::new(&self.output, self.rate)
+                .open_native_async()
+                .map_err(std::io::Error::from)?;
+
+            info!(path = %self.output, rate = %self.rate, "opened serial port");
+            self.port = Some(port);
+        }
+
+        Ok(self.port.as_mut().unwrap())
+    }
+
+    async fn send(&mut self) -> Result<(), DeviceError> {
+        let buf = std::mem::take(&mut self.buf);
+
+        let result = async {
+            let port = self.port()?;
+            port.write_all(&buf).await?;
+            port.flush().await?;
+            Ok::<_, DeviceError>(())
+        }
+        .await;
+
+        self.buf = buf;
+        result
+    }
+}
+
+#[async_trait]
+impl WritingDevice for AdalightDeviceImpl {
+    type Config = models::Adalight;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        let mut this = Self {
+            output: config.output.clone(),
+            rate: config.rate,
+            port: None,
+            last_error: None,
+            buf: Vec::with_capacity(ADALIGHT_HEADER_SIZE + config.hardware_led_count as usize * 3),
+        };
+
+        // Try to open the port early
+        if let Err(error) = this.port() {
+            warn!(%error, path = %config.output, "failed to open serial port, will try again later");
+        }
+
+        Ok(this)
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        self.buf.clear();
+        self.buf.extend_from_slice(&header(led_data.len()));
+
+        for led in led_data {
+            let (r, g, b) = config.color_order.reorder_from_rgb(*led).into_components();
+            self.buf.extend_from_slice(&[r, g, b]);
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        match self.send().await {
+            Ok(()) => {
+                self.last_error = None;
+                Ok(self.buf.len())
+            }
+            Err(error) => {
+                // Reopen the port on the next write
+                self.port = None;
+
+                if self.last_error.is_none() {
+                    error!(path = %self.output, error = %error, "failed to write to serial port");
+                }
+
+                self.last_error = Some(error.to_string());
+                Ok(0)
+            }
+        }
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+}
+
+/// Adalight frame header: magic, LED count minus one (big endian) and checksum
+fn header(led_count: usize) -> [u8; ADALIGHT_HEADER_SIZE] {
+    let [hi, lo] = (led_count.saturating_sub(1) as u16).to_be_bytes();
+
+    [
+        ADALIGHT_MAGIC[0],
+        ADALIGHT_MAGIC[1],
+        ADALIGHT_MAGIC[2],
+        hi,
+        lo,
+        hi ^ lo ^ 0x55,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        assert_eq!(header(1), [b'A', b'd', b'a', 0, 0, 0x55]);
+        assert_eq!(
+            header(300),
+            [b'A', b'd', b'a', 0x01, 0x2B, 0x01 ^ 0x2B ^ 0x55]
+        );
+    }
+}
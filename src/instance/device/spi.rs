@@ -0,0 +1,142 @@
+//! SPI output of LED strip drivers
+//!
+//! Drivers write through the [embedded_hal::spi::SpiDevice] trait, so they work with any of the
+//! adapters below, selected by the `output` of the device:
+//!
+//! * a path to a Linux spidev device (e.g. `/dev/spidev0.0` on a Raspberry Pi or BeagleBone)
+//! * `ftdi`, optionally followed by a libftdi device description (e.g. `ftdi:i:0x0403:0x6014`),
+//!   for FTDI USB to SPI adapters, behind the `ftdi` cargo feature
+
+use embedded_hal::spi::{ErrorKind, SpiDevice};
+use thiserror::Error;
+
+use super::DeviceError;
+
+#[cfg(feature = "ftdi")]
+mod ftdi;
+mod spidev;
+
+#[derive(Debug, Error)]
+pub enum SpiError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "ftdi")]
+    #[error(transparent)]
+    Ftdi(#[from] ftdi::FtdiError),
+    #[error("FTDI adapters require the ftdi cargo feature")]
+    FtdiDisabled,
+    #[error("SPI {0} operations are not supported by this adapter")]
+    Unsupported(&'static str),
+}
+
+impl embedded_hal::spi::Error for SpiError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// SPI adapter, type-erased so drivers don't depend on the backend
+type SpiBus = Box<dyn SpiDevice<u8, Error = SpiError> + Send>;
+
+/// Description of the FTDI adapter if `output` designates one
+fn ftdi_description(output: &str) -> Option<&str> {
+    if output == "ftdi" {
+        Some("")
+    } else {
+        output.strip_prefix("ftdi:")
+    }
+}
+
+/// Open the adapter designated by `output`, clocking data at `rate` Hz in SPI mode 0
+fn open(output: &str, rate: u32) -> Result<SpiBus, SpiError> {
+    match ftdi_description(output) {
+        #[cfg(feature = "ftdi")]
+        Some(description) => Ok(Box::new(ftdi::FtdiSpi::open(description, rate)?)),
+        #[cfg(not(feature = "ftdi"))]
+        Some(_) => Err(SpiError::FtdiDisabled),
+        None => Ok(Box::new(spidev::SpidevSpi::open(output, rate)?)),
+    }
+}
+
+/// SPI device of a LED strip, opened again on the next write if it isn't available
+pub struct SpiOutput {
+    output: String,
+    rate: u32,
+    /// Opened adapter, moved to a blocking task while writing
+    bus: Option<SpiBus>,
+    /// Error initializing the device, logged when it first occurs
+    last_error: Option<String>,
+}
+
+impl SpiOutput {
+    pub fn new(output: &str, rate: i32) -> Self {
+        let mut this = Self {
+            output: output.to_owned(),
+            rate: rate as _,
+            bus: None,
+            last_error: None,
+        };
+
+        // Try to open the device early
+        match this.try_init() {
+            Ok(bus) => this.bus = Some(bus),
+            Err(error) => {
+                warn!(%error, %output, "failed to initialize SPI device, will try again later")
+            }
+        }
+
+        this
+    }
+
+    fn try_init(&mut self) -> Result<SpiBus, SpiError> {
+        match self.bus.take() {
+            Some(bus) => Ok(bus),
+            None => {
+                let bus = open(&self.output, self.rate)?;
+                info!(output = %self.output, "initialized SPI device");
+                Ok(bus)
+            }
+        }
+    }
+
+    /// Write `buf` to the device
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written, 0 if the device couldn't be opened.
+    pub async fn write(&mut self, buf: &mut Vec<u8>) -> Result<usize, DeviceError> {
+        match self.try_init() {
+            Ok(mut bus) => {
+                self.last_error = None;
+
+                // The transfer blocks for as long as the data takes to be clocked out, so it's
+                // performed outside of the async runtime workers
+                let data = std::mem::take(buf);
+                let (bus, data, result) = tokio::task::spawn_blocking(move || {
+                    let result = bus.write(&data);
+                    (bus, data, result)
+                })
+                .await
+                .expect("SPI transfer panicked");
+
+                self.bus = Some(bus);
+                *buf = data;
+                result?;
+
+                Ok(buf.len())
+            }
+            Err(err) => {
+                if self.last_error.is_none() {
+                    error!(error = %err, "failed to initialize SPI device");
+                }
+
+                self.last_error = Some(err.to_string());
+                Ok(0)
+            }
+        }
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+}
@@ -0,0 +1,258 @@
+//! USB to SPI adapters built on the MPSSE engine of FTDI chips (FT232H, FT2232H, FT4232H)
+//!
+//! libftdi1 is not linked at build time, it is loaded when the device starts. The adapter clocks
+//! data out in SPI mode 0 on ADBUS0 (SCK) and ADBUS1 (MOSI), with an active-low chip select on
+//! ADBUS3. Only writes are supported, which is all LED strips need.
+
+use std::ffi::{c_char, c_int, c_uchar, c_void, CStr, CString};
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use libloading::Library;
+use thiserror::Error;
+
+use super::SpiError;
+
+#[derive(Debug, Error)]
+pub enum FtdiError {
+    #[error("cannot load libftdi1: {0}")]
+    Load(#[from] libloading::Error),
+    #[error("invalid FTDI device description")]
+    InvalidDescription,
+    #[error("cannot allocate a libftdi1 context")]
+    Context,
+    #[error("libftdi1 error: {0}")]
+    Library(String),
+}
+
+/// First FT232H, used when no device is described
+const DEFAULT_DESCRIPTION: &str = "i:0x0403:0x6014";
+
+// Definitions from ftdi.h
+
+const BITMODE_RESET: c_uchar = 0x00;
+const BITMODE_MPSSE: c_uchar = 0x02;
+
+type NewFn = unsafe extern "C" fn() -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+type OpenStringFn = unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int;
+type CloseFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type SetBitmodeFn = unsafe extern "C" fn(*mut c_void, c_uchar, c_uchar) -> c_int;
+type SetLatencyTimerFn = unsafe extern "C" fn(*mut c_void, c_uchar) -> c_int;
+type WriteDataFn = unsafe extern "C" fn(*mut c_void, *const c_uchar, c_int) -> c_int;
+type GetErrorStringFn = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+
+// MPSSE commands
+
+/// Clock bytes out on the falling edge, MSB first, followed by the length minus one
+const WRITE_BYTES_NEG_MSB: u8 = 0x11;
+/// Set the value and direction of the low byte pins
+const SET_BITS_LOW: u8 = 0x80;
+const LOOPBACK_END: u8 = 0x85;
+/// Set the clock divisor, for a 60 MHz base clock
+const TCK_DIVISOR: u8 = 0x86;
+const DISABLE_CLK_DIV5: u8 = 0x8A;
+const DISABLE_3_PHASE_CLK: u8 = 0x8D;
+const DISABLE_ADAPTIVE_CLK: u8 = 0x97;
+
+/// Maximum length of a single write command
+const MAX_WRITE_BYTES: usize = 65536;
+
+const PIN_SCK: u8 = 0x01;
+const PIN_MOSI: u8 = 0x02;
+const PIN_CS: u8 = 0x08;
+const PIN_OUTPUTS: u8 = PIN_SCK | PIN_MOSI | PIN_CS;
+
+pub struct FtdiSpi {
+    free: FreeFn,
+    close: CloseFn,
+    write_data: WriteDataFn,
+    get_error_string: GetErrorStringFn,
+    context: *mut c_void,
+    /// true once the USB device is open, and has to be closed
+    opened: bool,
+    /// Commands of the current transaction
+    commands: Vec<u8>,
+    // Must outlive the function pointers above
+    _library: Library,
+}
+
+// Safety: the libftdi1 context is only accessed by the owner of the adapter
+unsafe impl Send for FtdiSpi {}
+
+impl FtdiSpi {
+    const LIBRARY_NAME: &'static str = "libftdi1.so.2";
+
+    pub fn open(description: &str, rate: u32) -> Result<Self, FtdiError> {
+        let description = CString::new(if description.is_empty() {
+            DEFAULT_DESCRIPTION
+        } else {
+            description
+        })
+        .map_err(|_| FtdiError::InvalidDescription)?;
+
+        // Safety: loading the library has no initialization side-effects
+        let library = unsafe { Library::new(Self::LIBRARY_NAME)? };
+        debug!(path = %Self::LIBRARY_NAME, "loaded libftdi1");
+
+        // Safety: the symbol types match the declarations in ftdi.h
+        let (
+            new,
+            free,
+            open_string,
+            close,
+            set_bitmode,
+            set_latency_timer,
+            write_data,
+            get_error_string,
+        ) = unsafe {
+            (
+                *library.get::<NewFn>(b"ftdi_new\0")?,
+                *library.get::<FreeFn>(b"ftdi_free\0")?,
+                *library.get::<OpenStringFn>(b"ftdi_usb_open_string\0")?,
+                *library.get::<CloseFn>(b"ftdi_usb_close\0")?,
+                *library.get::<SetBitmodeFn>(b"ftdi_set_bitmode\0")?,
+                *library.get::<SetLatencyTimerFn>(b"ftdi_set_latency_timer\0")?,
+                *library.get::<WriteDataFn>(b"ftdi_write_data\0")?,
+                *library.get::<GetErrorStringFn>(b"ftdi_get_error_string\0")?,
+            )
+        };
+
+        // Safety: allocating a context has no preconditions
+        let context = unsafe { new() };
+        if context.is_null() {
+            return Err(FtdiError::Context);
+        }
+
+        // From now on, dropping the adapter releases the context
+        let mut this = Self {
+            free,
+            close,
+            write_data,
+            get_error_string,
+            context,
+            opened: false,
+            commands: Vec::new(),
+            _library: library,
+        };
+
+        // Safety: the context is valid, and the description is a nul-terminated string
+        this.check(unsafe { open_string(context, description.as_ptr()) })?;
+        this.opened = true;
+
+        // Safety: the context is valid and the device is open
+        this.check(unsafe { set_bitmode(context, 0, BITMODE_RESET) })?;
+        this.check(unsafe { set_bitmode(context, 0, BITMODE_MPSSE) })?;
+        this.check(unsafe { set_latency_timer(context, 1) })?;
+
+        // SCK = 60 MHz / ((1 + divisor) * 2), rounded down to at most the requested rate
+        let divisor = 30_000_000u32
+            .div_ceil(rate.max(1))
+            .saturating_sub(1)
+            .min(u16::MAX as u32) as u16;
+        let [divisor_low, divisor_high] = divisor.to_le_bytes();
+
+        this.commands.extend_from_slice(&[
+            DISABLE_CLK_DIV5,
+            DISABLE_ADAPTIVE_CLK,
+            DISABLE_3_PHASE_CLK,
+            LOOPBACK_END,
+            TCK_DIVISOR,
+            divisor_low,
+            divisor_high,
+            SET_BITS_LOW,
+            PIN_CS,
+            PIN_OUTPUTS,
+        ]);
+        this.send()?;
+
+        Ok(this)
+    }
+
+    fn check(&self, result: c_int) -> Result<c_int, FtdiError> {
+        if result >= 0 {
+            return Ok(result);
+        }
+
+        // Safety: the context is valid, and holds the message of the last error
+        let message = unsafe { CStr::from_ptr((self.get_error_string)(self.context)) };
+        Err(FtdiError::Library(message.to_string_lossy().into_owned()))
+    }
+
+    /// Send the pending commands to the adapter
+    fn send(&mut self) -> Result<(), FtdiError> {
+        // Safety: the context is valid and the device is open
+        let written = self.check(unsafe {
+            (self.write_data)(
+                self.context,
+                self.commands.as_ptr(),
+                self.commands.len() as c_int,
+            )
+        })?;
+
+        let expected = self.commands.len();
+        self.commands.clear();
+
+        if written as usize != expected {
+            return Err(FtdiError::Library(format!(
+                "short write, {} of {} bytes",
+                written, expected
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FtdiSpi {
+    fn drop(&mut self) {
+        // Safety: the context is valid, and isn't used afterwards
+        unsafe {
+            if self.opened {
+                (self.close)(self.context);
+            }
+
+            (self.free)(self.context);
+        }
+    }
+}
+
+impl ErrorType for FtdiSpi {
+    type Error = SpiError;
+}
+
+impl SpiDevice for FtdiSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SpiError> {
+        // Drop the commands of a failed transaction
+        self.commands.clear();
+        self.commands
+            .extend_from_slice(&[SET_BITS_LOW, 0, PIN_OUTPUTS]);
+
+        for operation in operations {
+            match operation {
+                Operation::Write(buf) => {
+                    for chunk in buf.chunks(MAX_WRITE_BYTES) {
+                        let [length_low, length_high] = ((chunk.len() - 1) as u16).to_le_bytes();
+                        self.commands.extend_from_slice(&[
+                            WRITE_BYTES_NEG_MSB,
+                            length_low,
+                            length_high,
+                        ]);
+                        self.commands.extend_from_slice(chunk);
+                    }
+                }
+                Operation::DelayNs(ns) => {
+                    self.send()?;
+                    std::thread::sleep(std::time::Duration::from_nanos(*ns as u64));
+                }
+                Operation::Read(_) => return Err(SpiError::Unsupported("read")),
+                Operation::Transfer(..) | Operation::TransferInPlace(_) => {
+                    return Err(SpiError::Unsupported("transfer"))
+                }
+            }
+        }
+
+        self.commands
+            .extend_from_slice(&[SET_BITS_LOW, PIN_CS, PIN_OUTPUTS]);
+        Ok(self.send()?)
+    }
+}
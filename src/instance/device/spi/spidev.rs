@@ -0,0 +1,59 @@
+//! SPI controllers exposed by the Linux spidev driver
+
+use ::spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+
+use super::SpiError;
+
+pub struct SpidevSpi(Spidev);
+
+impl SpidevSpi {
+    pub fn open(path: &str, rate: u32) -> Result<Self, SpiError> {
+        let mut dev = Spidev::open(path)?;
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(rate)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build();
+        dev.configure(&options)?;
+
+        Ok(Self(dev))
+    }
+}
+
+impl ErrorType for SpidevSpi {
+    type Error = SpiError;
+}
+
+impl SpiDevice for SpidevSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SpiError> {
+        // spidev reads and writes from separate buffers, in-place transfers send a copy of theirs
+        let sent: Vec<Vec<u8>> = operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::TransferInPlace(buf) => buf.to_vec(),
+                _ => Vec::new(),
+            })
+            .collect();
+
+        let mut transfers = operations
+            .iter_mut()
+            .zip(&sent)
+            .map(|(operation, sent)| match operation {
+                Operation::Read(buf) => Ok(SpidevTransfer::read(buf)),
+                Operation::Write(buf) => Ok(SpidevTransfer::write(buf)),
+                Operation::Transfer(read, write) if read.len() == write.len() => {
+                    Ok(SpidevTransfer::read_write(write, read))
+                }
+                Operation::Transfer(..) => Err(SpiError::Unsupported("uneven transfer")),
+                Operation::TransferInPlace(buf) => Ok(SpidevTransfer::read_write(sent, buf)),
+                Operation::DelayNs(ns) => Ok(SpidevTransfer::delay(
+                    ns.div_ceil(1000).min(u16::MAX as u32) as u16,
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // All transfers are performed with the chip select asserted
+        Ok(self.0.transfer_multiple(&mut transfers)?)
+    }
+}
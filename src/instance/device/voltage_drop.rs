@@ -0,0 +1,232 @@
+//! Compensation of the voltage drop along long strips
+//!
+//! The strip is modeled as a chain of resistors between its LEDs, fed by wires at the injection
+//! points. The voltage at each LED is solved from the current the frame draws, then LEDs are
+//! dimmed so their lit channels keep the same balance at that voltage.
+
+use crate::models::{Color, VoltageDrop};
+
+/// Fractions of the supply voltage under which a channel starts dimming, and at which it is off
+///
+/// Approximate values for WS281x strips: red LEDs have a lower forward voltage than green and
+/// blue ones, which is why the far end of a strip turns orange.
+const CHANNEL_THRESHOLDS: [(f64, f64); 3] = [(0.70, 0.40), (0.84, 0.56), (0.84, 0.56)];
+
+/// Current drawn by the driver of a LED regardless of its color, in milliamperes
+const QUIESCENT_CURRENT: f64 = 1.0;
+
+/// Conductance of connections without resistance, in siemens
+const MAX_CONDUCTANCE: f64 = 1e6;
+
+/// Number of times the voltages are solved
+///
+/// Starved channels draw less current, so the voltages are refined from the current drawn at the
+/// previous estimate, averaging the estimates so they don't oscillate.
+const ITERATIONS: usize = 8;
+
+pub struct VoltageDropCompensator {
+    config: VoltageDrop,
+    voltages: Vec<f64>,
+    /// Current drawn by each LED, in amperes
+    currents: Vec<f64>,
+    /// Scratch buffers of the tridiagonal solver
+    upper: Vec<f64>,
+    rhs: Vec<f64>,
+}
+
+impl VoltageDropCompensator {
+    pub fn new(config: VoltageDrop) -> Self {
+        Self {
+            config,
+            voltages: Vec::new(),
+            currents: Vec::new(),
+            upper: Vec::new(),
+            rhs: Vec::new(),
+        }
+    }
+
+    /// Dim the LEDs of `led_data` to compensate for the voltage drop
+    pub fn apply(&mut self, led_data: &mut [Color]) {
+        if led_data.is_empty() {
+            return;
+        }
+
+        let supply = self.config.voltage as f64;
+        let channel_current = self.config.channel_current as f64;
+
+        self.voltages.clear();
+        self.voltages.resize(led_data.len(), supply);
+        self.currents.resize(led_data.len(), 0.);
+
+        for _ in 0..ITERATIONS {
+            for ((current, led), voltage) in self
+                .currents
+                .iter_mut()
+                .zip(led_data.iter())
+                .zip(&self.voltages)
+            {
+                let factors = channel_factors(*voltage, supply);
+                let output = compensate(*led, factors);
+                let drawn: f64 = [output.red, output.green, output.blue]
+                    .iter()
+                    .zip(factors)
+                    .map(|(component, factor)| *component as f64 / 255. * factor)
+                    .sum();
+
+                *current = (QUIESCENT_CURRENT + channel_current * drawn) / 1000.;
+            }
+
+            self.solve_voltages();
+        }
+
+        for (led, voltage) in led_data.iter_mut().zip(&self.voltages) {
+            *led = compensate(*led, channel_factors(*voltage, supply));
+        }
+    }
+
+    /// Refine the voltage at each LED from the current it draws
+    fn solve_voltages(&mut self) {
+        let n = self.currents.len();
+        let supply = self.config.voltage as f64;
+
+        let conductance = |resistance: f64| {
+            if resistance > 0. {
+                (1. / resistance).min(MAX_CONDUCTANCE)
+            } else {
+                MAX_CONDUCTANCE
+            }
+        };
+
+        // Resistance of the strip between two LEDs, and of the wires to an injection point
+        let segment =
+            conductance(self.config.strip_resistance as f64 * self.config.length as f64 / n as f64);
+        let feed = conductance(
+            2. * self.config.wire_gauge.resistance() as f64 * self.config.wire_length as f64,
+        );
+
+        let mut feeds = vec![0.; n];
+        for led in &self.config.injection_points {
+            if let Some(feeds) = feeds.get_mut(*led as usize) {
+                *feeds += feed;
+            }
+        }
+
+        // Without a valid injection point, the strip is fed from its start
+        if feeds.iter().all(|feed| *feed == 0.) {
+            feeds[0] = feed;
+        }
+
+        // Nodal analysis: each LED draws its current from its neighbours and injection point,
+        // which is a tridiagonal system solved with the Thomas algorithm
+        self.upper.resize(n, 0.);
+        self.rhs.resize(n, 0.);
+
+        for (i, current) in self.currents.iter().enumerate() {
+            let lower = if i > 0 { segment } else { 0. };
+            let upper = if i + 1 < n { segment } else { 0. };
+            let diagonal = lower + upper + feeds[i];
+            let rhs = feeds[i] * supply - current;
+
+            let (previous_upper, previous_rhs) = if i > 0 {
+                (self.upper[i - 1], self.rhs[i - 1])
+            } else {
+                (0., 0.)
+            };
+
+            let pivot = diagonal + lower * previous_upper;
+            self.upper[i] = -upper / pivot;
+            self.rhs[i] = (rhs + lower * previous_rhs) / pivot;
+        }
+
+        // Back substitution, averaged with the previous estimate
+        let mut next = 0.;
+        for i in (0..n).rev() {
+            let solved = self.rhs[i] - self.upper[i] * next;
+            next = solved;
+            self.voltages[i] = (self.voltages[i] + solved) / 2.;
+        }
+    }
+}
+
+/// Relative brightness of each channel at `voltage`
+fn channel_factors(voltage: f64, supply: f64) -> [f64; 3] {
+    let mut factors = [0.; 3];
+
+    for (factor, (full, off)) in factors.iter_mut().zip(CHANNEL_THRESHOLDS) {
+        *factor = ((voltage - off * supply) / ((full - off) * supply)).clamp(0., 1.);
+    }
+
+    factors
+}
+
+/// Dim the channels of `color` so they are displayed with the same balance
+///
+/// Only lit channels are balanced: a red LED doesn't need to be dimmed because its blue channel
+/// would be weaker.
+fn compensate(color: Color, factors: [f64; 3]) -> Color {
+    let components = [color.red, color.green, color.blue];
+
+    let weakest = components
+        .iter()
+        .zip(factors)
+        .filter(|(component, _)| **component > 0)
+        .map(|(_, factor)| factor)
+        .fold(1f64, f64::min);
+
+    let scale = |i: usize| {
+        if factors[i] > 0. {
+            (components[i] as f64 * weakest / factors[i]).round() as u8
+        } else {
+            0
+        }
+    };
+
+    Color::new(scale(0), scale(1), scale(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(injection_points: Vec<u32>) -> VoltageDrop {
+        VoltageDrop {
+            length: 5.0,
+            voltage: 5.0,
+            strip_resistance: 0.06,
+            channel_current: 20.0,
+            injection_points,
+            wire_gauge: Default::default(),
+            wire_length: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_voltage_drop() {
+        let white = Color::new(255, 255, 255);
+
+        let mut compensator = VoltageDropCompensator::new(strip(vec![0]));
+        let mut led_data = vec![white; 300];
+        compensator.apply(&mut led_data);
+
+        // The voltage decreases along the strip, the far end being dimmed
+        assert!(compensator.voltages.windows(2).all(|v| v[0] >= v[1]));
+        assert_eq!(led_data[0], white);
+        assert!(led_data[299].red < led_data[150].red);
+        assert!(led_data[299].red < 255);
+
+        // Feeding both ends halves the distance to the farthest LED
+        let mut compensator = VoltageDropCompensator::new(strip(vec![0, 299]));
+        let mut both_ends = vec![white; 300];
+        compensator.apply(&mut both_ends);
+        assert_eq!(both_ends[299], white);
+        assert!(both_ends[150].red >= led_data[299].red);
+
+        // Red needs less voltage than green and blue, so a red LED isn't dimmed for them
+        let red = Color::new(255, 0, 0);
+        assert_eq!(compensate(red, channel_factors(4.0, 5.0)), red);
+        assert_eq!(
+            compensate(white, channel_factors(4.0, 5.0)),
+            Color::new(219, 255, 255)
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Derivation of the white channels of RGBW and RGBCW LEDs
+//!
+//! Each white LED is modeled by the RGB color it matches at full brightness. The largest amount
+//! of that color which fits in a LED's color is moved to the white channel, and the RGB channels
+//! only emit what is left.
+
+use crate::{
+    color::kelvin_to_rgb16,
+    models::{Color, WhiteAlgorithm, WhiteChannels, WhiteOutput},
+};
+
+/// Levels of the white channels of a LED
+///
+/// RGBW LEDs only use `white`, RGBCW LEDs use it for their cold white channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WhiteLevels {
+    pub white: u8,
+    pub warm: u8,
+}
+
+pub struct WhiteConverter {
+    channels: WhiteChannels,
+    /// Even split of the white part between the cold and warm LEDs of RGBCW strips
+    split: bool,
+    /// RGB colors of the white (or cold white) and warm white LEDs, normalized to 1
+    white: [f32; 3],
+    warm: [f32; 3],
+}
+
+impl WhiteConverter {
+    pub fn new(config: &WhiteOutput) -> Self {
+        let neutral = [1.; 3];
+
+        let (white, warm) = match config.algorithm {
+            WhiteAlgorithm::SubtractMinimum => (neutral, neutral),
+            WhiteAlgorithm::Accurate => match config.channels {
+                WhiteChannels::Rgbw => (temperature_point(config.white_temperature), neutral),
+                WhiteChannels::Rgbcw => (
+                    temperature_point(config.cold_temperature),
+                    temperature_point(config.warm_temperature),
+                ),
+            },
+            WhiteAlgorithm::Custom => match config.channels {
+                WhiteChannels::Rgbw => (color_point(config.white_point), neutral),
+                WhiteChannels::Rgbcw => (
+                    color_point(config.cold_point),
+                    color_point(config.warm_point),
+                ),
+            },
+        };
+
+        Self {
+            channels: config.channels,
+            split: config.algorithm == WhiteAlgorithm::SubtractMinimum,
+            white,
+            warm,
+        }
+    }
+
+    /// Move the white part of the colors of `led_data` to `white_data`
+    pub fn apply(&self, led_data: &mut [Color], white_data: &mut Vec<WhiteLevels>) {
+        white_data.clear();
+        white_data.extend(led_data.iter_mut().map(|led| self.convert(led)));
+    }
+
+    fn convert(&self, led: &mut Color) -> WhiteLevels {
+        let mut color = [led.red, led.green, led.blue].map(|c| c as f32 / 255.);
+
+        let levels = match self.channels {
+            WhiteChannels::Rgbw => WhiteLevels {
+                white: extract(&mut color, self.white),
+                warm: 0,
+            },
+            WhiteChannels::Rgbcw if self.split => {
+                let amount = color.iter().copied().fold(1f32, f32::min);
+                for c in &mut color {
+                    *c -= amount;
+                }
+
+                // Both LEDs lit at half the amount emit as much as one of them at the full amount
+                let half = to_level(amount / 2.);
+                WhiteLevels {
+                    white: half,
+                    warm: half,
+                }
+            }
+            WhiteChannels::Rgbcw => {
+                // Reddish colors are better matched by the warm LEDs, bluish ones by the cold LEDs
+                if color[0] >= color[2] {
+                    let warm = extract(&mut color, self.warm);
+                    let white = extract(&mut color, self.white);
+                    WhiteLevels { white, warm }
+                } else {
+                    let white = extract(&mut color, self.white);
+                    let warm = extract(&mut color, self.warm);
+                    WhiteLevels { white, warm }
+                }
+            }
+        };
+
+        *led = Color::new(to_level(color[0]), to_level(color[1]), to_level(color[2]));
+        levels
+    }
+}
+
+/// Remove the largest amount of `point` that fits in `color`, and return it as a channel level
+fn extract(color: &mut [f32; 3], point: [f32; 3]) -> u8 {
+    let amount = color
+        .iter()
+        .zip(point)
+        .filter(|(_, p)| *p > 0.)
+        .map(|(c, p)| c / p)
+        .fold(1f32, f32::min);
+
+    for (c, p) in color.iter_mut().zip(point) {
+        *c = (*c - amount * p).max(0.);
+    }
+
+    to_level(amount)
+}
+
+fn to_level(value: f32) -> u8 {
+    (value * 255.).round().clamp(0., 255.) as u8
+}
+
+/// Color of a white LED of the given color temperature
+fn temperature_point(temperature: u32) -> [f32; 3] {
+    let (r, g, b) = kelvin_to_rgb16(temperature).into_components();
+    normalize([r, g, b].map(|c| c as f32))
+}
+
+fn color_point(color: Color) -> [f32; 3] {
+    normalize([color.red, color.green, color.blue].map(|c| c as f32))
+}
+
+fn normalize(point: [f32; 3]) -> [f32; 3] {
+    let max = point.iter().copied().fold(0f32, f32::max);
+    if max > 0. {
+        point.map(|c| c / max)
+    } else {
+        // Validation rejects black white points, fall back to a neutral white
+        [1.; 3]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(channels: WhiteChannels, algorithm: WhiteAlgorithm) -> WhiteOutput {
+        serde_json::from_value(serde_json::json!({
+            "channels": channels,
+            "algorithm": algorithm,
+            "whitePoint": [255, 200, 150],
+            "coldPoint": [200, 220, 255],
+            "warmPoint": [255, 180, 100],
+        }))
+        .unwrap()
+    }
+
+    fn convert(config: &WhiteOutput, color: Color) -> (Color, WhiteLevels) {
+        let mut led_data = [color];
+        let mut white_data = Vec::new();
+        WhiteConverter::new(config).apply(&mut led_data, &mut white_data);
+        (led_data[0], white_data[0])
+    }
+
+    #[test]
+    fn test_white_output() {
+        let rgbw = config(WhiteChannels::Rgbw, WhiteAlgorithm::SubtractMinimum);
+        assert_eq!(
+            convert(&rgbw, Color::new(255, 128, 64)),
+            (Color::new(191, 64, 0), WhiteLevels { white: 64, warm: 0 })
+        );
+
+        // The white LEDs replace as much of the color as they can
+        let rgbw = config(WhiteChannels::Rgbw, WhiteAlgorithm::Custom);
+        assert_eq!(
+            convert(&rgbw, Color::new(255, 200, 150)),
+            (
+                Color::new(0, 0, 0),
+                WhiteLevels {
+                    white: 255,
+                    warm: 0
+                }
+            )
+        );
+        assert_eq!(
+            convert(&rgbw, Color::new(255, 255, 255)),
+            (
+                Color::new(0, 55, 105),
+                WhiteLevels {
+                    white: 255,
+                    warm: 0
+                }
+            )
+        );
+
+        let rgbcw = config(WhiteChannels::Rgbcw, WhiteAlgorithm::SubtractMinimum);
+        assert_eq!(
+            convert(&rgbcw, Color::new(200, 200, 200)),
+            (
+                Color::new(0, 0, 0),
+                WhiteLevels {
+                    white: 100,
+                    warm: 100
+                }
+            )
+        );
+
+        // Warm colors use the warm LEDs first, cold colors the cold LEDs
+        let rgbcw = config(WhiteChannels::Rgbcw, WhiteAlgorithm::Custom);
+        let (color, levels) = convert(&rgbcw, Color::new(255, 180, 100));
+        assert_eq!((color, levels.warm), (Color::new(0, 0, 0), 255));
+        let (color, levels) = convert(&rgbcw, Color::new(200, 220, 255));
+        assert_eq!((color, levels.white), (Color::new(0, 0, 0), 255));
+
+        // Accurate white points come from the color temperature
+        let rgbw = config(WhiteChannels::Rgbw, WhiteAlgorithm::Accurate);
+        let (color, levels) = convert(&rgbw, Color::new(0, 0, 255));
+        assert_eq!((color, levels.white), (Color::new(0, 0, 255), 0));
+        let (_, levels) = convert(&rgbw, Color::new(255, 255, 255));
+        assert_eq!(levels.white, 255);
+    }
+}
@@ -0,0 +1,244 @@
+use std::{net::SocketAddr, time::Duration};
+
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::{common::*, DeviceError};
+use crate::models::{self, WledProtocol};
+
+pub type WledDevice = Rewriter<WledDeviceImpl>;
+
+const PROTOCOL_WARLS: u8 = 1;
+const PROTOCOL_DRGB: u8 = 2;
+const PROTOCOL_DNRGB: u8 = 4;
+
+/// Maximum number of LEDs in a single packet, for each protocol
+const WARLS_MAX_LEDS: usize = 255;
+const DRGB_MAX_LEDS: usize = 490;
+const DNRGB_MAX_LEDS: usize = 489;
+
+/// Time allowed for the WLED JSON API to answer LED count queries
+const INFO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maximum size of the JSON API reply
+const MAX_INFO_SIZE: u64 = 64 * 1024;
+
+pub struct WledDeviceImpl {
+    output: UdpOutput,
+    notified_truncated: bool,
+    /// Packets for the current LED data, only the first `packet_count` are valid
+    packets: Vec<Vec<u8>>,
+    packet_count: usize,
+}
+
+#[async_trait]
+impl WritingDevice for WledDeviceImpl {
+    type Config = models::Wled;
+
+    fn new(config: &Self::Config) -> Result<Self, DeviceError> {
+        Ok(Self {
+            output: UdpOutput::new("WLED controller", &config.host, config.port),
+            notified_truncated: false,
+            packets: Vec::new(),
+            packet_count: 0,
+        })
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        if config.protocol == WledProtocol::Warls
+            && led_data.len() > WARLS_MAX_LEDS
+            && !self.notified_truncated
+        {
+            self.notified_truncated = true;
+            warn!(
+                "WARLS only addresses {} LEDs, use DRGB or DNRGB for the remaining {}",
+                WARLS_MAX_LEDS,
+                led_data.len() - WARLS_MAX_LEDS
+            );
+        }
+
+        self.packet_count = encode(config.protocol, config.timeout, led_data, &mut self.packets);
+
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        let packets = &self.packets[..self.packet_count];
+        let result = self.output.send(packets).await;
+        self.output
+            .report(result, packets.iter().map(Vec::len).sum())
+    }
+
+    fn last_error(&self) -> Option<&str> {
+        self.output.last_error()
+    }
+
+    async fn query_led_count(
+        &mut self,
+        config: &Self::Config,
+    ) -> Result<Option<usize>, DeviceError> {
+        let address = match self.output.address().await {
+            Ok(address) => SocketAddr::new(address.ip(), 80),
+            Err(error) => {
+                warn!(host = %config.host, error = %error, "cannot query WLED LED count");
+                return Ok(None);
+            }
+        };
+
+        match tokio::time::timeout(INFO_TIMEOUT, led_count(address, &config.host)).await {
+            Ok(Ok(count)) => Ok(Some(count)),
+            Ok(Err(error)) => {
+                warn!(host = %config.host, error = %error, "cannot query WLED LED count");
+                Ok(None)
+            }
+            Err(_) => {
+                warn!(host = %config.host, "timed out querying WLED LED count");
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WledInfo {
+    leds: WledLeds,
+}
+
+#[derive(Deserialize)]
+struct WledLeds {
+    count: usize,
+}
+
+/// Read the LED count from the WLED JSON API
+async fn led_count(address: SocketAddr, host: &str) -> Result<usize, DeviceError> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream
+        .write_all(
+            format!(
+                "GET /json/info HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                host
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut response = Vec::new();
+    stream
+        .take(MAX_INFO_SIZE)
+        .read_to_end(&mut response)
+        .await?;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| DeviceError::InvalidReply("truncated HTTP response".to_owned()))?;
+
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(DeviceError::InvalidReply(status_line.to_owned()));
+    }
+
+    let info: WledInfo =
+        serde_json::from_str(body).map_err(|error| DeviceError::InvalidReply(error.to_string()))?;
+    Ok(info.leds.count)
+}
+
+/// Encode LED data into realtime UDP packets, reusing the buffers in `packets`
+///
+/// DRGB data for more LEDs than fit in a single packet is split into DNRGB packets.
+///
+/// # Returns
+///
+/// The number of packets to send
+fn encode(
+    protocol: WledProtocol,
+    timeout: u8,
+    led_data: &[models::Color],
+    packets: &mut Vec<Vec<u8>>,
+) -> usize {
+    let mut count = 0;
+
+    match protocol {
+        WledProtocol::Warls => {
+            let packet = next_packet(packets, &mut count, &[PROTOCOL_WARLS, timeout]);
+            for (index, led) in led_data.iter().take(WARLS_MAX_LEDS).enumerate() {
+                packet.extend_from_slice(&[index as u8, led.red, led.green, led.blue]);
+            }
+        }
+        WledProtocol::Drgb if led_data.len() <= DRGB_MAX_LEDS => {
+            let packet = next_packet(packets, &mut count, &[PROTOCOL_DRGB, timeout]);
+            for led in led_data {
+                packet.extend_from_slice(&[led.red, led.green, led.blue]);
+            }
+        }
+        WledProtocol::Drgb | WledProtocol::Dnrgb => {
+            for (i, chunk) in led_data.chunks(DNRGB_MAX_LEDS).enumerate() {
+                let [start_high, start_low] = ((i * DNRGB_MAX_LEDS) as u16).to_be_bytes();
+                let packet = next_packet(
+                    packets,
+                    &mut count,
+                    &[PROTOCOL_DNRGB, timeout, start_high, start_low],
+                );
+                for led in chunk {
+                    packet.extend_from_slice(&[led.red, led.green, led.blue]);
+                }
+            }
+        }
+    }
+
+    count
+}
+
+/// Start the next packet with the given header, reusing an existing buffer if possible
+fn next_packet<'p>(
+    packets: &'p mut Vec<Vec<u8>>,
+    count: &mut usize,
+    header: &[u8],
+) -> &'p mut Vec<u8> {
+    if *count == packets.len() {
+        packets.push(Vec::new());
+    }
+
+    let packet = &mut packets[*count];
+    packet.clear();
+    packet.extend_from_slice(header);
+    *count += 1;
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let leds: Vec<_> = (0..1000)
+            .map(|i| models::Color::new(i as u8, 1, 2))
+            .collect();
+        let mut packets = Vec::new();
+
+        // Small setups fit in a single DRGB packet
+        assert_eq!(encode(WledProtocol::Drgb, 2, &leds[..3], &mut packets), 1);
+        assert_eq!(packets[0], [2, 2, 0, 1, 2, 1, 1, 2, 2, 1, 2]);
+
+        // Larger ones are split
+        assert_eq!(encode(WledProtocol::Drgb, 255, &leds, &mut packets), 3);
+        assert_eq!(&packets[0][..4], [4, 255, 0, 0]);
+        assert_eq!(&packets[1][..4], [4, 255, 0x01, 0xE9]);
+        assert_eq!(&packets[2][..4], [4, 255, 0x03, 0xD2]);
+        assert_eq!(packets[2].len(), 4 + (1000 - 2 * DNRGB_MAX_LEDS) * 3);
+
+        // WARLS is limited to the first 255 LEDs
+        assert_eq!(encode(WledProtocol::Warls, 1, &leds, &mut packets), 1);
+        assert_eq!(packets[0].len(), 2 + WARLS_MAX_LEDS * 4);
+        assert_eq!(&packets[0][2 + 4 * 10..2 + 4 * 11], [10, 10, 1, 2]);
+    }
+}
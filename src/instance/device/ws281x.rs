@@ -0,0 +1,273 @@
+//! WS281x strips driven through the rpi_ws281x library
+//!
+//! The library is not linked at build time, it is loaded when the device starts. It has to be
+//! built as a shared library (`cmake -DBUILD_SHARED=ON`) and installed as `libws2811.so`, and
+//! accessing the peripherals requires running as root.
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+
+use async_trait::async_trait;
+use libloading::Library;
+use thiserror::Error;
+
+use super::{common::*, DeviceError, WhiteLevels};
+use crate::models;
+
+pub type Ws281xDevice = Rewriter<Ws281xImpl>;
+
+#[derive(Debug, Error)]
+pub enum Ws281xError {
+    #[error("cannot load the rpi_ws281x library: {0}")]
+    Load(#[from] libloading::Error),
+    #[error("rpi_ws281x error: {0}")]
+    Library(String),
+}
+
+// Definitions from ws2811.h
+
+const RPI_PWM_CHANNELS: usize = 2;
+const WS2811_SUCCESS: c_int = 0;
+
+/// Strip types, holding the shift of the white, red, green and blue channels in the LED values
+///
+/// The library sends the channels in this order, so the LED values are packed with the channels
+/// already reordered.
+const WS2811_STRIP_RGB: c_int = 0x00100800;
+const SK6812_STRIP_RGBW: c_int = 0x18100800;
+
+#[repr(C)]
+struct Channel {
+    gpionum: c_int,
+    invert: c_int,
+    count: c_int,
+    strip_type: c_int,
+    leds: *mut u32,
+    brightness: u8,
+    wshift: u8,
+    rshift: u8,
+    gshift: u8,
+    bshift: u8,
+    gamma: *mut u8,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self {
+            gpionum: 0,
+            invert: 0,
+            count: 0,
+            strip_type: 0,
+            leds: std::ptr::null_mut(),
+            brightness: 0,
+            wshift: 0,
+            rshift: 0,
+            gshift: 0,
+            bshift: 0,
+            gamma: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+struct Ws2811 {
+    render_wait_time: u64,
+    device: *mut c_void,
+    rpi_hw: *const c_void,
+    freq: u32,
+    dmanum: c_int,
+    channel: [Channel; RPI_PWM_CHANNELS],
+}
+
+type InitFn = unsafe extern "C" fn(*mut Ws2811) -> c_int;
+type FiniFn = unsafe extern "C" fn(*mut Ws2811);
+type RenderFn = unsafe extern "C" fn(*mut Ws2811) -> c_int;
+type GetReturnStrFn = unsafe extern "C" fn(c_int) -> *const c_char;
+
+/// Initialized rpi_ws281x library
+struct Driver {
+    fini: FiniFn,
+    render: RenderFn,
+    get_return_str: GetReturnStrFn,
+    /// Boxed, as the library state shouldn't move once initialized
+    state: Box<Ws2811>,
+    /// Index of the configured channel in `state`
+    channel: usize,
+    // Must outlive the function pointers above
+    _library: Library,
+}
+
+// Safety: the library state is only accessed by the owner of the driver
+unsafe impl Send for Driver {}
+
+impl Driver {
+    const LIBRARY_NAME: &'static str = "libws2811.so";
+
+    fn new(config: &models::Ws281x) -> Result<Self, Ws281xError> {
+        // Safety: loading the library has no initialization side-effects
+        let library = unsafe { Library::new(Self::LIBRARY_NAME)? };
+        debug!(path = %Self::LIBRARY_NAME, "loaded rpi_ws281x library");
+
+        let mut state = Box::new(Ws2811 {
+            render_wait_time: 0,
+            device: std::ptr::null_mut(),
+            rpi_hw: std::ptr::null(),
+            freq: config.frequency,
+            dmanum: config.dma_channel as _,
+            channel: Default::default(),
+        });
+
+        let channel = config.pwm_channel as usize;
+        state.channel[channel] = Channel {
+            gpionum: config.gpio as _,
+            invert: config.invert as _,
+            count: config.hardware_led_count as _,
+            strip_type: if config.white_output.is_some() {
+                SK6812_STRIP_RGBW
+            } else {
+                WS2811_STRIP_RGB
+            },
+            brightness: config.brightness,
+            ..Default::default()
+        };
+
+        // Safety: the symbol types match the declarations in ws2811.h
+        let (init, fini, render, get_return_str) = unsafe {
+            (
+                *library.get::<InitFn>(b"ws2811_init\0")?,
+                *library.get::<FiniFn>(b"ws2811_fini\0")?,
+                *library.get::<RenderFn>(b"ws2811_render\0")?,
+                *library.get::<GetReturnStrFn>(b"ws2811_get_return_t_str\0")?,
+            )
+        };
+
+        // Safety: the state is filled in as expected by the library, which releases what it
+        // allocated if initialization fails
+        check(get_return_str, unsafe { init(&mut *state) })?;
+
+        Ok(Self {
+            fini,
+            render,
+            get_return_str,
+            state,
+            channel,
+            _library: library,
+        })
+    }
+
+    /// Send `leds` to the strip, waiting for the previous frame to be clocked out first
+    fn render(&mut self, leds: &[u32]) -> Result<(), Ws281xError> {
+        let channel = &mut self.state.channel[self.channel];
+
+        // Safety: the library allocated `count` LEDs for the channel when it was initialized
+        let dst = unsafe { std::slice::from_raw_parts_mut(channel.leds, channel.count as usize) };
+        for (dst, src) in dst.iter_mut().zip(leds) {
+            *dst = *src;
+        }
+
+        // Safety: the state was initialized by the library
+        let result = unsafe { (self.render)(&mut *self.state) };
+        check(self.get_return_str, result)
+    }
+}
+
+fn check(get_return_str: GetReturnStrFn, result: c_int) -> Result<(), Ws281xError> {
+    if result == WS2811_SUCCESS {
+        return Ok(());
+    }
+
+    // Safety: the library returns static strings for all result codes
+    let message = unsafe { CStr::from_ptr(get_return_str(result)) };
+    Err(Ws281xError::Library(message.to_string_lossy().into_owned()))
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        // Safety: the state was initialized by the library, and isn't used afterwards
+        unsafe { (self.fini)(&mut *self.state) };
+    }
+}
+
+pub struct Ws281xImpl {
+    /// Driver of the strip, moved to a blocking task while rendering
+    driver: Option<Driver>,
+    leds: Vec<u32>,
+    /// 4 for RGBW strips, 3 otherwise
+    channel_count: usize,
+    /// Levels of the white channels of RGBW chips (e.g. SK6812)
+    white_data: Vec<WhiteLevels>,
+}
+
+#[async_trait]
+impl WritingDevice for Ws281xImpl {
+    type Config = models::Ws281x;
+
+    fn new(config: &models::Ws281x) -> Result<Self, DeviceError> {
+        let driver = Driver::new(config)?;
+        info!(gpio = %config.gpio, dma = %config.dma_channel, "initialized rpi_ws281x");
+
+        Ok(Self {
+            driver: Some(driver),
+            leds: vec![0; config.hardware_led_count as usize],
+            channel_count: if config.white_output.is_some() { 4 } else { 3 },
+            white_data: Vec::new(),
+        })
+    }
+
+    fn set_white_data(&mut self, _config: &Self::Config, white_data: &[WhiteLevels]) {
+        self.white_data.clear();
+        self.white_data.extend_from_slice(white_data);
+    }
+
+    async fn set_let_data(
+        &mut self,
+        config: &Self::Config,
+        led_data: &[models::Color],
+    ) -> Result<(), DeviceError> {
+        for (i, (dst, led)) in self.leds.iter_mut().zip(led_data).enumerate() {
+            let white = self.white_data.get(i).copied().unwrap_or_default();
+            *dst = encode_led(config.color_order.reorder_from_rgb(*led), white.white);
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<usize, DeviceError> {
+        // unwrap: the driver is put back after every render
+        let mut driver = self.driver.take().unwrap();
+        let leds = std::mem::take(&mut self.leds);
+
+        // Rendering waits for the DMA transfer of the previous frame, so it's performed outside of
+        // the async runtime workers
+        let (driver, leds, result) = tokio::task::spawn_blocking(move || {
+            let result = driver.render(&leds);
+            (driver, leds, result)
+        })
+        .await
+        .expect("rpi_ws281x render panicked");
+
+        let bytes = leds.len() * self.channel_count;
+
+        self.driver = Some(driver);
+        self.leds = leds;
+        result?;
+
+        Ok(bytes)
+    }
+}
+
+/// Pack a color whose channels are in wire order, and its white level, into a LED value
+fn encode_led(color: models::Color, white: u8) -> u32 {
+    let (a, b, c) = color.into_components();
+    (white as u32) << 24 | (a as u32) << 16 | (b as u32) << 8 | c as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_led() {
+        // With the red channel shifted the most, colors are sent as packed, and white last
+        assert_eq!(encode_led(models::Color::new(1, 2, 3), 4), 0x04010203);
+    }
+}
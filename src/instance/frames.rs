@@ -0,0 +1,109 @@
+use std::{sync::Arc, time::Instant};
+
+use tokio::sync::broadcast;
+
+use crate::models::Color;
+
+/// Number of frames an observer can fall behind before frames are skipped
+const FRAME_CHANNEL_CAPACITY: usize = 8;
+
+/// LED colors of an instance, as written to its device
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Colors in layout order, after adjustments and smoothing
+    pub led_colors: Arc<Vec<Color>>,
+    /// Time at which the frame was written
+    pub timestamp: Instant,
+}
+
+/// Sender of the frames of an instance to its observers
+///
+/// Only the instance holds it, so observers see the end of the stream when it stops.
+#[derive(Debug)]
+pub struct FrameSender(broadcast::Sender<Frame>);
+
+impl FrameSender {
+    pub fn new() -> Self {
+        Self(broadcast::channel(FRAME_CHANNEL_CAPACITY).0)
+    }
+
+    pub fn subscribe(&self) -> FrameObserver {
+        FrameObserver {
+            rx: self.0.subscribe(),
+            skipped: 0,
+        }
+    }
+
+    /// Send a frame, only copying the LED colors if someone is observing them
+    pub fn send(&self, led_colors: &[Color]) {
+        if self.0.receiver_count() > 0 {
+            // ok: observers may have been dropped since the check
+            self.0
+                .send(Frame {
+                    led_colors: Arc::new(led_colors.to_vec()),
+                    timestamp: Instant::now(),
+                })
+                .ok();
+        }
+    }
+}
+
+/// Observer of the frames written by an instance, see [super::InstanceHandle::observe_frames]
+#[derive(Debug)]
+pub struct FrameObserver {
+    rx: broadcast::Receiver<Frame>,
+    skipped: u64,
+}
+
+impl FrameObserver {
+    /// Wait for the next frame
+    ///
+    /// Observers which fall behind skip the oldest frames.
+    ///
+    /// # Returns
+    ///
+    /// `None` once the instance stopped. Restarted instances have new handles, which have to be
+    /// observed again.
+    pub async fn next(&mut self) -> Option<Frame> {
+        loop {
+            match self.rx.recv().await {
+                Ok(frame) => return Some(frame),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => self.skipped += skipped,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Number of frames skipped because this observer fell behind
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_observer() {
+        let sender = FrameSender::new();
+        // Frames without observers are dropped
+        sender.send(&[Color::new(1, 1, 1)]);
+
+        let mut observer = sender.subscribe();
+        for i in 0..FRAME_CHANNEL_CAPACITY + 2 {
+            sender.send(&[Color::new(i as _, 0, 0)]);
+        }
+        drop(sender);
+
+        let frame = observer.next().await.unwrap();
+        assert_eq!(frame.led_colors[0], Color::new(2, 0, 0));
+        assert_eq!(observer.skipped(), 2);
+
+        let mut count = 1;
+        while observer.next().await.is_some() {
+            count += 1;
+        }
+        assert_eq!(count, FRAME_CHANNEL_CAPACITY);
+    }
+}
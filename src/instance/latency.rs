@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+use crate::models;
+
+/// End-to-end latency tracking for image inputs
+///
+/// Images are timestamped when they are captured or received by a server. The latency of an image
+/// is measured when the first LED data derived from it is written to the device.
+pub struct FrameLatency {
+    budget: Option<Duration>,
+    report_interval: Option<Duration>,
+    /// Timestamp of the last image handled, if it hasn't been written yet
+    pending: Option<Instant>,
+    samples: Vec<Duration>,
+    dropped: usize,
+    last_report: Instant,
+}
+
+impl FrameLatency {
+    pub fn new(config: &models::FrameLatency) -> Self {
+        let millis = |ms: u32| Some(Duration::from_millis(ms as _)).filter(|d| !d.is_zero());
+
+        Self {
+            budget: millis(config.budget_ms),
+            report_interval: millis(config.report_interval_secs * 1000),
+            pending: None,
+            samples: Vec::new(),
+            dropped: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Check if an image with the given timestamp exceeds the latency budget
+    ///
+    /// Images for which this returns true are counted as dropped.
+    pub fn is_stale(&mut self, timestamp: Instant) -> bool {
+        match self.budget {
+            Some(budget) if timestamp.elapsed() > budget => {
+                self.dropped += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Notify that an image with the given timestamp is now the output target
+    pub fn frame_received(&mut self, timestamp: Instant) {
+        self.pending = Some(timestamp);
+    }
+
+    /// Notify that LED data was written to the device
+    pub fn frame_written(&mut self) {
+        let report_interval = match self.report_interval {
+            Some(report_interval) => report_interval,
+            None => {
+                self.pending = None;
+                return;
+            }
+        };
+
+        if let Some(timestamp) = self.pending.take() {
+            self.samples.push(timestamp.elapsed());
+        }
+
+        if self.last_report.elapsed() >= report_interval {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        self.last_report = Instant::now();
+
+        if self.samples.is_empty() {
+            if self.dropped > 0 {
+                info!(dropped = %self.dropped, "frame latency");
+                self.dropped = 0;
+            }
+
+            return;
+        }
+
+        self.samples.sort_unstable();
+
+        info!(
+            frames = %self.samples.len(),
+            dropped = %self.dropped,
+            p50 = ?percentile(&self.samples, 0.5),
+            p90 = ?percentile(&self.samples, 0.9),
+            p99 = ?percentile(&self.samples, 0.99),
+            max = ?self.samples[self.samples.len() - 1],
+            "frame latency"
+        );
+
+        self.samples.clear();
+        self.dropped = 0;
+    }
+}
+
+/// Nearest-rank percentile of a sorted, non-empty slice
+fn percentile(sorted: &[Duration], p: f32) -> Duration {
+    let rank = ((sorted.len() as f32 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&samples, 0.5), Duration::from_millis(50));
+        assert_eq!(percentile(&samples, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&samples, 1.), Duration::from_millis(100));
+        assert_eq!(
+            percentile(&[Duration::from_millis(7)], 0.9),
+            Duration::from_millis(7)
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Album artwork of the media playing on an MPRIS player
+
+use std::{collections::HashMap, convert::TryFrom, sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{sync::mpsc, task::JoinHandle};
+use zbus::{zvariant::OwnedValue, Connection};
+
+use crate::{
+    global::{
+        Global, InputMessage, InputMessageData, InputSourceError, InputSourceHandle,
+        InputSourceName, Message, PriorityGuard,
+    },
+    image::{Image, RawImage, RawImageError},
+    models::{self, Color},
+};
+
+#[derive(Debug, Error)]
+pub enum MediaArtworkError {
+    #[error("D-Bus error: {0}")]
+    DBus(#[from] zbus::Error),
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("http error: {0}")]
+    Http(#[from] ureq::Error),
+    #[error("invalid artwork: {0}")]
+    Image(#[from] RawImageError),
+    #[error("unsupported artwork url: {0}")]
+    UnsupportedUrl(String),
+    #[error(transparent)]
+    InputSource(#[from] InputSourceError),
+}
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Maximum size of an artwork download
+const MAX_ARTWORK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Time allowed for downloading an artwork, so a stalled server doesn't hold the artwork forever
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Artworks are downscaled to this size before being sent, the LED layout doesn't need more
+const THUMBNAIL_SIZE: u16 = 32;
+
+/// Interval between two frames of a transition
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Handle to the artwork task, which stops it when dropped
+pub struct MediaArtworkHandle {
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for MediaArtworkHandle {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+/// Start following the artwork of the configured player
+///
+/// Artworks are sent to `tx` at the configured priority, until the returned handle is dropped.
+pub async fn start(
+    config: models::MediaArtwork,
+    global: Global,
+    tx: mpsc::Sender<InputMessage>,
+) -> Result<MediaArtworkHandle, MediaArtworkError> {
+    let connection = Connection::session().await?;
+    let source = global
+        .register_input_source(InputSourceName::MediaArtwork, Some(config.priority))
+        .await?;
+
+    let join_handle = tokio::spawn(
+        MediaArtwork {
+            _guard: PriorityGuard::new_mpsc(tx.clone(), &source),
+            config,
+            connection,
+            source,
+            tx,
+            art_url: None,
+            current: None,
+        }
+        .run(),
+    );
+
+    Ok(MediaArtworkHandle { join_handle })
+}
+
+struct MediaArtwork {
+    // Clears our priority when the task stops, so it must be dropped before source
+    _guard: PriorityGuard,
+    config: models::MediaArtwork,
+    connection: Connection,
+    source: InputSourceHandle<InputMessage>,
+    tx: mpsc::Sender<InputMessage>,
+    /// URL of the artwork currently displayed
+    art_url: Option<String>,
+    /// Thumbnail of the artwork currently displayed
+    current: Option<Vec<Color>>,
+}
+
+impl MediaArtwork {
+    async fn run(mut self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.poll_interval_ms as _));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let art_url = match current_art_url(&self.connection, &self.config.player).await {
+                Ok(art_url) => art_url,
+                Err(error) => {
+                    debug!(error = %error, "cannot query MPRIS players");
+                    None
+                }
+            };
+
+            if art_url == self.art_url {
+                continue;
+            }
+
+            debug!(art_url = ?art_url, "media artwork changed");
+            self.art_url = art_url.clone();
+
+            let result = match art_url {
+                Some(art_url) => match load_thumbnail(&art_url).await {
+                    Ok(thumbnail) => self.transition(thumbnail).await,
+                    Err(error) => {
+                        warn!(art_url = %art_url, error = %error, "cannot load media artwork");
+                        self.clear().await
+                    }
+                },
+                None => self.clear().await,
+            };
+
+            if result.is_err() {
+                // The instance stopped
+                break;
+            }
+        }
+    }
+
+    async fn send(&self, data: InputMessageData) -> Result<(), ()> {
+        self.tx
+            .send(InputMessage::new(
+                self.source.id(),
+                self.source.name().component(),
+                data,
+            ))
+            .await
+            .map_err(|_| ())
+    }
+
+    async fn clear(&mut self) -> Result<(), ()> {
+        self.current = None;
+        self.send(InputMessageData::Clear {
+            priority: self.config.priority,
+        })
+        .await
+    }
+
+    /// Crossfade from the current artwork to the given one
+    async fn transition(&mut self, target: Vec<Color>) -> Result<(), ()> {
+        let from = self
+            .current
+            .take()
+            .unwrap_or_else(|| vec![Color::default(); target.len()]);
+
+        let steps = (self.config.transition_ms / FRAME_INTERVAL.as_millis() as u32).max(1);
+        let mut interval = tokio::time::interval(FRAME_INTERVAL);
+
+        for step in 1..=steps {
+            interval.tick().await;
+
+            self.send(InputMessageData::Image {
+                priority: self.config.priority,
+                duration: None,
+                image: Arc::new(blend(&from, &target, step as f32 / steps as f32)),
+                region: None,
+            })
+            .await?;
+        }
+
+        self.current = Some(target);
+        Ok(())
+    }
+}
+
+/// Find the artwork URL of the media playing on the given player
+///
+/// Playing players are preferred over paused ones.
+async fn current_art_url(
+    connection: &Connection,
+    player: &str,
+) -> Result<Option<String>, MediaArtworkError> {
+    let dbus = zbus::fdo::DBusProxy::new(connection).await?;
+    let mut paused = None;
+
+    for name in dbus.list_names().await.map_err(zbus::Error::from)? {
+        let player_name = match name.as_str().strip_prefix(MPRIS_PREFIX) {
+            Some(player_name) => player_name,
+            None => continue,
+        };
+
+        if !player.is_empty() && !player_name.starts_with(player) {
+            continue;
+        }
+
+        let proxy = zbus::Proxy::new(connection, name.to_owned(), MPRIS_PATH, MPRIS_PLAYER).await?;
+        let status: String = proxy.get_property("PlaybackStatus").await?;
+        if status == "Stopped" {
+            continue;
+        }
+
+        let metadata: HashMap<String, OwnedValue> = proxy.get_property("Metadata").await?;
+        let art_url = metadata
+            .get("mpris:artUrl")
+            .and_then(|value| <&str>::try_from(&**value).ok())
+            .filter(|art_url| !art_url.is_empty())
+            .map(ToOwned::to_owned);
+
+        if status == "Playing" {
+            return Ok(art_url);
+        } else if paused.is_none() {
+            paused = Some(art_url);
+        }
+    }
+
+    Ok(paused.flatten())
+}
+
+/// Download and decode an artwork, then downscale it
+async fn load_thumbnail(art_url: &str) -> Result<Vec<Color>, MediaArtworkError> {
+    let data = if let Some(path) = art_url.strip_prefix("file://") {
+        tokio::fs::read(percent_decode(path)).await?
+    } else if art_url.starts_with("http://") || art_url.starts_with("https://") {
+        let art_url = art_url.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let agent: ureq::Agent = ureq::Agent::config_builder()
+                .timeout_global(Some(DOWNLOAD_TIMEOUT))
+                .build()
+                .into();
+
+            agent
+                .get(&art_url)
+                .call()?
+                .body_mut()
+                .with_config()
+                .limit(MAX_ARTWORK_SIZE)
+                .read_to_vec()
+        })
+        .await
+        .expect("artwork download panicked")?
+    } else {
+        return Err(MediaArtworkError::UnsupportedUrl(art_url.to_owned()));
+    };
+
+    let image = crate::image::decoder::decode(&data)?;
+    Ok(thumbnail(&image))
+}
+
+/// Nearest-neighbor downscale of an image to THUMBNAIL_SIZE x THUMBNAIL_SIZE
+fn thumbnail(image: &impl Image) -> Vec<Color> {
+    let (width, height) = (image.width() as u32, image.height() as u32);
+    let size = THUMBNAIL_SIZE as u32;
+
+    (0..size)
+        .flat_map(|y| (0..size).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            image
+                .color_at((x * width / size) as _, (y * height / size) as _)
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Linear interpolation between two thumbnails
+fn blend(from: &[Color], to: &[Color], t: f32) -> RawImage {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+
+    let data = from
+        .iter()
+        .zip(to)
+        .flat_map(|(a, b)| {
+            [
+                mix(a.red, b.red),
+                mix(a.green, b.green),
+                mix(a.blue, b.blue),
+            ]
+        })
+        .collect();
+
+    RawImage::try_from((data, THUMBNAIL_SIZE as u32, THUMBNAIL_SIZE as u32))
+        .expect("invalid thumbnail size")
+}
+
+/// Decode %XX escapes in the path of a file URL
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        if let Some(byte) = escaped {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            percent_decode("/home/user/My%20Music/cover%C3%A9.jpg"),
+            "/home/user/My Music/coveré.jpg"
+        );
+        assert_eq!(percent_decode("/tmp/100%"), "/tmp/100%");
+        assert_eq!(percent_decode("/tmp/%zz"), "/tmp/%zz");
+    }
+}
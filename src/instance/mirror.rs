@@ -0,0 +1,223 @@
+//! Output of another instance, used as an input
+
+use std::{sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::FrameObserver;
+use crate::{
+    global::{
+        Global, InputMessage, InputMessageData, InputSourceError, InputSourceHandle,
+        InputSourceName, Message, PriorityGuard,
+    },
+    models::{self, Color},
+};
+
+#[derive(Debug, Error)]
+pub enum InstanceMirrorError {
+    #[error("an instance cannot mirror itself")]
+    SelfMirror,
+    #[error("instances mirror each other: {0:?}")]
+    Cycle(Vec<i32>),
+    #[error(transparent)]
+    InputSource(#[from] InputSourceError),
+}
+
+/// Interval at which a source instance that isn't running is looked up again
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Handle to the mirroring task, which stops it when dropped
+pub struct InstanceMirrorHandle {
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for InstanceMirrorHandle {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+/// Start mirroring the configured instance
+///
+/// Its frames are resampled to `led_count` LEDs and sent to `tx` at the configured priority,
+/// until the returned handle is dropped.
+pub async fn start(
+    config: models::InstanceMirror,
+    instance_id: i32,
+    led_count: usize,
+    global: Global,
+    tx: mpsc::Sender<InputMessage>,
+) -> Result<InstanceMirrorHandle, InstanceMirrorError> {
+    if config.source == instance_id {
+        return Err(InstanceMirrorError::SelfMirror);
+    }
+
+    if let Some(cycle) = global
+        .read_config(|config| config.mirror_cycle(instance_id))
+        .await
+    {
+        return Err(InstanceMirrorError::Cycle(cycle));
+    }
+
+    let source = global
+        .register_input_source(
+            InputSourceName::InstanceMirror {
+                source: config.source,
+            },
+            Some(config.priority),
+        )
+        .await?;
+
+    let join_handle = tokio::spawn(
+        InstanceMirror {
+            _guard: PriorityGuard::new_mpsc(tx.clone(), &source),
+            config,
+            led_count,
+            global,
+            source,
+            tx,
+        }
+        .run(),
+    );
+
+    Ok(InstanceMirrorHandle { join_handle })
+}
+
+struct InstanceMirror {
+    // Clears our priority when the task stops, so it must be dropped before source
+    _guard: PriorityGuard,
+    config: models::InstanceMirror,
+    led_count: usize,
+    global: Global,
+    source: InputSourceHandle<InputMessage>,
+    tx: mpsc::Sender<InputMessage>,
+}
+
+impl InstanceMirror {
+    async fn run(self) {
+        let mut waiting = false;
+
+        loop {
+            let mut observer = match self.observe().await {
+                Some(observer) => observer,
+                None => {
+                    if !waiting {
+                        waiting = true;
+                        info!(source = %self.config.source, "waiting for the mirrored instance to start");
+                    }
+
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                    continue;
+                }
+            };
+
+            info!(source = %self.config.source, "mirroring instance");
+            waiting = false;
+
+            while let Some(frame) = observer.next().await {
+                let led_colors = resample(
+                    &frame.led_colors,
+                    self.config.first_led as _,
+                    self.config.led_count as _,
+                    self.led_count,
+                );
+
+                let data = InputMessageData::LedColors {
+                    priority: self.config.priority,
+                    duration: None,
+                    led_colors: Arc::new(led_colors),
+                };
+
+                if self.send(data).await.is_err() {
+                    // Our instance stopped
+                    return;
+                }
+            }
+
+            // The source instance stopped, don't keep showing its last frame
+            let data = InputMessageData::Clear {
+                priority: self.config.priority,
+            };
+
+            if self.send(data).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn observe(&self) -> Option<FrameObserver> {
+        self.global
+            .get_instance(self.config.source)
+            .await?
+            .observe_frames()
+            .await
+            .ok()
+    }
+
+    async fn send(&self, data: InputMessageData) -> Result<(), ()> {
+        self.tx
+            .send(InputMessage::new(
+                self.source.id(),
+                self.source.name().component(),
+                data,
+            ))
+            .await
+            .map_err(|_| ())
+    }
+}
+
+/// Resample the mirrored range of `source` to `led_count` LEDs
+///
+/// `count` LEDs are mirrored from `first_led`, or all of them if `count` is 0. Each LED gets the
+/// average of the part of the range it covers.
+fn resample(source: &[Color], first_led: usize, count: usize, led_count: usize) -> Vec<Color> {
+    let start = first_led.min(source.len());
+    let end = if count == 0 {
+        source.len()
+    } else {
+        (start + count).min(source.len())
+    };
+    let range = &source[start..end];
+
+    if range.is_empty() {
+        return vec![Color::default(); led_count];
+    }
+
+    (0..led_count)
+        .map(|i| {
+            let from = i * range.len() / led_count;
+            let to = ((i + 1) * range.len() / led_count).max(from + 1);
+            let leds = &range[from..to];
+
+            let (r, g, b) = leds.iter().fold((0u32, 0u32, 0u32), |(r, g, b), led| {
+                (
+                    r + led.red as u32,
+                    g + led.green as u32,
+                    b + led.blue as u32,
+                )
+            });
+            let n = leds.len() as u32;
+            Color::new((r / n) as u8, (g / n) as u8, (b / n) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample() {
+        let source: Vec<_> = (0..6).map(|i| Color::new(i * 10, 0, 0)).collect();
+        let red = |leds: Vec<Color>| leds.iter().map(|led| led.red).collect::<Vec<_>>();
+
+        // Downscaling averages, upscaling repeats
+        assert_eq!(red(resample(&source, 0, 0, 3)), [5, 25, 45]);
+        assert_eq!(red(resample(&source, 4, 0, 4)), [40, 40, 50, 50]);
+        assert_eq!(red(resample(&source, 1, 2, 2)), [10, 20]);
+
+        // Ranges past the end of the source are black
+        assert_eq!(red(resample(&source, 10, 2, 2)), [0, 0]);
+    }
+}
@@ -1,18 +1,22 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
-use std::pin::Pin;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use futures::Future;
+use thiserror::Error;
 use tokio::select;
 
 use crate::{
-    api::types::PriorityInfo,
+    api::{json::message::EffectRequest, types::PriorityInfo},
     component::ComponentName,
+    effects::InstanceEffects,
     global::{Global, InputMessage, InputMessageData, Message},
     models::Color,
 };
 
+mod coalescer;
+pub use coalescer::*;
+
 mod effect_runner;
 pub use effect_runner::StartEffectError;
 use effect_runner::*;
@@ -20,14 +24,38 @@ use effect_runner::*;
 mod muxed_message;
 pub use muxed_message::*;
 
+mod timeline;
+pub use timeline::*;
+
 #[derive(Debug, Clone, Copy)]
 pub struct MuxerConfig {
+    pub instance_id: i32,
     pub led_count: usize,
+    /// Total size of the frames queued by each running effect, in bytes
+    pub effect_queue_bytes: usize,
+    /// Latch time of the device, reported to effects
+    pub latch_time: Duration,
+    /// Size of the images painted by effects
+    pub image_size: (u32, u32),
 }
 
 impl From<MuxerConfig> for EffectRunnerConfig {
-    fn from(MuxerConfig { led_count }: MuxerConfig) -> Self {
-        Self { led_count }
+    fn from(
+        MuxerConfig {
+            instance_id,
+            led_count,
+            effect_queue_bytes,
+            latch_time,
+            image_size,
+        }: MuxerConfig,
+    ) -> Self {
+        Self {
+            instance_id,
+            led_count,
+            effect_queue_bytes,
+            latch_time,
+            image_size,
+        }
     }
 }
 
@@ -39,45 +67,111 @@ struct InputEntry {
     effect_key: Option<RunningEffectKey>,
 }
 
-type BoxedTimeoutCallback =
-    Box<dyn Fn() -> Pin<Box<dyn Future<Output = (usize, i32)> + Send + Sync>> + Send + Sync>;
+#[derive(Debug, Clone, Copy)]
+struct InputTimeout {
+    priority: i32,
+    expires: Instant,
+}
 
 pub struct PriorityMuxer {
     global: Global,
     inputs: BTreeMap<i32, InputEntry>,
     input_id: usize,
-    timeouts: HashMap<usize, BoxedTimeoutCallback>,
+    timeouts: HashMap<usize, InputTimeout>,
     effect_runner: EffectRunner,
+    timeline: PriorityTimeline,
+    /// Registered inputs when the priorities were last checked for changes
+    registered: Vec<RegisteredInput>,
+    priorities_changed: bool,
+    /// Components whose inputs are ignored
+    disabled: HashSet<ComponentName>,
+    /// Priority selected by the user, visible instead of the lowest priority until it is cleared
+    selected: Option<i32>,
+}
+
+#[derive(Debug, Error)]
+pub enum SelectPriorityError {
+    #[error("no input at priority {priority}")]
+    NotFound { priority: i32 },
+}
+
+/// Identity of an input in the priority list, to detect changes reported to API clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RegisteredInput {
+    priority: i32,
+    source_id: usize,
+    component: ComponentName,
+    effect_key: Option<RunningEffectKey>,
 }
 
 pub const MAX_PRIORITY: i32 = 256;
 const MUXER_ID: usize = 0;
 
 impl PriorityMuxer {
-    pub async fn new(global: Global, config: MuxerConfig) -> Self {
+    pub async fn new(global: Global, effects: Arc<InstanceEffects>, config: MuxerConfig) -> Self {
         let mut this = Self {
             global: global.clone(),
             inputs: Default::default(),
             timeouts: Default::default(),
             input_id: 0,
-            effect_runner: EffectRunner::new(global, config.into()),
+            effect_runner: EffectRunner::new(global, effects, config.into()),
+            timeline: Default::default(),
+            registered: Default::default(),
+            priorities_changed: false,
+            disabled: Default::default(),
+            selected: None,
         };
 
         // Start by clearing all outputs
         this.clear_all().await;
+        this.record_visible().await;
 
         this
     }
 
+    /// Priority of the visible input: the selected one, or the lowest one with auto-selection
     fn current_priority(&self) -> i32 {
-        *self.inputs.keys().next().unwrap()
+        self.selected
+            .unwrap_or_else(|| *self.inputs.keys().next().unwrap())
+    }
+
+    fn current_input(&self) -> Option<(i32, &InputEntry)> {
+        match self.selected {
+            Some(priority) => self.inputs.get(&priority).map(|entry| (priority, entry)),
+            None => self
+                .inputs
+                .iter()
+                .next()
+                .map(|(&priority, entry)| (priority, entry)),
+        }
     }
 
     fn notify_output_change(&mut self) -> Option<MuxedMessage> {
-        let target = self.inputs.values().next()?;
-        Some(MuxedMessage::new(
-            target.message.data().clone().try_into().ok()?,
-        ))
+        let (_, target) = self.current_input()?;
+        Some(
+            MuxedMessage::new(target.message.data().clone().try_into().ok()?)
+                .with_smoothing(target.message.smoothing().cloned()),
+        )
+    }
+
+    /// Check if an update at `priority` changes the output
+    fn is_visible(&self, priority: i32) -> bool {
+        match self.selected {
+            Some(selected) => priority == selected,
+            None => priority <= self.current_priority(),
+        }
+    }
+
+    /// Remove the input at `priority`, going back to auto-selection if it was selected
+    fn remove_input(&mut self, priority: i32) -> Option<InputEntry> {
+        let removed = self.inputs.remove(&priority);
+
+        if removed.is_some() && self.selected == Some(priority) {
+            debug!(priority = %priority, "selected priority removed, enabling auto-selection");
+            self.selected = None;
+        }
+
+        removed
     }
 
     fn insert_input(
@@ -108,19 +202,10 @@ impl PriorityMuxer {
             self.timeouts.remove(&input_id);
         }
 
-        // Add the future for the current input
+        // Add the timeout for the current input
         if let Some(expires) = expires {
-            let id = self.input_id;
-
-            self.timeouts.insert(
-                self.input_id,
-                Box::new(move || {
-                    Box::pin(async move {
-                        tokio::time::sleep_until(expires.into()).await;
-                        (id, priority)
-                    })
-                }),
-            );
+            self.timeouts
+                .insert(self.input_id, InputTimeout { priority, expires });
         }
 
         // Increment id
@@ -129,13 +214,53 @@ impl PriorityMuxer {
         before
     }
 
+    /// Extend the deadline of the current input at `input`'s priority, if `input` is a
+    /// retrigger of it: same source, same output and both have a duration.
+    ///
+    /// # Returns
+    ///
+    /// `input` back if it isn't a retrigger and needs to be inserted as a new entry
+    fn try_retrigger(&mut self, priority: i32, input: InputMessage) -> Result<(), InputMessage> {
+        let entry = match self.inputs.get_mut(&priority) {
+            Some(entry) => entry,
+            None => return Err(input),
+        };
+
+        let timeout = match self.timeouts.get_mut(&entry.input_id) {
+            Some(timeout) => timeout,
+            None => return Err(input),
+        };
+
+        let duration = match input.data().duration().and_then(|d| d.to_std().ok()) {
+            Some(duration) => duration,
+            None => return Err(input),
+        };
+
+        if entry.effect_key.is_some()
+            || entry.message.source_id() != input.source_id()
+            || entry.message.component() != input.component()
+            || !entry.message.data().same_output(input.data())
+            || entry.message.smoothing() != input.smoothing()
+        {
+            return Err(input);
+        }
+
+        let expires = Instant::now() + duration;
+        timeout.expires = expires;
+        entry.expires = Some(expires);
+        entry.message = input;
+
+        Ok(())
+    }
+
     fn clear_inputs(&mut self) {
         self.inputs.clear();
         self.timeouts.clear();
+        self.selected = None;
     }
 
     fn clear_input(&mut self, priority: i32) -> bool {
-        if let Some(InputEntry { input_id, .. }) = self.inputs.remove(&priority) {
+        if let Some(InputEntry { input_id, .. }) = self.remove_input(priority) {
             self.timeouts.remove(&input_id);
             true
         } else {
@@ -190,8 +315,18 @@ impl PriorityMuxer {
 
     async fn handle_input(&mut self, input: InputMessage) -> Option<MuxedMessage> {
         let priority = input.data().priority().unwrap();
-        let is_new = priority < self.current_priority();
-        let notify = priority <= self.current_priority();
+
+        // Fast path: same data sent again, only the deadline changes
+        let input = match self.try_retrigger(priority, input) {
+            Ok(()) => {
+                trace!(priority = %priority, "retriggered input");
+                return None;
+            }
+            Err(input) => input,
+        };
+
+        let notify = self.is_visible(priority);
+        let is_new = notify && priority != self.current_priority();
 
         let before = self.insert_input(priority, input.clone(), None);
         trace!(
@@ -210,7 +345,9 @@ impl PriorityMuxer {
         }
 
         if notify {
+            // The output is the new input, so it keeps its capture time
             self.notify_output_change()
+                .map(|message| message.with_timestamp(input.timestamp()))
         } else {
             None
         }
@@ -222,7 +359,7 @@ impl PriorityMuxer {
         // Check if the input for the target priority is still the one mentioned in the future
         if let Some(input) = self.inputs.get(&priority) {
             if input.input_id == id {
-                if let Some(removed) = self.inputs.remove(&priority) {
+                if let Some(removed) = self.remove_input(priority) {
                     debug!(input = ?removed, "input timeout");
                 }
             } else {
@@ -233,9 +370,9 @@ impl PriorityMuxer {
         // Remove the future
         self.timeouts.remove(&id);
 
-        // If the timeout priority is <=, then it was the current input
-        if current_priority >= priority {
-            debug!(priority = %current_priority, "current priority changed");
+        // The expired input was the visible one
+        if current_priority == priority {
+            debug!(priority = %self.current_priority(), "current priority changed");
             self.notify_output_change()
         } else {
             None
@@ -245,8 +382,26 @@ impl PriorityMuxer {
     pub async fn handle_message(&mut self, input: InputMessage) -> Option<MuxedMessage> {
         trace!(input = ?input, "got input");
 
+        let component = input_component(&input);
+        if self.disabled.contains(&component) {
+            match input.data() {
+                InputMessageData::ClearAll | InputMessageData::Clear { .. } => {}
+                InputMessageData::Effect { response, .. } => {
+                    if let Some(tx) = (*response.lock().await).take() {
+                        tx.send(Err(StartEffectError::Disabled(component))).ok();
+                    }
+
+                    return None;
+                }
+                _ => {
+                    trace!(component = %component, "ignoring input of disabled component");
+                    return None;
+                }
+            }
+        }
+
         // Check if this will change the output
-        match input.data() {
+        let output = match input.data() {
             InputMessageData::ClearAll => self.clear_all().await,
             InputMessageData::Clear { priority } => self.clear(*priority).await,
             InputMessageData::Effect {
@@ -276,18 +431,225 @@ impl PriorityMuxer {
                 None
             }
             _ => self.handle_input(input).await,
+        };
+
+        self.record_visible().await;
+        self.check_priorities();
+        output
+    }
+
+    /// Enable or disable the inputs of a component
+    ///
+    /// Disabling a component clears the priorities it registered, and ignores its inputs until it
+    /// is enabled again.
+    pub async fn set_component_enabled(
+        &mut self,
+        component: ComponentName,
+        enabled: bool,
+    ) -> Option<MuxedMessage> {
+        if enabled {
+            self.disabled.remove(&component);
+            return None;
+        }
+
+        if !self.disabled.insert(component) {
+            return None;
+        }
+
+        let priorities: Vec<_> = self
+            .inputs
+            .iter()
+            .filter(|(_, entry)| input_component(&entry.message) == component)
+            .map(|(&priority, _)| priority)
+            .collect();
+
+        let mut output = None;
+        for priority in priorities {
+            output = self.clear(priority).await.or(output);
+        }
+
+        self.record_visible().await;
+        self.check_priorities();
+        output
+    }
+
+    /// Check if inputs were registered or removed since the last check
+    fn check_priorities(&mut self) {
+        let registered = self
+            .inputs
+            .iter()
+            .map(|(&priority, entry)| RegisteredInput {
+                priority,
+                source_id: entry.message.source_id(),
+                component: entry.message.component(),
+                effect_key: entry.effect_key,
+            });
+
+        if !registered.clone().eq(self.registered.iter().copied()) {
+            self.registered = registered.collect();
+            self.priorities_changed = true;
+        }
+    }
+
+    /// Returns true if inputs were registered or removed since the last call
+    pub fn take_priorities_changed(&mut self) -> bool {
+        std::mem::take(&mut self.priorities_changed)
+    }
+
+    /// Update the arguments of the effect running at `priority`
+    ///
+    /// Effects which can't update their arguments while running are restarted with the new ones,
+    /// keeping their remaining duration.
+    pub async fn update_effect_args(
+        &mut self,
+        priority: i32,
+        args: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), StartEffectError> {
+        let entry = self
+            .inputs
+            .get(&priority)
+            .filter(|entry| entry.effect_key.is_some())
+            .ok_or(StartEffectError::NotRunning { priority })?;
+
+        let value: serde_json::Value = args.clone().into();
+        if let InputMessageData::Effect { effect, .. } = entry.message.data() {
+            self.effect_runner
+                .validate_args(&effect.name, &value)
+                .await?;
+        }
+
+        if self
+            .effect_runner
+            .update_args(entry.effect_key.unwrap(), value)
+        {
+            debug!(priority = %priority, "updated effect arguments");
+            return Ok(());
+        }
+
+        let (effect, response) = match entry.message.data() {
+            InputMessageData::Effect {
+                effect, response, ..
+            } => (
+                EffectRequest {
+                    name: effect.name.clone(),
+                    args,
+                },
+                response.clone(),
+            ),
+            _ => return Err(StartEffectError::NotRunning { priority }),
+        };
+
+        let duration = entry
+            .expires
+            .map(|expires| expires.saturating_duration_since(Instant::now()))
+            .and_then(|remaining| chrono::Duration::from_std(remaining).ok());
+        let (source_id, component) = (entry.message.source_id(), entry.message.component());
+
+        debug!(priority = %priority, "restarting effect with new arguments");
+        let key = self
+            .effect_runner
+            .start(priority, duration, &effect)
+            .await?;
+
+        // The new effect replaces the previous one in the priority list
+        self.insert_input(
+            priority,
+            InputMessage::new(
+                source_id,
+                component,
+                InputMessageData::Effect {
+                    priority,
+                    duration,
+                    effect: Arc::new(effect),
+                    response,
+                },
+            ),
+            Some(key),
+        );
+
+        self.check_priorities();
+        Ok(())
+    }
+
+    /// Show the input at `priority` instead of the lowest priority, or go back to auto-selection
+    ///
+    /// The selection lasts until the selected input is cleared or expires.
+    pub fn select_priority(
+        &mut self,
+        priority: Option<i32>,
+    ) -> Result<Option<MuxedMessage>, SelectPriorityError> {
+        if let Some(priority) = priority {
+            if !self.inputs.contains_key(&priority) {
+                return Err(SelectPriorityError::NotFound { priority });
+            }
+        }
+
+        if self.selected == priority {
+            return Ok(None);
         }
+
+        let previous = self.current_priority();
+        self.selected = priority;
+        debug!(priority = ?priority, "selected priority changed");
+
+        // The visible flags and auto-selection state of the priorities changed
+        self.priorities_changed = true;
+
+        Ok(if previous != self.current_priority() {
+            self.notify_output_change()
+        } else {
+            None
+        })
+    }
+
+    /// true if the visible input is the one with the lowest priority
+    pub fn is_auto_select(&self) -> bool {
+        self.selected.is_none()
+    }
+
+    /// Record a change of the visible input in the timeline
+    async fn record_visible(&mut self) {
+        let visible = self.current_input().map(|(priority, entry)| VisibleInput {
+            priority,
+            source_id: entry.message.source_id(),
+            component: entry.message.component(),
+        });
+
+        if !self.timeline.is_change(visible.as_ref()) {
+            return;
+        }
+
+        let visible = match visible {
+            Some(visible) => Some((
+                visible,
+                self.global
+                    .read_input_sources(|sources| {
+                        sources
+                            .get(&visible.source_id)
+                            .map(|source| source.name().to_string())
+                            .unwrap_or_default()
+                    })
+                    .await,
+            )),
+            None => None,
+        };
+
+        self.timeline.transition(visible);
+    }
+
+    /// Last changes of the visible input, most recent first
+    pub fn timeline(&self) -> impl Iterator<Item = &TimelineEvent> {
+        self.timeline.iter()
     }
 
     pub async fn current_priorities(&self) -> Vec<PriorityInfo> {
         self.global
             .read_input_sources(|sources| {
-                // Inputs are sorted by priority, so i == 0 denotes the
-                // current (active) entry
+                let current_priority = self.current_priority();
+
                 self.inputs
-                    .values()
-                    .enumerate()
-                    .map(|(i, entry)| {
+                    .iter()
+                    .map(|(&priority, entry)| {
                         PriorityInfo::new(
                             &entry.message,
                             sources
@@ -295,7 +657,7 @@ impl PriorityMuxer {
                                 .map(|source| source.name().to_string())
                                 .unwrap_or_default(),
                             entry.expires,
-                            i == 0,
+                            priority == current_priority,
                         )
                     })
                     .collect()
@@ -311,23 +673,19 @@ impl PriorityMuxer {
             Some(msg) => {
                 match msg {
                     EffectRunnerUpdate::Message(msg) => {
-                        (msg.priority() <= self.current_priority()).then_some(msg)
+                        self.is_visible(msg.priority()).then_some(msg)
                     }
                     EffectRunnerUpdate::Completed { key, priority } => {
                         let notify = self.current_priority() == priority;
 
-                        // Remove corresponding input entry
-                        let entry = self.inputs.entry(priority);
-                        match entry {
-                            std::collections::btree_map::Entry::Vacant(_) => {
-                                // Effect was already removed by a clear call or similar
-                            }
-                            std::collections::btree_map::Entry::Occupied(entry) => {
-                                // Remove the input entry if it's the one that triggered the effect
-                                if entry.get().effect_key == Some(key) {
-                                    entry.remove();
-                                }
-                            }
+                        // Remove the input entry if it's the one that triggered the effect, it
+                        // may already have been removed by a clear call or similar
+                        if self
+                            .inputs
+                            .get(&priority)
+                            .is_some_and(|entry| entry.effect_key == Some(key))
+                        {
+                            self.remove_input(priority);
                         }
 
                         // Notify of the priority change, if any
@@ -347,11 +705,15 @@ impl PriorityMuxer {
     }
 
     pub async fn update(&mut self) -> Option<MuxedMessage> {
-        // Check for input timeouts
-        if !self.timeouts.is_empty() {
+        // Check for input timeouts, only the earliest one can expire first
+        let output = if let Some((&id, &InputTimeout { priority, expires })) = self
+            .timeouts
+            .iter()
+            .min_by_key(|(_, timeout)| timeout.expires)
+        {
             select! {
-                id = futures::future::select_all(self.timeouts.values().map(|f| f())) => {
-                    self.handle_timeout(id.0).await
+                _ = tokio::time::sleep_until(expires.into()) => {
+                    self.handle_timeout((id, priority)).await
                 },
                 msg = self.effect_runner.update() => {
                     self.handle_effect_message(msg).await
@@ -360,6 +722,149 @@ impl PriorityMuxer {
         } else {
             let msg = self.effect_runner.update().await;
             self.handle_effect_message(msg).await
-        }
+        };
+
+        self.record_visible().await;
+        self.check_priorities();
+        output
+    }
+}
+
+/// Component an input belongs to, for enabling or disabling it
+///
+/// Effect requests are sent by API clients on behalf of the effect component.
+fn input_component(input: &InputMessage) -> ComponentName {
+    match input.data() {
+        InputMessageData::Effect { .. } => ComponentName::Effect,
+        _ => input.component(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::{
+        global::{GlobalData, Paths},
+        image::RawImage,
+        models::{Config, GlobalConfig},
+    };
+
+    async fn muxer() -> PriorityMuxer {
+        let config = Config::new(GlobalConfig::default(), []);
+        let global = GlobalData::new(&config, Paths::new(None).unwrap()).wrap();
+
+        PriorityMuxer::new(
+            global,
+            Default::default(),
+            MuxerConfig {
+                instance_id: 0,
+                led_count: 1,
+                effect_queue_bytes: 1 << 20,
+                latch_time: Default::default(),
+                image_size: (1, 1),
+            },
+        )
+        .await
+    }
+
+    fn color(color: Color, duration: Option<i64>) -> InputMessage {
+        InputMessage::new(
+            1,
+            ComponentName::Color,
+            InputMessageData::SolidColor {
+                priority: 100,
+                duration: duration.map(chrono::Duration::milliseconds),
+                color,
+            },
+        )
+    }
+
+    fn image(data: Vec<u8>, duration: Option<i64>) -> InputMessage {
+        InputMessage::new(
+            1,
+            ComponentName::Image,
+            InputMessageData::Image {
+                priority: 100,
+                duration: duration.map(chrono::Duration::milliseconds),
+                image: Arc::new(RawImage::try_from((data, 1, 1)).unwrap()),
+                region: None,
+            },
+        )
+    }
+
+    /// Input id and expiry of the entry at priority 100
+    fn entry(muxer: &PriorityMuxer) -> (usize, Option<Instant>) {
+        let entry = &muxer.inputs[&100];
+        (entry.input_id, entry.expires)
+    }
+
+    #[tokio::test]
+    async fn test_retrigger_extends_expiry() {
+        let mut muxer = muxer().await;
+        let red = Color::new(255, 0, 0);
+
+        assert!(muxer.handle_message(color(red, Some(1000))).await.is_some());
+        let (id, expires) = entry(&muxer);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // The same color again only moves the deadline, without a new output
+        assert!(muxer.handle_message(color(red, Some(1000))).await.is_none());
+        let (retriggered_id, retriggered_expires) = entry(&muxer);
+        assert_eq!(retriggered_id, id);
+        assert!(retriggered_expires.unwrap() > expires.unwrap());
+        assert_eq!(muxer.timeouts[&id].expires, retriggered_expires.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_retrigger_different_output() {
+        let mut muxer = muxer().await;
+        let (red, green) = (Color::new(255, 0, 0), Color::new(0, 255, 0));
+
+        muxer.handle_message(color(red, Some(1000))).await;
+        let (id, _) = entry(&muxer);
+
+        // Another color is a new input, which replaces the output
+        let output = muxer.handle_message(color(green, Some(1000))).await;
+        assert_eq!(output.and_then(|message| message.color()), Some(green));
+        assert_ne!(entry(&muxer).0, id);
+
+        // Same for images with other pixels
+        assert!(muxer
+            .handle_message(image(vec![1, 2, 3], Some(1000)))
+            .await
+            .is_some());
+        let (id, _) = entry(&muxer);
+        assert!(muxer
+            .handle_message(image(vec![1, 2, 3], Some(1000)))
+            .await
+            .is_none());
+        assert_eq!(entry(&muxer).0, id);
+        assert!(muxer
+            .handle_message(image(vec![4, 5, 6], Some(1000)))
+            .await
+            .is_some());
+        assert_ne!(entry(&muxer).0, id);
+    }
+
+    #[tokio::test]
+    async fn test_retrigger_without_duration() {
+        let mut muxer = muxer().await;
+        let red = Color::new(255, 0, 0);
+
+        // Inputs without a duration are always inserted again
+        assert!(muxer.handle_message(color(red, None)).await.is_some());
+        let (id, expires) = entry(&muxer);
+        assert_eq!(expires, None);
+
+        assert!(muxer.handle_message(color(red, None)).await.is_some());
+        assert_ne!(entry(&muxer).0, id);
+
+        // Including when they replace an input with a duration, or the other way around
+        assert!(muxer.handle_message(color(red, Some(1000))).await.is_some());
+        assert!(muxer.handle_message(color(red, None)).await.is_some());
+        assert_eq!(entry(&muxer).1, None);
     }
 }
@@ -0,0 +1,102 @@
+use super::MuxedMessage;
+
+/// Last output of the muxer within a tick
+///
+/// When priorities switch several times in a row, e.g. an input expires just before a new one
+/// arrives, only the final state reaches the core, so the device doesn't get conflicting frames
+/// back to back.
+#[derive(Debug, Default)]
+pub struct OutputCoalescer {
+    pending: Option<MuxedMessage>,
+}
+
+impl OutputCoalescer {
+    pub fn push(&mut self, mut message: MuxedMessage) {
+        if let Some(previous) = self.pending.take() {
+            trace!(
+                previous = %previous.priority(),
+                next = %message.priority(),
+                "coalescing muxer output"
+            );
+
+            // The region of an image is relative to the previous image of its priority, which may
+            // be the one that was just dropped
+            message.clear_region();
+        }
+
+        self.pending = Some(message);
+    }
+
+    pub fn take(&mut self) -> Option<MuxedMessage> {
+        self.pending.take()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        component::ComponentName,
+        global::{GlobalData, InputMessage, InputMessageData, Message, Paths},
+        instance::muxer::{MuxerConfig, PriorityMuxer},
+        models::{Color, Config, GlobalConfig},
+    };
+
+    fn color(priority: i32, color: Color, duration: Option<i64>) -> InputMessage {
+        InputMessage::new(
+            1,
+            ComponentName::Color,
+            InputMessageData::SolidColor {
+                priority,
+                duration: duration.map(chrono::Duration::milliseconds),
+                color,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_rapid_switch() {
+        let config = Config::new(GlobalConfig::default(), []);
+        let global = GlobalData::new(&config, Paths::new(None).unwrap()).wrap();
+        let mut muxer = PriorityMuxer::new(
+            global,
+            Default::default(),
+            MuxerConfig {
+                instance_id: 0,
+                led_count: 1,
+                effect_queue_bytes: 1 << 20,
+                latch_time: Default::default(),
+                image_size: (1, 1),
+            },
+        )
+        .await;
+        let mut output = OutputCoalescer::default();
+
+        let (red, green, blue) = (
+            Color::new(255, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(0, 0, 255),
+        );
+
+        for input in [color(150, blue, None), color(100, red, Some(20))] {
+            if let Some(message) = muxer.handle_message(input).await {
+                output.push(message);
+            }
+        }
+        assert_eq!(output.take().and_then(|m| m.color()), Some(red));
+
+        // The red input expires, and a green one arrives in the same tick: blue is never output
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        output.push(muxer.update().await.unwrap());
+        output.push(muxer.handle_message(color(100, green, None)).await.unwrap());
+
+        assert_eq!(output.take().and_then(|m| m.color()), Some(green));
+        assert!(!output.is_pending());
+    }
+}
@@ -1,12 +1,18 @@
-use slotmap::SlotMap;
+use std::{sync::Arc, time::Duration};
+
+use slotmap::{SecondaryMap, SlotMap};
 use thiserror::Error;
 use tokio::sync::mpsc;
 
 use crate::{
     api::json::message::EffectRequest,
-    effects::{self, EffectDefinitionError, EffectRunHandle, RunEffectError},
+    component::ComponentName,
+    effects::{
+        self, EffectDefinitionError, EffectRunHandle, InstanceEffects, RunEffectError, SchemaError,
+    },
     global::Global,
     instance::muxer::MuxedMessageData,
+    models::SmoothingOverride,
 };
 
 use super::MuxedMessage;
@@ -17,8 +23,14 @@ pub enum StartEffectError {
     Definition(#[from] EffectDefinitionError),
     #[error(transparent)]
     Run(#[from] RunEffectError),
+    #[error("invalid effect arguments: {0}")]
+    InvalidArgs(#[from] SchemaError),
     #[error("effect '{name}' not found")]
     NotFound { name: String },
+    #[error("no effect running at priority {priority}")]
+    NotRunning { priority: i32 },
+    #[error("the {0} component is disabled")]
+    Disabled(ComponentName),
 }
 
 slotmap::new_key_type! { pub struct RunningEffectKey; }
@@ -36,26 +48,36 @@ pub enum EffectRunnerUpdate {
 
 #[derive(Debug, Clone, Copy)]
 pub struct EffectRunnerConfig {
+    /// Id of the instance, checkpoints of its effects are kept separate from other instances
+    pub instance_id: i32,
     pub led_count: usize,
+    pub effect_queue_bytes: usize,
+    pub latch_time: Duration,
+    pub image_size: (u32, u32),
 }
 
 pub struct EffectRunner {
     global: Global,
+    effects: Arc<InstanceEffects>,
     effect_tx: mpsc::Sender<EffectMessage>,
     effect_rx: mpsc::Receiver<EffectMessage>,
     running_effects: SlotMap<RunningEffectKey, Option<EffectRunHandle>>,
+    /// Smoothing settings requested by the running effects
+    smoothing: SecondaryMap<RunningEffectKey, Arc<SmoothingOverride>>,
     config: EffectRunnerConfig,
 }
 
 impl EffectRunner {
-    pub fn new(global: Global, config: EffectRunnerConfig) -> Self {
+    pub fn new(global: Global, effects: Arc<InstanceEffects>, config: EffectRunnerConfig) -> Self {
         let (effect_tx, effect_rx) = mpsc::channel(4);
 
         Self {
             global,
+            effects,
             effect_tx,
             effect_rx,
             running_effects: Default::default(),
+            smoothing: Default::default(),
             config,
         }
     }
@@ -66,6 +88,39 @@ impl EffectRunner {
         }
     }
 
+    /// Send new arguments to a running effect
+    ///
+    /// # Returns
+    ///
+    /// `false` if the effect needs to be restarted to use them
+    pub fn update_args(&mut self, key: RunningEffectKey, args: serde_json::Value) -> bool {
+        if let Some(Some(handle)) = self.running_effects.get_mut(key) {
+            handle.update_args(args)
+        } else {
+            false
+        }
+    }
+
+    /// Check arguments against the schema of the effect `name`, if it has one
+    pub async fn validate_args(
+        &self,
+        name: &str,
+        args: &serde_json::Value,
+    ) -> Result<(), SchemaError> {
+        self.global
+            .read_effects(|effects| {
+                match self
+                    .effects
+                    .find_effect(effects, name)
+                    .and_then(|handle| handle.definition.schema.as_ref())
+                {
+                    Some(schema) => schema.validate(args),
+                    None => Ok(()),
+                }
+            })
+            .await
+    }
+
     pub async fn clear_all(&mut self) -> bool {
         let mut cleared_effects = false;
 
@@ -108,17 +163,45 @@ impl EffectRunner {
         duration: Option<chrono::Duration>,
         effect: &EffectRequest,
     ) -> Result<RunningEffectKey, StartEffectError> {
-        // TODO: Read per-instance effects
+        let checkpoint_dir = self
+            .global
+            .paths()
+            .await
+            .resolve_path(effects::CHECKPOINT_DIR);
+
+        if let Err(err) = self
+            .validate_args(&effect.name, &effect.args.clone().into())
+            .await
+        {
+            warn!(name = %effect.name, error = %err, "invalid effect arguments");
+            return Err(err.into());
+        }
+
         self.global
             .clone()
             .read_effects(|effects| {
                 // Find the effect definition
-                let result = if let Some(handle) = effects.find_effect(&effect.name) {
+                let result = if let Some(handle) = self.effects.find_effect(effects, &effect.name) {
                     let key = self.running_effects.insert(None);
+                    let smoothing = effect_smoothing(&handle.definition.args, &effect.args);
+
+                    let args: serde_json::Value = effect.args.clone().into();
+                    let checkpoint = effects::Checkpoint::new(
+                        &checkpoint_dir,
+                        self.config.instance_id,
+                        &effect.name,
+                        args.clone(),
+                    );
 
                     match handle.run(
-                        effect.args.clone().into(),
-                        self.config.led_count,
+                        args,
+                        effects::EffectRunConfig {
+                            led_count: self.config.led_count,
+                            queue_bytes: self.config.effect_queue_bytes,
+                            latch_time: self.config.latch_time,
+                            image_size: self.config.image_size,
+                            checkpoint: Some(checkpoint),
+                        },
                         duration,
                         priority,
                         self.effect_tx.clone(),
@@ -126,6 +209,9 @@ impl EffectRunner {
                     ) {
                         Ok(handle) => {
                             *self.running_effects.get_mut(key).unwrap() = Some(handle);
+                            if let Some(smoothing) = smoothing {
+                                self.smoothing.insert(key, Arc::new(smoothing));
+                            }
                             info!(name = %effect.name, "started effect");
                             Ok(key)
                         }
@@ -182,6 +268,8 @@ impl EffectRunner {
                 .expect("handle shouldn't be null")
         };
 
+        let smoothing = self.smoothing.get(key).cloned();
+
         // Turn this into a MuxedMessage
         match msg.kind {
             effects::EffectMessageKind::SetColor { color } => Some(EffectRunnerUpdate::Message(
@@ -189,7 +277,8 @@ impl EffectRunner {
                     priority: running_effect().priority,
                     duration: None,
                     color,
-                }),
+                })
+                .with_smoothing(smoothing),
             )),
 
             effects::EffectMessageKind::SetImage { image } => Some(EffectRunnerUpdate::Message(
@@ -197,19 +286,25 @@ impl EffectRunner {
                     priority: running_effect().priority,
                     duration: None,
                     image: image.clone(),
-                }),
+                    region: None,
+                })
+                .with_smoothing(smoothing),
             )),
 
-            effects::EffectMessageKind::SetLedColors { colors } => Some(
-                EffectRunnerUpdate::Message(MuxedMessage::new(MuxedMessageData::LedColors {
-                    priority: running_effect().priority,
-                    duration: None,
-                    led_colors: colors.clone(),
-                })),
-            ),
+            effects::EffectMessageKind::SetLedColors { colors } => {
+                Some(EffectRunnerUpdate::Message(
+                    MuxedMessage::new(MuxedMessageData::LedColors {
+                        priority: running_effect().priority,
+                        duration: None,
+                        led_colors: colors.clone(),
+                    })
+                    .with_smoothing(smoothing),
+                ))
+            }
 
             effects::EffectMessageKind::Completed { result } => {
                 // The effect has completed, remove it from the running_effects list
+                self.smoothing.remove(key);
                 let priority = if let Some(mut effect) = self.running_effects.remove(key).flatten()
                 {
                     effect.finish().await;
@@ -233,3 +328,69 @@ impl EffectRunner {
         }
     }
 }
+
+/// Smoothing settings requested by an effect through the `smoothing-*` arguments of hyperion.ng
+///
+/// Arguments of the request take precedence over those of the definition. Nothing is requested
+/// unless `smoothing-custom-settings` is true.
+fn effect_smoothing(
+    definition: &serde_json::Value,
+    request: &serde_json::Map<String, serde_json::Value>,
+) -> Option<SmoothingOverride> {
+    let arg = |name: &str| request.get(name).or_else(|| definition.get(name));
+
+    if !arg("smoothing-custom-settings")?.as_bool()? {
+        return None;
+    }
+
+    Some(SmoothingOverride {
+        enable: Some(true),
+        time_ms: arg("smoothing-time_ms")
+            .and_then(serde_json::Value::as_u64)
+            .map(|time_ms| time_ms.clamp(25, 5000) as u32),
+        update_frequency: arg("smoothing-updateFrequency")
+            .and_then(serde_json::Value::as_f64)
+            .map(|frequency| frequency.clamp(1., 2000.) as f32),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_effect_smoothing() {
+        let definition = json!({
+            "smoothing-custom-settings": true,
+            "smoothing-time_ms": 50,
+            "smoothing-updateFrequency": 60.0,
+        });
+
+        assert_eq!(
+            effect_smoothing(&definition, &Default::default()),
+            Some(SmoothingOverride {
+                enable: Some(true),
+                time_ms: Some(50),
+                update_frequency: Some(60.),
+            })
+        );
+
+        // The request can turn the custom settings off, or change them
+        let request = json!({ "smoothing-custom-settings": false });
+        assert_eq!(
+            effect_smoothing(&definition, request.as_object().unwrap()),
+            None
+        );
+
+        let request = json!({ "smoothing-time_ms": 1 });
+        assert_eq!(
+            effect_smoothing(&definition, request.as_object().unwrap())
+                .and_then(|smoothing| smoothing.time_ms),
+            Some(25)
+        );
+
+        assert_eq!(effect_smoothing(&json!({}), &Default::default()), None);
+    }
+}
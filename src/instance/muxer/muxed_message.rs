@@ -1,21 +1,57 @@
-use std::{convert::TryFrom, sync::Arc};
+use std::{convert::TryFrom, sync::Arc, time::Instant};
 
 use super::InputMessageData;
-use crate::{image::RawImage, models::Color};
+use crate::{
+    image::{ImageRegion, RawImage},
+    models::{Color, SmoothingOverride},
+};
 
 #[derive(Debug, Clone)]
 pub struct MuxedMessage {
     data: MuxedMessageData,
+    /// Time at which the data was captured
+    timestamp: Instant,
+    /// Smoothing settings of the input, if they replace the instance ones
+    smoothing: Option<Arc<SmoothingOverride>>,
 }
 
 impl MuxedMessage {
     pub fn new(data: MuxedMessageData) -> Self {
-        Self { data }
+        Self {
+            data,
+            timestamp: Instant::now(),
+            smoothing: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Instant) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_smoothing(mut self, smoothing: Option<Arc<SmoothingOverride>>) -> Self {
+        self.smoothing = smoothing;
+        self
     }
 
     pub fn data(&self) -> &MuxedMessageData {
         &self.data
     }
+
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    pub fn smoothing(&self) -> Option<&SmoothingOverride> {
+        self.smoothing.as_deref()
+    }
+
+    /// Process the whole image instead of its updated region
+    pub fn clear_region(&mut self) {
+        if let MuxedMessageData::Image { region, .. } = &mut self.data {
+            *region = None;
+        }
+    }
 }
 
 impl std::ops::Deref for MuxedMessage {
@@ -38,6 +74,7 @@ pub enum MuxedMessageData {
         priority: i32,
         duration: Option<chrono::Duration>,
         image: Arc<RawImage>,
+        region: Option<ImageRegion>,
     },
     LedColors {
         priority: i32,
@@ -84,10 +121,12 @@ impl TryFrom<InputMessageData> for MuxedMessageData {
                 priority,
                 duration,
                 image,
+                region,
             } => Ok(Self::Image {
                 priority,
                 duration,
                 image,
+                region,
             }),
             InputMessageData::LedColors {
                 priority,
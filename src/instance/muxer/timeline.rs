@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+use crate::component::ComponentName;
+
+/// Number of events kept by [PriorityTimeline]
+pub const PRIORITY_TIMELINE_CAPACITY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineEventKind {
+    /// The input became the visible one
+    Activated,
+    /// The input is no longer the visible one
+    Deactivated,
+}
+
+/// Change of the visible input of a muxer
+#[derive(Debug, Clone)]
+pub struct TimelineEvent {
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub kind: TimelineEventKind,
+    pub priority: i32,
+    pub component: ComponentName,
+    /// Name of the input source
+    pub origin: String,
+}
+
+/// Identity of the visible input: a source replacing its own input at the same priority (e.g. a
+/// stream of images) does not count as a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisibleInput {
+    pub priority: i32,
+    pub source_id: usize,
+    pub component: ComponentName,
+}
+
+/// Ring buffer of the last changes of the visible input
+#[derive(Debug, Default)]
+pub struct PriorityTimeline {
+    events: VecDeque<TimelineEvent>,
+    visible: Option<(VisibleInput, String)>,
+}
+
+impl PriorityTimeline {
+    /// Returns true if `input` is not the currently visible input
+    pub fn is_change(&self, input: Option<&VisibleInput>) -> bool {
+        self.visible.as_ref().map(|(visible, _)| visible) != input
+    }
+
+    /// Record that `input` is now the visible input, deactivating the previous one
+    pub fn transition(&mut self, input: Option<(VisibleInput, String)>) {
+        let time = chrono::Utc::now();
+
+        if let Some((previous, origin)) = self.visible.take() {
+            self.push(TimelineEvent {
+                time,
+                kind: TimelineEventKind::Deactivated,
+                priority: previous.priority,
+                component: previous.component,
+                origin,
+            });
+        }
+
+        if let Some((current, origin)) = &input {
+            self.push(TimelineEvent {
+                time,
+                kind: TimelineEventKind::Activated,
+                priority: current.priority,
+                component: current.component,
+                origin: origin.clone(),
+            });
+        }
+
+        self.visible = input;
+    }
+
+    fn push(&mut self, event: TimelineEvent) {
+        if self.events.len() >= PRIORITY_TIMELINE_CAPACITY {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
+    }
+
+    /// Iterate over the recorded events, most recent first
+    pub fn iter(&self) -> impl Iterator<Item = &TimelineEvent> {
+        self.events.iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(priority: i32, source_id: usize) -> VisibleInput {
+        VisibleInput {
+            priority,
+            source_id,
+            component: ComponentName::Color,
+        }
+    }
+
+    #[test]
+    fn test_timeline_transitions() {
+        let mut timeline = PriorityTimeline::default();
+
+        timeline.transition(Some((input(256, 0), String::new())));
+        assert!(!timeline.is_change(Some(&input(256, 0))));
+        assert!(timeline.is_change(Some(&input(100, 1))));
+
+        timeline.transition(Some((input(100, 1), "JSON".to_owned())));
+
+        let events: Vec<_> = timeline
+            .iter()
+            .map(|event| (event.kind, event.priority))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (TimelineEventKind::Activated, 100),
+                (TimelineEventKind::Deactivated, 256),
+                (TimelineEventKind::Activated, 256),
+            ]
+        );
+
+        for i in 0..PRIORITY_TIMELINE_CAPACITY {
+            timeline.transition(Some((input(i as i32, 1), String::new())));
+        }
+
+        assert_eq!(timeline.iter().count(), PRIORITY_TIMELINE_CAPACITY);
+        assert_eq!(
+            timeline.iter().next().map(|event| event.priority),
+            Some(PRIORITY_TIMELINE_CAPACITY as i32 - 1)
+        );
+    }
+}
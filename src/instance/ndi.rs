@@ -0,0 +1,408 @@
+//! NDI receiver input
+//!
+//! The NDI runtime is not linked at build time, it is loaded when the receiver starts. If it isn't
+//! installed in a standard location, set `NDI_RUNTIME_DIR_V6` (or `NDI_RUNTIME_DIR_V5`) to the
+//! directory containing it.
+
+use std::{
+    convert::TryFrom,
+    ffi::{c_char, c_void, CString},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use libloading::Library;
+use thiserror::Error;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    global::{
+        Global, InputMessage, InputMessageData, InputSourceError, InputSourceName, Message,
+        PriorityGuard,
+    },
+    image::RawImage,
+    models,
+};
+
+#[derive(Debug, Error)]
+pub enum NdiError {
+    #[error("cannot load the NDI runtime: {0}")]
+    Load(#[from] libloading::Error),
+    #[error("the NDI runtime failed to initialize")]
+    Initialize,
+    #[error("invalid source name: {0}")]
+    InvalidSource(#[from] std::ffi::NulError),
+    #[error("cannot connect to source")]
+    CreateReceiver,
+    #[error("connection to the source was lost")]
+    ConnectionLost,
+    #[error(transparent)]
+    InputSource(#[from] InputSourceError),
+}
+
+/// Time to wait for a frame before checking if the receiver should stop, in milliseconds
+const CAPTURE_TIMEOUT_MS: u32 = 500;
+
+/// Time to wait after an error, before the runtime tries reconnecting
+const ERROR_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Definitions from Processing.NDI.Lib.h
+
+const RECV_COLOR_FORMAT_RGBX_RGBA: i32 = 2;
+const RECV_BANDWIDTH_LOWEST: i32 = 0;
+const RECV_BANDWIDTH_HIGHEST: i32 = 100;
+const FRAME_TYPE_VIDEO: i32 = 1;
+const FRAME_TYPE_ERROR: i32 = 4;
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+const FOURCC_RGBA: u32 = fourcc(b"RGBA");
+const FOURCC_RGBX: u32 = fourcc(b"RGBX");
+
+#[repr(C)]
+struct Source {
+    p_ndi_name: *const c_char,
+    p_url_address: *const c_char,
+}
+
+#[repr(C)]
+struct RecvCreateV3 {
+    source_to_connect_to: Source,
+    color_format: i32,
+    bandwidth: i32,
+    allow_video_fields: bool,
+    p_ndi_recv_name: *const c_char,
+}
+
+#[repr(C)]
+struct VideoFrameV2 {
+    xres: i32,
+    yres: i32,
+    four_cc: u32,
+    frame_rate_n: i32,
+    frame_rate_d: i32,
+    picture_aspect_ratio: f32,
+    frame_format_type: i32,
+    timecode: i64,
+    p_data: *mut u8,
+    line_stride_in_bytes: i32,
+    p_metadata: *const c_char,
+    timestamp: i64,
+}
+
+type InitializeFn = unsafe extern "C" fn() -> bool;
+type RecvCreateV3Fn = unsafe extern "C" fn(*const RecvCreateV3) -> *mut c_void;
+type RecvDestroyFn = unsafe extern "C" fn(*mut c_void);
+type RecvCaptureV2Fn =
+    unsafe extern "C" fn(*mut c_void, *mut VideoFrameV2, *mut c_void, *mut c_void, u32) -> i32;
+type RecvFreeVideoV2Fn = unsafe extern "C" fn(*mut c_void, *const VideoFrameV2);
+
+/// Loaded NDI runtime
+struct Runtime {
+    recv_create_v3: RecvCreateV3Fn,
+    recv_destroy: RecvDestroyFn,
+    recv_capture_v2: RecvCaptureV2Fn,
+    recv_free_video_v2: RecvFreeVideoV2Fn,
+    // Must outlive the function pointers above
+    _library: Library,
+}
+
+impl Runtime {
+    #[cfg(target_os = "windows")]
+    const LIBRARY_NAMES: &'static [&'static str] = &["Processing.NDI.Lib.x64.dll"];
+    #[cfg(target_os = "macos")]
+    const LIBRARY_NAMES: &'static [&'static str] = &["libndi.dylib"];
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    const LIBRARY_NAMES: &'static [&'static str] = &["libndi.so.6", "libndi.so.5", "libndi.so"];
+
+    fn candidates() -> impl Iterator<Item = PathBuf> {
+        ["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR_V5"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .flat_map(|dir| {
+                Self::LIBRARY_NAMES
+                    .iter()
+                    .map(move |name| PathBuf::from(&dir).join(name))
+            })
+            .chain(Self::LIBRARY_NAMES.iter().map(PathBuf::from))
+    }
+
+    fn load() -> Result<Self, NdiError> {
+        let mut last_error = None;
+
+        for candidate in Self::candidates() {
+            // Safety: loading the NDI runtime has no initialization side-effects
+            match unsafe { Library::new(&candidate) } {
+                Ok(library) => {
+                    debug!(path = %candidate.display(), "loaded NDI runtime");
+                    return Self::from_library(library);
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.expect("no NDI runtime candidates").into())
+    }
+
+    fn from_library(library: Library) -> Result<Self, NdiError> {
+        // Safety: the symbol types match the declarations in Processing.NDI.Lib.h
+        unsafe {
+            let initialize = *library.get::<InitializeFn>(b"NDIlib_initialize\0")?;
+            let this = Self {
+                recv_create_v3: *library.get(b"NDIlib_recv_create_v3\0")?,
+                recv_destroy: *library.get(b"NDIlib_recv_destroy\0")?,
+                recv_capture_v2: *library.get(b"NDIlib_recv_capture_v2\0")?,
+                recv_free_video_v2: *library.get(b"NDIlib_recv_free_video_v2\0")?,
+                _library: library,
+            };
+
+            if !initialize() {
+                return Err(NdiError::Initialize);
+            }
+
+            Ok(this)
+        }
+    }
+}
+
+/// Connection to an NDI source
+struct Receiver {
+    runtime: Runtime,
+    instance: *mut c_void,
+}
+
+impl Receiver {
+    fn connect(config: &models::NdiReceiver, name: &str) -> Result<Self, NdiError> {
+        let runtime = Runtime::load()?;
+
+        let source = CString::new(config.source.as_str())?;
+        let url_address = CString::new(config.url_address.as_str())?;
+        let name = CString::new(name)?;
+
+        let settings = RecvCreateV3 {
+            source_to_connect_to: Source {
+                p_ndi_name: source.as_ptr(),
+                p_url_address: if config.url_address.is_empty() {
+                    std::ptr::null()
+                } else {
+                    url_address.as_ptr()
+                },
+            },
+            color_format: RECV_COLOR_FORMAT_RGBX_RGBA,
+            bandwidth: match config.bandwidth {
+                models::NdiBandwidth::Lowest => RECV_BANDWIDTH_LOWEST,
+                models::NdiBandwidth::Highest => RECV_BANDWIDTH_HIGHEST,
+            },
+            allow_video_fields: false,
+            p_ndi_recv_name: name.as_ptr(),
+        };
+
+        // Safety: settings and the strings it points to are valid for the duration of the call
+        let instance = unsafe { (runtime.recv_create_v3)(&settings) };
+        if instance.is_null() {
+            return Err(NdiError::CreateReceiver);
+        }
+
+        Ok(Self { runtime, instance })
+    }
+
+    /// Wait for the next video frame
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if no frame was received before the timeout
+    fn capture(&mut self, timeout_ms: u32) -> Result<Option<RawImage>, NdiError> {
+        // Safety: the frame is only read if the runtime filled it in
+        unsafe {
+            let mut frame = std::mem::zeroed::<VideoFrameV2>();
+            let frame_type = (self.runtime.recv_capture_v2)(
+                self.instance,
+                &mut frame,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                timeout_ms,
+            );
+
+            match frame_type {
+                FRAME_TYPE_VIDEO => {
+                    let image = Self::convert(&frame);
+                    (self.runtime.recv_free_video_v2)(self.instance, &frame);
+                    Ok(image)
+                }
+                FRAME_TYPE_ERROR => Err(NdiError::ConnectionLost),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    /// Convert an RGBX or RGBA frame to a raw image
+    ///
+    /// # Safety
+    ///
+    /// `frame` must have been filled in by the NDI runtime and not freed yet
+    unsafe fn convert(frame: &VideoFrameV2) -> Option<RawImage> {
+        if frame.four_cc != FOURCC_RGBA && frame.four_cc != FOURCC_RGBX {
+            warn!(four_cc = %frame.four_cc, "unsupported NDI frame format");
+            return None;
+        }
+
+        if frame.xres <= 0
+            || frame.yres <= 0
+            || frame.line_stride_in_bytes < frame.xres.saturating_mul(4)
+            || frame.p_data.is_null()
+        {
+            warn!(
+                xres = %frame.xres,
+                yres = %frame.yres,
+                stride = %frame.line_stride_in_bytes,
+                "invalid NDI frame dimensions"
+            );
+            return None;
+        }
+
+        let (width, height) = (frame.xres as usize, frame.yres as usize);
+        let stride = frame.line_stride_in_bytes as usize;
+        let data = std::slice::from_raw_parts(frame.p_data, stride * height);
+
+        let mut rgb = Vec::with_capacity(width * height * RawImage::CHANNELS as usize);
+        for line in data.chunks_exact(stride) {
+            for pixel in line[..width * 4].chunks_exact(4) {
+                rgb.extend_from_slice(&pixel[..3]);
+            }
+        }
+
+        RawImage::try_from((rgb, width as u32, height as u32)).ok()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        // Safety: instance was created by recv_create_v3 and is destroyed only once
+        unsafe { (self.runtime.recv_destroy)(self.instance) }
+    }
+}
+
+/// Handle to a running NDI receiver, which stops it when dropped
+pub struct NdiReceiverHandle {
+    stop: Arc<AtomicBool>,
+    _join_handle: JoinHandle<()>,
+}
+
+impl Drop for NdiReceiverHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Start receiving images from the configured NDI source
+///
+/// Images are sent to `tx` at the configured priority, until the returned handle is dropped.
+pub async fn start(
+    config: models::NdiReceiver,
+    receiver_name: String,
+    global: Global,
+    tx: mpsc::Sender<InputMessage>,
+) -> Result<NdiReceiverHandle, NdiError> {
+    let source_handle = global
+        .register_input_source(
+            InputSourceName::Ndi {
+                source: config.source.clone(),
+            },
+            Some(config.priority),
+        )
+        .await?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let join_handle = tokio::task::spawn_blocking({
+        let stop = stop.clone();
+
+        move || {
+            let mut receiver = match Receiver::connect(&config, &receiver_name) {
+                Ok(receiver) => receiver,
+                Err(error) => {
+                    error!(source = %config.source, error = %error, "cannot start NDI receiver");
+                    return;
+                }
+            };
+
+            info!(source = %config.source, "NDI receiver started");
+
+            // Clear our priority when stopping
+            let _guard = PriorityGuard::new_mpsc(tx.clone(), &source_handle);
+
+            while !stop.load(Ordering::Relaxed) {
+                match receiver.capture(CAPTURE_TIMEOUT_MS) {
+                    Ok(Some(image)) => {
+                        let message = InputMessage::new(
+                            source_handle.id(),
+                            source_handle.name().component(),
+                            InputMessageData::Image {
+                                priority: config.priority,
+                                duration: None,
+                                image: Arc::new(image),
+                                region: None,
+                            },
+                        );
+
+                        if tx.blocking_send(message).is_err() {
+                            // Instance stopped
+                            break;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        // The runtime reconnects on its own
+                        warn!(source = %config.source, error = %error, "NDI receiver error");
+                        std::thread::sleep(ERROR_DELAY);
+                    }
+                }
+            }
+
+            info!(source = %config.source, "NDI receiver stopped");
+        }
+    });
+
+    Ok(NdiReceiverHandle {
+        stop,
+        _join_handle: join_handle,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(xres: i32, yres: i32, stride: i32, data: &mut [u8]) -> VideoFrameV2 {
+        // Safety: all-zero is a valid VideoFrameV2
+        let mut frame = unsafe { std::mem::zeroed::<VideoFrameV2>() };
+        frame.xres = xres;
+        frame.yres = yres;
+        frame.four_cc = FOURCC_RGBX;
+        frame.line_stride_in_bytes = stride;
+        frame.p_data = data.as_mut_ptr();
+        frame
+    }
+
+    #[test]
+    fn test_convert() {
+        // 1x2 frame with padding at the end of each line
+        let mut data = [1, 2, 3, 255, 0, 0, 4, 5, 6, 255, 0, 0];
+
+        // Safety: the frames point to data, which holds yres lines of stride bytes
+        unsafe {
+            let image = Receiver::convert(&frame(1, 2, 6, &mut data)).unwrap();
+            assert_eq!(image.data(), &[1, 2, 3, 4, 5, 6]);
+
+            assert!(Receiver::convert(&frame(2, 2, 6, &mut data)).is_none());
+            assert!(Receiver::convert(&frame(0, 2, 6, &mut data)).is_none());
+            assert!(Receiver::convert(&frame(1, -1, 6, &mut data)).is_none());
+            assert!(Receiver::convert(&frame(1, 2, 0, &mut data)).is_none());
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use chrono::{NaiveTime, Timelike};
+
+use crate::models;
+
+const MINUTES_PER_DAY: f32 = 24. * 60.;
+
+/// Interval between two evaluations of the brightness cap
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Brightness cap of an instance over the day
+pub struct QuietHours {
+    config: models::QuietHours,
+    interval: tokio::time::Interval,
+    current: f32,
+}
+
+impl QuietHours {
+    pub fn new(config: models::QuietHours) -> Self {
+        let current = brightness_cap(&config, chrono::Local::now().time());
+
+        Self {
+            config,
+            interval: tokio::time::interval(UPDATE_INTERVAL),
+            current,
+        }
+    }
+
+    /// Current brightness cap, between 0 and 1
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    /// Wait for the brightness cap to change
+    pub async fn update(&mut self) -> f32 {
+        if !self.config.enable {
+            return futures::future::pending().await;
+        }
+
+        loop {
+            self.interval.tick().await;
+
+            let cap = brightness_cap(&self.config, chrono::Local::now().time());
+            if cap != self.current {
+                self.current = cap;
+                return cap;
+            }
+        }
+    }
+}
+
+fn minutes(time: NaiveTime) -> f32 {
+    time.num_seconds_from_midnight() as f32 / 60.
+}
+
+/// Minutes from `from` to `to`, wrapping around midnight
+fn minutes_until(from: f32, to: f32) -> f32 {
+    (to - from).rem_euclid(MINUTES_PER_DAY)
+}
+
+/// Compute the brightness cap at the given local time
+fn brightness_cap(config: &models::QuietHours, time: NaiveTime) -> f32 {
+    if !config.enable {
+        return 1.;
+    }
+
+    let cap = config.max_brightness as f32 / 100.;
+    let (start, end, now) = (minutes(config.start), minutes(config.end), minutes(time));
+
+    if minutes_until(start, now) < minutes_until(start, end) {
+        // Within the quiet hours
+        return cap;
+    }
+
+    let ramp = config.ramp_minutes as f32;
+    let ramp_factor = |distance: f32| {
+        if distance < ramp {
+            cap + (1. - cap) * distance / ramp
+        } else {
+            1.
+        }
+    };
+
+    // Ramp down before the start, ramp up after the end
+    ramp_factor(minutes_until(now, start)).min(ramp_factor(minutes_until(end, now)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_hours_ramps() {
+        let config = models::QuietHours {
+            enable: true,
+            max_brightness: 20,
+            ramp_minutes: 10,
+            ..Default::default()
+        };
+
+        let at = |h, m| brightness_cap(&config, NaiveTime::from_hms_opt(h, m, 0).unwrap());
+
+        assert_eq!(at(12, 0), 1.);
+        assert_eq!(at(21, 50), 1.);
+        assert!((at(21, 55) - 0.6).abs() < 1e-4);
+        assert_eq!(at(22, 0), 0.2);
+        assert_eq!(at(2, 0), 0.2);
+        assert!((at(7, 5) - 0.6).abs() < 1e-4);
+        assert_eq!(at(7, 10), 1.);
+    }
+}
@@ -0,0 +1,94 @@
+//! Display of the captured screen
+
+use thiserror::Error;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    global::{
+        Global, InputMessage, InputMessageData, InputSourceError, InputSourceName, Message,
+        PriorityGuard,
+    },
+    grabber::screen::ScreenCaptureError,
+    models,
+};
+
+#[derive(Debug, Error)]
+pub enum ScreenError {
+    #[error(transparent)]
+    Capture(#[from] ScreenCaptureError),
+    #[error(transparent)]
+    InputSource(#[from] InputSourceError),
+}
+
+/// Handle to the forwarding task, which stops it when dropped
+pub struct ScreenHandle {
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for ScreenHandle {
+    fn drop(&mut self) {
+        self.join_handle.abort();
+    }
+}
+
+/// Start showing the captured screen
+///
+/// Frames are sent to `tx` at the system priority of `config`, until the returned handle is
+/// dropped. Returns `None` if no screen grabber is configured or detected.
+pub async fn start(
+    config: &models::InstanceCapture,
+    global: Global,
+    tx: mpsc::Sender<InputMessage>,
+) -> Result<Option<ScreenHandle>, ScreenError> {
+    let capture = match global.screen_capture().await? {
+        Some(capture) => capture,
+        None => return Ok(None),
+    };
+
+    let source = global
+        .register_input_source(
+            InputSourceName::SystemGrabber {
+                name: capture.name().to_owned(),
+            },
+            Some(config.system_priority),
+        )
+        .await?;
+    let priority = config.system_priority;
+
+    let join_handle = tokio::spawn(async move {
+        // Clear our priority when stopping
+        let mut guard = PriorityGuard::new_mpsc(tx.clone(), &source);
+        let mut frames = capture.subscribe();
+
+        while frames.changed().await.is_ok() {
+            let frame = frames.borrow_and_update().clone();
+            let image = match frame {
+                Some(image) => image,
+                None => {
+                    // The capture stopped, don't keep showing its last frame
+                    guard.clear().await;
+                    continue;
+                }
+            };
+
+            guard.set_priority(Some(priority));
+            let message = InputMessage::new(
+                source.id(),
+                source.name().component(),
+                InputMessageData::Image {
+                    priority,
+                    duration: None,
+                    image,
+                    region: None,
+                },
+            );
+
+            if tx.send(message).await.is_err() {
+                // The instance stopped
+                break;
+            }
+        }
+    });
+
+    Ok(Some(ScreenHandle { join_handle }))
+}
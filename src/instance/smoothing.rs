@@ -3,10 +3,16 @@ use std::time::{Duration, Instant};
 use crate::models;
 
 // TODO: Implement decay smoothing
-// TODO: Implement dithering
 
 pub struct Smoothing {
+    /// Settings of the instance
+    base: models::Smoothing,
+    /// Settings in use, those of the instance unless the visible input overrides them
     config: models::Smoothing,
+    /// Override of the visible input, if any
+    current_override: Option<models::SmoothingOverride>,
+    /// false if smoothing was disabled at runtime
+    enabled: bool,
     led_data: Vec<models::Color>,
     current_data: Vec<models::Color16>,
     target_data: Vec<models::Color16>,
@@ -20,7 +26,10 @@ impl Smoothing {
         let now = Instant::now();
 
         Self {
+            base: config.clone(),
             config,
+            current_override: None,
+            enabled: true,
             led_data: vec![Default::default(); led_count],
             current_data: vec![Default::default(); led_count],
             target_data: vec![Default::default(); led_count],
@@ -30,9 +39,45 @@ impl Smoothing {
         }
     }
 
+    fn is_enabled(&self) -> bool {
+        self.config.enable && self.enabled
+    }
+
+    /// Enable or disable smoothing at runtime, on top of the configuration
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+
+            // Jump to the target right away when disabling
+            let now = Instant::now();
+            self.plan_update(now);
+            self.next_update.get_or_insert(now);
+        }
+    }
+
+    /// Use the smoothing settings requested by the visible input, or the instance ones if it
+    /// didn't request any
+    ///
+    /// The settings apply from the next target on.
+    pub fn set_override(&mut self, smoothing: Option<&models::SmoothingOverride>) {
+        if self.current_override.as_ref() == smoothing {
+            return;
+        }
+
+        if let Some(smoothing) = smoothing {
+            debug!(smoothing = ?smoothing, "using the smoothing settings of the input");
+        }
+
+        self.current_override = smoothing.cloned();
+        self.config = match smoothing {
+            Some(smoothing) => self.base.with_override(smoothing),
+            None => self.base.clone(),
+        };
+    }
+
     /// Given the current time, prepare the next update
     fn plan_update(&mut self, now: Instant) -> SmoothingUpdate {
-        if self.config.enable && now < self.target_time {
+        if self.is_enabled() && now < self.target_time {
             // Smoothing enabled, the continuous update should happen at that time
             let next_update = self.next_update.unwrap_or(
                 now + Duration::from_micros(
@@ -63,13 +108,8 @@ impl Smoothing {
                     .clamp(0, 65535) as u16;
             }
         } else {
-            // Smoothing disabled, update as soon as possible
-            if self.config.enable {
-                self.next_update = None;
-            } else {
-                // Or linear update complete, color is stable
-                self.next_update = Some(now);
-            }
+            // Smoothing disabled or linear update complete, color is stable
+            self.next_update = None;
 
             // Update current data from target data
             self.current_data.copy_from_slice(&self.target_data);
@@ -96,7 +136,19 @@ impl Smoothing {
         self.previous_write_time = now;
         self.target_time = now + Duration::from_millis(self.config.time_ms as _);
 
+        // Without smoothing, the new data still has to be output once
         self.plan_update(now);
+        self.next_update.get_or_insert(now);
+    }
+
+    /// LED data from the last update
+    pub fn current(&self) -> &[models::Color] {
+        &self.led_data
+    }
+
+    /// Smoothed colors from the last update, before they are truncated to 8 bits
+    pub fn current16(&self) -> &[models::Color16] {
+        &self.current_data
     }
 
     pub async fn update(&mut self) -> (&[models::Color], SmoothingUpdate) {
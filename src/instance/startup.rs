@@ -0,0 +1,239 @@
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::models::{InstanceStartup, ReadinessProbe};
+
+#[derive(Debug, Error)]
+enum ProbeError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("timed out")]
+    Timeout,
+    #[error("unsupported url: {0}")]
+    InvalidUrl(String),
+    #[error("unexpected response: {0}")]
+    Response(String),
+}
+
+/// Wait until the device of an instance should be initialized
+pub async fn wait_ready(config: &InstanceStartup) {
+    if config.delay_ms > 0 {
+        debug!(delay_ms = %config.delay_ms, "delaying device start");
+        tokio::time::sleep(Duration::from_millis(config.delay_ms as _)).await;
+    }
+
+    let probe = match &config.probe {
+        Some(probe) => probe,
+        None => return,
+    };
+
+    let interval = Duration::from_millis(config.probe_interval_ms as _);
+    let deadline = Instant::now() + Duration::from_millis(config.probe_timeout_ms as _);
+    let mut notified = false;
+
+    loop {
+        let started = Instant::now();
+
+        match tokio::time::timeout(interval, run_probe(probe)).await {
+            Ok(Ok(())) => {
+                debug!(probe = ?probe, "device is ready");
+                return;
+            }
+            Ok(Err(error)) if !notified => {
+                notified = true;
+                info!(probe = ?probe, error = %error, "waiting for device to become ready");
+            }
+            Err(_) if !notified => {
+                notified = true;
+                info!(probe = ?probe, error = %ProbeError::Timeout, "waiting for device to become ready");
+            }
+            _ => {}
+        }
+
+        if Instant::now() >= deadline {
+            warn!(probe = ?probe, "device is still not ready, starting anyway");
+            return;
+        }
+
+        tokio::time::sleep_until((started + interval).into()).await;
+    }
+}
+
+async fn run_probe(probe: &ReadinessProbe) -> Result<(), ProbeError> {
+    match probe {
+        ReadinessProbe::Tcp { host, port } => {
+            TcpStream::connect((host.as_str(), *port)).await?;
+            Ok(())
+        }
+        ReadinessProbe::Http { url } => http_get(url).await,
+    }
+}
+
+async fn http_get(url: &str) -> Result<(), ProbeError> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| ProbeError::InvalidUrl(url.to_owned()))?;
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| ProbeError::InvalidUrl(url.to_owned()))?,
+        ),
+        None => (authority, 80u16),
+    };
+
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                path, authority
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).await?;
+
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') || status.starts_with('3') => Ok(()),
+        _ => Err(ProbeError::Response(status_line.trim().to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncReadExt, net::TcpListener, task::JoinHandle};
+    use validator::Validate;
+
+    use super::*;
+
+    /// Serve a single HTTP request with the given status line, returning the request
+    async fn http_server(status: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buffer = [0; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buffer).await.unwrap();
+                assert!(n > 0, "connection closed before the end of the request");
+                request.extend_from_slice(&buffer[..n]);
+            }
+
+            stream
+                .write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes())
+                .await
+                .unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let probe = ReadinessProbe::Tcp {
+            host: "127.0.0.1".to_owned(),
+            port: listener.local_addr().unwrap().port(),
+        };
+
+        assert!(run_probe(&probe).await.is_ok());
+
+        drop(listener);
+        assert!(matches!(run_probe(&probe).await, Err(ProbeError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn test_http_probe() {
+        let (url, server) = http_server("200 OK").await;
+        let authority = url
+            .trim_start_matches("http://")
+            .trim_end_matches("/health")
+            .to_owned();
+
+        assert!(run_probe(&ReadinessProbe::Http { url }).await.is_ok());
+        assert_eq!(
+            server.await.unwrap(),
+            format!(
+                "GET /health HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                authority
+            )
+        );
+
+        let (url, server) = http_server("503 Service Unavailable").await;
+        assert!(matches!(
+            run_probe(&ReadinessProbe::Http { url }).await,
+            Err(ProbeError::Response(status)) if status == "HTTP/1.1 503 Service Unavailable"
+        ));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_probe_invalid_url() {
+        for url in ["https://127.0.0.1/health", "http://127.0.0.1:http/"] {
+            let probe = ReadinessProbe::Http {
+                url: url.to_owned(),
+            };
+
+            assert!(matches!(
+                run_probe(&probe).await,
+                Err(ProbeError::InvalidUrl(invalid)) if invalid == url
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate_probe_url() {
+        let startup = |url: &str| InstanceStartup {
+            probe: Some(ReadinessProbe::Http {
+                url: url.to_owned(),
+            }),
+            ..Default::default()
+        };
+
+        assert!(startup("http://127.0.0.1/health").validate().is_ok());
+        assert!(startup("https://127.0.0.1/health").validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let config = InstanceStartup {
+            probe: Some(ReadinessProbe::Tcp {
+                host: "127.0.0.1".to_owned(),
+                port,
+            }),
+            probe_interval_ms: 100,
+            probe_timeout_ms: 300,
+            ..Default::default()
+        };
+
+        // The device is started anyway once the probe timeout expires
+        let started = Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), wait_ready(&config))
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+}
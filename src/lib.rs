@@ -1,17 +1,26 @@
 #![allow(clippy::comparison_chain)]
+// Failed sends return the input message in their error, which only happens on shutdown
+#![allow(clippy::result_large_err)]
 
 #[macro_use]
 extern crate tracing;
 
 pub mod api;
+mod builder;
+pub use builder::*;
+pub mod client;
 pub mod color;
 pub mod component;
 pub mod db;
 pub mod effects;
+pub mod forwarder;
 pub mod global;
+pub mod grabber;
 pub mod image;
 pub mod instance;
 pub mod models;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod serde;
 pub mod servers;
 pub mod web;
@@ -1,9 +1,8 @@
 #[macro_use]
 extern crate tracing;
 
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
-use hyperion::effects::EffectRegistry;
 use structopt::StructOpt;
 use tokio::runtime::Builder;
 use tokio::signal;
@@ -23,12 +22,39 @@ struct Opts {
         env = "DATABASE_URL"
     )]
     database_path: PathBuf,
+    /// Open the configuration database in read-only mode. This is also enabled automatically if
+    /// the database can't be written to
+    #[structopt(long)]
+    read_only: bool,
     /// Path to a TOML config file. Overrides the configuration database
     #[structopt(short, long = "config")]
     config_path: Option<PathBuf>,
+    /// Write back a config file upgraded from an older layout, keeping the original as a .bak
+    /// file. Comments are not preserved
+    #[structopt(long)]
+    upgrade_config: bool,
+    /// Fail on unknown configuration keys, instead of ignoring them with a warning
+    #[structopt(long)]
+    strict_config: bool,
+    /// Don't advertise the servers over mDNS
+    #[structopt(long)]
+    no_mdns: bool,
+    /// Don't answer SSDP searches
+    #[structopt(long)]
+    no_ssdp: bool,
     /// Dump the loaded configuration
     #[structopt(long)]
     dump_config: bool,
+    /// Print the configuration of a new instance created from a template (ws2812-spi, pipe,
+    /// wled, adalight), to be added to the configuration file
+    #[structopt(long)]
+    instance_template: Option<hyperion::models::InstanceTemplate>,
+    /// Number of LEDs for --instance-template
+    #[structopt(long, default_value = "60")]
+    led_count: u32,
+    /// Device output for --instance-template, e.g. the SPI device node, serial port or WLED hostname
+    #[structopt(long)]
+    device_output: Option<String>,
     /// Path to the user root folder. Defaults to .config/hyperion.rs (Linux) or
     /// %APPDATA%\hyperion.rs (Windows)
     #[structopt(long)]
@@ -38,191 +64,152 @@ struct Opts {
     core_threads: Option<usize>,
 }
 
-async fn run(opts: Opts) -> color_eyre::eyre::Result<()> {
+async fn run(opts: Opts, log_buffer: hyperion::global::LogBuffer) -> color_eyre::eyre::Result<()> {
     // Path resolver
     let paths = hyperion::global::Paths::new(opts.user_root.clone())?;
 
+    // Report blocked runtime workers for the whole lifetime of the process
+    tokio::spawn(hyperion::global::Watchdog::new().run());
+
+    while run_daemon(&opts, &paths, &log_buffer).await? {
+        info!("reloading configuration");
+    }
+
+    Ok(())
+}
+
+/// Load the configuration and run all components until asked to stop
+///
+/// # Returns
+///
+/// true if the daemon should be started again with a reloaded configuration
+async fn run_daemon(
+    opts: &Opts,
+    paths: &hyperion::global::Paths,
+    log_buffer: &hyperion::global::LogBuffer,
+) -> color_eyre::eyre::Result<bool> {
     // Load configuration
+    let mut database_path = None;
+    let config_backend;
     let mut backend: Box<dyn hyperion::models::backend::ConfigBackend> =
         if let Some(config_path) = opts.config_path.as_deref() {
-            Box::new(hyperion::models::backend::FileBackend::new(config_path))
+            config_backend = format!("file {}", config_path.display());
+            Box::new(
+                hyperion::models::backend::FileBackend::new(config_path)
+                    .write_upgrade(opts.upgrade_config)
+                    .strict(opts.strict_config),
+            )
         } else {
             // Connect to database
-            let db = hyperion::db::Db::open(&paths.resolve_path(opts.database_path)).await?;
-            Box::new(hyperion::models::backend::DbBackend::new(db))
+            let path = paths.resolve_path(opts.database_path.clone());
+            let db = if opts.read_only {
+                hyperion::db::Db::open_read_only(&path).await?
+            } else {
+                hyperion::db::Db::open(&path).await?
+            };
+            config_backend = format!("database {}", path.display());
+            database_path = Some(path);
+            Box::new(hyperion::models::backend::DbBackend::new(db).strict(opts.strict_config))
         };
 
     let config = backend.load().await?;
 
+    let warnings = config.warnings();
+
     // Dump configuration if this was asked
     if opts.dump_config {
+        for warning in &warnings {
+            println!("# warning: {}", warning);
+        }
+
         print!("{}", config.to_string()?);
-        return Ok(());
+        return Ok(false);
     }
 
-    // Create the global state object
-    let global = hyperion::global::GlobalData::new(&config).wrap();
-
-    // Discover effects
-    let mut effects = EffectRegistry::new();
-    let providers = hyperion::effects::Providers::new();
-
-    // TODO: Per-instance effect discovery
-    for path in ["$SYSTEM/effects"] {
-        // Resolve path variables
-        let path = paths.resolve_path(path);
-
-        // Discover effect files
-        let mut discovered = hyperion::effects::EffectDefinition::read_dir(&path).await?;
-        discovered.sort_by(|a, b| a.file.cmp(&b.file));
-
-        // Register them
-        effects.add_definitions(&providers, discovered);
+    // Print a new instance if this was asked
+    if let Some(template) = opts.instance_template {
+        let id = config.instances.keys().last().map_or(0, |id| id + 1);
+        let instance = template.instantiate(
+            id,
+            &hyperion::models::TemplateParams {
+                led_count: opts.led_count,
+                output: opts.device_output.clone(),
+                ..Default::default()
+            },
+        );
+
+        let mut instances = BTreeMap::new();
+        instances.insert(id.to_string(), instance);
+        let mut root = BTreeMap::new();
+        root.insert("instances", instances);
+
+        print!("{}", toml::to_string_pretty(&root)?);
+        return Ok(false);
     }
 
-    info!("discovered {} effects", effects.len());
-
-    global
-        .write_effects(|e| {
-            // Replace effect registry with our discovered one
-            *e = effects;
-        })
-        .await;
-
-    // Spawn the hook runner
-    tokio::spawn(
-        hyperion::global::HookRunner::new(
-            config.global.hooks.clone(),
-            global.subscribe_events().await,
-        )
-        .run(),
-    );
-
-    // Keep a list of all instances
-    let mut instances = Vec::with_capacity(config.instances.len());
-
-    // Initialize and spawn the devices
-    for (&id, inst) in &config.instances {
-        // Create the instance
-        let (inst, handle) = hyperion::instance::Instance::new(global.clone(), inst.clone()).await;
-        // Register the instance globally using its handle
-        global.register_instance(handle.clone()).await;
-        // Keep it around
-        instances.push(handle);
-        // Run the instance futures
-        tokio::spawn({
-            let global = global.clone();
-            let event_tx = global.get_event_tx().await;
-
-            async move {
-                event_tx
-                    .send(hyperion::global::Event::instance(
-                        id,
-                        hyperion::global::InstanceEventKind::Start,
-                    ))
-                    .map(|_| ())
-                    .unwrap_or_else(|err| {
-                        error!(error = %err, "event error");
-                    });
-
-                let result = inst.run().await;
-
-                if let Err(error) = result {
-                    error!(error = %error, "instance error");
-                }
-
-                global.unregister_instance(id).await;
-
-                event_tx
-                    .send(hyperion::global::Event::instance(
-                        id,
-                        hyperion::global::InstanceEventKind::Stop,
-                    ))
-                    .map(|_| ())
-                    .unwrap_or_else(|err| {
-                        error!(error = %err, "event error");
-                    });
-            }
-        });
+    for warning in &warnings {
+        warn!(%warning, "configuration warning");
     }
 
-    // Start the Flatbuffers servers
-    let _flatbuffers_server = if config.global.flatbuffers_server.enable {
-        Some(
-            hyperion::servers::bind(
-                "Flatbuffers",
-                config.global.flatbuffers_server.clone(),
-                global.clone(),
-                hyperion::servers::flat::handle_client,
-            )
-            .await?,
-        )
-    } else {
-        None
-    };
-
-    // Start the JSON server
-    let _json_server = hyperion::servers::bind(
-        "JSON",
-        config.global.json_server,
-        global.clone(),
-        hyperion::servers::json::handle_client,
-    )
-    .await?;
-
-    // Start the Protobuf server
-    let _proto_server = if config.global.proto_server.enable {
-        Some(
-            hyperion::servers::bind(
-                "Protobuf",
-                config.global.proto_server.clone(),
-                global.clone(),
-                hyperion::servers::proto::handle_client,
-            )
-            .await?,
-        )
-    } else {
-        None
-    };
+    // Create the global state object
+    let read_only = backend.is_read_only();
+    if read_only {
+        warn!("running in read-only mode, configuration changes will be refused");
+    }
 
-    // Start the webconfig server
-    let _webconfig_server = tokio::task::spawn(
-        hyperion::web::bind(global.clone(), &config.global.web_config, &paths).await?,
+    let environment = hyperion::global::EnvironmentReport::new(
+        &config,
+        if read_only {
+            format!("{} (read-only)", config_backend)
+        } else {
+            config_backend
+        },
     );
-
-    // Global event handle
-    let event_tx = global.get_event_tx().await;
-
-    // We have started
-    event_tx.send(hyperion::global::Event::Start)?;
+    environment.log();
+
+    let mut builder = hyperion::Builder::new(config)
+        .paths(paths.clone())
+        .read_only(read_only)
+        .environment(environment)
+        .log_buffer(log_buffer.clone())
+        .servers(true)
+        .mdns(!opts.no_mdns)
+        .ssdp(!opts.no_ssdp);
+    if let Some(path) = database_path {
+        builder = builder.database_path(path);
+    }
+    let hyperion = builder.build().await?;
 
     // Should we continue running?
     let mut abort = false;
+    let mut reload = false;
 
     while !abort {
         tokio::select! {
             _ = signal::ctrl_c() => {
                 abort = true;
             }
+            _ = hyperion.global().reload_requested() => {
+                abort = true;
+                reload = true;
+            }
         }
     }
 
-    // Stop all instances
-    for instance in instances.into_iter() {
-        instance.stop().await.ok();
-    }
-
-    // We have finished running properly
-    event_tx.send(hyperion::global::Event::Stop)?;
+    hyperion.stop().await;
 
-    Ok(())
+    Ok(reload)
 }
 
-fn install_tracing(opts: &Opts) -> Result<(), tracing_subscriber::util::TryInitError> {
+fn install_tracing(
+    opts: &Opts,
+    log_buffer: &hyperion::global::LogBuffer,
+) -> Result<(), tracing_subscriber::util::TryInitError> {
     use tracing_error::ErrorLayer;
     use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-    let fmt_layer = fmt::layer();
+    // Log to stderr, stdout may be used for LED data by the pipe device
+    let fmt_layer = fmt::layer().with_writer(std::io::stderr);
 
     let filter_layer = EnvFilter::try_from_env("HYPERION_LOG").unwrap_or_else(|_| {
         EnvFilter::new(match opts.verbose {
@@ -236,6 +223,8 @@ fn install_tracing(opts: &Opts) -> Result<(), tracing_subscriber::util::TryInitE
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
+        // Records streamed to JSON clients, filtered like the ones on stderr
+        .with(log_buffer.layer())
         .with(ErrorLayer::default())
         .try_init()
 }
@@ -243,7 +232,8 @@ fn install_tracing(opts: &Opts) -> Result<(), tracing_subscriber::util::TryInitE
 #[paw::main]
 fn main(opts: Opts) -> color_eyre::eyre::Result<()> {
     color_eyre::install()?;
-    install_tracing(&opts)?;
+    let log_buffer = hyperion::global::LogBuffer::new();
+    install_tracing(&opts, &log_buffer)?;
 
     // Create tokio runtime
     let thd_count = opts
@@ -254,5 +244,5 @@ fn main(opts: Opts) -> color_eyre::eyre::Result<()> {
         .worker_threads(thd_count)
         .enable_all()
         .build()?;
-    rt.block_on(run(opts))
+    rt.block_on(run(opts, log_buffer))
 }
@@ -1,7 +1,4 @@
-use std::{
-    collections::BTreeMap,
-    convert::{TryFrom, TryInto},
-};
+use std::{collections::BTreeMap, convert::TryFrom};
 
 use serde_derive::{Deserialize, Serialize};
 use strum_macros::{EnumDiscriminants, EnumString};
@@ -27,14 +24,33 @@ pub use layouts::*;
 mod meta;
 pub use meta::*;
 
+mod templates;
+pub use templates::*;
+
+mod unknown_keys;
+use unknown_keys::*;
+
 mod users;
 pub use users::*;
 
+mod warnings;
+pub use warnings::*;
+
 pub type Color = palette::rgb::LinSrgb<u8>;
 pub type Color16 = palette::rgb::LinSrgb<u16>;
 
 pub trait ServerConfig {
     fn port(&self) -> u16;
+
+    /// Time after which clients which didn't send any request are disconnected
+    fn timeout(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+/// Client idle timeout from a number of seconds, 0 disabling it
+fn timeout_secs(seconds: u32) -> Option<std::time::Duration> {
+    (seconds > 0).then(|| std::time::Duration::from_secs(seconds as _))
 }
 
 fn default_true() -> bool {
@@ -68,6 +84,7 @@ pub enum SettingData {
     Framegrabber(Framegrabber),
     General(General),
     GrabberV4L2(GrabberV4L2),
+    GrabberAudio(GrabberAudio),
     InstanceCapture(InstanceCapture),
     JsonServer(JsonServer),
     LedConfig(LedConfig),
@@ -78,7 +95,19 @@ pub enum SettingData {
     Smoothing(Smoothing),
     WebConfig(WebConfig),
     // hyperion.rs settings
+    BootSplash(BootSplash),
     Hooks(Hooks),
+    Mqtt(Mqtt),
+    InstanceStartup(InstanceStartup),
+    QuietHours(QuietHours),
+    FrameLatency(FrameLatency),
+    MemoryLimits(MemoryLimits),
+    NdiReceiver(NdiReceiver),
+    MediaArtwork(MediaArtwork),
+    DataPresets(DataPresets),
+    InstanceMirror(InstanceMirror),
+    DryRun(DryRun),
+    UdpListener(UdpListener),
 }
 
 impl Validate for SettingData {
@@ -96,6 +125,7 @@ impl Validate for SettingData {
             SettingData::Framegrabber(setting) => setting.validate(),
             SettingData::General(setting) => setting.validate(),
             SettingData::GrabberV4L2(setting) => setting.validate(),
+            SettingData::GrabberAudio(setting) => setting.validate(),
             SettingData::InstanceCapture(setting) => setting.validate(),
             SettingData::JsonServer(setting) => setting.validate(),
             SettingData::LedConfig(setting) => setting.validate(),
@@ -105,11 +135,45 @@ impl Validate for SettingData {
             SettingData::ProtoServer(setting) => setting.validate(),
             SettingData::Smoothing(setting) => setting.validate(),
             SettingData::WebConfig(setting) => setting.validate(),
+            SettingData::BootSplash(setting) => setting.validate(),
             SettingData::Hooks(setting) => setting.validate(),
+            SettingData::Mqtt(setting) => setting.validate(),
+            SettingData::InstanceStartup(setting) => setting.validate(),
+            SettingData::QuietHours(setting) => setting.validate(),
+            SettingData::FrameLatency(setting) => setting.validate(),
+            SettingData::MemoryLimits(setting) => setting.validate(),
+            SettingData::NdiReceiver(setting) => setting.validate(),
+            SettingData::MediaArtwork(setting) => setting.validate(),
+            SettingData::DataPresets(setting) => setting.validate(),
+            SettingData::InstanceMirror(setting) => setting.validate(),
+            SettingData::DryRun(setting) => setting.validate(),
+            SettingData::UdpListener(setting) => setting.validate(),
         }
     }
 }
 
+impl SettingData {
+    /// Returns true if this setting applies to all instances
+    pub fn is_global(&self) -> bool {
+        matches!(
+            self,
+            SettingData::FlatbuffersServer(_)
+                | SettingData::Forwarder(_)
+                | SettingData::Framegrabber(_)
+                | SettingData::General(_)
+                | SettingData::GrabberV4L2(_)
+                | SettingData::GrabberAudio(_)
+                | SettingData::JsonServer(_)
+                | SettingData::Logger(_)
+                | SettingData::Network(_)
+                | SettingData::ProtoServer(_)
+                | SettingData::WebConfig(_)
+                | SettingData::Hooks(_)
+                | SettingData::Mqtt(_)
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum SettingErrorKind {
     #[error(transparent)]
@@ -163,14 +227,20 @@ impl std::fmt::Display for SettingError {
 }
 
 macro_rules! convert_settings {
-    ($db:ident, $($name:literal => $variant:ident),*) => {
+    ($db:ident, $strict:ident, $unknown:ident, $($name:literal => $variant:ident),*) => {
         match $db.ty.as_str() {
             $($name => {
-                let config = SettingData::$variant(serde_json::from_str(&$db.config).map_err(|err| {
+                let value: serde_json::Value = serde_json::from_str(&$db.config).map_err(|err| {
                     SettingError::new(err, $name)
-                })?);
+                })?;
+                let config = SettingData::$variant(
+                    deserialize_value(value, $strict, &mut $unknown).map_err(|err| {
+                        SettingError::new(err.into_inner(), $name)
+                    })?,
+                );
 
                 (
+                    $name,
                     match config.validate() {
                         Ok(_) => Ok(config),
                         Err(err) => Err(SettingError::new(err, $name)),
@@ -191,36 +261,70 @@ impl TryFrom<db_models::DbSetting> for Setting {
     type Error = SettingError;
 
     fn try_from(db: db_models::DbSetting) -> Result<Self, Self::Error> {
-        let (config, updated_at) = convert_settings!(db,
-            "backgroundEffect" => BackgroundEffect,
-            "blackborderdetector" => BlackBorderDetector,
-            "boblightServer" => BoblightServer,
-            "color" => ColorAdjustment,
-            "device" => Device,
-            "effects" => Effects,
-            "flatbufServer" => FlatbuffersServer,
-            "foregroundEffect" => ForegroundEffect,
-            "forwarder" => Forwarder,
-            "framegrabber" => Framegrabber,
-            "general" => General,
-            "grabberV4L2" => GrabberV4L2,
-            "instCapture" => InstanceCapture,
-            "jsonServer" => JsonServer,
-            "ledConfig" => LedConfig,
-            "leds" => Leds,
-            "logger" => Logger,
-            "network" => Network,
-            "protoServer" => ProtoServer,
-            "smoothing" => Smoothing,
-            "webConfig" => WebConfig,
-            "hooks" => Hooks
+        Self::from_db(db, true).map(|(setting, _)| setting)
+    }
+}
+
+impl Setting {
+    /// Load a setting from the database
+    ///
+    /// Unless `strict` is set, unknown keys are ignored and returned as warnings.
+    pub fn from_db(
+        db: db_models::DbSetting,
+        strict: bool,
+    ) -> Result<(Self, Vec<ConfigWarning>), SettingError> {
+        let mut unknown = Vec::new();
+        let (name, config, updated_at) = convert_settings!(db, strict, unknown,
+                "backgroundEffect" => BackgroundEffect,
+                "blackborderdetector" => BlackBorderDetector,
+                "boblightServer" => BoblightServer,
+                "color" => ColorAdjustment,
+                "device" => Device,
+                "effects" => Effects,
+                "flatbufServer" => FlatbuffersServer,
+                "foregroundEffect" => ForegroundEffect,
+                "forwarder" => Forwarder,
+                "framegrabber" => Framegrabber,
+                "general" => General,
+                "grabberV4L2" => GrabberV4L2,
+                "grabberAudio" => GrabberAudio,
+                "instCapture" => InstanceCapture,
+                "jsonServer" => JsonServer,
+                "ledConfig" => LedConfig,
+                "leds" => Leds,
+                "logger" => Logger,
+                "network" => Network,
+                "protoServer" => ProtoServer,
+                "smoothing" => Smoothing,
+                "webConfig" => WebConfig,
+                "bootSplash" => BootSplash,
+                "hooks" => Hooks,
+                "mqtt" => Mqtt,
+                "instanceStartup" => InstanceStartup,
+                "quietHours" => QuietHours,
+                "frameLatency" => FrameLatency,
+                "memoryLimits" => MemoryLimits,
+                "ndiReceiver" => NdiReceiver,
+                "mediaArtwork" => MediaArtwork,
+                "dataPresets" => DataPresets,
+                "instanceMirror" => InstanceMirror,
+                "dryRun" => DryRun,
+                "udpListener" => UdpListener
         );
 
-        Ok(Self {
-            hyperion_inst: db.hyperion_inst,
-            config,
-            updated_at,
-        })
+        let warnings = unknown
+            .into_iter()
+            .map(|path| ConfigWarning::unknown_key(db.hyperion_inst, name, &path))
+            .collect();
+
+        Ok((
+            Self {
+                hyperion_inst: db.hyperion_inst,
+                config,
+                updated_at,
+            },
+            warnings,
+        ))
     }
 }
 
@@ -244,10 +348,26 @@ pub enum ConfigError {
     User(#[from] UserError),
     #[error("missing hyperion_inst field on instance setting {0}")]
     MissingHyperionInst(&'static str),
+    #[error("unknown instance {0}")]
+    UnknownInstance(i32),
     #[error("invalid TOML")]
     Toml(#[from] toml::de::Error),
+    #[error("invalid TOML at {location}")]
+    TomlKey {
+        location: String,
+        #[source]
+        source: toml::de::Error,
+    },
     #[error("instance id must be an integer, got {0}")]
     InvalidId(String),
+    #[error("configuration version {version} is newer than the supported version {supported}")]
+    UnsupportedVersion { version: i64, supported: u32 },
+    #[error("invalid configuration version: {0}")]
+    InvalidVersion(String),
+    #[error("error serializing configuration")]
+    SerializeToml(#[from] toml::ser::Error),
+    #[error("error serializing setting")]
+    SerializeSetting(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -256,11 +376,307 @@ pub struct Config {
     pub global: GlobalConfig,
     meta: Vec<Meta>,
     users: Vec<User>,
+    /// Issues found while loading, e.g. unknown keys
+    load_warnings: Vec<ConfigWarning>,
 }
 
 impl Config {
+    /// Create a configuration in code, e.g. for embedding hyperion
+    ///
+    /// Instances are identified by their `instance.id` field.
+    pub fn new(global: GlobalConfig, instances: impl IntoIterator<Item = InstanceConfig>) -> Self {
+        Self {
+            instances: instances
+                .into_iter()
+                .map(|instance| (instance.instance.id, instance))
+                .collect(),
+            global,
+            meta: vec![Meta::new()],
+            users: Vec::new(),
+            load_warnings: Vec::new(),
+        }
+    }
+
     pub fn uuid(&self) -> uuid::Uuid {
         // There should always be a meta uuid
         self.meta.first().map(|meta| meta.uuid).unwrap_or_default()
     }
+
+    /// Replace a setting of this configuration
+    pub fn apply_setting(&mut self, setting: Setting) -> Result<(), ConfigError> {
+        let global = &mut self.global;
+        let instance = match setting.hyperion_inst {
+            Some(id) => Some(
+                self.instances
+                    .get_mut(&id)
+                    .ok_or(ConfigError::UnknownInstance(id))?,
+            ),
+            None => None,
+        };
+        let instance = |name| instance.ok_or(ConfigError::MissingHyperionInst(name));
+
+        match setting.config {
+            SettingData::BackgroundEffect(config) => {
+                instance("backgroundEffect")?.background_effect = config
+            }
+            SettingData::BlackBorderDetector(config) => {
+                instance("blackborderdetector")?.black_border_detector = config
+            }
+            SettingData::BoblightServer(config) => {
+                instance("boblightServer")?.boblight_server = config
+            }
+            SettingData::ColorAdjustment(config) => instance("color")?.color = config,
+            SettingData::Device(config) => instance("device")?.device = config,
+            SettingData::Effects(config) => instance("effects")?.effects = config,
+            SettingData::ForegroundEffect(config) => {
+                instance("foregroundEffect")?.foreground_effect = config
+            }
+            SettingData::InstanceCapture(config) => {
+                instance("instCapture")?.instance_capture = config
+            }
+            SettingData::LedConfig(config) => instance("ledConfig")?.led_config = config,
+            SettingData::Leds(config) => instance("leds")?.leds = config,
+            SettingData::Smoothing(config) => instance("smoothing")?.smoothing = config,
+            SettingData::BootSplash(config) => instance("bootSplash")?.boot_splash = config,
+            SettingData::InstanceStartup(config) => instance("instanceStartup")?.startup = config,
+            SettingData::QuietHours(config) => instance("quietHours")?.quiet_hours = config,
+            SettingData::FrameLatency(config) => instance("frameLatency")?.frame_latency = config,
+            SettingData::MemoryLimits(config) => instance("memoryLimits")?.memory_limits = config,
+            SettingData::NdiReceiver(config) => instance("ndiReceiver")?.ndi_receiver = config,
+            SettingData::MediaArtwork(config) => instance("mediaArtwork")?.media_artwork = config,
+            SettingData::DataPresets(config) => instance("dataPresets")?.data_presets = config,
+            SettingData::InstanceMirror(config) => {
+                instance("instanceMirror")?.instance_mirror = config
+            }
+            SettingData::DryRun(config) => instance("dryRun")?.dry_run = config,
+            SettingData::UdpListener(config) => instance("udpListener")?.udp_listener = config,
+            SettingData::FlatbuffersServer(config) => global.flatbuffers_server = config,
+            SettingData::Forwarder(config) => global.forwarder = config,
+            SettingData::Framegrabber(config) => global.framegrabber = config,
+            SettingData::General(config) => global.general = config,
+            SettingData::GrabberV4L2(config) => global.grabber_v4l2 = config,
+            SettingData::GrabberAudio(config) => global.grabber_audio = config,
+            SettingData::JsonServer(config) => global.json_server = config,
+            SettingData::Logger(config) => global.logger = config,
+            SettingData::Network(config) => global.network = config,
+            SettingData::ProtoServer(config) => global.proto_server = config,
+            SettingData::WebConfig(config) => global.web_config = config,
+            SettingData::Hooks(config) => global.hooks = config,
+            SettingData::Mqtt(config) => global.mqtt = config,
+        }
+
+        Ok(())
+    }
+
+    /// User accounts and API tokens
+    pub fn users(&self) -> &[User] {
+        &self.users
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use proptest::{prelude::*, strategy::Union};
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Generate JSON values shaped like `value`, with some of its leaves replaced
+    ///
+    /// Most generated values still deserialize to the type `value` was serialized from, so
+    /// round-trip properties get exercised on more than just the defaults.
+    pub(crate) fn similar(value: Value) -> BoxedStrategy<Value> {
+        match value {
+            Value::Bool(_) => any::<bool>().prop_map(Value::Bool).boxed(),
+            Value::Number(number) if number.is_f64() => prop_oneof![
+                3 => Just(Value::Number(number)),
+                1 => (-1e3f64..1e3).prop_map(Value::from),
+            ]
+            .boxed(),
+            // Out of range integers are rare, since they make the whole value invalid
+            Value::Number(number) => prop_oneof![
+                8 => Just(Value::Number(number)),
+                3 => (0u64..=255).prop_map(Value::from),
+                1 => prop_oneof![
+                    any::<u32>().prop_map(Value::from),
+                    any::<i32>().prop_map(Value::from),
+                ],
+            ]
+            .boxed(),
+            Value::String(string) => prop_oneof![
+                3 => Just(string),
+                1 => "\\PC{0,12}",
+            ]
+            .prop_map(Value::String)
+            .boxed(),
+            Value::Array(items) => items
+                .into_iter()
+                .map(similar)
+                .collect::<Vec<_>>()
+                .prop_map(Value::Array)
+                .boxed(),
+            Value::Object(fields) => fields
+                .into_iter()
+                .map(|(key, value)| similar(value).prop_map(move |value| (key.clone(), value)))
+                .collect::<Vec<_>>()
+                .prop_map(|fields| Value::Object(fields.into_iter().collect()))
+                .boxed(),
+            Value::Null => Just(Value::Null).boxed(),
+        }
+    }
+
+    /// Generate JSON values shaped like any of `seeds`
+    pub(crate) fn similar_to<T: Serialize>(seeds: Vec<T>) -> BoxedStrategy<Value> {
+        Union::new(
+            seeds
+                .iter()
+                .map(|seed| similar(serde_json::to_value(seed).unwrap())),
+        )
+        .boxed()
+    }
+
+    /// TOML documents must be tables, so settings are round-tripped as a section
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Section<T> {
+        section: T,
+    }
+
+    fn assert_round_trip<T>(value: T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<T>(&json).unwrap(), value, "{}", json);
+
+        let section = Section { section: value };
+        let toml = toml::to_string(&section).unwrap();
+        assert_eq!(
+            toml::from_str::<Section<T>>(&toml).unwrap(),
+            section,
+            "{}",
+            toml
+        );
+    }
+
+    fn devices() -> Vec<Device> {
+        [
+            r#"{"type": "dummy"}"#,
+            r#"{"type": "ws2812spi", "hardwareLedCount": 1, "output": "/dev/spidev0.0"}"#,
+            r#"{"type": "sk6812spi", "hardwareLedCount": 1, "output": "/dev/spidev0.0"}"#,
+            r#"{"type": "apa102", "hardwareLedCount": 1, "output": "/dev/spidev0.0"}"#,
+            r#"{"type": "ws281x", "hardwareLedCount": 1, "gpio": 18, "dmaChannel": 10}"#,
+            r#"{"type": "e131", "hardwareLedCount": 1, "host": "192.168.1.10", "universe": 2}"#,
+            r#"{"type": "atmoorb", "hardwareLedCount": 2, "orbIds": "1, 2"}"#,
+            r#"{"type": "ddp", "hardwareLedCount": 1, "host": "192.168.1.10"}"#,
+            r#"{
+                "type": "philipshue",
+                "blackLightsTimeout": 15000,
+                "brightnessFactor": 1.0,
+                "brightnessMax": 1.0,
+                "brightnessMin": 0.0,
+                "brightnessThreshold": 0.0,
+                "clientkey": "",
+                "colorOrder": "rgb",
+                "debugLevel": "",
+                "debugStreamer": false,
+                "groupId": 0,
+                "hardwareLedCount": 1,
+                "lightIds": ["1"],
+                "output": "",
+                "restoreOriginalState": true,
+                "sslHSTimeoutMax": 900,
+                "sslHSTimeoutMin": 400,
+                "sslReadTimeout": 0,
+                "switchOffOnBlack": false,
+                "transitiontime": 1.0,
+                "useEntertainmentAPI": true,
+                "username": "",
+                "verbose": false
+            }"#,
+            r#"{"type": "file", "hardwareLedCount": 1, "output": "/dev/null"}"#,
+            r#"{"type": "pipe", "hardwareLedCount": 1, "segments": [{"leds": "0-1"}]}"#,
+            r#"{"type": "wled", "hardwareLedCount": 1, "host": "wled.local"}"#,
+            r#"{"type": "adalight", "hardwareLedCount": 1}"#,
+            r#"{"type": "boblight", "hardwareLedCount": 1, "host": "127.0.0.1"}"#,
+        ]
+        .iter()
+        .map(|json| serde_json::from_str(json).unwrap())
+        .collect()
+    }
+
+    macro_rules! round_trip_tests {
+        ($($name:ident: $ty:ty = $seeds:expr;)*) => {
+            proptest! {
+                $(
+                    #[test]
+                    fn $name(json in similar_to::<$ty>($seeds)) {
+                        if let Ok(value) = serde_json::from_value::<$ty>(json) {
+                            assert_round_trip(value);
+                        }
+                    }
+                )*
+            }
+        };
+    }
+
+    round_trip_tests! {
+        round_trip_background_effect: BackgroundEffect = vec![Default::default()];
+        round_trip_black_border_detector: BlackBorderDetector = vec![Default::default()];
+        round_trip_boblight_server: BoblightServer = vec![Default::default()];
+        round_trip_color_adjustment: ColorAdjustment = vec![Default::default()];
+        round_trip_device: Device = devices();
+        round_trip_effects: Effects = vec![Default::default()];
+        round_trip_flatbuffers_server: FlatbuffersServer = vec![Default::default()];
+        round_trip_foreground_effect: ForegroundEffect = vec![Default::default()];
+        round_trip_forwarder: Forwarder = vec![Default::default()];
+        round_trip_framegrabber: Framegrabber = vec![Default::default()];
+        round_trip_general: General = vec![Default::default()];
+        round_trip_grabber_v4l2: GrabberV4L2 = vec![Default::default()];
+        round_trip_grabber_audio: GrabberAudio = vec![Default::default()];
+        round_trip_instance_capture: InstanceCapture = vec![Default::default()];
+        round_trip_json_server: JsonServer = vec![Default::default()];
+        round_trip_led_config: LedConfig = vec![Default::default()];
+        round_trip_leds: Leds = vec![Default::default()];
+        round_trip_logger: Logger = vec![Default::default()];
+        round_trip_network: Network = vec![Default::default()];
+        round_trip_proto_server: ProtoServer = vec![Default::default()];
+        round_trip_smoothing: Smoothing = vec![Default::default()];
+        round_trip_web_config: WebConfig = vec![Default::default()];
+        round_trip_boot_splash: BootSplash = vec![Default::default()];
+        round_trip_hooks: Hooks = vec![Default::default()];
+        round_trip_mqtt: Mqtt = vec![Default::default()];
+        round_trip_instance_startup: InstanceStartup = vec![Default::default()];
+        round_trip_quiet_hours: QuietHours = vec![Default::default()];
+        round_trip_frame_latency: FrameLatency = vec![Default::default()];
+        round_trip_memory_limits: MemoryLimits = vec![Default::default()];
+        round_trip_ndi_receiver: NdiReceiver = vec![Default::default()];
+        round_trip_media_artwork: MediaArtwork = vec![Default::default()];
+        round_trip_data_presets: DataPresets = vec![Default::default()];
+        round_trip_instance_mirror: InstanceMirror = vec![Default::default()];
+        round_trip_dry_run: DryRun = vec![Default::default(), DryRun { enable: true }];
+        round_trip_udp_listener: UdpListener = vec![Default::default()];
+    }
+
+    #[test]
+    fn test_setting_unknown_field() {
+        let setting = db_models::DbSetting {
+            ty: "smoothing".to_owned(),
+            config: r#"{"enable": true, "bogus": 1}"#.to_owned(),
+            hyperion_inst: Some(0),
+            updated_at: "2021-01-01T00:00:00Z".to_owned(),
+        };
+
+        let error = Setting::try_from(setting.clone()).unwrap_err().to_string();
+        assert!(error.contains("`smoothing`"), "{}", error);
+        assert!(error.contains("unknown field `bogus`"), "{}", error);
+
+        let (_, warnings) = Setting::from_db(setting, false).unwrap();
+        assert_eq!(
+            warnings[0].to_string(),
+            "instance 0: smoothing: unknown key `bogus` ignored"
+        );
+    }
 }
@@ -8,6 +8,11 @@ mod file;
 #[async_trait]
 pub trait ConfigBackend {
     async fn load(&mut self) -> Result<Config, ConfigError>;
+
+    /// Returns true if this backend can't persist configuration changes
+    fn is_read_only(&self) -> bool {
+        false
+    }
 }
 
 pub use db::DbBackend;
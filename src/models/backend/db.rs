@@ -1,6 +1,7 @@
 use std::{collections::BTreeMap, convert::TryFrom};
 
 use async_trait::async_trait;
+use sqlx::Connection;
 
 use super::ConfigBackend;
 use crate::{
@@ -10,25 +11,147 @@ use crate::{
 
 pub struct DbBackend {
     db: Db,
+    strict: bool,
 }
 
 impl DbBackend {
     pub fn new(db: Db) -> Self {
         Self::from(db)
     }
+
+    /// Fail on unknown setting keys, instead of ignoring them with a warning
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Get the id for a new instance, after all the existing ones
+    pub async fn next_instance_id(&mut self) -> Result<i32, ConfigError> {
+        Ok(
+            sqlx::query_scalar("SELECT COALESCE(MAX(instance) + 1, 0) FROM instances")
+                .fetch_one(&mut *self.db)
+                .await?,
+        )
+    }
+
+    /// Persist a new instance and its settings
+    pub async fn create_instance(&mut self, config: &InstanceConfig) -> Result<(), ConfigError> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let settings = instance_settings(config)?;
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO instances (instance, friendly_name, enabled, last_use) VALUES (?, ?, ?, ?)",
+        )
+        .bind(config.instance.id)
+        .bind(&config.instance.friendly_name)
+        .bind(config.instance.enabled as i32)
+        .bind(config.instance.last_use.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        for (ty, setting) in settings {
+            sqlx::query(
+                "INSERT INTO settings (type, config, hyperion_inst, updated_at) VALUES (?, ?, ?, ?)",
+            )
+            .bind(ty)
+            .bind(setting)
+            .bind(config.instance.id)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Insert or update an entry of the `auth` table
+    pub async fn save_user(&mut self, user: &User) -> Result<(), ConfigError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO auth (user, password, token, salt, comment, id, created_at, last_use) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user.name)
+        .bind(hex::encode(&user.password).into_bytes())
+        .bind(hex::encode(&user.token).into_bytes())
+        .bind(user.salt.as_bytes())
+        .bind(&user.comment)
+        .bind(&user.id)
+        .bind(user.created_at.to_rfc3339())
+        .bind(user.last_use.to_rfc3339())
+        .execute(&mut *self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Replace settings, all at once
+    pub async fn save_settings(
+        &mut self,
+        settings: &[db_models::DbSetting],
+    ) -> Result<(), ConfigError> {
+        let mut tx = self.db.begin().await?;
+
+        for setting in settings {
+            // Global settings have a NULL hyperion_inst, which the primary key doesn't deduplicate
+            sqlx::query("DELETE FROM settings WHERE type = ? AND hyperion_inst IS ?")
+                .bind(&setting.ty)
+                .bind(setting.hyperion_inst)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query(
+                "INSERT INTO settings (type, config, hyperion_inst, updated_at) VALUES (?, ?, ?, ?)",
+            )
+            .bind(&setting.ty)
+            .bind(&setting.config)
+            .bind(setting.hyperion_inst)
+            .bind(&setting.updated_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Remove an entry of the `auth` table
+    pub async fn delete_user(&mut self, name: &str) -> Result<(), ConfigError> {
+        sqlx::query("DELETE FROM auth WHERE user = ?")
+            .bind(name)
+            .execute(&mut *self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Serialize the settings of an instance, as stored in the settings table
+fn instance_settings(config: &InstanceConfig) -> Result<Vec<(&'static str, String)>, ConfigError> {
+    Ok(config
+        .settings()?
+        .into_iter()
+        .map(|(ty, setting)| (ty, setting.to_string()))
+        .collect())
 }
 
 impl From<Db> for DbBackend {
     fn from(db: Db) -> Self {
-        Self { db }
+        Self { db, strict: false }
     }
 }
 
 #[async_trait]
 impl ConfigBackend for DbBackend {
+    fn is_read_only(&self) -> bool {
+        self.db.is_read_only()
+    }
+
     async fn load(&mut self) -> Result<Config, ConfigError> {
         let mut instances = BTreeMap::new();
         let mut global = GlobalConfigCreator::default();
+        let mut load_warnings = Vec::new();
 
         for instance in sqlx::query_as::<_, db_models::DbInstance>("SELECT * FROM instances")
             .fetch_all(&mut *self.db)
@@ -44,9 +167,10 @@ impl ConfigBackend for DbBackend {
             .fetch_all(&mut *self.db)
             .await?
             .into_iter()
-            .map(Setting::try_from)
+            .map(|setting| Setting::from_db(setting, self.strict))
         {
-            let setting = setting?;
+            let (setting, warnings) = setting?;
+            load_warnings.extend(warnings);
             match setting.config {
                 SettingData::BackgroundEffect(config) => {
                     match instances.get_mut(
@@ -158,6 +282,116 @@ impl ConfigBackend for DbBackend {
                         None => continue,
                     }
                 }
+                SettingData::BootSplash(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("bootSplash"))?,
+                    ) {
+                        Some(instance) => instance.boot_splash = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::InstanceStartup(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("instanceStartup"))?,
+                    ) {
+                        Some(instance) => instance.startup = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::QuietHours(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("quietHours"))?,
+                    ) {
+                        Some(instance) => instance.quiet_hours = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::FrameLatency(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("frameLatency"))?,
+                    ) {
+                        Some(instance) => instance.frame_latency = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::MemoryLimits(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("memoryLimits"))?,
+                    ) {
+                        Some(instance) => instance.memory_limits = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::NdiReceiver(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("ndiReceiver"))?,
+                    ) {
+                        Some(instance) => instance.ndi_receiver = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::MediaArtwork(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("mediaArtwork"))?,
+                    ) {
+                        Some(instance) => instance.media_artwork = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::DataPresets(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("dataPresets"))?,
+                    ) {
+                        Some(instance) => instance.data_presets = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::InstanceMirror(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("instanceMirror"))?,
+                    ) {
+                        Some(instance) => instance.instance_mirror = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::DryRun(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("dryRun"))?,
+                    ) {
+                        Some(instance) => instance.dry_run = Some(config),
+                        None => continue,
+                    }
+                }
+                SettingData::UdpListener(config) => {
+                    match instances.get_mut(
+                        &setting
+                            .hyperion_inst
+                            .ok_or(ConfigError::MissingHyperionInst("udpListener"))?,
+                    ) {
+                        Some(instance) => instance.udp_listener = Some(config),
+                        None => continue,
+                    }
+                }
 
                 SettingData::FlatbuffersServer(config) => {
                     global.flatbuffers_server = Some(config);
@@ -174,6 +408,9 @@ impl ConfigBackend for DbBackend {
                 SettingData::GrabberV4L2(config) => {
                     global.grabber_v4l2 = Some(config);
                 }
+                SettingData::GrabberAudio(config) => {
+                    global.grabber_audio = Some(config);
+                }
                 SettingData::JsonServer(config) => {
                     global.json_server = Some(config);
                 }
@@ -192,6 +429,9 @@ impl ConfigBackend for DbBackend {
                 SettingData::Hooks(config) => {
                     global.hooks = Some(config);
                 }
+                SettingData::Mqtt(config) => {
+                    global.mqtt = Some(config);
+                }
             }
         }
 
@@ -229,6 +469,7 @@ impl ConfigBackend for DbBackend {
             global,
             meta,
             users,
+            load_warnings,
         })
     }
 }
@@ -246,6 +487,17 @@ struct InstanceConfigCreator {
     led_config: Option<LedConfig>,
     leds: Option<Leds>,
     smoothing: Option<Smoothing>,
+    boot_splash: Option<BootSplash>,
+    startup: Option<InstanceStartup>,
+    quiet_hours: Option<QuietHours>,
+    frame_latency: Option<FrameLatency>,
+    memory_limits: Option<MemoryLimits>,
+    ndi_receiver: Option<NdiReceiver>,
+    media_artwork: Option<MediaArtwork>,
+    data_presets: Option<DataPresets>,
+    instance_mirror: Option<InstanceMirror>,
+    dry_run: Option<DryRun>,
+    udp_listener: Option<UdpListener>,
 }
 
 impl From<InstanceConfigCreator> for InstanceConfig {
@@ -263,6 +515,17 @@ impl From<InstanceConfigCreator> for InstanceConfig {
             led_config: creator.led_config.unwrap_or_default(),
             leds: creator.leds.unwrap_or_default(),
             smoothing: creator.smoothing.unwrap_or_default(),
+            boot_splash: creator.boot_splash.unwrap_or_default(),
+            startup: creator.startup.unwrap_or_default(),
+            quiet_hours: creator.quiet_hours.unwrap_or_default(),
+            frame_latency: creator.frame_latency.unwrap_or_default(),
+            memory_limits: creator.memory_limits.unwrap_or_default(),
+            ndi_receiver: creator.ndi_receiver.unwrap_or_default(),
+            media_artwork: creator.media_artwork.unwrap_or_default(),
+            data_presets: creator.data_presets.unwrap_or_default(),
+            instance_mirror: creator.instance_mirror.unwrap_or_default(),
+            dry_run: creator.dry_run.unwrap_or_default(),
+            udp_listener: creator.udp_listener.unwrap_or_default(),
         }
     }
 }
@@ -282,6 +545,17 @@ impl InstanceConfigCreator {
             led_config: None,
             leds: None,
             smoothing: None,
+            boot_splash: None,
+            startup: None,
+            quiet_hours: None,
+            frame_latency: None,
+            memory_limits: None,
+            ndi_receiver: None,
+            media_artwork: None,
+            data_presets: None,
+            instance_mirror: None,
+            dry_run: None,
+            udp_listener: None,
         }
     }
 }
@@ -294,12 +568,14 @@ impl From<GlobalConfigCreator> for GlobalConfig {
             framegrabber: creator.framegrabber.unwrap_or_default(),
             general: creator.general.unwrap_or_default(),
             grabber_v4l2: creator.grabber_v4l2.unwrap_or_default(),
+            grabber_audio: creator.grabber_audio.unwrap_or_default(),
             json_server: creator.json_server.unwrap_or_default(),
             logger: creator.logger.unwrap_or_default(),
             network: creator.network.unwrap_or_default(),
             proto_server: creator.proto_server.unwrap_or_default(),
             web_config: creator.web_config.unwrap_or_default(),
             hooks: creator.hooks.unwrap_or_default(),
+            mqtt: creator.mqtt.unwrap_or_default(),
         }
     }
 }
@@ -311,10 +587,12 @@ struct GlobalConfigCreator {
     framegrabber: Option<Framegrabber>,
     general: Option<General>,
     grabber_v4l2: Option<GrabberV4L2>,
+    grabber_audio: Option<GrabberAudio>,
     json_server: Option<JsonServer>,
     logger: Option<Logger>,
     network: Option<Network>,
     proto_server: Option<ProtoServer>,
     web_config: Option<WebConfig>,
     hooks: Option<Hooks>,
+    mqtt: Option<Mqtt>,
 }
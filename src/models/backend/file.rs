@@ -1,6 +1,5 @@
 use std::{
     collections::BTreeMap,
-    convert::TryFrom,
     path::{Path, PathBuf},
 };
 
@@ -9,6 +8,9 @@ use async_trait::async_trait;
 use super::ConfigBackend;
 use crate::models::*;
 
+mod upgrade;
+pub use upgrade::{Upgrade, CONFIG_VERSION};
+
 pub trait ConfigExt {
     fn to_string(&self) -> Result<String, toml::ser::Error>;
 }
@@ -21,14 +23,32 @@ impl ConfigExt for Config {
 
 pub struct FileBackend {
     path: PathBuf,
+    write_upgrade: bool,
+    strict: bool,
 }
 
 impl FileBackend {
     pub fn new(path: &Path) -> Self {
         Self {
             path: path.to_owned(),
+            write_upgrade: false,
+            strict: false,
         }
     }
+
+    /// Fail on unknown keys, instead of ignoring them with a warning
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Write back files upgraded from an older layout, keeping the original as `<path>.bak`
+    ///
+    /// Comments of the original file are not preserved.
+    pub fn write_upgrade(mut self, write_upgrade: bool) -> Self {
+        self.write_upgrade = write_upgrade;
+        self
+    }
 }
 
 #[async_trait]
@@ -40,13 +60,77 @@ impl ConfigBackend for FileBackend {
         let mut full = String::new();
         file.read_to_string(&mut full).await?;
 
-        let config: DeserializableConfig = toml::from_str(&full)?;
-        Ok(config.try_into()?)
+        let (config, upgrade) = parse(&full, self.strict)?;
+        if !upgrade.is_needed() {
+            return Ok(config);
+        }
+
+        warn!(
+            path = %self.path.display(),
+            from = upgrade.from,
+            to = CONFIG_VERSION,
+            "upgraded configuration file from an older layout"
+        );
+        for change in &upgrade.changes {
+            warn!(path = %self.path.display(), "{}", change);
+        }
+
+        if self.write_upgrade {
+            let mut backup = self.path.clone().into_os_string();
+            backup.push(".bak");
+
+            tokio::fs::copy(&self.path, &backup).await?;
+            tokio::fs::write(&self.path, config.to_string()?).await?;
+
+            info!(
+                path = %self.path.display(),
+                backup = %Path::new(&backup).display(),
+                "wrote upgraded configuration file"
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+/// Parse a TOML configuration file, upgrading it first if it uses an older layout
+///
+/// Errors name the key path they occurred at: TOML spans only point at the table header for
+/// some unknown keys, and global sections are checked after parsing, without any span.
+fn parse(source: &str, strict: bool) -> Result<(Config, Upgrade), ConfigError> {
+    let mut table: toml::Table =
+        serde_path_to_error::deserialize(toml::Deserializer::parse(source)?).map_err(key_error)?;
+    let upgrade = upgrade::upgrade(&mut table)?;
+
+    let mut unknown = Vec::new();
+    let config: DeserializableConfig =
+        deserialize_value(toml::Value::Table(table), strict, &mut unknown).map_err(key_error)?;
+    let mut config = config.into_config(strict, &mut unknown)?;
+
+    config.load_warnings = unknown
+        .iter()
+        .map(|path| ConfigWarning::unknown_key(None, "config", path))
+        .collect();
+
+    Ok((config, upgrade))
+}
+
+fn key_error(error: serde_path_to_error::Error<toml::de::Error>) -> ConfigError {
+    let location = match error.path().iter().next() {
+        Some(_) => format!("`{}`", error.path()),
+        None => "top level table".to_owned(),
+    };
+
+    ConfigError::TomlKey {
+        location,
+        source: error.into_inner(),
     }
 }
 
 #[derive(Serialize)]
 struct SerializableConfig<'c> {
+    #[serde(rename = "configVersion")]
+    config_version: u32,
     instances: BTreeMap<String, &'c InstanceConfig>,
     #[serde(flatten)]
     global: &'c GlobalConfig,
@@ -57,6 +141,7 @@ struct SerializableConfig<'c> {
 impl<'c> From<&'c Config> for SerializableConfig<'c> {
     fn from(config: &'c Config) -> Self {
         Self {
+            config_version: CONFIG_VERSION,
             instances: config
                 .instances
                 .iter()
@@ -80,20 +165,19 @@ fn default_users() -> Vec<User> {
 #[derive(Deserialize)]
 struct DeserializableConfig {
     instances: BTreeMap<String, InstanceConfig>,
-    #[serde(default, flatten)]
-    global: GlobalConfig,
+    /// Global sections, parsed separately so unknown sections are reported instead of ignored
+    #[serde(flatten)]
+    global: toml::Table,
     #[serde(default = "default_meta")]
     meta: Vec<Meta>,
     #[serde(default = "default_users")]
     users: Vec<User>,
 }
 
-impl TryFrom<DeserializableConfig> for Config {
-    type Error = ConfigError;
-
-    fn try_from(value: DeserializableConfig) -> Result<Self, Self::Error> {
-        Ok(Self {
-            instances: value
+impl DeserializableConfig {
+    fn into_config(self, strict: bool, unknown: &mut Vec<String>) -> Result<Config, ConfigError> {
+        Ok(Config {
+            instances: self
                 .instances
                 .into_iter()
                 .map(|(k, v)| {
@@ -102,9 +186,98 @@ impl TryFrom<DeserializableConfig> for Config {
                         .map(|k| (k, v))
                 })
                 .collect::<Result<_, _>>()?,
-            global: value.global,
-            meta: value.meta,
-            users: value.users,
+            global: deserialize_value(toml::Value::Table(self.global), strict, unknown)
+                .map_err(key_error)?,
+            meta: self.meta,
+            users: self.users,
+            load_warnings: Vec::new(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::models::tests::similar_to;
+
+    /// Error message with its sources, as reported on startup
+    fn report(error: ConfigError) -> String {
+        let mut report = error.to_string();
+        let mut source = std::error::Error::source(&error);
+        while let Some(error) = source {
+            report += &format!(": {}", error);
+            source = error.source();
+        }
+        report
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        for (source, location, key) in [
+            (
+                "[instances.0.instance]\n[instances.0.smoothing]\nbogus = 1\n",
+                "`instances.0.smoothing",
+                "bogus",
+            ),
+            (
+                "[instances.0.instance]\n[instances.0.device]\ntype = \"dummy\"\nbogus = 1\n",
+                "`instances.0.device`",
+                "bogus",
+            ),
+            (
+                "[instances.0.instance]\n[jsonServer]\nbogus = 1\n",
+                "`jsonServer",
+                "bogus",
+            ),
+            ("bogus = 1\n[instances.0.instance]\n", "`bogus`", "bogus"),
+            (
+                "[instances.0.instance]\n[bogusSection]\nport = 1\n",
+                "`bogusSection`",
+                "bogusSection",
+            ),
+        ] {
+            // Unknown keys are only errors in strict mode
+            let (config, _) = parse(source, false).unwrap();
+            let warning = config.warnings().remove(0).to_string();
+            assert!(warning.contains(key), "{}", warning);
+
+            let report = report(parse(source, true).unwrap_err());
+            assert!(report.contains(location), "{}", report);
+            assert!(
+                report.contains(&format!("unknown field `{}`", key)),
+                "{}",
+                report
+            );
+        }
+    }
+
+    fn instance() -> InstanceConfig {
+        toml::from_str("[instance]").unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_config(
+            instance in similar_to(vec![instance()]),
+            global in similar_to(vec![GlobalConfig::default()]),
+        ) {
+            if let (Ok(instance), Ok(global)) = (
+                serde_json::from_value::<InstanceConfig>(instance),
+                serde_json::from_value::<GlobalConfig>(global),
+            ) {
+                let config = Config {
+                    instances: std::iter::once((0, instance)).collect(),
+                    global,
+                    meta: default_meta(),
+                    users: default_users(),
+                    load_warnings: Vec::new(),
+                };
+
+                let source = config.to_string().unwrap();
+                prop_assert_eq!(parse(&source, true).unwrap().0, config, "{}", source);
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Upgrades of configuration files written for previous releases
+//!
+//! Files hold the version of their layout in a top-level `configVersion` key, files without it
+//! are version 0. Older files are migrated one version at a time before being parsed.
+
+use crate::models::ConfigError;
+
+/// Version of the configuration file layout of this release
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Top-level key holding the version of a configuration file
+pub const CONFIG_VERSION_KEY: &str = "configVersion";
+
+/// Changes made while upgrading a configuration file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Upgrade {
+    /// Version of the file before the upgrade
+    pub from: u32,
+    /// Description of each change
+    pub changes: Vec<String>,
+}
+
+impl Upgrade {
+    /// Returns true if the file was written for a previous release
+    pub fn is_needed(&self) -> bool {
+        self.from < CONFIG_VERSION
+    }
+}
+
+type Migration = fn(&mut toml::Table, &mut Vec<String>);
+
+/// Migration from each version to the next one, starting at version 0
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1];
+
+/// Upgrade a configuration file to the current layout, removing its version key
+pub fn upgrade(table: &mut toml::Table) -> Result<Upgrade, ConfigError> {
+    let from = match table.remove(CONFIG_VERSION_KEY) {
+        None => 0,
+        Some(toml::Value::Integer(version)) if version > CONFIG_VERSION as i64 => {
+            return Err(ConfigError::UnsupportedVersion {
+                version,
+                supported: CONFIG_VERSION,
+            });
+        }
+        Some(toml::Value::Integer(version)) if version >= 0 => version as u32,
+        Some(other) => return Err(ConfigError::InvalidVersion(other.to_string())),
+    };
+
+    let mut changes = Vec::new();
+    for migration in &MIGRATIONS[from as usize..] {
+        migration(table, &mut changes);
+    }
+
+    Ok(Upgrade { from, changes })
+}
+
+/// Move `table[from]` to `table[to]`, unless `to` is already set
+fn rename(table: &mut toml::Table, path: &str, from: &str, to: &str, changes: &mut Vec<String>) {
+    if table.contains_key(to) {
+        return;
+    }
+
+    if let Some(value) = table.remove(from) {
+        table.insert(to.to_owned(), value);
+        changes.push(format!("renamed `{}{}` to `{}{}`", path, from, path, to));
+    }
+}
+
+/// Version 0: files written before versioning, possibly with setting names from hyperion.ng
+fn v0_to_v1(table: &mut toml::Table, changes: &mut Vec<String>) {
+    rename(table, "", "flatbufServer", "flatbuffersServer", changes);
+
+    if let Some(instances) = table.get_mut("instances").and_then(|v| v.as_table_mut()) {
+        for (id, instance) in instances.iter_mut() {
+            if let Some(instance) = instance.as_table_mut() {
+                let path = format!("instances.{}.", id);
+                for (from, to) in [
+                    ("blackborderdetector", "blackBorderDetector"),
+                    ("instCapture", "instanceCapture"),
+                    ("instanceStartup", "startup"),
+                ] {
+                    rename(instance, &path, from, to, changes);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade() {
+        let mut table: toml::Table = "[flatbufServer]\nport = 19400\n\
+            [instances.0.instCapture]\n[instances.0.instanceCapture]\n"
+            .parse()
+            .unwrap();
+
+        let changes = upgrade(&mut table).unwrap();
+        assert_eq!(changes.from, 0);
+        assert_eq!(
+            changes.changes,
+            ["renamed `flatbufServer` to `flatbuffersServer`"]
+        );
+        assert!(table.contains_key("flatbuffersServer"));
+        // Sections which are already in the current layout win
+        assert!(table["instances"]["0"].get("instCapture").is_some());
+
+        let mut table: toml::Table = "configVersion = 1\n[flatbufServer]\n".parse().unwrap();
+        assert!(!upgrade(&mut table).unwrap().is_needed());
+        assert!(table.contains_key("flatbufServer"));
+
+        let mut table: toml::Table = "configVersion = 99\n".parse().unwrap();
+        assert!(upgrade(&mut table).is_err());
+    }
+}
@@ -17,10 +17,67 @@ pub trait DeviceConfig: Sync + Send {
     fn latch_time(&self) -> std::time::Duration {
         Default::default()
     }
+
+    /// Time the LED data is held back before being written, so the LEDs stay in sync with
+    /// displays which add processing delay to the video
+    fn output_delay(&self) -> std::time::Duration {
+        Default::default()
+    }
+
+    /// Maximum number of frames written per second, frames received faster than that are
+    /// coalesced into the latest one
+    fn max_fps(&self) -> Option<u32> {
+        None
+    }
+
+    /// Groups of layout LEDs driving each of the device's native segments
+    ///
+    /// If empty, LEDs from the layout are mapped 1:1 to the device.
+    fn segments(&self) -> &[DeviceSegment] {
+        &[]
+    }
+
+    /// LED kept lit while the output is black, if any
+    fn keep_alive(&self) -> Option<&KeepAlive> {
+        None
+    }
+
+    /// Model of the voltage drop along the strip, to compensate for it
+    fn voltage_drop(&self) -> Option<&VoltageDrop> {
+        None
+    }
+
+    /// Lookup tables applied to the output levels, if any
+    fn lut(&self) -> Option<&DeviceLut> {
+        None
+    }
+
+    /// White channels of the LEDs and how they are derived from the colors, if any
+    fn white_output(&self) -> Option<&WhiteOutput> {
+        None
+    }
+
+    /// Interval at which the device should be asked for its current LED count
+    ///
+    /// Only relevant for devices whose LED count can change at runtime, such as network
+    /// controllers that can be reconfigured remotely.
+    fn led_count_refresh_time(&self) -> Option<std::time::Duration> {
+        None
+    }
 }
 
 macro_rules! impl_device_config {
     ($t:ty) => {
+        impl_device_config!(@impl $t {});
+    };
+    ($t:ty, white_output) => {
+        impl_device_config!(@impl $t {
+            fn white_output(&self) -> Option<&WhiteOutput> {
+                self.white_output.as_ref()
+            }
+        });
+    };
+    (@impl $t:ty { $($extra:tt)* }) => {
         impl DeviceConfig for $t {
             fn hardware_led_count(&self) -> usize {
                 self.hardware_led_count as _
@@ -37,10 +94,315 @@ macro_rules! impl_device_config {
             fn latch_time(&self) -> std::time::Duration {
                 std::time::Duration::from_millis(self.latch_time as _)
             }
+
+            fn output_delay(&self) -> std::time::Duration {
+                std::time::Duration::from_millis(self.output_delay as _)
+            }
+
+            fn max_fps(&self) -> Option<u32> {
+                if self.max_fps == 0 {
+                    None
+                } else {
+                    Some(self.max_fps)
+                }
+            }
+
+            fn segments(&self) -> &[DeviceSegment] {
+                &self.segments
+            }
+
+            fn keep_alive(&self) -> Option<&KeepAlive> {
+                self.keep_alive.as_ref()
+            }
+
+            fn voltage_drop(&self) -> Option<&VoltageDrop> {
+                self.voltage_drop.as_ref()
+            }
+
+            fn lut(&self) -> Option<&DeviceLut> {
+                self.lut.as_ref()
+            }
+
+            $($extra)*
         }
     };
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum SegmentAggregation {
+    /// Average of all the LED colors in the segment
+    #[default]
+    Mean,
+    /// Average of the most common color range in the segment
+    Dominant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DeviceSegment {
+    /// Indices of the layout LEDs in this segment, in the same format as channel adjustments
+    pub leds: String,
+    #[serde(default = "Default::default")]
+    pub aggregation: SegmentAggregation,
+}
+
+fn default_keep_alive_level() -> u8 {
+    1
+}
+
+/// LED kept at a low brightness while the output is otherwise black
+///
+/// Some power supplies and controllers turn off when all LEDs are black, and take a while to come
+/// back on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KeepAlive {
+    /// Index of the device LED to keep lit
+    #[serde(default = "Default::default")]
+    pub led: u32,
+    /// Value of all the channels of this LED
+    #[serde(default = "default_keep_alive_level")]
+    #[validate(range(min = 1))]
+    pub level: u8,
+}
+
+/// Lookup tables from the output levels of each channel to the levels written to the device
+///
+/// Tables have either 256 entries (8-bit levels) or 65536 entries (16-bit levels, sampled at the
+/// 8-bit levels of the output). An empty table leaves its channel unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[validate(schema(function = "validate_device_lut"))]
+pub struct DeviceLut {
+    /// Text file holding the tables instead, with one line per entry: either a level for all the
+    /// channels, or red, green and blue levels
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub red: Vec<u16>,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub green: Vec<u16>,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub blue: Vec<u16>,
+}
+
+impl DeviceLut {
+    /// Check the size and levels of a table
+    pub fn validate_table(table: &[u16]) -> Result<(), validator::ValidationError> {
+        match table.len() {
+            0 | 65536 => Ok(()),
+            256 if table.iter().all(|level| *level <= 255) => Ok(()),
+            256 => Err(validator::ValidationError::new("lut_level_out_of_range")),
+            _ => Err(validator::ValidationError::new("lut_size")),
+        }
+    }
+}
+
+fn validate_device_lut(config: &DeviceLut) -> Result<(), validator::ValidationError> {
+    let tables = [&config.red, &config.green, &config.blue];
+    if config.file.is_some() && tables.iter().any(|table| !table.is_empty()) {
+        return Err(validator::ValidationError::new("lut_file_and_tables"));
+    }
+
+    tables
+        .iter()
+        .try_for_each(|table| DeviceLut::validate_table(table))
+}
+
+/// Gauge of the wires feeding power to a strip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum WireGauge {
+    Awg12,
+    Awg14,
+    Awg16,
+    #[default]
+    Awg18,
+    Awg20,
+    Awg22,
+    Awg24,
+}
+
+impl WireGauge {
+    /// Resistance of a copper wire of this gauge, in ohms per meter
+    pub fn resistance(self) -> f32 {
+        match self {
+            WireGauge::Awg12 => 0.00521,
+            WireGauge::Awg14 => 0.00828,
+            WireGauge::Awg16 => 0.0132,
+            WireGauge::Awg18 => 0.0210,
+            WireGauge::Awg20 => 0.0333,
+            WireGauge::Awg22 => 0.0530,
+            WireGauge::Awg24 => 0.0842,
+        }
+    }
+}
+
+fn default_supply_voltage() -> f32 {
+    5.0
+}
+
+fn default_strip_resistance() -> f32 {
+    0.06
+}
+
+fn default_channel_current() -> f32 {
+    20.0
+}
+
+fn default_injection_points() -> Vec<u32> {
+    vec![0]
+}
+
+fn default_wire_length() -> f32 {
+    1.0
+}
+
+/// Physical model of a strip, to compensate for the voltage drop along it
+///
+/// Far from the power injection points, the supply voltage drops with the current drawn by the
+/// LEDs. Green and blue need more voltage than red, so bright colors shift towards orange. The
+/// colors of the LEDs are dimmed so their channels stay balanced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VoltageDrop {
+    /// Length of the strip in meters
+    #[validate(range(exclusive_min = 0.0))]
+    pub length: f32,
+    /// Supply voltage in volts
+    #[serde(default = "default_supply_voltage")]
+    #[validate(range(min = 1.0, max = 48.0))]
+    pub voltage: f32,
+    /// Resistance of the power traces of the strip in ohms per meter, both rails combined
+    #[serde(default = "default_strip_resistance")]
+    #[validate(range(min = 0.0))]
+    pub strip_resistance: f32,
+    /// Current drawn by one channel of a LED at full brightness, in milliamperes
+    #[serde(default = "default_channel_current")]
+    #[validate(range(min = 0.0))]
+    pub channel_current: f32,
+    /// Device LEDs where power is injected
+    #[serde(default = "default_injection_points")]
+    #[validate(length(min = 1))]
+    pub injection_points: Vec<u32>,
+    /// Gauge of the wires from the power supply to the injection points
+    #[serde(default = "Default::default")]
+    pub wire_gauge: WireGauge,
+    /// Length of each wire from the power supply to an injection point, in meters
+    #[serde(default = "default_wire_length")]
+    #[validate(range(min = 0.0))]
+    pub wire_length: f32,
+}
+
+/// White channels sent after the RGB channels of each LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhiteChannels {
+    /// One white channel
+    Rgbw,
+    /// A cold white channel, then a warm white channel
+    Rgbcw,
+}
+
+impl WhiteChannels {
+    /// Number of white channels of each LED
+    pub fn count(self) -> usize {
+        match self {
+            WhiteChannels::Rgbw => 1,
+            WhiteChannels::Rgbcw => 2,
+        }
+    }
+}
+
+/// How the white channels are derived from the RGB colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[derive(Default)]
+pub enum WhiteAlgorithm {
+    /// Move the part common to the three channels to the white LEDs, assuming they are as white
+    /// as the RGB ones at full brightness. Cold and warm LEDs share it evenly.
+    #[default]
+    SubtractMinimum,
+    /// Move as much of the color as the white LEDs can reproduce to them, given their color
+    /// temperature
+    Accurate,
+    /// Same as `accurate`, with the measured colors of the white LEDs
+    Custom,
+}
+
+fn default_white_temperature() -> u32 {
+    4500
+}
+
+fn default_cold_temperature() -> u32 {
+    6500
+}
+
+fn default_warm_temperature() -> u32 {
+    2700
+}
+
+fn default_white_point() -> super::Color {
+    super::Color::new(255, 255, 255)
+}
+
+/// White channels of RGBW and RGBCW LEDs
+///
+/// The white channels are derived from the colors once they have been adjusted, right before
+/// they are written to the device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[validate(schema(function = "validate_white_output"))]
+pub struct WhiteOutput {
+    pub channels: WhiteChannels,
+    #[serde(default = "Default::default")]
+    pub algorithm: WhiteAlgorithm,
+    /// Color temperature of the white LEDs of RGBW strips in kelvin, for the `accurate` algorithm
+    #[serde(default = "default_white_temperature")]
+    #[validate(range(min = 1000, max = 40000))]
+    pub white_temperature: u32,
+    /// Color temperature of the cold white LEDs of RGBCW strips in kelvin, for the `accurate`
+    /// algorithm
+    #[serde(default = "default_cold_temperature")]
+    #[validate(range(min = 1000, max = 40000))]
+    pub cold_temperature: u32,
+    /// Color temperature of the warm white LEDs of RGBCW strips in kelvin, for the `accurate`
+    /// algorithm
+    #[serde(default = "default_warm_temperature")]
+    #[validate(range(min = 1000, max = 40000))]
+    pub warm_temperature: u32,
+    /// RGB color matching the white LEDs of RGBW strips, for the `custom` algorithm
+    #[serde(
+        default = "default_white_point",
+        serialize_with = "crate::serde::serialize_color_as_array"
+    )]
+    pub white_point: super::Color,
+    /// RGB color matching the cold white LEDs of RGBCW strips, for the `custom` algorithm
+    #[serde(
+        default = "default_white_point",
+        serialize_with = "crate::serde::serialize_color_as_array"
+    )]
+    pub cold_point: super::Color,
+    /// RGB color matching the warm white LEDs of RGBCW strips, for the `custom` algorithm
+    #[serde(
+        default = "default_white_point",
+        serialize_with = "crate::serde::serialize_color_as_array"
+    )]
+    pub warm_point: super::Color,
+}
+
+fn validate_white_output(config: &WhiteOutput) -> Result<(), validator::ValidationError> {
+    let black = super::Color::new(0, 0, 0);
+    if [config.white_point, config.cold_point, config.warm_point].contains(&black) {
+        return Err(validator::ValidationError::new("black_white_point"));
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 #[derive(Default)]
@@ -57,7 +419,22 @@ pub struct Dummy {
     pub hardware_led_count: u32,
     pub rewrite_time: u32,
     pub latch_time: u32,
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
     pub mode: DummyDeviceMode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
 }
 
 impl_device_config!(Dummy);
@@ -68,7 +445,13 @@ impl Default for Dummy {
             hardware_led_count: 1,
             rewrite_time: 0,
             latch_time: 0,
+            output_delay: 0,
+            max_fps: 0,
             mode: Default::default(),
+            segments: Default::default(),
+            keep_alive: None,
+            voltage_drop: None,
+            lut: None,
         }
     }
 }
@@ -92,14 +475,245 @@ pub struct Ws2812Spi {
     pub invert: bool,
     #[serde(default = "Default::default")]
     pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
     pub output: String,
     #[serde(default = "default_ws_spi_rate")]
     pub rate: i32,
     #[serde(default = "default_ws_spi_rewrite_time")]
     pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub white_output: Option<WhiteOutput>,
+}
+
+impl_device_config!(Ws2812Spi, white_output);
+
+fn default_sk6812_white_output() -> WhiteOutput {
+    WhiteOutput {
+        channels: WhiteChannels::Rgbw,
+        algorithm: Default::default(),
+        white_temperature: default_white_temperature(),
+        cold_temperature: default_cold_temperature(),
+        warm_temperature: default_warm_temperature(),
+        white_point: default_white_point(),
+        cold_point: default_white_point(),
+        warm_point: default_white_point(),
+    }
+}
+
+/// SK6812 RGBW strips driven through SPI
+///
+/// They use the same timings as WS2812 strips, with a white channel derived from the colors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Sk6812Spi {
+    #[serde(default = "Default::default")]
+    pub color_order: ColorOrder,
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    #[serde(default = "default_false")]
+    pub invert: bool,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    pub output: String,
+    #[serde(default = "default_ws_spi_rate")]
+    pub rate: i32,
+    #[serde(default = "default_ws_spi_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+    #[serde(default = "default_sk6812_white_output")]
+    #[validate(nested)]
+    pub white_output: WhiteOutput,
+}
+
+impl_device_config!(@impl Sk6812Spi {
+    fn white_output(&self) -> Option<&WhiteOutput> {
+        Some(&self.white_output)
+    }
+});
+
+impl From<Sk6812Spi> for Ws2812Spi {
+    fn from(config: Sk6812Spi) -> Self {
+        Self {
+            color_order: config.color_order,
+            hardware_led_count: config.hardware_led_count,
+            invert: config.invert,
+            latch_time: config.latch_time,
+            output_delay: config.output_delay,
+            max_fps: config.max_fps,
+            output: config.output,
+            rate: config.rate,
+            rewrite_time: config.rewrite_time,
+            segments: config.segments,
+            keep_alive: config.keep_alive,
+            voltage_drop: config.voltage_drop,
+            lut: config.lut,
+            white_output: Some(config.white_output),
+        }
+    }
+}
+
+fn default_apa102_color_order() -> ColorOrder {
+    ColorOrder::Bgr
+}
+
+fn default_apa102_rate() -> i32 {
+    1000000
+}
+
+fn default_apa102_brightness() -> u8 {
+    31
+}
+
+/// APA102 (and SK9822) strips driven through SPI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Apa102 {
+    /// Order of the channels on the wire, blue first for most strips
+    #[serde(default = "default_apa102_color_order")]
+    pub color_order: ColorOrder,
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    /// Global brightness of the LEDs, from 0 to 31
+    ///
+    /// Lower values dim the LEDs without losing color resolution, at the cost of a visible PWM
+    /// flicker on some strips.
+    #[serde(default = "default_apa102_brightness")]
+    #[validate(range(max = 31))]
+    pub brightness: u8,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    pub output: String,
+    #[serde(default = "default_apa102_rate")]
+    pub rate: i32,
+    #[serde(default = "default_ws_spi_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
 }
 
-impl_device_config!(Ws2812Spi);
+impl_device_config!(Apa102);
+
+fn default_ws281x_gpio() -> u8 {
+    18
+}
+
+fn default_ws281x_dma_channel() -> u8 {
+    10
+}
+
+fn default_ws281x_frequency() -> u32 {
+    800000
+}
+
+fn default_ws281x_brightness() -> u8 {
+    255
+}
+
+/// WS281x strips driven by the PWM, PCM or SPI peripherals of a Raspberry Pi through the
+/// rpi_ws281x library
+///
+/// The peripheral is selected by the GPIO: 12 or 18 for PWM channel 0, 13 or 19 for PWM channel 1,
+/// 21 for PCM and 10 for SPI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Ws281x {
+    #[serde(default = "Default::default")]
+    pub color_order: ColorOrder,
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    #[serde(default = "default_ws281x_gpio")]
+    pub gpio: u8,
+    /// DMA channel used for the transfers, which must not be in use by the system
+    #[serde(default = "default_ws281x_dma_channel")]
+    #[validate(range(max = 14))]
+    pub dma_channel: u8,
+    /// PWM channel of the GPIO
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1))]
+    pub pwm_channel: u8,
+    /// Invert the output signal, for level shifters made of a single transistor
+    #[serde(default = "default_false")]
+    pub invert: bool,
+    #[serde(default = "default_ws281x_frequency")]
+    pub frequency: u32,
+    /// Brightness scale applied by the library, from 0 to 255
+    #[serde(default = "default_ws281x_brightness")]
+    pub brightness: u8,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    #[serde(default = "default_ws_spi_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub white_output: Option<WhiteOutput>,
+}
+
+impl_device_config!(Ws281x, white_output);
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
@@ -132,12 +746,32 @@ pub struct PhilipsHue {
     pub use_entertainment_api: bool,
     pub username: String,
     pub verbose: bool,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
 }
 
 impl DeviceConfig for PhilipsHue {
     fn hardware_led_count(&self) -> usize {
         self.hardware_led_count as _
     }
+
+    fn segments(&self) -> &[DeviceSegment] {
+        &self.segments
+    }
+
+    fn keep_alive(&self) -> Option<&KeepAlive> {
+        self.keep_alive.as_ref()
+    }
+
+    fn voltage_drop(&self) -> Option<&VoltageDrop> {
+        self.voltage_drop.as_ref()
+    }
 }
 
 fn default_file_rewrite_time() -> u32 {
@@ -153,27 +787,572 @@ pub struct File {
     pub hardware_led_count: u32,
     #[serde(default = "Default::default")]
     pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
     pub output: String,
     #[serde(default = "default_file_rewrite_time")]
     pub rewrite_time: u32,
     #[serde(default = "Default::default")]
     pub print_time_stamp: bool,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
 }
 
 impl DeviceConfig for File {
     fn hardware_led_count(&self) -> usize {
         self.hardware_led_count as _
     }
+
+    fn output_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.output_delay as _)
+    }
+
+    fn max_fps(&self) -> Option<u32> {
+        if self.max_fps == 0 {
+            None
+        } else {
+            Some(self.max_fps)
+        }
+    }
+
+    fn segments(&self) -> &[DeviceSegment] {
+        &self.segments
+    }
+
+    fn keep_alive(&self) -> Option<&KeepAlive> {
+        self.keep_alive.as_ref()
+    }
+
+    fn voltage_drop(&self) -> Option<&VoltageDrop> {
+        self.voltage_drop.as_ref()
+    }
+
+    fn lut(&self) -> Option<&DeviceLut> {
+        self.lut.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum PipeFormat {
+    /// Big-endian u32 length followed by RGB bytes
+    #[default]
+    Binary,
+    /// One JSON object per line
+    Json,
+}
+
+fn default_pipe_output() -> String {
+    "-".to_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Pipe {
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    /// Path to the output file or named pipe, or - for stdout
+    #[serde(default = "default_pipe_output")]
+    pub output: String,
+    #[serde(default = "Default::default")]
+    pub format: PipeFormat,
+    #[serde(default = "Default::default")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub white_output: Option<WhiteOutput>,
+}
+
+impl_device_config!(Pipe, white_output);
+
+fn default_adalight_output() -> String {
+    "/dev/ttyUSB0".to_owned()
+}
+
+fn default_adalight_rate() -> u32 {
+    115200
+}
+
+fn default_adalight_rewrite_time() -> u32 {
+    1000
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Adalight {
+    #[serde(default = "Default::default")]
+    pub color_order: ColorOrder,
+    #[validate(range(min = 1, max = 65536))]
+    pub hardware_led_count: u32,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    /// Path to the serial port
+    #[serde(default = "default_adalight_output")]
+    pub output: String,
+    /// Baud rate, which must match the one in the Arduino sketch
+    #[serde(default = "default_adalight_rate")]
+    #[validate(range(min = 1))]
+    pub rate: u32,
+    /// The Adalight sketch blanks the LEDs when it doesn't receive data for a while
+    #[serde(default = "default_adalight_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+}
+
+impl_device_config!(Adalight);
+
+/// WLED realtime UDP protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum WledProtocol {
+    /// Index and color of each LED, up to 255 LEDs
+    Warls,
+    /// Colors of all LEDs, up to 490 LEDs. Larger setups are sent as DNRGB packets.
+    #[default]
+    Drgb,
+    /// Colors of up to 489 LEDs starting at a given index, as many packets as needed
+    Dnrgb,
+}
+
+fn default_wled_port() -> u16 {
+    21324
+}
+
+fn default_wled_timeout() -> u8 {
+    2
+}
+
+fn default_wled_rewrite_time() -> u32 {
+    1000
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Wled {
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    /// Hostname or IP address of the WLED controller
+    pub host: String,
+    #[serde(default = "default_wled_port")]
+    pub port: u16,
+    #[serde(default = "Default::default")]
+    pub protocol: WledProtocol,
+    /// Seconds without data before WLED goes back to its own effects, 255 to never time out
+    #[serde(default = "default_wled_timeout")]
+    #[validate(range(min = 1))]
+    pub timeout: u8,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    /// Should be shorter than the timeout, so WLED stays in realtime mode on static inputs
+    #[serde(default = "default_wled_rewrite_time")]
+    pub rewrite_time: u32,
+    /// Interval at which the LED count is read from the WLED JSON API in milliseconds, 0 to
+    /// disable
+    #[serde(default = "Default::default")]
+    pub led_count_refresh_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+}
+
+impl DeviceConfig for Wled {
+    fn hardware_led_count(&self) -> usize {
+        self.hardware_led_count as _
+    }
+
+    fn rewrite_time(&self) -> Option<std::time::Duration> {
+        if self.rewrite_time == 0 {
+            None
+        } else {
+            Some(std::time::Duration::from_millis(self.rewrite_time as _))
+        }
+    }
+
+    fn latch_time(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.latch_time as _)
+    }
+
+    fn output_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.output_delay as _)
+    }
+
+    fn max_fps(&self) -> Option<u32> {
+        if self.max_fps == 0 {
+            None
+        } else {
+            Some(self.max_fps)
+        }
+    }
+
+    fn segments(&self) -> &[DeviceSegment] {
+        &self.segments
+    }
+
+    fn keep_alive(&self) -> Option<&KeepAlive> {
+        self.keep_alive.as_ref()
+    }
+
+    fn voltage_drop(&self) -> Option<&VoltageDrop> {
+        self.voltage_drop.as_ref()
+    }
+
+    fn lut(&self) -> Option<&DeviceLut> {
+        self.lut.as_ref()
+    }
+
+    fn led_count_refresh_time(&self) -> Option<std::time::Duration> {
+        if self.led_count_refresh_time == 0 {
+            None
+        } else {
+            Some(std::time::Duration::from_millis(
+                self.led_count_refresh_time as _,
+            ))
+        }
+    }
+}
+
+fn default_e131_port() -> u16 {
+    5568
+}
+
+fn default_e131_universe() -> u16 {
+    1
+}
+
+fn default_e131_universe_size() -> u16 {
+    510
+}
+
+fn default_e131_priority() -> u8 {
+    100
+}
+
+fn default_e131_source_name() -> String {
+    "hyperion.rs".to_owned()
+}
+
+/// DMX controllers and pixel controllers (Falcon, ESPixelStick...) receiving E1.31 (sACN) over UDP
+///
+/// The LEDs are split over consecutive universes starting at `universe`, without spanning a LED
+/// over two universes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct E131 {
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    /// Hostname or IP address of the controller, empty to multicast each universe to its
+    /// standard group
+    #[serde(default = "String::new")]
+    pub host: String,
+    #[serde(default = "default_e131_port")]
+    pub port: u16,
+    /// First universe of the LEDs
+    #[serde(default = "default_e131_universe")]
+    #[validate(range(min = 1, max = 63999))]
+    pub universe: u16,
+    /// Number of DMX channels used in each universe
+    #[serde(default = "default_e131_universe_size")]
+    #[validate(range(min = 3, max = 512))]
+    pub universe_size: u16,
+    /// Priority of the data, receivers use the source with the highest one
+    #[serde(default = "default_e131_priority")]
+    #[validate(range(max = 200))]
+    pub priority: u8,
+    #[serde(default = "default_e131_source_name")]
+    #[validate(length(max = 63))]
+    pub source_name: String,
+    /// Component identifier of the source, derived from the hostname and source name if unset
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub cid: Option<uuid::Uuid>,
+    #[serde(default = "Default::default")]
+    pub color_order: ColorOrder,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    #[serde(default = "default_ws_spi_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+}
+
+impl_device_config!(E131);
+
+fn default_boblight_port() -> u16 {
+    19333
+}
+
+fn default_boblight_priority() -> u8 {
+    128
+}
+
+/// Client of a boblightd server, for setups whose hardware is driven by boblightd
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Boblight {
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    /// Hostname or IP address of the boblightd server
+    pub host: String,
+    #[serde(default = "default_boblight_port")]
+    pub port: u16,
+    /// Priority among the clients of boblightd, lower values win. 255 turns the client off.
+    #[serde(default = "default_boblight_priority")]
+    #[validate(range(max = 254))]
+    pub priority: u8,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    #[serde(default = "Default::default")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+}
+
+impl_device_config!(Boblight);
+
+fn default_atmoorb_host() -> String {
+    "239.255.255.250".to_owned()
+}
+
+fn default_atmoorb_port() -> u16 {
+    49692
+}
+
+fn default_atmoorb_num_leds() -> u16 {
+    24
+}
+
+/// AtmoOrb lamps, listening for colors on a UDP multicast group
+///
+/// Each orb is driven by a single LED: the first LED drives orb 1, the second orb 2, and so on.
+/// Only the orbs listed in `orbIds` are sent colors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[validate(schema(function = "validate_atmoorb"))]
+pub struct AtmoOrb {
+    #[validate(range(min = 1, max = 255))]
+    pub hardware_led_count: u32,
+    /// Multicast group (or address of a single orb) the colors are sent to
+    #[serde(default = "default_atmoorb_host")]
+    pub host: String,
+    #[serde(default = "default_atmoorb_port")]
+    pub port: u16,
+    /// Identifiers of the orbs to drive, as a list or a comma-separated string
+    #[serde(deserialize_with = "crate::serde::from_comma_list")]
+    #[validate(length(min = 1))]
+    pub orb_ids: Vec<u8>,
+    /// Let the orbs fade to the new colors
+    #[serde(default = "Default::default")]
+    pub use_orb_smoothing: bool,
+    /// Color difference above which the orbs change color immediately, even with smoothing. 0
+    /// to always smooth.
+    #[serde(default = "Default::default")]
+    pub skip_smoothing_diff: u8,
+    /// Number of LEDs inside each orb, which sets the size of the datagrams
+    #[serde(default = "default_atmoorb_num_leds")]
+    #[validate(range(min = 1, max = 255))]
+    pub num_leds: u16,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    #[serde(default = "default_ws_spi_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
 }
 
+fn validate_atmoorb(config: &AtmoOrb) -> Result<(), validator::ValidationError> {
+    // Orb 0 addresses all the orbs at once, and each orb needs a LED to take its color from
+    if config
+        .orb_ids
+        .iter()
+        .any(|&id| id == 0 || id as u32 > config.hardware_led_count)
+    {
+        return Err(validator::ValidationError::new("invalid_orb_id"));
+    }
+
+    Ok(())
+}
+
+impl_device_config!(AtmoOrb);
+
+fn default_ddp_port() -> u16 {
+    4048
+}
+
+fn default_ddp_destination() -> u8 {
+    1
+}
+
+/// Controllers receiving the Distributed Display Protocol over UDP (WLED, xLights, ESPixelStick...)
+///
+/// Frames larger than a single datagram are split at data offsets, the last datagram pushes the
+/// whole frame to the LEDs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Ddp {
+    #[validate(range(min = 1))]
+    pub hardware_led_count: u32,
+    /// Hostname or IP address of the controller
+    pub host: String,
+    #[serde(default = "default_ddp_port")]
+    pub port: u16,
+    /// Output of the controller receiving the data, 1 for its default output
+    #[serde(default = "default_ddp_destination")]
+    #[validate(range(min = 1))]
+    pub destination_id: u8,
+    #[serde(default = "Default::default")]
+    pub color_order: ColorOrder,
+    #[serde(default = "Default::default")]
+    pub latch_time: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 10000))]
+    pub output_delay: u32,
+    #[serde(default = "Default::default")]
+    #[validate(range(max = 1000))]
+    pub max_fps: u32,
+    #[serde(default = "default_ws_spi_rewrite_time")]
+    pub rewrite_time: u32,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<DeviceSegment>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub voltage_drop: Option<VoltageDrop>,
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub lut: Option<DeviceLut>,
+}
+
+impl_device_config!(Ddp);
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, IntoStaticStr, Delegate, From)]
 #[serde(rename_all = "lowercase", tag = "type", deny_unknown_fields)]
+#[strum(serialize_all = "lowercase")]
 #[delegate(DeviceConfig)]
 pub enum Device {
     Dummy(Dummy),
     Ws2812Spi(Ws2812Spi),
+    Sk6812Spi(Sk6812Spi),
+    Apa102(Apa102),
+    Ws281x(Ws281x),
     PhilipsHue(PhilipsHue),
     File(File),
+    Pipe(Pipe),
+    Wled(Wled),
+    E131(E131),
+    Adalight(Adalight),
+    Boblight(Boblight),
+    AtmoOrb(AtmoOrb),
+    Ddp(Ddp),
 }
 
 impl Default for Device {
@@ -187,8 +1366,18 @@ impl Validate for Device {
         match self {
             Device::Dummy(device) => device.validate(),
             Device::Ws2812Spi(device) => device.validate(),
+            Device::Sk6812Spi(device) => device.validate(),
+            Device::Apa102(device) => device.validate(),
+            Device::Ws281x(device) => device.validate(),
             Device::PhilipsHue(device) => device.validate(),
             Device::File(device) => device.validate(),
+            Device::Pipe(device) => device.validate(),
+            Device::Wled(device) => device.validate(),
+            Device::E131(device) => device.validate(),
+            Device::Adalight(device) => device.validate(),
+            Device::Boblight(device) => device.validate(),
+            Device::AtmoOrb(device) => device.validate(),
+            Device::Ddp(device) => device.validate(),
         }
     }
 }
@@ -3,7 +3,7 @@ use std::num::NonZeroUsize;
 use serde_derive::{Deserialize, Serialize};
 use validator::Validate;
 
-use super::ServerConfig;
+use super::{timeout_secs, Color, ServerConfig};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
@@ -13,6 +13,8 @@ pub struct FlatbuffersServer {
     pub port: u16,
     #[validate(range(min = 1))]
     pub timeout: u32,
+    /// Clamp out-of-range priorities from legacy clients instead of rejecting them
+    pub legacy_priorities: bool,
 }
 
 impl Default for FlatbuffersServer {
@@ -21,6 +23,7 @@ impl Default for FlatbuffersServer {
             enable: true,
             port: 19400,
             timeout: 5,
+            legacy_priorities: false,
         }
     }
 }
@@ -29,6 +32,10 @@ impl ServerConfig for FlatbuffersServer {
     fn port(&self) -> u16 {
         self.port
     }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        timeout_secs(self.timeout)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
@@ -59,8 +66,12 @@ pub enum FramegrabberType {
     DispmanX,
     DirectX9,
     Framebuffer,
+    /// Scanout buffer of a KMS/DRM display, without a desktop session
+    Drm,
     OSX,
     QT,
+    /// Screen sharing through xdg-desktop-portal, for Wayland desktops
+    Wayland,
     X11,
     XCB,
 }
@@ -206,16 +217,113 @@ impl Default for GrabberV4L2 {
     }
 }
 
+/// Visualization rendered from the captured audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[derive(Default)]
+pub enum AudioEffect {
+    /// Level meter rising with the volume
+    #[default]
+    VuMeter,
+    /// One level meter per frequency band, from the lowest to the highest (hyperion.rs extension)
+    Spectrum,
+}
+
+/// Mirroring of the rendered audio visualization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[derive(Default)]
+pub enum AudioFlip {
+    #[default]
+    NoChange,
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct VuMeter {
+    pub flip: AudioFlip,
+    #[serde(serialize_with = "crate::serde::serialize_color_as_array")]
+    pub hot_color: Color,
+    #[serde(serialize_with = "crate::serde::serialize_color_as_array")]
+    pub warn_color: Color,
+    /// Level above which the hot color is used, in percent
+    #[validate(range(min = 0, max = 100))]
+    pub warn_value: u32,
+    #[serde(serialize_with = "crate::serde::serialize_color_as_array")]
+    pub safe_color: Color,
+    /// Level above which the warn color is used, in percent
+    #[validate(range(min = 0, max = 100))]
+    pub safe_value: u32,
+    /// Gain applied to the captured levels, 0 to adjust it automatically to the loudest sounds
+    #[validate(range(min = 0., max = 200.))]
+    pub multiplier: f32,
+    /// Headroom kept by the automatic gain, in percent
+    #[validate(range(min = 0, max = 100))]
+    pub tolerance: u32,
+}
+
+impl Default for VuMeter {
+    fn default() -> Self {
+        Self {
+            flip: Default::default(),
+            hot_color: Color::new(255, 0, 0),
+            warn_color: Color::new(255, 255, 0),
+            warn_value: 80,
+            safe_color: Color::new(0, 255, 0),
+            safe_value: 45,
+            multiplier: 1.,
+            tolerance: 5,
+        }
+    }
+}
+
+/// Audio capture, shared by the instances with `audioEnable` in their `instCapture` settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct GrabberAudio {
+    /// Name of the ALSA capture device, `auto` for the default one
+    pub device: String,
+    pub audio_effect: AudioEffect,
+    #[validate(nested)]
+    pub vu_meter: VuMeter,
+    /// Number of frequency bands of the spectrum (hyperion.rs extension)
+    #[validate(range(min = 1, max = 64))]
+    pub bands: u32,
+}
+
+impl Default for GrabberAudio {
+    fn default() -> Self {
+        Self {
+            device: "auto".to_owned(),
+            audio_effect: Default::default(),
+            vu_meter: Default::default(),
+            bands: 16,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(default, deny_unknown_fields)]
 pub struct JsonServer {
     #[validate(range(min = 1024))]
     pub port: u16,
+    /// Idle timeout of clients in seconds, 0 to keep idle clients connected
+    pub timeout: u32,
+    /// Clamp out-of-range priorities from legacy clients instead of rejecting them
+    #[serde(rename = "legacyPriorities")]
+    pub legacy_priorities: bool,
 }
 
 impl Default for JsonServer {
     fn default() -> Self {
-        Self { port: 19444 }
+        Self {
+            port: 19444,
+            timeout: 0,
+            legacy_priorities: false,
+        }
     }
 }
 
@@ -223,6 +331,10 @@ impl ServerConfig for JsonServer {
     fn port(&self) -> u16 {
         self.port
     }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        timeout_secs(self.timeout)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -286,6 +398,8 @@ pub struct ProtoServer {
     pub port: u16,
     #[validate(range(min = 1))]
     pub timeout: u32,
+    /// Clamp out-of-range priorities from legacy clients instead of rejecting them
+    pub legacy_priorities: bool,
 }
 
 impl Default for ProtoServer {
@@ -294,6 +408,7 @@ impl Default for ProtoServer {
             enable: true,
             port: 19445,
             timeout: 5,
+            legacy_priorities: false,
         }
     }
 }
@@ -302,6 +417,10 @@ impl ServerConfig for ProtoServer {
     fn port(&self) -> u16 {
         self.port
     }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        timeout_secs(self.timeout)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
@@ -311,16 +430,26 @@ pub struct WebConfig {
     pub document_root: String,
     #[validate(range(min = 80))]
     pub port: u16,
+    /// Port of the HTTPS server, started when both `crt_path` and `key_path` are set
     #[validate(range(min = 80))]
     pub ssl_port: u16,
+    /// PEM certificate chain of the HTTPS server
     pub crt_path: String,
+    /// PEM private key of the HTTPS server, encrypted with `key_pass_phrase` if it isn't empty
     pub key_path: String,
     pub key_pass_phrase: String,
+    /// Redirect all requests on the plain HTTP port to the HTTPS server
+    pub ssl_redirect: bool,
     pub max_sessions: NonZeroUsize,
 }
 
 impl WebConfig {
     pub const SYSTEM_DOCUMENT_ROOT: &'static str = "$ROOT/webconfig";
+
+    /// Check if the HTTPS server is configured
+    pub fn is_ssl_enabled(&self) -> bool {
+        !self.crt_path.is_empty() && !self.key_path.is_empty()
+    }
 }
 
 impl Default for WebConfig {
@@ -332,6 +461,7 @@ impl Default for WebConfig {
             crt_path: String::new(),
             key_path: String::new(),
             key_pass_phrase: String::new(),
+            ssl_redirect: false,
             max_sessions: NonZeroUsize::new(4).unwrap(),
         }
     }
@@ -352,12 +482,65 @@ pub struct Hooks {
     /// Command to run when an instance is deactivated. HYPERION_INSTANCE_ID environment variable
     /// will hold the instance id.
     pub instance_deactivate: Vec<String>,
+    /// Command to run when the LED count reported by an instance device changes.
+    /// HYPERION_INSTANCE_ID, HYPERION_PREVIOUS_LED_COUNT and HYPERION_LED_COUNT environment
+    /// variables will hold the instance id, and the previous and new LED counts.
+    pub instance_led_count_changed: Vec<String>,
+    /// Command to run when a grabber starts or stops capturing, or when a component of an
+    /// instance is toggled by an API client. HYPERION_COMPONENT and HYPERION_COMPONENT_ENABLED
+    /// environment variables will hold the component name (e.g. V4L) and true or false, and
+    /// HYPERION_INSTANCE_ID the instance id for toggled components.
+    pub component_state_changed: Vec<String>,
     /// Command to run when hyperion.rs starts
     pub start: Vec<String>,
     /// Command to run when hyperion.rs stops
     pub stop: Vec<String>,
 }
 
+/// MQTT client, announcing instances as Home Assistant lights
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Mqtt {
+    pub enable: bool,
+    /// Hostname of the MQTT broker
+    pub host: String,
+    #[validate(range(min = 1))]
+    pub port: u16,
+    /// Username to connect with, or empty for anonymous connections
+    pub username: String,
+    pub password: String,
+    /// Prefix of the state and command topics of instances
+    #[validate(length(min = 1))]
+    pub base_topic: String,
+    /// Publish Home Assistant discovery messages
+    pub home_assistant: bool,
+    /// Topic prefix Home Assistant subscribes to for discovery messages
+    #[validate(length(min = 1))]
+    pub discovery_prefix: String,
+    /// Identifier of this server in discovery messages, or empty to use the hostname
+    pub node_id: String,
+    /// Priority of the color set through MQTT
+    #[validate(range(min = 100, max = 254))]
+    pub priority: i32,
+}
+
+impl Default for Mqtt {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            host: "localhost".to_owned(),
+            port: 1883,
+            username: String::new(),
+            password: String::new(),
+            base_topic: "hyperion".to_owned(),
+            home_assistant: true,
+            discovery_prefix: "homeassistant".to_owned(),
+            node_id: String::new(),
+            priority: 150,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct GlobalConfig {
@@ -367,10 +550,36 @@ pub struct GlobalConfig {
     pub general: General,
     #[serde(rename = "grabberV4L2")]
     pub grabber_v4l2: GrabberV4L2,
+    pub grabber_audio: GrabberAudio,
     pub json_server: JsonServer,
     pub logger: Logger,
     pub network: Network,
     pub proto_server: ProtoServer,
     pub web_config: WebConfig,
     pub hooks: Hooks,
+    pub mqtt: Mqtt,
+}
+
+impl GlobalConfig {
+    /// Global settings, by setting type
+    pub fn settings(&self) -> Result<Vec<(&'static str, serde_json::Value)>, serde_json::Error> {
+        Ok(vec![
+            (
+                "flatbufServer",
+                serde_json::to_value(&self.flatbuffers_server)?,
+            ),
+            ("forwarder", serde_json::to_value(&self.forwarder)?),
+            ("framegrabber", serde_json::to_value(&self.framegrabber)?),
+            ("general", serde_json::to_value(&self.general)?),
+            ("grabberV4L2", serde_json::to_value(&self.grabber_v4l2)?),
+            ("grabberAudio", serde_json::to_value(&self.grabber_audio)?),
+            ("jsonServer", serde_json::to_value(self.json_server)?),
+            ("logger", serde_json::to_value(&self.logger)?),
+            ("network", serde_json::to_value(&self.network)?),
+            ("protoServer", serde_json::to_value(&self.proto_server)?),
+            ("webConfig", serde_json::to_value(&self.web_config)?),
+            ("hooks", serde_json::to_value(&self.hooks)?),
+            ("mqtt", serde_json::to_value(&self.mqtt)?),
+        ])
+    }
 }
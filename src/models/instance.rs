@@ -6,7 +6,7 @@ use validator::Validate;
 
 use crate::db::models as db_models;
 
-use super::{default_true, Color, Device, ServerConfig};
+use super::{default_true, timeout_secs, Color, Device, DeviceConfig, ServerConfig};
 
 #[derive(Debug, Error)]
 pub enum InstanceError {
@@ -14,6 +14,34 @@ pub enum InstanceError {
     Chrono(#[from] chrono::ParseError),
 }
 
+/// Inconsistency between the LED counts of the sections of an instance configuration
+///
+/// These are not fatal: at runtime, LED data is truncated or padded with black to fit the
+/// device, and adjustment ranges are clamped to the layout.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LedCountMismatch {
+    #[error("the layout has {layout} LEDs but the device is configured for {device}")]
+    Device { layout: usize, device: usize },
+    #[error("the device has {segments} segments but is configured for {device} LEDs")]
+    Segments { segments: usize, device: usize },
+    #[error("device segment {segment} targets LED {index} but the layout only has {layout} LEDs")]
+    Segment {
+        segment: usize,
+        index: usize,
+        layout: usize,
+    },
+    #[error("the keep-alive LED is {led} but the device is configured for {device} LEDs")]
+    KeepAlive { led: usize, device: usize },
+    #[error("power is injected at LED {led} but the device is configured for {device} LEDs")]
+    InjectionPoint { led: usize, device: usize },
+    #[error("channel adjustment `{id}` targets LED {index} but the layout only has {layout} LEDs")]
+    Adjustment {
+        id: String,
+        index: usize,
+        layout: usize,
+    },
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Instance {
@@ -70,6 +98,358 @@ impl Default for BackgroundEffect {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum BootSplashAnimation {
+    /// Light up the LEDs one after the other, then fade out
+    #[default]
+    Wipe,
+    /// Fade all the LEDs in and out
+    Pulse,
+}
+
+/// Animation played when the instance starts, until the first input is received
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct BootSplash {
+    pub enable: bool,
+    pub animation: BootSplashAnimation,
+    #[serde(serialize_with = "crate::serde::serialize_color_as_array")]
+    pub color: Color,
+    #[validate(range(min = 100, max = 10000))]
+    pub duration_ms: u32,
+}
+
+impl Default for BootSplash {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            animation: Default::default(),
+            color: Color::from_components((255, 255, 255)),
+            duration_ms: 1500,
+        }
+    }
+}
+
+/// Time window during which the output brightness of an instance is capped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct QuietHours {
+    pub enable: bool,
+    /// Local time at which the quiet hours start
+    pub start: chrono::NaiveTime,
+    /// Local time at which the quiet hours end
+    pub end: chrono::NaiveTime,
+    /// Maximum brightness during the quiet hours, in percent
+    #[validate(range(max = 100))]
+    pub max_brightness: u32,
+    /// Duration of the transitions before the start and after the end of the quiet hours
+    #[validate(range(max = 720))]
+    pub ramp_minutes: u32,
+}
+
+impl Default for QuietHours {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            start: chrono::NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: chrono::NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            max_brightness: 30,
+            ramp_minutes: 15,
+        }
+    }
+}
+
+/// Latency tracking for image inputs, from their capture to the device write
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct FrameLatency {
+    /// Images older than this when they reach the instance are dropped, in milliseconds. 0 to
+    /// never drop images.
+    #[validate(range(max = 10000))]
+    pub budget_ms: u32,
+    /// Interval between latency reports in the logs, in seconds. 0 to disable reports.
+    #[validate(range(max = 3600))]
+    pub report_interval_secs: u32,
+}
+
+impl Default for FrameLatency {
+    fn default() -> Self {
+        Self {
+            budget_ms: 0,
+            report_interval_secs: 60,
+        }
+    }
+}
+
+/// Limits on the size of the frames waiting to be processed
+///
+/// Frames are dropped, oldest first, when inputs send them faster than the instance processes
+/// them, so a burst of large images can't exhaust the memory of the host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct MemoryLimits {
+    /// Total size of the frames queued for this instance, in MiB
+    #[validate(range(min = 1, max = 4096))]
+    pub input_queue_mb: u32,
+    /// Total size of the frames queued by each running effect, in MiB
+    #[validate(range(min = 1, max = 4096))]
+    pub effect_queue_mb: u32,
+}
+
+impl MemoryLimits {
+    pub fn input_queue_bytes(&self) -> usize {
+        self.input_queue_mb as usize * 1024 * 1024
+    }
+
+    pub fn effect_queue_bytes(&self) -> usize {
+        self.effect_queue_mb as usize * 1024 * 1024
+    }
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            input_queue_mb: 64,
+            effect_queue_mb: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum NdiBandwidth {
+    /// Low resolution preview stream, which is enough for computing LED colors
+    #[default]
+    Lowest,
+    /// Full resolution stream
+    Highest,
+}
+
+/// Receive images from an NDI source on the network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct NdiReceiver {
+    pub enable: bool,
+    /// Name of the NDI source, as advertised on the network (e.g. `HOST (Source name)`)
+    pub source: String,
+    /// Address of the NDI source, to connect without discovery
+    pub url_address: String,
+    #[validate(range(min = 100, max = 254))]
+    pub priority: i32,
+    pub bandwidth: NdiBandwidth,
+}
+
+impl Default for NdiReceiver {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            source: String::new(),
+            url_address: String::new(),
+            priority: 150,
+            bandwidth: Default::default(),
+        }
+    }
+}
+
+/// Show the album artwork of the media currently playing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct MediaArtwork {
+    pub enable: bool,
+    /// Name of the MPRIS player to follow (e.g. `spotify`), or empty to follow any player
+    pub player: String,
+    #[validate(range(min = 100, max = 254))]
+    pub priority: i32,
+    #[validate(range(min = 250, max = 60000))]
+    pub poll_interval_ms: u32,
+    /// Duration of the crossfade between two artworks
+    #[validate(range(max = 30000))]
+    pub transition_ms: u32,
+}
+
+impl Default for MediaArtwork {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            player: String::new(),
+            priority: 220,
+            poll_interval_ms: 2000,
+            transition_ms: 3000,
+        }
+    }
+}
+
+/// Use the output of another instance as an input of this one
+///
+/// The mirrored LEDs are resampled to the LED count of this instance, so a differently-shaped
+/// device can follow the same processed data without capturing it again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct InstanceMirror {
+    pub enable: bool,
+    /// Id of the instance whose output is mirrored
+    #[validate(range(min = 0))]
+    pub source: i32,
+    /// First LED of the source output to mirror
+    pub first_led: u32,
+    /// Number of LEDs to mirror, 0 for all the LEDs from `first_led`
+    pub led_count: u32,
+    #[validate(range(min = 1, max = 254))]
+    pub priority: i32,
+}
+
+impl Default for InstanceMirror {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            source: 0,
+            first_led: 0,
+            led_count: 0,
+            priority: 240,
+        }
+    }
+}
+
+/// Rule mapping a value of a JSON document to a color or an effect
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+#[validate(schema(function = "validate_data_preset_rule"))]
+pub struct DataPresetRule {
+    /// JSON pointer to the value this rule checks (e.g. `/main/temp`)
+    pub pointer: String,
+    /// The rule matches if the value is equal to this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equals: Option<serde_json::Value>,
+    /// The rule matches if the value is a number greater than this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub above: Option<f64>,
+    /// The rule matches if the value is a number less than this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option<f64>,
+    #[serde(rename = "type")]
+    pub ty: EffectType,
+    #[serde(serialize_with = "crate::serde::serialize_color_as_array")]
+    pub color: Color,
+    pub effect: String,
+}
+
+impl Default for DataPresetRule {
+    fn default() -> Self {
+        Self {
+            pointer: String::new(),
+            equals: None,
+            above: None,
+            below: None,
+            ty: EffectType::Color,
+            color: Color::from_components((255, 255, 255)),
+            effect: String::new(),
+        }
+    }
+}
+
+fn validate_data_preset_rule(rule: &DataPresetRule) -> Result<(), validator::ValidationError> {
+    if !rule.pointer.is_empty() && !rule.pointer.starts_with('/') {
+        return Err(validator::ValidationError::new("invalid_pointer"));
+    }
+
+    if rule.ty == EffectType::Effect && rule.effect.is_empty() {
+        return Err(validator::ValidationError::new("missing_effect"));
+    }
+
+    Ok(())
+}
+
+/// Show colors or effects depending on the contents of a JSON document fetched periodically
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct DataPresets {
+    pub enable: bool,
+    /// URL of the JSON document
+    pub url: String,
+    #[validate(range(min = 1, max = 86400))]
+    pub poll_interval_secs: u32,
+    #[validate(range(min = 100, max = 254))]
+    pub priority: i32,
+    /// Rules checked in order, the first matching one is applied. If none match, the priority
+    /// is cleared.
+    #[validate(nested)]
+    pub rules: Vec<DataPresetRule>,
+}
+
+impl Default for DataPresets {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            url: String::new(),
+            poll_interval_secs: 60,
+            priority: 250,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Run the whole pipeline of an instance without writing to its device
+///
+/// The output is still sent to the LED color and frame observers (e.g. the JSON `ledcolors`
+/// stream), so a new layout or calibration can be checked on live content before it reaches the
+/// strip.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct DryRun {
+    pub enable: bool,
+}
+
+/// Check that the device of an instance is reachable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "type", deny_unknown_fields)]
+pub enum ReadinessProbe {
+    /// Connect to a TCP port
+    Tcp { host: String, port: u16 },
+    /// Send a GET request to an http:// URL, expecting a 2xx or 3xx status. HTTPS is not
+    /// supported.
+    Http { url: String },
+}
+
+fn validate_instance_startup(startup: &InstanceStartup) -> Result<(), validator::ValidationError> {
+    if let Some(ReadinessProbe::Http { url }) = &startup.probe {
+        if !url.starts_with("http://") {
+            return Err(validator::ValidationError::new("unsupported_probe_url"));
+        }
+    }
+
+    Ok(())
+}
+
+/// How the device of an instance is brought up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+#[validate(schema(function = "validate_instance_startup"))]
+pub struct InstanceStartup {
+    /// Delay before initializing the device
+    #[validate(range(max = 600000))]
+    pub delay_ms: u32,
+    /// Probe which must succeed before initializing the device
+    pub probe: Option<ReadinessProbe>,
+    #[validate(range(min = 100))]
+    pub probe_interval_ms: u32,
+    /// Time after which the device is initialized even if the probe still fails
+    pub probe_timeout_ms: u32,
+}
+
+impl Default for InstanceStartup {
+    fn default() -> Self {
+        Self {
+            delay_ms: 0,
+            probe: None,
+            probe_interval_ms: 1000,
+            probe_timeout_ms: 60000,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase", deny_unknown_fields)]
 pub enum BlackBorderDetectorMode {
@@ -115,6 +495,8 @@ pub struct BoblightServer {
     pub port: u16,
     #[validate(range(min = 100, max = 254))]
     pub priority: i32,
+    /// Idle timeout of clients in seconds, 0 to keep idle clients connected
+    pub timeout: u32,
 }
 
 impl Default for BoblightServer {
@@ -123,6 +505,7 @@ impl Default for BoblightServer {
             enable: false,
             port: 19333,
             priority: 128,
+            timeout: 0,
         }
     }
 }
@@ -131,6 +514,42 @@ impl ServerConfig for BoblightServer {
     fn port(&self) -> u16 {
         self.port
     }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        timeout_secs(self.timeout)
+    }
+}
+
+/// Listener for raw RGB frames sent over UDP
+///
+/// Each datagram holds three bytes per LED, in the order of the LED layout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct UdpListener {
+    pub enable: bool,
+    /// Address to listen on, multicast groups are joined on all interfaces
+    pub address: String,
+    #[validate(range(min = 1024))]
+    pub port: u16,
+    #[validate(range(min = 100, max = 254))]
+    pub priority: i32,
+    /// Duration of the received colors in milliseconds, 0 to keep them until replaced
+    pub timeout: u32,
+    /// Allow other processes to bind the same port
+    pub shared: bool,
+}
+
+impl Default for UdpListener {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            address: "239.255.28.1".to_owned(),
+            port: 2801,
+            priority: 200,
+            timeout: 10000,
+            shared: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -148,6 +567,10 @@ pub struct ColorAdjustment {
     pub image_to_led_mapping_type: ImageToLedMappingType,
     #[validate(nested)]
     pub channel_adjustment: Vec<ChannelAdjustment>,
+    /// Per-LED gain correction, applied after the channel adjustments (hyperion.rs extension)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[validate(nested)]
+    pub led_gain: Vec<LedGain>,
 }
 
 impl Default for ColorAdjustment {
@@ -156,10 +579,73 @@ impl Default for ColorAdjustment {
             rgb_temperature: 6600,
             image_to_led_mapping_type: ImageToLedMappingType::MulticolorMean,
             channel_adjustment: vec![ChannelAdjustment::default()],
+            led_gain: Vec::new(),
         }
     }
 }
 
+/// Gain correction of a range of LEDs, e.g. to compensate the voltage drop along a strip
+///
+/// When entries overlap, the last one applies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct LedGain {
+    /// LEDs to correct, with the same syntax as channel adjustments
+    pub leds: String,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub red: f32,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub green: f32,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub blue: f32,
+}
+
+impl Default for LedGain {
+    fn default() -> Self {
+        Self {
+            leds: "*".to_owned(),
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        }
+    }
+}
+
+impl LedGain {
+    /// Gains evening out a linear brightness drift between LEDs `first` and `last`
+    ///
+    /// `start` and `end` are the brightness of the red, green and blue channels measured on
+    /// these LEDs, in any unit. Each LED is dimmed to the dimmest measured endpoint, so no gain
+    /// is above 1.
+    pub fn ramp(first: usize, last: usize, start: [f32; 3], end: [f32; 3]) -> Vec<Self> {
+        let (first, last, start, end) = if first <= last {
+            (first, last, start, end)
+        } else {
+            (last, first, end, start)
+        };
+
+        let span = (last - first).max(1) as f32;
+        (first..=last)
+            .map(|index| {
+                let t = (index - first) as f32 / span;
+                let gain = |c: usize| {
+                    let measured = start[c] + (end[c] - start[c]) * t;
+                    let gain = start[c].min(end[c]) / measured;
+                    // Keep the configuration readable
+                    (gain * 1000.0).round() / 1000.0
+                };
+
+                Self {
+                    leds: index.to_string(),
+                    red: gain(0),
+                    green: gain(1),
+                    blue: gain(2),
+                }
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ChannelAdjustment {
@@ -296,6 +782,10 @@ pub struct InstanceCapture {
     pub v4l_enable: bool,
     #[validate(range(min = 100, max = 253))]
     pub v4l_priority: i32,
+    /// Show the audio visualization of the `grabberAudio` settings
+    pub audio_enable: bool,
+    #[validate(range(min = 100, max = 253))]
+    pub audio_priority: i32,
 }
 
 impl Default for InstanceCapture {
@@ -305,6 +795,8 @@ impl Default for InstanceCapture {
             system_priority: 250,
             v4l_enable: false,
             v4l_priority: 240,
+            audio_enable: false,
+            audio_priority: 230,
         }
     }
 }
@@ -471,6 +963,47 @@ impl Default for Leds {
     }
 }
 
+impl Leds {
+    /// Maximum dimension of the grid returned by [Leds::grid_size]
+    const MAX_GRID_SIZE: u32 = 80;
+
+    /// Size of the grid formed by the centers of the LEDs, with one column per distinct
+    /// horizontal position and one row per distinct vertical position
+    ///
+    /// This is the size of the images painted by effects, so each pixel maps to at least one LED.
+    /// The aspect ratio is kept between 1:2 and 2:1, and the largest dimension is limited to
+    /// [Leds::MAX_GRID_SIZE].
+    pub fn grid_size(&self) -> (u32, u32) {
+        let distinct = |center: fn(&Led) -> f32| {
+            let mut centers: Vec<_> = self
+                .leds
+                .iter()
+                .map(|led| (center(led).clamp(0., 1.) * 1000.) as u32)
+                .collect();
+            centers.sort_unstable();
+            centers.dedup();
+            centers.len().max(1) as u32
+        };
+
+        let mut width = distinct(|led| (led.hmin + led.hmax) / 2.);
+        let mut height = distinct(|led| (led.vmin + led.vmax) / 2.);
+
+        if width > 2 * height {
+            height = (width / 2).max(1);
+        } else if 2 * width < height {
+            width = (height / 2).max(1);
+        }
+
+        if width > Self::MAX_GRID_SIZE || height > Self::MAX_GRID_SIZE {
+            let largest = width.max(height);
+            width = (width * Self::MAX_GRID_SIZE / largest).max(1);
+            height = (height * Self::MAX_GRID_SIZE / largest).max(1);
+        }
+
+        (width, height)
+    }
+}
+
 impl serde::Serialize for Leds {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -543,6 +1076,31 @@ impl Default for Smoothing {
     }
 }
 
+impl Smoothing {
+    /// Settings of this smoothing with the values of `over` replacing the configured ones
+    pub fn with_override(&self, over: &SmoothingOverride) -> Self {
+        Self {
+            enable: over.enable.unwrap_or(self.enable),
+            time_ms: over.time_ms.unwrap_or(self.time_ms),
+            update_frequency: over.update_frequency.unwrap_or(self.update_frequency),
+            ..self.clone()
+        }
+    }
+}
+
+/// Smoothing settings requested by an input, used instead of the instance ones while it is
+/// visible
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct SmoothingOverride {
+    pub enable: Option<bool>,
+    #[serde(rename = "time_ms")]
+    #[validate(range(min = 25, max = 5000))]
+    pub time_ms: Option<u32>,
+    #[validate(range(min = 1., max = 2000.))]
+    pub update_frequency: Option<f32>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct InstanceConfig {
@@ -581,9 +1139,153 @@ pub struct InstanceConfig {
     #[validate(nested)]
     #[serde(default = "Default::default")]
     pub smoothing: Smoothing,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub boot_splash: BootSplash,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub startup: InstanceStartup,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub quiet_hours: QuietHours,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub frame_latency: FrameLatency,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub memory_limits: MemoryLimits,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub ndi_receiver: NdiReceiver,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub media_artwork: MediaArtwork,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub data_presets: DataPresets,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub instance_mirror: InstanceMirror,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub dry_run: DryRun,
+    #[validate(nested)]
+    #[serde(default = "Default::default")]
+    pub udp_listener: UdpListener,
 }
 
 impl InstanceConfig {
+    /// Settings of this instance, by setting type
+    pub fn settings(&self) -> Result<Vec<(&'static str, serde_json::Value)>, serde_json::Error> {
+        Ok(vec![
+            (
+                "backgroundEffect",
+                serde_json::to_value(&self.background_effect)?,
+            ),
+            (
+                "blackborderdetector",
+                serde_json::to_value(&self.black_border_detector)?,
+            ),
+            (
+                "boblightServer",
+                serde_json::to_value(&self.boblight_server)?,
+            ),
+            ("color", serde_json::to_value(&self.color)?),
+            ("device", serde_json::to_value(&self.device)?),
+            ("effects", serde_json::to_value(&self.effects)?),
+            (
+                "foregroundEffect",
+                serde_json::to_value(&self.foreground_effect)?,
+            ),
+            ("instCapture", serde_json::to_value(&self.instance_capture)?),
+            ("ledConfig", serde_json::to_value(&self.led_config)?),
+            ("leds", serde_json::to_value(&self.leds)?),
+            ("smoothing", serde_json::to_value(&self.smoothing)?),
+            ("bootSplash", serde_json::to_value(&self.boot_splash)?),
+            ("instanceStartup", serde_json::to_value(&self.startup)?),
+            ("quietHours", serde_json::to_value(&self.quiet_hours)?),
+            ("frameLatency", serde_json::to_value(&self.frame_latency)?),
+            ("memoryLimits", serde_json::to_value(&self.memory_limits)?),
+            ("ndiReceiver", serde_json::to_value(&self.ndi_receiver)?),
+            ("mediaArtwork", serde_json::to_value(&self.media_artwork)?),
+            ("dataPresets", serde_json::to_value(&self.data_presets)?),
+            (
+                "instanceMirror",
+                serde_json::to_value(&self.instance_mirror)?,
+            ),
+            ("dryRun", serde_json::to_value(&self.dry_run)?),
+            ("udpListener", serde_json::to_value(&self.udp_listener)?),
+        ])
+    }
+
+    /// Check that the LED layout, the device and the channel adjustments agree on the LED count
+    pub fn led_count_mismatches(&self) -> Vec<LedCountMismatch> {
+        let mut mismatches = Vec::new();
+
+        let layout = self.leds.leds.len();
+        let device = self.device.hardware_led_count();
+        let segments = self.device.segments();
+
+        if segments.is_empty() {
+            if layout != device {
+                mismatches.push(LedCountMismatch::Device { layout, device });
+            }
+        } else {
+            if segments.len() != device {
+                mismatches.push(LedCountMismatch::Segments {
+                    segments: segments.len(),
+                    device,
+                });
+            }
+
+            for (segment, config) in segments.iter().enumerate() {
+                if let Some(index) = crate::color::LedMatch::from(config.leds.as_str())
+                    .max_index()
+                    .filter(|index| *index >= layout)
+                {
+                    mismatches.push(LedCountMismatch::Segment {
+                        segment,
+                        index,
+                        layout,
+                    });
+                }
+            }
+        }
+
+        if let Some(led) = self
+            .device
+            .keep_alive()
+            .map(|keep_alive| keep_alive.led as usize)
+            .filter(|led| *led >= device)
+        {
+            mismatches.push(LedCountMismatch::KeepAlive { led, device });
+        }
+
+        if let Some(voltage_drop) = self.device.voltage_drop() {
+            for led in &voltage_drop.injection_points {
+                let led = *led as usize;
+                if led >= device {
+                    mismatches.push(LedCountMismatch::InjectionPoint { led, device });
+                }
+            }
+        }
+
+        for adjustment in &self.color.channel_adjustment {
+            if let Some(index) = crate::color::LedMatch::from(adjustment.leds.as_str())
+                .max_index()
+                .filter(|index| *index >= layout)
+            {
+                mismatches.push(LedCountMismatch::Adjustment {
+                    id: adjustment.id.clone(),
+                    index,
+                    layout,
+                });
+            }
+        }
+
+        mismatches
+    }
+
     pub fn new_dummy(id: i32) -> Self {
         Self {
             instance: Instance {
@@ -603,6 +1305,17 @@ impl InstanceConfig {
             led_config: Default::default(),
             leds: Default::default(),
             smoothing: Default::default(),
+            boot_splash: Default::default(),
+            startup: Default::default(),
+            quiet_hours: Default::default(),
+            frame_latency: Default::default(),
+            memory_limits: Default::default(),
+            ndi_receiver: Default::default(),
+            media_artwork: Default::default(),
+            data_presets: Default::default(),
+            instance_mirror: Default::default(),
+            dry_run: Default::default(),
+            udp_listener: Default::default(),
         }
     }
 }
@@ -0,0 +1,223 @@
+//! Instance templates for common setups
+
+use serde_derive::Deserialize;
+use strum_macros::{Display, EnumString};
+use validator::Validate;
+
+use super::{
+    Adalight, ClassicLedConfig, ColorOrder, Device, InstanceConfig, Pipe, PipeFormat, Smoothing,
+    ToLeds, Wled, Ws2812Spi,
+};
+
+/// Parameters of an instance template
+#[derive(Debug, Clone, PartialEq, Deserialize, Validate)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TemplateParams {
+    /// Friendly name of the instance, defaults to the name of the template
+    pub name: Option<String>,
+    /// Number of LEDs, spread around the screen in the placeholder layout
+    #[validate(range(min = 1, max = 4096))]
+    pub led_count: u32,
+    /// Device output (SPI device node, file path, hostname...), defaults to the usual one for the
+    /// device
+    pub output: Option<String>,
+}
+
+impl Default for TemplateParams {
+    fn default() -> Self {
+        Self {
+            name: None,
+            led_count: 60,
+            output: None,
+        }
+    }
+}
+
+/// Template for a new instance, with device defaults, smoothing settings and a placeholder layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, EnumString, Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum InstanceTemplate {
+    /// WS2812 strip on the SPI bus, e.g. on a Raspberry Pi
+    Ws2812Spi,
+    /// Pipe device writing JSON lines to stdout, for testing and scripting
+    Pipe,
+    /// WLED controller on the network, using its realtime UDP protocol
+    Wled,
+    /// Arduino running the Adalight sketch, on a USB serial port
+    Adalight,
+}
+
+impl InstanceTemplate {
+    /// Create the configuration of a new instance from this template
+    pub fn instantiate(self, id: i32, params: &TemplateParams) -> InstanceConfig {
+        let mut config = InstanceConfig::new_dummy(id);
+
+        config.instance.friendly_name = params
+            .name
+            .clone()
+            .unwrap_or_else(|| self.friendly_name().to_owned());
+
+        config.led_config.classic = classic_layout(params.led_count);
+        config.leds = config.led_config.classic.to_leds();
+
+        match self {
+            Self::Ws2812Spi => {
+                config.device = Device::from(Ws2812Spi {
+                    // WS2812B strips expect GRB data
+                    color_order: ColorOrder::Grb,
+                    hardware_led_count: params.led_count,
+                    invert: false,
+                    latch_time: 0,
+                    output_delay: 0,
+                    max_fps: 0,
+                    output: params
+                        .output
+                        .clone()
+                        .unwrap_or_else(|| "/dev/spidev0.0".to_owned()),
+                    rate: 3_000_000,
+                    rewrite_time: 1000,
+                    segments: Vec::new(),
+                    keep_alive: None,
+                    voltage_drop: None,
+                    lut: None,
+                    white_output: None,
+                });
+
+                config.smoothing = Smoothing {
+                    time_ms: 150,
+                    update_frequency: 50.,
+                    ..Default::default()
+                };
+            }
+            Self::Pipe => {
+                config.device = Device::from(Pipe {
+                    hardware_led_count: params.led_count,
+                    latch_time: 0,
+                    output_delay: 0,
+                    max_fps: 0,
+                    output: params.output.clone().unwrap_or_else(|| "-".to_owned()),
+                    format: PipeFormat::Json,
+                    rewrite_time: 0,
+                    segments: Vec::new(),
+                    keep_alive: None,
+                    voltage_drop: None,
+                    lut: None,
+                    white_output: None,
+                });
+            }
+            Self::Wled => {
+                config.device = Device::from(Wled {
+                    hardware_led_count: params.led_count,
+                    host: params
+                        .output
+                        .clone()
+                        .unwrap_or_else(|| "wled.local".to_owned()),
+                    port: 21324,
+                    protocol: Default::default(),
+                    timeout: 2,
+                    latch_time: 0,
+                    output_delay: 0,
+                    max_fps: 0,
+                    rewrite_time: 1000,
+                    // Follow changes made from the WLED UI
+                    led_count_refresh_time: 30_000,
+                    segments: Vec::new(),
+                    keep_alive: None,
+                    voltage_drop: None,
+                    lut: None,
+                });
+
+                config.smoothing = Smoothing {
+                    time_ms: 100,
+                    update_frequency: 40.,
+                    ..Default::default()
+                };
+            }
+            Self::Adalight => {
+                config.device = Device::from(Adalight {
+                    color_order: ColorOrder::Rgb,
+                    hardware_led_count: params.led_count,
+                    latch_time: 0,
+                    output_delay: 0,
+                    max_fps: 0,
+                    output: params
+                        .output
+                        .clone()
+                        .unwrap_or_else(|| "/dev/ttyUSB0".to_owned()),
+                    rate: 115200,
+                    rewrite_time: 1000,
+                    segments: Vec::new(),
+                    keep_alive: None,
+                    voltage_drop: None,
+                    lut: None,
+                });
+
+                config.smoothing = Smoothing {
+                    time_ms: 150,
+                    update_frequency: 25.,
+                    ..Default::default()
+                };
+            }
+        }
+
+        config
+    }
+
+    fn friendly_name(self) -> &'static str {
+        match self {
+            Self::Ws2812Spi => "WS2812 SPI",
+            Self::Pipe => "Pipe",
+            Self::Wled => "WLED",
+            Self::Adalight => "Adalight",
+        }
+    }
+}
+
+/// Spread LEDs around the edges of a 16:9 screen
+fn classic_layout(led_count: u32) -> ClassicLedConfig {
+    let horizontal = (led_count as f32 * 16. / 50.).round() as u32;
+    let vertical = led_count - 2 * horizontal;
+
+    ClassicLedConfig {
+        top: horizontal,
+        bottom: horizontal,
+        left: vertical / 2,
+        right: vertical - vertical / 2,
+        // Corners of the screen
+        ptrh: 100,
+        pblv: 100,
+        pbrh: 100,
+        pbrv: 100,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_are_consistent() {
+        for template in [
+            InstanceTemplate::Ws2812Spi,
+            InstanceTemplate::Pipe,
+            InstanceTemplate::Wled,
+            InstanceTemplate::Adalight,
+        ] {
+            for led_count in [1, 3, 60, 151] {
+                let config = template.instantiate(
+                    1,
+                    &TemplateParams {
+                        led_count,
+                        ..Default::default()
+                    },
+                );
+
+                config.validate().unwrap();
+                assert_eq!(config.leds.leds.len(), led_count as usize);
+                assert!(config.led_count_mismatches().is_empty());
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Loading configurations with keys hyperion.rs doesn't know about
+//!
+//! Settings deny unknown fields, so they are detected at the exact path they occur at. Unless
+//! loading is strict, the unknown key is then removed and deserialization is attempted again.
+
+use serde::de::DeserializeOwned;
+use serde_path_to_error::Segment;
+
+/// Maximum number of unknown keys removed from a single value
+const MAX_UNKNOWN_KEYS: usize = 256;
+
+/// Untyped configuration value, i.e. a TOML or JSON document
+pub(crate) trait ConfigValue: Clone {
+    fn get_mut(&mut self, segment: &str) -> Option<&mut Self>;
+
+    /// Remove `key` if this is a table containing it
+    fn remove(&mut self, key: &str) -> bool;
+}
+
+impl ConfigValue for toml::Value {
+    fn get_mut(&mut self, segment: &str) -> Option<&mut Self> {
+        match self {
+            toml::Value::Table(table) => table.get_mut(segment),
+            toml::Value::Array(array) => array.get_mut(segment.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self {
+            toml::Value::Table(table) => table.remove(key).is_some(),
+            _ => false,
+        }
+    }
+}
+
+impl ConfigValue for serde_json::Value {
+    fn get_mut(&mut self, segment: &str) -> Option<&mut Self> {
+        match self {
+            serde_json::Value::Object(object) => object.get_mut(segment),
+            serde_json::Value::Array(array) => array.get_mut(segment.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self {
+            serde_json::Value::Object(object) => object.remove(key).is_some(),
+            _ => false,
+        }
+    }
+}
+
+/// Deserialize a configuration value
+///
+/// Unless `strict` is set, unknown keys are removed instead of failing, and their path is
+/// appended to `unknown`.
+pub(crate) fn deserialize_value<T, V>(
+    mut value: V,
+    strict: bool,
+    unknown: &mut Vec<String>,
+) -> Result<T, serde_path_to_error::Error<V::Error>>
+where
+    T: DeserializeOwned,
+    V: ConfigValue + serde::Deserializer<'static>,
+{
+    let mut removed = 0;
+
+    loop {
+        let error = match serde_path_to_error::deserialize(value.clone()) {
+            Ok(result) => return Ok(result),
+            Err(error) => error,
+        };
+
+        if strict || removed >= MAX_UNKNOWN_KEYS {
+            return Err(error);
+        }
+
+        let path: Vec<_> = error
+            .path()
+            .iter()
+            .map(|segment| match segment {
+                Segment::Seq { index } => index.to_string(),
+                Segment::Map { key } | Segment::Enum { variant: key } => key.clone(),
+                Segment::Unknown => "?".to_owned(),
+            })
+            .collect();
+        match unknown_field(&error.inner().to_string())
+            .and_then(|key| remove_key(&mut value, &path, key))
+        {
+            Some(path) => {
+                unknown.push(path);
+                removed += 1;
+            }
+            None => return Err(error),
+        }
+    }
+}
+
+/// Name of the field of an unknown field error
+fn unknown_field(message: &str) -> Option<&str> {
+    let start = message.find("unknown field `")? + "unknown field `".len();
+    let end = start + message[start..].find('`')?;
+    Some(&message[start..end])
+}
+
+/// Remove the unknown `key` reported at `path`, returning its full path
+///
+/// Depending on the deserializer, the reported path is either the table containing the key, or
+/// the key itself.
+fn remove_key<V: ConfigValue>(value: &mut V, path: &[String], key: &str) -> Option<String> {
+    let (parent, full_path) = if path.last().map(String::as_str) == Some(key) {
+        (&path[..path.len() - 1], path.to_vec())
+    } else {
+        let mut full_path = path.to_vec();
+        full_path.push(key.to_owned());
+        (path, full_path)
+    };
+
+    let mut table = value;
+    for segment in parent {
+        table = table.get_mut(segment)?;
+    }
+
+    if table.remove(key) {
+        Some(full_path.join("."))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Inner {
+        a: u32,
+    }
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Outer {
+        inner: Vec<Inner>,
+    }
+
+    #[test]
+    fn test_deserialize_value() {
+        let value = serde_json::json!({
+            "inner": [{ "a": 1 }, { "a": 2, "b": 3, "c": 4 }],
+            "d": 5,
+        });
+
+        let mut unknown = Vec::new();
+        let outer: Outer = deserialize_value(value.clone(), false, &mut unknown).unwrap();
+        assert_eq!(outer.inner, [Inner { a: 1 }, Inner { a: 2 }]);
+        assert_eq!(unknown, ["d", "inner.1.b", "inner.1.c"]);
+
+        let error = deserialize_value::<Outer, _>(value, true, &mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("unknown field `d`"), "{}", error);
+    }
+}
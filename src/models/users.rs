@@ -45,11 +45,16 @@ pub struct User {
 }
 
 impl User {
+    /// Name of the administrator account
+    pub const ADMIN: &'static str = "Hyperion";
+    /// Password of the administrator account until it is changed
+    pub const DEFAULT_PASSWORD: &'static str = "hyperion";
+
     pub fn hyperion() -> Self {
-        let name = "Hyperion".to_owned();
+        let name = Self::ADMIN.to_owned();
         let salt = Self::generate_salt();
         let token = Self::generate_token();
-        let password = Self::hash_password("hyperion", salt.as_bytes());
+        let password = Self::hash_password(Self::DEFAULT_PASSWORD, salt.as_bytes());
         let created_at = chrono::Utc::now();
         let last_use = created_at;
 
@@ -65,6 +70,40 @@ impl User {
         }
     }
 
+    /// Create the entry of an API token
+    ///
+    /// Only the hash of `token` is stored. The `auth` table is keyed by user name, so token
+    /// entries use their id as a name.
+    pub fn api_token(token: &str, comment: String, id: String) -> Self {
+        let created_at = chrono::Utc::now();
+
+        Self {
+            name: id.clone(),
+            password: Vec::new(),
+            token: Self::hash_token(token),
+            salt: String::new(),
+            comment: Some(comment),
+            id: Some(id),
+            created_at,
+            last_use: created_at,
+        }
+    }
+
+    /// Returns true if this entry is an API token rather than a user account
+    pub fn is_api_token(&self) -> bool {
+        self.id.is_some()
+    }
+
+    /// Returns true if `password` is the password of this user
+    pub fn check_password(&self, password: &str) -> bool {
+        !self.is_api_token() && Self::hash_password(password, self.salt.as_bytes()) == self.password
+    }
+
+    pub fn set_password(&mut self, password: &str) {
+        self.salt = Self::generate_salt();
+        self.password = Self::hash_password(password, self.salt.as_bytes());
+    }
+
     pub fn generate_token() -> Vec<u8> {
         let mut hasher = sha2::Sha512::default();
         hasher.update(uuid::Uuid::new_v4().as_bytes());
@@ -81,6 +120,12 @@ impl User {
         hasher.update(salt);
         hasher.finalize().to_vec()
     }
+
+    pub fn hash_token(token: &str) -> Vec<u8> {
+        let mut hasher = sha2::Sha512::default();
+        hasher.update(token.as_bytes());
+        hasher.finalize().to_vec()
+    }
 }
 
 impl TryFrom<db_models::DbUser> for User {
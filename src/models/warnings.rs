@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use super::{Config, DeviceConfig, InstanceConfig, LedCountMismatch, ToLeds};
+
+/// Non-fatal configuration issue
+///
+/// Warnings point at settings which are valid on their own but are likely to be a mistake when
+/// combined with the rest of the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigWarning {
+    /// Instance the warning applies to, None for global settings
+    pub instance: Option<i32>,
+    /// Name of the setting the warning is about
+    pub setting: &'static str,
+    pub message: String,
+}
+
+impl ConfigWarning {
+    fn new(instance: Option<i32>, setting: &'static str, message: String) -> Self {
+        Self {
+            instance,
+            setting,
+            message,
+        }
+    }
+
+    /// Key which was ignored while loading the configuration
+    pub(crate) fn unknown_key(instance: Option<i32>, setting: &'static str, path: &str) -> Self {
+        Self::new(instance, setting, format!("unknown key `{}` ignored", path))
+    }
+}
+
+impl std::fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(instance) = self.instance {
+            write!(f, "instance {}: ", instance)?;
+        }
+
+        write!(f, "{}: {}", self.setting, self.message)
+    }
+}
+
+impl Config {
+    /// Check this configuration for likely misconfigurations
+    pub fn warnings(&self) -> Vec<ConfigWarning> {
+        let mut warnings = self.load_warnings.clone();
+
+        self.check_ports(&mut warnings);
+
+        for (&id, instance) in &self.instances {
+            instance.check(id, &mut warnings);
+
+            if let Some(cycle) = self.mirror_cycle(id) {
+                warnings.push(ConfigWarning::new(
+                    Some(id),
+                    "instanceMirror",
+                    format!(
+                        "instances mirror each other ({}), the mirror won't be started",
+                        cycle
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" -> ")
+                    ),
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Chain of mirrored instances leading back to the given one, if there is one
+    ///
+    /// Instances in such a cycle would feed each other their output forever.
+    pub fn mirror_cycle(&self, id: i32) -> Option<Vec<i32>> {
+        let mut chain = vec![id];
+        let mut current = id;
+
+        while let Some(instance) = self
+            .instances
+            .get(&current)
+            .filter(|instance| instance.instance_mirror.enable)
+        {
+            current = instance.instance_mirror.source;
+            let looped = chain.contains(&current);
+            chain.push(current);
+
+            if looped {
+                // A cycle further down the chain is reported for the instances in it
+                return (current == id).then_some(chain);
+            }
+        }
+
+        None
+    }
+
+    fn check_ports(&self, warnings: &mut Vec<ConfigWarning>) {
+        let mut ports: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        let global = &self.global;
+
+        ports
+            .entry(global.json_server.port)
+            .or_default()
+            .push("jsonServer".to_owned());
+        ports
+            .entry(global.web_config.port)
+            .or_default()
+            .push("webConfig".to_owned());
+
+        if global.web_config.is_ssl_enabled() {
+            ports
+                .entry(global.web_config.ssl_port)
+                .or_default()
+                .push("webConfig (HTTPS)".to_owned());
+        }
+
+        if global.flatbuffers_server.enable {
+            ports
+                .entry(global.flatbuffers_server.port)
+                .or_default()
+                .push("flatbufServer".to_owned());
+        }
+
+        if global.proto_server.enable {
+            ports
+                .entry(global.proto_server.port)
+                .or_default()
+                .push("protoServer".to_owned());
+        }
+
+        for (id, instance) in &self.instances {
+            if instance.instance.enabled && instance.boblight_server.enable {
+                ports
+                    .entry(instance.boblight_server.port)
+                    .or_default()
+                    .push(format!("boblightServer (instance {})", id));
+            }
+        }
+
+        for (port, servers) in ports {
+            if servers.len() > 1 {
+                warnings.push(ConfigWarning::new(
+                    None,
+                    "network",
+                    format!("port {} is used by {}", port, servers.join(", ")),
+                ));
+            }
+        }
+    }
+}
+
+impl InstanceConfig {
+    fn check(&self, id: i32, warnings: &mut Vec<ConfigWarning>) {
+        let led_count = self.leds.leds.len();
+
+        for mismatch in self.led_count_mismatches() {
+            let setting = match mismatch {
+                LedCountMismatch::Device { .. }
+                | LedCountMismatch::Segments { .. }
+                | LedCountMismatch::Segment { .. }
+                | LedCountMismatch::KeepAlive { .. }
+                | LedCountMismatch::InjectionPoint { .. } => "device",
+                LedCountMismatch::Adjustment { .. } => "color",
+            };
+
+            warnings.push(ConfigWarning::new(Some(id), setting, mismatch.to_string()));
+        }
+
+        let classic_count = self.led_config.classic.to_leds().leds.len();
+        let matrix_count =
+            (self.led_config.matrix.ledshoriz * self.led_config.matrix.ledsvert) as usize;
+        if led_count != classic_count && led_count != matrix_count {
+            warnings.push(ConfigWarning::new(
+                Some(id),
+                "leds",
+                format!(
+                    "the layout has {} LEDs, which matches neither the classic ({}) nor the matrix ({}) LED configuration",
+                    led_count, classic_count, matrix_count
+                ),
+            ));
+        }
+
+        let smoothing = &self.smoothing;
+        if smoothing.enable {
+            let update_period_ms = 1000. / smoothing.update_frequency;
+
+            if (smoothing.time_ms as f32) < update_period_ms {
+                warnings.push(ConfigWarning::new(
+                    Some(id),
+                    "smoothing",
+                    format!(
+                        "the smoothing time ({} ms) is shorter than the update period ({:.1} ms), smoothing will have no effect",
+                        smoothing.time_ms, update_period_ms
+                    ),
+                ));
+            }
+
+            let latch_time = self.device.latch_time();
+            if !latch_time.is_zero() {
+                let max_frequency = 1. / latch_time.as_secs_f32();
+
+                if smoothing.update_frequency > max_frequency {
+                    warnings.push(ConfigWarning::new(
+                        Some(id),
+                        "smoothing",
+                        format!(
+                            "the update frequency ({} Hz) is higher than the device can handle with its latch time ({:.1} Hz max), updates will be dropped",
+                            smoothing.update_frequency, max_frequency
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn led_count_mismatch() {
+        let mut config: InstanceConfig = toml::from_str("[instance]").unwrap();
+        let mut warnings = Vec::new();
+        config.check(0, &mut warnings);
+        assert_eq!(warnings, vec![]);
+
+        config.leds.leds.push(config.leds.leds[0].clone());
+        config.check(0, &mut warnings);
+        assert_eq!(
+            warnings.iter().map(|w| w.setting).collect::<Vec<_>>(),
+            vec!["device", "leds"]
+        );
+    }
+
+    #[test]
+    fn mirror_cycle() {
+        let instance = |id, source: Option<i32>| {
+            let mut config = InstanceConfig::new_dummy(id);
+            if let Some(source) = source {
+                config.instance_mirror.enable = true;
+                config.instance_mirror.source = source;
+            }
+            config
+        };
+
+        // 0 -> 1 -> 2 -> 0, and 3 mirrors the cycle without being part of it
+        let config = Config::new(
+            Default::default(),
+            [
+                instance(0, Some(1)),
+                instance(1, Some(2)),
+                instance(2, Some(0)),
+                instance(3, Some(0)),
+                instance(4, None),
+            ],
+        );
+
+        assert_eq!(config.mirror_cycle(0), Some(vec![0, 1, 2, 0]));
+        assert_eq!(config.mirror_cycle(2), Some(vec![2, 0, 1, 2]));
+        assert_eq!(config.mirror_cycle(3), None);
+        assert_eq!(config.mirror_cycle(4), None);
+        assert_eq!(
+            config
+                .warnings()
+                .iter()
+                .filter(|w| w.setting == "instanceMirror")
+                .filter_map(|w| w.instance)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+}
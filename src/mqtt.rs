@@ -0,0 +1,312 @@
+//! MQTT client, announcing instances as Home Assistant lights
+//!
+//! Each instance gets a state topic (`<baseTopic>/<id>/state`) and a command topic
+//! (`<baseTopic>/<id>/set`) using the JSON schema of Home Assistant lights. Turning a light on
+//! sets its color on the instance at the configured priority, turning it off clears it.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use bytes::Bytes;
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc};
+
+mod home_assistant;
+pub use home_assistant::*;
+
+use crate::{
+    component::ComponentName,
+    global::{
+        Event, Global, InputMessage, InputMessageData, InputSourceError, InputSourceHandle,
+        InputSourceName, InstanceEvent, InstanceEventKind, Message,
+    },
+    models,
+};
+
+#[derive(Debug, Error)]
+pub enum MqttError {
+    #[error(transparent)]
+    InputSource(#[from] InputSourceError),
+}
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Delay before reconnecting after a connection error
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Start the MQTT client
+///
+/// It connects in the background, reconnecting on errors, until hyperion stops. It has to be
+/// started before the instances so it sees them start.
+pub async fn start(config: models::Mqtt, global: Global) -> Result<(), MqttError> {
+    let source = global
+        .register_input_source(InputSourceName::Mqtt, Some(config.priority))
+        .await?;
+
+    let node_id = if config.node_id.is_empty() {
+        hostname::get()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "hyperion".to_owned())
+    } else {
+        config.node_id.clone()
+    };
+    let node_id = sanitize_node_id(&node_id);
+
+    let status_topic = format!("{}/status", config.base_topic);
+    let mut options = MqttOptions::new(
+        format!("hyperion-rs-{}", node_id),
+        config.host.clone(),
+        config.port,
+    );
+    options
+        .set_keep_alive(Duration::from_secs(30))
+        .set_last_will(LastWill::new(
+            &status_topic,
+            OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+    if !config.username.is_empty() {
+        options.set_credentials(config.username.clone(), config.password.clone());
+    }
+
+    let (client, event_loop) = AsyncClient::new(options, 64);
+    let (tx, rx) = mpsc::channel(64);
+    let events = global.subscribe_events().await;
+
+    tokio::spawn(poll(event_loop, tx, config.host.clone()));
+    tokio::spawn(
+        MqttClient {
+            config,
+            node_id,
+            status_topic,
+            global,
+            client,
+            source,
+            lights: Default::default(),
+        }
+        .run(rx, events),
+    );
+
+    Ok(())
+}
+
+enum Incoming {
+    Connected,
+    Publish { topic: String, payload: Bytes },
+}
+
+/// Drive the connection to the broker, forwarding the incoming packets the client needs
+///
+/// Requests of the client are only sent while this runs, so it has its own task.
+async fn poll(mut event_loop: EventLoop, tx: mpsc::Sender<Incoming>, host: String) {
+    let mut connected = false;
+
+    while !tx.is_closed() {
+        let incoming = match event_loop.poll().await {
+            Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                info!(host = %host, "connected to MQTT broker");
+                connected = true;
+                Incoming::Connected
+            }
+            Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => Incoming::Publish {
+                topic: publish.topic,
+                payload: publish.payload,
+            },
+            Ok(_) => continue,
+            Err(error) => {
+                // Only log the first error, the broker may be down for a while
+                if connected {
+                    warn!(error = %error, "MQTT connection error");
+                } else {
+                    debug!(error = %error, "MQTT connection error");
+                }
+
+                connected = false;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        if tx.send(incoming).await.is_err() {
+            break;
+        }
+    }
+}
+
+struct MqttClient {
+    config: models::Mqtt,
+    node_id: String,
+    status_topic: String,
+    global: Global,
+    client: AsyncClient,
+    source: InputSourceHandle<InputMessage>,
+    lights: BTreeMap<i32, LightState>,
+}
+
+impl MqttClient {
+    async fn run(
+        mut self,
+        mut incoming: mpsc::Receiver<Incoming>,
+        mut events: broadcast::Receiver<Event>,
+    ) {
+        loop {
+            tokio::select! {
+                incoming = incoming.recv() => match incoming {
+                    Some(Incoming::Connected) => self.on_connected().await,
+                    Some(Incoming::Publish { topic, payload }) => {
+                        self.on_command(&topic, &payload).await
+                    }
+                    None => break,
+                },
+                event = events.recv() => match event {
+                    Ok(Event::Instance(InstanceEvent { id, kind: InstanceEventKind::Start })) => {
+                        self.announce(id).await;
+                    }
+                    Ok(Event::Instance(InstanceEvent { id, kind: InstanceEventKind::Stop })) => {
+                        // Discovery messages are kept, the instance may only be restarting
+                        self.publish(self.topics(id).availability, OFFLINE.into()).await;
+                    }
+                    Ok(Event::Stop) | Err(broadcast::error::RecvError::Closed) => break,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                },
+            }
+        }
+
+        self.publish(self.status_topic.clone(), OFFLINE.into())
+            .await;
+        // ok: the connection may already be closed
+        self.client.disconnect().await.ok();
+    }
+
+    fn topics(&self, id: i32) -> LightTopics {
+        let base = &self.config.base_topic;
+
+        LightTopics {
+            state: format!("{}/{}/state", base, id),
+            command: format!("{}/{}/set", base, id),
+            availability: format!("{}/{}/availability", base, id),
+        }
+    }
+
+    async fn on_connected(&mut self) {
+        self.publish(self.status_topic.clone(), ONLINE.into()).await;
+
+        let commands = format!("{}/+/set", self.config.base_topic);
+        if let Err(error) = self.client.subscribe(&commands, QoS::AtLeastOnce).await {
+            warn!(topic = %commands, error = %error, "cannot subscribe to MQTT topic");
+        }
+
+        for (id, _) in self.global.instances().await {
+            self.announce(id).await;
+        }
+    }
+
+    /// Publish the discovery message, availability and state of an instance
+    async fn announce(&mut self, id: i32) {
+        let instance = match self.global.get_instance(id).await {
+            Some(instance) => instance,
+            None => return,
+        };
+
+        let topics = self.topics(id);
+
+        if self.config.home_assistant {
+            let friendly_name = instance
+                .config()
+                .await
+                .map(|config| config.instance.friendly_name.clone())
+                .unwrap_or_default();
+
+            let topic = format!(
+                "{}/light/{}/{}/config",
+                self.config.discovery_prefix, self.node_id, id
+            );
+            let payload = discovery_payload(
+                &self.node_id,
+                id,
+                &friendly_name,
+                &topics,
+                &self.status_topic,
+            );
+
+            self.publish(topic, payload.to_string().into()).await;
+        }
+
+        self.publish(topics.availability, ONLINE.into()).await;
+
+        // Restarted instances lost the color they displayed
+        self.update_light(id).await;
+    }
+
+    async fn on_command(&mut self, topic: &str, payload: &[u8]) {
+        let id = match topic
+            .strip_prefix(self.config.base_topic.as_str())
+            .and_then(|topic| topic.strip_prefix('/'))
+            .and_then(|topic| topic.strip_suffix("/set"))
+            .and_then(|id| id.parse().ok())
+        {
+            Some(id) => id,
+            None => return,
+        };
+
+        let command: LightCommand = match serde_json::from_slice(payload) {
+            Ok(command) => command,
+            Err(error) => {
+                warn!(topic = %topic, error = %error, "invalid MQTT light command");
+                return;
+            }
+        };
+
+        if self.global.get_instance(id).await.is_none() {
+            warn!(topic = %topic, "MQTT light command for an unknown instance");
+            return;
+        }
+
+        self.lights.entry(id).or_default().apply(&command);
+        self.update_light(id).await;
+    }
+
+    /// Send the color of a light to its instance, and publish its state
+    async fn update_light(&mut self, id: i32) {
+        let state = self.lights.get(&id).copied().unwrap_or_default();
+
+        if let Some(instance) = self.global.get_instance(id).await {
+            let priority = self.config.priority;
+            let data = if state.on {
+                InputMessageData::SolidColor {
+                    priority,
+                    duration: None,
+                    color: state.output(),
+                }
+            } else {
+                InputMessageData::Clear { priority }
+            };
+
+            // ok: the instance may have stopped, it is updated again when it starts
+            instance
+                .send(InputMessage::new(
+                    self.source.id(),
+                    ComponentName::Color,
+                    data,
+                ))
+                .await
+                .ok();
+        }
+
+        self.publish(self.topics(id).state, state.payload().to_string().into())
+            .await;
+    }
+
+    /// Publish a retained message
+    async fn publish(&self, topic: String, payload: Vec<u8>) {
+        if let Err(error) = self
+            .client
+            .publish(&topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            warn!(topic = %topic, error = %error, "cannot publish MQTT message");
+        }
+    }
+}
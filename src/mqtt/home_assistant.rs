@@ -0,0 +1,188 @@
+//! Home Assistant MQTT light, with the JSON schema
+//!
+//! See <https://www.home-assistant.io/integrations/light.mqtt/#json-schema>.
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::models::Color;
+
+/// State of the light of an instance, as controlled by Home Assistant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightState {
+    pub on: bool,
+    pub brightness: u8,
+    pub color: Color,
+}
+
+impl Default for LightState {
+    fn default() -> Self {
+        Self {
+            on: false,
+            brightness: 255,
+            color: Color::new(255, 255, 255),
+        }
+    }
+}
+
+impl LightState {
+    /// Color to display, scaled by the brightness
+    pub fn output(&self) -> Color {
+        let scale = |c: u8| (c as u16 * self.brightness as u16 / 255) as u8;
+        Color::new(
+            scale(self.color.red),
+            scale(self.color.green),
+            scale(self.color.blue),
+        )
+    }
+
+    /// Apply a command received on the command topic
+    ///
+    /// Changing the color or brightness turns the light on, as Home Assistant expects.
+    pub fn apply(&mut self, command: &LightCommand) {
+        if let Some(color) = command.color {
+            self.color = Color::new(color.r, color.g, color.b);
+        }
+
+        if let Some(brightness) = command.brightness {
+            self.brightness = brightness;
+        }
+
+        self.on = match command.state {
+            Some(OnOff::On) => true,
+            Some(OnOff::Off) => false,
+            None => self.on || command.color.is_some() || command.brightness.is_some(),
+        };
+    }
+
+    /// Payload of the state topic
+    pub fn payload(&self) -> serde_json::Value {
+        json!({
+            "state": if self.on { OnOff::On } else { OnOff::Off },
+            "brightness": self.brightness,
+            "color_mode": "rgb",
+            "color": RgbColor {
+                r: self.color.red,
+                g: self.color.green,
+                b: self.color.blue,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OnOff {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RgbColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Payload of the command topic
+///
+/// Other fields (transition, effect...) are ignored, they aren't announced in the discovery
+/// message.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct LightCommand {
+    pub state: Option<OnOff>,
+    pub brightness: Option<u8>,
+    pub color: Option<RgbColor>,
+}
+
+/// Topics of the light of an instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LightTopics {
+    pub state: String,
+    pub command: String,
+    pub availability: String,
+}
+
+/// Discovery message announcing an instance as a light
+pub fn discovery_payload(
+    node_id: &str,
+    id: i32,
+    friendly_name: &str,
+    topics: &LightTopics,
+    status_topic: &str,
+) -> serde_json::Value {
+    let unique_id = format!("{}_{}", node_id, id);
+    let name = if friendly_name.is_empty() {
+        format!("Instance {}", id)
+    } else {
+        friendly_name.to_owned()
+    };
+
+    json!({
+        // Entities without a name are named after their device
+        "name": null,
+        "unique_id": unique_id,
+        "schema": "json",
+        "state_topic": topics.state,
+        "command_topic": topics.command,
+        "availability": [
+            { "topic": status_topic },
+            { "topic": topics.availability },
+        ],
+        "availability_mode": "all",
+        "brightness": true,
+        "supported_color_modes": ["rgb"],
+        "device": {
+            "identifiers": [unique_id],
+            "name": name,
+            "manufacturer": "hyperion.rs",
+            "model": "Instance",
+            "sw_version": crate::global::version(),
+        },
+    })
+}
+
+/// Node id of discovery topics, which only allow alphanumeric characters, `-` and `_`
+pub fn sanitize_node_id(node_id: &str) -> String {
+    node_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_command() {
+        let mut state = LightState::default();
+
+        let command: LightCommand = serde_json::from_str(
+            r#"{"state":"ON","brightness":128,"color":{"r":255,"g":0,"b":10},"transition":2}"#,
+        )
+        .unwrap();
+        state.apply(&command);
+        assert!(state.on);
+        assert_eq!(state.output(), Color::new(128, 0, 5));
+
+        state.apply(&serde_json::from_str(r#"{"state":"OFF"}"#).unwrap());
+        assert!(!state.on);
+        assert_eq!(state.color, Color::new(255, 0, 10));
+
+        state.apply(&serde_json::from_str(r#"{"brightness":255}"#).unwrap());
+        assert!(state.on);
+        assert_eq!(state.payload()["state"], "ON");
+    }
+
+    #[test]
+    fn test_sanitize_node_id() {
+        assert_eq!(sanitize_node_id("living-room.local"), "living-room_local");
+    }
+}
@@ -5,3 +5,6 @@ pub use self::base64::*;
 
 mod color;
 pub use self::color::*;
+
+mod list;
+pub use self::list::*;
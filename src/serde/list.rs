@@ -0,0 +1,59 @@
+//! Deserialization of lists written as comma-separated strings
+
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+/// Serde visitor for lists given either as a sequence or as a comma-separated string
+struct CommaListVisitor<T>(PhantomData<T>);
+
+impl<'de, T> serde::de::Visitor<'de> for CommaListVisitor<T>
+where
+    T: FromStr + serde::Deserialize<'de>,
+    T::Err: fmt::Display,
+{
+    type Value = Vec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list or a comma-separated string")
+    }
+
+    fn visit_str<E>(self, string: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        string
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.parse()
+                    .map_err(|err| E::custom(format!("invalid item `{}`: {}", item, err)))
+            })
+            .collect()
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+}
+
+/// Decode a list written either as a sequence or as a comma-separated string (e.g. `"1, 2"`)
+///
+/// # Parameters
+///
+/// `deserializer`: Serde deserializer
+pub fn from_comma_list<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr + serde::Deserialize<'de>,
+    T::Err: fmt::Display,
+{
+    deserializer.deserialize_any(CommaListVisitor(PhantomData))
+}
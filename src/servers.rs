@@ -1,19 +1,296 @@
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
 
+use futures::{Stream, StreamExt};
+use parse_display::Display;
+use thiserror::Error;
 use tokio::{
     net::{TcpListener, TcpStream},
-    task::JoinHandle,
+    task::{JoinHandle, JoinSet},
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::{global::Global, models::ServerConfig};
+use crate::{
+    global::{Global, Paths},
+    instance::InstanceHandle,
+    models::{Config, ServerConfig},
+};
 
 pub mod boblight;
 pub mod flat;
 pub mod json;
+pub mod mdns;
 pub mod proto;
+pub mod ssdp;
+pub mod udp_listener;
+
+mod port_owner;
+pub use port_owner::port_owner;
+
+/// Time given to connected clients to finish their current request and clean up when the server
+/// shuts down, before they are aborted
+const CLIENT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Server state passed to client handlers
+#[derive(Debug, Clone)]
+pub struct ClientContext {
+    shutdown: CancellationToken,
+    timeout: Option<Duration>,
+}
+
+impl ClientContext {
+    /// Wait for the next request from a client
+    ///
+    /// # Returns
+    ///
+    /// `None` if the client disconnected, didn't send anything for longer than the server
+    /// timeout, or if the server is shutting down. This is only waited for between requests, so
+    /// handlers always get to send their response and clear their inputs before closing the
+    /// connection.
+    pub async fn next_request<S: Stream + Unpin>(&self, stream: &mut S) -> Option<S::Item> {
+        let request = async {
+            match self.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, stream.next()).await {
+                    Ok(request) => request,
+                    Err(_) => {
+                        info!(timeout = ?timeout, "client idle for too long, disconnecting");
+                        None
+                    }
+                },
+                None => stream.next().await,
+            }
+        };
+
+        tokio::select! {
+            request = request => request,
+            _ = self.shutdown.cancelled() => None,
+        }
+    }
+}
+
+/// Identifier of a server started by the daemon
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ServerId {
+    #[display("JSON")]
+    Json,
+    #[display("Webconfig")]
+    Web,
+    #[display("Flatbuffers")]
+    Flatbuffers,
+    #[display("Protobuf")]
+    Protobuf,
+    #[display("Boblight(instance {instance})")]
+    Boblight { instance: i32 },
+    #[display("UDP listener(instance {instance})")]
+    UdpListener { instance: i32 },
+}
+
+impl ServerId {
+    /// Servers shared by all instances
+    pub const GLOBAL: [ServerId; 4] = [
+        ServerId::Json,
+        ServerId::Web,
+        ServerId::Flatbuffers,
+        ServerId::Protobuf,
+    ];
+
+    /// Check if this server is required to control the daemon, and always runs
+    pub fn is_enabled_always(&self) -> bool {
+        matches!(self, ServerId::Json | ServerId::Web)
+    }
+
+    /// Check if this server should run according to the configuration
+    pub fn is_enabled(&self, config: &Config) -> bool {
+        match self {
+            ServerId::Json | ServerId::Web => true,
+            ServerId::Flatbuffers => config.global.flatbuffers_server.enable,
+            ServerId::Protobuf => config.global.proto_server.enable,
+            ServerId::Boblight { instance } => config
+                .instances
+                .get(instance)
+                .is_some_and(|instance| instance.boblight_server.enable),
+            ServerId::UdpListener { instance } => config
+                .instances
+                .get(instance)
+                .is_some_and(|instance| instance.udp_listener.enable),
+        }
+    }
+
+    /// Port this server listens on according to the configuration
+    pub fn port(&self, config: &Config) -> Option<u16> {
+        match self {
+            ServerId::Json => Some(config.global.json_server.port),
+            ServerId::Web => Some(config.global.web_config.port),
+            ServerId::Flatbuffers => Some(config.global.flatbuffers_server.port),
+            ServerId::Protobuf => Some(config.global.proto_server.port),
+            ServerId::Boblight { instance } => config
+                .instances
+                .get(instance)
+                .map(|instance| instance.boblight_server.port),
+            ServerId::UdpListener { instance } => config
+                .instances
+                .get(instance)
+                .map(|instance| instance.udp_listener.port),
+        }
+    }
+
+    /// Bind this server using its current configuration
+    ///
+    /// # Parameters
+    ///
+    /// * `instance`: handle to the instance receiving the Boblight server or UDP listener inputs
+    pub(crate) async fn bind(
+        self,
+        config: &Config,
+        paths: &Paths,
+        global: Global,
+        instance: Option<InstanceHandle>,
+    ) -> Result<ServerHandle, ServerError> {
+        let port = self.port(config).ok_or(ServerError::NoInstance(self))?;
+
+        let result = match self {
+            ServerId::Json => {
+                bind(
+                    "JSON",
+                    config.global.json_server,
+                    global,
+                    json::handle_client,
+                )
+                .await
+            }
+            ServerId::Web => crate::web::bind(global, &config.global.web_config, paths)
+                .await
+                .map(|server| ServerHandle::spawn(port, server)),
+            ServerId::Flatbuffers => {
+                bind(
+                    "Flatbuffers",
+                    config.global.flatbuffers_server.clone(),
+                    global,
+                    flat::handle_client,
+                )
+                .await
+            }
+            ServerId::Protobuf => {
+                bind(
+                    "Protobuf",
+                    config.global.proto_server.clone(),
+                    global,
+                    proto::handle_client,
+                )
+                .await
+            }
+            ServerId::Boblight { instance: id } => {
+                let config = &config.instances[&id];
+                let handle = instance.ok_or(ServerError::NoInstance(self))?;
+                let priority = config.boblight_server.priority;
+
+                bind(
+                    "Boblight",
+                    config.boblight_server.clone(),
+                    global,
+                    move |tcp, global, context| {
+                        boblight::handle_client(tcp, priority, handle.clone(), global, context)
+                    },
+                )
+                .await
+            }
+            ServerId::UdpListener { instance: id } => {
+                let handle = instance.ok_or(ServerError::NoInstance(self))?;
+
+                udp_listener::bind(config.instances[&id].udp_listener.clone(), handle, global).await
+            }
+        };
+
+        result.map_err(|error| ServerError::bind(port, error))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("port {port} is already in use by {owner}")]
+    AddrInUse { port: u16, owner: String },
+    #[error("cannot listen on port {port}: {source}")]
+    Bind {
+        port: u16,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{0} server has no running instance")]
+    NoInstance(ServerId),
+}
+
+/// Server which could not be started, and is retried in the background
+#[derive(Debug, Clone)]
+pub struct ServerFailure {
+    /// Port the server should listen on
+    pub port: Option<u16>,
+    /// Error of the last attempt
+    pub error: String,
+    pub attempts: u32,
+}
+
+impl ServerError {
+    fn bind(port: u16, error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::AddrInUse {
+            Self::AddrInUse {
+                port,
+                owner: port_owner(port).unwrap_or_else(|| "another program".to_owned()),
+            }
+        } else {
+            Self::Bind {
+                port,
+                source: error,
+            }
+        }
+    }
+}
 
 pub struct ServerHandle {
-    join_handle: JoinHandle<()>,
+    port: u16,
+    shutdown: CancellationToken,
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// Port this server is listening on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Run a server which handles its own connections, until it is shut down
+    fn spawn(port: u16, server: impl futures::Future<Output = ()> + Send + 'static) -> Self {
+        let shutdown = CancellationToken::new();
+        let join_handle = tokio::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                tokio::select! {
+                    _ = server => {}
+                    _ = shutdown.cancelled() => {}
+                }
+            }
+        });
+
+        Self {
+            port,
+            shutdown,
+            join_handle: Some(join_handle),
+        }
+    }
+
+    /// Stop accepting connections, and wait for connected clients to stop
+    ///
+    /// Clients are notified through the [ClientContext] passed to their handler: they should
+    /// finish processing the current request, clear their inputs and close their socket.
+    pub async fn shutdown(mut self) {
+        self.shutdown.cancel();
+
+        if let Some(join_handle) = self.join_handle.take() {
+            join_handle.await.ok();
+        }
+    }
 }
 
 pub async fn bind<T, E, F, H>(
@@ -26,54 +303,100 @@ where
     T: ServerConfig + Send + 'static,
     F: futures::Future<Output = Result<(), E>> + Send + 'static,
     E: From<std::io::Error> + std::fmt::Display + Send + 'static,
-    H: Fn((TcpStream, SocketAddr), Global) -> F + Send + 'static,
+    H: Fn((TcpStream, SocketAddr), Global, ClientContext) -> F + Send + 'static,
 {
     // Compute binding address
     let address = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), options.port());
 
     // Setup listener
     let listener = TcpListener::bind(&address).await?;
+    let port = listener.local_addr()?.port();
 
     // Notify we are listening
     info!(address = %address, "{} server listening", name);
 
+    let shutdown = CancellationToken::new();
+    let context = ClientContext {
+        shutdown: shutdown.clone(),
+        timeout: options.timeout(),
+    };
+
     // Spawn accepting loop
-    let join_handle = tokio::spawn(async move {
-        let result: Result<(), _> = loop {
-            match listener.accept().await {
-                Ok(incoming) => {
-                    tokio::spawn({
-                        let peer_addr = incoming.1;
-                        let ft = handle_client(incoming, global.clone());
-
-                        async move {
-                            let result = ft.await;
-
-                            match result {
-                                Ok(_) => {
-                                    info!(peer_addr = %peer_addr, "client disconnected");
-                                }
-                                Err(error) => {
-                                    error!(peer_addr = %peer_addr, error = %error, "client error");
+    let join_handle = tokio::spawn({
+        let shutdown = shutdown.clone();
+
+        async move {
+            let mut clients = JoinSet::new();
+
+            let result: Result<(), _> = loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break Ok(()),
+                    incoming = listener.accept() => match incoming {
+                        Ok(incoming) => {
+                            let peer_addr = incoming.1;
+                            let ft = handle_client(incoming, global.clone(), context.clone());
+
+                            clients.spawn(async move {
+                                let result = ft.await;
+
+                                match result {
+                                    Ok(_) => {
+                                        info!(peer_addr = %peer_addr, "client disconnected");
+                                    }
+                                    Err(error) => {
+                                        error!(peer_addr = %peer_addr, error = %error, "client error");
+                                    }
                                 }
-                            }
+                            });
                         }
-                    });
+                        Err(error) => break Err(error),
+                    },
+                    // Reap finished clients
+                    Some(_) = clients.join_next(), if !clients.is_empty() => {}
+                }
+            };
+
+            // Stop listening
+            drop(listener);
+
+            if let Err(error) = result {
+                error!(error = %error, "{} server terminated", name);
+
+                // Keep serving connected clients until the shutdown is requested
+                while !clients.is_empty() {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = clients.join_next() => {}
+                    }
                 }
-                Err(error) => break Err(error),
             }
-        };
 
-        if let Err(error) = result {
-            error!(error = %error, "{} server terminated", name);
+            if tokio::time::timeout(CLIENT_SHUTDOWN_TIMEOUT, async {
+                while clients.join_next().await.is_some() {}
+            })
+            .await
+            .is_err()
+            {
+                warn!(
+                    remaining = %clients.len(),
+                    "{} server clients did not stop in time, aborting them", name
+                );
+
+                clients.shutdown().await;
+            }
         }
     });
 
-    Ok(ServerHandle { join_handle })
+    Ok(ServerHandle {
+        port,
+        shutdown,
+        join_handle: Some(join_handle),
+    })
 }
 
 impl Drop for ServerHandle {
     fn drop(&mut self) {
-        self.join_handle.abort();
+        // The accept loop stops the clients in the background
+        self.shutdown.cancel();
     }
 }
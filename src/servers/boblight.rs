@@ -9,8 +9,9 @@ use tokio_util::codec::Framed;
 
 use crate::{
     api::boblight::{self, BoblightApiError},
-    global::{Global, InputSourceName},
+    global::{ClientProtocol, Global, InputSourceName},
     instance::InstanceHandle,
+    servers::ClientContext,
 };
 
 /// Boblight protocol codec definition
@@ -27,45 +28,64 @@ pub enum BoblightServerError {
     Api(#[from] BoblightApiError),
 }
 
-#[instrument(skip(socket, led_count, instance, global))]
+#[instrument(skip(socket, priority, instance, global, context))]
 pub async fn handle_client(
     (socket, peer_addr): (TcpStream, SocketAddr),
-    led_count: usize,
+    priority: i32,
     instance: InstanceHandle,
     global: Global,
+    context: ClientContext,
 ) -> Result<(), BoblightServerError> {
     debug!("accepted new connection");
 
     let framed = Framed::new(socket, BoblightCodec::new());
     let (mut writer, mut reader) = framed.split();
 
+    let client = global
+        .register_client(ClientProtocol::Boblight, peer_addr)
+        .await;
+
     let source_handle = global
-        .register_input_source(InputSourceName::Boblight { peer_addr }, None)
+        .register_input_source(InputSourceName::Boblight { peer_addr }, Some(priority))
         .await
         .unwrap();
 
-    let mut connection = boblight::ClientConnection::new(source_handle, led_count, instance);
+    // Read the LEDs on each connection, they may have changed since the server started
+    let leds = instance
+        .config()
+        .await
+        .map_err(BoblightApiError::from)?
+        .leds
+        .leds
+        .clone();
+    let mut connection = boblight::ClientConnection::new(source_handle, &leds, priority, instance);
 
-    while let Some(request) = reader.next().await {
+    while let Some(request) = context.next_request(&mut reader).await {
         trace!(request = ?request, "processing");
 
         match request {
-            Ok(request) => match connection.handle_request(request).await {
-                Ok(response) => {
-                    if let Some(response) = response {
-                        writer.send(response).await?;
-                        writer.flush().await?;
+            Ok(request) => {
+                client.negotiated(request.feature());
+
+                match connection.handle_request(request).await {
+                    Ok(response) => {
+                        if let Some(response) = response {
+                            writer.send(response).await?;
+                            writer.flush().await?;
+                        }
+                    }
+                    Err(error) => {
+                        warn!(error = %error, "boblight error");
                     }
                 }
-                Err(error) => {
-                    warn!(error = %error, "boblight error");
-                }
-            },
+            }
             Err(error) => {
                 warn!(error = %error, "boblight error");
             }
         }
     }
 
+    connection.clear().await;
+    writer.close().await?;
     Ok(())
 }
@@ -29,6 +29,12 @@ impl BoblightCodec {
     }
 }
 
+impl Default for BoblightCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Decoder for BoblightCodec {
     type Item = message::BoblightRequest;
     type Error = BoblightCodecError;
@@ -7,8 +7,15 @@ use thiserror::Error;
 use tokio::net::TcpStream;
 
 use crate::{
-    api::flat::{self, message, FlatApiError},
-    global::{Global, InputMessage, InputSourceHandle, PriorityGuard},
+    api::{
+        flat::{self, message, FlatApiError},
+        types::PriorityCompat,
+    },
+    global::{
+        AccessLevel, AuthError, ClientHandle, ClientProtocol, Global, InputMessage,
+        InputSourceHandle, PriorityGuard,
+    },
+    servers::ClientContext,
 };
 
 #[derive(Debug, Error)]
@@ -31,6 +38,14 @@ fn register_response(builder: &mut flatbuffers::FlatBufferBuilder, priority: i32
     bytes::Bytes::copy_from_slice(builder.finished_data())
 }
 
+/// Reply to a successful command from an unregistered client
+fn success_response(builder: &mut flatbuffers::FlatBufferBuilder) -> bytes::Bytes {
+    let reply = message::ReplyBuilder::new(builder).finish();
+
+    builder.finish(reply, None);
+    bytes::Bytes::copy_from_slice(builder.finished_data())
+}
+
 fn error_response(
     builder: &mut flatbuffers::FlatBufferBuilder,
     error: impl std::fmt::Display,
@@ -46,24 +61,64 @@ fn error_response(
     bytes::Bytes::copy_from_slice(builder.finished_data())
 }
 
+/// Record the protocol features used by a request
+fn negotiate(client: &ClientHandle, request: &message::Request, registered: bool) {
+    if let Some(register) = request.command_as_register() {
+        client.set_origin(register.origin());
+        client.negotiated("register");
+        client.negotiated(&format!("priority:{}", register.priority()));
+        return;
+    }
+
+    if !registered {
+        client.negotiated("unregistered");
+    } else if let Some(image) = request.command_as_image() {
+        if image.region_width() > 0 && image.region_height() > 0 {
+            client.negotiated("image-region");
+        } else {
+            client.negotiated("image");
+        }
+    } else if request.command_as_color().is_some() {
+        client.negotiated("color");
+    } else if let Some(clear) = request.command_as_clear() {
+        client.negotiated(if clear.priority() < 0 {
+            "clear-all"
+        } else {
+            "clear"
+        });
+    }
+}
+
 async fn handle_request(
     peer_addr: SocketAddr,
     request_bytes: bytes::BytesMut,
     source: &mut Option<InputSourceHandle<InputMessage>>,
     global: &Global,
     priority_guard: &mut Option<PriorityGuard>,
+    priority_compat: &mut PriorityCompat,
+    client: &ClientHandle,
 ) -> Result<(), FlatServerError> {
     let request = message::root_as_request(request_bytes.as_ref())?;
 
     trace!(request = ?request.command_type(), "processing");
-
-    Ok(flat::handle_request(peer_addr, request, source, global, priority_guard).await?)
+    negotiate(client, &request, source.is_some());
+
+    Ok(flat::handle_request(
+        peer_addr,
+        request,
+        source,
+        global,
+        priority_guard,
+        priority_compat,
+    )
+    .await?)
 }
 
-#[instrument(skip(socket, global))]
+#[instrument(skip(socket, global, context))]
 pub async fn handle_client(
     (socket, peer_addr): (TcpStream, SocketAddr),
     global: Global,
+    context: ClientContext,
 ) -> Result<(), FlatServerError> {
     debug!("accepted new connection");
 
@@ -73,48 +128,88 @@ pub async fn handle_client(
 
     let (mut writer, mut reader) = framed.split();
 
+    // Flatbuffers clients can't log in, only accept them if they don't need to
+    let (access, legacy_priorities) = global
+        .read_config(|config| {
+            (
+                AccessLevel::for_peer(&config.global.network, Some(peer_addr.ip())),
+                config.global.flatbuffers_server.legacy_priorities,
+            )
+        })
+        .await;
+    if access < AccessLevel::Api {
+        warn!("rejecting unauthorized client");
+
+        let mut builder = flatbuffers::FlatBufferBuilder::new();
+        writer
+            .send(error_response(&mut builder, AuthError::NoAuthorization))
+            .await?;
+        writer.close().await?;
+        return Ok(());
+    }
+
+    let client = global
+        .register_client(ClientProtocol::Flatbuffers, peer_addr)
+        .await;
     let mut source = None;
     let mut priority_guard = None;
+    let mut priority_compat = PriorityCompat::new(legacy_priorities);
     let mut builder = flatbuffers::FlatBufferBuilder::new();
 
-    while let Some(request_bytes) = reader.next().await {
-        let request_bytes = match request_bytes {
-            Ok(rb) => rb,
-            Err(error) => {
-                error!(error = %error, "error reading frame");
-                continue;
-            }
-        };
-
-        builder.reset();
-
-        let reply = match handle_request(
-            peer_addr,
-            request_bytes,
-            &mut source,
-            &global,
-            &mut priority_guard,
-        )
-        .await
-        {
-            Ok(()) => {
-                if let Some(source) = source.as_ref() {
-                    register_response(&mut builder, source.priority().unwrap())
-                } else {
-                    error_response(&mut builder, "unregistered source")
+    let result = async {
+        while let Some(request_bytes) = context.next_request(&mut reader).await {
+            let request_bytes = match request_bytes {
+                Ok(rb) => rb,
+                Err(error) => {
+                    error!(error = %error, "error reading frame");
+                    continue;
+                }
+            };
+
+            builder.reset();
+
+            let reply = match handle_request(
+                peer_addr,
+                request_bytes,
+                &mut source,
+                &global,
+                &mut priority_guard,
+                &mut priority_compat,
+                &client,
+            )
+            .await
+            {
+                Ok(()) => {
+                    if let Some(source) = source.as_ref() {
+                        register_response(&mut builder, source.priority().unwrap())
+                    } else {
+                        // The client cleared its own priority
+                        success_response(&mut builder)
+                    }
                 }
-            }
-            Err(error) => {
-                error!(error = %error, "error processing request");
+                Err(error) => {
+                    error!(error = %error, "error processing request");
 
-                error_response(&mut builder, error)
-            }
-        };
+                    error_response(&mut builder, error)
+                }
+            };
+
+            trace!(response = ?reply, "sending");
+            writer.send(reply).await?;
+            writer.flush().await?;
+        }
+
+        Ok::<_, FlatServerError>(())
+    }
+    .await;
 
-        trace!(response = ?reply, "sending");
-        writer.send(reply).await?;
-        writer.flush().await?;
+    // Release the registered priority whether the client disconnected, timed out or failed
+    if let Some(priority_guard) = priority_guard.as_mut() {
+        debug!("clearing registered priority");
+        priority_guard.clear().await;
     }
 
+    result?;
+    writer.close().await?;
     Ok(())
 }
@@ -9,7 +9,8 @@ use tokio_util::codec::Framed;
 
 use crate::{
     api::json::{self, JsonApiError},
-    global::{Global, InputSourceName},
+    global::{AccessLevel, ClientProtocol, Global, InputSourceName},
+    servers::ClientContext,
 };
 
 /// JSON protocol codec definition
@@ -26,25 +27,54 @@ pub enum JsonServerError {
     Api(#[from] JsonApiError),
 }
 
-#[instrument(skip(socket, global))]
+#[instrument(skip(socket, global, context))]
 pub async fn handle_client(
     (socket, peer_addr): (TcpStream, SocketAddr),
     global: Global,
+    context: ClientContext,
 ) -> Result<(), JsonServerError> {
     debug!("accepted new connection");
 
-    let framed = Framed::new(socket, JsonCodec::new());
+    let framed: Framed<_, JsonCodec> = Framed::new(socket, JsonCodec::new());
     let (mut writer, mut reader) = framed.split();
 
+    let (access, legacy_priorities) = global
+        .read_config(|config| {
+            (
+                AccessLevel::for_peer(&config.global.network, Some(peer_addr.ip())),
+                config.global.json_server.legacy_priorities,
+            )
+        })
+        .await;
+
     // unwrap: cannot fail because the priority is None
     let mut client_connection = json::ClientConnection::new(
         global
             .register_input_source(InputSourceName::Json { peer_addr }, None)
             .await
             .unwrap(),
-    );
+        access,
+    )
+    .with_client(
+        global
+            .register_client(ClientProtocol::Json, peer_addr)
+            .await,
+    )
+    .with_legacy_priorities(legacy_priorities);
+
+    loop {
+        let request = tokio::select! {
+            request = context.next_request(&mut reader) => match request {
+                Some(request) => request,
+                None => break,
+            },
+            update = client_connection.next_update(&global) => {
+                writer.send(update).await?;
+                writer.flush().await?;
+                continue;
+            },
+        };
 
-    while let Some(request) = reader.next().await {
         trace!(request = ?request, "processing request");
 
         let mut tan = None;
@@ -72,5 +102,6 @@ pub async fn handle_client(
         writer.flush().await?;
     }
 
+    writer.close().await?;
     Ok(())
 }
@@ -1,4 +1,7 @@
+use std::marker::PhantomData;
+
 use bytes::BytesMut;
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
 use tokio_util::codec::{Decoder, Encoder, LinesCodec};
 
@@ -15,22 +18,32 @@ pub enum JsonCodecError {
 }
 
 /// JSON tokio codec
-pub struct JsonCodec {
+///
+/// Decodes incoming requests by default, clients decode the replies instead.
+pub struct JsonCodec<T = message::HyperionMessage> {
     /// Line parsing codec
     lines: LinesCodec,
+    _item: PhantomData<fn() -> T>,
 }
 
-impl JsonCodec {
+impl<T> JsonCodec<T> {
     /// Create a new JsonCodec
     pub fn new() -> Self {
         Self {
             lines: LinesCodec::new(),
+            _item: PhantomData,
         }
     }
 }
 
-impl Decoder for JsonCodec {
-    type Item = message::HyperionMessage;
+impl<T> Default for JsonCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for JsonCodec<T> {
+    type Item = T;
     type Error = JsonCodecError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
@@ -44,14 +57,10 @@ impl Decoder for JsonCodec {
     }
 }
 
-impl Encoder<message::HyperionResponse> for JsonCodec {
+impl<T, U: Serialize> Encoder<U> for JsonCodec<T> {
     type Error = JsonCodecError;
 
-    fn encode(
-        &mut self,
-        item: message::HyperionResponse,
-        dst: &mut BytesMut,
-    ) -> Result<(), Self::Error> {
+    fn encode(&mut self, item: U, dst: &mut BytesMut) -> Result<(), Self::Error> {
         match serde_json::to_string(&item) {
             Ok(encoded) => Ok(self.lines.encode(encoded, dst)?),
             Err(encode_error) => Err(encode_error.into()),
@@ -0,0 +1,209 @@
+//! mDNS advertisement of the servers, for clients discovering hyperion.ng servers
+//!
+//! Services are named `<general name>@<hostname>`, with the types used by hyperion.ng. They follow
+//! the running servers, including the ones started or stopped after startup.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::ServerId;
+use crate::global::Global;
+
+#[derive(Debug, Error)]
+pub enum MdnsError {
+    #[error("mDNS error: {0}")]
+    Mdns(#[from] mdns_sd::Error),
+}
+
+/// Interval between two checks of the running servers
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// mDNS service type of a server, if it is advertised
+pub fn service_type(id: ServerId) -> Option<&'static str> {
+    match id {
+        ServerId::Json => Some("_hyperiond-json._tcp.local."),
+        ServerId::Flatbuffers => Some("_hyperiond-flatbuf._tcp.local."),
+        ServerId::Protobuf => Some("_hyperiond-protobuf._tcp.local."),
+        ServerId::Web => Some("_hyperiond-http._tcp.local."),
+        ServerId::Boblight { .. } | ServerId::UdpListener { .. } => None,
+    }
+}
+
+/// Handle to the mDNS responder, which withdraws the advertisements when dropped
+pub struct MdnsHandle {
+    stop: CancellationToken,
+    join_handle: JoinHandle<()>,
+}
+
+impl MdnsHandle {
+    /// Withdraw the advertisements and wait for the responder to stop
+    pub async fn stop(mut self) {
+        self.stop.cancel();
+        // ok: the task doesn't panic
+        (&mut self.join_handle).await.ok();
+    }
+}
+
+impl Drop for MdnsHandle {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// Start advertising the running servers
+pub fn start(global: Global) -> Result<MdnsHandle, MdnsError> {
+    let daemon = ServiceDaemon::new()?;
+    let hostname = hostname::get()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "hyperion".to_owned());
+
+    let stop = CancellationToken::new();
+    let join_handle = tokio::spawn(
+        Responder {
+            daemon,
+            global,
+            hostname,
+            registered: Default::default(),
+        }
+        .run(stop.clone()),
+    );
+
+    Ok(MdnsHandle { stop, join_handle })
+}
+
+/// Advertised service
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Service {
+    name: String,
+    port: u16,
+}
+
+struct Responder {
+    daemon: ServiceDaemon,
+    global: Global,
+    hostname: String,
+    /// Advertised services, with their full name
+    registered: BTreeMap<ServerId, (Service, String)>,
+}
+
+impl Responder {
+    async fn run(mut self, stop: CancellationToken) {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.refresh().await,
+                _ = stop.cancelled() => break,
+            }
+        }
+
+        for (_, fullname) in std::mem::take(&mut self.registered).into_values() {
+            self.unregister(&fullname);
+        }
+
+        // ok: the daemon may have stopped on its own
+        self.daemon.shutdown().ok();
+    }
+
+    /// Update the advertisements to match the running servers
+    async fn refresh(&mut self) {
+        let (name, uuid) = self
+            .global
+            .read_config(|config| (config.global.general.name.clone(), config.uuid()))
+            .await;
+        let name = format!("{}@{}", name, self.hostname);
+
+        let services: BTreeMap<_, _> = self
+            .global
+            .servers()
+            .await
+            .into_iter()
+            .filter(|(id, _)| service_type(*id).is_some())
+            .map(|(id, port)| {
+                (
+                    id,
+                    Service {
+                        name: name.clone(),
+                        port,
+                    },
+                )
+            })
+            .collect();
+
+        let stale: Vec<_> = self
+            .registered
+            .iter()
+            .filter(|(id, (service, _))| services.get(id) != Some(service))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in stale {
+            if let Some((_, fullname)) = self.registered.remove(&id) {
+                self.unregister(&fullname);
+            }
+        }
+
+        let version = crate::global::version();
+        let properties = [("id", uuid.to_string()), ("version", version)];
+
+        for (id, service) in services {
+            if self.registered.contains_key(&id) {
+                continue;
+            }
+
+            let ty = service_type(id).unwrap();
+            let result = ServiceInfo::new(
+                ty,
+                &service.name,
+                &format!("{}.local.", self.hostname),
+                "",
+                service.port,
+                &properties[..],
+            )
+            .map(ServiceInfo::enable_addr_auto)
+            .and_then(|info| {
+                let fullname = info.get_fullname().to_owned();
+                self.daemon.register(info).map(|_| fullname)
+            });
+
+            match result {
+                Ok(fullname) => {
+                    debug!(server = %id, name = %fullname, port = %service.port, "advertising server");
+                    self.registered.insert(id, (service, fullname));
+                }
+                Err(error) => {
+                    warn!(server = %id, error = %error, "cannot advertise server");
+                }
+            }
+        }
+    }
+
+    fn unregister(&self, fullname: &str) {
+        if let Err(error) = self.daemon.unregister(fullname) {
+            warn!(name = %fullname, error = %error, "cannot withdraw advertisement");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_type() {
+        // hyperion.ng clients look for these exact types
+        assert_eq!(
+            service_type(ServerId::Json),
+            Some("_hyperiond-json._tcp.local.")
+        );
+        assert_eq!(
+            service_type(ServerId::Flatbuffers),
+            Some("_hyperiond-flatbuf._tcp.local.")
+        );
+        assert_eq!(service_type(ServerId::Boblight { instance: 0 }), None);
+    }
+}
@@ -0,0 +1,75 @@
+//! Lookup of the process listening on a TCP port, to diagnose bind failures
+//!
+//! This reads the socket tables in `/proc/net` and the file descriptors of running processes, so
+//! it only finds processes of the same user unless running as root.
+
+/// Describe the process listening on `port`, if it can be found
+#[cfg(target_os = "linux")]
+pub fn port_owner(port: u16) -> Option<String> {
+    let inode = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .find_map(|table| listening_inode(&table, port))?;
+
+    let target = format!("socket:[{}]", inode);
+
+    std::fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .find(|pid| {
+            std::fs::read_dir(format!("/proc/{}/fd", pid))
+                .map(|fds| {
+                    fds.filter_map(|fd| fd.ok())
+                        .filter_map(|fd| std::fs::read_link(fd.path()).ok())
+                        .any(|link| link.as_os_str() == target.as_str())
+                })
+                .unwrap_or(false)
+        })
+        .map(
+            |pid| match std::fs::read_to_string(format!("/proc/{}/comm", pid)) {
+                Ok(name) => format!("process {} ({})", pid, name.trim()),
+                Err(_) => format!("process {}", pid),
+            },
+        )
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn port_owner(_port: u16) -> Option<String> {
+    None
+}
+
+/// Find the inode of the socket listening on `port` in a `/proc/net/tcp` table
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn listening_inode(table: &str, port: u16) -> Option<u64> {
+    /// Socket state of listening sockets
+    const TCP_LISTEN: &str = "0A";
+
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (_, local_port) = fields.get(1)?.rsplit_once(':')?;
+
+        if u16::from_str_radix(local_port, 16).ok()? == port && *fields.get(3)? == TCP_LISTEN {
+            fields.get(9)?.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listening_inode() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:4BF0 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 41234 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:4BF0 0100007F:D3C2 01 00000000:00000000 00:00000000 00000000  1000        0 51234 1 0000000000000000 20 4 30 10 -1
+   2: 00000000:4C21 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 61234 1 0000000000000000 100 0 0 10 0";
+
+        assert_eq!(listening_inode(table, 19440), Some(41234));
+        assert_eq!(listening_inode(table, 19489), Some(61234));
+        assert_eq!(listening_inode(table, 19444), None);
+    }
+}
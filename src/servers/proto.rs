@@ -8,8 +8,12 @@ use tokio::net::TcpStream;
 use tokio_util::codec::Framed;
 
 use crate::{
-    api::proto::{self, message, ProtoApiError},
-    global::{Global, InputSourceName, PriorityGuard},
+    api::{
+        proto::{self, message, ProtoApiError},
+        types::PriorityCompat,
+    },
+    global::{ClientProtocol, Global, InputSourceName, PriorityGuard},
+    servers::ClientContext,
 };
 
 mod codec;
@@ -51,10 +55,16 @@ fn error_response(peer_addr: SocketAddr, error: impl std::fmt::Display) -> messa
 pub async fn handle_client(
     (socket, peer_addr): (TcpStream, SocketAddr),
     global: Global,
+    context: ClientContext,
 ) -> Result<(), ProtoServerError> {
     debug!("accepted new connection from {}", peer_addr);
 
-    let (mut writer, mut reader) = Framed::new(socket, ProtoCodec::new()).split();
+    let framed: Framed<_, ProtoCodec> = Framed::new(socket, ProtoCodec::new());
+    let (mut writer, mut reader) = framed.split();
+
+    let client = global
+        .register_client(ClientProtocol::Protobuf, peer_addr)
+        .await;
 
     // unwrap: cannot fail because the priority is None
     let source = global
@@ -63,8 +73,13 @@ pub async fn handle_client(
         .unwrap();
 
     let mut priority_guard = PriorityGuard::new_broadcast(&source);
+    let mut priority_compat = PriorityCompat::new(
+        global
+            .read_config(|config| config.global.proto_server.legacy_priorities)
+            .await,
+    );
 
-    while let Some(request) = reader.next().await {
+    while let Some(request) = context.next_request(&mut reader).await {
         let request = match request {
             Ok(rb) => rb,
             Err(error) => {
@@ -74,8 +89,15 @@ pub async fn handle_client(
         };
 
         trace!("({}) got request: {:?}", peer_addr, request);
-
-        let reply = match proto::handle_request(peer_addr, request, &source, &mut priority_guard) {
+        client.negotiated(&request.command().as_str_name().to_ascii_lowercase());
+
+        let reply = match proto::handle_request(
+            peer_addr,
+            request,
+            &source,
+            &mut priority_guard,
+            &mut priority_compat,
+        ) {
             Ok(()) => success_response(peer_addr),
             Err(error) => {
                 error!("({}) error processing request: {}", peer_addr, error);
@@ -88,5 +110,7 @@ pub async fn handle_client(
         writer.flush().await?;
     }
 
+    priority_guard.clear().await;
+    writer.close().await?;
     Ok(())
 }
@@ -1,5 +1,6 @@
+use std::marker::PhantomData;
+
 use bytes::BytesMut;
-use prost::Message;
 use thiserror::Error;
 use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
 
@@ -17,15 +18,18 @@ pub enum ProtoCodecError {
     Encode(#[from] prost::EncodeError),
 }
 
-/// JSON tokio codec
-pub struct ProtoCodec {
+/// Protobuf tokio codec
+///
+/// Decodes incoming requests by default, clients decode the replies instead.
+pub struct ProtoCodec<T = message::HyperionRequest> {
     /// Line parsing codec
     inner: LengthDelimitedCodec,
     /// Buffer for encoding messages
     buf: BytesMut,
+    _item: PhantomData<fn() -> T>,
 }
 
-impl ProtoCodec {
+impl<T> ProtoCodec<T> {
     /// Create a new ProtoCodec
     pub fn new() -> Self {
         Self {
@@ -33,18 +37,25 @@ impl ProtoCodec {
                 .length_field_length(4)
                 .new_codec(),
             buf: BytesMut::new(),
+            _item: PhantomData,
         }
     }
 }
 
-impl Decoder for ProtoCodec {
-    type Item = message::HyperionRequest;
+impl<T> Default for ProtoCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: prost::Message + Default> Decoder for ProtoCodec<T> {
+    type Item = T;
     type Error = ProtoCodecError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         match self.inner.decode(src) {
             Ok(inner_result) => Ok(match inner_result {
-                Some(ref data) => Some(message::HyperionRequest::decode(data.clone().freeze())?),
+                Some(ref data) => Some(T::decode(data.clone().freeze())?),
                 None => None,
             }),
             Err(error) => Err(error.into()),
@@ -52,10 +63,10 @@ impl Decoder for ProtoCodec {
     }
 }
 
-impl<T: prost::Message> Encoder<T> for ProtoCodec {
+impl<T, U: prost::Message> Encoder<U> for ProtoCodec<T> {
     type Error = ProtoCodecError;
 
-    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, item: U, dst: &mut BytesMut) -> Result<(), Self::Error> {
         self.buf.clear();
         self.buf.reserve(item.encoded_len());
 
@@ -0,0 +1,403 @@
+//! SSDP discovery, for clients looking for hyperion.ng servers on the network
+//!
+//! M-SEARCH requests are answered with the location of `description.xml`, served by the web
+//! server on its configured port. Nothing is advertised while the web server isn't running.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use thiserror::Error;
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use super::ServerId;
+use crate::global::Global;
+
+#[derive(Debug, Error)]
+pub enum SsdpError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Device type searched for by hyperion.ng clients
+pub const SEARCH_TARGET: &str = "urn:hyperion-project.org:device:basic:1";
+
+/// Generic UPnP device type, also listed in the description
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:Basic:1";
+
+const SSDP_ADDRESS: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900);
+
+/// Time clients may cache an advertisement for
+const MAX_AGE: Duration = Duration::from_secs(1800);
+
+/// Handle to the SSDP responder, which withdraws the advertisement when dropped
+pub struct SsdpHandle {
+    stop: CancellationToken,
+    join_handle: JoinHandle<()>,
+}
+
+impl SsdpHandle {
+    /// Withdraw the advertisement and wait for the responder to stop
+    pub async fn stop(mut self) {
+        self.stop.cancel();
+        // ok: the task doesn't panic
+        (&mut self.join_handle).await.ok();
+    }
+}
+
+impl Drop for SsdpHandle {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// Start answering SSDP searches
+pub fn start(global: Global) -> Result<SsdpHandle, SsdpError> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Other SSDP services may be running on this host
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_ADDRESS.port())).into())?;
+    socket.join_multicast_v4(SSDP_ADDRESS.ip(), &Ipv4Addr::UNSPECIFIED)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+
+    let stop = CancellationToken::new();
+    let join_handle = tokio::spawn(
+        Responder {
+            socket,
+            global,
+            advertised: None,
+        }
+        .run(stop.clone()),
+    );
+
+    Ok(SsdpHandle { stop, join_handle })
+}
+
+/// Information advertised about this server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub uuid: uuid::Uuid,
+    pub name: String,
+    pub servers: BTreeMap<ServerId, u16>,
+}
+
+impl Device {
+    /// Current information, if the web server is running
+    pub async fn current(global: &Global) -> Option<Self> {
+        let (uuid, name) = global
+            .read_config(|config| (config.uuid(), config.global.general.name.clone()))
+            .await;
+        let servers: BTreeMap<_, _> = global.servers().await.into_iter().collect();
+
+        servers.contains_key(&ServerId::Web).then_some(Self {
+            uuid,
+            name,
+            servers,
+        })
+    }
+
+    fn port(&self, id: ServerId) -> Option<u16> {
+        self.servers.get(&id).copied()
+    }
+
+    /// Search targets this device matches, with their unique service name
+    fn targets(&self, search_target: &str) -> Vec<(String, String)> {
+        let uuid = format!("uuid:{}", self.uuid);
+        let all = vec![
+            (
+                "upnp:rootdevice".to_owned(),
+                format!("{}::upnp:rootdevice", uuid),
+            ),
+            (uuid.clone(), uuid.clone()),
+            (
+                SEARCH_TARGET.to_owned(),
+                format!("{}::{}", uuid, SEARCH_TARGET),
+            ),
+            (DEVICE_TYPE.to_owned(), format!("{}::{}", uuid, DEVICE_TYPE)),
+        ];
+
+        all.into_iter()
+            .filter(|(target, _)| search_target == "ssdp:all" || search_target == target)
+            .collect()
+    }
+
+    /// Headers common to search responses and notifications
+    fn headers(&self, host: IpAddr) -> String {
+        let mut headers = format!(
+            "CACHE-CONTROL: max-age={}\r\n\
+             LOCATION: http://{}:{}/description.xml\r\n\
+             SERVER: {} UPnP/1.0 Hyperion/{}\r\n\
+             HYPERION-NAME: {}\r\n",
+            MAX_AGE.as_secs(),
+            host,
+            self.port(ServerId::Web).unwrap_or_default(),
+            std::env::consts::OS,
+            crate::global::version(),
+            self.name,
+        );
+
+        if let Some(port) = self.port(ServerId::Json) {
+            headers.push_str(&format!("HYPERION-JSS-PORT: {}\r\n", port));
+        }
+        if let Some(port) = self.port(ServerId::Flatbuffers) {
+            headers.push_str(&format!("HYPERION-FBS-PORT: {}\r\n", port));
+        }
+
+        headers
+    }
+
+    /// Responses to a search, from the address `host`
+    pub fn search_responses(&self, search_target: &str, host: IpAddr) -> Vec<String> {
+        let date = chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S GMT");
+
+        self.targets(search_target)
+            .into_iter()
+            .map(|(target, usn)| {
+                format!(
+                    "HTTP/1.1 200 OK\r\n{}DATE: {}\r\nEXT:\r\nST: {}\r\nUSN: {}\r\n\r\n",
+                    self.headers(host),
+                    date,
+                    target,
+                    usn
+                )
+            })
+            .collect()
+    }
+
+    /// Notifications of the device joining (`ssdp:alive`) or leaving (`ssdp:byebye`)
+    fn notifications(&self, kind: &str, host: IpAddr) -> Vec<String> {
+        let headers = if kind == "ssdp:alive" {
+            self.headers(host)
+        } else {
+            String::new()
+        };
+
+        self.targets("ssdp:all")
+            .into_iter()
+            .map(|(target, usn)| {
+                format!(
+                    "NOTIFY * HTTP/1.1\r\nHOST: {}\r\n{}NT: {}\r\nNTS: {}\r\nUSN: {}\r\n\r\n",
+                    SSDP_ADDRESS, headers, target, kind, usn
+                )
+            })
+            .collect()
+    }
+
+    /// UPnP device description, for clients reaching the web server at `host`
+    pub fn description(&self, host: &str) -> String {
+        let mut ports = String::new();
+        for (tag, id) in [
+            ("jsonServer", ServerId::Json),
+            ("protoBuffer", ServerId::Protobuf),
+            ("flatBuffer", ServerId::Flatbuffers),
+        ] {
+            if let Some(port) = self.port(id) {
+                ports.push_str(&format!("<{tag}>{port}</{tag}>", tag = tag, port = port));
+            }
+        }
+
+        format!(
+            r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<URLBase>http://{host}</URLBase>
+<device>
+<deviceType>{device_type}</deviceType>
+<friendlyName>{name}</friendlyName>
+<manufacturer>Hyperion Open Source Ambient Lighting</manufacturer>
+<manufacturerURL>https://www.hyperion-project.org</manufacturerURL>
+<modelDescription>Hyperion Open Source Ambient Light</modelDescription>
+<modelName>Hyperion</modelName>
+<modelNumber>{version}</modelNumber>
+<modelURL>https://github.com/alixinne/hyperion.rs</modelURL>
+<serialNumber>{uuid}</serialNumber>
+<UDN>uuid:{uuid}</UDN>
+<ports>{ports}</ports>
+<presentationURL>index.html</presentationURL>
+</device>
+</root>
+"#,
+            host = escape_xml(host),
+            device_type = DEVICE_TYPE,
+            name = escape_xml(&self.name),
+            version = escape_xml(&crate::global::version()),
+            uuid = self.uuid,
+            ports = ports,
+        )
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Search target of an M-SEARCH request
+fn parse_search(request: &str) -> Option<&str> {
+    let mut lines = request.lines();
+    if !lines.next()?.starts_with("M-SEARCH * HTTP/1.1") {
+        return None;
+    }
+
+    let mut discover = false;
+    let mut search_target = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("MAN") {
+                discover = value == "\"ssdp:discover\"";
+            } else if name.eq_ignore_ascii_case("ST") {
+                search_target = Some(value);
+            }
+        }
+    }
+
+    search_target.filter(|_| discover)
+}
+
+/// Address of the interface used to reach `peer`
+fn local_address(peer: SocketAddr) -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    socket.local_addr().ok().map(|address| address.ip())
+}
+
+struct Responder {
+    socket: UdpSocket,
+    global: Global,
+    /// Last advertised device, to notify its changes
+    advertised: Option<Device>,
+}
+
+impl Responder {
+    async fn run(mut self, stop: CancellationToken) {
+        let mut interval = tokio::time::interval(MAX_AGE / 2);
+        let mut buf = vec![0u8; 2048];
+
+        loop {
+            tokio::select! {
+                result = self.socket.recv_from(&mut buf) => match result {
+                    Ok((len, peer)) => {
+                        if let Some(search_target) = std::str::from_utf8(&buf[..len])
+                            .ok()
+                            .and_then(parse_search)
+                        {
+                            let search_target = search_target.to_owned();
+                            self.on_search(&search_target, peer).await;
+                        }
+                    }
+                    Err(error) => {
+                        warn!(error = %error, "SSDP socket error");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                },
+                _ = interval.tick() => self.notify().await,
+                _ = stop.cancelled() => break,
+            }
+        }
+
+        if let Some(device) = self.advertised.take() {
+            self.send_notifications(&device, "ssdp:byebye").await;
+        }
+    }
+
+    async fn on_search(&mut self, search_target: &str, peer: SocketAddr) {
+        let device = match Device::current(&self.global).await {
+            Some(device) => device,
+            None => return,
+        };
+
+        let host = match local_address(peer) {
+            Some(host) => host,
+            None => return,
+        };
+
+        for response in device.search_responses(search_target, host) {
+            if let Err(error) = self.socket.send_to(response.as_bytes(), peer).await {
+                debug!(peer = %peer, error = %error, "cannot answer SSDP search");
+            }
+        }
+    }
+
+    /// Announce the device, or its departure if the web server stopped
+    async fn notify(&mut self) {
+        let device = Device::current(&self.global).await;
+
+        if let Some(advertised) = self.advertised.take() {
+            if device.as_ref() != Some(&advertised) {
+                self.send_notifications(&advertised, "ssdp:byebye").await;
+            }
+        }
+
+        if let Some(device) = device {
+            self.send_notifications(&device, "ssdp:alive").await;
+            self.advertised = Some(device);
+        }
+    }
+
+    async fn send_notifications(&self, device: &Device, kind: &str) {
+        let host = match local_address(SSDP_ADDRESS.into()) {
+            Some(host) => host,
+            None => return,
+        };
+
+        for notification in device.notifications(kind, host) {
+            if let Err(error) = self
+                .socket
+                .send_to(notification.as_bytes(), SSDP_ADDRESS)
+                .await
+            {
+                debug!(error = %error, "cannot send SSDP notification");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_responses() {
+        let request = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: urn:hyperion-project.org:device:basic:1\r\n\r\n";
+        let search_target = parse_search(request).unwrap();
+        assert_eq!(search_target, SEARCH_TARGET);
+
+        let device = Device {
+            uuid: uuid::Uuid::nil(),
+            name: "Living <room>".to_owned(),
+            servers: vec![(ServerId::Web, 8090), (ServerId::Json, 19444)]
+                .into_iter()
+                .collect(),
+        };
+
+        let responses = device.search_responses(search_target, Ipv4Addr::LOCALHOST.into());
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].contains("LOCATION: http://127.0.0.1:8090/description.xml\r\n"));
+        assert!(responses[0].contains("HYPERION-JSS-PORT: 19444\r\n"));
+        assert!(!responses[0].contains("HYPERION-FBS-PORT"));
+        assert_eq!(
+            device
+                .search_responses("ssdp:all", Ipv4Addr::LOCALHOST.into())
+                .len(),
+            4
+        );
+        assert!(device
+            .search_responses("urn:other", Ipv4Addr::LOCALHOST.into())
+            .is_empty());
+
+        let description = device.description("127.0.0.1:8090");
+        assert!(description.contains("<friendlyName>Living &lt;room&gt;</friendlyName>"));
+        assert!(description.contains("<ports><jsonServer>19444</jsonServer></ports>"));
+
+        assert_eq!(parse_search("NOTIFY * HTTP/1.1\r\nST: ssdp:all\r\n"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Raw UDP listener, for senders which stream LED colors without any protocol
+//!
+//! Each datagram holds the RGB colors of the LEDs of the instance, three bytes per LED in the
+//! order of the LED layout. There is no acknowledgement nor connection: the colors are shown at
+//! the configured priority as soon as they are received.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use super::ServerHandle;
+use crate::{
+    global::{Global, InputMessage, InputMessageData, InputSourceName, PriorityGuard},
+    instance::InstanceHandle,
+    models::{self, Color},
+};
+
+/// Largest datagram accepted, enough for 21845 LEDs
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// Start listening for raw colors sent to the given instance
+pub async fn bind(
+    config: models::UdpListener,
+    instance: InstanceHandle,
+    global: Global,
+) -> io::Result<ServerHandle> {
+    let socket = open_socket(&config)?;
+
+    let source = global
+        .register_input_source(
+            InputSourceName::UdpListener { port: config.port },
+            Some(config.priority),
+        )
+        .await
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+    // The server is restarted with the instance, so the LED count can't change while it runs
+    let led_count = instance
+        .config()
+        .await
+        .map_err(io::Error::other)?
+        .leds
+        .leds
+        .len();
+
+    let tx = instance.input_channel().clone();
+    let duration =
+        (config.timeout > 0).then(|| chrono::Duration::milliseconds(config.timeout as _));
+
+    let server = async move {
+        // Clears our priority when the server stops
+        let _guard = PriorityGuard::new_mpsc(tx.clone(), &source);
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let mut last_sender = None;
+
+        info!(address = %config.address, port = %config.port, "UDP listener started");
+
+        loop {
+            let (len, sender) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(error) => {
+                    warn!(error = %error, "UDP listener error");
+                    continue;
+                }
+            };
+
+            if last_sender != Some(sender) {
+                debug!(sender = %sender, "receiving colors");
+                last_sender = Some(sender);
+            }
+
+            let message = InputMessage::new(
+                source.id(),
+                source.name().component(),
+                InputMessageData::LedColors {
+                    priority: config.priority,
+                    duration,
+                    led_colors: Arc::new(decode(&buffer[..len], led_count)),
+                },
+            );
+
+            if tx.send(message).await.is_err() {
+                // The instance stopped
+                break;
+            }
+        }
+    };
+
+    Ok(ServerHandle::spawn(config.port, server))
+}
+
+/// Bind the listening socket, joining the configured address if it is a multicast group
+fn open_socket(config: &models::UdpListener) -> io::Result<UdpSocket> {
+    let address: IpAddr = config
+        .address
+        .parse()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+    let socket = Socket::new(
+        Domain::for_address(SocketAddr::new(address, config.port)),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if config.shared {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+
+    match address {
+        IpAddr::V4(group) if group.is_multicast() => {
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port)).into())?;
+            socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+        }
+        IpAddr::V6(group) if group.is_multicast() => {
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, config.port)).into())?;
+            socket.join_multicast_v6(&group, 0)?;
+        }
+        address => {
+            socket.bind(&SocketAddr::new(address, config.port).into())?;
+        }
+    }
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Colors of `led_count` LEDs from a datagram of RGB triplets
+///
+/// LEDs missing from the datagram are black, and extra bytes are ignored.
+fn decode(datagram: &[u8], led_count: usize) -> Vec<Color> {
+    let mut colors: Vec<_> = datagram
+        .chunks_exact(3)
+        .take(led_count)
+        .map(|rgb| Color::new(rgb[0], rgb[1], rgb[2]))
+        .collect();
+    colors.resize(led_count, Color::default());
+
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode(&[255, 0, 0, 0, 255, 0], 2),
+            [Color::new(255, 0, 0), Color::new(0, 255, 0)]
+        );
+
+        // Short datagrams are padded with black, partial and extra LEDs are dropped
+        assert_eq!(
+            decode(&[1, 2, 3, 4, 5], 2),
+            [Color::new(1, 2, 3), Color::default()]
+        );
+        assert_eq!(decode(&[1, 2, 3, 4, 5, 6], 1), [Color::new(1, 2, 3)]);
+    }
+}
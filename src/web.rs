@@ -1,27 +1,45 @@
 use std::net::SocketAddr;
 
-use futures::{Future, SinkExt, StreamExt};
+use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
 use warp::{http::StatusCode, path::FullPath, Filter, Rejection};
 
 use crate::{
     api::json::message,
     global::{Global, Paths},
     models::WebConfig,
+    servers::ssdp,
 };
 
 mod session;
 use session::*;
 
+#[cfg(feature = "tls")]
+mod tls;
+
+/// Address of the client, for requests accepted by warp or by the HTTPS listener
+fn remote() -> impl Filter<Extract = (Option<SocketAddr>,), Error = std::convert::Infallible> + Clone
+{
+    let remote = warp::filters::addr::remote();
+
+    #[cfg(feature = "tls")]
+    let remote = remote.and(warp::ext::optional::<tls::TlsPeer>()).map(
+        |remote: Option<SocketAddr>, peer: Option<tls::TlsPeer>| remote.or(peer.map(|peer| peer.0)),
+    );
+
+    remote
+}
+
+/// Bind the webconfig server, and its HTTPS server if a certificate is configured
 pub async fn bind(
     global: Global,
     config: &WebConfig,
     paths: &Paths,
-) -> Result<impl Future<Output = ()>, std::io::Error> {
+) -> Result<BoxFuture<'static, ()>, std::io::Error> {
     let session_store = SessionStore::new(config.max_sessions as _);
 
     let ws = warp::ws()
         .and(session_store.request())
-        .and(warp::filters::addr::remote())
+        .and(remote())
         .and({
             let global = global.clone();
             warp::any().map(move || global.clone())
@@ -29,7 +47,7 @@ pub async fn bind(
         .map(
             |ws: warp::ws::Ws,
              session: SessionInstance,
-             _remote: Option<SocketAddr>,
+             remote: Option<SocketAddr>,
              global: Global| {
                 (
                     ws.on_upgrade({
@@ -40,10 +58,24 @@ pub async fn bind(
                             let (mut tx, mut rx) = websocket.split();
 
                             async move {
-                                while let Some(result) = rx.next().await {
-                                    if let Some(message) =
-                                        session.write().await.handle_result(&global, result).await
-                                    {
+                                loop {
+                                    // Only lock the session while waiting for updates if there
+                                    // are subscriptions, the session is shared with JSON-RPC
+                                    let subscribed = session.read().await.has_subscriptions();
+
+                                    let message = tokio::select! {
+                                        result = rx.next() => match result {
+                                            Some(result) => {
+                                                session.write().await.handle_result(&global, result, remote).await
+                                            }
+                                            None => None,
+                                        },
+                                        update = async {
+                                            session.write().await.next_update(&global).await
+                                        }, if subscribed => Some(update),
+                                    };
+
+                                    if let Some(message) = message {
                                         if let Err(error) = tx.send(message).await {
                                             warn!(error = %error, "websocket error");
                                         }
@@ -89,17 +121,99 @@ pub async fn bind(
                 })),
     );
 
+    // GET /blackout returns the current state, POST /blackout/{enable,disable,toggle} changes it
+    let blackout = warp::path("blackout")
+        .and(
+            warp::get()
+                .and(warp::path::end())
+                .map(|| None)
+                .or(warp::post()
+                    .and(warp::path::param())
+                    .and(warp::path::end())
+                    .map(Some))
+                .unify(),
+        )
+        .and(warp::filters::header::optional("Authorization"))
+        .and(session_store.request())
+        .and(remote())
+        .and({
+            let global = global.clone();
+            warp::any().map(move || global.clone())
+        })
+        .and_then(
+            |action: Option<String>,
+             authorization: Option<String>,
+             session: SessionInstance,
+             remote: Option<SocketAddr>,
+             global: Global| {
+                async move {
+                    let enable = match action.as_deref() {
+                        None => None,
+                        Some("enable") => Some(true),
+                        Some("disable") => Some(false),
+                        Some("toggle") => Some(!global.is_blackout().await),
+                        Some(_) => return Err(warp::reject::not_found()),
+                    };
+
+                    // Handled as a blackout command of the session, so the client needs the
+                    // same access as on /json-rpc
+                    let request = message::HyperionMessage {
+                        tan: None,
+                        command: message::HyperionCommand::Blackout(message::Blackout { enable }),
+                    };
+
+                    let reply = warp::reply::json(
+                        &session
+                            .session()
+                            .write()
+                            .await
+                            .handle_request(&global, request, remote, authorization.as_deref())
+                            .await,
+                    );
+
+                    Ok::<_, Rejection>((reply, session))
+                }
+            },
+        )
+        .untuple_one()
+        .and_then(reply_session);
+
+    // UPnP description of this server, for clients discovering it over SSDP
+    let description = warp::path("description.xml")
+        .and(warp::path::end())
+        .and(warp::header::optional::<String>("host"))
+        .and_then({
+            let global = global.clone();
+            let port = config.port;
+            move |host: Option<String>| {
+                let global = global.clone();
+
+                async move {
+                    let device = ssdp::Device::current(&global)
+                        .await
+                        .ok_or_else(warp::reject::not_found)?;
+                    let host = host.unwrap_or_else(|| format!("localhost:{}", port));
+
+                    Ok::<_, Rejection>(warp::reply::with_header(
+                        device.description(&host),
+                        "Content-Type",
+                        "text/xml",
+                    ))
+                }
+            }
+        });
+
     let json_rpc = warp::path("json-rpc")
         .and(warp::body::json())
         .and(warp::filters::header::optional("Authorization"))
         .and(session_store.request())
-        .and(warp::filters::addr::remote())
+        .and(remote())
         .and(warp::any().map(move || global.clone()))
         .and_then(
             |request: message::HyperionMessage,
-             _authorization: Option<String>,
+             authorization: Option<String>,
              session: SessionInstance,
-             _remote: Option<SocketAddr>,
+             remote: Option<SocketAddr>,
              global: Global| {
                 async move {
                     let reply = warp::reply::json(
@@ -107,7 +221,7 @@ pub async fn bind(
                             .session()
                             .write()
                             .await
-                            .handle_request(&global, request)
+                            .handle_request(&global, request, remote, authorization.as_deref())
                             .await,
                     );
 
@@ -126,21 +240,57 @@ pub async fn bind(
 
     // TODO: Serve error pages from /errorpages/*
 
+    let routes = ws
+        .or(cgi)
+        .or(blackout)
+        .or(description)
+        .or(json_rpc)
+        .or(files)
+        .with(warp::filters::log::log("hyperion::web"));
+
+    // A bad certificate or a busy HTTPS port shouldn't take the plain server down with it, and
+    // redirecting to a server which isn't running would lock users out of the webconfig
+    #[cfg(feature = "tls")]
+    let https = if config.is_ssl_enabled() {
+        match tls::TlsListener::bind(config, paths).await {
+            Ok(https) => Some(https),
+            Err(error) => {
+                error!(error = %error, "cannot start the HTTPS server, only serving the webconfig over HTTP");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    #[cfg(not(feature = "tls"))]
+    if config.is_ssl_enabled() {
+        warn!("this build has no HTTPS support, only serving the webconfig over HTTP");
+    }
+
     let address = SocketAddr::from(([0, 0, 0, 0], config.port));
-    let listener = tokio::net::TcpListener::bind(address).await;
-
-    match listener {
-        Ok(listener) => {
-            info!(address = %address, "Webconfig server listening");
-            Ok(warp::serve(
-                ws.or(cgi)
-                    .or(json_rpc)
-                    .or(files)
-                    .with(warp::filters::log::log("hyperion::web")),
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!(address = %address, "Webconfig server listening");
+
+    #[cfg(feature = "tls")]
+    if let Some(https) = https {
+        let http = if config.ssl_redirect {
+            warp::serve(
+                tls::redirect(config.ssl_port).with(warp::filters::log::log("hyperion::web")),
             )
             .incoming(listener)
-            .run())
-        }
-        Err(error) => Err(error),
+            .run()
+            .boxed()
+        } else {
+            warp::serve(routes.clone()).incoming(listener).run().boxed()
+        };
+
+        return Ok(
+            futures::future::join(http, https.serve(warp::service(routes)))
+                .map(|_| ())
+                .boxed(),
+        );
     }
+
+    Ok(warp::serve(routes).incoming(listener).run().boxed())
 }
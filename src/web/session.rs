@@ -1,4 +1,4 @@
-use std::{convert::TryInto, fmt::Display, num::NonZeroUsize, sync::Arc};
+use std::{convert::TryInto, fmt::Display, net::SocketAddr, num::NonZeroUsize, sync::Arc};
 
 use lru::LruCache;
 use thiserror::Error;
@@ -10,7 +10,7 @@ use crate::{
         message::{HyperionMessage, HyperionResponse},
         ClientConnection, JsonApiError,
     },
-    global::{Global, InputSourceError},
+    global::{AccessLevel, Global, InputSourceError},
 };
 
 #[derive(Debug, Error)]
@@ -32,13 +32,25 @@ pub struct Session {
 }
 
 impl Session {
-    async fn json_api(&mut self, global: &Global) -> Result<&mut ClientConnection, SessionError> {
+    async fn json_api(
+        &mut self,
+        global: &Global,
+        remote: Option<SocketAddr>,
+    ) -> Result<&mut ClientConnection, SessionError> {
         if self.json_api.is_none() {
             // Generate a session ID
             if self.id.is_nil() {
                 self.id = uuid::Uuid::new_v4();
             }
 
+            // The session may be shared by several connections, the first one decides whether
+            // the client is local
+            let access = global
+                .read_config(|config| {
+                    AccessLevel::for_peer(&config.global.network, remote.map(|addr| addr.ip()))
+                })
+                .await;
+
             // Can't use SocketAddr, see https://github.com/seanmonstar/warp/issues/830
             self.json_api = Some(ClientConnection::new(
                 global
@@ -49,6 +61,7 @@ impl Session {
                         None,
                     )
                     .await?,
+                access,
             ));
         }
 
@@ -59,8 +72,9 @@ impl Session {
         &mut self,
         global: &Global,
         message: Message,
+        remote: Option<SocketAddr>,
     ) -> Result<Message, SessionError> {
-        let json_api = self.json_api(global).await?;
+        let json_api = self.json_api(global, remote).await?;
 
         if message.is_text() {
             let request: HyperionMessage = serde_json::from_str(message.to_str().unwrap())?;
@@ -71,6 +85,23 @@ impl Session {
         Err(SessionError::NotImplemented)
     }
 
+    /// Returns true if the JSON API client of this session subscribed to updates
+    pub fn has_subscriptions(&self) -> bool {
+        self.json_api
+            .as_ref()
+            .is_some_and(ClientConnection::has_subscriptions)
+    }
+
+    /// Wait for the next update to push to this session, see [ClientConnection::next_update]
+    pub async fn next_update(&mut self, global: &Global) -> Message {
+        let update = match self.json_api.as_mut() {
+            Some(json_api) => json_api.next_update(global).await,
+            None => futures::future::pending().await,
+        };
+
+        Message::text(serde_json::to_string(&update).unwrap())
+    }
+
     fn error_message<T: Display>(&self, e: T) -> Message {
         Message::text(
             serde_json::to_string(&serde_json::json!({ "error": e.to_string() })).unwrap(),
@@ -82,6 +113,7 @@ impl Session {
         &mut self,
         global: &Global,
         result: Result<Message, warp::Error>,
+        remote: Option<SocketAddr>,
     ) -> Option<Message> {
         match result {
             Ok(message) => {
@@ -91,7 +123,7 @@ impl Session {
                     return None;
                 }
 
-                let response = self.handle_message(global, message).await;
+                let response = self.handle_message(global, message, remote).await;
 
                 trace!(response = ?response, "ws response");
 
@@ -104,22 +136,31 @@ impl Session {
         }
     }
 
-    #[instrument(skip(global, request))]
+    #[instrument(skip(global, request, authorization))]
     pub async fn handle_request(
         &mut self,
         global: &Global,
         request: HyperionMessage,
+        remote: Option<SocketAddr>,
+        authorization: Option<&str>,
     ) -> HyperionResponse {
         trace!(request = ?request, "JSON RPC request");
 
         let tan = request.tan;
-        let api = match self.json_api(global).await {
+        let api = match self.json_api(global, remote).await {
             Ok(api) => api,
             Err(error) => {
                 return HyperionResponse::error(&error).with_tan(tan);
             }
         };
 
+        // Clients may authenticate each request with an "Authorization: token <token>" header
+        if let Some(token) = authorization.and_then(|header| header.strip_prefix("token ")) {
+            if let Err(error) = api.login(global, token.trim()).await {
+                return HyperionResponse::error(&error).with_tan(tan);
+            }
+        }
+
         let response = match api.handle_request(request, global).await {
             Ok(response) => response,
             Err(error) => {